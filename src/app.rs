@@ -9,66 +9,152 @@ use vulkano::{
             DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger,
             DebugUtilsMessengerCallback, DebugUtilsMessengerCreateInfo,
         },
-        InstanceCreateInfo, InstanceExtensions,
+        Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions,
     },
     memory::allocator::StandardMemoryAllocator,
+    VulkanLibrary,
 };
 use vulkano_util::{
     context::{VulkanoConfig, VulkanoContext},
     window::VulkanoWindows,
 };
 
+use crate::memory::MemoryTracker;
+
+/// What tracing rays from shaders needs of the device.
+const RAY_QUERY_EXTENSIONS: DeviceExtensions = DeviceExtensions {
+    khr_acceleration_structure: true,
+    khr_deferred_host_operations: true,
+    khr_ray_query: true,
+    ..DeviceExtensions::empty()
+};
+const RAY_QUERY_FEATURES: DeviceFeatures = DeviceFeatures {
+    acceleration_structure: true,
+    ray_query: true,
+    ..DeviceFeatures::empty()
+};
+
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Whether any device supports `extensions` and `features`. Checked on an
+/// instance of its own, as the context is created with the extensions it
+/// requires. False without a Vulkan library.
+fn any_device_supports(extensions: &DeviceExtensions, features: &DeviceFeatures) -> bool {
+    let Ok(library) = VulkanLibrary::new() else {
+        return false;
+    };
+    let Ok(instance) = Instance::new(
+        library,
+        InstanceCreateInfo {
+            flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+            ..Default::default()
+        },
+    ) else {
+        return false;
+    };
+    let Ok(mut devices) = instance.enumerate_physical_devices() else {
+        return false;
+    };
+    devices.any(|device| {
+        device.supported_extensions().contains(extensions)
+            && device.supported_features().contains(features)
+    })
+}
+
+#[cfg(test)]
+fn validation_layer_available() -> bool {
+    let Ok(library) = VulkanLibrary::new() else {
+        return false;
+    };
+    library
+        .layer_properties()
+        .is_ok_and(|mut layers| layers.any(|layer| layer.name() == VALIDATION_LAYER))
+}
+
+/// What the game needs of the instance and device.
+fn config() -> VulkanoConfig {
+    let mut config = VulkanoConfig {
+        device_extensions: DeviceExtensions {
+            khr_swapchain: true,
+            ext_mesh_shader: true,
+            // khr_ray_tracing_pipeline: true,
+            ..DeviceExtensions::empty()
+        },
+        device_features: DeviceFeatures {
+            dynamic_rendering: true,
+            fill_mode_non_solid: true,
+            mesh_shader: true,
+            task_shader: true,
+            maintenance4: true,
+            shader_int16: true,
+            shader_float16: true,
+            synchronization2: true,
+            buffer_device_address: true,
+            buffer_device_address_capture_replay: true,
+            // For the draw statistics
+            pipeline_statistics_query: true,
+            // For the arrays of chunk buffer pages
+            shader_storage_buffer_array_dynamic_indexing: true,
+            ..DeviceFeatures::empty()
+        },
+        instance_create_info: InstanceCreateInfo {
+            enabled_layers: vec![VALIDATION_LAYER.to_owned()],
+            enabled_extensions: InstanceExtensions {
+                ext_debug_utils: true,
+                ..InstanceExtensions::empty()
+            },
+            ..Default::default()
+        },
+
+        ..Default::default()
+    };
+
+    config
+        .instance_create_info
+        .enabled_extensions
+        .ext_swapchain_colorspace = true;
+    config
+}
+
 pub struct App {
     pub context: VulkanoContext,
     pub windows: VulkanoWindows,
     pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
     pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    pub memory_tracker: Arc<MemoryTracker>,
+    /// Whether the device was created with `RAY_QUERY_EXTENSIONS`.
+    pub ray_query: bool,
     _debug_callback: DebugUtilsMessenger,
 
     pub validation_error_encountered: Arc<AtomicBool>,
 }
 
 impl App {
-    pub fn new() -> Self {
-        let mut config = VulkanoConfig {
-            device_extensions: DeviceExtensions {
-                khr_swapchain: true,
-                ext_mesh_shader: true,
-                // khr_acceleration_structure: true,
-                // khr_ray_tracing_pipeline: true,
-                // khr_deferred_host_operations: true,
-                ..DeviceExtensions::empty()
-            },
-            device_features: DeviceFeatures {
-                dynamic_rendering: true,
-                fill_mode_non_solid: true,
-                mesh_shader: true,
-                task_shader: true,
-                maintenance4: true,
-                shader_int16: true,
-                shader_float16: true,
-                synchronization2: true,
-                buffer_device_address: true,
-                buffer_device_address_capture_replay: true,
-                ..DeviceFeatures::empty()
-            },
-            instance_create_info: InstanceCreateInfo {
-                enabled_layers: vec!["VK_LAYER_KHRONOS_validation".to_owned()],
-                enabled_extensions: InstanceExtensions {
-                    ext_debug_utils: true,
-                    ..InstanceExtensions::empty()
-                },
-                ..Default::default()
-            },
-
-            ..Default::default()
-        };
+    /// With `ray_query`, the device is created with ray queries if any
+    /// device supports them.
+    pub fn new(ray_query: bool) -> Self {
+        let ray_query =
+            ray_query && any_device_supports(&RAY_QUERY_EXTENSIONS, &RAY_QUERY_FEATURES);
+        let mut config = config();
+        if ray_query {
+            config.device_extensions = config.device_extensions.union(&RAY_QUERY_EXTENSIONS);
+            config.device_features = config.device_features.union(&RAY_QUERY_FEATURES);
+        }
+        Self::with_config(config, ray_query)
+    }
 
-        config
-            .instance_create_info
-            .enabled_extensions
-            .ext_swapchain_colorspace = true;
+    /// An app that only renders offscreen, without ray queries, or `None` if
+    /// no device can run the game or the validation layer is missing, as on
+    /// machines without a GPU and without lavapipe or SwiftShader.
+    #[cfg(test)]
+    pub fn headless() -> Option<Self> {
+        let config = config();
+        let supported = validation_layer_available()
+            && any_device_supports(&config.device_extensions, &config.device_features);
+        supported.then(|| Self::with_config(config, false))
+    }
 
+    fn with_config(config: VulkanoConfig, ray_query: bool) -> Self {
         let context = VulkanoContext::new(config);
         let windows = VulkanoWindows::default();
 
@@ -150,6 +236,8 @@ impl App {
             windows,
             command_buffer_allocator,
             descriptor_set_allocator,
+            memory_tracker: Arc::new(MemoryTracker::default()),
+            ray_query,
             _debug_callback: debug_callback,
             validation_error_encountered,
         }
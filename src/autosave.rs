@@ -0,0 +1,130 @@
+//! Periodic saving of the columns changed since the last save. Only the
+//! changed columns are copied, and `ChunkLoader` writes the copies in the
+//! background, so an autosave costs the frame little more than the copies.
+
+use std::{collections::HashSet, sync::mpsc::Receiver, time::Duration};
+
+use crate::{
+    chunk_loader::ChunkLoader,
+    events::WorldEvent,
+    types::{ChunkPosition, ColumnPosition, World},
+};
+
+/// Saves the columns of a world changed since the last autosave every
+/// `interval`. Columns unloaded meanwhile were saved by the loader already.
+pub struct Autosave {
+    pub interval: Duration,
+    /// Time since the last autosave.
+    elapsed: Duration,
+    dirty: HashSet<ColumnPosition>,
+    world_events: Receiver<WorldEvent>,
+}
+
+fn column_of(section: ChunkPosition) -> ColumnPosition {
+    ColumnPosition {
+        x: section.x,
+        z: section.z,
+    }
+}
+
+impl Autosave {
+    pub fn new(world: &mut World, interval: Duration) -> Self {
+        Self {
+            interval,
+            elapsed: Duration::ZERO,
+            dirty: HashSet::new(),
+            world_events: world.events.subscribe(),
+        }
+    }
+
+    /// Columns changed since the last autosave.
+    pub fn dirty(&self) -> usize {
+        self.dirty.len()
+    }
+
+    /// Queues the changed columns for saving once `interval` has passed,
+    /// returning how many were queued.
+    pub fn update(&mut self, world: &World, loader: &mut ChunkLoader, elapsed: Duration) -> usize {
+        for event in self.world_events.try_iter() {
+            match event {
+                WorldEvent::BlockChanged { position, .. } => {
+                    self.dirty
+                        .insert(column_of(ChunkPosition::of_block(position)));
+                }
+                WorldEvent::ChunkUnloaded(section) => {
+                    self.dirty.remove(&column_of(section));
+                }
+                WorldEvent::ChunkLoaded(_) => {}
+            }
+        }
+        self.elapsed += elapsed;
+        if self.elapsed < self.interval {
+            return 0;
+        }
+        self.elapsed = Duration::ZERO;
+        let count = self.dirty.len();
+        loader.save_columns(world, self.dirty.drain());
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        biome::BiomeRegistry,
+        storage::RegionStorage,
+        types::BlockRegistry,
+        worldgen::{WorldGenerator, WorldSeed},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_autosave() {
+        let directory =
+            std::env::temp_dir().join(format!("block-world-autosave-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let block_registry = BlockRegistry::default();
+        let generator =
+            WorldGenerator::new(WorldSeed(1), &block_registry, BiomeRegistry::default());
+        let storage = RegionStorage::new(&directory, &block_registry).unwrap();
+        let mut world = World::new(block_registry);
+        let mut loader = ChunkLoader::new(1, 100, usize::MAX).with_storage(storage);
+        loader.update(&mut world, &generator, [8, 64, 8]);
+
+        let mut autosave = Autosave::new(&mut world, Duration::from_secs(10));
+        world.set_block([1, 200, 1], 1);
+        world.set_block([2, 200, 1], 1);
+        world.set_block([20, 200, 1], 1);
+        assert_eq!(
+            autosave.update(&world, &mut loader, Duration::from_secs(5)),
+            0
+        );
+        assert_eq!(autosave.dirty(), 2);
+        assert_eq!(
+            autosave.update(&world, &mut loader, Duration::from_secs(5)),
+            2
+        );
+        assert_eq!(autosave.dirty(), 0);
+
+        // Changes after the copies were taken are not saved
+        world.set_block([1, 200, 1], 2);
+        loader.flush();
+        assert_eq!(loader.saving(), 0);
+        drop(loader);
+        let mut storage = RegionStorage::new(&directory, &world.block_registry).unwrap();
+        let saved = storage
+            .load_column(ColumnPosition { x: 0, z: 0 })
+            .unwrap()
+            .unwrap();
+        let (_, section) = saved.iter().find(|(position, _)| position.y == 12).unwrap();
+        assert_eq!(section.blocks[200 - 192][1][1], 1);
+        assert!(storage
+            .load_column(ColumnPosition { x: 0, z: 1 })
+            .unwrap()
+            .is_none());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,20 @@
+//! The dedicated server, which runs the world for clients started with
+//! `--connect` without opening a window or touching a GPU. It listens on the
+//! address given as its only argument, or on the default one.
+
+use std::env;
+
+use block_world::{net::DEFAULT_ADDRESS, setup::serve};
+use log::info;
+
+fn main() {
+    env::set_var("RUST_LOG", "info");
+    env_logger::init();
+    info!("Starting the block-world server");
+    let args = env::args().collect::<Vec<_>>();
+    match args.as_slice() {
+        [_] => serve(DEFAULT_ADDRESS),
+        [_, address] => serve(address),
+        _ => eprintln!("Usage: block-world-server [address]"),
+    }
+}
@@ -0,0 +1,158 @@
+use indexmap::{indexmap, IndexMap};
+use serde::{Deserialize, Serialize};
+
+use crate::types::Tint;
+
+pub type BiomeId = usize;
+
+/// RGB color multiplied onto grayscale block textures.
+pub type TintColor = [u8; 3];
+
+/// Tint colors of a single column of the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BiomeColors {
+    pub grass: TintColor,
+    pub foliage: TintColor,
+}
+
+impl BiomeColors {
+    pub const PLAINS: Self = Self {
+        grass: [145, 189, 89],
+        foliage: [119, 171, 47],
+    };
+
+    pub fn tint_color(&self, tint: Tint) -> Option<TintColor> {
+        match tint {
+            Tint::None => None,
+            Tint::Grass => Some(self.grass),
+            Tint::Foliage => Some(self.foliage),
+        }
+    }
+}
+
+impl Default for BiomeColors {
+    fn default() -> Self {
+        Self::PLAINS
+    }
+}
+
+/// Packs a tint color as RGBA8 for `unpackUnorm4x8` in the shaders. Untinted
+/// blocks are packed as white.
+pub fn pack_tint_color(color: Option<TintColor>) -> u32 {
+    let [r, g, b] = color.unwrap_or([255, 255, 255]);
+    u32::from_le_bytes([r, g, b, 255])
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Biome {
+    pub name: String,
+    /// Climate the biome is selected for, both in the range of the climate
+    /// noise (`-1.0..=1.0`).
+    pub temperature: f32,
+    pub humidity: f32,
+    /// Name of the block type covering the surface.
+    pub surface_block: String,
+    /// Name of the block type in the few layers below the surface.
+    pub subsurface_block: String,
+    /// Average number of trees per chunk.
+    pub tree_density: f32,
+    pub colors: BiomeColors,
+}
+
+#[derive(Debug, Clone)]
+pub struct BiomeRegistry {
+    pub biomes: IndexMap<String, Biome>,
+}
+
+impl Default for BiomeRegistry {
+    fn default() -> Self {
+        let biomes = indexmap! {
+            "plains".to_string() => Biome {
+                name: "plains".to_string(),
+                temperature: 0.2,
+                humidity: 0.0,
+                surface_block: "grass".to_string(),
+                subsurface_block: "dirt".to_string(),
+                tree_density: 0.2,
+                colors: BiomeColors::PLAINS,
+            },
+            "forest".to_string() => Biome {
+                name: "forest".to_string(),
+                temperature: 0.1,
+                humidity: 0.6,
+                surface_block: "grass".to_string(),
+                subsurface_block: "dirt".to_string(),
+                tree_density: 5.0,
+                colors: BiomeColors {
+                    grass: [121, 192, 90],
+                    foliage: [89, 174, 48],
+                },
+            },
+            "desert".to_string() => Biome {
+                name: "desert".to_string(),
+                temperature: 0.8,
+                humidity: -0.6,
+                surface_block: "sand".to_string(),
+                subsurface_block: "sand".to_string(),
+                tree_density: 0.0,
+                colors: BiomeColors {
+                    grass: [191, 183, 85],
+                    foliage: [174, 164, 42],
+                },
+            },
+            "tundra".to_string() => Biome {
+                name: "tundra".to_string(),
+                temperature: -0.7,
+                humidity: 0.0,
+                surface_block: "grass".to_string(),
+                subsurface_block: "dirt".to_string(),
+                tree_density: 0.5,
+                colors: BiomeColors {
+                    grass: [128, 180, 151],
+                    foliage: [96, 161, 123],
+                },
+            },
+        };
+
+        Self { biomes }
+    }
+}
+
+impl BiomeRegistry {
+    /// Selects the biome whose climate is closest to the given one.
+    pub fn select(&self, temperature: f32, humidity: f32) -> BiomeId {
+        self.biomes
+            .values()
+            .enumerate()
+            .map(|(id, biome)| {
+                let dt = biome.temperature - temperature;
+                let dh = biome.humidity - humidity;
+                (id, dt * dt + dh * dh)
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(id, _)| id)
+            .expect("biome registry is empty")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_closest_climate() {
+        let biome_registry = BiomeRegistry::default();
+        let name = |id: BiomeId| biome_registry.biomes[id].name.as_str();
+
+        assert_eq!(name(biome_registry.select(0.9, -0.9)), "desert");
+        assert_eq!(name(biome_registry.select(0.0, 0.9)), "forest");
+        assert_eq!(name(biome_registry.select(-1.0, 0.0)), "tundra");
+        assert_eq!(name(biome_registry.select(0.2, 0.0)), "plains");
+    }
+
+    #[test]
+    fn test_pack_tint_color() {
+        assert_eq!(pack_tint_color(None), 0xffff_ffff);
+        assert_eq!(pack_tint_color(Some([0x12, 0x34, 0x56])), 0xff56_3412);
+    }
+}
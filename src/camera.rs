@@ -0,0 +1,258 @@
+//! How the world is seen: from the player's eyes or orbiting behind them,
+//! and projected onto the screen with the clip planes and the field of view,
+//! which eases wider while sprinting and narrower while zooming.
+
+use cgmath::{perspective, Deg, InnerSpace, Matrix4, Point3, Rad, Vector3};
+
+use crate::types::World;
+
+/// Field of view while sprinting, relative to the one set.
+const SPRINT_FOV_SCALE: f32 = 1.15;
+/// Field of view while zooming, unless the one set is narrower.
+const ZOOM_FOVY: Deg<f32> = Deg(15.0);
+/// Widest field of view, past which the projection degenerates.
+pub const MAX_FOVY: Deg<f32> = Deg(170.0);
+/// How quickly the field of view follows its target: the gap shrinks by a
+/// factor of e every `1 / FOV_EASING` seconds.
+const FOV_EASING: f32 = 12.0;
+/// How much faster the camera moves while sprinting.
+pub const SPRINT_SPEED: f32 = 2.0;
+/// Blocks between the player and the third-person camera.
+pub const ORBIT_DISTANCE: f32 = 4.0;
+/// Blocks kept between the third-person camera and the terrain behind the
+/// player, so the near plane does not cut into it.
+const ORBIT_MARGIN: f32 = 0.3;
+
+/// How distances map to depth. Fixed once the renderer is created, as the
+/// pipelines, the Hi-Z pyramid and FSR are set up for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DepthMode {
+    /// From 0 at the near plane to 1 at the far plane.
+    #[default]
+    Standard,
+    /// From 1 at the near plane towards 0 at infinity, so there is no far
+    /// plane to tune for long render distances. Paired with a float depth
+    /// buffer, whose precision then falls off with distance like the
+    /// projection's does.
+    InfiniteReversed,
+}
+
+impl DepthMode {
+    pub fn is_reversed(self) -> bool {
+        self == DepthMode::InfiniteReversed
+    }
+
+    /// The depth where nothing was drawn, which depth buffers are cleared
+    /// to.
+    pub fn far_depth(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::InfiniteReversed => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CameraMode {
+    /// From the player's eyes, with the held block in view.
+    #[default]
+    FirstPerson,
+    /// Behind the player, looking the same way.
+    ThirdPerson,
+}
+
+impl CameraMode {
+    pub fn toggled(self) -> Self {
+        match self {
+            CameraMode::FirstPerson => CameraMode::ThirdPerson,
+            CameraMode::ThirdPerson => CameraMode::FirstPerson,
+        }
+    }
+
+    /// Where the camera is for a player with their eyes at `eye`, looking
+    /// along `forward`. Behind the player it comes closer where blocks are
+    /// in the way, so it never looks from inside the terrain.
+    pub fn position(self, world: &World, eye: Point3<f32>, forward: Vector3<f32>) -> Point3<f32> {
+        match self {
+            CameraMode::FirstPerson => eye,
+            CameraMode::ThirdPerson => {
+                let back = -forward.normalize();
+                let distance = world
+                    .raycast(eye.into(), back.into(), ORBIT_DISTANCE + ORBIT_MARGIN)
+                    .map_or(ORBIT_DISTANCE, |hit| {
+                        (hit.distance - ORBIT_MARGIN).clamp(0.0, ORBIT_DISTANCE)
+                    });
+                eye + back * distance
+            }
+        }
+    }
+}
+
+/// The projection parameters, which can change at runtime.
+#[derive(Debug, Clone)]
+pub struct CameraSettings {
+    /// Vertical field of view when neither sprinting nor zooming.
+    pub fovy: Deg<f32>,
+    pub near: f32,
+    /// Ignored with an infinite `depth_mode`.
+    pub far: f32,
+    pub depth_mode: DepthMode,
+    pub sprinting: bool,
+    pub zooming: bool,
+    pub mode: CameraMode,
+    /// The field of view on its way to `target_fovy`.
+    current_fovy: Deg<f32>,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        let fovy = Deg(60.0);
+        Self {
+            fovy,
+            near: 0.1,
+            far: 200.0,
+            depth_mode: DepthMode::Standard,
+            sprinting: false,
+            zooming: false,
+            mode: CameraMode::FirstPerson,
+            current_fovy: fovy,
+        }
+    }
+}
+
+impl CameraSettings {
+    /// The field of view eased towards.
+    pub fn target_fovy(&self) -> Deg<f32> {
+        if self.zooming {
+            Deg(ZOOM_FOVY.0.min(self.fovy.0))
+        } else if self.sprinting {
+            Deg((self.fovy.0 * SPRINT_FOV_SCALE).min(MAX_FOVY.0))
+        } else {
+            self.fovy
+        }
+    }
+
+    /// Eases the field of view `delta` seconds further.
+    pub fn update(&mut self, delta: f32) {
+        let target = self.target_fovy();
+        let t = 1.0 - (-delta * FOV_EASING).exp();
+        self.current_fovy += (target - self.current_fovy) * t;
+    }
+
+    /// The field of view of the current frame.
+    pub fn current_fovy(&self) -> Deg<f32> {
+        self.current_fovy
+    }
+
+    /// The distance to the far plane, infinite if there is none.
+    pub fn far_plane(&self) -> f32 {
+        match self.depth_mode {
+            DepthMode::Standard => self.far,
+            DepthMode::InfiniteReversed => f32::INFINITY,
+        }
+    }
+
+    /// The projection of the current frame onto an image of `aspect`.
+    pub fn projection(&self, aspect: f32) -> Matrix4<f32> {
+        match self.depth_mode {
+            DepthMode::Standard => perspective(self.current_fovy, aspect, self.near, self.far),
+            DepthMode::InfiniteReversed => {
+                // Like `perspective` in x and y, with the depth `near / -z`
+                let f = 1.0 / (Rad::from(self.current_fovy).0 / 2.0).tan();
+                #[rustfmt::skip]
+                let projection = Matrix4::new(
+                    f / aspect, 0.0, 0.0, 0.0,
+                    0.0, f, 0.0, 0.0,
+                    0.0, 0.0, 0.0, -1.0,
+                    0.0, 0.0, self.near, 0.0,
+                );
+                projection
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector4;
+
+    use crate::types::BlockRegistry;
+
+    use super::*;
+
+    #[test]
+    fn test_fov_easing() {
+        let mut settings = CameraSettings::default();
+        settings.update(1.0);
+        assert_eq!(settings.current_fovy(), settings.fovy);
+
+        settings.zooming = true;
+        settings.update(0.05);
+        let fovy = settings.current_fovy();
+        assert!(fovy < settings.fovy && fovy > ZOOM_FOVY);
+        // The same time in smaller steps eases the same way
+        let mut stepped = CameraSettings {
+            zooming: true,
+            ..Default::default()
+        };
+        for _ in 0..5 {
+            stepped.update(0.01);
+        }
+        assert!((stepped.current_fovy().0 - fovy.0).abs() < 1e-3);
+
+        settings.update(2.0);
+        assert!((settings.current_fovy().0 - ZOOM_FOVY.0).abs() < 1e-3);
+        settings.zooming = false;
+        settings.sprinting = true;
+        settings.update(2.0);
+        assert!((settings.current_fovy().0 - 69.0).abs() < 1e-3);
+
+        let projection = settings.projection(2.0);
+        assert!((projection.y.y / projection.x.x - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_infinite_reversed_depth() {
+        let settings = CameraSettings {
+            depth_mode: DepthMode::InfiniteReversed,
+            ..Default::default()
+        };
+        let standard = CameraSettings::default();
+        let projection = settings.projection(1.5);
+        let depth = |z: f32| {
+            let clip = projection * Vector4::new(0.0, 0.0, -z, 1.0);
+            clip.z / clip.w
+        };
+        assert!((depth(settings.near) - 1.0).abs() < 1e-6);
+        assert!(depth(1e6) > 0.0 && depth(1e6) < 1e-6);
+        assert!(depth(10.0) > depth(100.0));
+        assert_eq!(settings.far_plane(), f32::INFINITY);
+
+        // x and y project as with a far plane
+        let point = Vector4::new(1.0, 2.0, -5.0, 1.0);
+        let [clip, standard_clip] = [projection, standard.projection(1.5)].map(|m| m * point);
+        assert!((clip.x / clip.w - standard_clip.x / standard_clip.w).abs() < 1e-6);
+        assert!((clip.y / clip.w - standard_clip.y / standard_clip.w).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_orbit() {
+        let mut world = World::new(BlockRegistry::default());
+        let eye = Point3::new(0.5, 10.5, 0.5);
+        let forward = Vector3::new(2.0, 0.0, 0.0);
+        assert_eq!(CameraMode::FirstPerson.position(&world, eye, forward), eye);
+        let mode = CameraMode::FirstPerson.toggled();
+        assert_eq!(
+            mode.position(&world, eye, forward),
+            Point3::new(0.5 - ORBIT_DISTANCE, 10.5, 0.5)
+        );
+
+        // A wall behind the player pulls the camera in front of it
+        world.fill_cuboid([-3, 5, -3], [-2, 15, 4], 1);
+        let position = mode.position(&world, eye, forward);
+        assert!((position.x - (-2.0 + ORBIT_MARGIN)).abs() < 1e-5);
+        // Right against the wall, it is at the player's eyes
+        let eye = Point3::new(-1.9, 10.5, 0.5);
+        assert_eq!(mode.position(&world, eye, forward), eye);
+    }
+}
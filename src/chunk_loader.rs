@@ -0,0 +1,443 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::Path,
+};
+
+use log::warn;
+
+use crate::{
+    storage::{ColumnWriter, RegionStorage},
+    types::{Chunk, ChunkPosition, ColumnPosition, World},
+    worldgen::WorldGenerator,
+};
+
+/// What changed in the world during a `ChunkLoader::update`.
+#[derive(Debug, Default)]
+pub struct LoaderUpdate {
+    /// Columns that came into render distance.
+    pub entered: Vec<ColumnPosition>,
+    /// Columns that left render distance. They may still be loaded.
+    pub left: Vec<ColumnPosition>,
+    /// Sections dropped from the world to stay within `max_cached_columns`.
+    /// They were queued for saving before, if the loader has storage.
+    pub evicted: Vec<(ChunkPosition, Chunk)>,
+}
+
+impl LoaderUpdate {
+    /// Adds what changed in the `later` update, as if both were one. A column
+    /// that entered render distance in one and left it in the other did
+    /// neither.
+    pub fn merge(&mut self, later: LoaderUpdate) {
+        for column in later.entered {
+            match self.left.iter().position(|&left| left == column) {
+                Some(i) => {
+                    self.left.swap_remove(i);
+                }
+                None => self.entered.push(column),
+            }
+        }
+        for column in later.left {
+            match self.entered.iter().position(|&entered| entered == column) {
+                Some(i) => {
+                    self.entered.swap_remove(i);
+                }
+                None => self.left.push(column),
+            }
+        }
+        self.evicted.extend(later.evicted);
+    }
+}
+
+/// Keeps the columns around the camera loaded. Columns within
+/// `render_distance` are generated (at most `generate_budget` per update,
+/// nearest first); columns outside it stay cached until there are more than
+/// `max_cached_columns` of them, at which point the least recently used ones
+/// are unloaded. With storage, unloaded columns are saved and loaded back
+/// instead of being generated again. Saving happens in the background, see
+/// `ColumnWriter`.
+pub struct ChunkLoader {
+    /// Radius in columns.
+    pub render_distance: i32,
+    /// Loaded columns kept outside render distance.
+    pub max_cached_columns: usize,
+    pub generate_budget: usize,
+
+    visible: HashSet<ColumnPosition>,
+    last_used: HashMap<ColumnPosition, u64>,
+    tick: u64,
+    storage: Option<ColumnWriter>,
+}
+
+impl ChunkLoader {
+    pub fn new(render_distance: i32, max_cached_columns: usize, generate_budget: usize) -> Self {
+        Self {
+            render_distance,
+            max_cached_columns,
+            generate_budget,
+            visible: HashSet::new(),
+            last_used: HashMap::new(),
+            tick: 0,
+            storage: None,
+        }
+    }
+
+    pub fn with_storage(mut self, storage: RegionStorage) -> Self {
+        self.storage = Some(ColumnWriter::new(storage));
+        self
+    }
+
+    /// Columns within render distance of `center`, nearest first.
+    pub fn columns_in_range(&self, center: ColumnPosition) -> Vec<ColumnPosition> {
+        let r = self.render_distance;
+        let mut columns = (-r..=r)
+            .flat_map(|dx| (-r..=r).map(move |dz| (dx, dz)))
+            .filter(|(dx, dz)| dx * dx + dz * dz <= r * r)
+            .map(|(dx, dz)| ColumnPosition {
+                x: center.x + dx,
+                z: center.z + dz,
+            })
+            .collect::<Vec<_>>();
+        columns.sort_by_key(|column| {
+            let (dx, dz) = (column.x - center.x, column.z - center.z);
+            dx * dx + dz * dz
+        });
+        columns
+    }
+
+    /// Upper bound of the number of columns in render distance at once.
+    pub fn max_visible_columns(&self) -> usize {
+        self.columns_in_range(ColumnPosition { x: 0, z: 0 }).len()
+    }
+
+    /// Columns currently in render distance and loaded.
+    pub fn visible(&self) -> &HashSet<ColumnPosition> {
+        &self.visible
+    }
+
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        generator: &WorldGenerator,
+        center: [i32; 3],
+    ) -> LoaderUpdate {
+        self.tick += 1;
+        let mut update = LoaderUpdate::default();
+
+        let mut budget = self.generate_budget;
+        let mut visible = HashSet::new();
+        for column in self.columns_in_range(ColumnPosition::of_block(center)) {
+            if !world.is_column_loaded(column) {
+                if budget == 0 {
+                    continue;
+                }
+                budget -= 1;
+            }
+            self.load_column(world, generator, column);
+            visible.insert(column);
+            if !self.visible.contains(&column) {
+                update.entered.push(column);
+            }
+        }
+        update
+            .left
+            .extend(self.visible.difference(&visible).copied());
+        update.evicted = self.evict(world, &visible);
+        self.visible = visible;
+
+        update
+    }
+
+    /// Unloads the least recently used of the loaded columns not `in_use`
+    /// while there are more than `max_cached_columns` of them, saving them
+    /// if the loader has storage, and returns their sections. The columns
+    /// `in_use` count as used now.
+    pub fn evict(
+        &mut self,
+        world: &mut World,
+        in_use: &HashSet<ColumnPosition>,
+    ) -> Vec<(ChunkPosition, Chunk)> {
+        for column in in_use {
+            if let Some(last_used) = self.last_used.get_mut(column) {
+                *last_used = self.tick;
+            }
+        }
+        // Loaded from now on counts as used later
+        self.tick += 1;
+
+        let mut evicted = Vec::new();
+        let mut cached = self
+            .last_used
+            .iter()
+            .filter(|(column, _)| !in_use.contains(column))
+            .map(|(&column, &last_used)| (last_used, column))
+            .collect::<Vec<_>>();
+        if cached.len() > self.max_cached_columns {
+            cached.sort_by_key(|&(last_used, column)| (last_used, column.x, column.z));
+
+            let excess = cached.len() - self.max_cached_columns;
+            for (_, column) in cached.into_iter().take(excess) {
+                self.last_used.remove(&column);
+                // Persisted before it is dropped, with whatever was changed
+                self.save_columns(world, [column]);
+                evicted.extend(world.unload_column(column));
+            }
+        }
+        evicted
+    }
+
+    /// Makes sure `column` is loaded, from storage if it was saved there and
+    /// generated otherwise, regardless of the budget. The column counts as
+    /// used now.
+    pub fn load_column(
+        &mut self,
+        world: &mut World,
+        generator: &WorldGenerator,
+        column: ColumnPosition,
+    ) {
+        if !world.is_column_loaded(column) && !self.load_saved_column(world, column) {
+            generator.generate(world, column);
+        }
+        self.last_used.insert(column, self.tick);
+    }
+
+    /// Saves every loaded column to storage, if the loader has storage.
+    pub fn save_all(&mut self, world: &World) {
+        let columns = world
+            .chunks
+            .keys()
+            .map(|position| ColumnPosition {
+                x: position.x,
+                z: position.z,
+            })
+            .collect::<HashSet<_>>();
+        self.save_columns(world, columns);
+    }
+
+    /// Queues copies of the loaded ones of `columns` to be saved in the
+    /// background, if the loader has storage. The world can change right
+    /// away without affecting what is saved.
+    pub fn save_columns(
+        &mut self,
+        world: &World,
+        columns: impl IntoIterator<Item = ColumnPosition>,
+    ) {
+        let Some(storage) = &self.storage else {
+            return;
+        };
+        for column in columns {
+            let sections = column
+                .sections(world.height)
+                .filter_map(|position| Some((position, world.chunks.get(&position)?.clone())))
+                .collect::<Vec<_>>();
+            if !sections.is_empty() {
+                storage.save_column(column, sections);
+            }
+        }
+    }
+
+    /// Number of columns waiting to be written to storage.
+    pub fn saving(&self) -> usize {
+        self.storage.as_ref().map_or(0, ColumnWriter::pending)
+    }
+
+    /// Waits until every column queued for saving is written.
+    pub fn flush(&self) {
+        if let Some(storage) = &self.storage {
+            storage.flush();
+        }
+    }
+
+    /// Saves every loaded column and copies the storage into `destination`,
+    /// see `RegionStorage::snapshot`. Fails without storage.
+    pub fn snapshot(&mut self, world: &World, destination: &Path) -> io::Result<()> {
+        self.save_all(world);
+        self.writer()?
+            .with_storage(|storage| storage.snapshot(destination))
+    }
+
+    /// Replaces the storage with the snapshot in `source`, see
+    /// `RegionStorage::restore`, and loads the columns in render distance
+    /// from it again. The other loaded columns are dropped without saving
+    /// them. Fails without storage.
+    pub fn restore(
+        &mut self,
+        world: &mut World,
+        generator: &WorldGenerator,
+        source: &Path,
+    ) -> io::Result<()> {
+        let block_registry = &world.block_registry;
+        self.writer()?
+            .with_storage(|storage| storage.restore(source, block_registry))?;
+        let loaded = world
+            .chunks
+            .keys()
+            .map(|position| ColumnPosition {
+                x: position.x,
+                z: position.z,
+            })
+            .collect::<HashSet<_>>();
+        for column in loaded {
+            world.unload_column(column);
+            if self.visible.contains(&column) {
+                self.load_column(world, generator, column);
+            } else {
+                self.last_used.remove(&column);
+            }
+        }
+        Ok(())
+    }
+
+    fn writer(&self) -> io::Result<&ColumnWriter> {
+        self.storage
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no storage"))
+    }
+
+    /// Loads `column` from storage, returning whether it was saved there.
+    /// Columns that fail to load are generated again.
+    fn load_saved_column(&mut self, world: &mut World, column: ColumnPosition) -> bool {
+        let Some(storage) = &self.storage else {
+            return false;
+        };
+        match storage.load_column(column) {
+            Ok(Some(sections)) => {
+                world.insert_chunks(sections);
+                world.is_column_loaded(column)
+            }
+            Ok(None) => false,
+            Err(err) => {
+                warn!("Failed to load column {:?}, generating it: {}", column, err);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{biome::BiomeRegistry, types::BlockRegistry, worldgen::WorldSeed};
+
+    use super::*;
+
+    fn world_and_generator() -> (World, WorldGenerator) {
+        let block_registry = BlockRegistry::default();
+        let generator =
+            WorldGenerator::new(WorldSeed(1), &block_registry, BiomeRegistry::default());
+        (World::new(block_registry), generator)
+    }
+
+    #[test]
+    fn test_generate_budget() {
+        let (mut world, generator) = world_and_generator();
+        let mut loader = ChunkLoader::new(1, 100, 2);
+
+        let update = loader.update(&mut world, &generator, [8, 64, 8]);
+        assert_eq!(update.entered.len(), 2);
+        // The column the center is in comes first
+        assert_eq!(update.entered[0], ColumnPosition { x: 0, z: 0 });
+
+        loader.update(&mut world, &generator, [8, 64, 8]);
+        let update = loader.update(&mut world, &generator, [8, 64, 8]);
+        assert_eq!(update.entered.len(), 1);
+        assert_eq!(loader.visible().len(), loader.max_visible_columns());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used_columns() {
+        let (mut world, generator) = world_and_generator();
+        let mut loader = ChunkLoader::new(0, 1, 1);
+
+        for x in 0..3 {
+            let update = loader.update(&mut world, &generator, [x * 16, 64, 0]);
+            assert_eq!(update.entered, vec![ColumnPosition { x, z: 0 }]);
+            if x > 0 {
+                assert_eq!(update.left, vec![ColumnPosition { x: x - 1, z: 0 }]);
+            }
+            if x < 2 {
+                assert!(update.evicted.is_empty());
+            } else {
+                // Column 0 was used longest ago
+                assert_eq!(update.evicted.len(), world.height.sections().len());
+                assert!(update.evicted.iter().all(|(position, _)| position.x == 0));
+            }
+        }
+
+        assert!(!world.is_column_loaded(ColumnPosition { x: 0, z: 0 }));
+        assert!(world.is_column_loaded(ColumnPosition { x: 1, z: 0 }));
+        assert!(world.is_column_loaded(ColumnPosition { x: 2, z: 0 }));
+
+        // Returning to a cached column doesn't generate it again
+        let update = loader.update(&mut world, &generator, [16, 64, 0]);
+        assert_eq!(update.entered, vec![ColumnPosition { x: 1, z: 0 }]);
+        assert!(update.evicted.is_empty());
+    }
+
+    #[test]
+    fn test_evicts_columns_not_in_use() {
+        let (mut world, generator) = world_and_generator();
+        let mut loader = ChunkLoader::new(0, 1, usize::MAX);
+        let column = |x| ColumnPosition { x, z: 0 };
+        for x in 0..3 {
+            loader.load_column(&mut world, &generator, column(x));
+        }
+
+        // One of the columns not in use stays cached, the one loaded last
+        let in_use = HashSet::from([column(0)]);
+        let evicted = loader.evict(&mut world, &in_use);
+        assert!(evicted.iter().all(|(position, _)| position.x == 1));
+        assert!(world.is_column_loaded(column(0)));
+        assert!(!world.is_column_loaded(column(1)));
+        assert!(world.is_column_loaded(column(2)));
+
+        // Column 2 was used before column 1, which is loaded again
+        loader.load_column(&mut world, &generator, column(1));
+        loader.evict(&mut world, &in_use);
+        assert!(world.is_column_loaded(column(0)));
+        assert!(!world.is_column_loaded(column(2)));
+        assert!(world.is_column_loaded(column(1)));
+    }
+
+    #[test]
+    fn test_evicted_columns_are_saved() {
+        let directory =
+            std::env::temp_dir().join(format!("block-world-evict-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let (mut world, generator) = world_and_generator();
+        let storage = RegionStorage::new(&directory, &world.block_registry).unwrap();
+        let mut loader = ChunkLoader::new(0, 0, usize::MAX).with_storage(storage);
+
+        loader.update(&mut world, &generator, [8, 64, 8]);
+        world.set_block([1, 200, 1], 1);
+        let update = loader.update(&mut world, &generator, [40, 64, 8]);
+        assert!(update.evicted.iter().all(|(position, _)| position.x == 0));
+        assert!(!world.is_column_loaded(ColumnPosition { x: 0, z: 0 }));
+
+        // Coming back loads the change from storage
+        loader.flush();
+        loader.update(&mut world, &generator, [8, 64, 8]);
+        assert_eq!(world[[1, 200, 1]], 1);
+        drop(loader);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_merge_updates() {
+        let column = |x| ColumnPosition { x, z: 0 };
+        let mut update = LoaderUpdate {
+            entered: vec![column(0), column(1)],
+            left: vec![column(2)],
+            evicted: Vec::new(),
+        };
+        update.merge(LoaderUpdate {
+            entered: vec![column(2), column(3)],
+            left: vec![column(1)],
+            evicted: Vec::new(),
+        });
+        // Columns 1 and 2 came and went, or went and came back
+        assert_eq!(update.entered, vec![column(0), column(3)]);
+        assert!(update.left.is_empty());
+    }
+}
@@ -0,0 +1,678 @@
+//! The in-game console: a line of input opened over the game, with a history
+//! of submitted lines, tab completion of commands and block names, and the
+//! commands it understands.
+
+use std::collections::VecDeque;
+
+use crate::{
+    camera::MAX_FOVY,
+    edit::{Brush, Mask},
+    settings::FsrSettings,
+    types::{BlockRegistry, BlockTypeId},
+    worldgen::structure::Rotation,
+};
+
+/// Output lines kept, the oldest are dropped first.
+const MAX_OUTPUT: usize = 100;
+/// Blocks one `/fill` may set, `/brush` paint over or `/schem save` copy, so
+/// a typo does not stall the game.
+pub const MAX_FILL_VOLUME: i64 = 32768;
+/// Every command with its usage.
+const COMMANDS: [(&str, &str); 14] = [
+    (
+        "brush",
+        "/brush sphere <x> <y> <z> <radius>, /brush cylinder <x> <y> <z> <radius> <height>, /brush line|cuboid <x1> <y1> <z1> <x2> <y2> <z2>, then <block> [<replaced block>]",
+    ),
+    ("clip", "/clip <near> <far>"),
+    ("fill", "/fill <x1> <y1> <z1> <x2> <y2> <z2> <block>"),
+    ("fov", "/fov <degrees>"),
+    (
+        "fsr",
+        "/fsr sharpening|autoexposure|debug on|off, /fsr sharpness <0-1>",
+    ),
+    ("redo", "/redo"),
+    ("restore", "/restore <name>"),
+    (
+        "schem",
+        "/schem save <name> <x1> <y1> <z1> <x2> <y2> <z2>, /schem load <name> <x> <y> <z> [0|90|180|270]",
+    ),
+    ("seed", "/seed"),
+    ("setblock", "/setblock <x> <y> <z> <block>"),
+    ("snapshot", "/snapshot <name>"),
+    ("timescale", "/timescale <scale>"),
+    ("tp", "/tp <x> <y> <z>"),
+    ("undo", "/undo"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Moves the camera to the position.
+    Teleport([f32; 3]),
+    /// Sets every block from `min` to `max`, exclusive, see
+    /// `World::fill_cuboid`.
+    Fill {
+        min: [i32; 3],
+        max: [i32; 3],
+        block_type_id: BlockTypeId,
+    },
+    /// Shows the seed of the world.
+    Seed,
+    SetBlock {
+        position: [i32; 3],
+        block_type_id: BlockTypeId,
+    },
+    /// Speeds up or slows down the simulation by the factor.
+    TimeScale(f32),
+    /// Sets the vertical field of view, in degrees.
+    Fov(f32),
+    /// Sets the near and far clip planes.
+    Clip {
+        near: f32,
+        far: f32,
+    },
+    Fsr(FsrOption),
+    /// Saves a snapshot of the world under the name.
+    Snapshot(String),
+    /// Brings the world back to the snapshot of the name.
+    Restore(String),
+    /// Saves the blocks from `min` to `max`, exclusive, as the schematic of
+    /// the name.
+    SaveSchematic {
+        name: String,
+        min: [i32; 3],
+        max: [i32; 3],
+    },
+    /// Pastes the schematic of the name with its origin at `origin`.
+    LoadSchematic {
+        name: String,
+        origin: [i32; 3],
+        rotation: Rotation,
+    },
+    /// Paints the blocks under the brush that the mask matches, as one edit.
+    Brush {
+        brush: Brush,
+        block_type_id: BlockTypeId,
+        mask: Mask,
+    },
+    /// Reverts the last edit of a brush.
+    Undo,
+    /// Makes the last undone edit again.
+    Redo,
+}
+
+/// One of the `FsrSettings` set by `/fsr`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsrOption {
+    Sharpening(bool),
+    Sharpness(f32),
+    AutoExposure(bool),
+    DebugChecking(bool),
+}
+
+impl FsrOption {
+    pub fn apply(self, settings: &mut FsrSettings) {
+        match self {
+            FsrOption::Sharpening(on) => settings.sharpening = on,
+            FsrOption::Sharpness(sharpness) => settings.sharpness = sharpness,
+            FsrOption::AutoExposure(on) => settings.auto_exposure = on,
+            FsrOption::DebugChecking(on) => settings.debug_checking = on,
+        }
+    }
+}
+
+/// Parses a line typed into the console, with or without its leading `/`.
+/// The error explains what is wrong, for the console's output.
+pub fn parse_command(line: &str, block_registry: &BlockRegistry) -> Result<Command, String> {
+    let mut words = line.trim().trim_start_matches('/').split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let args = words.collect::<Vec<_>>();
+    let Some(&(_, usage)) = COMMANDS.iter().find(|(command, _)| *command == name) else {
+        let names = COMMANDS.map(|(command, _)| format!("/{}", command));
+        return Err(format!(
+            "Unknown command {:?}, try {}",
+            name,
+            names.join(", ")
+        ));
+    };
+    let usage = || format!("Usage: {}", usage);
+    let number = |arg: &str| arg.parse::<f32>().ok().filter(|v| v.is_finite());
+    let coordinates = |args: &[&str]| -> Option<[i32; 3]> {
+        Some([
+            args[0].parse().ok()?,
+            args[1].parse().ok()?,
+            args[2].parse().ok()?,
+        ])
+    };
+    let switch = |arg: &str| match arg {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    };
+    // Names of snapshots and schematics are file names
+    let file_name = |name: &str| {
+        let valid = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        valid.then(|| name.to_string()).ok_or_else(usage)
+    };
+    let within_limit = |volume: i64| {
+        if volume > MAX_FILL_VOLUME {
+            return Err(format!(
+                "Cannot take {} blocks, at most {}",
+                volume, MAX_FILL_VOLUME
+            ));
+        }
+        Ok(())
+    };
+    // The cuboid from corner to corner, both included, as its lowest corner
+    // and the one past its highest
+    let cuboid = |from: [i32; 3], to: [i32; 3]| {
+        let min = [0, 1, 2].map(|i| from[i].min(to[i]));
+        let max = [0, 1, 2].map(|i| from[i].max(to[i]) as i64 + 1);
+        // In i64, as the extents of corners far apart overflow i32. Their
+        // product can still overflow i64, so it saturates.
+        within_limit(
+            (0..3)
+                .map(|i| max[i] - min[i] as i64)
+                .fold(1i64, i64::saturating_mul),
+        )?;
+        // The end past the highest corner has to be a coordinate too
+        let [Some(x), Some(y), Some(z)] = max.map(|max| i32::try_from(max).ok()) else {
+            return Err("Coordinates are out of range".to_string());
+        };
+        Ok((min, [x, y, z]))
+    };
+    let block = |name: &str| {
+        block_registry
+            .block_types
+            .get_index_of(name)
+            .ok_or_else(|| format!("Unknown block {:?}", name))
+    };
+
+    match (name, args.as_slice()) {
+        ("tp", &[x, y, z]) => match (number(x), number(y), number(z)) {
+            (Some(x), Some(y), Some(z)) => Ok(Command::Teleport([x, y, z])),
+            _ => Err(usage()),
+        },
+        ("setblock", &[x, y, z, name]) => Ok(Command::SetBlock {
+            position: coordinates(&[x, y, z]).ok_or_else(usage)?,
+            block_type_id: block(name)?,
+        }),
+        ("fill", &[x1, y1, z1, x2, y2, z2, name]) => {
+            let from = coordinates(&[x1, y1, z1]).ok_or_else(usage)?;
+            let to = coordinates(&[x2, y2, z2]).ok_or_else(usage)?;
+            let (min, max) = cuboid(from, to)?;
+            Ok(Command::Fill {
+                min,
+                max,
+                block_type_id: block(name)?,
+            })
+        }
+        ("seed", &[]) => Ok(Command::Seed),
+        ("fov", &[degrees]) => match number(degrees) {
+            Some(degrees) if degrees > 0.0 && degrees <= MAX_FOVY.0 => Ok(Command::Fov(degrees)),
+            _ => Err(usage()),
+        },
+        ("clip", &[near, far]) => match (number(near), number(far)) {
+            (Some(near), Some(far)) if near > 0.0 && far > near => Ok(Command::Clip { near, far }),
+            _ => Err(usage()),
+        },
+        ("timescale", &[scale]) => match number(scale) {
+            Some(scale) if scale >= 0.0 => Ok(Command::TimeScale(scale)),
+            _ => Err(usage()),
+        },
+        ("fsr", &[option, value]) => {
+            let option = match option {
+                "sharpening" => switch(value).map(FsrOption::Sharpening),
+                "autoexposure" => switch(value).map(FsrOption::AutoExposure),
+                "debug" => switch(value).map(FsrOption::DebugChecking),
+                "sharpness" => number(value)
+                    .filter(|sharpness| (0.0..=1.0).contains(sharpness))
+                    .map(FsrOption::Sharpness),
+                _ => None,
+            };
+            option.map(Command::Fsr).ok_or_else(usage)
+        }
+        ("snapshot", &[name]) => Ok(Command::Snapshot(file_name(name)?)),
+        ("restore", &[name]) => Ok(Command::Restore(file_name(name)?)),
+        ("schem", &["save", name, x1, y1, z1, x2, y2, z2]) => {
+            let from = coordinates(&[x1, y1, z1]).ok_or_else(usage)?;
+            let to = coordinates(&[x2, y2, z2]).ok_or_else(usage)?;
+            let (min, max) = cuboid(from, to)?;
+            Ok(Command::SaveSchematic {
+                name: file_name(name)?,
+                min,
+                max,
+            })
+        }
+        ("schem", &["load", name, x, y, z, ref rotation @ ..]) if rotation.len() <= 1 => {
+            let rotation = match rotation.first().copied().unwrap_or("0") {
+                "0" => Rotation::None,
+                "90" => Rotation::Clockwise90,
+                "180" => Rotation::Clockwise180,
+                "270" => Rotation::Clockwise270,
+                _ => return Err(usage()),
+            };
+            Ok(Command::LoadSchematic {
+                name: file_name(name)?,
+                origin: coordinates(&[x, y, z]).ok_or_else(usage)?,
+                rotation,
+            })
+        }
+        ("brush", &[shape, ref args @ ..]) => {
+            let integer = |arg: &str| arg.parse::<i32>().ok().filter(|&n| n >= 0);
+            // The blocks a brush covers at most, and the corners of the box
+            // around it, in i64 and saturating as in `cuboid`
+            let (brush, volume, lowest, highest, rest) = match (shape, args) {
+                ("sphere", &[x, y, z, radius, ref rest @ ..]) => {
+                    let center = coordinates(&[x, y, z]).ok_or_else(usage)?;
+                    let radius = integer(radius).ok_or_else(usage)?;
+                    let r = radius as i64;
+                    (
+                        Brush::Sphere { center, radius },
+                        (2 * r + 1).saturating_pow(3),
+                        center.map(|c| c as i64 - r),
+                        center.map(|c| c as i64 + r),
+                        rest,
+                    )
+                }
+                ("cylinder", &[x, y, z, radius, height, ref rest @ ..]) => {
+                    let base = coordinates(&[x, y, z]).ok_or_else(usage)?;
+                    let radius = integer(radius).ok_or_else(usage)?;
+                    let height = integer(height).ok_or_else(usage)?;
+                    let (r, h) = (radius as i64, height as i64);
+                    (
+                        Brush::Cylinder {
+                            base,
+                            radius,
+                            height,
+                        },
+                        (2 * r + 1).saturating_pow(2).saturating_mul(h),
+                        base.map(|c| c as i64 - r),
+                        [
+                            base[0] as i64 + r,
+                            base[1] as i64 + h - 1,
+                            base[2] as i64 + r,
+                        ],
+                        rest,
+                    )
+                }
+                ("line" | "cuboid", &[x1, y1, z1, x2, y2, z2, ref rest @ ..]) => {
+                    let from = coordinates(&[x1, y1, z1]).ok_or_else(usage)?;
+                    let to = coordinates(&[x2, y2, z2]).ok_or_else(usage)?;
+                    let extents = [0, 1, 2].map(|i| (to[i] as i64 - from[i] as i64).abs() + 1);
+                    let lowest = [0, 1, 2].map(|i| from[i].min(to[i]));
+                    let highest = [0, 1, 2].map(|i| from[i].max(to[i]));
+                    let (brush, volume) = match shape {
+                        "line" => (Brush::Line { from, to }, *extents.iter().max().unwrap()),
+                        _ => (
+                            Brush::Cuboid {
+                                min: lowest,
+                                max: highest,
+                            },
+                            extents.into_iter().fold(1, i64::saturating_mul),
+                        ),
+                    };
+                    (
+                        brush,
+                        volume,
+                        lowest.map(i64::from),
+                        highest.map(i64::from),
+                        rest,
+                    )
+                }
+                _ => return Err(usage()),
+            };
+            within_limit(volume)?;
+            if lowest
+                .iter()
+                .chain(&highest)
+                .any(|&c| i32::try_from(c).is_err())
+            {
+                return Err("Coordinates are out of range".to_string());
+            }
+            let (block_type_id, mask) = match *rest {
+                [name] => (block(name)?, Mask::All),
+                [name, replaced] => (block(name)?, Mask::Only(block(replaced)?)),
+                _ => return Err(usage()),
+            };
+            Ok(Command::Brush {
+                brush,
+                block_type_id,
+                mask,
+            })
+        }
+        ("undo", &[]) => Ok(Command::Undo),
+        ("redo", &[]) => Ok(Command::Redo),
+        _ => Err(usage()),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Console {
+    open: bool,
+    input: String,
+    /// Submitted lines, oldest first, without repeats in a row.
+    history: Vec<String>,
+    /// Entry of `history` shown in the input while browsing it.
+    browsing: Option<usize>,
+    output: VecDeque<String>,
+    /// Lines submitted since the last `take_submitted`.
+    submitted: Vec<String>,
+    /// What tab completes arguments to, in registry order.
+    block_names: Vec<String>,
+}
+
+impl Console {
+    pub fn new(block_registry: &BlockRegistry) -> Self {
+        Self {
+            block_names: block_registry.block_types.keys().cloned().collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Opens the console with `input` already typed, like the `/` of a
+    /// command.
+    pub fn open(&mut self, input: &str) {
+        self.open = true;
+        self.input = input.to_string();
+        self.browsing = None;
+    }
+
+    pub fn close(&mut self) {
+        self.open = false;
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// The output, oldest line first.
+    pub fn output(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.output.iter().map(String::as_str)
+    }
+
+    /// Adds a line to the output.
+    pub fn print(&mut self, line: impl Into<String>) {
+        if self.output.len() == MAX_OUTPUT {
+            self.output.pop_front();
+        }
+        self.output.push_back(line.into());
+    }
+
+    /// Types `text` at the end of the input, leaving out control characters.
+    pub fn type_text(&mut self, text: &str) {
+        self.input.extend(text.chars().filter(|c| !c.is_control()));
+    }
+
+    pub fn backspace(&mut self) {
+        self.input.pop();
+    }
+
+    /// Submits the input for `take_submitted`, echoing it to the output, and
+    /// closes the console.
+    pub fn submit(&mut self) {
+        let line = std::mem::take(&mut self.input).trim().to_string();
+        self.open = false;
+        self.browsing = None;
+        if line.is_empty() {
+            return;
+        }
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        self.print(format!("> {}", line));
+        self.submitted.push(line);
+    }
+
+    pub fn take_submitted(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.submitted)
+    }
+
+    /// Replaces the input with the previous line of the history.
+    pub fn history_previous(&mut self) {
+        let index = match self.browsing {
+            Some(index) => index.saturating_sub(1),
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        self.browsing = Some(index);
+        self.input = self.history[index].clone();
+    }
+
+    /// Replaces the input with the next line of the history, or an empty
+    /// line past its end.
+    pub fn history_next(&mut self) {
+        let Some(index) = self.browsing else {
+            return;
+        };
+        if index + 1 < self.history.len() {
+            self.browsing = Some(index + 1);
+            self.input = self.history[index + 1].clone();
+        } else {
+            self.browsing = None;
+            self.input.clear();
+        }
+    }
+
+    /// Completes the last word of the input: the command name in the first
+    /// word, block names after it. A single match is completed with a space
+    /// after it, several as far as they agree, and then they are listed in
+    /// the output.
+    pub fn complete(&mut self) {
+        let start = self.input.rfind(' ').map_or(0, |space| space + 1);
+        let word = &self.input[start..];
+        let candidates = if start == 0 {
+            let name = word.trim_start_matches('/');
+            COMMANDS
+                .iter()
+                .filter(|(command, _)| command.starts_with(name))
+                .map(|(command, _)| format!("/{}", command))
+                .collect::<Vec<_>>()
+        } else {
+            self.block_names
+                .iter()
+                .filter(|name| name.starts_with(word))
+                .cloned()
+                .collect()
+        };
+
+        match candidates.as_slice() {
+            [] => {}
+            [candidate] => {
+                self.input.replace_range(start.., candidate);
+                self.input.push(' ');
+            }
+            [first, rest @ ..] => {
+                let common = rest.iter().fold(first.as_str(), |common, candidate| {
+                    let len = common
+                        .chars()
+                        .zip(candidate.chars())
+                        .take_while(|(a, b)| a == b)
+                        .map(|(a, _)| a.len_utf8())
+                        .sum();
+                    &common[..len]
+                });
+                if common.len() > word.len() {
+                    let common = common.to_string();
+                    self.input.replace_range(start.., &common);
+                } else {
+                    self.print(candidates.join("  "));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_console() {
+        let block_registry = BlockRegistry::default();
+        let stone = block_registry.block_types.get_index_of("stone").unwrap();
+        let dirt = block_registry.block_types.get_index_of("dirt").unwrap();
+        assert_eq!(
+            parse_command("/tp 1 2.5 -3", &block_registry),
+            Ok(Command::Teleport([1.0, 2.5, -3.0]))
+        );
+        assert_eq!(
+            parse_command("/fill 3 5 0 1 5 2 stone", &block_registry),
+            Ok(Command::Fill {
+                min: [1, 5, 0],
+                max: [4, 6, 3],
+                block_type_id: stone,
+            })
+        );
+        assert_eq!(
+            parse_command("setblock 0 64 0 stone", &block_registry),
+            Ok(Command::SetBlock {
+                position: [0, 64, 0],
+                block_type_id: stone,
+            })
+        );
+        assert_eq!(parse_command("/seed", &block_registry), Ok(Command::Seed));
+        assert_eq!(
+            parse_command("/timescale 0.5", &block_registry),
+            Ok(Command::TimeScale(0.5))
+        );
+        assert_eq!(
+            parse_command("/fov 90", &block_registry),
+            Ok(Command::Fov(90.0))
+        );
+        assert_eq!(
+            parse_command("/clip 0.05 1000", &block_registry),
+            Ok(Command::Clip {
+                near: 0.05,
+                far: 1000.0
+            })
+        );
+        assert_eq!(
+            parse_command("/fsr sharpness 0.25", &block_registry),
+            Ok(Command::Fsr(FsrOption::Sharpness(0.25)))
+        );
+        assert_eq!(
+            parse_command("/fsr autoexposure off", &block_registry),
+            Ok(Command::Fsr(FsrOption::AutoExposure(false)))
+        );
+        assert_eq!(
+            parse_command("/snapshot before-fill_2", &block_registry),
+            Ok(Command::Snapshot("before-fill_2".to_string()))
+        );
+        assert_eq!(
+            parse_command("/restore before-fill_2", &block_registry),
+            Ok(Command::Restore("before-fill_2".to_string()))
+        );
+        assert_eq!(
+            parse_command("/schem save hut 2 0 2 0 3 0", &block_registry),
+            Ok(Command::SaveSchematic {
+                name: "hut".to_string(),
+                min: [0, 0, 0],
+                max: [3, 4, 3],
+            })
+        );
+        assert_eq!(
+            parse_command("/schem load hut 10 64 -5 90", &block_registry),
+            Ok(Command::LoadSchematic {
+                name: "hut".to_string(),
+                origin: [10, 64, -5],
+                rotation: Rotation::Clockwise90,
+            })
+        );
+        assert_eq!(
+            parse_command("/brush sphere 0 64 0 3 sand stone", &block_registry),
+            Ok(Command::Brush {
+                brush: Brush::Sphere {
+                    center: [0, 64, 0],
+                    radius: 3,
+                },
+                block_type_id: block_registry.block_types.get_index_of("sand").unwrap(),
+                mask: Mask::Only(stone),
+            })
+        );
+        assert_eq!(
+            parse_command("/brush cuboid 3 5 0 1 5 2 dirt", &block_registry),
+            Ok(Command::Brush {
+                brush: Brush::Cuboid {
+                    min: [1, 5, 0],
+                    max: [3, 5, 2],
+                },
+                block_type_id: dirt,
+                mask: Mask::All,
+            })
+        );
+        assert_eq!(parse_command("/undo", &block_registry), Ok(Command::Undo));
+        assert_eq!(parse_command("/redo", &block_registry), Ok(Command::Redo));
+        for line in [
+            "/brush sphere 0 0 0 -1 stone",
+            "/brush sphere 0 0 0 100 stone",
+            "/brush cylinder 0 0 0 1 2 stone dirt sand",
+            "/brush line 0 0 0 1000000 0 0 stone",
+            "/brush sphere 2147483647 0 0 1 stone",
+            "/brush cuboid 0 0 0 1 1 1",
+            "/brush cone 0 0 0 1 stone",
+            "/schem load hut 0 0 0 45",
+            "/schem save ../hut 0 0 0 1 1 1",
+            "/tp 1 2",
+            "/snapshot ../region",
+            "/restore",
+            "/fsr sharpness 2",
+            "/fsr sharpening yes",
+            "/tp 1 2 nan",
+            "/timescale -1",
+            "/fov 180",
+            "/clip 10 1",
+            "/setblock 0 0 0 cheese",
+            "/fill 0 0 0 100 100 100 stone",
+            "/fill -2147483648 0 0 2147483647 0 0 stone",
+            "/fill 2147483647 0 0 2147483647 0 0 stone",
+            "/dance",
+        ] {
+            assert!(parse_command(line, &block_registry).is_err(), "{}", line);
+        }
+
+        let mut console = Console::new(&block_registry);
+        console.open("/");
+        console.type_text("se");
+        console.complete();
+        // "/seed" and "/setblock" agree on "/se" only, so both are listed
+        assert_eq!(console.input(), "/se");
+        assert_eq!(console.output().last(), Some("/seed  /setblock"));
+        console.type_text("t\t");
+        console.complete();
+        assert_eq!(console.input(), "/setblock ");
+        console.type_text("0 64 0 di");
+        console.complete();
+        assert_eq!(console.input(), "/setblock 0 64 0 dirt ");
+        console.submit();
+        assert!(!console.is_open());
+        let submitted = console.take_submitted();
+        assert_eq!(submitted, ["/setblock 0 64 0 dirt"]);
+        assert_eq!(
+            parse_command(&submitted[0], &block_registry),
+            Ok(Command::SetBlock {
+                position: [0, 64, 0],
+                block_type_id: dirt,
+            })
+        );
+        assert!(console.take_submitted().is_empty());
+
+        console.open("");
+        console.type_text("/seed");
+        console.submit();
+        console.open("");
+        console.history_previous();
+        assert_eq!(console.input(), "/seed");
+        console.history_previous();
+        console.history_previous();
+        assert_eq!(console.input(), "/setblock 0 64 0 dirt");
+        console.history_next();
+        console.history_next();
+        assert_eq!(console.input(), "");
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::{
+    events::WorldEvent,
+    types::{BlockTypeId, ChunkPosition, World},
+};
+
+/// The blocks an edit paints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Brush {
+    Sphere {
+        center: [i32; 3],
+        radius: i32,
+    },
+    /// An upright cylinder standing on the block `base`.
+    Cylinder {
+        base: [i32; 3],
+        radius: i32,
+        height: i32,
+    },
+    /// A 26-connected line of blocks, both ends included.
+    Line {
+        from: [i32; 3],
+        to: [i32; 3],
+    },
+    /// The blocks from `min` to `max`, both included.
+    Cuboid {
+        min: [i32; 3],
+        max: [i32; 3],
+    },
+}
+
+impl Brush {
+    pub fn positions(&self) -> Vec<[i32; 3]> {
+        match *self {
+            Brush::Sphere { center, radius } => {
+                let r = radius;
+                let mut positions = Vec::new();
+                for dx in -r..=r {
+                    for dy in -r..=r {
+                        for dz in -r..=r {
+                            if dx * dx + dy * dy + dz * dz <= r * r {
+                                positions.push([center[0] + dx, center[1] + dy, center[2] + dz]);
+                            }
+                        }
+                    }
+                }
+                positions
+            }
+            Brush::Cylinder {
+                base,
+                radius,
+                height,
+            } => {
+                let r = radius;
+                let mut positions = Vec::new();
+                for dx in -r..=r {
+                    for dz in -r..=r {
+                        if dx * dx + dz * dz <= r * r {
+                            positions.extend(
+                                (0..height).map(|dy| [base[0] + dx, base[1] + dy, base[2] + dz]),
+                            );
+                        }
+                    }
+                }
+                positions
+            }
+            Brush::Line { from, to } => {
+                let delta = [0, 1, 2].map(|i| to[i] - from[i]);
+                let steps = delta.iter().map(|d| d.abs()).max().unwrap();
+                (0..=steps)
+                    .map(|step| {
+                        [0, 1, 2].map(|i| {
+                            let t = step as f32 / steps.max(1) as f32;
+                            from[i] + (delta[i] as f32 * t).round() as i32
+                        })
+                    })
+                    .collect()
+            }
+            Brush::Cuboid { min, max } => (min[0]..=max[0])
+                .flat_map(|x| {
+                    (min[1]..=max[1]).flat_map(move |y| (min[2]..=max[2]).map(move |z| [x, y, z]))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Which of the blocks under a brush it replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mask {
+    All,
+    /// Only blocks of this type, like WorldEdit's `//replace`.
+    Only(BlockTypeId),
+}
+
+impl Mask {
+    fn matches(&self, block_type_id: BlockTypeId) -> bool {
+        match *self {
+            Mask::All => true,
+            Mask::Only(only) => block_type_id == only,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BlockChange {
+    position: [i32; 3],
+    before: BlockTypeId,
+    after: BlockTypeId,
+}
+
+/// The block changes of one transaction, in the order they were made.
+#[derive(Debug, Default)]
+struct Edit {
+    changes: Vec<BlockChange>,
+}
+
+/// Edits made to a world as one step of the history. Blocks are changed in the
+/// world right away, so later edits of the transaction see earlier ones.
+pub struct Transaction<'a> {
+    world: &'a mut World,
+}
+
+impl Transaction<'_> {
+    pub fn world(&self) -> &World {
+        self.world
+    }
+
+    /// Sets the block at `position`. Blocks outside the world height or in
+    /// sections that aren't loaded are left alone, so edits never create
+    /// sections of unloaded columns.
+    pub fn set(&mut self, position: [i32; 3], block_type_id: BlockTypeId) {
+        if !self.world.height.contains(position[1])
+            || !self
+                .world
+                .chunks
+                .contains_key(&ChunkPosition::of_block(position))
+        {
+            return;
+        }
+        self.world.set_block(position, block_type_id);
+    }
+
+    /// Sets the blocks under `brush` that `mask` matches.
+    pub fn paint(&mut self, brush: &Brush, block_type_id: BlockTypeId, mask: Mask) {
+        for position in brush.positions() {
+            if mask.matches(self.world[position]) {
+                self.set(position, block_type_id);
+            }
+        }
+    }
+}
+
+/// Undo and redo stacks of the edits made to a world. The changes of an edit
+/// are the `BlockChanged` events the world emits during it, and like every
+/// change made through `World::set_block`, undos and redos emit them too.
+pub struct EditHistory {
+    /// Edits kept for undoing; the oldest are dropped beyond this.
+    pub max_edits: usize,
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl EditHistory {
+    pub fn new(max_edits: usize) -> Self {
+        Self {
+            max_edits,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    /// Runs `edit` as one transaction, returning how many blocks it changed.
+    /// Unless it changed none, it becomes the edit `undo` reverts and the
+    /// edits that were undone can no longer be redone.
+    pub fn edit(&mut self, world: &mut World, edit: impl FnOnce(&mut Transaction)) -> usize {
+        // Forgotten by the world once dropped
+        let events = world.events.subscribe();
+        edit(&mut Transaction { world });
+
+        let mut edit = Edit::default();
+        // Index into `edit.changes` of the change of every position, which
+        // goes from the block before its first event to the one after its
+        // last
+        let mut indices = HashMap::new();
+        for event in events.try_iter() {
+            let WorldEvent::BlockChanged {
+                position,
+                before,
+                after,
+            } = event
+            else {
+                continue;
+            };
+            match indices.entry(position) {
+                Entry::Occupied(index) => edit.changes[*index.get()].after = after,
+                Entry::Vacant(entry) => {
+                    entry.insert(edit.changes.len());
+                    edit.changes.push(BlockChange {
+                        position,
+                        before,
+                        after,
+                    });
+                }
+            }
+        }
+        // A block set back to what it was is no change
+        edit.changes.retain(|change| change.before != change.after);
+        if edit.changes.is_empty() {
+            return 0;
+        }
+
+        let changed = edit.changes.len();
+        self.undo.push(edit);
+        if self.undo.len() > self.max_edits {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+        changed
+    }
+
+    /// Reverts the last edit, returning whether there was one. Blocks in
+    /// sections unloaded since are skipped.
+    pub fn undo(&mut self, world: &mut World) -> bool {
+        let Some(edit) = self.undo.pop() else {
+            return false;
+        };
+        for change in edit.changes.iter().rev() {
+            set_loaded(world, change.position, change.before);
+        }
+        self.redo.push(edit);
+        true
+    }
+
+    /// Makes the last undone edit again, returning whether there was one.
+    pub fn redo(&mut self, world: &mut World) -> bool {
+        let Some(edit) = self.redo.pop() else {
+            return false;
+        };
+        for change in &edit.changes {
+            set_loaded(world, change.position, change.after);
+        }
+        self.undo.push(edit);
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+fn set_loaded(world: &mut World, position: [i32; 3], block_type_id: BlockTypeId) {
+    if world
+        .chunks
+        .contains_key(&ChunkPosition::of_block(position))
+    {
+        world.set_block(position, block_type_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::BlockRegistry;
+
+    use super::*;
+
+    #[test]
+    fn test_brushes() {
+        assert_eq!(
+            Brush::Line {
+                from: [0, 0, 0],
+                to: [4, 2, -1],
+            }
+            .positions(),
+            [[0, 0, 0], [1, 1, 0], [2, 1, -1], [3, 2, -1], [4, 2, -1]]
+        );
+        assert_eq!(
+            Brush::Sphere {
+                center: [0, 0, 0],
+                radius: 1,
+            }
+            .positions()
+            .len(),
+            7
+        );
+        assert_eq!(
+            Brush::Cylinder {
+                base: [0, 0, 0],
+                radius: 1,
+                height: 3,
+            }
+            .positions()
+            .len(),
+            15
+        );
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut world = World::new(BlockRegistry::default());
+        let id = |name: &str| world.block_registry.block_types.get_index_of(name).unwrap();
+        let (stone, dirt, sand) = (id("stone"), id("dirt"), id("sand"));
+        world
+            .chunks
+            .insert(ChunkPosition { x: 0, y: 0, z: 0 }, Default::default());
+        world.fill_cuboid([0, 0, 0], [4, 1, 1], stone);
+        world[[0, 0, 0]] = dirt;
+
+        let mut history = EditHistory::new(8);
+        let changed = history.edit(&mut world, |transaction| {
+            let brush = Brush::Cuboid {
+                min: [0, 0, 0],
+                max: [5, 0, 0],
+            };
+            transaction.paint(&brush, sand, Mask::Only(stone));
+            // Outside the loaded section
+            transaction.set([-1, 0, 0], sand);
+        });
+        assert_eq!(changed, 3);
+        assert_eq!(world[[0, 0, 0]], dirt);
+        assert_eq!(world[[3, 0, 0]], sand);
+        assert_eq!(world[[4, 0, 0]], 0);
+        assert!(!world
+            .chunks
+            .contains_key(&ChunkPosition { x: -1, y: 0, z: 0 }));
+
+        // Setting blocks back is no change
+        assert_eq!(
+            history.edit(&mut world, |transaction| {
+                transaction.set([0, 0, 0], sand);
+                transaction.set([0, 0, 0], dirt);
+            }),
+            0
+        );
+
+        assert!(history.undo(&mut world));
+        assert_eq!(world[[3, 0, 0]], stone);
+        assert!(!history.undo(&mut world));
+        assert!(history.redo(&mut world));
+        assert_eq!(world[[3, 0, 0]], sand);
+        assert!(!history.redo(&mut world));
+    }
+}
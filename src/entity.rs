@@ -0,0 +1,268 @@
+//! Objects that aren't blocks, like mobs, dropped items and players, kept in
+//! an ECS world apart from the block `World` and advanced in fixed ticks.
+
+use std::time::Duration;
+
+use bevy_ecs::{
+    prelude::*,
+    schedule::{IntoSystemConfigs, ScheduleLabel},
+};
+use cgmath::{EuclideanSpace, Matrix4, One, Point3, Quaternion, Vector3};
+
+use crate::types::BlockTypeId;
+
+/// Entity ticks per second.
+pub const TICK_RATE: u32 = 20;
+/// Ticks one update runs at most. After a stall, like a long step or the
+/// window being dragged, the time past this is dropped rather than caught up
+/// on, which would stall the next update too.
+const MAX_TICKS_PER_UPDATE: u32 = 10;
+
+/// Where an entity is, in blocks.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub position: Point3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: f32,
+}
+
+impl Transform {
+    pub fn at(position: Point3<f32>) -> Self {
+        Self {
+            position,
+            rotation: Quaternion::one(),
+            scale: 1.0,
+        }
+    }
+
+    /// The transform `t` of the way from `self` to `other`.
+    pub fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            position: self.position + (other.position - self.position) * t,
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
+    }
+
+    /// Transforms model space, where a block spans 0 to 1, to the world.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position.to_vec())
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_scale(self.scale)
+    }
+}
+
+/// The transform at the start of the current tick, which rendering
+/// interpolates from. Added to every entity with a `Transform`.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct PreviousTransform(pub Transform);
+
+/// Blocks per second the entity moves by.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Velocity(pub Vector3<f32>);
+
+/// Draws the entity as a box with the faces of a block type, or as a quad
+/// facing the camera with its north face if `billboard`.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Renderable {
+    pub block_type_id: BlockTypeId,
+    pub billboard: bool,
+}
+
+/// The tick being run, readable by systems.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Tick {
+    pub number: u64,
+    /// Length of a tick in seconds.
+    pub delta: f32,
+}
+
+/// Schedule run once per tick.
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TickSchedule;
+
+fn store_previous_transforms(
+    mut commands: Commands,
+    mut query: Query<(Entity, &Transform, Option<&mut PreviousTransform>)>,
+) {
+    for (entity, transform, previous) in &mut query {
+        match previous {
+            Some(mut previous) => previous.0 = *transform,
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(PreviousTransform(*transform));
+            }
+        }
+    }
+}
+
+fn apply_velocity(tick: Res<Tick>, mut query: Query<(&mut Transform, &Velocity)>) {
+    for (mut transform, velocity) in &mut query {
+        transform.position += velocity.0 * tick.delta;
+    }
+}
+
+/// The ECS world with its tick schedule. More systems can be added to the
+/// schedule with `add_systems`; they run after the previous transforms are
+/// stored and before velocities are applied.
+pub struct Entities {
+    pub ecs: World,
+    schedule: Schedule,
+    /// Time not used up by ticks yet.
+    accumulated: Duration,
+}
+
+impl Default for Entities {
+    fn default() -> Self {
+        let mut ecs = World::new();
+        ecs.insert_resource(Tick {
+            number: 0,
+            delta: 1.0 / TICK_RATE as f32,
+        });
+        let mut schedule = Schedule::new(TickSchedule);
+        schedule.add_systems((store_previous_transforms, apply_velocity).chain());
+        Self {
+            ecs,
+            schedule,
+            accumulated: Duration::ZERO,
+        }
+    }
+}
+
+impl Entities {
+    pub fn add_systems<M>(&mut self, systems: impl IntoSystemConfigs<M>) {
+        self.schedule.add_systems(
+            systems
+                .into_configs()
+                .after(store_previous_transforms)
+                .before(apply_velocity),
+        );
+    }
+
+    /// Runs one tick.
+    pub fn tick(&mut self) {
+        self.schedule.run(&mut self.ecs);
+        self.ecs.resource_mut::<Tick>().number += 1;
+    }
+
+    /// Runs as many ticks as fit into the time passed, up to
+    /// `MAX_TICKS_PER_UPDATE`, keeping the rest of a tick for the next
+    /// update, and returns how many ran.
+    pub fn update(&mut self, elapsed: Duration) -> u32 {
+        let tick_duration = Duration::from_secs(1) / TICK_RATE;
+        self.accumulated += elapsed;
+        let mut ticks = 0;
+        while self.accumulated >= tick_duration {
+            if ticks == MAX_TICKS_PER_UPDATE {
+                // Drops the whole ticks left, keeping how far into the next
+                // one the time is
+                let into_tick = self.accumulated.as_nanos() % tick_duration.as_nanos();
+                self.accumulated = Duration::from_nanos(into_tick as u64);
+                break;
+            }
+            self.accumulated -= tick_duration;
+            self.tick();
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// How far the time passed is into the next tick, from 0 to 1.
+    pub fn interpolation(&self) -> f32 {
+        self.accumulated.as_secs_f32() * TICK_RATE as f32
+    }
+
+    /// The entities to draw, with their transforms interpolated between the
+    /// last two ticks.
+    pub fn renderables(&mut self) -> Vec<(Entity, Transform, Renderable)> {
+        let t = self.interpolation();
+        self.ecs
+            .query::<(Entity, &Transform, Option<&PreviousTransform>, &Renderable)>()
+            .iter(&self.ecs)
+            .map(|(entity, transform, previous, renderable)| {
+                let transform = match previous {
+                    Some(previous) => previous.0.lerp(transform, t),
+                    None => *transform,
+                };
+                (entity, transform, *renderable)
+            })
+            .collect()
+    }
+
+    /// Number of ticks run so far.
+    pub fn tick_number(&self) -> u64 {
+        self.ecs.resource::<Tick>().number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_ticks() {
+        let mut entities = Entities::default();
+        let moving = entities
+            .ecs
+            .spawn((
+                Transform::at(Point3::new(0.0, 64.0, 0.0)),
+                Velocity(Vector3::new(2.0, 0.0, 0.0)),
+            ))
+            .id();
+        let still = entities
+            .ecs
+            .spawn((
+                Transform::at(Point3::new(1.0, 2.0, 3.0)),
+                Renderable {
+                    block_type_id: 1,
+                    billboard: false,
+                },
+            ))
+            .id();
+
+        assert_eq!(entities.update(Duration::from_millis(30)), 0);
+        assert_eq!(entities.update(Duration::from_millis(95)), 2);
+        assert_eq!(entities.tick_number(), 2);
+
+        let position = entities.ecs.get::<Transform>(moving).unwrap().position;
+        assert!((position.x - 0.2).abs() < 1e-6);
+        assert_eq!(
+            entities.ecs.get::<Transform>(still).unwrap().position,
+            Point3::new(1.0, 2.0, 3.0)
+        );
+
+        // 35 ms into the third tick, drawn 70% of the way from the first tick
+        // to the second
+        entities.ecs.entity_mut(moving).insert(Renderable {
+            block_type_id: 1,
+            billboard: true,
+        });
+        entities.update(Duration::from_millis(10));
+        let renderables = entities.renderables();
+        let (_, transform, _) = renderables.iter().find(|(e, ..)| *e == moving).unwrap();
+        assert!((transform.position.x - 0.17).abs() < 1e-5);
+
+        // Systems added later run before velocities are applied
+        entities.add_systems(|mut query: Query<&mut Velocity>| {
+            for mut velocity in &mut query {
+                velocity.0 = Vector3::new(0.0, 0.0, 0.0);
+            }
+        });
+        entities.tick();
+        let position = entities.ecs.get::<Transform>(moving).unwrap().position;
+        assert!((position.x - 0.2).abs() < 1e-6);
+
+        // A stall of a minute catches up on a few ticks only
+        let ticks = entities.tick_number();
+        assert_eq!(
+            entities.update(Duration::from_secs(60) + Duration::from_millis(20)),
+            MAX_TICKS_PER_UPDATE
+        );
+        assert_eq!(entities.tick_number(), ticks + MAX_TICKS_PER_UPDATE as u64);
+        assert_eq!(entities.update(Duration::ZERO), 0);
+        // The 35 ms into a tick before and the 20 ms past whole ticks of the
+        // stall leave it 5 ms into one
+        assert!((entities.interpolation() - 0.1).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::types::{BlockTypeId, ChunkPosition};
+
+/// A change made to a `World` through its methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorldEvent {
+    BlockChanged {
+        position: [i32; 3],
+        before: BlockTypeId,
+        after: BlockTypeId,
+    },
+    /// The section was added to the world, or replaced.
+    ChunkLoaded(ChunkPosition),
+    ChunkUnloaded(ChunkPosition),
+}
+
+/// Sends every event of a world to all subscribers, which receive them at
+/// their own pace. Subscribers that dropped their receiver are forgotten.
+#[derive(Debug, Default)]
+pub struct WorldEvents {
+    subscribers: Vec<Sender<WorldEvent>>,
+}
+
+impl WorldEvents {
+    /// Receives the events emitted from now on.
+    pub fn subscribe(&mut self) -> Receiver<WorldEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn emit(&mut self, event: WorldEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{BlockRegistry, ColumnPosition, World};
+
+    use super::*;
+
+    #[test]
+    fn test_world_events() {
+        let mut world = World::new(BlockRegistry::default());
+        let events = world.events.subscribe();
+        let dropped = world.events.subscribe();
+        drop(dropped);
+
+        let column = ColumnPosition { x: 0, z: 0 };
+        world.insert_chunks(
+            column
+                .sections(world.height)
+                .map(|section| (section, Default::default())),
+        );
+        world.set_block([1, 2, 3], 1);
+        // Setting the same block again changes nothing
+        world.set_block([1, 2, 3], 1);
+        world.unload_column(column);
+
+        let events = events.try_iter().collect::<Vec<_>>();
+        let sections = world.height.sections().len();
+        assert_eq!(events.len(), sections * 2 + 1);
+        assert_eq!(
+            events[sections],
+            WorldEvent::BlockChanged {
+                position: [1, 2, 3],
+                before: 0,
+                after: 1,
+            }
+        );
+        assert!(matches!(events[sections + 1], WorldEvent::ChunkUnloaded(_)));
+        assert_eq!(world.events.subscribers.len(), 1);
+    }
+}
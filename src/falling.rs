@@ -0,0 +1,224 @@
+//! Blocks like sand that fall when nothing holds them up. An unsupported
+//! block is replaced by air and a falling entity showing it, which is placed
+//! back as a block where it lands. Both go through `World::set_block`, so the
+//! renderer remeshes the sections like for any other edit.
+
+use std::{collections::BTreeSet, sync::mpsc::Receiver};
+
+use bevy_ecs::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use crate::{
+    entity::{Entities, Renderable, Tick, Transform, Velocity},
+    events::WorldEvent,
+    types::{BlockTypeId, ChunkPosition, Shape, World},
+};
+
+/// Acceleration of falling blocks, in blocks per second squared.
+const GRAVITY: f32 = 20.0;
+/// Fastest falling blocks fall, in blocks per second.
+const TERMINAL_VELOCITY: f32 = 40.0;
+
+/// A block falling as an entity.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FallingBlock {
+    pub block_type_id: BlockTypeId,
+    /// The lowest y the block has fallen through, which the blocks below are
+    /// checked from to find where it lands.
+    fallen_to: i32,
+}
+
+fn apply_gravity(tick: Res<Tick>, mut query: Query<&mut Velocity, With<FallingBlock>>) {
+    for mut velocity in &mut query {
+        velocity.0.y = (velocity.0.y - GRAVITY * tick.delta).max(-TERMINAL_VELOCITY);
+    }
+}
+
+/// Whether falling blocks fall through `block_type_id`.
+fn passable(world: &World, block_type_id: BlockTypeId) -> bool {
+    block_type_id == 0
+        || matches!(
+            world.block_registry.block_types[block_type_id].shape,
+            Shape::Fluid(_)
+        )
+}
+
+/// Turns the blocks with `BlockType::gravity` of a world into falling
+/// entities and back.
+pub struct FallingBlocks {
+    world_events: Receiver<WorldEvent>,
+}
+
+impl FallingBlocks {
+    /// Falling blocks of `world`, simulated among `entities`.
+    pub fn new(world: &mut World, entities: &mut Entities) -> Self {
+        entities.add_systems(apply_gravity);
+        Self {
+            world_events: world.events.subscribe(),
+        }
+    }
+
+    /// Makes the blocks left without support by the changes since the last
+    /// update fall, including blocks that were stacked on them, and places
+    /// the falling blocks that reached the ground, returning where they were
+    /// placed. Call after ticking `entities`.
+    pub fn update(&mut self, world: &mut World, entities: &mut Entities) -> Vec<[i32; 3]> {
+        loop {
+            // Ordered so the same changes always spawn in the same order
+            let changed = self
+                .world_events
+                .try_iter()
+                .filter_map(|event| match event {
+                    WorldEvent::BlockChanged { position, .. } => Some(position),
+                    _ => None,
+                })
+                .flat_map(|[x, y, z]| [[x, y, z], [x, y + 1, z]])
+                .collect::<BTreeSet<_>>();
+            if changed.is_empty() {
+                break;
+            }
+            for position in changed {
+                self.fall(world, entities, position);
+            }
+        }
+        self.land(world, entities)
+    }
+
+    /// Replaces the block at `position` with a falling one if it has gravity
+    /// and nothing below to rest on.
+    fn fall(&mut self, world: &mut World, entities: &mut Entities, [x, y, z]: [i32; 3]) {
+        let block_type_id = world[[x, y, z]];
+        if !world.block_registry.block_types[block_type_id].gravity
+            || !passable(world, world[[x, y - 1, z]])
+            || y <= world.height.min_y
+            || !world
+                .chunks
+                .contains_key(&ChunkPosition::of_block([x, y - 1, z]))
+        {
+            return;
+        }
+        world.set_block([x, y, z], 0);
+        entities.ecs.spawn((
+            Transform::at(Point3::new(x as f32, y as f32, z as f32)),
+            Velocity(Vector3::new(0.0, 0.0, 0.0)),
+            Renderable {
+                block_type_id,
+                billboard: false,
+            },
+            FallingBlock {
+                block_type_id,
+                fallen_to: y,
+            },
+        ));
+    }
+
+    /// Places the falling blocks on whatever they fell onto since the last
+    /// update, checking every block passed so none are fallen through.
+    /// Blocks falling out of the world, or onto a spot filled meanwhile, are
+    /// lost. Returns where blocks were placed.
+    fn land(&mut self, world: &mut World, entities: &mut Entities) -> Vec<[i32; 3]> {
+        let mut landed = Vec::new();
+        let mut falling = entities
+            .ecs
+            .query::<(Entity, &Transform, &FallingBlock)>()
+            .iter(&entities.ecs)
+            .map(|(entity, transform, falling)| (entity, transform.position, *falling))
+            .collect::<Vec<_>>();
+        // Lowest first, so blocks falling onto each other stack up
+        falling.sort_by(|(_, a, _), (_, b, _)| a.y.total_cmp(&b.y));
+        for (entity, position, falling) in falling {
+            let (x, z) = (position.x.floor() as i32, position.z.floor() as i32);
+            let bottom = position.y.floor() as i32;
+            let ground = (bottom.max(world.height.min_y)..falling.fallen_to)
+                .rev()
+                .find(|&y| !passable(world, world[[x, y, z]]));
+            match ground {
+                Some(y) => {
+                    entities.ecs.despawn(entity);
+                    if passable(world, world[[x, y + 1, z]]) {
+                        world.set_block([x, y + 1, z], falling.block_type_id);
+                        landed.push([x, y + 1, z]);
+                    }
+                }
+                None if bottom < world.height.min_y => {
+                    entities.ecs.despawn(entity);
+                }
+                None => {
+                    let mut falling = entities.ecs.get_mut::<FallingBlock>(entity).unwrap();
+                    falling.fallen_to = falling.fallen_to.min(bottom);
+                }
+            }
+        }
+        landed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::types::{BlockRegistry, Chunk};
+
+    use super::*;
+
+    #[test]
+    fn test_falling_blocks() {
+        let mut world = World::new(BlockRegistry::default());
+        world.insert_chunks((3..5).map(|y| (ChunkPosition { x: 0, y, z: 0 }, Chunk::default())));
+        let block_types = &world.block_registry.block_types;
+        let (stone, sand) = (
+            block_types.get_index_of("stone").unwrap(),
+            block_types.get_index_of("sand").unwrap(),
+        );
+        let mut entities = Entities::default();
+        let mut falling_blocks = FallingBlocks::new(&mut world, &mut entities);
+
+        world.set_block([2, 50, 2], stone);
+        world.set_block([2, 51, 2], sand);
+        world.set_block([2, 70, 2], sand);
+        world.set_block([2, 71, 2], sand);
+        // Supported sand stays
+        falling_blocks.update(&mut world, &mut entities);
+        assert_eq!(world[[2, 51, 2]], sand);
+        // The stacked sand falls together
+        assert_eq!(world[[2, 70, 2]], 0);
+        assert_eq!(world[[2, 71, 2]], 0);
+        assert_eq!(
+            entities
+                .ecs
+                .query::<&FallingBlock>()
+                .iter(&entities.ecs)
+                .count(),
+            2
+        );
+
+        // Falling 18 blocks takes under 1.5 seconds
+        for _ in 0..3 {
+            entities.update(Duration::from_millis(500));
+            falling_blocks.update(&mut world, &mut entities);
+        }
+        assert_eq!(world[[2, 52, 2]], sand);
+        assert_eq!(world[[2, 53, 2]], sand);
+        assert_eq!(
+            entities
+                .ecs
+                .query::<&FallingBlock>()
+                .iter(&entities.ecs)
+                .count(),
+            0
+        );
+
+        // Removing the support makes the column fall again
+        world.set_block([2, 50, 2], 0);
+        falling_blocks.update(&mut world, &mut entities);
+        assert_eq!(world[[2, 51, 2]], 0);
+        assert_eq!(
+            entities
+                .ecs
+                .query::<&FallingBlock>()
+                .iter(&entities.ecs)
+                .count(),
+            3
+        );
+    }
+}
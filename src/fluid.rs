@@ -0,0 +1,193 @@
+//! Fluids flowing through the world on scheduled ticks.
+//!
+//! A fluid block type is a source, `Shape::Fluid(0)`, followed by its flowing
+//! variants, one per level. Fluid falls into air below it and otherwise
+//! spreads sideways one level further per block, up to `MAX_FLUID_LEVEL`.
+//! Flowing blocks keep the level of the closest source feeding them and dry
+//! up once nothing does, and flowing blocks between two sources on solid
+//! ground become sources themselves.
+
+use crate::{
+    tick::TickContext,
+    types::{BlockTypeId, Shape, MAX_FLUID_LEVEL},
+};
+
+/// Ticks between a fluid or one of its neighbors changing and the fluid
+/// flowing on.
+const FLUID_TICK_DELAY: u64 = 5;
+
+const HORIZONTAL_OFFSETS: [[i32; 2]; 4] = [[1, 0], [-1, 0], [0, 1], [0, -1]];
+
+/// The source block type and level of `block`, if it is a fluid.
+fn fluid(context: &TickContext, block: BlockTypeId) -> Option<(BlockTypeId, u8)> {
+    match context.world.block_registry.block_types[block].shape {
+        Shape::Fluid(level) => Some((block - level as BlockTypeId, level)),
+        _ => None,
+    }
+}
+
+fn horizontal_neighbors([x, y, z]: [i32; 3]) -> [[i32; 3]; 4] {
+    HORIZONTAL_OFFSETS.map(|[dx, dz]| [x + dx, y, z + dz])
+}
+
+/// The level a flowing block of `source` at `position` settles to: the first
+/// one under falling fluid, one past the lowest level next to it, or a
+/// source between two sources on solid ground or on the fluid. `None` if
+/// nothing feeds it.
+fn flowing_level(context: &TickContext, source: BlockTypeId, position: [i32; 3]) -> Option<u8> {
+    let [x, y, z] = position;
+    let same_fluid = |block| fluid(context, block).filter(|&(s, _)| s == source);
+    if same_fluid(context.block([x, y + 1, z])).is_some() {
+        return Some(1);
+    }
+    let levels = horizontal_neighbors(position)
+        .into_iter()
+        .filter_map(|neighbor| same_fluid(context.block(neighbor)))
+        .map(|(_, level)| level)
+        .collect::<Vec<_>>();
+    let below = context.block([x, y - 1, z]);
+    let sources = levels.iter().filter(|&&level| level == 0).count();
+    if sources >= 2 && (below == source || context.world.block_registry.is_block_opaque_cube(below))
+    {
+        return Some(0);
+    }
+    levels
+        .into_iter()
+        .min()
+        .map(|level| level + 1)
+        .filter(|&level| level <= MAX_FLUID_LEVEL)
+}
+
+/// Schedules the fluid at `position` to flow on after a change next to it.
+pub fn fluid_neighbor_update(context: &mut TickContext, position: [i32; 3], _changed: [i32; 3]) {
+    context.schedule(position, FLUID_TICK_DELAY);
+}
+
+/// Settles the level of flowing fluid, then lets the fluid fall or spread
+/// into the air around it. The changes schedule the next updates of the
+/// blocks around.
+pub fn fluid_tick(context: &mut TickContext, position: [i32; 3]) {
+    let Some((source, level)) = fluid(context, context.block(position)) else {
+        return;
+    };
+    if level > 0 {
+        match flowing_level(context, source, position) {
+            None => {
+                context.set_block(position, 0);
+                return;
+            }
+            Some(settled) if settled != level => {
+                context.set_block(position, source + settled as BlockTypeId);
+                return;
+            }
+            Some(_) => (),
+        }
+    }
+
+    let [x, y, z] = position;
+    let below = context.block([x, y - 1, z]);
+    if below == 0 {
+        context.set_block([x, y - 1, z], source + 1);
+        return;
+    }
+    if fluid(context, below).is_some_and(|(s, _)| s == source) || level == MAX_FLUID_LEVEL {
+        return;
+    }
+    for neighbor in horizontal_neighbors(position) {
+        if context.block(neighbor) == 0 {
+            context.set_block(neighbor, source + level as BlockTypeId + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tick::WorldTicks,
+        types::{BlockRegistry, Chunk, ChunkPosition, World, WATER_BLOCK},
+    };
+
+    use super::*;
+
+    /// A section with a stone floor at y 64 and the id of water.
+    fn world_with_floor() -> (World, BlockTypeId) {
+        let mut world = World::new(BlockRegistry::default());
+        world.insert_chunks([(ChunkPosition { x: 0, y: 4, z: 0 }, Chunk::default())]);
+        let stone = world
+            .block_registry
+            .block_types
+            .get_index_of("stone")
+            .unwrap();
+        let water = world
+            .block_registry
+            .block_types
+            .get_index_of(WATER_BLOCK)
+            .unwrap();
+        world.fill_cuboid([0, 64, 0], [16, 65, 16], stone);
+        (world, water)
+    }
+
+    fn run(ticks: &mut WorldTicks, world: &mut World, count: usize) {
+        for _ in 0..count {
+            ticks.tick(world);
+        }
+    }
+
+    #[test]
+    fn test_fluid_spreads() {
+        let (mut world, water) = world_with_floor();
+        let mut ticks = WorldTicks::with_builtin_behaviors(0, &mut world);
+        world.set_block([8, 65, 8], water);
+        run(&mut ticks, &mut world, 500);
+
+        assert_eq!(world[[8, 65, 8]], water);
+        assert_eq!(world[[9, 65, 8]], water + 1);
+        assert_eq!(world[[12, 65, 8]], water + 4);
+        assert_eq!(world[[10, 65, 10]], water + 4);
+        assert_eq!(world[[15, 65, 8]], water + 7);
+        assert_eq!(world[[15, 65, 15]], 0);
+        assert_eq!(world[[8, 66, 8]], 0);
+
+        // Falls down before spreading
+        world.set_block([2, 68, 2], water);
+        run(&mut ticks, &mut world, 500);
+        assert_eq!(world[[2, 67, 2]], water + 1);
+        assert_eq!(world[[2, 65, 2]], water + 1);
+        assert_eq!(world[[3, 65, 2]], water + 2);
+        assert_eq!(world[[3, 67, 2]], 0);
+    }
+
+    #[test]
+    fn test_fluid_dries_up() {
+        let (mut world, water) = world_with_floor();
+        let mut ticks = WorldTicks::with_builtin_behaviors(0, &mut world);
+        world.set_block([8, 65, 8], water);
+        run(&mut ticks, &mut world, 500);
+        assert_eq!(world[[11, 65, 8]], water + 3);
+
+        world.set_block([8, 65, 8], 0);
+        run(&mut ticks, &mut world, 2000);
+        for x in 0..16 {
+            for z in 0..16 {
+                assert_eq!(world[[x, 65, z]], 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fluid_sources_form() {
+        let (mut world, water) = world_with_floor();
+        let mut ticks = WorldTicks::with_builtin_behaviors(0, &mut world);
+        world.set_block([4, 65, 8], water);
+        world.set_block([6, 65, 8], water);
+        run(&mut ticks, &mut world, 500);
+        assert_eq!(world[[5, 65, 8]], water);
+
+        // The new source keeps water around after the others are removed
+        world.set_block([4, 65, 8], 0);
+        world.set_block([6, 65, 8], 0);
+        run(&mut ticks, &mut world, 500);
+        assert_eq!(world[[5, 65, 8]], water);
+        assert_eq!(world[[6, 65, 8]], water + 1);
+    }
+}
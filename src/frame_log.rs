@@ -0,0 +1,180 @@
+//! Metrics of every frame written to a file, for looking into performance
+//! outside of the game and comparing builds. Files ending in `.json` get one
+//! JSON object per frame and line, other files CSV.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use serde_json::json;
+
+const CSV_HEADER: &str = "frame,frame_time_ms,chunks,primitives,upload_bytes,gpu_passes";
+
+/// What a frame cost. The GPU metrics come from queries that are read once
+/// the GPU is done with them, so they are those of the frame drawn with the
+/// same frame in flight before.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameRecord {
+    pub frame_time: Duration,
+    /// Chunks the world renderer drew.
+    pub chunks: usize,
+    /// Primitives that reached the rasterizer, if the query was available.
+    pub primitives: Option<u64>,
+    /// Bytes staged for upload to the GPU.
+    pub upload_bytes: u64,
+    /// GPU time of every frame graph pass, in the order they ran. Empty
+    /// without timestamps.
+    pub passes: Vec<(&'static str, Duration)>,
+}
+
+enum Format {
+    /// The passes go into one column as `name=milliseconds` separated by
+    /// `;`, as they differ between settings.
+    Csv,
+    JsonLines,
+}
+
+/// Records are buffered and written out when the log is dropped at the
+/// latest.
+pub struct FrameLog {
+    writer: BufWriter<File>,
+    format: Format,
+    /// Frames written so far.
+    frames: u64,
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1e6
+}
+
+impl FrameLog {
+    /// Creates the log at `path`, replacing what was there.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let format = match path.extension() {
+            Some(extension) if extension == "json" => Format::JsonLines,
+            _ => Format::Csv,
+        };
+        let mut writer = BufWriter::new(File::create(path)?);
+        if let Format::Csv = format {
+            writeln!(writer, "{}", CSV_HEADER)?;
+        }
+        Ok(Self {
+            writer,
+            format,
+            frames: 0,
+        })
+    }
+
+    /// Writes the record of the next frame.
+    pub fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+        let frame = self.frames;
+        self.frames += 1;
+        match self.format {
+            Format::Csv => {
+                let passes = record
+                    .passes
+                    .iter()
+                    .map(|(name, time)| format!("{}={:.3}", name, milliseconds(*time)))
+                    .collect::<Vec<_>>();
+                writeln!(
+                    self.writer,
+                    "{},{:.3},{},{},{},{}",
+                    frame,
+                    milliseconds(record.frame_time),
+                    record.chunks,
+                    record
+                        .primitives
+                        .map(|primitives| primitives.to_string())
+                        .unwrap_or_default(),
+                    record.upload_bytes,
+                    passes.join(";"),
+                )
+            }
+            Format::JsonLines => {
+                let passes = record
+                    .passes
+                    .iter()
+                    .map(|(name, time)| json!({ "name": name, "ms": milliseconds(*time) }))
+                    .collect::<Vec<_>>();
+                let line = json!({
+                    "frame": frame,
+                    "frame_time_ms": milliseconds(record.frame_time),
+                    "chunks": record.chunks,
+                    "primitives": record.primitives,
+                    "upload_bytes": record.upload_bytes,
+                    "gpu_passes": passes,
+                });
+                writeln!(self.writer, "{}", line)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_frame_log() {
+        let records = [
+            FrameRecord {
+                frame_time: Duration::from_micros(16_667),
+                chunks: 40,
+                primitives: None,
+                upload_bytes: 1024,
+                passes: Vec::new(),
+            },
+            FrameRecord {
+                frame_time: Duration::from_millis(8),
+                chunks: 41,
+                primitives: Some(5000),
+                upload_bytes: 0,
+                passes: vec![
+                    ("world", Duration::from_micros(2500)),
+                    ("fsr", Duration::from_micros(500)),
+                ],
+            },
+        ];
+        let directory = std::env::temp_dir();
+        let id = std::process::id();
+
+        let path = directory.join(format!("block-world-frames-{}.csv", id));
+        let mut log = FrameLog::create(&path).unwrap();
+        for record in &records {
+            log.write(record).unwrap();
+        }
+        drop(log);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "{}\n0,16.667,40,,1024,\n1,8.000,41,5000,0,world=2.500;fsr=0.500\n",
+                CSV_HEADER
+            )
+        );
+        fs::remove_file(&path).unwrap();
+
+        let path = directory.join(format!("block-world-frames-{}.json", id));
+        let mut log = FrameLog::create(&path).unwrap();
+        for record in &records {
+            log.write(record).unwrap();
+        }
+        drop(log);
+        let lines = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["primitives"], serde_json::Value::Null);
+        assert_eq!(lines[1]["frame"], 1);
+        assert_eq!(lines[1]["gpu_passes"][1]["name"], "fsr");
+        assert_eq!(lines[1]["gpu_passes"][0]["ms"], 2.5);
+        fs::remove_file(&path).unwrap();
+    }
+}
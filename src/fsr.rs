@@ -1,26 +1,36 @@
 use std::{marker::PhantomData, mem};
 
-use ash::vk::ImageMemoryBarrier2;
 use cgmath::{Rad, Vector2};
 use fsr_sys::{
     contextCreate, contextDestroy, contextDispatch, getJitterOffset, getJitterPhaseCount,
     vk::{self, getDevice, getTextureResource},
     Context, ContextDescription, Dimensions2D, DispatchDescription, FloatCoords2D, MsgType,
-    Resource, ENABLE_AUTO_EXPOSURE, ENABLE_DEBUG_CHECKING, MESSAGE_TYPE_ERROR,
-    MESSAGE_TYPE_WARNING, OK, RESOURCE_STATE_COMPUTE_READ, RESOURCE_STATE_UNORDERED_ACCESS,
+    Resource, ENABLE_AUTO_EXPOSURE, ENABLE_DEBUG_CHECKING, ENABLE_DEPTH_INFINITE,
+    ENABLE_DEPTH_INVERTED, MESSAGE_TYPE_ERROR, MESSAGE_TYPE_WARNING, OK,
+    RESOURCE_STATE_COMPUTE_READ, RESOURCE_STATE_UNORDERED_ACCESS,
 };
 use log::{debug, error, warn};
+use tracing::instrument;
 use vulkano::{
     command_buffer::sys::RawRecordingCommandBuffer, device::Device, format::Format,
     image::view::ImageView, Handle, VulkanObject,
 };
 use widestring::{widecstr, WideCStr};
 
-use crate::renderer::render_faces::Camera;
+use crate::{
+    camera::DepthMode,
+    renderer::{render_faces::Camera, MOTION_VECTOR_UV_SCALE},
+    settings::FsrSettings,
+};
 
 pub struct FsrContextVulkan {
-    _scrach_buffer: Vec<u8>,
+    scrach_buffer: Vec<u8>,
     context: Box<Context>,
+    /// What `context` was created with, to create it again when `settings`
+    /// change its flags.
+    context_description: ContextDescription,
+    depth_mode: DepthMode,
+    settings: FsrSettings,
     render_size: [u32; 2],
     display_size: [u32; 2],
 
@@ -41,11 +51,15 @@ unsafe extern "C" fn on_fsr_message(msg_type: MsgType, message: *const u16) {
 }
 
 impl FsrContextVulkan {
+    /// `depth_mode` is the one of the depth buffers passed to `dispatch`.
+    /// `None` if FSR is not available on the device.
     pub unsafe fn new(
         vulkan_device: &Device,
         render_size: [u32; 2],
         display_size: [u32; 2],
-    ) -> Self {
+        depth_mode: DepthMode,
+        settings: FsrSettings,
+    ) -> Option<Self> {
         let physical_device = vulkan_device.physical_device();
         let get_device_proc_addr = physical_device.instance().fns().v1_0.get_device_proc_addr;
         let physical_device = physical_device.handle().as_raw();
@@ -63,7 +77,7 @@ impl FsrContextVulkan {
                 height: display_size[1],
             },
             fpMessage: Some(on_fsr_message),
-            flags: ENABLE_DEBUG_CHECKING | ENABLE_AUTO_EXPOSURE,
+            flags: context_flags(depth_mode, &settings),
             ..Default::default()
         };
 
@@ -74,24 +88,62 @@ impl FsrContextVulkan {
             physical_device,
             mem::transmute(get_device_proc_addr),
         );
-        assert_eq!(err, OK, "Failed to get Vulkan FSR interface");
+        if err != OK {
+            error!("Failed to get Vulkan FSR interface: {}", err);
+            return None;
+        }
 
         let mut context = Box::new(Context::default());
         let err = contextCreate(context.as_mut(), &context_description);
-        assert_eq!(err, OK, "Failed to create FSR context with Vulkan");
+        if err != OK {
+            error!("Failed to create FSR context with Vulkan: {}", err);
+            return None;
+        }
 
         let jitter_phase_count = getJitterPhaseCount(render_size[0] as _, display_size[0] as _);
 
-        Self {
-            _scrach_buffer: scrach_buffer,
+        Some(Self {
+            scrach_buffer,
             context,
+            context_description,
+            depth_mode,
+            settings,
             render_size,
             display_size,
             jitter_phase_count,
             frame_index: 0,
             non_send_sync: PhantomData,
             jitter_offset: [0.0, 0.0],
+        })
+    }
+
+    pub fn settings(&self) -> &FsrSettings {
+        &self.settings
+    }
+
+    /// Applies `settings` from the next `dispatch` on. Auto-exposure and
+    /// debug checking are fixed in the context, so changing them creates it
+    /// again, which loses the history and must wait until the GPU is done
+    /// with the old one.
+    pub unsafe fn set_settings(&mut self, settings: FsrSettings) {
+        let flags = context_flags(self.depth_mode, &settings);
+        self.settings = settings;
+        if flags == self.context_description.flags {
+            return;
         }
+        contextDestroy(self.context.as_mut());
+        self.context_description.flags = flags;
+        let err = contextCreate(self.context.as_mut(), &self.context_description);
+        assert_eq!(err, OK, "Failed to create FSR context with Vulkan");
+    }
+
+    pub fn render_size(&self) -> [u32; 2] {
+        self.render_size
+    }
+
+    /// Size of the host memory FSR uses for its backend state.
+    pub fn scratch_memory_size(&self) -> usize {
+        self.scrach_buffer.len()
     }
 
     unsafe fn get_texture_resource(
@@ -134,13 +186,17 @@ impl FsrContextVulkan {
         )
     }
 
+    /// Records upscaling `color` into `output`. The inputs have to be in
+    /// `READ_ONLY_OPTIMAL` and `output` in `GENERAL`, the layouts the frame
+    /// graph gives the sampled and storage images of an external pass.
+    #[instrument(skip_all)]
     pub unsafe fn dispatch(
         &mut self,
-        device: ash::Device,
         command_buffer: &RawRecordingCommandBuffer,
         color: &ImageView,
         depth: &ImageView,
         motion_vector: &ImageView,
+        reactive: &ImageView,
         output: &ImageView,
         frame_time_delta: f32,
         camera: Camera,
@@ -152,6 +208,7 @@ impl FsrContextVulkan {
         );
         assert_eq!(color.image().extent(), depth.image().extent());
         assert_eq!(color.image().extent(), motion_vector.image().extent());
+        assert_eq!(color.image().extent(), reactive.image().extent());
 
         assert_eq!(
             output.image().extent(),
@@ -159,53 +216,6 @@ impl FsrContextVulkan {
         );
 
         let command_buffer = command_buffer.handle();
-        let memory_barrier_color = ImageMemoryBarrier2 {
-            new_layout: ash::vk::ImageLayout::READ_ONLY_OPTIMAL,
-            image: color.image().handle(),
-            subresource_range: ash::vk::ImageSubresourceRange {
-                aspect_mask: ash::vk::ImageAspectFlags::COLOR,
-                level_count: 1,
-                layer_count: 1,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
-        let memory_barrier_depth = ImageMemoryBarrier2 {
-            new_layout: ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            image: depth.image().handle(),
-            subresource_range: ash::vk::ImageSubresourceRange {
-                aspect_mask: ash::vk::ImageAspectFlags::DEPTH,
-                level_count: 1,
-                layer_count: 1,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
-        let memory_barrier_motion_vector = ImageMemoryBarrier2 {
-            image: motion_vector.image().handle(),
-            ..memory_barrier_color
-        };
-        let memory_barrier_output = ImageMemoryBarrier2 {
-            new_layout: ash::vk::ImageLayout::GENERAL,
-            image: output.image().handle(),
-            subresource_range: ash::vk::ImageSubresourceRange {
-                aspect_mask: ash::vk::ImageAspectFlags::COLOR,
-                level_count: 1,
-                layer_count: 1,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
-        let image_memory_barriers = [
-            memory_barrier_color,
-            memory_barrier_depth,
-            memory_barrier_motion_vector,
-            memory_barrier_output,
-        ];
-        let dependency_info =
-            ash::vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
-        device.cmd_pipeline_barrier2(command_buffer, &dependency_info);
-
         let input_extent = color.image().extent();
         let dispatch_description = DispatchDescription {
             commandList: vk::getCommandList(command_buffer.as_raw()),
@@ -214,7 +224,7 @@ impl FsrContextVulkan {
             motionVectors: self
                 .get_texture_resource(motion_vector, widecstr!("FSR2_InputMotionVector")),
             exposure: self.get_texture_resource_empty(widecstr!("FSR2_InputExposure")),
-            reactive: self.get_texture_resource_empty(widecstr!("FSR2_EmptyInputReactiveMap")),
+            reactive: self.get_texture_resource(reactive, widecstr!("FSR2_InputReactiveMap")),
             transparencyAndComposition: self
                 .get_texture_resource_empty(widecstr!("FSR2_EmptyTransparencyAndCompositionMap")),
             output: self.get_texture_resource_with_state(
@@ -226,13 +236,14 @@ impl FsrContextVulkan {
                 x: self.jitter_offset[0],
                 y: self.jitter_offset[1],
             },
+            // FSR wants them in render pixels
             motionVectorScale: FloatCoords2D {
-                x: input_extent[0] as _,
-                y: input_extent[1] as _,
+                x: input_extent[0] as f32 * MOTION_VECTOR_UV_SCALE,
+                y: input_extent[1] as f32 * MOTION_VECTOR_UV_SCALE,
             },
             reset: false,
-            enableSharpening: true,
-            sharpness: 0.5,
+            enableSharpening: self.settings.sharpening,
+            sharpness: self.settings.sharpness,
             frameTimeDelta: frame_time_delta,
             preExposure: 1.0,
             renderSize: Dimensions2D {
@@ -240,7 +251,9 @@ impl FsrContextVulkan {
                 height: input_extent[1],
             },
 
-            cameraFar: camera.far,
+            // FSR wants the largest float rather than infinity for an
+            // infinite far plane
+            cameraFar: camera.far.min(f32::MAX),
             cameraNear: camera.near,
             cameraFovAngleVertical: Rad::from(camera.fovy).0,
             ..Default::default()
@@ -248,33 +261,6 @@ impl FsrContextVulkan {
         debug!("Dispatching FSR context");
         let err = contextDispatch(self.context.as_mut(), &dispatch_description);
         assert_eq!(err, OK, "Failed to dispatch FSR context");
-
-        // Set the image layouts to GENERAL
-        let memory_barrier_color = ImageMemoryBarrier2 {
-            new_layout: ash::vk::ImageLayout::GENERAL,
-            ..memory_barrier_color
-        };
-        let memory_barrier_depth = ImageMemoryBarrier2 {
-            new_layout: ash::vk::ImageLayout::GENERAL,
-            ..memory_barrier_depth
-        };
-        let memory_barrier_motion_vector = ImageMemoryBarrier2 {
-            new_layout: ash::vk::ImageLayout::GENERAL,
-            ..memory_barrier_motion_vector
-        };
-        let memory_barrier_output = ImageMemoryBarrier2 {
-            new_layout: ash::vk::ImageLayout::GENERAL,
-            ..memory_barrier_output
-        };
-        let image_memory_barriers = [
-            memory_barrier_color,
-            memory_barrier_depth,
-            memory_barrier_motion_vector,
-            memory_barrier_output,
-        ];
-        let dependency_info =
-            ash::vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
-        device.cmd_pipeline_barrier2(command_buffer, &dependency_info);
     }
 
     pub unsafe fn step_jitter(&mut self) -> Vector2<f32> {
@@ -295,6 +281,23 @@ impl FsrContextVulkan {
     }
 }
 
+/// The flags of a context upscaling depth of `depth_mode`. The color is
+/// linear and within 0 to 1, as FSR assumes without
+/// `ENABLE_HIGH_DYNAMIC_RANGE`.
+fn context_flags(depth_mode: DepthMode, settings: &FsrSettings) -> u32 {
+    let mut flags = match depth_mode {
+        DepthMode::Standard => 0,
+        DepthMode::InfiniteReversed => ENABLE_DEPTH_INVERTED | ENABLE_DEPTH_INFINITE,
+    };
+    if settings.auto_exposure {
+        flags |= ENABLE_AUTO_EXPOSURE;
+    }
+    if settings.debug_checking {
+        flags |= ENABLE_DEBUG_CHECKING;
+    }
+    flags
+}
+
 impl Drop for FsrContextVulkan {
     fn drop(&mut self) {
         unsafe {
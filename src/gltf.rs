@@ -0,0 +1,360 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Cursor},
+    path::Path,
+};
+
+use image::ImageFormat;
+use serde_json::{json, Value};
+
+use crate::{
+    biome::TintColor,
+    renderer::culling::{cull_faces, greedy_mesh},
+    settings::WorldEdges,
+    texture::MISSING_TEXTURE,
+    types::{Direction, Shape, TextureId, World},
+};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: &[u8; 4] = b"JSON";
+const CHUNK_BIN: &[u8; 4] = b"BIN\0";
+
+// Enums of the glTF specification
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const NEAREST: u32 = 9728;
+const REPEAT: u32 = 10497;
+
+/// Geometry of the faces using one texture.
+#[derive(Default)]
+struct Primitive {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    colors: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl Primitive {
+    /// Adds a `size[0]`x`size[1]` rectangle facing `direction` whose lowest
+    /// corner is `origin` (world coordinates). Textures repeat every block,
+    /// aligned with the world.
+    fn push_quad(
+        &mut self,
+        direction: Direction,
+        origin: [f32; 3],
+        size: [f32; 2],
+        tint: Option<TintColor>,
+    ) {
+        let (dx, dy, dz) = direction.to_offset();
+        let normal = [dx, dy, dz];
+        let [u_axis, v_axis] = direction.plane_axes();
+
+        let base = self.positions.len() as u32;
+        let [width, height] = size;
+        let u_start = origin[u_axis] - origin[u_axis].floor();
+        let v_start = origin[v_axis] - origin[v_axis].floor();
+        let v_top = (origin[v_axis] + height).ceil() - origin[v_axis];
+        for [du, dv] in [[0.0, 0.0], [width, 0.0], [width, height], [0.0, height]] {
+            let mut corner = origin;
+            corner[u_axis] += du;
+            corner[v_axis] += dv;
+            self.positions.push(corner);
+            self.normals.push(normal.map(|v| v as f32));
+            // Side textures are upright, with v growing downwards
+            let v = if direction.axis() == 1 {
+                v_start + dv
+            } else {
+                v_top - dv
+            };
+            self.uvs.push([u_start + du, v]);
+            self.colors
+                .push(tint.unwrap_or([255; 3]).map(|c| c as f32 / 255.0));
+        }
+
+        // Counter-clockwise seen from the side the quad faces
+        let mut u = [0; 3];
+        u[u_axis] = 1;
+        let mut v = [0; 3];
+        v[v_axis] = 1;
+        let cross = [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ];
+        let facing = (0..3).map(|i| cross[i] * normal[i]).sum::<i32>() > 0;
+        let order = if facing {
+            [0, 1, 2, 0, 2, 3]
+        } else {
+            [0, 2, 1, 0, 3, 2]
+        };
+        self.indices.extend(order.map(|i| base + i));
+    }
+}
+
+/// Accumulates the binary buffer and the accessors and buffer views into it.
+#[derive(Default)]
+struct Buffer {
+    bytes: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Buffer {
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // Accessors need their data aligned to the component size
+        self.bytes.resize(self.bytes.len().next_multiple_of(4), 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.bytes.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.bytes.extend_from_slice(bytes);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    fn push_floats<const N: usize>(&mut self, values: &[[f32; N]], with_bounds: bool) -> usize {
+        let bytes = values
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>();
+        let view = self.push_view(&bytes, Some(ARRAY_BUFFER));
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": values.len(),
+            "type": format!("VEC{}", N),
+        });
+        if with_bounds {
+            let min = (0..N)
+                .map(|i| values.iter().map(|v| v[i]).fold(f32::INFINITY, f32::min))
+                .collect::<Vec<_>>();
+            let max = (0..N)
+                .map(|i| {
+                    values
+                        .iter()
+                        .map(|v| v[i])
+                        .fold(f32::NEG_INFINITY, f32::max)
+                })
+                .collect::<Vec<_>>();
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let bytes = indices
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>();
+        let view = self.push_view(&bytes, Some(ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+}
+
+/// Writes the visible geometry of every loaded section of `world` as a
+/// binary glTF 2.0 file. Faces are culled like for rendering and merged into
+/// rectangles by greedy meshing, except for blocks that aren't cubes, which
+/// keep the faces of their models. Every texture gets its own material instead
+/// of an atlas tile, so the merged rectangles can repeat it; biome tints are
+/// vertex colors.
+pub fn write_glb(world: &World) -> io::Result<Vec<u8>> {
+    let block_registry = &world.block_registry;
+    let fallback = block_registry
+        .texture_registry
+        .get_index_of(MISSING_TEXTURE)
+        .unwrap_or(0);
+
+    let models = block_registry
+        .block_types
+        .values()
+        .map(|block_type| (block_type.shape != Shape::Cube).then(|| block_type.model(fallback)))
+        .collect::<Vec<_>>();
+
+    let mut primitives: BTreeMap<TextureId, Primitive> = BTreeMap::new();
+    for (section, faces) in cull_faces(world, WorldEdges::Solid) {
+        let origin = section.origin();
+        let world_position = |position: [u32; 3]| [0, 1, 2].map(|i| origin[i] + position[i] as i32);
+        let (cubes, shaped): (Vec<_>, Vec<_>) = faces
+            .into_iter()
+            .partition(|face| models[face.block_type_id].is_none());
+        let quads = greedy_mesh(&cubes, |face| {
+            let block_type = &block_registry.block_types[face.block_type_id];
+            let texture = block_type.texture(face.direction).unwrap_or(fallback);
+            let (x, y, z) = face.position;
+            (texture, world.tint_color(world_position([x, y, z])))
+        });
+        for quad in quads {
+            let (texture, tint) = quad.key;
+            let mut corner = world_position(quad.position).map(|v| v as f32);
+            if quad.direction.is_positive() {
+                corner[quad.direction.axis()] += 1.0;
+            }
+            primitives.entry(texture).or_default().push_quad(
+                quad.direction,
+                corner,
+                quad.size.map(|v| v as f32),
+                tint,
+            );
+        }
+
+        for face in shaped {
+            let model = models[face.block_type_id].as_ref().unwrap();
+            let (x, y, z) = face.position;
+            let position = world_position([x, y, z]);
+            let tint = world.tint_color(position);
+            let axis = face.direction.axis();
+            for voxel in &model.voxels {
+                let mut corner = [0, 1, 2].map(|i| position[i] as f32 + voxel.from[i] / 16.0);
+                if face.direction.is_positive() {
+                    corner[axis] = position[axis] as f32 + voxel.to[axis] / 16.0;
+                }
+                let size = face
+                    .direction
+                    .plane_axes()
+                    .map(|a| (voxel.to[a] - voxel.from[a]) / 16.0);
+                primitives
+                    .entry(voxel.faces.get(face.direction).texture)
+                    .or_default()
+                    .push_quad(face.direction, corner, size, tint);
+            }
+        }
+    }
+
+    let mut buffer = Buffer::default();
+    let mut json_primitives = Vec::new();
+    let mut materials = Vec::new();
+    let mut images = Vec::new();
+    for (texture, primitive) in &primitives {
+        let mut png = Vec::new();
+        block_registry.texture_registry[*texture]
+            .image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .map_err(io::Error::other)?;
+        images.push(json!({
+            "bufferView": buffer.push_view(&png, None),
+            "mimeType": "image/png",
+        }));
+        materials.push(json!({
+            "name": block_registry.texture_registry.get_index(*texture).unwrap().0,
+            "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": materials.len() },
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            },
+            "alphaMode": "MASK",
+        }));
+
+        json_primitives.push(json!({
+            "attributes": {
+                "POSITION": buffer.push_floats(&primitive.positions, true),
+                "NORMAL": buffer.push_floats(&primitive.normals, false),
+                "TEXCOORD_0": buffer.push_floats(&primitive.uvs, false),
+                "COLOR_0": buffer.push_floats(&primitive.colors, false),
+            },
+            "indices": buffer.push_indices(&primitive.indices),
+            "material": materials.len() - 1,
+        }));
+    }
+    let textures = (0..images.len())
+        .map(|i| json!({ "source": i, "sampler": 0 }))
+        .collect::<Vec<_>>();
+    buffer
+        .bytes
+        .resize(buffer.bytes.len().next_multiple_of(4), 0);
+
+    let document = json!({
+        "asset": { "version": "2.0", "generator": "block-world" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "name": "world", "mesh": 0 }],
+        "meshes": [{ "primitives": json_primitives }],
+        "materials": materials,
+        "textures": textures,
+        "samplers": [{
+            "magFilter": NEAREST,
+            "minFilter": NEAREST,
+            "wrapS": REPEAT,
+            "wrapT": REPEAT,
+        }],
+        "images": images,
+        "accessors": buffer.accessors,
+        "bufferViews": buffer.buffer_views,
+        "buffers": [{ "byteLength": buffer.bytes.len() }],
+    });
+    let mut json = serde_json::to_vec(&document)?;
+    json.resize(json.len().next_multiple_of(4), b' ');
+
+    let mut glb = Vec::new();
+    let length = 12 + 8 + json.len() + 8 + buffer.bytes.len();
+    glb.extend_from_slice(GLB_MAGIC);
+    glb.extend_from_slice(&GLB_VERSION.to_le_bytes());
+    glb.extend_from_slice(&(length as u32).to_le_bytes());
+    for (chunk_type, data) in [(CHUNK_JSON, &json), (CHUNK_BIN, &buffer.bytes)] {
+        glb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        glb.extend_from_slice(chunk_type);
+        glb.extend_from_slice(data);
+    }
+    Ok(glb)
+}
+
+/// Writes `world` to a `.glb` file at `path`, see `write_glb`.
+pub fn export_glb(world: &World, path: impl AsRef<Path>) -> io::Result<()> {
+    fs::write(path, write_glb(world)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::BlockRegistry;
+
+    use super::*;
+
+    fn document(glb: &[u8]) -> Value {
+        assert_eq!(&glb[0..4], GLB_MAGIC);
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+        let json_len = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(&glb[16..20], CHUNK_JSON);
+        serde_json::from_slice(&glb[20..20 + json_len]).unwrap()
+    }
+
+    #[test]
+    fn test_merged_box() {
+        let mut world = World::new(BlockRegistry::default());
+        // A 2x1x1 box in the middle of a section, all of its faces visible
+        world.fill_cuboid([4, 4, 4], [6, 5, 5], 1);
+
+        let document = document(&write_glb(&world).unwrap());
+        let primitives = document["meshes"][0]["primitives"].as_array().unwrap();
+        assert_eq!(primitives.len(), 1);
+        let accessors = &document["accessors"];
+        let positions =
+            &accessors[primitives[0]["attributes"]["POSITION"].as_u64().unwrap() as usize];
+        // Six merged quads
+        assert_eq!(positions["count"], 24);
+        assert_eq!(positions["min"], json!([4.0, 4.0, 4.0]));
+        assert_eq!(positions["max"], json!([6.0, 5.0, 5.0]));
+        let indices = &accessors[primitives[0]["indices"].as_u64().unwrap() as usize];
+        assert_eq!(indices["count"], 36);
+        assert_eq!(document["images"].as_array().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,541 @@
+//! Rendering worlds into offscreen images without a window, for tests that
+//! run on any machine with a Vulkan device, lavapipe and SwiftShader
+//! included. Frames are drawn by the faces pipeline as in the game, without
+//! the passes after it, and finished before `render` returns.
+//!
+//! The golden-image tests compare frames of fixed scenes with the PNGs in
+//! `tests/golden`. A missing reference fails the test. They are written
+//! from the frames with `BLOCK_WORLD_UPDATE_GOLDEN` set, after a change
+//! that is meant to alter the picture or for a new test. References are rendered with
+//! lavapipe, other devices rasterize edges slightly differently.
+
+use std::{
+    collections::HashSet,
+    sync::{atomic::Ordering, Arc},
+};
+
+use cgmath::{Point3, Vector2, Vector3};
+use image::{Rgba, RgbaImage};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::CopyImageToBufferInfo,
+    device::Queue,
+    format::Format,
+    image::{view::ImageView, ImageUsage, SampleCount},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::graphics::{subpass::PipelineRenderingCreateInfo, viewport::Viewport},
+    sync::{self, GpuFuture},
+};
+
+use crate::{
+    app::App,
+    camera::{CameraSettings, DepthMode},
+    renderer::{
+        block_sampler, depth_format, draw,
+        frame_graph::{FrameGraph, QueueKind, Usage},
+        frames::FramesInFlight,
+        hi_z::HiZPyramid,
+        render_faces::{Camera, RenderFacesPipeline},
+        voxel_renderer::render_target,
+        Attachment, COLOR_FORMAT,
+    },
+    settings::WorldEdges,
+    types::{ColumnPosition, World},
+    weather::Weather,
+};
+
+/// Columns around the camera the faces pipeline keeps, which only matters
+/// to `WorldEdges::FogWall`.
+const RENDER_DISTANCE: u32 = 4;
+
+/// Draws the opaque world into a color image of its own, one frame at a
+/// time.
+pub struct HeadlessRenderer {
+    app: App,
+    queue: Arc<Queue>,
+    extent: [u32; 2],
+    depth_mode: DepthMode,
+    frames: FramesInFlight,
+    color: Arc<ImageView>,
+    /// The color of the last frame, copied out for the CPU.
+    readback: Subbuffer<[u8]>,
+    depth: Attachment,
+    motion_vector: Arc<ImageView>,
+    hi_z: HiZPyramid,
+    render_faces: RenderFacesPipeline,
+    weather: Weather,
+    /// Columns uploaded to the faces pipeline.
+    uploaded: HashSet<ColumnPosition>,
+    previous_camera: Option<Camera>,
+}
+
+/// The value of the half float `bits`.
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10 & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32 / 1024.0;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-14),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// Encodes the linear `value` in sRGB, as the encode pass does for the
+/// swapchain.
+fn encode_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+fn loaded_columns(world: &World) -> HashSet<ColumnPosition> {
+    world
+        .chunks
+        .keys()
+        .map(|position| ColumnPosition {
+            x: position.x,
+            z: position.z,
+        })
+        .collect()
+}
+
+impl HeadlessRenderer {
+    /// A renderer of `world` into images of `extent`, with room for as many
+    /// sections as it has loaded now.
+    pub fn new(app: App, world: &mut World, extent: [u32; 2]) -> Self {
+        let queue = app.context.graphics_queue().clone();
+        let queue_family_indices = [queue.queue_family_index()];
+        let depth_mode = DepthMode::Standard;
+        let image = |format, usage| {
+            render_target(
+                &app,
+                [extent[0], extent[1], 1],
+                format,
+                usage,
+                SampleCount::Sample1,
+                &queue_family_indices,
+            )
+        };
+        let color = image(
+            COLOR_FORMAT,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+        );
+        let readback = Buffer::new_slice::<u8>(
+            app.context.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            extent[0] as u64 * extent[1] as u64 * COLOR_FORMAT.block_size(),
+        )
+        .unwrap();
+        let depth = Attachment {
+            image: image(
+                depth_format(depth_mode),
+                ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::SAMPLED,
+            ),
+            resolve: None,
+        };
+        let motion_vector = image(
+            Format::R16G16_SFLOAT,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+        );
+
+        let hi_z = HiZPyramid::new(&app, &[depth.image.clone()], depth_mode);
+        let render_faces = RenderFacesPipeline::new(
+            &app,
+            queue.clone(),
+            PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(COLOR_FORMAT), Some(Format::R16G16_SFLOAT)],
+                depth_attachment_format: Some(depth_format(depth_mode)),
+                ..Default::default()
+            },
+            SampleCount::Sample1,
+            &world.block_registry,
+            world.events.subscribe(),
+            world.chunks.len().max(1) as u64,
+            &hi_z,
+            block_sampler(app.context.device().clone(), 0.0),
+            false,
+            false,
+            WorldEdges::default(),
+            RENDER_DISTANCE,
+        );
+        Self {
+            frames: FramesInFlight::new(app.context.device(), 1),
+            app,
+            queue,
+            extent,
+            depth_mode,
+            color,
+            readback,
+            depth,
+            motion_vector,
+            hi_z,
+            render_faces,
+            weather: Weather::new(0),
+            uploaded: HashSet::new(),
+            previous_camera: None,
+        }
+    }
+
+    /// Whether the validation layer reported an error so far.
+    pub fn validation_error_encountered(&self) -> bool {
+        self.app
+            .validation_error_encountered
+            .load(Ordering::Relaxed)
+    }
+
+    /// The last frame, encoded in sRGB and opaque.
+    pub fn image(&self) -> RgbaImage {
+        let pixels = self.readback.read().unwrap();
+        let channel = |offset: usize| {
+            let bits = u16::from_le_bytes([pixels[offset], pixels[offset + 1]]);
+            encode_srgb(f16_to_f32(bits))
+        };
+        RgbaImage::from_fn(self.extent[0], self.extent[1], |x, y| {
+            let offset = (y * self.extent[0] + x) as usize * 8;
+            Rgba([
+                channel(offset),
+                channel(offset + 2),
+                channel(offset + 4),
+                255,
+            ])
+        })
+    }
+
+    /// A camera at `position` looking toward `forward` with the default
+    /// settings, for the extent of the images.
+    pub fn camera(&self, position: Point3<f32>, forward: Vector3<f32>) -> Camera {
+        let settings = CameraSettings::default();
+        let aspect = self.extent[0] as f32 / self.extent[1] as f32;
+        Camera {
+            position,
+            view: cgmath::Matrix4::look_at_rh(position, position + forward, Vector3::unit_y()),
+            proj: settings.projection(aspect),
+            near: settings.near,
+            far: settings.far_plane(),
+            fovy: settings.current_fovy(),
+            jitter: Vector2::new(0.0, 0.0),
+        }
+    }
+
+    /// Draws `world` as `camera` sees it, uploading the columns loaded since
+    /// the last frame and the edits made since, and waits for it to finish
+    /// and be copied out for `image`.
+    pub fn render(&mut self, world: &World, camera: &Camera) {
+        let loaded = loaded_columns(world);
+        let entered = loaded
+            .difference(&self.uploaded)
+            .copied()
+            .collect::<Vec<_>>();
+        let left = self
+            .uploaded
+            .difference(&loaded)
+            .copied()
+            .collect::<Vec<_>>();
+        self.uploaded = loaded;
+        let camera_block = [
+            camera.position.x.floor() as i32,
+            camera.position.y.floor() as i32,
+            camera.position.z.floor() as i32,
+        ];
+        let previous_camera = self.previous_camera.replace(camera.clone());
+        let viewport = Viewport {
+            extent: [self.extent[0] as f32, self.extent[1] as f32],
+            ..Default::default()
+        };
+
+        let frame = self.frames.next_frame();
+        let before = sync::now(self.queue.device().clone());
+        let mut graph = FrameGraph::new(frame, self.queue.clone(), self.queue.clone(), before);
+        graph.import("color", self.color.clone());
+        graph.import("depth", self.depth.image.clone());
+        graph.import("motion_vectors", self.motion_vector.clone());
+        graph.pass(
+            "world",
+            QueueKind::Graphics,
+            &[
+                ("color", Usage::ColorAttachment),
+                ("depth", Usage::DepthAttachment),
+                ("motion_vectors", Usage::ColorAttachment),
+            ],
+            |builder| {
+                self.render_faces
+                    .update_chunks(builder, world, &entered, &left);
+                self.render_faces.update_visibility(builder, camera_block);
+                if previous_camera.is_some() {
+                    self.hi_z.build(builder, 0);
+                }
+                self.render_faces.cull_blocks(builder, camera);
+                draw(
+                    builder,
+                    self.color.clone(),
+                    self.motion_vector.clone(),
+                    &self.depth,
+                    self.depth_mode,
+                    viewport,
+                    |builder| {
+                        self.render_faces.render_cube_faces(
+                            builder,
+                            previous_camera.as_ref().unwrap_or(camera),
+                            camera,
+                            previous_camera.is_some(),
+                            &self.weather,
+                        )
+                    },
+                );
+            },
+        );
+        graph.pass(
+            "readback",
+            QueueKind::Graphics,
+            &[("color", Usage::CopySource)],
+            |builder| {
+                builder
+                    .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                        self.color.image().clone(),
+                        self.readback.clone(),
+                    ))
+                    .unwrap();
+            },
+        );
+        let after = frame.submit(graph.finish());
+        self.render_faces.submit_uploads(after.clone());
+        after.wait(None).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf};
+
+    use crate::{
+        biome::BiomeRegistry,
+        texture::TextureRegistry,
+        types::{BlockRegistry, BlockTypeId, Chunk},
+        worldgen::{WorldGenerator, WorldSeed},
+    };
+
+    use super::*;
+
+    /// Extent of the golden images.
+    const GOLDEN_EXTENT: [u32; 2] = [160, 120];
+    /// Difference per channel up to which pixels count as equal, for the
+    /// rounding of drivers.
+    const CHANNEL_TOLERANCE: u8 = 8;
+    /// Share of the pixels that may differ, for edges rasterized on the
+    /// other side of a pixel center.
+    const MAX_DIFFERENT_PIXELS: f64 = 0.005;
+
+    /// The share of pixels of `image` differing from `reference` by more
+    /// than `CHANNEL_TOLERANCE`, or `None` if their sizes differ.
+    fn different_pixels(image: &RgbaImage, reference: &RgbaImage) -> Option<f64> {
+        if image.dimensions() != reference.dimensions() {
+            return None;
+        }
+        let different = image
+            .pixels()
+            .zip(reference.pixels())
+            .filter(|(a, b)| {
+                a.0.iter()
+                    .zip(b.0)
+                    .any(|(&a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE)
+            })
+            .count();
+        Some(different as f64 / (image.width() * image.height()) as f64)
+    }
+
+    /// Compares `image` with the reference `name`, writing it as the
+    /// reference instead if they are being updated. A mismatching image,
+    /// or one without a reference, is kept in the temporary directory to
+    /// look at.
+    fn assert_golden(name: &str, image: &RgbaImage) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{}.png", name));
+        if env::var_os("BLOCK_WORLD_UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image.save(&path).unwrap();
+            eprintln!("Wrote the golden image {}", path.display());
+            return;
+        }
+        let actual = env::temp_dir().join(format!("block-world-golden-{}.png", name));
+        if !path.exists() {
+            image.save(&actual).unwrap();
+            panic!(
+                "{} has no reference at {}, the frame is at {}. Render the references with \
+                 lavapipe and BLOCK_WORLD_UPDATE_GOLDEN set.",
+                name,
+                path.display(),
+                actual.display()
+            );
+        }
+        let reference = image::open(&path).unwrap().to_rgba8();
+        let different = different_pixels(image, &reference);
+        if different.map_or(true, |different| different > MAX_DIFFERENT_PIXELS) {
+            image.save(&actual).unwrap();
+            panic!(
+                "{} differs from {} in {:?} of the pixels, the frame is at {}",
+                name,
+                path.display(),
+                different,
+                actual.display()
+            );
+        }
+    }
+
+    /// Empty columns around the origin with the block textures of the game,
+    /// for scenes placed by hand.
+    fn empty_world() -> World {
+        let textures =
+            TextureRegistry::from_directory(concat!(env!("CARGO_MANIFEST_DIR"), "/textures"))
+                .unwrap();
+        let mut world = World::new(BlockRegistry::new(textures));
+        for x in -1..=1 {
+            for z in -1..=1 {
+                let sections = ColumnPosition { x, z }
+                    .sections(world.height)
+                    .map(|chunk_position| (chunk_position, Chunk::default()))
+                    .collect::<Vec<_>>();
+                world.insert_chunks(sections);
+            }
+        }
+        world
+    }
+
+    fn block_id(world: &World, name: &str) -> BlockTypeId {
+        world.block_registry.block_types.get_index_of(name).unwrap()
+    }
+
+    /// Renders `world` from `position` looking at `target` and compares the
+    /// frame with the reference `name`.
+    fn render_golden(name: &str, mut world: World, position: Point3<f32>, target: Point3<f32>) {
+        let Some(app) = App::headless() else {
+            eprintln!("No Vulkan device can run the game, skipping");
+            return;
+        };
+        let mut renderer = HeadlessRenderer::new(app, &mut world, GOLDEN_EXTENT);
+        let camera = renderer.camera(position, target - position);
+        renderer.render(&world, &camera);
+        assert!(!renderer.validation_error_encountered());
+        assert_golden(name, &renderer.image());
+    }
+
+    /// The columns around the origin of the world of seed 1.
+    fn test_world() -> World {
+        let block_registry = BlockRegistry::default();
+        let generator =
+            WorldGenerator::new(WorldSeed(1), &block_registry, BiomeRegistry::default());
+        let mut world = World::new(block_registry);
+        for x in -1..=1 {
+            for z in -1..=1 {
+                generator.generate(&mut world, ColumnPosition { x, z });
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn test_headless_frames() {
+        let Some(app) = App::headless() else {
+            eprintln!("No Vulkan device can run the game, skipping");
+            return;
+        };
+        let mut world = test_world();
+        let mut renderer = HeadlessRenderer::new(app, &mut world, [128, 96]);
+        let forward = Vector3::new(1.0, -0.4, 0.0);
+        for x in 0..3 {
+            let camera = renderer.camera(Point3::new(x as f32 * 4.0 - 16.0, 100.0, 8.0), forward);
+            renderer.render(&world, &camera);
+        }
+
+        // Edits upload the blocks around them again
+        let stone = world
+            .block_registry
+            .block_types
+            .get_index_of("stone")
+            .unwrap();
+        world.set_block([0, 120, 8], stone);
+        let camera = renderer.camera(Point3::new(-8.0, 100.0, 8.0), forward);
+        renderer.render(&world, &camera);
+        assert!(!renderer.validation_error_encountered());
+    }
+
+    #[test]
+    fn test_golden_single_block() {
+        let mut world = empty_world();
+        let grass = block_id(&world, "grass");
+        world.set_block([0, 64, 0], grass);
+        render_golden(
+            "single_block",
+            world,
+            Point3::new(-2.0, 66.5, 3.0),
+            Point3::new(0.5, 64.5, 0.5),
+        );
+    }
+
+    #[test]
+    fn test_golden_chunk_edge() {
+        // A wall across the corner of four sections and the sections above,
+        // whose faces between them have to be culled like any other
+        let mut world = empty_world();
+        let stone = block_id(&world, "stone");
+        for x in -3..3 {
+            for y in 14..18 {
+                world.set_block([x, y, 0], stone);
+                world.set_block([x, y, -1], stone);
+            }
+        }
+        render_golden(
+            "chunk_edge",
+            world,
+            Point3::new(1.5, 19.0, 7.0),
+            Point3::new(0.0, 16.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn test_golden_transparent_block() {
+        // The stone behind the water keeps the face toward it
+        let mut world = empty_world();
+        let stone = block_id(&world, "stone");
+        let water = block_id(&world, "water");
+        world.set_block([0, 64, 0], stone);
+        world.set_block([0, 64, 1], water);
+        render_golden(
+            "transparent_block",
+            world,
+            Point3::new(1.5, 66.0, 4.0),
+            Point3::new(0.5, 64.5, 0.5),
+        );
+    }
+
+    #[test]
+    fn test_different_pixels() {
+        let reference = RgbaImage::from_pixel(10, 10, Rgba([100, 150, 200, 255]));
+        let mut image = reference.clone();
+        image.put_pixel(0, 0, Rgba([100 + CHANNEL_TOLERANCE, 150, 200, 255]));
+        assert_eq!(different_pixels(&image, &reference), Some(0.0));
+        image.put_pixel(1, 0, Rgba([100, 150, 200 - CHANNEL_TOLERANCE - 1, 255]));
+        assert_eq!(different_pixels(&image, &reference), Some(0.01));
+        assert_eq!(different_pixels(&RgbaImage::new(10, 5), &reference), None);
+
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(encode_srgb(1.0), 255);
+        assert_eq!(encode_srgb(0.5), 188);
+    }
+}
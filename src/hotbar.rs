@@ -0,0 +1,147 @@
+//! The blocks at hand, one of which is selected for placing, and the edits
+//! clicking makes to the world.
+
+use crate::types::{BlockRegistry, BlockTypeId, World, UNKNOWN_BLOCK};
+
+pub const HOTBAR_SLOTS: usize = 9;
+/// Blocks away the player can break and place blocks.
+pub const REACH: f32 = 6.0;
+
+#[derive(Debug, Clone)]
+pub struct Hotbar {
+    /// Air for an empty slot.
+    pub slots: [BlockTypeId; HOTBAR_SLOTS],
+    selected: usize,
+}
+
+impl Hotbar {
+    /// Fills the slots with the placeable block types in registry order:
+    /// one variant of each, and neither air nor the unknown block.
+    pub fn new(block_registry: &BlockRegistry) -> Self {
+        let mut slots = [0; HOTBAR_SLOTS];
+        let placeable = block_registry
+            .block_types
+            .values()
+            .enumerate()
+            .filter(|(id, block_type)| {
+                *id != 0
+                    && block_type.name != UNKNOWN_BLOCK
+                    && block_type.name == block_type.base_name()
+            })
+            .map(|(id, _)| id);
+        for (slot, id) in slots.iter_mut().zip(placeable) {
+            *slot = id;
+        }
+        Self { slots, selected: 0 }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// The block in the selected slot.
+    pub fn held(&self) -> BlockTypeId {
+        self.slots[self.selected]
+    }
+
+    /// Selects `slot`, if there is one.
+    pub fn select(&mut self, slot: usize) {
+        if slot < HOTBAR_SLOTS {
+            self.selected = slot;
+        }
+    }
+
+    /// Moves the selection by `steps` slots, wrapping around at the ends.
+    pub fn scroll(&mut self, steps: i32) {
+        self.selected = (self.selected as i32 + steps).rem_euclid(HOTBAR_SLOTS as i32) as usize;
+    }
+}
+
+/// What a click does to the block looked at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Break,
+    /// Puts the held block against the face looked at.
+    Place,
+}
+
+/// The block `action` sets, and its new type, when looking along
+/// `direction` from `eye` with `held` in hand. Nothing is set when no block
+/// is in reach, or when placing with an empty hand or into a block.
+pub fn action_target(
+    world: &World,
+    eye: [f32; 3],
+    direction: [f32; 3],
+    action: Action,
+    held: BlockTypeId,
+) -> Option<([i32; 3], BlockTypeId)> {
+    let hit = world.raycast(eye, direction, REACH)?;
+    match action {
+        Action::Break => Some((hit.position, 0)),
+        Action::Place => {
+            let (dx, dy, dz) = hit.face.to_offset();
+            let [x, y, z] = hit.position;
+            let position = [x + dx, y + dy, z + dz];
+            let free = world.height.contains(position[1]) && world[position] == 0;
+            (held != 0 && free).then(|| (position, world.block_registry.oriented(held, hit.face)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::Direction;
+
+    use super::*;
+
+    #[test]
+    fn test_hotbar() {
+        let mut world = World::new(BlockRegistry::default());
+        let mut hotbar = Hotbar::new(&world.block_registry);
+        let names = hotbar
+            .slots
+            .iter()
+            .map(|&id| world.block_registry.block_types[id].name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names[..3], ["stone", "grass", "dirt"]);
+        assert!(!names.iter().any(|name| name.contains('[')));
+
+        hotbar.scroll(-1);
+        assert_eq!(hotbar.selected(), HOTBAR_SLOTS - 1);
+        hotbar.scroll(2);
+        assert_eq!(hotbar.selected(), 1);
+        hotbar.select(HOTBAR_SLOTS);
+        assert_eq!(hotbar.selected(), 1);
+
+        let log = world
+            .block_registry
+            .block_types
+            .get_index_of("log")
+            .unwrap();
+        hotbar.slots[4] = log;
+        hotbar.select(4);
+        world.fill_cuboid([-2, 5, -2], [3, 6, 3], 1);
+        let eye = [0.5, 7.5, 0.5];
+        let down = [0.0, -1.0, 0.0];
+        assert_eq!(
+            action_target(&world, eye, down, Action::Place, hotbar.held()),
+            Some(([0, 6, 0], log))
+        );
+        assert_eq!(
+            action_target(&world, eye, down, Action::Break, hotbar.held()),
+            Some(([0, 5, 0], 0))
+        );
+        assert_eq!(
+            action_target(&world, eye, [0.0, 1.0, 0.0], Action::Break, hotbar.held()),
+            None
+        );
+
+        // Placed against a side, a log lies along the side's axis
+        world.set_block([0, 6, 2], 1);
+        let along_z = world.block_registry.oriented(log, Direction::North);
+        assert_eq!(
+            action_target(&world, eye, [0.0, -0.5, 1.0], Action::Place, hotbar.held()),
+            Some(([0, 6, 1], along_z))
+        );
+    }
+}
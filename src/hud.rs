@@ -0,0 +1,395 @@
+//! The 2D overlay drawn over the upscaled image at display resolution: the
+//! crosshair, the hotbar, the name of the held block, the player's
+//! coordinates and the console. It is laid out in logical pixels, which the
+//! window's scale factor makes physical, as quads from the top left corner.
+
+use cgmath::Point3;
+
+use crate::{
+    biome::BiomeColors,
+    console::Console,
+    hotbar::{Hotbar, HOTBAR_SLOTS},
+    text::{GlyphQuad, TextRenderer},
+    types::{BlockRegistry, Direction, TextureId},
+};
+
+/// Pixels between the HUD and the edges of the screen.
+const MARGIN: f32 = 12.0;
+/// Height of the text, in pixels.
+const TEXT_SIZE: f32 = 20.0;
+const CROSSHAIR_LENGTH: f32 = 20.0;
+const CROSSHAIR_WIDTH: f32 = 2.0;
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const SHADOW: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const SLOT_SIZE: f32 = 64.0;
+/// Space around the block inside a slot.
+const SLOT_PADDING: f32 = 10.0;
+const SLOT_BORDER: f32 = 3.0;
+/// Output lines shown above the console's input.
+const CONSOLE_LINES: usize = 10;
+const MENU_WIDTH: f32 = 520.0;
+/// Behind the selected line of a menu.
+const HIGHLIGHT: [f32; 4] = [1.0, 1.0, 1.0, 0.2];
+/// The whole of a texture.
+const FULL_UV: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+/// What a `HudQuad` is textured with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HudTexture {
+    /// A layer of the block textures.
+    Block(TextureId),
+    /// The glyph atlas of the `TextRenderer`, whose coverage scales the
+    /// alpha.
+    Glyphs,
+}
+
+/// A rectangle of the HUD.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HudQuad {
+    /// Top left corner, in pixels.
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    /// Multiplied with the texture, if any.
+    pub color: [f32; 4],
+    pub texture: Option<HudTexture>,
+    /// Left, top, right and bottom edges of the part of the texture shown,
+    /// from 0 to 1.
+    pub uv: [f32; 4],
+}
+
+pub struct Hud {
+    quads: Vec<HudQuad>,
+    text: TextRenderer,
+    glyphs: Vec<GlyphQuad>,
+    /// Physical pixels per logical pixel.
+    scale: f32,
+}
+
+impl Default for Hud {
+    fn default() -> Self {
+        Self {
+            quads: Vec::new(),
+            text: TextRenderer::default(),
+            glyphs: Vec::new(),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Hud {
+    pub fn quads(&self) -> &[HudQuad] {
+        &self.quads
+    }
+
+    /// The renderer of the HUD's text, whose atlas the glyph quads sample.
+    pub fn text_renderer(&self) -> &TextRenderer {
+        &self.text
+    }
+
+    /// Sets the scale factor of the window, by which the HUD grows on HiDPI
+    /// screens. Text is rasterized at the scaled size to stay sharp.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    /// Lays out the HUD of a frame on a screen of `screen_size` physical
+    /// pixels. `saving` columns are waiting to be written to disk.
+    pub fn update(
+        &mut self,
+        screen_size: [f32; 2],
+        position: Point3<f32>,
+        hotbar: &Hotbar,
+        block_registry: &BlockRegistry,
+        console: &Console,
+        saving: usize,
+    ) {
+        self.quads.clear();
+        self.text.begin_frame();
+        let screen_size = self.logical(screen_size);
+        self.crosshair(screen_size);
+        self.hotbar(screen_size, hotbar, block_registry);
+        if console.is_open() {
+            self.console(screen_size, console);
+        }
+        let line_height = self.line_height();
+        self.text(
+            [MARGIN, MARGIN],
+            TEXT_SIZE,
+            WHITE,
+            &format!(
+                "XYZ: {:.1} / {:.1} / {:.1}",
+                position.x, position.y, position.z
+            ),
+        );
+        let held = &block_registry.block_types[hotbar.held()];
+        self.text(
+            [MARGIN, MARGIN + line_height],
+            TEXT_SIZE,
+            WHITE,
+            held.base_name(),
+        );
+        if saving > 0 {
+            self.text(
+                [MARGIN, MARGIN + 2.0 * line_height],
+                TEXT_SIZE,
+                WHITE,
+                &format!("Saving: {} columns left", saving),
+            );
+        }
+    }
+
+    pub fn rect(&mut self, position: [f32; 2], size: [f32; 2], color: [f32; 4]) {
+        self.push(HudQuad {
+            position,
+            size,
+            color,
+            texture: None,
+            uv: FULL_UV,
+        });
+    }
+
+    /// Adds `quad`, laid out in logical pixels. Its corners are rounded to
+    /// whole physical pixels so edges stay crisp at fractional scales.
+    fn push(&mut self, quad: HudQuad) {
+        let [left, top] = quad.position.map(|v| (v * self.scale).round());
+        let [right, bottom] =
+            [0, 1].map(|i| ((quad.position[i] + quad.size[i]) * self.scale).round());
+        self.quads.push(HudQuad {
+            position: [left, top],
+            size: [right - left, bottom - top],
+            ..quad
+        });
+    }
+
+    /// `screen_size` in physical pixels, in logical pixels.
+    fn logical(&self, screen_size: [f32; 2]) -> [f32; 2] {
+        screen_size.map(|v| v / self.scale)
+    }
+
+    /// Logical pixels from the top of a line of text to the next.
+    fn line_height(&self) -> f32 {
+        self.text.line_height(TEXT_SIZE * self.scale) / self.scale
+    }
+
+    /// The slots along the bottom of the screen, each showing the side of
+    /// its block, with the selected one framed.
+    fn hotbar(&mut self, screen_size: [f32; 2], hotbar: &Hotbar, block_registry: &BlockRegistry) {
+        let width = SLOT_SIZE * HOTBAR_SLOTS as f32;
+        let left = ((screen_size[0] - width) / 2.0).floor();
+        let top = screen_size[1] - MARGIN - SLOT_SIZE;
+        self.rect([left, top], [width, SLOT_SIZE], SHADOW);
+        for (i, &block_type_id) in hotbar.slots.iter().enumerate() {
+            let x = left + i as f32 * SLOT_SIZE;
+            if i == hotbar.selected() {
+                let outer = SLOT_SIZE + SLOT_BORDER * 2.0;
+                self.rect([x - SLOT_BORDER, top - SLOT_BORDER], [outer, outer], WHITE);
+                self.rect([x, top], [SLOT_SIZE, SLOT_SIZE], SHADOW);
+            }
+            if block_type_id == 0 {
+                continue;
+            }
+            let block_type = &block_registry.block_types[block_type_id];
+            let [r, g, b] = BiomeColors::default()
+                .tint_color(block_type.tint)
+                .unwrap_or([255, 255, 255])
+                .map(|v| v as f32 / 255.0);
+            self.push(HudQuad {
+                position: [x + SLOT_PADDING, top + SLOT_PADDING],
+                size: [SLOT_SIZE - SLOT_PADDING * 2.0; 2],
+                color: [r, g, b, 1.0],
+                texture: block_type.texture(Direction::North).map(HudTexture::Block),
+                uv: FULL_UV,
+            });
+        }
+    }
+
+    /// The last lines of the console's output over a dark panel above the
+    /// hotbar, with the input and a cursor below them.
+    fn console(&mut self, screen_size: [f32; 2], console: &Console) {
+        let line_height = self.line_height();
+        let bottom = screen_size[1] - MARGIN * 3.0 - SLOT_SIZE;
+        let top = bottom - line_height * (CONSOLE_LINES + 1) as f32;
+        self.rect(
+            [MARGIN, top - MARGIN / 2.0],
+            [screen_size[0] - MARGIN * 2.0, bottom - top + MARGIN],
+            SHADOW,
+        );
+        // Newest first, upwards from the input
+        for (i, line) in console.output().rev().take(CONSOLE_LINES).enumerate() {
+            let y = bottom - (i + 2) as f32 * line_height;
+            self.text([MARGIN * 2.0, y], TEXT_SIZE, WHITE, line);
+        }
+        self.text(
+            [MARGIN * 2.0, bottom - line_height],
+            TEXT_SIZE,
+            WHITE,
+            &format!("{}_", console.input()),
+        );
+    }
+
+    /// A panel in the middle of the screen with `title` over `lines`, the
+    /// `selected` one highlighted.
+    pub fn menu(&mut self, screen_size: [f32; 2], title: &str, lines: &[String], selected: usize) {
+        let screen_size = self.logical(screen_size);
+        let line_height = self.line_height();
+        let height = line_height * (lines.len() + 2) as f32;
+        let left = ((screen_size[0] - MENU_WIDTH) / 2.0).floor();
+        let top = ((screen_size[1] - height) / 2.0).floor();
+        self.rect(
+            [left - MARGIN, top - MARGIN],
+            [MENU_WIDTH + MARGIN * 2.0, height + MARGIN * 2.0],
+            SHADOW,
+        );
+        self.text([left, top], TEXT_SIZE, WHITE, title);
+        for (i, line) in lines.iter().enumerate() {
+            let y = top + (i + 2) as f32 * line_height;
+            if i == selected {
+                self.rect(
+                    [left - MARGIN / 2.0, y],
+                    [MENU_WIDTH + MARGIN, line_height],
+                    HIGHLIGHT,
+                );
+            }
+            self.text([left, y], TEXT_SIZE, WHITE, line);
+        }
+    }
+
+    /// A plus in the middle of the screen, with a dark outline so it shows
+    /// on bright and dark blocks alike.
+    fn crosshair(&mut self, screen_size: [f32; 2]) {
+        let [cx, cy] = screen_size.map(|v| (v / 2.0).floor());
+        let (long, short) = (CROSSHAIR_LENGTH, CROSSHAIR_WIDTH);
+        for (outline, color) in [(1.0, SHADOW), (0.0, WHITE)] {
+            self.rect(
+                [cx - long / 2.0 - outline, cy - short / 2.0 - outline],
+                [long + outline * 2.0, short + outline * 2.0],
+                color,
+            );
+            self.rect(
+                [cx - short / 2.0 - outline, cy - long / 2.0 - outline],
+                [short + outline * 2.0, long + outline * 2.0],
+                color,
+            );
+        }
+    }
+
+    /// Writes `text` with its top left corner at `position`, `size` pixels
+    /// high, over a drop shadow. `\n` starts a new line. Returns the width
+    /// of the longest line. Unlike the rest, the glyphs are laid out in
+    /// physical pixels, at the size they are rasterized at.
+    pub fn text(&mut self, position: [f32; 2], size: f32, color: [f32; 4], text: &str) -> f32 {
+        self.glyphs.clear();
+        let position = position.map(|v| (v * self.scale).round());
+        let size = size * self.scale;
+        let width = self.text.layout(position, size, text, &mut self.glyphs) / self.scale;
+        let offset = (size / 12.0).round().max(1.0);
+        for (offset, color) in [(offset, SHADOW), (0.0, color)] {
+            self.quads.extend(self.glyphs.iter().map(|glyph| HudQuad {
+                position: glyph.position.map(|v| v + offset),
+                size: glyph.size,
+                color,
+                texture: Some(HudTexture::Glyphs),
+                uv: glyph.uv,
+            }));
+        }
+        width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hud() {
+        let mut hud = Hud::default();
+        // Every glyph is drawn twice, over its shadow
+        let width = hud.text([0.0, 0.0], 20.0, WHITE, "1 ");
+        assert!(width > 10.0 && width < 40.0);
+        assert_eq!(hud.quads().len(), 2);
+        assert_eq!(hud.quads()[0].color, SHADOW);
+        assert_eq!(hud.quads()[1].color, WHITE);
+        assert_eq!(
+            hud.quads()[0].position,
+            hud.quads()[1].position.map(|v| v + 2.0)
+        );
+        assert!(hud
+            .quads()
+            .iter()
+            .all(|quad| quad.texture == Some(HudTexture::Glyphs) && quad.size[1] <= 20.0));
+
+        let block_registry = BlockRegistry::default();
+        let hotbar = Hotbar::new(&block_registry);
+        hud.update(
+            [1680.0, 960.0],
+            Point3::new(1.0, 2.0, 3.0),
+            &hotbar,
+            &block_registry,
+            &Console::new(&block_registry),
+            0,
+        );
+        // The crosshair is centered on the screen
+        let crosshair = hud.quads()[..4]
+            .iter()
+            .filter(|quad| quad.color == WHITE)
+            .collect::<Vec<_>>();
+        assert_eq!(crosshair.len(), 2);
+        for quad in crosshair {
+            assert_eq!(quad.position[0] + quad.size[0] / 2.0, 840.0);
+            assert_eq!(quad.position[1] + quad.size[1] / 2.0, 480.0);
+        }
+        // A textured quad for every filled slot
+        let filled = hotbar.slots.iter().filter(|&&id| id != 0).count();
+        assert_eq!(
+            hud.quads()
+                .iter()
+                .filter(|quad| matches!(quad.texture, Some(HudTexture::Block(_))))
+                .count(),
+            filled
+        );
+
+        // The open console adds its panel and the glyphs of its input
+        let closed = hud.quads().len();
+        let mut console = Console::new(&block_registry);
+        console.open("/seed");
+        hud.update(
+            [1680.0, 960.0],
+            Point3::new(1.0, 2.0, 3.0),
+            &hotbar,
+            &block_registry,
+            &console,
+            0,
+        );
+        assert!(hud.quads().len() >= closed + 1 + "/seed_".len() * 2);
+
+        // A menu adds its panel and the highlight of the selected line
+        let before = hud.quads().len();
+        hud.menu(
+            [1680.0, 960.0],
+            "Settings",
+            &["A".to_string(), "B".to_string()],
+            1,
+        );
+        assert_eq!(hud.quads().len(), before + 2 + "SettingsAB".len() * 2);
+        assert_eq!(hud.quads()[before].color, SHADOW);
+        assert!(hud.quads().iter().any(|quad| quad.color == HIGHLIGHT));
+
+        // Scaled, the HUD stays centered on the physical screen and grows
+        let crosshair_length = hud.quads()[1].size[1];
+        hud.set_scale(2.0);
+        hud.update(
+            [3360.0, 1920.0],
+            Point3::new(1.0, 2.0, 3.0),
+            &hotbar,
+            &block_registry,
+            &Console::new(&block_registry),
+            0,
+        );
+        let crosshair = &hud.quads()[1];
+        assert_eq!(crosshair.position[0] + crosshair.size[0] / 2.0, 1680.0);
+        assert_eq!(crosshair.position[1] + crosshair.size[1] / 2.0, 960.0);
+        assert_eq!(crosshair.size[1], crosshair_length * 2.0);
+        let text_width = hud.text([0.0, 0.0], 20.0, WHITE, "1 ");
+        assert!((text_width - width).abs() < 2.0);
+    }
+}
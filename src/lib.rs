@@ -0,0 +1,44 @@
+//! The game's modules, shared by the game and the dedicated server, which
+//! runs without a window or a GPU.
+
+pub mod app;
+pub mod autosave;
+pub mod biome;
+pub mod camera;
+pub mod chunk_loader;
+pub mod console;
+pub mod edit;
+pub mod entity;
+pub mod events;
+pub mod falling;
+pub mod fluid;
+pub mod frame_log;
+pub mod fsr;
+pub mod gltf;
+#[cfg(test)]
+mod headless;
+pub mod hotbar;
+pub mod hud;
+pub mod lut;
+pub mod map;
+pub mod memory;
+pub mod menu;
+pub mod model;
+pub mod net;
+pub mod particles;
+pub mod plugin;
+pub mod renderer;
+pub mod replay;
+pub mod resources;
+pub mod settings;
+pub mod setup;
+pub mod simulation;
+pub mod storage;
+pub mod svo;
+pub mod text;
+pub mod texture;
+pub mod tick;
+pub mod types;
+pub mod viewmodel;
+pub mod weather;
+pub mod worldgen;
@@ -0,0 +1,153 @@
+//! 3D color lookup tables in the `.cube` format grading tools export, for
+//! changing the look of the final image without touching the shaders.
+
+use std::{fs, io, path::Path};
+
+/// Largest table accepted, far beyond the usual 33 or 65.
+pub const MAX_LUT_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut {
+    /// Entries along each axis.
+    pub size: u32,
+    /// `size`³ output colors, red changing fastest, then green, then blue.
+    pub colors: Vec<[f32; 3]>,
+    /// Input colors mapped to the first and last entries.
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+}
+
+impl Lut {
+    /// The table leaving colors as they are. Interpolation between the
+    /// corners is exact for it.
+    pub fn neutral() -> Self {
+        let colors = (0..8)
+            .map(|i| [i & 1, (i >> 1) & 1, (i >> 2) & 1].map(|c| c as f32))
+            .collect();
+        Self {
+            size: 2,
+            colors,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+
+    /// Reads a `.cube` file. 1D tables are not supported.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut colors = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("Line {}: {}", number + 1, message);
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap();
+            let triple = |words: std::str::SplitWhitespace| -> Result<[f32; 3], String> {
+                let values = words
+                    .map(|word| word.parse::<f32>().ok().filter(|v| v.is_finite()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| error("expected numbers"))?;
+                values
+                    .try_into()
+                    .map_err(|_| error("expected three numbers"))
+            };
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => return Err(error("1D tables are not supported")),
+                "LUT_3D_SIZE" => {
+                    let value = words
+                        .next()
+                        .and_then(|word| word.parse().ok())
+                        .filter(|size| (2..=MAX_LUT_SIZE).contains(size))
+                        .ok_or_else(|| error("expected a size from 2 to 256"))?;
+                    size = Some(value);
+                }
+                "DOMAIN_MIN" => domain_min = triple(words)?,
+                "DOMAIN_MAX" => domain_max = triple(words)?,
+                _ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    return Err(error("unknown keyword"));
+                }
+                _ => colors.push(triple(line.split_whitespace())?),
+            }
+        }
+
+        let size = size.ok_or("Missing LUT_3D_SIZE")?;
+        if colors.len() != size.pow(3) as usize {
+            return Err(format!(
+                "Expected {} colors, found {}",
+                size.pow(3),
+                colors.len()
+            ));
+        }
+        if (0..3).any(|i| domain_max[i] <= domain_min[i]) {
+            return Err("DOMAIN_MAX must be above DOMAIN_MIN".to_string());
+        }
+        Ok(Self {
+            size,
+            colors,
+            domain_min,
+            domain_max,
+        })
+    }
+
+    /// The colors as A2B10G10R10 texels of a 3D image, clamped to 0 to 1.
+    pub fn packed(&self) -> Vec<u32> {
+        self.colors
+            .iter()
+            .map(|color| {
+                let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 1023.0).round() as u32);
+                r | g << 10 | b << 20 | 3 << 30
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cube_file() {
+        let lut = Lut::parse(
+            "# Swaps red and green\n\
+             TITLE \"swap\"\n\
+             LUT_3D_SIZE 2\n\
+             DOMAIN_MIN 0 0 0\n\
+             \n\
+             0 0 0\n0 1 0\n1 0 0\n1 1 0\n\
+             0 0 1\n0 1 1\n1 0 1\n1 1 1\n",
+        )
+        .unwrap();
+        assert_eq!(lut.size, 2);
+        // Red only, the second entry
+        assert_eq!(lut.colors[1], [0.0, 1.0, 0.0]);
+        assert_eq!(lut.domain_max, [1.0; 3]);
+
+        let neutral = Lut::neutral();
+        assert_eq!(neutral.colors[1], [1.0, 0.0, 0.0]);
+        assert_eq!(neutral.colors[6], [0.0, 1.0, 1.0]);
+        let packed = neutral.packed();
+        assert_eq!(packed[0], 3 << 30);
+        assert_eq!(packed[7], u32::MAX);
+
+        for text in [
+            "0 0 0\n",
+            "LUT_3D_SIZE 2\n0 0 0\n",
+            "LUT_3D_SIZE 1\n0 0 0\n",
+            "LUT_1D_SIZE 2\n0 0 0\n1 1 1\n",
+            "LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0 1\n",
+            "LUT_3D_SIZE 2\nDOMAIN_MAX 0 0 0\n",
+        ] {
+            assert!(Lut::parse(text).is_err(), "{:?}", text);
+        }
+    }
+}
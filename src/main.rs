@@ -1,43 +1,61 @@
-use std::{env, io::Write, time::Instant};
+use std::{
+    cell::RefCell, collections::HashSet, env, io::Write, rc::Rc, sync::OnceLock, time::Instant,
+};
 
-use app::App;
-use cgmath::Vector2;
-use fsr::FsrContextVulkan;
-use log::{debug, info};
-use renderer::{
-    draw,
-    render_faces::{Camera, RenderFacesPipeline},
+use block_world::{
+    app::App,
+    camera::DepthMode,
+    chunk_loader::ChunkLoader,
+    frame_log::{FrameLog, FrameRecord},
+    gltf,
+    hud::Hud,
+    map,
+    menu::{SettingsMenu, MENU_KEY},
+    net::{Client, DEFAULT_ADDRESS},
+    renderer::{
+        encode::swapchain_format,
+        render_faces::Camera,
+        voxel_renderer::{FrameInput, VoxelRenderer},
+    },
+    replay::{Input, Player, Recorder},
+    settings::{GraphicsSettings, SETTINGS_PATH},
+    setup::{create_world, serve, MAX_CACHED_COLUMNS, REGION_DIRECTORY},
+    simulation::{Simulation, SimulationThread, Snapshot},
+    storage::{AnvilImporter, RegionStorage},
+    types::{ColumnPosition, World},
+    worldgen::{
+        heightmap::{HeightmapGenerator, HeightmapSettings},
+        SEA_LEVEL,
+    },
 };
+use cgmath::Vector2;
+use log::{info, warn};
+use tracing::info_span;
 use vulkano::{
-    command_buffer::{
-        CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage, CopyImageInfo,
-        RecordingCommandBuffer,
-    },
     format::Format,
-    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage, SampleCount},
-    memory::allocator::AllocationCreateInfo,
-    pipeline::graphics::{subpass::PipelineRenderingCreateInfo, viewport::Viewport},
-    sync::GpuFuture,
-    VulkanObject,
+    image::ImageUsage,
+    swapchain::{ColorSpace, PresentMode, SurfaceInfo},
 };
-use vulkano_util::{renderer::VulkanoWindowRenderer, window::WindowDescriptor};
+use vulkano_util::window::{WindowDescriptor, WindowMode};
 use winit::{
-    event::{Event, WindowEvent},
+    dpi::PhysicalPosition,
+    event::{ElementState, Event, KeyEvent, MouseScrollDelta, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
+    keyboard::PhysicalKey,
+    window::{Fullscreen, WindowId},
 };
 
-mod app;
-mod fsr;
-mod model;
-mod renderer;
-mod resources;
-mod texture;
-mod types;
+/// The swapchain format picked in the settings, for the swapchain creation,
+/// which takes a function without state.
+static SWAPCHAIN_FORMAT: OnceLock<Format> = OnceLock::new();
 
-fn run(app: &mut App) {
+fn run(app: &mut App, mut settings: GraphicsSettings) {
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
+    SWAPCHAIN_FORMAT
+        .set(swapchain_format(settings.swapchain_format))
+        .unwrap();
     let window_id = app.windows.create_window(
         &event_loop,
         &app.context,
@@ -46,310 +64,314 @@ fn run(app: &mut App) {
             height: 960.0,
             title: "block-world".to_string(),
             resizable: false,
+            mode: if settings.fullscreen {
+                WindowMode::BorderlessFullscreen
+            } else {
+                WindowMode::Windowed
+            },
             ..Default::default()
         },
         |create_info| {
-            create_info.image_usage = ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_DST;
-            // create_info.image_format = Format::R16G16B16A16_SFLOAT;
-            // create_info.image_color_space = ColorSpace::ExtendedSrgbLinear;
-        },
-    );
-
-    let queue = app.context.graphics_queue().clone();
-
-    let render_faces_pipeline = RenderFacesPipeline::new(
-        &app,
-        queue.clone(),
-        PipelineRenderingCreateInfo {
-            color_attachment_formats: vec![
-                Some(
-                    app.windows
-                        .get_renderer(window_id)
-                        .unwrap()
-                        .swapchain_format(),
-                ),
-                Some(Format::R16G16_SFLOAT),
-            ],
-            depth_attachment_format: Some(Format::D16_UNORM),
-            ..Default::default()
+            create_info.image_usage = ImageUsage::COLOR_ATTACHMENT;
+            create_info.image_format = *SWAPCHAIN_FORMAT.get().unwrap();
+            create_info.image_color_space = ColorSpace::SrgbNonLinear;
         },
     );
+    let present_mode = present_mode(app, window_id, settings.vsync);
+    app.windows
+        .get_renderer_mut(window_id)
+        .unwrap()
+        .set_present_mode(present_mode);
+
+    let mut frame_log = None;
+    let args = env::args().collect::<Vec<_>>();
+    match args.as_slice() {
+        // Projects without a far plane, for render distances beyond it
+        [_, flag] if flag == "--infinite-far" => settings.depth_mode = DepthMode::InfiniteReversed,
+        // Writes the metrics of every frame to a CSV or JSON file
+        [_, flag, path] if flag == "--frame-log" => {
+            frame_log = Some(FrameLog::create(path).unwrap());
+        }
+        _ => {}
+    }
+    let depth_mode = settings.depth_mode;
+    let render_distance = settings.render_distance as i32;
+    let Some((simulation, mut world)) =
+        SimulationThread::spawn(move || create_simulation(&args, depth_mode, render_distance))
+    else {
+        return;
+    };
 
-    // println!(
-    //     "{:?}",
-    //     app.windows
-    //         .get_renderer_mut(window_id)
-    //         .unwrap()
-    //         .set_present_mode()
-    // );
-
-    let render_start = Instant::now();
-    let camera_fn = |jitter: Vector2<f32>| {
-        let elapsed = render_start.elapsed().as_secs_f32();
-        let position = cgmath::Point3::new(
-            (elapsed * 0.5).sin() * 3.0,
-            elapsed.sin() * 3.0,
-            (elapsed * 0.5).cos() * 3.0,
-        );
-        let near = 0.1;
-        let far = 100.0;
-        let fovy = cgmath::Deg(60.0);
+    // Created again when the settings or the size of the window change
+    // what it was created with
+    let timed = frame_log.is_some();
+    let create_renderer = |app: &App, settings: &GraphicsSettings, world: &mut World| {
+        let window = app.windows.get_renderer(window_id).unwrap();
+        let voxel_renderer = VoxelRenderer::new(app, settings, world, window);
+        // Only the frame log reads the pass timings
+        if timed {
+            voxel_renderer.with_pass_timer(app)
+        } else {
+            voxel_renderer
+        }
+    };
+    let mut voxel_renderer = create_renderer(app, &settings, &mut world);
 
+    // Looks along the flight from where the simulation put the camera
+    let camera_fn = |snapshot: &Snapshot, aspect: f32| {
+        let settings = &snapshot.camera_settings;
+        let position = snapshot.position;
         Camera {
             position,
             view: cgmath::Matrix4::look_at_rh(
                 position,
-                cgmath::Point3::new(0.0, 0.0, 0.0),
+                position + snapshot.forward,
                 cgmath::Vector3::unit_y(),
             ),
-            proj: cgmath::perspective(fovy, 1680.0 / 960.0, near, far),
-            near,
-            far,
-            fovy,
-            jitter,
+            proj: settings.projection(aspect),
+            near: settings.near,
+            far: settings.far_plane(),
+            fovy: settings.current_fovy(),
+            // Set by the renderer
+            jitter: Vector2::new(0.0, 0.0),
         }
     };
 
-    let samples = SampleCount::Sample1;
-
-    let display_size_extent = app
-        .windows
-        .get_renderer_mut(window_id)
-        .unwrap()
-        .swapchain_image_view()
-        .image()
-        .extent();
-    let display_size = [display_size_extent[0], display_size_extent[1]];
-    let render_size = [1680, 960];
-    let render_size_extent = [render_size[0], render_size[1], 1];
-    // let render_size = display_size;
-    // let render_size_extent = [render_size[0], render_size[1], 1];
-
-    println!("Render size: {:?}", render_size);
-    println!("Display size: {:?}", display_size);
-
-    let color_image = ImageView::new_default(
-        Image::new(
-            app.memory_allocator(),
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                extent: render_size_extent,
-                format: app
-                    .windows
-                    .get_renderer(window_id)
-                    .unwrap()
-                    .swapchain_format(),
-                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
-                samples,
-                ..Default::default()
-            },
-            AllocationCreateInfo::default(),
-        )
-        .unwrap(),
-    )
-    .unwrap();
-    debug!(
-        "Color image view: {:?}, image: {:?}",
-        color_image.handle(),
-        color_image.image().handle()
-    );
-
-    let depth_image = ImageView::new_default(
-        Image::new(
-            app.memory_allocator(),
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                extent: render_size_extent,
-                format: Format::D16_UNORM,
-                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::SAMPLED,
-                samples,
-                ..Default::default()
-            },
-            AllocationCreateInfo::default(),
-        )
-        .unwrap(),
-    )
-    .unwrap();
-    debug!(
-        "Depth image view: {:?}, image: {:?}",
-        depth_image.handle(),
-        depth_image.image().handle()
-    );
-
-    let motion_vector_image = ImageView::new_default(
-        Image::new(
-            app.memory_allocator(),
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                extent: render_size_extent,
-                format: Format::R16G16_SFLOAT,
-                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
-                samples,
-                ..Default::default()
-            },
-            AllocationCreateInfo::default(),
-        )
-        .unwrap(),
-    )
-    .unwrap();
-    debug!(
-        "Motion vector image view: {:?}, image: {:?}",
-        motion_vector_image.handle(),
-        motion_vector_image.image().handle()
-    );
-
-    let output_image = ImageView::new_default(
-        Image::new(
-            app.memory_allocator(),
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                extent: display_size_extent,
-                format: app
-                    .windows
-                    .get_renderer(window_id)
-                    .unwrap()
-                    .swapchain_format(),
-                usage: ImageUsage::COLOR_ATTACHMENT
-                    | ImageUsage::STORAGE
-                    | ImageUsage::TRANSFER_SRC,
-                samples,
-                ..Default::default()
-            },
-            AllocationCreateInfo::default(),
-        )
-        .unwrap(),
-    )
-    .unwrap();
-    debug!(
-        "Output image view: {:?}, image: {:?}",
-        output_image.handle(),
-        output_image.image().handle()
-    );
-
-    let ash_device = unsafe {
-        ash::Device::load(
-            &app.context.instance().fns().v1_0,
-            app.context.device().handle(),
-        )
-    };
-
-    let mut fsr_context =
-        unsafe { FsrContextVulkan::new(app.context.device(), render_size, display_size) };
-    info!("FsrContextVulkan created");
-
-    let command_buffer_allocator = app.command_buffer_allocator.clone();
-    let mut previous_camera = camera_fn([0.0, 0.0].into());
+    let memory_tracker = app.memory_tracker.clone();
+    info!("Tracked memory: {}", memory_tracker.report());
+    let physical_device = app.context.device().physical_device().clone();
+    let mut memory_budget = memory_tracker.check_budget(&physical_device);
+    let mut budget_checked = Instant::now();
+
+    let mut hud = Hud::default();
+    let mut menu = SettingsMenu::default();
+    // As of the last step the simulation sent
+    let mut latest = None;
+    // Columns in render distance, uploaded again to a new renderer
+    let mut visible = HashSet::new();
+    // Whether the settings changed what the renderer was created with
+    let mut outdated = false;
+    // Queued by the event loop and handed to the simulation, or the menu
+    // while it is open, at the start of the next frame
+    let inputs = Rc::new(RefCell::new(Vec::new()));
+    let window_inputs = inputs.clone();
     let mut frame_time = Instant::now();
-    let mut redraw = |renderer: &mut VulkanoWindowRenderer| {
-        let before = renderer.acquire(None, |_| {}).unwrap();
-
-        let jitter = unsafe { fsr_context.step_jitter() };
-
-        let camera = camera_fn(jitter);
+    // Returns whether the game is over, which it is once the simulation
+    // ended after the last step of a recording played back or when the
+    // connection to the server was lost
+    let mut redraw = |app: &mut App| -> bool {
+        let _span = info_span!("frame").entered();
+        let elapsed = frame_time.elapsed();
+        frame_time = Instant::now();
+        let previous = settings.clone();
+        for input in inputs.take() {
+            match input {
+                Input::Key {
+                    code,
+                    pressed: true,
+                    ..
+                } if menu.is_open() || code == MENU_KEY => menu.press(code, &mut settings),
+                Input::MousePressed(_) | Input::Scroll(_) if menu.is_open() => {}
+                input => simulation.input(input),
+            }
+        }
+        if settings != previous {
+            if let Err(err) = settings.save(SETTINGS_PATH) {
+                warn!("Failed to save settings: {}", err);
+            }
+            let present_mode = present_mode(app, window_id, settings.vsync);
+            let window = app.windows.get_renderer_mut(window_id).unwrap();
+            if settings.vsync != previous.vsync {
+                window.set_present_mode(present_mode);
+            }
+            // Resizes the window, and the renderer with it
+            if settings.fullscreen != previous.fullscreen {
+                window
+                    .window()
+                    .set_fullscreen(settings.fullscreen.then_some(Fullscreen::Borderless(None)));
+            }
+            if settings.render_distance != previous.render_distance {
+                simulation.set_render_distance(settings.render_distance);
+            }
+            outdated |= settings.render_distance != previous.render_distance
+                || settings.fsr.quality != previous.fsr.quality
+                || settings.shadows != previous.shadows;
+        }
 
-        let viewport = Viewport {
-            extent: [render_size[0] as f32, render_size[1] as f32],
-            ..Default::default()
+        let Some(mut update) = simulation.update(&mut world) else {
+            return true;
         };
+        for column in &update.columns.left {
+            visible.remove(column);
+        }
+        visible.extend(update.columns.entered.iter().copied());
+        for option in update.fsr {
+            option.apply(&mut settings.fsr);
+            if let Err(err) = settings.save(SETTINGS_PATH) {
+                warn!("Failed to save settings: {}", err);
+            }
+            simulation.print(if voxel_renderer.set_fsr_settings(settings.fsr.clone()) {
+                format!("FSR: {:?}", option)
+            } else {
+                format!("FSR: {:?}, for when FSR is used", option)
+            });
+        }
+        if update.snapshot.is_some() {
+            latest = update.snapshot;
+        }
+        // Nothing to draw before the first step
+        let Some(snapshot) = &latest else {
+            return false;
+        };
+        let camera_block = [
+            snapshot.position.x.floor() as i32,
+            snapshot.position.y.floor() as i32,
+            snapshot.position.z.floor() as i32,
+        ];
+
+        // In physical pixels, which the renderer and FSR work in whatever the
+        // scale factor
+        let window = app.windows.get_renderer(window_id).unwrap();
+        let window_size = window.window_size().map(|size| size as u32);
+        // Minimized
+        if window_size.contains(&0) {
+            return false;
+        }
+        hud.set_scale(window.window().scale_factor() as f32);
+        if outdated || window_size != voxel_renderer.display_size() {
+            voxel_renderer = create_renderer(app, &settings, &mut world);
+            app.windows.get_renderer_mut(window_id).unwrap().resize();
+            outdated = false;
+            // The new renderer has none of the columns yet. Those beyond a
+            // shorter render distance leave in a later step.
+            let center = ColumnPosition::of_block(camera_block);
+            update.columns.entered = ChunkLoader::new(settings.render_distance as i32, 0, 0)
+                .columns_in_range(center)
+                .into_iter()
+                .filter(|column| visible.contains(column))
+                .collect();
+            update.columns.left.clear();
+        }
 
-        let mut builder = RecordingCommandBuffer::new(
-            command_buffer_allocator.clone(),
-            queue.queue_family_index(),
-            CommandBufferLevel::Primary,
-            CommandBufferBeginInfo {
-                usage: CommandBufferUsage::OneTimeSubmit,
-                ..Default::default()
-            },
-        )
-        .unwrap();
-
-        debug!(
-            "Swapchain image view: {:?}, image: {:?}",
-            renderer.swapchain_image_view().handle(),
-            renderer.swapchain_image_view().image().handle()
+        let display_size = voxel_renderer.display_size();
+        let camera = camera_fn(snapshot, display_size[0] as f32 / display_size[1] as f32);
+        let screen_size = [display_size[0] as f32, display_size[1] as f32];
+        hud.update(
+            screen_size,
+            snapshot.eye,
+            &snapshot.hotbar,
+            &world.block_registry,
+            &snapshot.console,
+            snapshot.saving,
         );
-
-        draw(
-            &mut builder,
-            color_image.clone(),
-            motion_vector_image.clone(),
-            depth_image.clone(),
-            viewport,
-            |builder| {
-                render_faces_pipeline.render_cube_faces(builder, &previous_camera, &camera);
+        if menu.is_open() {
+            hud.menu(
+                screen_size,
+                "Settings",
+                &menu.lines(&settings, app.ray_query),
+                menu.selected(),
+            );
+        }
+        let statistics = voxel_renderer.render(
+            app.windows.get_renderer_mut(window_id).unwrap(),
+            &world,
+            &camera,
+            &FrameInput {
+                elapsed,
+                delta: update.delta,
+                columns: &update.columns,
+                weather: &snapshot.weather,
+                renderables: &snapshot.renderables,
+                particles: &snapshot.particles,
+                viewmodel: (!snapshot.third_person()).then_some(&snapshot.viewmodel),
+                hud: &hud,
             },
         );
-        previous_camera = camera.clone();
-
-        let mut fsr_builder = RecordingCommandBuffer::new(
-            command_buffer_allocator.clone(),
-            queue.queue_family_index(),
-            CommandBufferLevel::Primary,
-            CommandBufferBeginInfo {
-                usage: CommandBufferUsage::OneTimeSubmit,
-                ..Default::default()
-            },
-        )
-        .unwrap();
 
-        let elapsed = frame_time.elapsed();
-        frame_time = Instant::now();
+        if budget_checked.elapsed().as_secs() >= 1 {
+            memory_budget = memory_tracker.check_budget(&physical_device);
+            budget_checked = Instant::now();
+        }
+        let budget = memory_budget
+            .map(|budget| format!("{}/{} MiB", budget.usage >> 20, budget.budget >> 20))
+            .unwrap_or_else(|| "unknown".to_string());
+        let draw = statistics
+            .draw
+            .map(|draw| {
+                format!(
+                    "{} primitives, {:.2}x overdraw",
+                    draw.primitives, draw.overdraw
+                )
+            })
+            .unwrap_or_else(|| "unknown".to_string());
         print!(
-            "Frame time: {:.2?}, FPS: {:.2}       \r",
+            "Frame time: {:.2?}, FPS: {:.2}, tracked memory: {} MiB, device budget: {}, world: {}       \r",
             elapsed,
             1.0 / elapsed.as_secs_f32(),
+            memory_tracker.total_usage() >> 20,
+            budget,
+            draw,
         );
         std::io::stdout().flush().unwrap();
 
-        let fsr_command_buffer = unsafe {
-            debug!("fsr_command_buffer: {:?}", fsr_builder.raw().handle());
-            fsr_context.dispatch(
-                ash_device.clone(),
-                &fsr_builder.raw(),
-                &color_image,
-                &depth_image,
-                &motion_vector_image,
-                &output_image,
-                elapsed.as_millis() as f32,
-                camera,
-            );
-            debug!("Recording command buffer");
-            fsr_builder
-                .copy_image(CopyImageInfo::images(
-                    output_image.image().clone(),
-                    renderer.swapchain_image_view().image().clone(),
-                ))
-                .unwrap();
-            fsr_builder.end().unwrap()
-        };
-
-        let command_buffer = builder.end().unwrap();
-
-        let after = before
-            .then_execute(queue.clone(), command_buffer)
-            .unwrap()
-            .then_execute(queue.clone(), fsr_command_buffer)
-            .unwrap()
-            .then_signal_semaphore_and_flush()
-            .unwrap()
-            .boxed();
-        renderer.present(after, true);
+        if let Some(frame_log) = &mut frame_log {
+            let record = FrameRecord {
+                frame_time: elapsed,
+                chunks: statistics.chunks,
+                primitives: statistics.draw.map(|draw| draw.primitives),
+                upload_bytes: statistics.upload_bytes,
+                passes: statistics.passes,
+            };
+            if let Err(err) = frame_log.write(&record) {
+                warn!("Failed to write the frame log: {}", err);
+            }
+        }
+        #[cfg(feature = "tracy")]
+        tracing_tracy::client::frame_mark();
+        false
     };
 
     event_loop
         .run(move |event, elwt| {
-            let renderer = app.windows.get_renderer_mut(window_id).unwrap();
             match event {
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::CloseRequested => elwt.exit(),
-                    WindowEvent::Resized(..) => {
-                        renderer.resize();
+                    // A new scale factor keeps the logical size of the window,
+                    // so its physical size changes along with it. The renderer
+                    // is recreated for the new size in the next redraw.
+                    WindowEvent::Resized(..) | WindowEvent::ScaleFactorChanged { .. } => {
+                        app.windows.get_renderer_mut(window_id).unwrap().resize();
                     }
-                    WindowEvent::ScaleFactorChanged { .. } => {
-                        renderer.resize();
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button,
+                        ..
+                    } => window_inputs.borrow_mut().push(Input::MousePressed(button)),
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        let y = match delta {
+                            MouseScrollDelta::LineDelta(_, y) => y,
+                            MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => y as f32,
+                        };
+                        window_inputs.borrow_mut().push(Input::Scroll(y));
                     }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(code),
+                                state,
+                                text,
+                                ..
+                            },
+                        ..
+                    } => window_inputs.borrow_mut().push(Input::Key {
+                        code,
+                        pressed: state == ElementState::Pressed,
+                        text: text.map(|text| text.to_string()),
+                    }),
                     WindowEvent::RedrawRequested => {
-                        redraw(renderer);
+                        if redraw(app) {
+                            elwt.exit();
+                        }
                         if app
                             .validation_error_encountered
                             .load(std::sync::atomic::Ordering::Relaxed)
@@ -368,10 +390,134 @@ fn run(app: &mut App) {
         .unwrap();
 }
 
+/// How the window presents with `vsync`. Without it, the first mode of those
+/// tearing the least that the surface supports.
+fn present_mode(app: &App, window_id: WindowId, vsync: bool) -> PresentMode {
+    if vsync {
+        return PresentMode::Fifo;
+    }
+    let surface = app.windows.get_renderer(window_id).unwrap().surface();
+    let supported = app
+        .context
+        .device()
+        .physical_device()
+        .surface_present_modes(&surface, SurfaceInfo::default())
+        .map(|modes| modes.into_iter().collect::<Vec<_>>())
+        .unwrap_or_default();
+    [PresentMode::Mailbox, PresentMode::Immediate]
+        .into_iter()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// Sets up the game's simulation as the command line asks, `None` if it
+/// only asks for a map or a glTF export.
+fn create_simulation(
+    args: &[String],
+    depth_mode: DepthMode,
+    render_distance: i32,
+) -> Option<Simulation> {
+    let (mut world, generator, plugins) = create_world();
+    let mut storage = RegionStorage::new(REGION_DIRECTORY, &world.block_registry).unwrap();
+    let mut client = None;
+    let mut recorder = None;
+    let mut replay = None;
+    match args {
+        // Converts a Minecraft world into the region files, replacing the
+        // columns it contains
+        [_, flag, directory] if flag == "--import-anvil" => {
+            AnvilImporter::new(&world.block_registry)
+                .import_directory(directory, &world.block_registry, world.height, &mut storage)
+                .unwrap();
+        }
+        // Replaces the terrain the heightmap covers with terrain built from it
+        [_, flag, path] if flag == "--import-heightmap" => {
+            let heightmap =
+                HeightmapGenerator::open(path, HeightmapSettings::default(), &world.block_registry)
+                    .unwrap();
+            for column in heightmap.columns() {
+                let sections = heightmap
+                    .generate_column(column.x, column.z, world.height)
+                    .into_chunks()
+                    .collect::<Vec<_>>();
+                storage.save_column(column, &sections).unwrap();
+            }
+            info!("Imported the heightmap {:?}", path);
+        }
+        // Loads the columns within render distance of the spawn and writes
+        // their top-down map
+        [_, flag, path] if flag == "--export-map" => {
+            ChunkLoader::new(render_distance, MAX_CACHED_COLUMNS, usize::MAX)
+                .with_storage(storage)
+                .update(&mut world, &generator, [0, SEA_LEVEL, 0]);
+            map::export_map(&world, path).unwrap();
+            info!("Exported the map to {:?}", path);
+            return None;
+        }
+        // Loads the columns within render distance of the spawn and writes
+        // their meshes as a glTF binary
+        [_, flag, path] if flag == "--export-gltf" => {
+            ChunkLoader::new(render_distance, MAX_CACHED_COLUMNS, usize::MAX)
+                .with_storage(storage)
+                .update(&mut world, &generator, [0, SEA_LEVEL, 0]);
+            gltf::export_glb(&world, path).unwrap();
+            info!("Exported the world to {:?}", path);
+            return None;
+        }
+        // Streams the world from a server instead of loading it locally
+        [_, flag, address] if flag == "--connect" => {
+            client = Some(Client::connect(address.as_str(), "player").unwrap());
+        }
+        // Records the input to play it back with `--replay`, in the world
+        // generated from the seed rather than the saved one
+        [_, flag, path] if flag == "--record" => {
+            recorder = Some(Recorder::create(path, generator.seed.0).unwrap());
+        }
+        // Plays back the input recorded with `--record` and exits after it
+        [_, flag, path] if flag == "--replay" => {
+            replay = Some(Player::open(path, generator.seed.0).unwrap());
+        }
+        _ => {}
+    }
+    let mut chunk_loader = ChunkLoader::new(render_distance, MAX_CACHED_COLUMNS, 2);
+    // Recordings start from the generated world, so they play back the same
+    if recorder.is_none() && replay.is_none() {
+        chunk_loader = chunk_loader.with_storage(storage);
+    }
+
+    let mut simulation = Simulation::new(world, generator, plugins, chunk_loader, depth_mode);
+    if let Some(client) = client {
+        simulation = simulation.with_client(client);
+    }
+    if let Some(recorder) = recorder {
+        simulation = simulation.with_recorder(recorder);
+    }
+    if let Some(replay) = replay {
+        simulation = simulation.with_replay(replay);
+    }
+    Some(simulation)
+}
+
 fn main() {
     env::set_var("RUST_LOG", "info");
     env_logger::init();
+    // Log records stay with env_logger, only spans go to Tracy
+    #[cfg(feature = "tracy")]
+    {
+        use tracing_subscriber::layer::SubscriberExt;
+        tracing::subscriber::set_global_default(
+            tracing_subscriber::registry().with(tracing_tracy::TracyLayer::default()),
+        )
+        .unwrap();
+    }
     info!("Starting block-world");
-    let mut app = App::new();
-    run(&mut app);
+    let args = env::args().collect::<Vec<_>>();
+    match args.as_slice() {
+        [_, flag] if flag == "--server" => return serve(DEFAULT_ADDRESS),
+        [_, flag, address] if flag == "--server" => return serve(address),
+        _ => {}
+    }
+    let settings = GraphicsSettings::load(SETTINGS_PATH).unwrap();
+    let mut app = App::new(settings.ray_traced());
+    run(&mut app, settings);
 }
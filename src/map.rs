@@ -0,0 +1,143 @@
+use std::{collections::HashMap, io, path::Path};
+
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    texture::MISSING_TEXTURE,
+    types::{Chunk, ColumnPosition, Direction, World, CHUNK_SIZE},
+};
+
+/// A top-down image of the loaded columns of a world, one pixel per block.
+pub struct WorldMap {
+    /// Block x and z of the top-left pixel. x grows to the right and z
+    /// downwards, so north is up.
+    pub origin: [i32; 2],
+    pub image: RgbaImage,
+}
+
+/// Draws the highest non-air block of every loaded block column in the
+/// average color of its top texture, tinted like in the world. Columns that
+/// aren't loaded and columns of only air are transparent. Returns `None` if
+/// nothing is loaded.
+pub fn render_map(world: &World) -> Option<WorldMap> {
+    let block_registry = &world.block_registry;
+    let fallback = block_registry
+        .texture_registry
+        .get_index_of(MISSING_TEXTURE);
+    let colors = block_registry
+        .block_types
+        .values()
+        .map(|block_type| {
+            block_type
+                .texture(Direction::Up)
+                .or(fallback)
+                .map_or([255; 3], |texture| {
+                    block_registry.texture_registry[texture].average_color()
+                })
+        })
+        .collect::<Vec<_>>();
+
+    // The sections of every column, highest first
+    let mut columns: HashMap<ColumnPosition, Vec<(i32, &Chunk)>> = HashMap::new();
+    for (position, chunk) in &world.chunks {
+        columns
+            .entry(ColumnPosition {
+                x: position.x,
+                z: position.z,
+            })
+            .or_default()
+            .push((position.y, chunk));
+    }
+    let min = [
+        columns.keys().map(|column| column.x).min()?,
+        columns.keys().map(|column| column.z).min()?,
+    ];
+    let max = [
+        columns.keys().map(|column| column.x).max()?,
+        columns.keys().map(|column| column.z).max()?,
+    ];
+
+    let size = CHUNK_SIZE as u32;
+    let mut image = RgbaImage::new(
+        (max[0] - min[0] + 1) as u32 * size,
+        (max[1] - min[1] + 1) as u32 * size,
+    );
+    for (column, sections) in &mut columns {
+        sections.sort_by_key(|&(y, _)| -y);
+        let left = (column.x - min[0]) as u32 * size;
+        let top = (column.z - min[1]) as u32 * size;
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let highest = sections.iter().find_map(|(_, chunk)| {
+                    (0..CHUNK_SIZE)
+                        .rev()
+                        .map(|y| chunk.blocks[y][x][z])
+                        .find(|&block_type_id| block_type_id != 0)
+                        .map(|block_type_id| (block_type_id, chunk))
+                });
+                let Some((block_type_id, chunk)) = highest else {
+                    continue;
+                };
+
+                let block_type = &block_registry.block_types[block_type_id];
+                let mut color = colors[block_type_id];
+                if let Some(tint) = chunk.biome_colors[x][z].tint_color(block_type.tint) {
+                    for (channel, tint) in color.iter_mut().zip(tint) {
+                        *channel = (*channel as u32 * tint as u32 / 255) as u8;
+                    }
+                }
+                let [r, g, b] = color;
+                image.put_pixel(left + x as u32, top + z as u32, Rgba([r, g, b, 255]));
+            }
+        }
+    }
+
+    Some(WorldMap {
+        origin: [min[0] * CHUNK_SIZE as i32, min[1] * CHUNK_SIZE as i32],
+        image,
+    })
+}
+
+/// Renders the map of `world` to a PNG file at `path`, see `render_map`.
+pub fn export_map(world: &World, path: impl AsRef<Path>) -> io::Result<()> {
+    let map = render_map(world)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no chunks are loaded"))?;
+    map.image
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::BlockRegistry;
+
+    use super::*;
+
+    #[test]
+    fn test_highest_block() {
+        let mut world = World::new(BlockRegistry::default());
+        assert!(render_map(&world).is_none());
+
+        let stone = world
+            .block_registry
+            .block_types
+            .get_index_of("stone")
+            .unwrap();
+        let grass = world
+            .block_registry
+            .block_types
+            .get_index_of("grass")
+            .unwrap();
+        world.fill_cuboid([-16, 0, 0], [16, 1, 16], stone);
+        // Grass in a higher section covers the stone
+        world[[-3, 40, 5]] = grass;
+
+        let map = render_map(&world).unwrap();
+        assert_eq!(map.origin, [-16, 0]);
+        assert_eq!(map.image.dimensions(), (32, 16));
+        // The placeholder texture averages to dark magenta; grass is tinted
+        assert_eq!(map.image.get_pixel(0, 0), &Rgba([127, 0, 127, 255]));
+        assert_eq!(map.image.get_pixel(13, 5), &Rgba([72, 0, 44, 255]));
+        assert_eq!(map.image.get_pixel(13, 6), &Rgba([127, 0, 127, 255]));
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use log::warn;
+use vulkano::{buffer::Subbuffer, device::physical::PhysicalDevice, image::Image, VulkanObject};
+
+/// Warn once device-local usage goes above this fraction of the budget.
+const BUDGET_WARNING_THRESHOLD: f64 = 0.9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    ChunkBuffers,
+    BlockModels,
+    EntityBuffers,
+    ParticleBuffers,
+    HudBuffers,
+    Textures,
+    RenderTargets,
+    FsrScratch,
+    AccelerationStructures,
+    Staging,
+}
+
+impl MemoryCategory {
+    pub const ALL: [MemoryCategory; 10] = {
+        use MemoryCategory::*;
+        [
+            ChunkBuffers,
+            BlockModels,
+            EntityBuffers,
+            ParticleBuffers,
+            HudBuffers,
+            Textures,
+            RenderTargets,
+            FsrScratch,
+            AccelerationStructures,
+            Staging,
+        ]
+    };
+
+    pub fn name(&self) -> &'static str {
+        use MemoryCategory::*;
+        match self {
+            ChunkBuffers => "chunk buffers",
+            BlockModels => "block models",
+            EntityBuffers => "entity buffers",
+            ParticleBuffers => "particle buffers",
+            HudBuffers => "HUD buffers",
+            Textures => "textures",
+            RenderTargets => "render targets",
+            FsrScratch => "FSR scratch",
+            AccelerationStructures => "acceleration structures",
+            Staging => "staging",
+        }
+    }
+}
+
+/// Bytes allocated per `MemoryCategory`. Allocations are recorded with
+/// `track` and released when the returned `TrackedAllocation` is dropped, so
+/// owners keep it next to the resource it describes.
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    usage: [AtomicU64; MemoryCategory::ALL.len()],
+    over_budget_warned: AtomicBool,
+}
+
+#[must_use = "the allocation is released when this is dropped"]
+#[derive(Debug)]
+pub struct TrackedAllocation {
+    tracker: Arc<MemoryTracker>,
+    category: MemoryCategory,
+    size: u64,
+}
+
+impl Drop for TrackedAllocation {
+    fn drop(&mut self) {
+        self.tracker.usage[self.category as usize].fetch_sub(self.size, Ordering::Relaxed);
+    }
+}
+
+impl MemoryTracker {
+    pub fn track(self: &Arc<Self>, category: MemoryCategory, size: u64) -> TrackedAllocation {
+        self.usage[category as usize].fetch_add(size, Ordering::Relaxed);
+        TrackedAllocation {
+            tracker: self.clone(),
+            category,
+            size,
+        }
+    }
+
+    pub fn track_buffer<T: ?Sized>(
+        self: &Arc<Self>,
+        category: MemoryCategory,
+        buffer: &Subbuffer<T>,
+    ) -> TrackedAllocation {
+        self.track(category, buffer.size())
+    }
+
+    pub fn track_image(
+        self: &Arc<Self>,
+        category: MemoryCategory,
+        image: &Image,
+    ) -> TrackedAllocation {
+        let size = image
+            .memory_requirements()
+            .iter()
+            .map(|requirements| requirements.layout.size())
+            .sum();
+        self.track(category, size)
+    }
+
+    pub fn usage(&self, category: MemoryCategory) -> u64 {
+        self.usage[category as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn total_usage(&self) -> u64 {
+        MemoryCategory::ALL
+            .iter()
+            .map(|&category| self.usage(category))
+            .sum()
+    }
+
+    /// A snapshot of the tracked usage.
+    pub fn report(&self) -> MemoryReport {
+        MemoryReport {
+            categories: MemoryCategory::ALL.map(|category| (category, self.usage(category))),
+        }
+    }
+
+    /// Queries the device budget and warns (once per crossing) when
+    /// device-local usage gets close to it.
+    pub fn check_budget(&self, physical_device: &PhysicalDevice) -> Option<MemoryBudget> {
+        let budget = MemoryBudget::query(physical_device)?;
+        let over = budget.usage as f64 > budget.budget as f64 * BUDGET_WARNING_THRESHOLD;
+        if over && !self.over_budget_warned.swap(true, Ordering::Relaxed) {
+            warn!(
+                "Device-local memory usage {} MiB is close to the budget of {} MiB ({})",
+                budget.usage >> 20,
+                budget.budget >> 20,
+                self.report()
+            );
+        } else if !over {
+            self.over_budget_warned.store(false, Ordering::Relaxed);
+        }
+        Some(budget)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryReport {
+    pub categories: [(MemoryCategory, u64); MemoryCategory::ALL.len()],
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (category, size)) in self.categories.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{}: {:.1} MiB",
+                category.name(),
+                *size as f64 / (1 << 20) as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Device-local memory budget and usage of the whole process, summed over
+/// all device-local heaps, as reported by `VK_EXT_memory_budget`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub budget: u64,
+    pub usage: u64,
+}
+
+impl MemoryBudget {
+    /// `None` if the device doesn't support `VK_EXT_memory_budget`.
+    pub fn query(physical_device: &PhysicalDevice) -> Option<Self> {
+        if !physical_device.supported_extensions().ext_memory_budget {
+            return None;
+        }
+
+        let mut budget_properties = ash::vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties =
+            ash::vk::PhysicalDeviceMemoryProperties2::default().push_next(&mut budget_properties);
+        let fns = physical_device.instance().fns();
+        unsafe {
+            (fns.v1_1.get_physical_device_memory_properties2)(
+                physical_device.handle(),
+                &mut properties,
+            );
+        }
+
+        let memory_properties = properties.memory_properties;
+        let mut budget = Self {
+            budget: 0,
+            usage: 0,
+        };
+        for heap in 0..memory_properties.memory_heap_count as usize {
+            if memory_properties.memory_heaps[heap]
+                .flags
+                .contains(ash::vk::MemoryHeapFlags::DEVICE_LOCAL)
+            {
+                budget.budget += budget_properties.heap_budget[heap];
+                budget.usage += budget_properties.heap_usage[heap];
+            }
+        }
+        Some(budget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracked_allocation_released_on_drop() {
+        let tracker = Arc::new(MemoryTracker::default());
+        let textures = tracker.track(MemoryCategory::Textures, 1024);
+        let chunks = tracker.track(MemoryCategory::ChunkBuffers, 4096);
+        assert_eq!(tracker.usage(MemoryCategory::Textures), 1024);
+        assert_eq!(tracker.total_usage(), 5120);
+
+        drop(chunks);
+        assert_eq!(tracker.usage(MemoryCategory::ChunkBuffers), 0);
+        assert_eq!(tracker.total_usage(), 1024);
+        drop(textures);
+        assert_eq!(tracker.total_usage(), 0);
+    }
+}
@@ -0,0 +1,171 @@
+//! The settings menu, opened over the game with `MENU_KEY` and worked with
+//! the arrow keys. It changes the graphics settings the render thread
+//! applies while the game runs and saves.
+
+use winit::keyboard::KeyCode;
+
+use crate::settings::{AntiAliasing, FsrQuality, GraphicsSettings, Shadows, MAX_RENDER_DISTANCE};
+
+/// Opens and closes the menu.
+pub const MENU_KEY: KeyCode = KeyCode::F10;
+const MIN_RENDER_DISTANCE: u32 = 2;
+
+/// A line of the menu, for one setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Row {
+    RenderDistance,
+    FsrQuality,
+    Shadows,
+    Vsync,
+    Fullscreen,
+}
+
+const ROWS: [Row; 5] = [
+    Row::RenderDistance,
+    Row::FsrQuality,
+    Row::Shadows,
+    Row::Vsync,
+    Row::Fullscreen,
+];
+
+#[derive(Debug, Default)]
+pub struct SettingsMenu {
+    open: bool,
+    /// Index of the row in `ROWS` the arrow keys change.
+    selected: usize,
+}
+
+impl SettingsMenu {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Reacts to a key pressed while the menu is open, or to `MENU_KEY`
+    /// while it is closed. Up and down select a row, left and right change
+    /// its setting in `settings`.
+    pub fn press(&mut self, code: KeyCode, settings: &mut GraphicsSettings) {
+        if !self.open {
+            self.open = code == MENU_KEY;
+            return;
+        }
+        match code {
+            MENU_KEY | KeyCode::Escape => self.open = false,
+            KeyCode::ArrowUp => self.selected = (self.selected + ROWS.len() - 1) % ROWS.len(),
+            KeyCode::ArrowDown => self.selected = (self.selected + 1) % ROWS.len(),
+            KeyCode::ArrowLeft => change(ROWS[self.selected], settings, -1),
+            KeyCode::ArrowRight | KeyCode::Enter => change(ROWS[self.selected], settings, 1),
+            _ => {}
+        }
+    }
+
+    /// A line of text per row, showing its setting. Ray tracing needs a
+    /// device created with ray queries, as it is at startup when a setting
+    /// traces rays.
+    pub fn lines(&self, settings: &GraphicsSettings, ray_query: bool) -> Vec<String> {
+        ROWS.iter()
+            .map(|row| match row {
+                Row::RenderDistance => {
+                    format!("Render distance: {} columns", settings.render_distance)
+                }
+                Row::FsrQuality => {
+                    let unused = if settings.anti_aliasing == AntiAliasing::Fsr {
+                        ""
+                    } else {
+                        " (FSR is off)"
+                    };
+                    format!("FSR quality: {:?}{}", settings.fsr.quality, unused)
+                }
+                Row::Shadows => match settings.shadows {
+                    Shadows::Off => "Shadows: off".to_string(),
+                    Shadows::RayTraced if ray_query => "Shadows: ray traced".to_string(),
+                    Shadows::RayTraced => "Shadows: ray traced, after a restart".to_string(),
+                },
+                Row::Vsync => format!("VSync: {}", on_off(settings.vsync)),
+                Row::Fullscreen => format!("Fullscreen: {}", on_off(settings.fullscreen)),
+            })
+            .collect()
+    }
+}
+
+/// Moves the setting of `row` a `step` forward or backward.
+fn change(row: Row, settings: &mut GraphicsSettings, step: i32) {
+    match row {
+        Row::RenderDistance => {
+            settings.render_distance = settings
+                .render_distance
+                .saturating_add_signed(step)
+                .clamp(MIN_RENDER_DISTANCE, MAX_RENDER_DISTANCE);
+        }
+        Row::FsrQuality => {
+            let qualities = FsrQuality::ALL;
+            let index = qualities
+                .iter()
+                .position(|&quality| quality == settings.fsr.quality)
+                .unwrap() as i32;
+            settings.fsr.quality =
+                qualities[(index + step).rem_euclid(qualities.len() as i32) as usize];
+        }
+        Row::Shadows => {
+            settings.shadows = match settings.shadows {
+                Shadows::Off => Shadows::RayTraced,
+                Shadows::RayTraced => Shadows::Off,
+            }
+        }
+        Row::Vsync => settings.vsync = !settings.vsync,
+        Row::Fullscreen => settings.fullscreen = !settings.fullscreen,
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_menu() {
+        let mut menu = SettingsMenu::default();
+        let mut settings = GraphicsSettings::default();
+        // Only the menu key opens it
+        menu.press(KeyCode::ArrowRight, &mut settings);
+        assert!(!menu.is_open());
+        menu.press(MENU_KEY, &mut settings);
+        assert!(menu.is_open());
+
+        menu.press(KeyCode::ArrowRight, &mut settings);
+        assert_eq!(settings.render_distance, 5);
+        for _ in 0..20 {
+            menu.press(KeyCode::ArrowRight, &mut settings);
+        }
+        assert_eq!(settings.render_distance, MAX_RENDER_DISTANCE);
+
+        menu.press(KeyCode::ArrowDown, &mut settings);
+        menu.press(KeyCode::ArrowLeft, &mut settings);
+        assert_eq!(settings.fsr.quality, FsrQuality::UltraPerformance);
+
+        // Up from the first row selects the last
+        menu.press(KeyCode::ArrowUp, &mut settings);
+        menu.press(KeyCode::ArrowUp, &mut settings);
+        assert_eq!(menu.selected(), ROWS.len() - 1);
+        menu.press(KeyCode::Enter, &mut settings);
+        assert!(settings.fullscreen);
+
+        let lines = menu.lines(&settings, false);
+        assert_eq!(lines.len(), ROWS.len());
+        assert_eq!(lines[0], "Render distance: 16 columns");
+        assert_eq!(lines[4], "Fullscreen: on");
+
+        menu.press(KeyCode::Escape, &mut settings);
+        assert!(!menu.is_open());
+    }
+}
@@ -1,18 +1,29 @@
 use std::cmp::Ordering;
 
-use crate::types::{Direction, TextureId};
+use crate::types::{BlockRotation, BlockTextures, Direction, Shape, TextureId, MAX_FLUID_LEVEL};
 
 #[derive(Debug, Clone, PartialEq)]
-struct Face {
+pub struct Face {
     pub uv: [f32; 4],
     pub texture: TextureId,
     pub cullface: Option<Direction>,
 }
 
+/// Faces of a voxel, indexed in `Direction::ALL` order.
 #[derive(Debug, Clone, PartialEq)]
-struct Faces([Face; 6]);
+pub struct Faces(pub [Face; 6]);
 
 impl Faces {
+    /// Faces textured from `textures`, using `fallback` for directions without
+    /// a texture.
+    pub fn from_block_textures(textures: &BlockTextures, fallback: TextureId) -> Self {
+        Self(Direction::ALL.map(|direction| Face {
+            uv: [0.0, 0.0, 1.0, 1.0],
+            texture: textures.0.get(&direction).copied().unwrap_or(fallback),
+            cullface: Some(direction),
+        }))
+    }
+
     pub fn new_with_texture_default_cullface(texture: TextureId) -> Self {
         Self(Direction::ALL.map(|direction| Face {
             uv: [0.0, 0.0, 1.0, 1.0],
@@ -28,28 +39,149 @@ impl Faces {
             cullface: None,
         }))
     }
+
+    pub fn get(&self, direction: Direction) -> &Face {
+        &self.0[direction as usize]
+    }
 }
 
+/// An axis-aligned box in model space, where a full block spans 0 to 16.
 #[derive(Debug, Clone, PartialEq)]
-struct Voxel {
-    from: [f32; 3],
-    to: [f32; 3],
-    faces: Faces,
+pub struct Voxel {
+    pub from: [f32; 3],
+    pub to: [f32; 3],
+    pub faces: Faces,
 }
 
 impl Voxel {
+    /// A part of a block model with the textures of `faces`. Faces on the
+    /// sides of the block cull against their neighbors, the others never do.
+    pub fn part(from: [f32; 3], to: [f32; 3], faces: &Faces) -> Self {
+        Self {
+            from,
+            to,
+            faces: Faces(Direction::ALL.map(|direction| Face {
+                uv: locked_uv(from, to, direction),
+                texture: faces.get(direction).texture,
+                cullface: on_side(from, to, direction).then_some(direction),
+            })),
+        }
+    }
+
     fn volume(&self) -> f32 {
         let [x1, y1, z1] = self.from;
         let [x2, y2, z2] = self.to;
         (x2 - x1) * (y2 - y1) * (z2 - z1)
     }
+
+    fn rotated(&self, rotation: BlockRotation) -> Self {
+        let turn = |point: [f32; 3]| {
+            rotation
+                .rotate_vector(point.map(|v| v - 8.0))
+                .map(|v| v + 8.0)
+        };
+        let (a, b) = (turn(self.from), turn(self.to));
+        let from = [0, 1, 2].map(|i| a[i].min(b[i]));
+        let to = [0, 1, 2].map(|i| a[i].max(b[i]));
+        let faces = Faces(Direction::ALL.map(|direction| {
+            let face = self.faces.get(rotation.unrotate(direction));
+            Face {
+                uv: locked_uv(from, to, direction),
+                texture: face.texture,
+                cullface: face.cullface.map(|cullface| rotation.rotate(cullface)),
+            }
+        }));
+        Self { from, to, faces }
+    }
+}
+
+/// Whether the face of the box from `from` to `to` pointing in `direction`
+/// lies on that side of the block.
+fn on_side(from: [f32; 3], to: [f32; 3], direction: Direction) -> bool {
+    let axis = direction.axis();
+    if direction.is_positive() {
+        to[axis] >= 16.0
+    } else {
+        from[axis] <= 0.0
+    }
+}
+
+/// Which of the 16×16 units of a block side a model covers, bit `u` of row
+/// `v` for the plane axes of the side. Coordinates are rounded to units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SideCoverage(pub [u16; 16]);
+
+impl SideCoverage {
+    pub const EMPTY: Self = Self([0; 16]);
+    pub const FULL: Self = Self([u16::MAX; 16]);
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::EMPTY
+    }
+
+    /// Whether every unit covered here is also covered by `other`.
+    pub fn is_covered_by(&self, other: &Self) -> bool {
+        self.0
+            .iter()
+            .zip(other.0)
+            .all(|(&row, other)| row & !other == 0)
+    }
+}
+
+/// How a model meets the six sides of its block, in `Direction::ALL` order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Occlusion {
+    /// The parts of each side covered by faces lying on it, which hide the
+    /// faces of neighbors they cover.
+    pub sides: [SideCoverage; 6],
+    /// Whether the model has faces pointing in each direction that lie
+    /// inside the block, which neighbors never hide.
+    pub inner: [bool; 6],
+}
+
+impl Occlusion {
+    /// A full block.
+    pub const FULL: Self = Self {
+        sides: [SideCoverage::FULL; 6],
+        inner: [false; 6],
+    };
+
+    /// Whether the faces pointing in `direction` are all hidden by a
+    /// neighbor with `neighbor` occlusion there.
+    pub fn is_hidden_by(&self, direction: Direction, neighbor: &Occlusion) -> bool {
+        !self.inner[direction as usize]
+            && self.sides[direction as usize]
+                .is_covered_by(&neighbor.sides[direction.opposite() as usize])
+    }
+}
+
+/// Uvs of the face of the box from `from` to `to` pointing in `direction`
+/// that keep the texture aligned with the world, as if the face was cut out
+/// of a full block face. Texture v runs down along y.
+fn locked_uv(from: [f32; 3], to: [f32; 3], direction: Direction) -> [f32; 4] {
+    let [u, v] = direction.plane_axes();
+    let (v_min, v_max) = if v == 1 {
+        (16.0 - to[v], 16.0 - from[v])
+    } else {
+        (from[v], to[v])
+    };
+    [from[u], v_min, to[u], v_max].map(|value| value / 16.0)
 }
 
-struct Model {
+#[derive(Debug, Clone)]
+pub struct Model {
     pub voxels: Vec<Voxel>,
 }
 
 impl Model {
+    pub fn cube(faces: Faces) -> Self {
+        Self::from_voxels([Voxel {
+            from: [0.0, 0.0, 0.0],
+            to: [16.0, 16.0, 16.0],
+            faces,
+        }])
+    }
+
     pub fn from_voxels(voxels: impl IntoIterator<Item = Voxel>) -> Self {
         let mut voxels = voxels.into_iter().collect::<Vec<_>>();
         voxels.sort_by(|a, b| {
@@ -59,11 +191,97 @@ impl Model {
         });
         Self { voxels }
     }
+
+    /// The model of `shape` with the textures of `faces`.
+    pub fn from_shape(shape: Shape, faces: Faces) -> Self {
+        match shape {
+            Shape::Cube => Self::cube(faces),
+            Shape::Slab => {
+                Self::from_voxels([Voxel::part([0.0, 0.0, 0.0], [16.0, 8.0, 16.0], &faces)])
+            }
+            Shape::Stairs => Self::from_voxels([
+                Voxel::part([0.0, 0.0, 0.0], [16.0, 8.0, 16.0], &faces),
+                Voxel::part([0.0, 8.0, 0.0], [16.0, 16.0, 8.0], &faces),
+            ]),
+            Shape::Fence => {
+                Self::from_voxels([Voxel::part([6.0, 0.0, 6.0], [10.0, 16.0, 10.0], &faces)])
+            }
+            Shape::Fluid(level) => {
+                let levels = (MAX_FLUID_LEVEL + 1) as f32;
+                let height = 16.0 * (levels - level as f32) / levels;
+                Self::from_voxels([Voxel::part([0.0, 0.0, 0.0], [16.0, height, 16.0], &faces)])
+            }
+        }
+    }
+
+    pub fn occlusion(&self) -> Occlusion {
+        let mut occlusion = Occlusion {
+            sides: [SideCoverage::EMPTY; 6],
+            inner: [false; 6],
+        };
+        for voxel in &self.voxels {
+            for direction in Direction::ALL {
+                if !on_side(voxel.from, voxel.to, direction) {
+                    if !self.hides_face(voxel, direction) {
+                        occlusion.inner[direction as usize] = true;
+                    }
+                    continue;
+                }
+                let [u, v] = direction.plane_axes();
+                let units = |axis: usize| {
+                    let from = voxel.from[axis].round().clamp(0.0, 16.0) as usize;
+                    let to = voxel.to[axis].round().clamp(0.0, 16.0) as usize;
+                    from..to
+                };
+                let row = units(u).fold(0u16, |row, unit| row | 1 << unit);
+                for v in units(v) {
+                    occlusion.sides[direction as usize].0[v] |= row;
+                }
+            }
+        }
+        occlusion
+    }
+
+    /// Whether another voxel lies against all of the face of `voxel` pointing
+    /// in `direction`.
+    fn hides_face(&self, voxel: &Voxel, direction: Direction) -> bool {
+        let axis = direction.axis();
+        let positive = direction.is_positive();
+        let plane = if positive {
+            voxel.to[axis]
+        } else {
+            voxel.from[axis]
+        };
+        self.voxels.iter().any(|other| {
+            let other_plane = if positive {
+                other.from[axis]
+            } else {
+                other.to[axis]
+            };
+            other_plane == plane
+                && direction
+                    .plane_axes()
+                    .iter()
+                    .all(|&a| other.from[a] <= voxel.from[a] && other.to[a] >= voxel.to[a])
+        })
+    }
+
+    /// The model turned by `rotation` about the center of the block. Faces
+    /// keep their textures while their cull faces turn along; uvs are locked
+    /// so textures stay upright instead of turning with the model.
+    pub fn rotated(self, rotation: BlockRotation) -> Self {
+        if rotation == BlockRotation::NONE {
+            return self;
+        }
+        Self::from_voxels(self.voxels.iter().map(|voxel| voxel.rotated(rotation)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Faces, Model, Voxel};
+    use crate::types::{BlockRotation, Direction, Shape};
+
+    use super::{Faces, Model, Occlusion, SideCoverage, Voxel};
 
     #[test]
     fn test_new_model() {
@@ -81,4 +299,79 @@ mod tests {
         ];
         let model = Model::from_voxels(voxels);
     }
+
+    #[test]
+    fn test_rotated_model() {
+        let mut faces = Faces::new_with_texture_default_cullface(0);
+        faces.0[Direction::Up as usize].texture = 1;
+        let slab = Model::from_voxels([Voxel {
+            from: [0.0, 0.0, 0.0],
+            to: [16.0, 8.0, 16.0],
+            faces,
+        }]);
+
+        // Standing on its side along the east wall
+        let rotation = BlockRotation::new(1, 1);
+        assert_eq!(rotation.rotate(Direction::Up), Direction::West);
+        assert_eq!(rotation.unrotate(Direction::West), Direction::Up);
+        let model = slab.rotated(rotation);
+        let voxel = &model.voxels[0];
+        assert_eq!(voxel.from, [8.0, 0.0, 0.0]);
+        assert_eq!(voxel.to, [16.0, 16.0, 16.0]);
+        let west = voxel.faces.get(Direction::West);
+        assert_eq!(west.texture, 1);
+        assert_eq!(west.cullface, Some(Direction::West));
+        assert_eq!(voxel.faces.get(Direction::Up).uv, [0.5, 0.0, 1.0, 1.0]);
+        assert_eq!(voxel.faces.get(Direction::North).uv, [0.5, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_shape_occlusion() {
+        let faces = Faces::new_with_texture_default_cullface(0);
+        assert_eq!(
+            Model::from_shape(Shape::Cube, faces.clone()).occlusion(),
+            Occlusion::FULL
+        );
+
+        let slab = Model::from_shape(Shape::Slab, faces.clone()).occlusion();
+        let up = Direction::Up as usize;
+        assert!(slab.inner[up] && slab.sides[up].is_empty());
+        assert_eq!(slab.sides[Direction::Down as usize], SideCoverage::FULL);
+        // Two slabs side by side hide each other's faces, but not a full one's
+        assert!(slab.is_hidden_by(Direction::East, &slab));
+        assert!(!Occlusion::FULL.is_hidden_by(Direction::East, &slab));
+        assert!(slab.is_hidden_by(Direction::East, &Occlusion::FULL));
+
+        // The back of stairs is full, their front only half
+        let stairs = Model::from_shape(Shape::Stairs, faces.clone()).occlusion();
+        assert_eq!(stairs.sides[Direction::North as usize], SideCoverage::FULL);
+        assert!(stairs.inner[Direction::South as usize]);
+        // The top step's bottom lies on the lower half
+        assert!(!stairs.inner[Direction::Down as usize]);
+        assert!(stairs.is_hidden_by(Direction::North, &Occlusion::FULL));
+        let turned = Model::from_shape(Shape::Stairs, faces.clone())
+            .rotated(BlockRotation::new(0, 1))
+            .occlusion();
+        assert_eq!(turned.sides[Direction::East as usize], SideCoverage::FULL);
+
+        let fence = Model::from_shape(Shape::Fence, faces).occlusion();
+        assert!(!fence.is_hidden_by(Direction::North, &Occlusion::FULL));
+        assert_eq!(
+            fence.sides[up]
+                .0
+                .iter()
+                .map(|row| row.count_ones())
+                .sum::<u32>(),
+            16
+        );
+
+        // Sources fill their block, flowing fluid gets lower with its level
+        assert_eq!(
+            Model::from_shape(Shape::Fluid(0), faces.clone()).occlusion(),
+            Occlusion::FULL
+        );
+        let flowing = Model::from_shape(Shape::Fluid(MAX_FLUID_LEVEL), faces);
+        assert_eq!(flowing.voxels[0].to, [16.0, 2.0, 16.0]);
+        assert!(flowing.occlusion().inner[up]);
+    }
 }
@@ -0,0 +1,259 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::ToSocketAddrs,
+};
+
+use log::{info, warn};
+
+use crate::{
+    chunk_loader::LoaderUpdate,
+    types::{BlockTypeId, ChunkPosition, ColumnPosition, World, CHUNK_SIZE},
+};
+
+use super::{
+    connection::Connection,
+    packing::{decompress_column, local_position},
+    protocol::{ClientMessage, PlayerId, ServerMessage, PROTOCOL_VERSION},
+};
+
+/// Columns requested at once. Requests beyond wait for earlier columns to
+/// arrive, so the nearest ones aren't queued behind far ones on the server.
+const MAX_PENDING_REQUESTS: usize = 8;
+
+/// What the client learns from `Welcome`.
+struct Session {
+    player_id: PlayerId,
+    /// Local block id of every server block id.
+    to_local: Vec<BlockTypeId>,
+    /// Server block id of every local block id, if the server knows it.
+    to_server: Vec<Option<BlockTypeId>>,
+}
+
+/// Another player on the same server.
+#[derive(Debug, Clone)]
+pub struct RemotePlayer {
+    pub name: String,
+    pub position: [f32; 3],
+}
+
+/// Mirrors the part of a server's world around the player. Takes the place
+/// of a `ChunkLoader`: columns are requested from the server instead of
+/// being generated, and block edits go through the server, so they show up
+/// in the local world once the server sends them back.
+pub struct Client {
+    connection: Connection,
+    session: Option<Session>,
+    /// Columns asked for that didn't arrive yet.
+    requested: HashSet<ColumnPosition>,
+    /// Columns received and loaded into the world.
+    loaded: HashSet<ColumnPosition>,
+    players: HashMap<PlayerId, RemotePlayer>,
+    /// The position last sent.
+    position: Option<[f32; 3]>,
+}
+
+impl Client {
+    pub fn connect(address: impl ToSocketAddrs, name: &str) -> io::Result<Self> {
+        let mut connection = Connection::connect(address)?;
+        info!("Connected to {}", connection.peer_addr()?);
+        connection.send(&ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+            name: name.to_string(),
+        });
+        Ok(Self {
+            connection,
+            session: None,
+            requested: HashSet::new(),
+            loaded: HashSet::new(),
+            players: HashMap::new(),
+            position: None,
+        })
+    }
+
+    /// Our id on the server, once it welcomed us.
+    pub fn player_id(&self) -> Option<PlayerId> {
+        self.session.as_ref().map(|session| session.player_id)
+    }
+
+    pub fn players(&self) -> &HashMap<PlayerId, RemotePlayer> {
+        &self.players
+    }
+
+    /// Applies what the server sent, sends the player's `position` if it
+    /// moved, and requests the columns of `in_range` that aren't loaded,
+    /// nearest to the player first and at most `MAX_PENDING_REQUESTS` at a
+    /// time. Loaded columns that aren't in range are unloaded. Columns enter
+    /// once they arrive. Fails when the connection is lost or the server
+    /// turned us away.
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        position: [f32; 3],
+        in_range: &[ColumnPosition],
+    ) -> io::Result<LoaderUpdate> {
+        let mut update = LoaderUpdate::default();
+
+        while let Some(message) = self.connection.receive::<ServerMessage>()? {
+            self.handle(message, world, &mut update)?;
+        }
+
+        if self.session.is_some() {
+            if self.position != Some(position) {
+                self.position = Some(position);
+                self.connection
+                    .send(&ClientMessage::PlayerPosition(position));
+            }
+
+            let in_range_set = in_range.iter().copied().collect::<HashSet<_>>();
+            let forgotten = self
+                .loaded
+                .iter()
+                .chain(&self.requested)
+                .filter(|column| !in_range_set.contains(column))
+                .copied()
+                .collect::<Vec<_>>();
+            for column in forgotten {
+                self.connection.send(&ClientMessage::ForgetColumn(column));
+                self.requested.remove(&column);
+                if self.loaded.remove(&column) {
+                    update.left.push(column);
+                    update.evicted.extend(world.unload_column(column));
+                }
+            }
+
+            let mut missing = in_range
+                .iter()
+                .filter(|column| !self.loaded.contains(column) && !self.requested.contains(column))
+                .copied()
+                .collect::<Vec<_>>();
+            let distance = |column: &ColumnPosition| {
+                let center = |v: i32| (v as f32 + 0.5) * CHUNK_SIZE as f32;
+                let dx = center(column.x) - position[0];
+                let dz = center(column.z) - position[2];
+                dx * dx + dz * dz
+            };
+            missing.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+            let free = MAX_PENDING_REQUESTS.saturating_sub(self.requested.len());
+            for column in missing.into_iter().take(free) {
+                self.requested.insert(column);
+                self.connection.send(&ClientMessage::RequestColumn(column));
+            }
+        }
+
+        self.connection.flush()?;
+        Ok(update)
+    }
+
+    /// Asks the server to set a block, given its local id. The local world
+    /// changes once the server confirms.
+    pub fn set_block(&mut self, position: [i32; 3], block_type_id: BlockTypeId) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        match session.to_server.get(block_type_id).copied().flatten() {
+            Some(block_type_id) => self.connection.send(&ClientMessage::SetBlock {
+                position,
+                block_type_id,
+            }),
+            None => warn!("The server doesn't know block {}", block_type_id),
+        }
+    }
+
+    fn handle(
+        &mut self,
+        message: ServerMessage,
+        world: &mut World,
+        update: &mut LoaderUpdate,
+    ) -> io::Result<()> {
+        match message {
+            ServerMessage::Welcome {
+                player_id,
+                metadata,
+            } => {
+                if metadata.height != world.height {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("the server's world height is {:?}", metadata.height),
+                    ));
+                }
+                let server_ids = metadata
+                    .block_names
+                    .iter()
+                    .enumerate()
+                    .map(|(id, name)| (name.as_str(), id))
+                    .collect::<HashMap<_, _>>();
+                let to_server = world
+                    .block_registry
+                    .block_names()
+                    .iter()
+                    .map(|name| server_ids.get(name.as_str()).copied())
+                    .collect();
+                info!("Joined as player {}", player_id);
+                self.session = Some(Session {
+                    player_id,
+                    to_local: world.block_registry.id_mapping(&metadata.block_names),
+                    to_server,
+                });
+            }
+            ServerMessage::Disconnect { reason } => {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, reason));
+            }
+            ServerMessage::Column { column, payload } => {
+                // Columns forgotten while on the way are dropped
+                if !self.requested.remove(&column) {
+                    return Ok(());
+                }
+                let session = self.session.as_ref().unwrap();
+                let sections = decompress_column(&payload)?;
+                world.insert_chunks(sections.into_iter().map(|(y, mut chunk)| {
+                    chunk.remap_blocks(&session.to_local);
+                    let position = ChunkPosition {
+                        x: column.x,
+                        y,
+                        z: column.z,
+                    };
+                    (position, chunk)
+                }));
+                self.loaded.insert(column);
+                update.entered.push(column);
+            }
+            ServerMessage::BlockDiff { section, changes } => {
+                let column = ColumnPosition {
+                    x: section.x,
+                    z: section.z,
+                };
+                if !self.loaded.contains(&column) {
+                    return Ok(());
+                }
+                let session = self.session.as_ref().unwrap();
+                let origin = section.origin();
+                for (index, block_type_id) in changes {
+                    let Some(offset) = local_position(index) else {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "block diff index is out of the section",
+                        ));
+                    };
+                    let position = [0, 1, 2].map(|i| origin[i] + offset[i]);
+                    let block_type_id = session.to_local.get(block_type_id).copied();
+                    world.set_block(position, block_type_id.unwrap_or(0));
+                }
+            }
+            ServerMessage::PlayerPosition {
+                player_id,
+                name,
+                position,
+            } => {
+                self.players
+                    .insert(player_id, RemotePlayer { name, position });
+            }
+            ServerMessage::PlayerLeft(player_id) => {
+                if let Some(player) = self.players.remove(&player_id) {
+                    info!("{} left", player.name);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Largest message accepted, so a corrupt length can't exhaust memory.
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// A non-blocking TCP stream of messages, each a little-endian `u32` length
+/// followed by that many bytes of bincode. `send` only queues messages;
+/// `flush` writes them out as far as the socket takes them.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    /// Whether the peer closed its side. Messages that arrived before are
+    /// still received.
+    closed: bool,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            closed: false,
+        })
+    }
+
+    pub fn connect(address: impl std::net::ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(address)?)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn send<T: Serialize>(&mut self, message: &T) {
+        let payload = bincode::serde::encode_to_vec(message, bincode::config::standard()).unwrap();
+        self.outgoing
+            .extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        self.outgoing.extend_from_slice(&payload);
+    }
+
+    /// Bytes queued by `send` that aren't written yet.
+    pub fn queued_bytes(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    /// Writes queued messages until the socket would block.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// The next message, if one has arrived completely. Fails once the peer
+    /// closed the connection and every message before was received.
+    pub fn receive<T: DeserializeOwned>(&mut self) -> io::Result<Option<T>> {
+        let mut buffer = [0; 16 * 1024];
+        while !self.closed {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let incomplete = || {
+            if self.closed {
+                Err(io::ErrorKind::UnexpectedEof.into())
+            } else {
+                Ok(None)
+            }
+        };
+        let Some(length) = self.incoming.first_chunk::<4>() else {
+            return incomplete();
+        };
+        let length = u32::from_le_bytes(*length) as usize;
+        if length > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("message of {} bytes is too large", length),
+            ));
+        }
+        if self.incoming.len() < 4 + length {
+            return incomplete();
+        }
+        let (message, _) = bincode::serde::decode_from_slice(
+            &self.incoming[4..4 + length],
+            bincode::config::standard(),
+        )
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        self.incoming.drain(..4 + length);
+        Ok(Some(message))
+    }
+}
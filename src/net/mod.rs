@@ -0,0 +1,125 @@
+//! Sharing one world between several processes: a `Server` owns the world
+//! and streams it to the `Client`s of the render processes over TCP.
+
+pub use self::client::{Client, RemotePlayer};
+pub use self::protocol::{ClientMessage, PlayerId, ServerMessage, PROTOCOL_VERSION};
+pub use self::server::Server;
+
+mod client;
+mod connection;
+mod packing;
+mod protocol;
+mod server;
+
+/// Address the server listens on unless told otherwise.
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:25575";
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use crate::{
+        biome::BiomeRegistry,
+        chunk_loader::ChunkLoader,
+        types::{BlockRegistry, ColumnPosition, World},
+        worldgen::{WorldGenerator, WorldSeed},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_client_server() {
+        let block_registry = BlockRegistry::default();
+        let generator =
+            WorldGenerator::new(WorldSeed(1), &block_registry, BiomeRegistry::default());
+        let mut server_world = World::new(block_registry.clone());
+        let mut server = Server::bind(
+            "127.0.0.1:0",
+            &mut server_world,
+            ChunkLoader::new(0, usize::MAX, usize::MAX),
+        )
+        .unwrap();
+        let address = server.local_addr().unwrap();
+
+        let column = ColumnPosition { x: 0, z: 0 };
+        let mut worlds = [
+            World::new(block_registry.clone()),
+            World::new(block_registry.clone()),
+        ];
+        let mut clients = [
+            Client::connect(address, "alice").unwrap(),
+            Client::connect(address, "bob").unwrap(),
+        ];
+        // Runs everything until `done` or a few seconds passed
+        let mut run = |server_world: &mut World,
+                       clients: &mut [Client; 2],
+                       worlds: &mut [World; 2],
+                       positions: [[f32; 3]; 2],
+                       done: &dyn Fn(&[Client; 2], &[World; 2]) -> bool| {
+            for _ in 0..500 {
+                server.update(server_world, &generator);
+                for ((client, world), position) in
+                    clients.iter_mut().zip(worlds.iter_mut()).zip(positions)
+                {
+                    client.update(world, position, &[column]).unwrap();
+                }
+                if done(clients, worlds) {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            panic!("timed out");
+        };
+
+        let positions = [[8.0, 80.0, 8.0]; 2];
+        run(
+            &mut server_world,
+            &mut clients,
+            &mut worlds,
+            positions,
+            &|_, worlds| worlds.iter().all(|world| world.is_column_loaded(column)),
+        );
+        assert_eq!(worlds[0][[1, 2, 3]], server_world[[1, 2, 3]]);
+        assert_ne!(clients[0].player_id(), clients[1].player_id());
+
+        let stone = block_registry.block_types.get_index_of("stone").unwrap();
+        clients[0].set_block([1, 100, 3], stone);
+        run(
+            &mut server_world,
+            &mut clients,
+            &mut worlds,
+            positions,
+            &|_, worlds| worlds.iter().all(|world| world[[1, 100, 3]] == stone),
+        );
+        assert_eq!(server_world[[1, 100, 3]], stone);
+
+        // Edits made by the server arrive as one diff, with the last change
+        server_world.set_block([2, 100, 3], stone);
+        server_world.set_block([2, 100, 3], 0);
+        server_world.set_block([2, 101, 3], stone);
+        run(
+            &mut server_world,
+            &mut clients,
+            &mut worlds,
+            positions,
+            &|_, worlds| worlds.iter().all(|world| world[[2, 101, 3]] == stone),
+        );
+        assert_eq!(worlds[1][[2, 100, 3]], 0);
+
+        run(
+            &mut server_world,
+            &mut clients,
+            &mut worlds,
+            [positions[0], [1.0, 2.0, 3.0]],
+            &|clients, _| {
+                clients[0]
+                    .players()
+                    .values()
+                    .any(|player| player.position == [1.0, 2.0, 3.0])
+            },
+        );
+        let player = &clients[0].players()[&clients[1].player_id().unwrap()];
+        assert_eq!(player.name, "bob");
+        assert_eq!(player.position, [1.0, 2.0, 3.0]);
+    }
+}
@@ -0,0 +1,199 @@
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{BlockTypeId, Chunk, CHUNK_SIZE};
+
+const BLOCKS_PER_SECTION: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+const COMPRESSION_LEVEL: i32 = 3;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Bits needed for indices into a palette of `len` entries.
+fn index_bits(len: usize) -> u32 {
+    usize::BITS - len.saturating_sub(1).leading_zeros()
+}
+
+/// The blocks of a section as indices into a palette of the block ids it
+/// contains, in `[y][x][z]` order. Every index takes as few bits as the
+/// palette size allows, with as many packed into each word as fit whole; a
+/// section of a single block needs no words at all.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PalettedBlocks {
+    palette: Vec<u32>,
+    words: Vec<u64>,
+}
+
+impl PalettedBlocks {
+    pub fn pack(blocks: &[[[BlockTypeId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]) -> Self {
+        let mut palette = Vec::new();
+        let indices = blocks
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|&id| {
+                let id = id as u32;
+                match palette.iter().position(|&entry| entry == id) {
+                    Some(index) => index as u64,
+                    None => {
+                        palette.push(id);
+                        palette.len() as u64 - 1
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let bits = index_bits(palette.len());
+        // A single block needs no bits
+        let words = match u64::BITS.checked_div(bits) {
+            None => Vec::new(),
+            Some(per_word) => indices
+                .chunks(per_word as usize)
+                .map(|indices| {
+                    indices
+                        .iter()
+                        .enumerate()
+                        .fold(0, |word, (i, &index)| word | index << (i as u32 * bits))
+                })
+                .collect(),
+        };
+        Self { palette, words }
+    }
+
+    /// Writes the blocks into `blocks`, failing if the indices don't match
+    /// the palette.
+    pub fn unpack(
+        &self,
+        blocks: &mut [[[BlockTypeId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+    ) -> io::Result<()> {
+        let bits = index_bits(self.palette.len());
+        let destination = blocks.iter_mut().flatten().flatten();
+        if bits == 0 {
+            let &[id] = self.palette.as_slice() else {
+                return Err(invalid_data("empty block palette"));
+            };
+            destination.for_each(|block| *block = id as BlockTypeId);
+            return Ok(());
+        }
+
+        let per_word = (u64::BITS / bits) as usize;
+        if self.words.len() != BLOCKS_PER_SECTION.div_ceil(per_word) {
+            return Err(invalid_data("wrong number of packed blocks"));
+        }
+        let mask = (1 << bits) - 1;
+        let indices = self
+            .words
+            .iter()
+            .flat_map(|&word| (0..per_word).map(move |i| (word >> (i as u32 * bits)) & mask));
+        for (block, index) in destination.zip(indices) {
+            let id = self
+                .palette
+                .get(index as usize)
+                .ok_or_else(|| invalid_data("packed block is out of its palette"))?;
+            *block = *id as BlockTypeId;
+        }
+        Ok(())
+    }
+}
+
+/// A section as sent over the network. Everything but the blocks goes as
+/// `Chunk` is saved, with its blocks left as air.
+#[derive(Deserialize, Serialize)]
+struct PackedSection {
+    y: i32,
+    blocks: PalettedBlocks,
+    rest: Chunk,
+}
+
+/// Packs the sections of a column, with their section y, and compresses
+/// them with zstd.
+pub fn compress_column(sections: &[(i32, Chunk)]) -> Vec<u8> {
+    let packed = sections
+        .iter()
+        .map(|(y, chunk)| PackedSection {
+            y: *y,
+            blocks: PalettedBlocks::pack(&chunk.blocks),
+            rest: Chunk {
+                blocks: Default::default(),
+                ..chunk.clone()
+            },
+        })
+        .collect::<Vec<_>>();
+    let payload = bincode::serde::encode_to_vec(&packed, bincode::config::standard()).unwrap();
+    zstd::encode_all(payload.as_slice(), COMPRESSION_LEVEL).unwrap()
+}
+
+/// Reverses `compress_column`.
+pub fn decompress_column(compressed: &[u8]) -> io::Result<Vec<(i32, Chunk)>> {
+    let payload = zstd::decode_all(compressed)?;
+    let (packed, _): (Vec<PackedSection>, _) =
+        bincode::serde::decode_from_slice(&payload, bincode::config::standard())
+            .map_err(|err| invalid_data(&err.to_string()))?;
+    packed
+        .into_iter()
+        .map(|section| {
+            let mut chunk = section.rest;
+            section.blocks.unpack(&mut chunk.blocks)?;
+            Ok((section.y, chunk))
+        })
+        .collect()
+}
+
+/// Index of a block inside its section in a block diff, see
+/// `ServerMessage::BlockDiff`.
+pub fn local_index(position: [i32; 3]) -> u16 {
+    let [x, y, z] = position.map(|v| v.rem_euclid(CHUNK_SIZE as i32) as u16);
+    let size = CHUNK_SIZE as u16;
+    (y * size + x) * size + z
+}
+
+/// Offset from the section origin of the block at `index`, if it is one.
+pub fn local_position(index: u16) -> Option<[i32; 3]> {
+    let size = CHUNK_SIZE as i32;
+    let index = index as i32;
+    (index < size * size * size).then(|| [index / size % size, index / (size * size), index % size])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paletted_blocks() {
+        let mut chunk = Chunk::default();
+        let packed = PalettedBlocks::pack(&chunk.blocks);
+        assert_eq!(packed.palette, vec![0]);
+        assert!(packed.words.is_empty());
+
+        for (i, block) in chunk.blocks.iter_mut().flatten().flatten().enumerate() {
+            *block = [1, 2, 3, 300, 5][i % 7 % 5];
+        }
+        chunk.biomes[3][4] = 2;
+        let packed = PalettedBlocks::pack(&chunk.blocks);
+        assert_eq!(packed.palette, vec![1, 2, 3, 300, 5]);
+        // Three bits per block, 21 to a word
+        assert_eq!(packed.words.len(), 4096usize.div_ceil(21));
+
+        let compressed = compress_column(&[(-1, chunk.clone())]);
+        let sections = decompress_column(&compressed).unwrap();
+        assert_eq!(sections[0].0, -1);
+        assert_eq!(sections[0].1.blocks, chunk.blocks);
+        assert_eq!(sections[0].1.biomes, chunk.biomes);
+
+        let mut corrupt = packed.clone();
+        corrupt.palette.truncate(3);
+        assert!(corrupt.unpack(&mut chunk.blocks).is_err());
+        let mut corrupt = packed;
+        corrupt.words.pop();
+        assert!(corrupt.unpack(&mut chunk.blocks).is_err());
+
+        for position in [[0, 0, 0], [-1, 17, 5], [15, -16, 31]] {
+            let index = local_index(position);
+            let offset = position.map(|v| v.rem_euclid(16));
+            assert_eq!(local_position(index), Some(offset));
+        }
+        assert_eq!(local_position(4096), None);
+    }
+}
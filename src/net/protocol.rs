@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::{BlockTypeId, ChunkPosition, ColumnPosition, WorldMetadata};
+
+/// Bumped whenever a message changes, so old clients are turned away.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+pub type PlayerId = u32;
+
+/// Block ids in messages are those of the server's registry.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum ClientMessage {
+    /// The first message of every connection.
+    Hello {
+        version: u32,
+        name: String,
+    },
+    /// Asks for the sections of a column. The server answers with `Column`
+    /// and keeps sending the column's block changes until it is forgotten.
+    RequestColumn(ColumnPosition),
+    /// The client unloaded the column.
+    ForgetColumn(ColumnPosition),
+    SetBlock {
+        position: [i32; 3],
+        block_type_id: BlockTypeId,
+    },
+    PlayerPosition([f32; 3]),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum ServerMessage {
+    /// Answers `Hello`. The block names of `metadata` give the meaning of
+    /// the server's block ids.
+    Welcome {
+        player_id: PlayerId,
+        metadata: WorldMetadata,
+    },
+    /// The server closes the connection after this.
+    Disconnect {
+        reason: String,
+    },
+    /// The sections of a requested column, see `packing::compress_column`.
+    Column {
+        column: ColumnPosition,
+        payload: Vec<u8>,
+    },
+    /// The blocks of a section that changed since the last update, by their
+    /// index in the section (see `packing::local_index`), each once.
+    BlockDiff {
+        section: ChunkPosition,
+        changes: Vec<(u16, BlockTypeId)>,
+    },
+    PlayerPosition {
+        player_id: PlayerId,
+        name: String,
+        position: [f32; 3],
+    },
+    PlayerLeft(PlayerId),
+}
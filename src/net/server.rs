@@ -0,0 +1,294 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    sync::mpsc::Receiver,
+};
+
+use log::{info, warn};
+
+use crate::{
+    chunk_loader::ChunkLoader,
+    events::WorldEvent,
+    settings::MAX_RENDER_DISTANCE,
+    types::{BlockTypeId, ChunkPosition, ColumnPosition, World},
+    worldgen::WorldGenerator,
+};
+
+use super::{
+    connection::Connection,
+    packing::{compress_column, local_index},
+    protocol::{ClientMessage, PlayerId, ServerMessage, PROTOCOL_VERSION},
+};
+
+struct Player {
+    connection: Connection,
+    /// Set by `Hello`; nothing else is accepted before it.
+    name: Option<String>,
+    /// Columns the player requested and didn't forget, which it gets the
+    /// block changes of.
+    columns: HashSet<ColumnPosition>,
+    /// Where the player last said it is, which the columns it requests
+    /// have to be near.
+    position: Option<[f32; 3]>,
+    disconnected: bool,
+}
+
+impl Player {
+    /// Whether `column` is within the largest render distance of the
+    /// player, with a column to spare for its requests crossing its moves.
+    fn in_range(&self, column: ColumnPosition) -> bool {
+        let Some([x, y, z]) = self.position else {
+            return false;
+        };
+        let center =
+            ColumnPosition::of_block([x.floor() as i32, y.floor() as i32, z.floor() as i32]);
+        let (dx, dz) = ((column.x - center.x) as i64, (column.z - center.z) as i64);
+        let range = MAX_RENDER_DISTANCE as i64 + 1;
+        dx * dx + dz * dz <= range * range
+    }
+
+    fn disconnect(&mut self, reason: &str) {
+        self.connection.send(&ServerMessage::Disconnect {
+            reason: reason.to_string(),
+        });
+        self.disconnected = true;
+    }
+}
+
+/// Owns the authoritative copy of a world and shares it with clients: it
+/// sends them the columns they ask for, applies their block edits and
+/// forwards the block changes of the world as per-section diffs, along with
+/// player positions.
+/// Columns are loaded through `loader` when first requested, and unloaded
+/// once no player holds them and the loader has cached enough others.
+pub struct Server {
+    listener: TcpListener,
+    players: HashMap<PlayerId, Player>,
+    next_player_id: PlayerId,
+    loader: ChunkLoader,
+    world_events: Receiver<WorldEvent>,
+}
+
+impl Server {
+    pub fn bind(
+        address: impl ToSocketAddrs,
+        world: &mut World,
+        loader: ChunkLoader,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            players: HashMap::new(),
+            next_player_id: 0,
+            loader,
+            world_events: world.events.subscribe(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts new players, handles the messages that arrived and sends the
+    /// changes made to `world` since the last update.
+    pub fn update(&mut self, world: &mut World, generator: &WorldGenerator) {
+        self.accept();
+
+        let ids = self.players.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            loop {
+                let player = self.players.get_mut(&id).unwrap();
+                if player.disconnected {
+                    break;
+                }
+                match player.connection.receive::<ClientMessage>() {
+                    Ok(Some(message)) => self.handle(id, message, world, generator),
+                    Ok(None) => break,
+                    Err(err) => {
+                        info!("Player {} disconnected: {}", id, err);
+                        player.disconnected = true;
+                    }
+                }
+            }
+        }
+
+        // Changes are batched per section, keeping the last one of a block
+        let mut diffs: HashMap<ChunkPosition, BTreeMap<u16, BlockTypeId>> = HashMap::new();
+        for event in self.world_events.try_iter() {
+            if let WorldEvent::BlockChanged {
+                position, after, ..
+            } = event
+            {
+                diffs
+                    .entry(ChunkPosition::of_block(position))
+                    .or_default()
+                    .insert(local_index(position), after);
+            }
+        }
+        for (section, changes) in diffs {
+            let column = ColumnPosition {
+                x: section.x,
+                z: section.z,
+            };
+            let message = ServerMessage::BlockDiff {
+                section,
+                changes: changes.into_iter().collect(),
+            };
+            for player in self.players.values_mut() {
+                if player.columns.contains(&column) {
+                    player.connection.send(&message);
+                }
+            }
+        }
+
+        let mut left = Vec::new();
+        for (&id, player) in &mut self.players {
+            if let Err(err) = player.connection.flush() {
+                info!("Player {} disconnected: {}", id, err);
+                player.disconnected = true;
+            }
+            if player.disconnected {
+                left.push(id);
+            }
+        }
+        for id in left {
+            let player = self.players.remove(&id).unwrap();
+            if player.name.is_some() {
+                self.broadcast(&ServerMessage::PlayerLeft(id));
+            }
+        }
+
+        let held = self
+            .players
+            .values()
+            .flat_map(|player| player.columns.iter().copied())
+            .collect::<HashSet<_>>();
+        self.loader.evict(world, &held);
+    }
+
+    /// Saves every loaded column, see `ChunkLoader::save_all`.
+    pub fn save(&mut self, world: &World) {
+        self.loader.save_all(world);
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => match Connection::new(stream) {
+                    Ok(connection) => {
+                        let id = self.next_player_id;
+                        self.next_player_id += 1;
+                        info!("Player {} connected from {}", id, address);
+                        self.players.insert(
+                            id,
+                            Player {
+                                connection,
+                                name: None,
+                                columns: HashSet::new(),
+                                position: None,
+                                disconnected: false,
+                            },
+                        );
+                    }
+                    Err(err) => warn!("Failed to set up connection from {}: {}", address, err),
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warn!("Failed to accept a connection: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        id: PlayerId,
+        message: ClientMessage,
+        world: &mut World,
+        generator: &WorldGenerator,
+    ) {
+        let player = self.players.get_mut(&id).unwrap();
+        let Some(name) = player.name.clone() else {
+            match message {
+                ClientMessage::Hello { version, .. } if version != PROTOCOL_VERSION => {
+                    player.disconnect(&format!(
+                        "protocol version {} is required, not {}",
+                        PROTOCOL_VERSION, version
+                    ));
+                }
+                ClientMessage::Hello { name, .. } => {
+                    info!("Player {} is {:?}", id, name);
+                    player.name = Some(name);
+                    player.connection.send(&ServerMessage::Welcome {
+                        player_id: id,
+                        metadata: world.metadata(),
+                    });
+                }
+                _ => player.disconnect("expected hello first"),
+            }
+            return;
+        };
+
+        match message {
+            ClientMessage::Hello { .. } => player.disconnect("said hello twice"),
+            ClientMessage::RequestColumn(column) if !player.in_range(column) => {
+                warn!("Player {} requested the distant column {:?}", id, column);
+            }
+            ClientMessage::RequestColumn(column) => {
+                self.loader.load_column(world, generator, column);
+                let sections = column
+                    .sections(world.height)
+                    .map(|position| (position.y, world.chunks[&position].clone()))
+                    .collect::<Vec<_>>();
+                let payload = compress_column(&sections);
+                let player = self.players.get_mut(&id).unwrap();
+                player.columns.insert(column);
+                player
+                    .connection
+                    .send(&ServerMessage::Column { column, payload });
+            }
+            ClientMessage::ForgetColumn(column) => {
+                player.columns.remove(&column);
+            }
+            ClientMessage::SetBlock {
+                position,
+                block_type_id,
+            } => {
+                // Only blocks in the player's columns can be edited
+                if block_type_id < world.block_registry.block_types.len()
+                    && world.height.contains(position[1])
+                    && player.columns.contains(&ColumnPosition::of_block(position))
+                {
+                    world.set_block(position, block_type_id);
+                } else {
+                    warn!("Player {} set an invalid block at {:?}", id, position);
+                }
+            }
+            ClientMessage::PlayerPosition(position) => {
+                player.position = Some(position);
+                let message = ServerMessage::PlayerPosition {
+                    player_id: id,
+                    name,
+                    position,
+                };
+                for (&other, player) in &mut self.players {
+                    if other != id && player.name.is_some() {
+                        player.connection.send(&message);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends `message` to every player that said hello.
+    fn broadcast(&mut self, message: &ServerMessage) {
+        for player in self.players.values_mut() {
+            if player.name.is_some() {
+                player.connection.send(message);
+            }
+        }
+    }
+}
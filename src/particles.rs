@@ -0,0 +1,319 @@
+//! Short-lived visual effects simulated on the CPU: debris of broken blocks,
+//! smoke of landing blocks, rain and snow. They don't affect the world and aren't saved.
+
+use std::sync::mpsc::Receiver;
+
+use cgmath::{Point3, Vector3};
+
+use crate::{
+    events::WorldEvent,
+    types::{BlockTypeId, Direction, TextureId, World},
+    worldgen::random::Random,
+};
+
+/// Particles alive at once; spawning more replaces the oldest.
+pub const MAX_PARTICLES: usize = 16384;
+/// Blocks per second squared.
+const GRAVITY: f32 = 20.0;
+/// Debris pieces per broken block, along each axis.
+const DEBRIS_PIECES: usize = 3;
+/// Puffs of smoke, or rather dust, raised by a falling block landing.
+const LANDING_PUFFS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParticleKind {
+    /// A piece of a broken block, textured with part of its face.
+    Debris,
+    /// Rises and fades out.
+    Smoke,
+    /// Falls fast and disappears on hitting a block.
+    Rain,
+    /// Drifts down slowly, swaying, and disappears on hitting a block.
+    Snow,
+}
+
+#[derive(Debug, Clone)]
+pub struct Particle {
+    pub kind: ParticleKind,
+    pub position: Point3<f32>,
+    pub velocity: Vector3<f32>,
+    /// Edge length in blocks.
+    pub size: f32,
+    /// Multiplied with the texture, if any; alpha fades with age.
+    pub color: [f32; 4],
+    pub texture: Option<TextureId>,
+    /// Part of the texture shown, as `[u_min, v_min, u_max, v_max]`.
+    pub uv: [f32; 4],
+    /// Seconds lived so far.
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+impl Particle {
+    /// Opacity at the particle's age; smoke, rain and snow fade out over
+    /// the second half of their lives.
+    pub fn opacity(&self) -> f32 {
+        let remaining = 1.0 - self.age / self.lifetime;
+        match self.kind {
+            ParticleKind::Debris => 1.0,
+            ParticleKind::Smoke | ParticleKind::Rain | ParticleKind::Snow => {
+                (remaining * 2.0).min(1.0)
+            }
+        }
+    }
+}
+
+/// All live particles. Debris is spawned for every block of the world that
+/// is broken, the rest on request.
+pub struct Particles {
+    particles: Vec<Particle>,
+    random: Random,
+    world_events: Receiver<WorldEvent>,
+}
+
+impl Particles {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            particles: Vec::new(),
+            random: Random::new(0),
+            world_events: world.events.subscribe(),
+        }
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    fn spawn(&mut self, particle: Particle) {
+        if self.particles.len() == MAX_PARTICLES {
+            let oldest = (0..self.particles.len())
+                .max_by(|&a, &b| {
+                    let age = |i: usize| self.particles[i].age / self.particles[i].lifetime;
+                    age(a).total_cmp(&age(b))
+                })
+                .unwrap();
+            self.particles.swap_remove(oldest);
+        }
+        self.particles.push(particle);
+    }
+
+    /// A value in `-1.0..1.0`.
+    fn spread(&mut self) -> f32 {
+        self.random.next_f32() * 2.0 - 1.0
+    }
+
+    /// Bursts the block at `position` into pieces textured with its faces.
+    pub fn spawn_debris(&mut self, world: &World, position: [i32; 3], block_type_id: BlockTypeId) {
+        let block_type = &world.block_registry.block_types[block_type_id];
+        let piece = 1.0 / DEBRIS_PIECES as f32;
+        for i in 0..DEBRIS_PIECES.pow(3) {
+            let cell = [
+                i % DEBRIS_PIECES,
+                i / DEBRIS_PIECES % DEBRIS_PIECES,
+                i / DEBRIS_PIECES.pow(2),
+            ];
+            let [x, y, z] = cell.map(|v| (v as f32 + 0.5) * piece);
+            let offset = Vector3::new(x, y, z);
+            let direction = Direction::ALL[self.random.next_below(6) as usize];
+            let [u, v] =
+                [self.random.next_f32(), self.random.next_f32()].map(|v| v * (1.0 - piece));
+            let velocity = (offset - Vector3::new(0.5, 0.5, 0.5)) * 6.0
+                + Vector3::new(self.spread(), 2.0 + self.spread(), self.spread());
+            let lifetime = 0.6 + self.random.next_f32() * 0.6;
+            self.spawn(Particle {
+                kind: ParticleKind::Debris,
+                position: Point3::new(position[0] as f32, position[1] as f32, position[2] as f32)
+                    + offset,
+                velocity,
+                size: piece * 0.6,
+                color: [1.0; 4],
+                texture: block_type.texture(direction),
+                uv: [u, v, u + piece, v + piece],
+                age: 0.0,
+                lifetime,
+            });
+        }
+    }
+
+    /// Puffs `count` particles of smoke up from around `position`.
+    pub fn spawn_smoke(&mut self, position: Point3<f32>, count: usize) {
+        for _ in 0..count {
+            let offset = Vector3::new(self.spread() * 0.4, 0.0, self.spread() * 0.4);
+            let velocity = Vector3::new(self.spread() * 0.2, 1.0, self.spread() * 0.2);
+            let gray = 0.4 + self.random.next_f32() * 0.3;
+            let lifetime = 1.5 + self.random.next_f32();
+            self.spawn(Particle {
+                kind: ParticleKind::Smoke,
+                position: position + offset,
+                velocity,
+                size: 0.3,
+                color: [gray, gray, gray, 0.6],
+                texture: None,
+                uv: [0.0, 0.0, 1.0, 1.0],
+                age: 0.0,
+                lifetime,
+            });
+        }
+    }
+
+    /// Raises dust off the top of the block at `position`, where a falling
+    /// block landed.
+    pub fn spawn_landing(&mut self, position: [i32; 3]) {
+        let [x, y, z] = position.map(|v| v as f32);
+        self.spawn_smoke(Point3::new(x + 0.5, y + 1.0, z + 0.5), LANDING_PUFFS);
+    }
+
+    /// Drops `count` raindrops from above `center` within `radius` blocks.
+    pub fn spawn_rain(&mut self, center: Point3<f32>, radius: f32, count: usize) {
+        for _ in 0..count {
+            let position =
+                center + Vector3::new(self.spread() * radius, 16.0, self.spread() * radius);
+            self.spawn(Particle {
+                kind: ParticleKind::Rain,
+                position,
+                velocity: Vector3::new(0.0, -16.0, 0.0),
+                size: 0.05,
+                color: [0.6, 0.7, 0.9, 0.5],
+                texture: None,
+                uv: [0.0, 0.0, 1.0, 1.0],
+                age: 0.0,
+                lifetime: 2.0,
+            });
+        }
+    }
+
+    /// Drops `count` snowflakes from above `center` within `radius` blocks.
+    pub fn spawn_snow(&mut self, center: Point3<f32>, radius: f32, count: usize) {
+        for _ in 0..count {
+            let position =
+                center + Vector3::new(self.spread() * radius, 16.0, self.spread() * radius);
+            let velocity = Vector3::new(self.spread() * 0.5, -2.0, self.spread() * 0.5);
+            self.spawn(Particle {
+                kind: ParticleKind::Snow,
+                position,
+                velocity,
+                size: 0.1,
+                color: [1.0, 1.0, 1.0, 0.9],
+                texture: None,
+                uv: [0.0, 0.0, 1.0, 1.0],
+                age: 0.0,
+                lifetime: 10.0,
+            });
+        }
+    }
+
+    /// Spawns debris for the blocks broken since the last update and moves
+    /// every particle on by `delta` seconds. Debris, rain and snow collide
+    /// with the blocks of `world`.
+    pub fn update(&mut self, world: &World, delta: f32) {
+        let broken = self
+            .world_events
+            .try_iter()
+            .filter_map(|event| match event {
+                WorldEvent::BlockChanged {
+                    position,
+                    before,
+                    after: 0,
+                } if before != 0 => Some((position, before)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for (position, block_type_id) in broken {
+            self.spawn_debris(world, position, block_type_id);
+        }
+
+        // Unloaded blocks are air, so particles fall through missing terrain
+        let solid = |position: Point3<f32>| {
+            let block = [position.x, position.y, position.z].map(|v| v.floor() as i32);
+            !world.block_registry.block_types[world[block]].transparent
+        };
+        self.particles.retain_mut(|particle| {
+            particle.age += delta;
+            if particle.age >= particle.lifetime {
+                return false;
+            }
+            match particle.kind {
+                ParticleKind::Debris => particle.velocity.y -= GRAVITY * delta,
+                ParticleKind::Smoke => particle.velocity *= 1.0 - delta,
+                ParticleKind::Rain => {}
+                // Sways around the way it is drifting
+                ParticleKind::Snow => {
+                    let sway = (particle.age * 2.0).sin() * delta;
+                    particle.velocity.x += particle.velocity.z * sway;
+                    particle.velocity.z -= particle.velocity.x * sway;
+                }
+            }
+            let next = particle.position + particle.velocity * delta;
+            if !solid(next) {
+                particle.position = next;
+                return true;
+            }
+            match particle.kind {
+                ParticleKind::Rain | ParticleKind::Snow => false,
+                // Debris comes to rest on the block it hits
+                _ => {
+                    particle.velocity = Vector3::new(0.0, 0.0, 0.0);
+                    true
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::BlockRegistry;
+
+    use super::*;
+
+    #[test]
+    fn test_particles() {
+        let mut world = World::new(BlockRegistry::default());
+        let stone = world
+            .block_registry
+            .block_types
+            .get_index_of("stone")
+            .unwrap();
+        world.set_block([0, 10, 0], stone);
+        let mut particles = Particles::new(&mut world);
+
+        world.set_block([0, 10, 0], 0);
+        particles.update(&world, 0.0);
+        assert_eq!(particles.particles().len(), DEBRIS_PIECES.pow(3));
+        assert!(particles
+            .particles()
+            .iter()
+            .all(|particle| particle.kind == ParticleKind::Debris && particle.texture.is_some()));
+
+        // Rain and snow stop at the floor, debris rests on it
+        for x in -5..=5 {
+            for z in -5..=5 {
+                world.set_block([x, 5, z], stone);
+            }
+        }
+        particles.update(&world, 0.0);
+        particles.spawn_rain(Point3::new(0.5, 0.0, 0.5), 1.0, 10);
+        particles.spawn_snow(Point3::new(0.5, -9.0, 0.5), 1.0, 10);
+        for _ in 0..50 {
+            particles.update(&world, 0.02);
+        }
+        assert!(particles
+            .particles()
+            .iter()
+            .all(|particle| particle.kind == ParticleKind::Debris && particle.position.y > 5.9));
+
+        for _ in 0..100 {
+            particles.update(&world, 0.02);
+        }
+        assert!(particles.particles().is_empty());
+
+        // Smoke rises off the block a falling block landed on
+        particles.spawn_landing([0, 5, 0]);
+        particles.update(&world, 0.1);
+        assert_eq!(particles.particles().len(), LANDING_PUFFS);
+        assert!(particles
+            .particles()
+            .iter()
+            .all(|particle| particle.kind == ParticleKind::Smoke && particle.position.y > 6.0));
+    }
+}
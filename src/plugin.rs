@@ -0,0 +1,556 @@
+use std::{collections::HashMap, fs, io, path::Path, sync::mpsc::Receiver};
+
+use log::{info, warn};
+use wasmtime::{
+    Caller, Config, Engine, Instance, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedFunc, WasmParams, WasmResults,
+};
+
+use crate::{
+    events::{WorldEvent, WorldEvents},
+    types::{
+        local_block_position, BlockRegistry, BlockRotation, BlockTextures, BlockType, BlockTypeId,
+        Chunk, ChunkPosition, Material, Orientation, Shape, Tint, World, WorldHeight, CHUNK_SIZE,
+    },
+    worldgen::ChunkColumn,
+};
+
+pub const PLUGIN_DIRECTORY: &str = "plugins";
+/// Module the host functions are imported from.
+const HOST_MODULE: &str = "block_world";
+/// Instructions a plugin may run per call before it is interrupted, so a
+/// plugin stuck in a loop can't hang the game.
+const FUEL_PER_CALL: u64 = 50_000_000;
+/// Linear memory a plugin may grow to.
+const MAX_MEMORY: usize = 64 << 20;
+
+/// A block type a plugin registered during `init`.
+struct PluginBlock {
+    name: String,
+    texture: String,
+    transparent: bool,
+}
+
+/// What the host functions act on during a call into a plugin.
+enum Context {
+    None,
+    /// `init`, the only time blocks can be registered. Names of the block
+    /// types, including the ones registered so far.
+    Init {
+        block_names: Vec<String>,
+        registered: Vec<PluginBlock>,
+    },
+    /// Block updates and ticks. The sections are lent from the world for the
+    /// call, and the changes made are sent as world events afterwards.
+    World {
+        chunks: HashMap<ChunkPosition, Chunk>,
+        height: WorldHeight,
+        changes: Vec<WorldEvent>,
+    },
+    /// `generate_column`, which can only see the column being generated.
+    Column(ChunkColumn),
+}
+
+struct PluginState {
+    limits: StoreLimits,
+    context: Context,
+    block_count: usize,
+}
+
+impl PluginState {
+    /// The block at world position `position`; air where the context has none.
+    fn block(&self, position: [i32; 3]) -> BlockTypeId {
+        match &self.context {
+            Context::World { chunks, height, .. } if height.contains(position[1]) => {
+                let [x, y, z] = local_block_position(position);
+                chunks
+                    .get(&ChunkPosition::of_block(position))
+                    .map_or(0, |chunk| chunk.blocks[y][x][z])
+            }
+            Context::Column(column) => match column_local(column, position) {
+                Some([x, z]) => column.block(x, position[1], z),
+                None => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    /// Sets the block at world position `position` if the context has it.
+    fn set_block(&mut self, position: [i32; 3], block_type_id: BlockTypeId) {
+        if block_type_id >= self.block_count {
+            return;
+        }
+        match &mut self.context {
+            Context::World {
+                chunks,
+                height,
+                changes,
+            } if height.contains(position[1]) => {
+                let Some(chunk) = chunks.get_mut(&ChunkPosition::of_block(position)) else {
+                    return;
+                };
+                let [x, y, z] = local_block_position(position);
+                let before = std::mem::replace(&mut chunk.blocks[y][x][z], block_type_id);
+                if before != block_type_id {
+                    changes.push(WorldEvent::BlockChanged {
+                        position,
+                        before,
+                        after: block_type_id,
+                    });
+                }
+            }
+            Context::Column(column) => {
+                if let Some([x, z]) = column_local(column, position) {
+                    *column.block_mut(x, position[1], z) = block_type_id;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Local x and z of world position `position` inside `column`, if it is in
+/// the column.
+fn column_local(column: &ChunkColumn, position: [i32; 3]) -> Option<[usize; 2]> {
+    let size = CHUNK_SIZE as i32;
+    let x = position[0] - column.x * size;
+    let z = position[2] - column.z * size;
+    ((0..size).contains(&x) && (0..size).contains(&z) && column.height.contains(position[1]))
+        .then_some([x as usize, z as usize])
+}
+
+/// A loaded WebAssembly plugin. Every plugin has a store of its own, so
+/// plugins can't see each other's memory, and its calls are limited in fuel
+/// and memory.
+///
+/// Plugins import their host functions from the `block_world` module:
+/// - `log(ptr, len)` logs the UTF-8 string at `ptr`.
+/// - `register_block(name_ptr, name_len, texture_ptr, texture_len,
+///   transparent) -> id` registers a block type during `init`, returning its
+///   id, or -1 if the name is taken or it is called later.
+/// - `get_block(x, y, z) -> id` and `set_block(x, y, z, id)` access the world
+///   during `on_block_update` and `on_tick` and the column being generated
+///   during `generate_column`.
+///
+/// They may export `memory` and any of:
+/// - `init()`, called once when the plugins are loaded.
+/// - `on_block_update(x, y, z, id)`, called after a block changed to `id`.
+/// - `on_tick(tick)`, called every tick.
+/// - `generate_column(column_x, column_z)`, called after the terrain of a
+///   column is generated.
+struct Plugin {
+    name: String,
+    store: Store<PluginState>,
+    init: Option<TypedFunc<(), ()>>,
+    on_block_update: Option<TypedFunc<(i32, i32, i32, i32), ()>>,
+    on_tick: Option<TypedFunc<i64, ()>>,
+    generate_column: Option<TypedFunc<(i32, i32), ()>>,
+}
+
+impl Plugin {
+    /// Calls `func` with fresh fuel. Traps are logged and otherwise ignored.
+    fn call<P: WasmParams, R: WasmResults>(
+        &mut self,
+        func: &TypedFunc<P, R>,
+        params: P,
+    ) -> Option<R> {
+        self.store.set_fuel(FUEL_PER_CALL).unwrap();
+        match func.call(&mut self.store, params) {
+            Ok(results) => Some(results),
+            Err(err) => {
+                warn!("Plugin {:?} failed: {:#}", self.name, err);
+                None
+            }
+        }
+    }
+}
+
+/// Loads WebAssembly plugins and calls into them.
+pub struct PluginHost {
+    engine: Engine,
+    linker: Linker<PluginState>,
+    plugins: Vec<Plugin>,
+    events: Option<Receiver<WorldEvent>>,
+}
+
+impl PluginHost {
+    pub fn new() -> Self {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).unwrap();
+        let mut linker = Linker::new(&engine);
+        define_host_functions(&mut linker);
+        Self {
+            engine,
+            linker,
+            plugins: Vec::new(),
+            events: None,
+        }
+    }
+
+    /// Loads every `.wasm` file in `directory`, which may not exist. Plugins
+    /// that fail to load are reported and skipped.
+    pub fn load_directory(&mut self, directory: impl AsRef<Path>) -> io::Result<()> {
+        let directory = directory.as_ref();
+        if !directory.exists() {
+            return Ok(());
+        }
+        let mut paths = fs::read_dir(directory)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        // Plugins register their blocks in load order
+        paths.sort();
+        for path in paths {
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "wasm")
+            {
+                let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+                match fs::read(&path) {
+                    Ok(bytes) => self.load(&name, &bytes),
+                    Err(err) => warn!("Failed to read plugin {:?}: {}", path, err),
+                }
+            }
+        }
+        info!("Loaded {} plugins from {:?}", self.plugins.len(), directory);
+        Ok(())
+    }
+
+    /// Loads the plugin `name` from a WebAssembly module, binary or text.
+    pub fn load(&mut self, name: &str, bytes: &[u8]) {
+        let module = match Module::new(&self.engine, bytes) {
+            Ok(module) => module,
+            Err(err) => {
+                warn!("Failed to compile plugin {:?}: {:#}", name, err);
+                return;
+            }
+        };
+        let mut store = Store::new(
+            &self.engine,
+            PluginState {
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(MAX_MEMORY)
+                    .instances(1)
+                    .build(),
+                context: Context::None,
+                block_count: 0,
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL_PER_CALL).unwrap();
+        let instance = match self.linker.instantiate(&mut store, &module) {
+            Ok(instance) => instance,
+            Err(err) => {
+                warn!("Failed to instantiate plugin {:?}: {:#}", name, err);
+                return;
+            }
+        };
+
+        self.plugins.push(Plugin {
+            name: name.to_string(),
+            init: typed_func(&instance, &mut store, "init"),
+            on_block_update: typed_func(&instance, &mut store, "on_block_update"),
+            on_tick: typed_func(&instance, &mut store, "on_tick"),
+            generate_column: typed_func(&instance, &mut store, "generate_column"),
+            store,
+        });
+    }
+
+    /// Calls `init` of every plugin, adding the block types they register to
+    /// `block_registry`. Has to happen before the world and the renderer are
+    /// created from the registry.
+    pub fn register_blocks(&mut self, block_registry: &mut BlockRegistry) {
+        for plugin in &mut self.plugins {
+            plugin.store.data_mut().context = Context::Init {
+                block_names: block_registry.block_names(),
+                registered: Vec::new(),
+            };
+            if let Some(init) = plugin.init.clone() {
+                plugin.call(&init, ());
+            }
+            let Context::Init { registered, .. } =
+                std::mem::replace(&mut plugin.store.data_mut().context, Context::None)
+            else {
+                unreachable!();
+            };
+            for block in registered {
+                let texture = block_registry
+                    .texture_registry
+                    .get_index_or_missing(&block.texture);
+                block_registry.block_types.insert(
+                    block.name.clone(),
+                    BlockType {
+                        name: block.name,
+                        textures: BlockTextures::uniform(texture),
+                        transparent: block.transparent,
+                        tint: Tint::None,
+                        light_level: 0,
+                        hardness: 1.0,
+                        shape: Shape::Cube,
+                        orientation: Orientation::None,
+                        reflectivity: 0.0,
+                        material: Material::default(),
+                        gravity: false,
+                        rotation: BlockRotation::NONE,
+                    },
+                );
+            }
+        }
+        for plugin in &mut self.plugins {
+            plugin.store.data_mut().block_count = block_registry.block_types.len();
+        }
+    }
+
+    /// Receives the world events the block updates of `update` are sent
+    /// from.
+    pub fn subscribe(&mut self, events: &mut WorldEvents) {
+        self.events = Some(events.subscribe());
+    }
+
+    /// Sends the blocks changed since the last call to `on_block_update`.
+    /// Changes plugins make are sent on the next call.
+    pub fn update(&mut self, world: &mut World) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        let changed = events
+            .try_iter()
+            .filter_map(|event| match event {
+                WorldEvent::BlockChanged {
+                    position, after, ..
+                } => Some((position, after)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if changed.is_empty() {
+            return;
+        }
+        self.call_with_world(world, |plugin| {
+            let Some(on_block_update) = plugin.on_block_update.clone() else {
+                return;
+            };
+            for &([x, y, z], block_type_id) in &changed {
+                plugin.call(&on_block_update, (x, y, z, block_type_id as i32));
+            }
+        });
+    }
+
+    pub fn tick(&mut self, world: &mut World, tick: u64) {
+        self.call_with_world(world, |plugin| {
+            if let Some(on_tick) = plugin.on_tick.clone() {
+                plugin.call(&on_tick, tick as i64);
+            }
+        });
+    }
+
+    /// Lets the plugins change `column` after its terrain was generated.
+    pub fn generate_column(&mut self, column: &mut ChunkColumn) {
+        for plugin in &mut self.plugins {
+            let Some(generate_column) = plugin.generate_column.clone() else {
+                continue;
+            };
+            let (x, z, height) = (column.x, column.z, column.height);
+            let taken = std::mem::replace(column, ChunkColumn::new(x, z, height));
+            plugin.store.data_mut().context = Context::Column(taken);
+            plugin.call(&generate_column, (x, z));
+            let Context::Column(taken) =
+                std::mem::replace(&mut plugin.store.data_mut().context, Context::None)
+            else {
+                unreachable!();
+            };
+            *column = taken;
+        }
+    }
+
+    /// Calls `call` for every plugin with the world's sections lent to it,
+    /// then emits the changes made as world events.
+    fn call_with_world(&mut self, world: &mut World, mut call: impl FnMut(&mut Plugin)) {
+        for plugin in &mut self.plugins {
+            plugin.store.data_mut().context = Context::World {
+                chunks: std::mem::take(&mut world.chunks),
+                height: world.height,
+                changes: Vec::new(),
+            };
+            call(plugin);
+            let Context::World {
+                chunks, changes, ..
+            } = std::mem::replace(&mut plugin.store.data_mut().context, Context::None)
+            else {
+                unreachable!();
+            };
+            world.chunks = chunks;
+            for event in changes {
+                world.events.emit(event);
+            }
+        }
+    }
+}
+
+impl Default for PluginHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The export `name` of a plugin, if it has one of the right type. Plugins
+/// only export the functions they need.
+fn typed_func<P: WasmParams, R: WasmResults>(
+    instance: &Instance,
+    store: &mut Store<PluginState>,
+    name: &str,
+) -> Option<TypedFunc<P, R>> {
+    instance.get_func(&mut *store, name)?;
+    instance
+        .get_typed_func(store, name)
+        .map_err(|err| warn!("Plugin export {:?} has the wrong type: {:#}", name, err))
+        .ok()
+}
+
+/// Reads the UTF-8 string of `len` bytes at `ptr` from the memory of the
+/// calling plugin.
+fn read_string(caller: &mut Caller<'_, PluginState>, ptr: i32, len: i32) -> Option<String> {
+    let memory = caller.get_export("memory")?.into_memory()?;
+    let bytes = memory
+        .data(&caller)
+        .get(ptr as u32 as usize..)?
+        .get(..len as u32 as usize)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+fn define_host_functions(linker: &mut Linker<PluginState>) {
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "log",
+            |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| {
+                if let Some(message) = read_string(&mut caller, ptr, len) {
+                    info!("[plugin] {}", message);
+                }
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "register_block",
+            |mut caller: Caller<'_, PluginState>,
+             name_ptr: i32,
+             name_len: i32,
+             texture_ptr: i32,
+             texture_len: i32,
+             transparent: i32|
+             -> i32 {
+                let (Some(name), Some(texture)) = (
+                    read_string(&mut caller, name_ptr, name_len),
+                    read_string(&mut caller, texture_ptr, texture_len),
+                ) else {
+                    return -1;
+                };
+                let Context::Init {
+                    block_names,
+                    registered,
+                } = &mut caller.data_mut().context
+                else {
+                    return -1;
+                };
+                if block_names.contains(&name) {
+                    warn!("Plugin block {:?} is already registered", name);
+                    return -1;
+                }
+                block_names.push(name.clone());
+                registered.push(PluginBlock {
+                    name,
+                    texture,
+                    transparent: transparent != 0,
+                });
+                block_names.len() as i32 - 1
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "get_block",
+            |caller: Caller<'_, PluginState>, x: i32, y: i32, z: i32| -> i32 {
+                caller.data().block([x, y, z]) as i32
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "set_block",
+            |mut caller: Caller<'_, PluginState>, x: i32, y: i32, z: i32, id: i32| {
+                if let Ok(id) = BlockTypeId::try_from(id) {
+                    caller.data_mut().set_block([x, y, z], id);
+                }
+            },
+        )
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::ColumnPosition;
+
+    use super::*;
+
+    /// Registers a lamp block, puts a lamp on every stone placed and a stone
+    /// at the corner of every generated column.
+    const PLUGIN: &str = r#"
+        (module
+            (import "block_world" "register_block"
+                (func $register_block (param i32 i32 i32 i32 i32) (result i32)))
+            (import "block_world" "set_block" (func $set_block (param i32 i32 i32 i32)))
+            (import "block_world" "get_block" (func $get_block (param i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "lamp")
+            (global $lamp (mut i32) (i32.const -1))
+            (func (export "init")
+                (global.set $lamp (call $register_block (i32.const 0) (i32.const 4)
+                    (i32.const 0) (i32.const 4) (i32.const 1))))
+            (func (export "on_block_update") (param i32 i32 i32 i32)
+                (if (i32.eq (local.get 3) (i32.const 1))
+                    (then (call $set_block (local.get 0) (i32.add (local.get 1) (i32.const 1))
+                        (local.get 2) (global.get $lamp)))))
+            (func (export "generate_column") (param i32 i32)
+                (call $set_block (i32.mul (local.get 0) (i32.const 16)) (i32.const 100)
+                    (i32.mul (local.get 1) (i32.const 16))
+                    (i32.add (call $get_block (i32.const 0) (i32.const 0) (i32.const 0))
+                        (i32.const 1))))
+            (func (export "on_tick") (param i64)
+                (loop $forever (br $forever))))
+    "#;
+
+    #[test]
+    fn test_plugin() {
+        let mut host = PluginHost::new();
+        host.load("lamps", PLUGIN.as_bytes());
+        host.load("broken", b"not a module");
+        assert_eq!(host.plugins.len(), 1);
+
+        let mut block_registry = BlockRegistry::default();
+        let block_count = block_registry.block_types.len();
+        host.register_blocks(&mut block_registry);
+        let lamp = block_registry.block_types.get_index_of("lamp").unwrap();
+        assert_eq!(lamp, block_count);
+
+        let mut world = World::new(block_registry);
+        host.subscribe(&mut world.events);
+        let mut column = ChunkColumn::new(-1, 2, world.height);
+        host.generate_column(&mut column);
+        assert_eq!(column.block(0, 100, 0), 1);
+        world.insert_chunks(column.into_chunks());
+
+        let events = world.events.subscribe();
+        world.set_block([-10, 40, 35], 1);
+        host.update(&mut world);
+        assert_eq!(world[[-10, 41, 35]], lamp);
+        assert_eq!(events.try_iter().count(), 2);
+
+        // The endless loop runs out of fuel
+        host.tick(&mut world, 0);
+        assert_eq!(world.chunks.len(), world.height.sections().len());
+        assert!(world.is_column_loaded(ColumnPosition { x: -1, z: 2 }));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{
+    model::{Model, Occlusion},
+    renderer::culling::{cull_faces_for_chunk, greedy_mesh},
+    settings::WorldEdges,
+    texture::MISSING_TEXTURE,
+    types::{BlockRegistry, ChunkPosition, Direction, Shape, World},
+};
+
+/// The models of the block types that aren't cubes, indexed by
+/// `BlockTypeId`. Their faces are traced as their voxels have them.
+pub fn shaped_models(block_registry: &BlockRegistry) -> Vec<Option<Model>> {
+    let fallback = block_registry
+        .texture_registry
+        .get_index_of(MISSING_TEXTURE)
+        .unwrap_or(0);
+    block_registry
+        .block_types
+        .values()
+        .map(|block_type| (block_type.shape != Shape::Cube).then(|| block_type.model(fallback)))
+        .collect()
+}
+
+/// Triangles of the visible faces of the section at `chunk_position`, three
+/// vertices each, relative to the section's origin. Rays only ask whether
+/// they hit anything, so the faces of cubes are merged regardless of their
+/// block type, like `write_glb` merges them by texture.
+pub fn section_triangles(
+    world: &World,
+    chunk_position: ChunkPosition,
+    models: &[Option<Model>],
+    occlusion: &[Occlusion],
+) -> Vec<[f32; 3]> {
+    // Faces toward unloaded sections are left out, rays rarely get there
+    let faces = cull_faces_for_chunk(
+        world,
+        occlusion,
+        &world.chunks[&chunk_position],
+        chunk_position,
+        WorldEdges::Solid,
+    );
+    let (cubes, shaped): (Vec<_>, Vec<_>) = faces
+        .into_iter()
+        .partition(|face| models[face.block_type_id].is_none());
+
+    let mut vertices = Vec::new();
+    for quad in greedy_mesh(&cubes, |_| ()) {
+        let mut corner = quad.position.map(|v| v as f32);
+        if quad.direction.is_positive() {
+            corner[quad.direction.axis()] += 1.0;
+        }
+        push_quad(
+            &mut vertices,
+            quad.direction,
+            corner,
+            quad.size.map(|v| v as f32),
+        );
+    }
+    for face in shaped {
+        let model = models[face.block_type_id].as_ref().unwrap();
+        let (x, y, z) = face.position;
+        let position = [x, y, z].map(|v| v as f32);
+        let axis = face.direction.axis();
+        for voxel in &model.voxels {
+            let mut corner = [0, 1, 2].map(|i| position[i] + voxel.from[i] / 16.0);
+            if face.direction.is_positive() {
+                corner[axis] = position[axis] + voxel.to[axis] / 16.0;
+            }
+            let size = face
+                .direction
+                .plane_axes()
+                .map(|a| (voxel.to[a] - voxel.from[a]) / 16.0);
+            push_quad(&mut vertices, face.direction, corner, size);
+        }
+    }
+    vertices
+}
+
+/// Pushes the two triangles of the rectangle of `size` along the plane axes
+/// of `direction` from `corner`. Rays hit both sides, so the winding doesn't
+/// matter.
+fn push_quad(vertices: &mut Vec<[f32; 3]>, direction: Direction, corner: [f32; 3], size: [f32; 2]) {
+    let [u_axis, v_axis] = direction.plane_axes();
+    let corners = [[0.0, 0.0], [size[0], 0.0], size, [0.0, size[1]]].map(|[du, dv]| {
+        let mut vertex = corner;
+        vertex[u_axis] += du;
+        vertex[v_axis] += dv;
+        vertex
+    });
+    vertices.extend([0, 1, 2, 0, 2, 3].map(|i| corners[i]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section_triangles() {
+        let mut world = World::new(BlockRegistry::default());
+        // A 2x1x1 box, whose faces merge into as many quads as one block's
+        world.fill_cuboid([4, 4, 4], [6, 5, 5], 1);
+        let section = ChunkPosition::of_block([4, 4, 4]);
+        let models = shaped_models(&world.block_registry);
+        let occlusion = world.block_registry.occlusion();
+        let vertices = section_triangles(&world, section, &models, &occlusion);
+        assert_eq!(vertices.len(), 36);
+        let min = vertices
+            .iter()
+            .fold([f32::MAX; 3], |min, v| [0, 1, 2].map(|i| min[i].min(v[i])));
+        let max = vertices
+            .iter()
+            .fold([f32::MIN; 3], |max, v| [0, 1, 2].map(|i| max[i].max(v[i])));
+        assert_eq!(min, [4.0, 4.0, 4.0]);
+        assert_eq!(max, [6.0, 5.0, 5.0]);
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    mem::size_of,
+    sync::{mpsc::Receiver, Arc},
+};
+
+use vulkano::{
+    acceleration_structure::{
+        AccelerationStructure, AccelerationStructureBuildGeometryInfo,
+        AccelerationStructureBuildRangeInfo, AccelerationStructureBuildType,
+        AccelerationStructureCreateInfo, AccelerationStructureGeometries,
+        AccelerationStructureGeometryInstancesData, AccelerationStructureGeometryInstancesDataType,
+        AccelerationStructureGeometryTrianglesData, AccelerationStructureInstance,
+        AccelerationStructureType, BuildAccelerationStructureFlags, BuildAccelerationStructureMode,
+        CopyAccelerationStructureInfo, CopyAccelerationStructureMode, GeometryFlags,
+    },
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::RecordingCommandBuffer,
+    device::Device,
+    format::Format,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    VulkanObject,
+};
+
+use crate::{
+    app::App,
+    events::WorldEvent,
+    memory::{MemoryCategory, MemoryTracker, TrackedAllocation},
+    model::{Model, Occlusion},
+    renderer::{culling::sections_affected_by_block, frames::FRAMES_IN_FLIGHT},
+    types::{BlockRegistry, ChunkPosition, Direction, World},
+};
+
+pub use self::geometry::{section_triangles, shaped_models};
+
+mod geometry;
+
+/// Bottom level structures compacted per update at most, so a burst of
+/// loaded sections is compacted over several frames.
+const MAX_COMPACTIONS_PER_UPDATE: usize = 16;
+
+/// The bottom level structure of a section, relative to its origin.
+struct SectionStructure {
+    structure: Arc<AccelerationStructure>,
+    /// Until the structure is compacted, the query its compacted size is
+    /// written to and the update it was built in.
+    compacted_size: Option<(Arc<QueryPool>, u64)>,
+    _memory: TrackedAllocation,
+}
+
+/// The loaded world as acceleration structures for ray queries, shared by
+/// everything tracing against it: a bottom level structure per section with
+/// visible faces, instanced at the section's origin by a top level
+/// structure. Sections are built again when they load or their blocks
+/// change, and compacted a few frames after they were built, once their
+/// compacted size is known.
+pub struct WorldAccelerationStructure {
+    device: Arc<Device>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    memory_tracker: Arc<MemoryTracker>,
+    models: Vec<Option<Model>>,
+    /// `BlockRegistry::occlusion`, for culling the faces of sections.
+    occlusion: Vec<Occlusion>,
+    sections: HashMap<ChunkPosition, SectionStructure>,
+    /// Block edits and section loads of the world the sections come from.
+    world_events: Receiver<WorldEvent>,
+    top_level: Option<(Arc<AccelerationStructure>, TrackedAllocation)>,
+    /// Per update, the section structures it replaced or dropped. The top
+    /// level structures of frames in flight may still reference them by
+    /// address, which doesn't keep them alive.
+    retired: VecDeque<Vec<SectionStructure>>,
+    /// Number of `update` calls.
+    updates: u64,
+}
+
+impl WorldAccelerationStructure {
+    pub fn new(
+        app: &App,
+        block_registry: &BlockRegistry,
+        world_events: Receiver<WorldEvent>,
+    ) -> Self {
+        Self {
+            device: app.context.device().clone(),
+            memory_allocator: app.memory_allocator(),
+            memory_tracker: app.memory_tracker.clone(),
+            models: shaped_models(block_registry),
+            occlusion: block_registry.occlusion(),
+            sections: HashMap::new(),
+            world_events,
+            top_level: None,
+            retired: VecDeque::new(),
+            updates: 0,
+        }
+    }
+
+    /// The top level structure, built by the first `update`.
+    pub fn top_level(&self) -> &Arc<AccelerationStructure> {
+        &self.top_level.as_ref().expect("not built yet").0
+    }
+
+    /// Records building the sections that changed since the last call and
+    /// compacting those built long enough ago, then the top level structure
+    /// if any of them did, followed by a barrier for the shaders tracing
+    /// against it in `dst_stage_mask`. Returns whether `top_level` is a new
+    /// structure.
+    pub fn update(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        world: &World,
+        dst_stage_mask: ash::vk::PipelineStageFlags2,
+    ) -> bool {
+        self.updates += 1;
+        let mut changed = self.receive_world_events();
+        if self.top_level.is_none() {
+            // Sections loaded before the events were subscribed to
+            changed.extend(world.chunks.keys().copied());
+        }
+
+        let mut retired = self.compact(builder);
+        let compacted = !retired.is_empty();
+        let mut built = Vec::new();
+        for &chunk_position in &changed {
+            retired.extend(self.sections.remove(&chunk_position));
+            if !world.chunks.contains_key(&chunk_position) {
+                continue;
+            }
+            let vertices = section_triangles(world, chunk_position, &self.models, &self.occlusion);
+            if !vertices.is_empty() {
+                let section = self.build_section(builder, vertices);
+                let (query_pool, _) = section.compacted_size.as_ref().unwrap();
+                built.push((query_pool.clone(), section.structure.clone()));
+                self.sections.insert(chunk_position, section);
+            }
+        }
+        self.retired.push_back(retired);
+        if self.retired.len() > FRAMES_IN_FLIGHT {
+            self.retired.pop_front();
+        }
+        if changed.is_empty() && !compacted && self.top_level.is_some() {
+            return false;
+        }
+
+        wait_for_builds(
+            &self.device,
+            builder,
+            ash::vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR,
+        );
+        self.query_compacted_sizes(builder, built);
+        self.top_level = Some(self.build_top_level(builder));
+        wait_for_builds(&self.device, builder, dst_stage_mask);
+        true
+    }
+
+    /// Handles the world events received since the last call, returning the
+    /// sections to build again.
+    fn receive_world_events(&mut self) -> HashSet<ChunkPosition> {
+        let mut changed = HashSet::new();
+        for event in self.world_events.try_iter() {
+            match event {
+                WorldEvent::BlockChanged { position, .. } => {
+                    changed.extend(sections_affected_by_block(position));
+                }
+                // Faces on the borders of the neighbors may be hidden now
+                WorldEvent::ChunkLoaded(chunk_position) => {
+                    changed.insert(chunk_position);
+                    changed
+                        .extend(Direction::ALL.map(|direction| chunk_position.offset(direction)));
+                }
+                WorldEvent::ChunkUnloaded(chunk_position) => {
+                    changed.insert(chunk_position);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Records copying the sections whose compacted size is known into
+    /// structures of that size, returning the ones they replace. Only
+    /// sections built `FRAMES_IN_FLIGHT` updates ago are asked, so the
+    /// frame writing the size has finished.
+    fn compact(&mut self, builder: &mut RecordingCommandBuffer) -> Vec<SectionStructure> {
+        let ready = self
+            .sections
+            .iter()
+            .filter_map(|(&chunk_position, section)| {
+                let (query_pool, built) = section.compacted_size.as_ref()?;
+                (self.updates - built > FRAMES_IN_FLIGHT as u64)
+                    .then_some((chunk_position, query_pool.clone()))
+            })
+            .take(MAX_COMPACTIONS_PER_UPDATE)
+            .collect::<Vec<_>>();
+
+        let mut retired = Vec::new();
+        for (chunk_position, query_pool) in ready {
+            let mut size = [0u64];
+            query_pool
+                .get_results(0..1, &mut size, QueryResultFlags::WAIT)
+                .unwrap();
+            let (structure, memory) =
+                self.create_structure(AccelerationStructureType::BottomLevel, size[0]);
+            let section = self.sections.get_mut(&chunk_position).unwrap();
+            unsafe {
+                builder
+                    .copy_acceleration_structure(CopyAccelerationStructureInfo {
+                        mode: CopyAccelerationStructureMode::Compact,
+                        ..CopyAccelerationStructureInfo::new(
+                            section.structure.clone(),
+                            structure.clone(),
+                        )
+                    })
+                    .unwrap()
+            };
+            retired.push(std::mem::replace(
+                section,
+                SectionStructure {
+                    structure,
+                    compacted_size: None,
+                    _memory: memory,
+                },
+            ));
+        }
+        retired
+    }
+
+    /// Records resetting the queries of the structures just built and writing
+    /// their compacted size into them.
+    fn query_compacted_sizes(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        built: Vec<(Arc<QueryPool>, Arc<AccelerationStructure>)>,
+    ) {
+        for (query_pool, structure) in built {
+            unsafe {
+                builder.reset_query_pool(query_pool.clone(), 0..1).unwrap();
+                builder
+                    .write_acceleration_structures_properties(
+                        [structure].into_iter().collect(),
+                        query_pool,
+                        0,
+                    )
+                    .unwrap();
+            }
+        }
+    }
+
+    fn build_section(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        vertices: Vec<[f32; 3]>,
+    ) -> SectionStructure {
+        let primitive_count = vertices.len() as u32 / 3;
+        let triangles = AccelerationStructureGeometryTrianglesData {
+            flags: GeometryFlags::OPAQUE,
+            max_vertex: primitive_count * 3 - 1,
+            vertex_stride: size_of::<[f32; 3]>() as u32,
+            vertex_data: Some(self.input_buffer(vertices).into_bytes()),
+            ..AccelerationStructureGeometryTrianglesData::new(Format::R32G32B32_SFLOAT)
+        };
+        let (structure, memory) = self.build(
+            builder,
+            AccelerationStructureType::BottomLevel,
+            AccelerationStructureGeometries::Triangles(vec![triangles]),
+            primitive_count,
+        );
+        let query_pool = QueryPool::new(
+            self.device.clone(),
+            QueryPoolCreateInfo {
+                query_count: 1,
+                ..QueryPoolCreateInfo::query_type(QueryType::AccelerationStructureCompactedSize)
+            },
+        )
+        .unwrap();
+        SectionStructure {
+            structure,
+            compacted_size: Some((query_pool, self.updates)),
+            _memory: memory,
+        }
+    }
+
+    fn build_top_level(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+    ) -> (Arc<AccelerationStructure>, TrackedAllocation) {
+        let mut instances = self
+            .sections
+            .iter()
+            .map(|(chunk_position, section)| {
+                let [x, y, z] = chunk_position.origin().map(|v| v as f32);
+                AccelerationStructureInstance {
+                    transform: [[1.0, 0.0, 0.0, x], [0.0, 1.0, 0.0, y], [0.0, 0.0, 1.0, z]],
+                    acceleration_structure_reference: section.structure.device_address().get(),
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<_>>();
+        if instances.is_empty() {
+            // Instances without a structure are never hit
+            instances.push(AccelerationStructureInstance::default());
+        }
+        let instance_count = instances.len() as u32;
+        let instances = AccelerationStructureGeometryInstancesData::new(
+            AccelerationStructureGeometryInstancesDataType::Values(Some(
+                self.input_buffer(instances),
+            )),
+        );
+        self.build(
+            builder,
+            AccelerationStructureType::TopLevel,
+            AccelerationStructureGeometries::Instances(instances),
+            instance_count,
+        )
+    }
+
+    /// A buffer of `data` the builds read. Like the scratch space, it only
+    /// lives until the build ran, so it isn't tracked.
+    fn input_buffer<T: vulkano::buffer::BufferContents>(&self, data: Vec<T>) -> Subbuffer<[T]> {
+        Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY
+                    | BufferUsage::SHADER_DEVICE_ADDRESS,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            data,
+        )
+        .unwrap()
+    }
+
+    fn device_buffer(&self, size: u64, usage: BufferUsage) -> Subbuffer<[u8]> {
+        Buffer::new_slice::<u8>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: usage | BufferUsage::SHADER_DEVICE_ADDRESS,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+            size,
+        )
+        .unwrap()
+    }
+
+    /// A structure of `ty` in `size` bytes of its own memory.
+    fn create_structure(
+        &self,
+        ty: AccelerationStructureType,
+        size: u64,
+    ) -> (Arc<AccelerationStructure>, TrackedAllocation) {
+        let storage = self.device_buffer(size, BufferUsage::ACCELERATION_STRUCTURE_STORAGE);
+        let memory = self
+            .memory_tracker
+            .track_buffer(MemoryCategory::AccelerationStructures, &storage);
+        let structure = unsafe {
+            AccelerationStructure::new(
+                self.device.clone(),
+                AccelerationStructureCreateInfo {
+                    ty,
+                    ..AccelerationStructureCreateInfo::new(storage)
+                },
+            )
+        }
+        .unwrap();
+        (structure, memory)
+    }
+
+    /// Creates a structure of `ty` large enough for `geometries` and records
+    /// building it. Bottom level structures may be compacted afterwards.
+    fn build(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        ty: AccelerationStructureType,
+        geometries: AccelerationStructureGeometries,
+        primitive_count: u32,
+    ) -> (Arc<AccelerationStructure>, TrackedAllocation) {
+        let mut flags = BuildAccelerationStructureFlags::PREFER_FAST_TRACE;
+        if ty == AccelerationStructureType::BottomLevel {
+            flags |= BuildAccelerationStructureFlags::ALLOW_COMPACTION;
+        }
+        let mut build_info = AccelerationStructureBuildGeometryInfo {
+            mode: BuildAccelerationStructureMode::Build,
+            flags,
+            ..AccelerationStructureBuildGeometryInfo::new(geometries)
+        };
+        let sizes = self
+            .device
+            .acceleration_structure_build_sizes(
+                AccelerationStructureBuildType::Device,
+                &build_info,
+                &[primitive_count],
+            )
+            .unwrap();
+
+        let (structure, memory) = self.create_structure(ty, sizes.acceleration_structure_size);
+        build_info.dst_acceleration_structure = Some(structure.clone());
+        build_info.scratch_data =
+            Some(self.device_buffer(sizes.build_scratch_size, BufferUsage::STORAGE_BUFFER));
+        unsafe {
+            builder
+                .build_acceleration_structure(
+                    build_info,
+                    [AccelerationStructureBuildRangeInfo {
+                        primitive_count,
+                        ..Default::default()
+                    }]
+                    .into_iter()
+                    .collect(),
+                )
+                .unwrap()
+        };
+        (structure, memory)
+    }
+}
+
+/// Records a barrier making `dst_stage_mask` wait for the builds and copies
+/// recorded before it to read the structures they wrote. The command buffer
+/// doesn't synchronize acceleration structures itself.
+fn wait_for_builds(
+    device: &Device,
+    builder: &mut RecordingCommandBuffer,
+    dst_stage_mask: ash::vk::PipelineStageFlags2,
+) {
+    let memory_barrier = ash::vk::MemoryBarrier2 {
+        src_stage_mask: ash::vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR,
+        src_access_mask: ash::vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+        dst_stage_mask,
+        dst_access_mask: ash::vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR,
+        ..Default::default()
+    };
+    let memory_barriers = [memory_barrier];
+    let dependency_info = ash::vk::DependencyInfo::default().memory_barriers(&memory_barriers);
+    let fns = device.fns();
+    unsafe {
+        (fns.v1_3.cmd_pipeline_barrier2)(builder.raw().handle(), &dependency_info);
+    }
+}
@@ -0,0 +1,131 @@
+#version 460
+#extension GL_EXT_ray_query : require
+
+// Darkens the frame where the blocks around a pixel hide it from the sky.
+// A few short rays are traced per pixel from the position and normal the
+// depth buffer gives, and the fraction of them that hit is blended into the
+// reprojected occlusion of the previous frames, so few rays are enough.
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+layout(set = 0, binding = 2) uniform sampler2D motion_vectors;
+layout(set = 0, binding = 3) uniform sampler2D history;
+layout(set = 0, binding = 4, r16f) uniform writeonly image2D
+    history_destination;
+layout(set = 0, binding = 5) uniform writeonly image2D destination;
+layout(set = 1, binding = 0) uniform accelerationStructureEXT world;
+
+layout(push_constant) uniform PushConstants {
+  mat4 inverse_view_proj;     // without jitter
+  vec4 camera_position;
+  float far_depth;            // depth where nothing was drawn
+  float motion_vector_scale;  // from motion vectors to UV units
+  uint history_valid;         // 0 when there is no history yet
+  uint frame;                 // seeds the ray directions
+}
+pc;
+
+const uint RAYS = 2;
+// Length of the rays, in blocks
+const float RADIUS = 1.5;
+// Weight of the current frame in the blend with the history
+const float BLEND = 0.1;
+
+vec3 world_position(ivec2 pixel, ivec2 size) {
+  vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+  float d = texelFetch(depth, pixel, 0).r;
+  vec4 position = pc.inverse_view_proj * vec4(uv * 2.0 - 1.0, d, 1.0);
+  return position.xyz / position.w;
+}
+
+// Of the two neighbors along an axis, the one nearer to `center`, so
+// normals don't bend across edges
+vec3 nearer(vec3 center, vec3 a, vec3 b) {
+  return distance(a, center) < distance(b, center) ? center - a : b - center;
+}
+
+uint hash(uint x) {
+  x ^= x >> 16;
+  x *= 0x7feb352du;
+  x ^= x >> 15;
+  x *= 0x846ca68bu;
+  x ^= x >> 16;
+  return x;
+}
+
+float random(inout uint state) {
+  state = hash(state);
+  return float(state) / 4294967296.0;
+}
+
+// A direction around `normal`, more likely the nearer it is to it
+vec3 cosine_direction(vec3 normal, inout uint state) {
+  float phi = 6.28318530718 * random(state);
+  float r = sqrt(random(state));
+  vec3 axis = abs(normal.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+  vec3 tangent = normalize(cross(normal, axis));
+  vec3 bitangent = cross(normal, tangent);
+  return normalize(tangent * (r * cos(phi)) + bitangent * (r * sin(phi)) +
+                   normal * sqrt(1.0 - r * r));
+}
+
+void main() {
+  ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  ivec2 size = imageSize(destination);
+  if (any(greaterThanEqual(pixel, size))) {
+    return;
+  }
+
+  vec4 current = texelFetch(color, pixel, 0);
+  if (texelFetch(depth, pixel, 0).r == pc.far_depth) {
+    imageStore(history_destination, pixel, vec4(1.0));
+    imageStore(destination, pixel, current);
+    return;
+  }
+
+  vec3 position = world_position(pixel, size);
+  vec3 left = world_position(max(pixel - ivec2(1, 0), 0), size);
+  vec3 right = world_position(min(pixel + ivec2(1, 0), size - 1), size);
+  vec3 down = world_position(max(pixel - ivec2(0, 1), 0), size);
+  vec3 up = world_position(min(pixel + ivec2(0, 1), size - 1), size);
+  vec3 normal = normalize(cross(nearer(position, left, right),
+                                nearer(position, down, up)));
+  // Toward the camera, whichever way up the image is
+  if (dot(normal, pc.camera_position.xyz - position) < 0.0) {
+    normal = -normal;
+  }
+
+  uint state = hash(uint(pixel.x) + hash(uint(pixel.y) + hash(pc.frame)));
+  uint hits = 0;
+  for (uint i = 0; i < RAYS; ++i) {
+    rayQueryEXT ray_query;
+    rayQueryInitializeEXT(
+        ray_query, world,
+        gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT, 0xff,
+        position + normal * 0.001, 0.0, cosine_direction(normal, state),
+        RADIUS);
+    while (rayQueryProceedEXT(ray_query)) {
+    }
+    if (rayQueryGetIntersectionTypeEXT(ray_query, true) !=
+        gl_RayQueryCommittedIntersectionNoneEXT) {
+      ++hits;
+    }
+  }
+  float occlusion = 1.0 - float(hits) / float(RAYS);
+
+  // Motion vectors point to the previous frame
+  vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+  vec2 previous_uv =
+      uv + texelFetch(motion_vectors, pixel, 0).xy * pc.motion_vector_scale;
+  bool on_screen = all(greaterThanEqual(previous_uv, vec2(0.0))) &&
+                   all(lessThanEqual(previous_uv, vec2(1.0)));
+  if (pc.history_valid != 0 && on_screen) {
+    occlusion = mix(texture(history, previous_uv).r, occlusion, BLEND);
+  }
+
+  imageStore(history_destination, pixel, vec4(occlusion));
+  // Alpha is kept for the reflections
+  imageStore(destination, pixel, vec4(current.rgb * occlusion, current.a));
+}
@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use cgmath::SquareMatrix;
+use vulkano::{
+    acceleration_structure::AccelerationStructure,
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::AllocationCreateInfo,
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    app::App,
+    camera::DepthMode,
+    memory::{MemoryCategory, TrackedAllocation},
+};
+
+use super::{render_faces::Camera, MOTION_VECTOR_UV_SCALE};
+
+mod cs {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/ambient_occlusion/ambient_occlusion.comp.glsl",
+        vulkan_version: "1.3"
+    );
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Ray traced ambient occlusion: short rays from every pixel against the
+/// world's acceleration structure, accumulated over frames like TAA
+/// accumulates color. Darkens the rendered frame into images of its own,
+/// before reflections and anti-aliasing.
+pub struct AmbientOcclusionPass {
+    pipeline: Arc<ComputePipeline>,
+    outputs: Vec<Arc<ImageView>>,
+    /// Per frame, the sets writing the occlusion into `history[0]` and
+    /// `history[1]`, each reading the other one.
+    sets: Vec<[Arc<DescriptorSet>; 2]>,
+    /// The set of the acceleration structure, once there is one.
+    world_set: Option<Arc<DescriptorSet>>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    depth_mode: DepthMode,
+    /// The history written last, if any.
+    current: Option<usize>,
+    frame: u32,
+    _memory: Vec<TrackedAllocation>,
+}
+
+impl AmbientOcclusionPass {
+    /// A pass darkening each of `color_images` with the depth and motion
+    /// vectors of the same index into the output of the same index, all
+    /// single-sampled and of the same extent. The outputs must be storage
+    /// images.
+    pub fn new(
+        app: &App,
+        color_images: &[Arc<ImageView>],
+        depth_images: &[Arc<ImageView>],
+        motion_vector_images: &[Arc<ImageView>],
+        outputs: Vec<Arc<ImageView>>,
+        depth_mode: DepthMode,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let cs = cs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .unwrap()
+        };
+
+        let extent = outputs[0].image().extent();
+        let mut memory = Vec::new();
+        let history = [(); 2].map(|_| {
+            let image = Image::new(
+                app.memory_allocator(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    extent,
+                    format: Format::R16_SFLOAT,
+                    usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .unwrap();
+            memory.push(
+                app.memory_tracker
+                    .track_image(MemoryCategory::RenderTargets, &image),
+            );
+            ImageView::new_default(image).unwrap()
+        });
+
+        // The history is reprojected between pixels
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let set_layout = pipeline.layout().set_layouts()[0].clone();
+        let sets = (0..outputs.len())
+            .map(|frame| {
+                assert_eq!(color_images[frame].image().extent(), extent);
+                [0, 1].map(|destination| {
+                    DescriptorSet::new(
+                        app.descriptor_set_allocator.clone(),
+                        set_layout.clone(),
+                        [
+                            WriteDescriptorSet::image_view_sampler(
+                                0,
+                                color_images[frame].clone(),
+                                sampler.clone(),
+                            ),
+                            WriteDescriptorSet::image_view_sampler(
+                                1,
+                                depth_images[frame].clone(),
+                                sampler.clone(),
+                            ),
+                            WriteDescriptorSet::image_view_sampler(
+                                2,
+                                motion_vector_images[frame].clone(),
+                                sampler.clone(),
+                            ),
+                            WriteDescriptorSet::image_view_sampler(
+                                3,
+                                history[1 - destination].clone(),
+                                sampler.clone(),
+                            ),
+                            WriteDescriptorSet::image_view(4, history[destination].clone()),
+                            WriteDescriptorSet::image_view(5, outputs[frame].clone()),
+                        ],
+                        None,
+                    )
+                    .unwrap()
+                })
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            outputs,
+            sets,
+            world_set: None,
+            descriptor_set_allocator: app.descriptor_set_allocator.clone(),
+            depth_mode,
+            current: None,
+            frame: 0,
+            _memory: memory,
+        }
+    }
+
+    /// The images `apply` writes to, one per frame.
+    pub fn outputs(&self) -> &[Arc<ImageView>] {
+        &self.outputs
+    }
+
+    /// Traces against `top_level` from now on. Has to be called before
+    /// `apply`, and again whenever the structure is replaced.
+    pub fn set_acceleration_structure(&mut self, top_level: Arc<AccelerationStructure>) {
+        self.world_set = Some(
+            DescriptorSet::new(
+                self.descriptor_set_allocator.clone(),
+                self.pipeline.layout().set_layouts()[1].clone(),
+                [WriteDescriptorSet::acceleration_structure(0, top_level)],
+                None,
+            )
+            .unwrap(),
+        );
+    }
+
+    /// Records darkening the frame `frame` was rendered into as seen by
+    /// `camera`, returning the image the result is written to.
+    pub fn apply(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        frame: usize,
+        camera: &Camera,
+    ) -> Arc<ImageView> {
+        let destination = self.current.map_or(0, |current| 1 - current);
+        let output = &self.outputs[frame];
+        let [width, height, _] = output.image().extent();
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                vec![
+                    self.sets[frame][destination].clone(),
+                    self.world_set.clone().expect("no acceleration structure"),
+                ],
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    inverse_view_proj: (camera.proj * camera.view).invert().unwrap().into(),
+                    camera_position: camera.position.to_homogeneous().into(),
+                    far_depth: self.depth_mode.far_depth(),
+                    motion_vector_scale: MOTION_VECTOR_UV_SCALE,
+                    history_valid: self.current.is_some() as u32,
+                    frame: self.frame,
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder
+                .dispatch([
+                    width.div_ceil(WORKGROUP_SIZE),
+                    height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                ])
+                .unwrap()
+        };
+        self.current = Some(destination);
+        self.frame = self.frame.wrapping_add(1);
+        output.clone()
+    }
+}
@@ -0,0 +1,162 @@
+use cgmath::Point3;
+
+use crate::types::{Chunk, ChunkPosition, CHUNK_SIZE};
+
+/// The smallest box around the blocks of a section that aren't air, as the
+/// first and last block positions inside the section it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionBounds {
+    pub min: [u8; 3],
+    pub max: [u8; 3],
+}
+
+impl SectionBounds {
+    /// The box of a single block.
+    pub fn block(position: [u8; 3]) -> Self {
+        Self {
+            min: position,
+            max: position,
+        }
+    }
+
+    /// The bounds of the blocks of `chunk`, none if it is all air.
+    pub fn of_chunk(chunk: &Chunk) -> Option<Self> {
+        let mut bounds: Option<Self> = None;
+        for (y, xz_plane) in chunk.blocks.iter().enumerate() {
+            for (x, z_column) in xz_plane.iter().enumerate() {
+                for (z, &block_type_id) in z_column.iter().enumerate() {
+                    if block_type_id != 0 {
+                        let position = [x as u8, y as u8, z as u8];
+                        bounds = Some(match bounds {
+                            Some(bounds) => bounds.including(position),
+                            None => Self::block(position),
+                        });
+                    }
+                }
+            }
+        }
+        bounds
+    }
+
+    /// These bounds grown to cover the block at `position`.
+    pub fn including(self, position: [u8; 3]) -> Self {
+        Self {
+            min: [0, 1, 2].map(|i| self.min[i].min(position[i])),
+            max: [0, 1, 2].map(|i| self.max[i].max(position[i])),
+        }
+    }
+
+    /// Whether the block at `position` lies on a face of the box, so the
+    /// box may shrink when it is removed.
+    pub fn on_boundary(&self, position: [u8; 3]) -> bool {
+        (0..3).any(|i| position[i] == self.min[i] || position[i] == self.max[i])
+    }
+
+    /// The bounds after the block at `position` of `chunk` changed, without
+    /// scanning the section unless a block on the boundary was removed.
+    /// `chunk` already holds the change.
+    pub fn after_change(bounds: Option<Self>, chunk: &Chunk, position: [u8; 3]) -> Option<Self> {
+        let [x, y, z] = position.map(|v| v as usize);
+        match bounds {
+            _ if chunk.blocks[y][x][z] != 0 => Some(match bounds {
+                Some(bounds) => bounds.including(position),
+                None => Self::block(position),
+            }),
+            Some(bounds) if bounds.on_boundary(position) => Self::of_chunk(chunk),
+            bounds => bounds,
+        }
+    }
+
+    /// The box in world space, from the corner of the first block to the
+    /// far corner of the last one.
+    pub fn world_aabb(&self, chunk_position: ChunkPosition) -> (Point3<f32>, Point3<f32>) {
+        let origin = [chunk_position.x, chunk_position.y, chunk_position.z]
+            .map(|v| (v * CHUNK_SIZE as i32) as f32);
+        let [min_x, min_y, min_z] = [0, 1, 2].map(|i| origin[i] + self.min[i] as f32);
+        let [max_x, max_y, max_z] = [0, 1, 2].map(|i| origin[i] + self.max[i] as f32 + 1.0);
+        (
+            Point3::new(min_x, min_y, min_z),
+            Point3::new(max_x, max_y, max_z),
+        )
+    }
+
+    /// Packs the bounds into the 4 bits per coordinate the cull shader
+    /// reads: `min` in the low 12 bits, `max` in the 12 above.
+    pub fn pack(&self) -> u32 {
+        let [x, y, z] = self.min.map(u32::from);
+        let [mx, my, mz] = self.max.map(u32::from);
+        x | y << 4 | z << 8 | mx << 12 | my << 16 | mz << 20
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STONE: usize = 1;
+
+    fn set(chunk: &mut Chunk, [x, y, z]: [u8; 3], block_type_id: usize) {
+        chunk.blocks[y as usize][x as usize][z as usize] = block_type_id;
+    }
+
+    #[test]
+    fn test_of_chunk() {
+        let mut chunk = Chunk::default();
+        assert_eq!(SectionBounds::of_chunk(&chunk), None);
+
+        set(&mut chunk, [3, 4, 5], STONE);
+        assert_eq!(
+            SectionBounds::of_chunk(&chunk),
+            Some(SectionBounds::block([3, 4, 5]))
+        );
+        set(&mut chunk, [10, 0, 15], STONE);
+        assert_eq!(
+            SectionBounds::of_chunk(&chunk),
+            Some(SectionBounds {
+                min: [3, 0, 5],
+                max: [10, 4, 15],
+            })
+        );
+    }
+
+    #[test]
+    fn test_after_change() {
+        let mut chunk = Chunk::default();
+        let mut bounds = None;
+        for position in [[2, 2, 2], [8, 8, 8], [5, 5, 5]] {
+            set(&mut chunk, position, STONE);
+            bounds = SectionBounds::after_change(bounds, &chunk, position);
+        }
+        let full = SectionBounds {
+            min: [2, 2, 2],
+            max: [8, 8, 8],
+        };
+        assert_eq!(bounds, Some(full));
+
+        // Removing a block inside keeps the box
+        set(&mut chunk, [5, 5, 5], 0);
+        bounds = SectionBounds::after_change(bounds, &chunk, [5, 5, 5]);
+        assert_eq!(bounds, Some(full));
+
+        // Removing one on the boundary shrinks it to what is left
+        set(&mut chunk, [8, 8, 8], 0);
+        bounds = SectionBounds::after_change(bounds, &chunk, [8, 8, 8]);
+        assert_eq!(bounds, Some(SectionBounds::block([2, 2, 2])));
+        set(&mut chunk, [2, 2, 2], 0);
+        bounds = SectionBounds::after_change(bounds, &chunk, [2, 2, 2]);
+        assert_eq!(bounds, None);
+        assert_eq!(bounds, SectionBounds::of_chunk(&chunk));
+    }
+
+    #[test]
+    fn test_world_aabb_and_pack() {
+        let bounds = SectionBounds {
+            min: [0, 1, 2],
+            max: [15, 14, 13],
+        };
+        let (min, max) = bounds.world_aabb(ChunkPosition { x: -1, y: 4, z: 2 });
+        assert_eq!(min, Point3::new(-16.0, 65.0, 34.0));
+        assert_eq!(max, Point3::new(0.0, 79.0, 46.0));
+        assert_eq!(bounds.pack(), 0xde_f210);
+    }
+}
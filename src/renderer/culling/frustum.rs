@@ -0,0 +1,228 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Point3, Vector3, Vector4};
+
+use crate::renderer::render_faces::Camera;
+
+/// Normals shorter than this are of planes at infinity, like the far plane
+/// of `DepthMode::InfiniteReversed`, which cull nothing.
+const MIN_NORMAL_LENGTH: f32 = 1e-6;
+
+/// A plane in world space. Points on the side `normal` points to are in
+/// front of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    /// Of unit length.
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// Normalizes the plane `v.xyz · p + v.w = 0`, none if it is at
+    /// infinity.
+    fn from_vector(v: Vector4<f32>) -> Option<Self> {
+        let length = v.truncate().magnitude();
+        (length >= MIN_NORMAL_LENGTH).then(|| Self {
+            normal: v.truncate() / length,
+            distance: v.w / length,
+        })
+    }
+
+    /// Distance of `point` in front of the plane, negative behind it.
+    pub fn signed_distance(&self, point: Point3<f32>) -> f32 {
+        self.normal.dot(Vector3::new(point.x, point.y, point.z)) + self.distance
+    }
+}
+
+/// The volume a camera sees, as the planes bounding it with their normals
+/// pointing in. Shared by CPU culling and the cull shader, which tests the
+/// same clip volume: Vulkan's, with `0 <= z <= w`.
+///
+/// The tests are conservative: a box or sphere near a corner of the
+/// frustum but outside of it may still be taken to intersect it, as only
+/// one plane at a time is tested.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far, without the planes at
+    /// infinity.
+    planes: Vec<Plane>,
+}
+
+impl Frustum {
+    /// The frustum of the clip volume of `view_proj`, with the planes
+    /// extracted from its rows. With reversed infinite depth, the far plane
+    /// is at infinity and left out.
+    pub fn from_view_proj(view_proj: Matrix4<f32>) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z]
+            .into_iter()
+            .filter_map(Plane::from_vector)
+            .collect();
+        Self { planes }
+    }
+
+    /// The frustum `camera` renders, moved by its jitter like the image is,
+    /// so nothing drawn at the edges is culled.
+    pub fn from_camera(camera: &Camera) -> Self {
+        // As in the shaders, the jitter is a translation in NDC, so clip
+        // space moves by it times w
+        let mut jitter = Matrix4::from_scale(1.0);
+        jitter.w.x = camera.jitter.x;
+        jitter.w.y = camera.jitter.y;
+        Self::from_view_proj(jitter * camera.proj * camera.view)
+    }
+
+    pub fn planes(&self) -> &[Plane] {
+        &self.planes
+    }
+
+    pub fn contains_point(&self, point: Point3<f32>) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Whether the sphere around `center` may intersect the frustum.
+    pub fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(center) >= -radius)
+    }
+
+    /// Whether the axis-aligned box from `min` to `max` may intersect the
+    /// frustum: for every plane, the corner furthest in front of it has to
+    /// be in front.
+    pub fn intersects_aabb(&self, min: Point3<f32>, max: Point3<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            let corner = Point3::new(
+                if plane.normal.x >= 0.0 { max.x } else { min.x },
+                if plane.normal.y >= 0.0 { max.y } else { min.y },
+                if plane.normal.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{perspective, vec2, Deg, Rad};
+
+    use super::*;
+
+    const NEAR: f32 = 0.1;
+    const FAR: f32 = 100.0;
+
+    /// A camera at `position` looking down -z, with a square image.
+    fn camera(position: Point3<f32>, reversed: bool) -> Camera {
+        let fovy = Deg(90.0);
+        let proj = if reversed {
+            let f = 1.0 / (Rad::from(fovy).0 / 2.0).tan();
+            #[rustfmt::skip]
+            let proj = Matrix4::new(
+                f, 0.0, 0.0, 0.0,
+                0.0, f, 0.0, 0.0,
+                0.0, 0.0, 0.0, -1.0,
+                0.0, 0.0, NEAR, 0.0,
+            );
+            proj
+        } else {
+            perspective(fovy, 1.0, NEAR, FAR)
+        };
+        Camera {
+            view: Matrix4::look_to_rh(position, -Vector3::unit_z(), Vector3::unit_y()),
+            proj,
+            position,
+            near: NEAR,
+            far: if reversed { f32::INFINITY } else { FAR },
+            fovy,
+            jitter: vec2(0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn test_standard_depth() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let frustum = Frustum::from_camera(&camera(origin, false));
+        assert_eq!(frustum.planes().len(), 6);
+        for plane in frustum.planes() {
+            assert!((plane.normal.magnitude() - 1.0).abs() < 1e-5);
+        }
+
+        assert!(frustum.contains_point(Point3::new(0.0, 0.0, -10.0)));
+        // With a 90° field of view, the sides are at 45°
+        assert!(frustum.contains_point(Point3::new(9.0, -9.0, -10.0)));
+        assert!(!frustum.contains_point(Point3::new(11.0, 0.0, -10.0)));
+        assert!(!frustum.contains_point(Point3::new(0.0, 11.0, -10.0)));
+        // Behind the camera and past the far plane
+        assert!(!frustum.contains_point(Point3::new(0.0, 0.0, 10.0)));
+        assert!(!frustum.contains_point(Point3::new(0.0, 0.0, -FAR - 1.0)));
+    }
+
+    #[test]
+    fn test_reversed_depth() {
+        let frustum = Frustum::from_camera(&camera(Point3::new(0.0, 0.0, 0.0), true));
+        // The far plane is at infinity
+        assert_eq!(frustum.planes().len(), 5);
+        assert!(frustum.contains_point(Point3::new(0.0, 0.0, -1e6)));
+        assert!(!frustum.contains_point(Point3::new(0.0, 0.0, -NEAR / 2.0)));
+        assert!(!frustum.contains_point(Point3::new(0.0, 0.0, 1.0)));
+        assert!(!frustum.contains_point(Point3::new(2e6, 0.0, -1e6)));
+    }
+
+    #[test]
+    fn test_moved_camera() {
+        let frustum = Frustum::from_camera(&camera(Point3::new(100.0, 64.0, -50.0), false));
+        assert!(frustum.contains_point(Point3::new(100.0, 64.0, -60.0)));
+        assert!(!frustum.contains_point(Point3::new(0.0, 0.0, -10.0)));
+    }
+
+    #[test]
+    fn test_aabb() {
+        let frustum = Frustum::from_camera(&camera(Point3::new(0.0, 0.0, 0.0), false));
+        let aabb = |min: [f32; 3], max: [f32; 3]| {
+            frustum.intersects_aabb(Point3::from(min), Point3::from(max))
+        };
+        assert!(aabb([-1.0, -1.0, -11.0], [1.0, 1.0, -9.0]));
+        // Straddling a side, or around the camera
+        assert!(aabb([9.0, 0.0, -11.0], [12.0, 1.0, -9.0]));
+        assert!(aabb([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]));
+        // Bigger than the frustum
+        assert!(aabb([-1e4, -1e4, -1e4], [1e4, 1e4, 1e4]));
+        // Beside, behind and past the far plane
+        assert!(!aabb([12.0, 0.0, -11.0], [13.0, 1.0, -9.0]));
+        assert!(!aabb([-1.0, -1.0, 1.0], [1.0, 1.0, 2.0]));
+        assert!(!aabb([-1.0, -1.0, -FAR - 2.0], [1.0, 1.0, -FAR - 1.0]));
+        // Past the far right edge, but in front of the right side and the
+        // far plane each on their own
+        assert!(aabb([101.0, -1.0, -200.0], [200.0, 1.0, -99.0]));
+        // A box of zero size is a point
+        assert!(aabb([0.0, 0.0, -10.0], [0.0, 0.0, -10.0]));
+    }
+
+    #[test]
+    fn test_sphere() {
+        let frustum = Frustum::from_camera(&camera(Point3::new(0.0, 0.0, 0.0), false));
+        assert!(frustum.intersects_sphere(Point3::new(0.0, 0.0, -10.0), 1.0));
+        // The right side is at 45°, so the center is 2 / √2 from it
+        let center = Point3::new(12.0, 0.0, -10.0);
+        assert!(!frustum.intersects_sphere(center, 1.0));
+        assert!(frustum.intersects_sphere(center, 1.5));
+        assert!(!frustum.intersects_sphere(Point3::new(0.0, 0.0, 5.0), 4.0));
+        assert!(frustum.intersects_sphere(Point3::new(0.0, 0.0, 5.0), 6.0));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let mut camera = camera(Point3::new(0.0, 0.0, 0.0), false);
+        // Just past the left and right edges
+        let left = Point3::new(-10.2, 0.0, -10.0);
+        let right = Point3::new(10.2, 0.0, -10.0);
+        assert!(!Frustum::from_camera(&camera).contains_point(left));
+        assert!(!Frustum::from_camera(&camera).contains_point(right));
+
+        // The image moves right, bringing in what is past the left edge
+        camera.jitter = vec2(0.05, 0.0);
+        let frustum = Frustum::from_camera(&camera);
+        assert!(frustum.contains_point(left));
+        assert!(!frustum.contains_point(right));
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use crate::types::{Direction, CHUNK_SIZE};
+
+use super::VisibleFace;
+
+/// A rectangle of visible faces of one section facing the same direction
+/// that share a key, like their block type and tint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreedyQuad<K> {
+    pub direction: Direction,
+    /// Position inside the section of the block the quad starts at.
+    pub position: [u32; 3],
+    /// Extent in blocks along the two axes of `Direction::plane_axes`.
+    pub size: [u32; 2],
+    pub key: K,
+}
+
+/// Merges the `faces` of a section into as few rectangles as possible, only
+/// merging faces with equal `key`s.
+pub fn greedy_mesh<K: Copy + Eq>(
+    faces: &[VisibleFace],
+    key: impl Fn(&VisibleFace) -> K,
+) -> Vec<GreedyQuad<K>> {
+    // Per direction and layer along it, the faces in the layer's plane
+    let mut layers: HashMap<(Direction, u32), [[Option<K>; CHUNK_SIZE]; CHUNK_SIZE]> =
+        HashMap::new();
+    for face in faces {
+        let (x, y, z) = face.position;
+        let position = [x, y, z];
+        let [u, v] = face.direction.plane_axes();
+        let layer = layers
+            .entry((face.direction, position[face.direction.axis()]))
+            .or_insert([[None; CHUNK_SIZE]; CHUNK_SIZE]);
+        layer[position[v] as usize][position[u] as usize] = Some(key(face));
+    }
+
+    let mut quads = Vec::new();
+    for ((direction, depth), mut mask) in layers {
+        let [u_axis, v_axis] = direction.plane_axes();
+        for v in 0..CHUNK_SIZE {
+            let mut u = 0;
+            while u < CHUNK_SIZE {
+                let Some(key) = mask[v][u] else {
+                    u += 1;
+                    continue;
+                };
+
+                let mut width = 1;
+                while u + width < CHUNK_SIZE && mask[v][u + width] == Some(key) {
+                    width += 1;
+                }
+                let mut height = 1;
+                while v + height < CHUNK_SIZE
+                    && mask[v + height][u..u + width]
+                        .iter()
+                        .all(|&other| other == Some(key))
+                {
+                    height += 1;
+                }
+                for row in &mut mask[v..v + height] {
+                    row[u..u + width].fill(None);
+                }
+
+                let mut position = [0; 3];
+                position[direction.axis()] = depth;
+                position[u_axis] = u as u32;
+                position[v_axis] = v as u32;
+                quads.push(GreedyQuad {
+                    direction,
+                    position,
+                    size: [width as u32, height as u32],
+                    key,
+                });
+                u += width;
+            }
+        }
+    }
+    quads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merges_equal_faces() {
+        // A 4x2 floor, half stone and half dirt
+        let faces = (0..4)
+            .flat_map(|x| (0..2).map(move |z| (x, z)))
+            .map(|(x, z)| VisibleFace {
+                position: (x, 5, z),
+                direction: Direction::Up,
+                block_type_id: if x < 2 { 1 } else { 3 },
+            })
+            .collect::<Vec<_>>();
+
+        let mut quads = greedy_mesh(&faces, |face| face.block_type_id);
+        quads.sort_by_key(|quad| quad.key);
+        assert_eq!(
+            quads,
+            [
+                GreedyQuad {
+                    direction: Direction::Up,
+                    position: [0, 5, 0],
+                    size: [2, 2],
+                    key: 1,
+                },
+                GreedyQuad {
+                    direction: Direction::Up,
+                    position: [2, 5, 0],
+                    size: [2, 2],
+                    key: 3,
+                },
+            ]
+        );
+
+        // Faces in other layers or directions stay apart
+        let mut faces = faces;
+        faces.push(VisibleFace {
+            position: (0, 6, 0),
+            direction: Direction::Up,
+            block_type_id: 1,
+        });
+        faces.push(VisibleFace {
+            position: (0, 5, 0),
+            direction: Direction::North,
+            block_type_id: 1,
+        });
+        assert_eq!(greedy_mesh(&faces, |face| face.block_type_id).len(), 4);
+    }
+}
@@ -1,14 +1,32 @@
 use std::collections::HashMap;
 
+use crate::model::Occlusion;
 use crate::renderer::render_faces::GpuChunk;
-use crate::types::{BlockTypeId, Chunk, ChunkPosition, Direction, World};
+use crate::settings::WorldEdges;
+use crate::types::{
+    local_block_position, BlockTypeId, Chunk, ChunkPosition, Direction, World, CHUNK_SIZE,
+};
 use rayon::prelude::*;
+use tracing::instrument;
+
+pub use self::{
+    bounds::SectionBounds,
+    frustum::{Frustum, Plane},
+    greedy::{greedy_mesh, GreedyQuad},
+    visibility::CaveCuller,
+};
+
+mod bounds;
+mod frustum;
+mod greedy;
+mod visibility;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct VisibleFace {
-    position: (u32, u32, u32),
-    direction: Direction,
-    block_type_id: BlockTypeId,
+    /// Position of the block inside its section.
+    pub position: (u32, u32, u32),
+    pub direction: Direction,
+    pub block_type_id: BlockTypeId,
 }
 
 impl VisibleFace {
@@ -26,10 +44,14 @@ impl VisibleFace {
     }
 }
 
+/// The visible faces of a section. `occlusion` is `BlockRegistry::occlusion`
+/// of the world's registry, computed once rather than for every section.
 pub fn cull_faces_for_chunk(
     world: &World,
+    occlusion: &[Occlusion],
     chunk: &Chunk,
     chunk_position: ChunkPosition,
+    edges: WorldEdges,
 ) -> Vec<VisibleFace> {
     chunk
         .blocks
@@ -44,9 +66,11 @@ pub fn cull_faces_for_chunk(
                         check_visible_faces_for_block(
                             *block_type_id,
                             world,
+                            occlusion,
                             chunk,
                             chunk_position,
                             (x as u32, y as u32, z as u32),
+                            edges,
                         )
                     })
             })
@@ -54,23 +78,84 @@ pub fn cull_faces_for_chunk(
         .collect()
 }
 
-pub fn cull_faces(world: &World) -> HashMap<ChunkPosition, Vec<VisibleFace>> {
+#[instrument(skip_all)]
+pub fn cull_faces(world: &World, edges: WorldEdges) -> HashMap<ChunkPosition, Vec<VisibleFace>> {
+    let occlusion = world.block_registry.occlusion();
     world
         .chunks
         .par_iter()
         .map(|(chunk_position, chunk)| {
-            let visible_faces = cull_faces_for_chunk(world, chunk, *chunk_position);
+            let visible_faces =
+                cull_faces_for_chunk(world, &occlusion, chunk, *chunk_position, edges);
             (*chunk_position, visible_faces)
         })
         .collect()
 }
 
+/// The sections whose visible faces a change of the block at `position` can
+/// change: its own, and the neighbors across the borders it lies on.
+pub fn sections_affected_by_block(position: [i32; 3]) -> Vec<ChunkPosition> {
+    let section = ChunkPosition::of_block(position);
+    let local = local_block_position(position);
+    let mut sections = vec![section];
+    for direction in Direction::ALL {
+        let (dx, dy, dz) = direction.to_offset();
+        let border = if [dx, dy, dz][direction.axis()] > 0 {
+            CHUNK_SIZE - 1
+        } else {
+            0
+        };
+        if local[direction.axis()] == border {
+            sections.push(section.offset(direction));
+        }
+    }
+    sections
+}
+
+/// The blocks whose visible faces a change of the block at `position` can
+/// change: itself and its six neighbors, which may lie in other sections.
+pub fn blocks_affected_by_block(position: [i32; 3]) -> [[i32; 3]; 7] {
+    let mut blocks = [position; 7];
+    for (block, direction) in blocks[1..].iter_mut().zip(Direction::ALL) {
+        let (dx, dy, dz) = direction.to_offset();
+        *block = [position[0] + dx, position[1] + dy, position[2] + dz];
+    }
+    blocks
+}
+
+/// The visible faces of the single block at `position`, for redoing the
+/// blocks around an edit without culling their whole sections. Blocks of
+/// unloaded sections have none. `occlusion` is as for `cull_faces_for_chunk`.
+pub fn cull_faces_for_block(
+    world: &World,
+    occlusion: &[Occlusion],
+    position: [i32; 3],
+    edges: WorldEdges,
+) -> Vec<VisibleFace> {
+    let chunk_position = ChunkPosition::of_block(position);
+    let Some(chunk) = world.chunks.get(&chunk_position) else {
+        return Vec::new();
+    };
+    let [x, y, z] = local_block_position(position);
+    check_visible_faces_for_block(
+        chunk.blocks[y][x][z],
+        world,
+        occlusion,
+        chunk,
+        chunk_position,
+        (x as u32, y as u32, z as u32),
+        edges,
+    )
+}
+
 fn check_visible_faces_for_block(
     block_type_id: BlockTypeId,
     world: &World,
+    occlusion: &[Occlusion],
     chunk: &Chunk,
     chunk_position: ChunkPosition,
     block_position: (u32, u32, u32),
+    edges: WorldEdges,
 ) -> Vec<VisibleFace> {
     if block_type_id == 0 {
         return Vec::new();
@@ -84,54 +169,42 @@ fn check_visible_faces_for_block(
     }
     let mut visible_faces = Vec::new();
 
-    // If the block is at the edge of the chunk, check for
-    // adjacent blocks in the neighboring chunk using the
+    // If the block is at the edge of the section, check for
+    // adjacent blocks in the neighboring section using the
     // chunk_position to index into the world's chunks.
-    // If the neighboring chunk doesn't exist, the face is
-    // invisible.
-    // For blocks at the top or bottom of the chunk, treat
-    // the neighboring chunk as air.
+    // If the neighboring section doesn't exist, `edges` decides
+    // whether it hides the face. Above and below the world height
+    // there is only air.
     for direction in Direction::ALL.into_iter() {
         let (dx, dy, dz) = direction.to_offset();
         let (nx, ny, nz) = (x as i32 + dx, y as i32 + dy, z as i32 + dz);
+        let size = CHUNK_SIZE as i32;
+
+        let neighbor_block_type_id =
+            if (0..size).contains(&nx) && (0..size).contains(&ny) && (0..size).contains(&nz) {
+                chunk.blocks[ny as usize][nx as usize][nz as usize]
+            } else {
+                let neighbor_chunk_position = chunk_position.offset(direction);
+                if !world.height.contains_section(neighbor_chunk_position.y) {
+                    0
+                } else if let Some(neighbor_chunk) = world.chunks.get(&neighbor_chunk_position) {
+                    neighbor_chunk.blocks[ny.rem_euclid(size) as usize]
+                        [nx.rem_euclid(size) as usize][nz.rem_euclid(size) as usize]
+                } else {
+                    match edges {
+                        WorldEdges::Solid => continue,
+                        WorldEdges::Air | WorldEdges::FogWall => 0,
+                    }
+                }
+            };
 
-        if y <= 0 && direction == Direction::Down || y >= 255 && direction == Direction::Up {
+        // Faces are only hidden where the neighbor covers all of them
+        if !occlusion[block_type_id].is_hidden_by(direction, &occlusion[neighbor_block_type_id]) {
             visible_faces.push(VisibleFace {
-                position: (x as u32, y as u32, z as u32),
+                position: (x, y, z),
                 direction,
                 block_type_id,
             });
-            continue;
-        }
-
-        if nx < 0 || nx >= 16 || nz < 0 || nz >= 16 {
-            let (cx, cz) = (chunk_position.x, chunk_position.z);
-            let (ncx, ncz) = (cx + dx, cz + dz);
-            let neighbor_chunk_position = ChunkPosition { x: ncx, z: ncz };
-            let neighbor_chunk = world.chunks.get(&neighbor_chunk_position);
-
-            if let Some(neighbor_chunk) = neighbor_chunk {
-                let neighbor_block_type_id = neighbor_chunk.blocks[y as usize]
-                    [((nx + 16) % 16) as usize][((nz + 16) % 16) as usize];
-
-                if block_registry.is_block_transparent(neighbor_block_type_id) {
-                    visible_faces.push(VisibleFace {
-                        position: (x as u32, y as u32, z as u32),
-                        direction,
-                        block_type_id,
-                    });
-                }
-            }
-        } else {
-            let neighbor_block_type_id = chunk.blocks[ny as usize][nx as usize][nz as usize];
-
-            if block_registry.is_block_transparent(neighbor_block_type_id) {
-                visible_faces.push(VisibleFace {
-                    position: (x as u32, y as u32, z as u32),
-                    direction,
-                    block_type_id,
-                });
-            }
         }
     }
     visible_faces
@@ -142,9 +215,11 @@ fn update_visible_faces(
     visible_faces: &mut HashMap<ChunkPosition, Vec<VisibleFace>>,
     chunk_positions: &[ChunkPosition],
 ) {
+    let occlusion = world.block_registry.occlusion();
     for chunk_position in chunk_positions {
         let chunk = world.chunks.get(chunk_position).unwrap();
-        let new_visible_faces = cull_faces_for_chunk(world, chunk, *chunk_position);
+        let new_visible_faces =
+            cull_faces_for_chunk(world, &occlusion, chunk, *chunk_position, WorldEdges::Solid);
         visible_faces.insert(*chunk_position, new_visible_faces);
     }
 }
@@ -162,16 +237,18 @@ mod tests {
         let block_registry = BlockRegistry::default();
         let world = World::new(block_registry);
         let chunk = Chunk::default();
-        let chunk_position = ChunkPosition { x: 0, z: 0 };
-        let block_position = (8, 64, 8);
+        let chunk_position = ChunkPosition { x: 0, y: 0, z: 0 };
+        let block_position = (8, 8, 8);
         let block_type_id = 1; // Replace with the actual block type ID
 
         let visible_faces = check_visible_faces_for_block(
             block_type_id,
             &world,
+            &world.block_registry.occlusion(),
             &chunk,
             chunk_position,
             block_position,
+            WorldEdges::Solid,
         );
 
         assert_eq!(visible_faces.len(), 6);
@@ -182,28 +259,32 @@ mod tests {
         let block_registry = BlockRegistry::default();
         let world = World::new(block_registry);
         let chunk = Chunk::default();
-        let chunk_position = ChunkPosition { x: 0, z: 0 };
+        let chunk_position = ChunkPosition { x: 0, y: 0, z: 0 };
         let block_type_id = 1; // Replace with the actual block type ID
 
-        // Top block
-        let block_position_top = (8, 255, 8);
+        // Top block of the topmost section
+        let block_position_top = (8, 15, 8);
         let visible_faces_top = check_visible_faces_for_block(
             block_type_id,
             &world,
+            &world.block_registry.occlusion(),
             &chunk,
-            chunk_position,
+            ChunkPosition { x: 0, y: 15, z: 0 },
             block_position_top,
+            WorldEdges::Solid,
         );
         assert_eq!(visible_faces_top.len(), 6);
 
-        // Bottom block
+        // Bottom block of the lowest section
         let block_position_bottom = (8, 0, 8);
         let visible_faces_bottom = check_visible_faces_for_block(
             block_type_id,
             &world,
+            &world.block_registry.occlusion(),
             &chunk,
             chunk_position,
             block_position_bottom,
+            WorldEdges::Solid,
         );
         assert_eq!(visible_faces_bottom.len(), 6);
     }
@@ -213,17 +294,19 @@ mod tests {
         let block_registry = BlockRegistry::default();
         let world = World::new(block_registry);
         let chunk = Chunk::default();
-        let chunk_position = ChunkPosition { x: 0, z: 0 };
+        let chunk_position = ChunkPosition { x: 0, y: 0, z: 0 };
         let block_type_id = 1; // Replace with the actual block type ID
 
         // Left edge
-        let block_position_left = (0, 64, 8);
+        let block_position_left = (0, 8, 8);
         let visible_faces_left = check_visible_faces_for_block(
             block_type_id,
             &world,
+            &world.block_registry.occlusion(),
             &chunk,
             chunk_position,
             block_position_left,
+            WorldEdges::Solid,
         )
         .into_iter()
         .map(|f| f.direction)
@@ -232,58 +315,90 @@ mod tests {
         assert!(!visible_faces_left.contains(&Direction::West));
 
         // Right edge
-        let block_position_right = (15, 64, 8);
+        let block_position_right = (15, 8, 8);
         let visible_faces_right = check_visible_faces_for_block(
             block_type_id,
             &world,
+            &world.block_registry.occlusion(),
             &chunk,
             chunk_position,
             block_position_right,
+            WorldEdges::Solid,
         );
         assert_eq!(visible_faces_right.len(), 5);
 
         // Front edge
-        let block_position_front = (8, 64, 0);
+        let block_position_front = (8, 8, 0);
         let visible_faces_front = check_visible_faces_for_block(
             block_type_id,
             &world,
+            &world.block_registry.occlusion(),
             &chunk,
             chunk_position,
             block_position_front,
+            WorldEdges::Solid,
         );
         assert_eq!(visible_faces_front.len(), 5);
 
         // Back edge
-        let block_position_back = (8, 64, 15);
+        let block_position_back = (8, 8, 15);
         let visible_faces_back = check_visible_faces_for_block(
             block_type_id,
             &world,
+            &world.block_registry.occlusion(),
             &chunk,
             chunk_position,
             block_position_back,
+            WorldEdges::Solid,
         );
         assert_eq!(visible_faces_back.len(), 5);
     }
 
+    #[test]
+    fn test_section_edge_not_loaded() {
+        let block_registry = BlockRegistry::default();
+        let world = World::new(block_registry);
+        let chunk = Chunk::default();
+        let block_type_id = 1;
+
+        // The section below is inside the world height but not loaded
+        let visible_faces = check_visible_faces_for_block(
+            block_type_id,
+            &world,
+            &world.block_registry.occlusion(),
+            &chunk,
+            ChunkPosition { x: 0, y: 4, z: 0 },
+            (8, 0, 8),
+            WorldEdges::Solid,
+        )
+        .into_iter()
+        .map(|f| f.direction)
+        .collect::<HashSet<_>>();
+        assert_eq!(visible_faces.len(), 5);
+        assert!(!visible_faces.contains(&Direction::Down));
+    }
+
     #[test]
     fn test_chunk_edge_loaded() {
         let block_registry = BlockRegistry::default();
         let mut world = World::new(block_registry);
-        let chunk_position = ChunkPosition { x: 0, z: 0 };
+        let chunk_position = ChunkPosition { x: 0, y: 0, z: 0 };
         world.chunks.insert(chunk_position, Chunk::default());
         let block_type_id = 1; // Replace with the actual block type ID
 
         let neighbor_chunk = Chunk::default();
-        let neighbor_chunk_position = ChunkPosition { x: 1, z: 0 };
+        let neighbor_chunk_position = ChunkPosition { x: 1, y: 0, z: 0 };
         world.chunks.insert(neighbor_chunk_position, neighbor_chunk);
 
-        let block_position_x_plus = (15, 64, 8);
+        let block_position_x_plus = (15, 8, 8);
         let visible_faces = check_visible_faces_for_block(
             block_type_id,
             &world,
+            &world.block_registry.occlusion(),
             &world.chunks[&chunk_position],
             chunk_position,
             block_position_x_plus,
+            WorldEdges::Solid,
         );
         assert_eq!(visible_faces.len(), 6);
 
@@ -291,55 +406,188 @@ mod tests {
             .chunks
             .get_mut(&neighbor_chunk_position)
             .unwrap()
-            .blocks[64][0][8] = 1; // solid block
+            .blocks[8][0][8] = 1; // solid block
         assert!(!world.block_registry.block_types[1].transparent);
         let visible_faces = check_visible_faces_for_block(
             block_type_id,
             &world,
+            &world.block_registry.occlusion(),
             &world.chunks[&chunk_position],
             chunk_position,
             block_position_x_plus,
+            WorldEdges::Solid,
         );
         assert_eq!(visible_faces.len(), 5);
     }
 
-    #[test]
-    fn test_chunk_dig_one_block() {
-        let chunk_position = ChunkPosition { x: 0, z: 0 };
+    /// Loads an empty chunk column at x = z = 0 and fills its lowest `depth`
+    /// layers with stone.
+    fn stone_column(depth: i32) -> World {
         let block_registry = BlockRegistry::default();
         let mut world = World::new(block_registry);
-        world.chunks.insert(chunk_position, Chunk::default());
-        let chunk = world.chunks.get_mut(&chunk_position).unwrap();
+        for y in world.height.sections() {
+            world
+                .chunks
+                .insert(ChunkPosition { x: 0, y, z: 0 }, Chunk::default());
+        }
 
         assert!(!world.block_registry.is_block_transparent(1));
 
         let stone_id = 1;
+        world.fill_cuboid([0, 0, 0], [16, depth, 16], stone_id);
+        world
+    }
 
-        for y in 0..64 {
-            for x in 0..16 {
-                for z in 0..16 {
-                    chunk.blocks[y as usize][x][z] = stone_id;
-                }
-            }
-        }
-        let visible_faces = cull_faces(&world);
+    fn count_faces(visible_faces: HashMap<ChunkPosition, Vec<VisibleFace>>) -> usize {
+        visible_faces.into_values().map(|v| v.len()).sum()
+    }
+
+    #[test]
+    fn test_chunk_dig_one_block() {
+        let mut world = stone_column(64);
+
+        let visible_faces = cull_faces(&world, WorldEdges::Solid);
+        assert_eq!(count_faces(visible_faces), 16 * 16 * 2);
+
+        world[[1, 63, 1]] = 0;
+
+        let visible_faces = cull_faces(&world, WorldEdges::Solid);
+        assert_eq!(count_faces(visible_faces), 16 * 16 * 2 + 4);
+    }
+
+    #[test]
+    fn test_world_edges() {
+        let world = stone_column(64);
+        let sides = 4 * 16 * 64;
         assert_eq!(
-            visible_faces
-                .into_iter()
-                .map(|(_, v)| v.len())
-                .sum::<usize>(),
-            16 * 16 * 2
+            count_faces(cull_faces(&world, WorldEdges::Air)),
+            16 * 16 * 2 + sides
         );
+        assert_eq!(
+            count_faces(cull_faces(&world, WorldEdges::FogWall)),
+            16 * 16 * 2 + sides
+        );
+    }
 
-        world.chunks.get_mut(&chunk_position).unwrap().blocks[63][1][1] = 0;
+    #[test]
+    fn test_cave_interior_faces() {
+        let mut world = stone_column(32);
+        // A sealed 4x4x4 cavern in the lowest section
+        world.fill_cuboid([4, 10, 4], [8, 14, 8], 0);
+
+        let visible_faces = cull_faces(&world, WorldEdges::Solid);
+        let bottom_section = visible_faces[&ChunkPosition { x: 0, y: 0, z: 0 }].clone();
+        assert_eq!(count_faces(visible_faces), 16 * 16 * 2 + 6 * 4 * 4);
+
+        // Every interior face points into the cavern
+        let interior_faces = bottom_section
+            .iter()
+            .filter(|face| face.position.1 > 0)
+            .collect::<Vec<_>>();
+        assert_eq!(interior_faces.len(), 6 * 4 * 4);
+        for face in interior_faces {
+            let (dx, dy, dz) = face.direction.to_offset();
+            let (x, y, z) = face.position;
+            let neighbor = (x as i32 + dx, y as i32 + dy, z as i32 + dz);
+            assert!((4..8).contains(&neighbor.0));
+            assert!((10..14).contains(&neighbor.1));
+            assert!((4..8).contains(&neighbor.2));
+        }
+    }
 
-        let visible_faces = cull_faces(&world);
+    #[test]
+    fn test_sections_affected_by_block() {
+        let section = |x, y, z| ChunkPosition { x, y, z };
+        assert_eq!(sections_affected_by_block([5, 20, 7]), [section(0, 1, 0)]);
+        // A corner block touches three neighbors
         assert_eq!(
-            visible_faces
+            sections_affected_by_block([-1, 16, 0])
                 .into_iter()
-                .map(|(_, v)| v.len())
-                .sum::<usize>(),
-            16 * 16 * 2 + 4
+                .collect::<HashSet<_>>(),
+            HashSet::from([
+                section(-1, 1, 0),
+                section(-1, 0, 0),
+                section(-1, 1, -1),
+                section(0, 1, 0),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_cull_faces_for_block() {
+        let mut world = stone_column(32);
+        // Dig across the border of the two lowest sections
+        world[[0, 15, 0]] = 0;
+        world[[0, 16, 0]] = 0;
+
+        let mut expected = cull_faces(&world, WorldEdges::Solid);
+        let occlusion = world.block_registry.occlusion();
+        for faces in expected.values_mut() {
+            faces.sort_by_key(|face| (face.position, face.direction as u8));
+        }
+        for position in [[0, 15, 0], [0, 16, 0]] {
+            for block in blocks_affected_by_block(position) {
+                let chunk_position = ChunkPosition::of_block(block);
+                let [x, y, z] = local_block_position(block).map(|v| v as u32);
+                let mut faces = cull_faces_for_block(&world, &occlusion, block, WorldEdges::Solid);
+                faces.sort_by_key(|face| (face.position, face.direction as u8));
+                let section_faces = expected.get(&chunk_position).map_or(Vec::new(), |faces| {
+                    faces
+                        .iter()
+                        .filter(|face| face.position == (x, y, z))
+                        .cloned()
+                        .collect()
+                });
+                assert_eq!(faces, section_faces);
+            }
+        }
+        // The dug blocks have no faces, the stone around them shows some
+        assert!(cull_faces_for_block(&world, &occlusion, [0, 15, 0], WorldEdges::Solid).is_empty());
+        assert_eq!(
+            cull_faces_for_block(&world, &occlusion, [1, 15, 0], WorldEdges::Solid).len(),
+            1
+        );
+        // Outside of the loaded sections
+        assert!(
+            cull_faces_for_block(&world, &occlusion, [-1, 15, 0], WorldEdges::Solid).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_partial_faces() {
+        let mut world = World::new(BlockRegistry::default());
+        let id = |name: &str| world.block_registry.block_types.get_index_of(name).unwrap();
+        let (stone, slab) = (id("stone"), id("stone_slab"));
+        let position = ChunkPosition { x: 0, y: 0, z: 0 };
+        world.chunks.insert(position, Chunk::default());
+        // A slab between stone to its west and another slab to its east,
+        // with stone on top
+        world[[4, 4, 4]] = stone;
+        world[[5, 4, 4]] = slab;
+        world[[6, 4, 4]] = slab;
+        world[[5, 5, 4]] = stone;
+
+        let faces = cull_faces_for_chunk(
+            &world,
+            &world.block_registry.occlusion(),
+            &world.chunks[&position],
+            position,
+            WorldEdges::Solid,
         );
+        let directions = |position| {
+            faces
+                .iter()
+                .filter(|face| face.position == position)
+                .map(|face| face.direction)
+                .collect::<HashSet<_>>()
+        };
+        let middle = directions((5, 4, 4));
+        assert!(!middle.contains(&Direction::West));
+        assert!(!middle.contains(&Direction::East));
+        // The top of the slab lies inside its block, the stone can't hide it
+        assert!(middle.contains(&Direction::Up));
+        // Neither slab covers all of a full block's face
+        assert!(directions((4, 4, 4)).contains(&Direction::East));
+        assert!(directions((5, 5, 4)).contains(&Direction::Down));
     }
 }
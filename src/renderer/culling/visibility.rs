@@ -0,0 +1,193 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::types::{BlockRegistry, Chunk, ChunkPosition, Direction, World, CHUNK_SIZE};
+
+/// Which faces of a section can see each other through its transparent and
+/// partial blocks. Bit `from * 6 + to` is set if `from` and `to` are connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionConnectivity(u64);
+
+impl SectionConnectivity {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self((1 << 36) - 1);
+
+    /// Flood fills the blocks of `chunk` that aren't opaque cubes, connecting
+    /// the faces every filled region touches.
+    pub fn compute(chunk: &Chunk, block_registry: &BlockRegistry) -> Self {
+        let see_through =
+            |[x, y, z]: [usize; 3]| !block_registry.is_block_opaque_cube(chunk.blocks[y][x][z]);
+        let all_positions = || {
+            (0..CHUNK_SIZE).flat_map(|y| {
+                (0..CHUNK_SIZE).flat_map(move |x| (0..CHUNK_SIZE).map(move |z| [x, y, z]))
+            })
+        };
+        if all_positions().all(see_through) {
+            return Self::ALL;
+        }
+
+        let mut connectivity = Self::NONE;
+        let mut visited = [[[false; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+        let mut stack = Vec::new();
+        for start in all_positions() {
+            let [x, y, z] = start;
+            if visited[y][x][z] || !see_through(start) {
+                continue;
+            }
+            visited[y][x][z] = true;
+            stack.push(start);
+
+            let mut touched = Vec::new();
+            while let Some(position) = stack.pop() {
+                for direction in Direction::ALL {
+                    let (dx, dy, dz) = direction.to_offset();
+                    let neighbor = [
+                        position[0] as i32 + dx,
+                        position[1] as i32 + dy,
+                        position[2] as i32 + dz,
+                    ];
+                    if neighbor
+                        .iter()
+                        .any(|&n| !(0..CHUNK_SIZE as i32).contains(&n))
+                    {
+                        if !touched.contains(&direction) {
+                            touched.push(direction);
+                        }
+                        continue;
+                    }
+                    let neighbor = neighbor.map(|n| n as usize);
+                    let [nx, ny, nz] = neighbor;
+                    if !visited[ny][nx][nz] && see_through(neighbor) {
+                        visited[ny][nx][nz] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+
+            for &from in &touched {
+                for &to in &touched {
+                    connectivity.connect(from, to);
+                }
+            }
+        }
+        connectivity
+    }
+
+    fn connect(&mut self, from: Direction, to: Direction) {
+        self.0 |= 1 << (from as u64 * 6 + to as u64);
+    }
+
+    pub fn connects(&self, from: Direction, to: Direction) -> bool {
+        self.0 & (1 << (from as u64 * 6 + to as u64)) != 0
+    }
+}
+
+/// Cave culling: sections are only visible if the camera can see into them
+/// through a chain of connected section faces, so caves sealed off from the
+/// camera are skipped entirely.
+#[derive(Debug, Default)]
+pub struct CaveCuller {
+    connectivity: HashMap<ChunkPosition, SectionConnectivity>,
+}
+
+impl CaveCuller {
+    /// Recomputes the connectivity of the loaded `sections`.
+    pub fn update(&mut self, world: &World, sections: impl IntoIterator<Item = ChunkPosition>) {
+        for section in sections {
+            if let Some(chunk) = world.chunks.get(&section) {
+                self.connectivity.insert(
+                    section,
+                    SectionConnectivity::compute(chunk, &world.block_registry),
+                );
+            }
+        }
+    }
+
+    pub fn remove(&mut self, section: ChunkPosition) {
+        self.connectivity.remove(&section);
+    }
+
+    /// Sections visible from a camera at `camera_position`. Like Minecraft's,
+    /// the search never turns back in a direction opposite to one it already
+    /// went in. If the camera is not in a known section, all are visible.
+    pub fn visible_sections(&self, camera_position: [i32; 3]) -> HashSet<ChunkPosition> {
+        let start = ChunkPosition::of_block(camera_position);
+        if !self.connectivity.contains_key(&start) {
+            return self.connectivity.keys().copied().collect();
+        }
+
+        let mut visible = HashSet::from([start]);
+        // Section, the face it was entered through, directions travelled
+        let mut queue = VecDeque::from([(start, None::<Direction>, 0u8)]);
+        while let Some((section, entered_through, travelled)) = queue.pop_front() {
+            let connectivity = self.connectivity[&section];
+            for direction in Direction::ALL {
+                if travelled & (1 << direction.opposite() as u8) != 0 {
+                    continue;
+                }
+                if entered_through.is_some_and(|from| !connectivity.connects(from, direction)) {
+                    continue;
+                }
+                let neighbor = section.offset(direction);
+                if !self.connectivity.contains_key(&neighbor) || !visible.insert(neighbor) {
+                    continue;
+                }
+                queue.push_back((
+                    neighbor,
+                    Some(direction.opposite()),
+                    travelled | 1 << direction as u8,
+                ));
+            }
+        }
+        visible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::BlockTypeId;
+
+    use super::*;
+
+    const STONE: BlockTypeId = 1;
+
+    #[test]
+    fn test_wall_splits_section() {
+        let block_registry = BlockRegistry::default();
+        assert!(!block_registry.is_block_transparent(STONE));
+        let mut chunk = Chunk::default();
+        chunk.blocks[8] = [[STONE; CHUNK_SIZE]; CHUNK_SIZE];
+
+        let connectivity = SectionConnectivity::compute(&chunk, &block_registry);
+        assert!(!connectivity.connects(Direction::Up, Direction::Down));
+        assert!(connectivity.connects(Direction::Up, Direction::North));
+        assert!(connectivity.connects(Direction::Down, Direction::East));
+        assert!(connectivity.connects(Direction::North, Direction::South));
+
+        chunk.blocks[8][3][3] = 0;
+        let connectivity = SectionConnectivity::compute(&chunk, &block_registry);
+        assert!(connectivity.connects(Direction::Up, Direction::Down));
+    }
+
+    #[test]
+    fn test_sealed_cave_not_visible() {
+        let mut world = World::new(BlockRegistry::default());
+        let mut solid = Chunk::default();
+        solid.blocks = [[[STONE; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+        let section = |y| ChunkPosition { x: 0, y, z: 0 };
+        // Air at the camera, a solid layer, and a sealed cave below it
+        world.chunks.insert(section(3), Chunk::default());
+        world.chunks.insert(section(2), solid.clone());
+        world.chunks.insert(section(1), Chunk::default());
+        world.chunks.insert(section(0), solid);
+
+        let mut culler = CaveCuller::default();
+        culler.update(&world, (0..4).map(section));
+
+        let visible = culler.visible_sections([8, 56, 8]);
+        assert_eq!(visible, HashSet::from([section(3), section(2)]));
+
+        // From inside the cave the surface is hidden instead
+        let visible = culler.visible_sections([8, 24, 8]);
+        assert_eq!(visible, HashSet::from([section(2), section(1), section(0)]));
+    }
+}
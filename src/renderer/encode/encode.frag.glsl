@@ -0,0 +1,59 @@
+#version 460
+
+// Grades the linear image with a 3D LUT and writes it to the swapchain.
+// Writes to sRGB formats encode by themselves, for the others it is done
+// here.
+
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+layout(constant_id = 1) const bool GAMMA_TEST = false;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler3D lut;
+
+layout(push_constant) uniform PushConstants {
+  // Colors at the first and last entries of the LUT
+  vec4 lut_domain_min;
+  vec4 lut_domain_max;
+}
+pc;
+
+layout(location = 0) out vec4 frag_color;
+
+// Height of the gamma test strip, in pixels
+const int TEST_HEIGHT = 48;
+
+vec3 encode_srgb(vec3 linear) {
+  return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055,
+             greaterThan(linear, vec3(0.0031308)));
+}
+
+vec3 decode_srgb(vec3 encoded) {
+  return mix(encoded / 12.92, pow((encoded + 0.055) / 1.055, vec3(2.4)),
+             greaterThan(encoded, vec3(0.04045)));
+}
+
+// In sRGB, which the tables are made for
+vec3 grade(vec3 linear) {
+  vec3 encoded = encode_srgb(clamp(linear, 0.0, 1.0));
+  vec3 uvw = (encoded - pc.lut_domain_min.xyz) /
+             (pc.lut_domain_max.xyz - pc.lut_domain_min.xyz);
+  // From the center of the first entry to the center of the last
+  float size = float(textureSize(lut, 0).x);
+  uvw = clamp(uvw, 0.0, 1.0) * ((size - 1.0) / size) + 0.5 / size;
+  return decode_srgb(texture(lut, uvw).rgb);
+}
+
+void main() {
+  ivec2 pixel = ivec2(gl_FragCoord.xy);
+  vec3 result = grade(texelFetch(color, pixel, 0).rgb);
+  if (GAMMA_TEST && pixel.y < TEST_HEIGHT) {
+    // From a step back, the black and white checkerboard gives off half the
+    // light, which the gray next to it only matches when encoded right
+    int width = textureSize(color, 0).x;
+    result = pixel.x < width / 2 ? vec3((pixel.x + pixel.y) & 1) : vec3(0.5);
+  }
+  if (ENCODE_SRGB) {
+    result = encode_srgb(clamp(result, 0.0, 1.0));
+  }
+  frag_color = vec4(result, 1.0);
+}
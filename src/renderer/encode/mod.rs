@@ -0,0 +1,276 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{CopyBufferToImageInfo, RecordingCommandBuffer},
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    format::{Format, NumericFormat},
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    shader::SpecializationConstant,
+};
+
+use crate::{
+    app::App,
+    lut::Lut,
+    memory::{MemoryCategory, TrackedAllocation},
+    settings::SwapchainFormat,
+};
+
+mod vs {
+    vulkano_shaders::shader!(
+        ty: "vertex",
+        path: "src/renderer/encode/encode.vert.glsl",
+    );
+}
+
+mod fs {
+    vulkano_shaders::shader!(
+        ty: "fragment",
+        path: "src/renderer/encode/encode.frag.glsl",
+    );
+}
+
+/// The swapchain image format of the setting. Desktop drivers all support
+/// both in BGRA order with the sRGB color space.
+pub fn swapchain_format(setting: SwapchainFormat) -> Format {
+    match setting {
+        SwapchainFormat::Srgb => Format::B8G8R8A8_SRGB,
+        SwapchainFormat::Unorm => Format::B8G8R8A8_UNORM,
+    }
+}
+
+/// Whether shaders writing linear colors to `format` have to encode them
+/// to sRGB themselves, as writes to it don't.
+pub fn needs_srgb_encoding(format: Format) -> bool {
+    format.numeric_format_color() != Some(NumericFormat::SRGB)
+}
+
+/// Draws the finished linear image onto the swapchain image, graded with a
+/// LUT and encoded for display.
+pub struct EncodePass {
+    pipeline: Arc<GraphicsPipeline>,
+    inputs: Vec<Arc<ImageView>>,
+    /// Per input image, the set drawing it.
+    sets: Vec<Arc<DescriptorSet>>,
+    lut_image: Arc<Image>,
+    /// The LUT until `upload_lut` copies it to `lut_image`.
+    lut_staging: Option<Subbuffer<[u32]>>,
+    lut_domain: [[f32; 4]; 2],
+    _memory: Vec<TrackedAllocation>,
+}
+
+impl EncodePass {
+    /// A pass drawing any of `inputs`, the size of the swapchain image, to
+    /// the swapchain image `rendering_info` is for, graded with `lut`. With
+    /// `gamma_test`, the top of the screen shows whether the encoding is
+    /// right.
+    pub fn new(
+        app: &App,
+        rendering_info: PipelineRenderingCreateInfo,
+        inputs: &[Arc<ImageView>],
+        lut: &Lut,
+        gamma_test: bool,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let encode_srgb = needs_srgb_encoding(rendering_info.color_attachment_formats[0].unwrap());
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let fs = fs::load(device.clone())
+                .unwrap()
+                .specialize(
+                    [
+                        (0, SpecializationConstant::Bool(encode_srgb)),
+                        (1, SpecializationConstant::Bool(gamma_test)),
+                    ]
+                    .into_iter()
+                    .collect(),
+                )
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    // Vertices come from the vertex index
+                    vertex_input_state: Some(VertexInputState::default()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        1,
+                        ColorBlendAttachmentState::default(),
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(rendering_info.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .unwrap()
+        };
+
+        let lut_staging = Buffer::from_iter(
+            app.memory_allocator(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            lut.packed(),
+        )
+        .unwrap();
+        // Ten bits per channel, which can be filtered on every device
+        let lut_image = Image::new(
+            app.memory_allocator(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim3d,
+                format: Format::A2B10G10R10_UNORM_PACK32,
+                extent: [lut.size; 3],
+                usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap();
+        let memory = vec![
+            app.memory_tracker
+                .track_buffer(MemoryCategory::Staging, &lut_staging),
+            app.memory_tracker
+                .track_image(MemoryCategory::Textures, &lut_image),
+        ];
+
+        // Only read with texelFetch, so the sampler doesn't matter
+        let sampler = Sampler::new(device.clone(), SamplerCreateInfo::default()).unwrap();
+        // Interpolates between the entries
+        let lut_sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let lut_view = ImageView::new_default(lut_image.clone()).unwrap();
+        let set_layout = pipeline.layout().set_layouts()[0].clone();
+        let sets = inputs
+            .iter()
+            .map(|input| {
+                DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    set_layout.clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(0, input.clone(), sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(
+                            1,
+                            lut_view.clone(),
+                            lut_sampler.clone(),
+                        ),
+                    ],
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let [min_r, min_g, min_b] = lut.domain_min;
+        let [max_r, max_g, max_b] = lut.domain_max;
+        Self {
+            pipeline,
+            inputs: inputs.to_vec(),
+            sets,
+            lut_image,
+            lut_staging: Some(lut_staging),
+            lut_domain: [[min_r, min_g, min_b, 0.0], [max_r, max_g, max_b, 0.0]],
+            _memory: memory,
+        }
+    }
+
+    /// Records the copy of the LUT to the GPU the first time it is called.
+    /// Has to be recorded outside of a render pass, before `draw`.
+    pub fn upload_lut(&mut self, builder: &mut RecordingCommandBuffer) {
+        let Some(lut_staging) = self.lut_staging.take() else {
+            return;
+        };
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+                lut_staging,
+                self.lut_image.clone(),
+            ))
+            .unwrap();
+    }
+
+    /// Records drawing `input`, one of the inputs the pass was created with,
+    /// in a rendering to the swapchain image begun by `draw_overlay`.
+    pub fn draw(&self, builder: &mut RecordingCommandBuffer, input: &Arc<ImageView>) {
+        let input = self
+            .inputs
+            .iter()
+            .position(|i| Arc::ptr_eq(i, input))
+            .expect("not an input of the pass");
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                self.sets[input].clone(),
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                fs::PushConstants {
+                    lut_domain_min: self.lut_domain[0],
+                    lut_domain_max: self.lut_domain[1],
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder.draw(3, 1, 0, 0).unwrap();
+        }
+    }
+}
@@ -0,0 +1,127 @@
+#version 460
+
+// Marches along each pixel's view ray up to what was drawn there, adding
+// the sunlight the fog scatters toward the camera and dimming what is
+// behind it. With LIGHT_SHAFTS defined, each sample traces a ray toward the
+// sun through the world's acceleration structure, so fog in the shade of
+// blocks stays dark and light comes through gaps in shafts.
+#ifdef LIGHT_SHAFTS
+#extension GL_EXT_ray_query : require
+#endif
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+layout(set = 0, binding = 2) uniform writeonly image2D destination;
+
+#ifdef LIGHT_SHAFTS
+layout(set = 1, binding = 0) uniform accelerationStructureEXT world;
+#endif
+
+layout(push_constant) uniform PushConstants {
+  mat4 inverse_view_proj;  // without jitter
+  vec4 camera_position;
+  float far_depth;       // depth where nothing was drawn
+  float density;         // extinction per block at `base_height`
+  float height_falloff;  // per block above `base_height`
+  float base_height;
+  uint samples;          // at least 1
+  uint frame;            // seeds where the samples start
+}
+pc;
+
+const float PI = 3.14159265;
+// Same as in render_faces.frag.glsl
+const vec3 LIGHT_DIRECTION = normalize(vec3(0.3, 1.0, 0.5));
+const vec3 SUN_COLOR = vec3(1.0, 0.97, 0.92) * 0.4 * PI;
+const vec3 SKY_AMBIENT = vec3(0.62, 0.66, 0.75);
+// How far the fog is marched where nothing was drawn, in blocks
+const float MAX_DISTANCE = 256.0;
+// How far the sun is traced from each sample, in blocks
+const float SHADOW_DISTANCE = 128.0;
+// Forward scattering of the Henyey-Greenstein phase function, which makes
+// the fog glow toward the sun
+const float ANISOTROPY = 0.6;
+
+float phase(float cos_theta) {
+  float g_2 = ANISOTROPY * ANISOTROPY;
+  float d = 1.0 + g_2 - 2.0 * ANISOTROPY * cos_theta;
+  return (1.0 - g_2) / (4.0 * PI * d * sqrt(d));
+}
+
+float density_at(vec3 position) {
+  return pc.density *
+         exp(-max(position.y - pc.base_height, 0.0) * pc.height_falloff);
+}
+
+float sun_visibility(vec3 position) {
+#ifdef LIGHT_SHAFTS
+  rayQueryEXT ray_query;
+  rayQueryInitializeEXT(
+      ray_query, world,
+      gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT, 0xff,
+      position, 0.0, LIGHT_DIRECTION, SHADOW_DISTANCE);
+  while (rayQueryProceedEXT(ray_query)) {
+  }
+  if (rayQueryGetIntersectionTypeEXT(ray_query, true) !=
+      gl_RayQueryCommittedIntersectionNoneEXT) {
+    return 0.0;
+  }
+#endif
+  return 1.0;
+}
+
+// Interleaved gradient noise, offsetting the samples of neighboring pixels
+// and frames so anti-aliasing smooths the steps out
+float noise(vec2 pixel) {
+  pixel += 5.588238 * float(pc.frame % 64);
+  return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
+void main() {
+  ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  ivec2 size = imageSize(destination);
+  if (any(greaterThanEqual(pixel, size))) {
+    return;
+  }
+
+  vec3 current = texelFetch(color, pixel, 0).rgb;
+  vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+  float d = texelFetch(depth, pixel, 0).r;
+  vec3 camera = pc.camera_position.xyz;
+  vec3 direction;
+  float ray_length;
+  if (d == pc.far_depth) {
+    // Any depth gives the direction; the far plane may be at infinity
+    vec4 position = pc.inverse_view_proj * vec4(uv * 2.0 - 1.0, 0.5, 1.0);
+    direction = normalize(position.xyz / position.w - camera);
+    ray_length = MAX_DISTANCE;
+  } else {
+    vec4 position = pc.inverse_view_proj * vec4(uv * 2.0 - 1.0, d, 1.0);
+    vec3 to_surface = position.xyz / position.w - camera;
+    ray_length = min(length(to_surface), MAX_DISTANCE);
+    direction = normalize(to_surface);
+  }
+
+  vec3 sun = SUN_COLOR * phase(dot(direction, LIGHT_DIRECTION));
+  // The sky lights the fog from everywhere, so evenly
+  vec3 ambient = SKY_AMBIENT / (4.0 * PI);
+  float step_length = ray_length / float(pc.samples);
+  float transmittance = 1.0;
+  vec3 scattered = vec3(0.0);
+  float t = step_length * noise(vec2(pixel));
+  for (uint i = 0; i < pc.samples; ++i) {
+    vec3 position = camera + direction * t;
+    float extinction = density_at(position);
+    float step_transmittance = exp(-extinction * step_length);
+    vec3 light = sun * sun_visibility(position) + ambient;
+    // Scattered over the step, as dimmed by the fog in front of it
+    scattered += transmittance * light * (1.0 - step_transmittance);
+    transmittance *= step_transmittance;
+    t += step_length;
+  }
+
+  imageStore(destination, pixel,
+             vec4(current * transmittance + scattered, 1.0));
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use cgmath::SquareMatrix;
+use vulkano::{
+    acceleration_structure::AccelerationStructure,
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+    },
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{app::App, camera::DepthMode, settings::Fog, worldgen::SEA_LEVEL};
+
+use super::render_faces::Camera;
+
+mod cs {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/fog/fog.comp.glsl",
+    );
+}
+
+/// The shader shadowing the fog, which needs ray queries.
+mod cs_light_shafts {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/fog/fog.comp.glsl",
+        define: [("LIGHT_SHAFTS", "")],
+        vulkan_version: "1.3"
+    );
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Volumetric fog lit by the sun, raymarched from the depth buffer. With
+/// light shafts, the sun is traced against the world's acceleration
+/// structure from every sample. Runs on the rendered frame after
+/// reflections and before anti-aliasing, so the noise of the samples is
+/// smoothed with the rest.
+pub struct FogPass {
+    pipeline: Arc<ComputePipeline>,
+    outputs: Vec<Arc<ImageView>>,
+    /// Per frame, the set fogging its color into its output.
+    sets: Vec<Arc<DescriptorSet>>,
+    /// The set of the acceleration structure, once there is one.
+    world_set: Option<Arc<DescriptorSet>>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    light_shafts: bool,
+    depth_mode: DepthMode,
+    frame: u32,
+}
+
+impl FogPass {
+    /// A pass fogging each of `color_images` with the depth of the same
+    /// index into the output of the same index, all single-sampled and of
+    /// the same extent. The outputs must be storage images.
+    pub fn new(
+        app: &App,
+        color_images: &[Arc<ImageView>],
+        depth_images: &[Arc<ImageView>],
+        outputs: Vec<Arc<ImageView>>,
+        depth_mode: DepthMode,
+        light_shafts: bool,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let cs = if light_shafts {
+                cs_light_shafts::load(device.clone())
+            } else {
+                cs::load(device.clone())
+            }
+            .unwrap()
+            .entry_point("main")
+            .unwrap();
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .unwrap()
+        };
+
+        // Depths are not interpolated between pixels
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let set_layout = pipeline.layout().set_layouts()[0].clone();
+        let sets = color_images
+            .iter()
+            .zip(depth_images)
+            .zip(&outputs)
+            .map(|((color, depth), output)| {
+                assert_eq!(color.image().extent(), output.image().extent());
+                DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    set_layout.clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(0, color.clone(), sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(1, depth.clone(), sampler.clone()),
+                        WriteDescriptorSet::image_view(2, output.clone()),
+                    ],
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            outputs,
+            sets,
+            world_set: None,
+            descriptor_set_allocator: app.descriptor_set_allocator.clone(),
+            light_shafts,
+            depth_mode,
+            frame: 0,
+        }
+    }
+
+    /// The images `apply` writes to, one per frame.
+    pub fn outputs(&self) -> &[Arc<ImageView>] {
+        &self.outputs
+    }
+
+    /// Traces the light shafts against `top_level` from now on. Has to be
+    /// called before `apply` with light shafts, and again whenever the
+    /// structure is replaced.
+    pub fn set_acceleration_structure(&mut self, top_level: Arc<AccelerationStructure>) {
+        assert!(self.light_shafts);
+        self.world_set = Some(
+            DescriptorSet::new(
+                self.descriptor_set_allocator.clone(),
+                self.pipeline.layout().set_layouts()[1].clone(),
+                [WriteDescriptorSet::acceleration_structure(0, top_level)],
+                None,
+            )
+            .unwrap(),
+        );
+    }
+
+    /// Records fogging the frame `frame` was rendered into as seen by
+    /// `camera`, returning the image the result is written to.
+    pub fn apply(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        frame: usize,
+        camera: &Camera,
+        settings: &Fog,
+    ) -> Arc<ImageView> {
+        let output = &self.outputs[frame];
+        let [width, height, _] = output.image().extent();
+        let mut sets = vec![self.sets[frame].clone()];
+        if self.light_shafts {
+            sets.push(self.world_set.clone().expect("no acceleration structure"));
+        }
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                sets,
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    inverse_view_proj: (camera.proj * camera.view).invert().unwrap().into(),
+                    camera_position: camera.position.to_homogeneous().into(),
+                    far_depth: self.depth_mode.far_depth(),
+                    density: settings.density,
+                    height_falloff: settings.height_falloff,
+                    base_height: SEA_LEVEL as f32,
+                    samples: settings.samples.max(1),
+                    frame: self.frame,
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder
+                .dispatch([
+                    width.div_ceil(WORKGROUP_SIZE),
+                    height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                ])
+                .unwrap()
+        };
+        self.frame = self.frame.wrapping_add(1);
+        output.clone()
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+/// The queue a pass is recorded for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueueKind {
+    Graphics,
+    Compute,
+}
+
+/// How a pass uses an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Usage {
+    /// Read in shaders.
+    Sampled,
+    /// Written, and maybe read, as a storage image.
+    Storage,
+    /// Drawn to.
+    ColorAttachment,
+    DepthAttachment,
+    /// Copied from, like a frame read back by the CPU.
+    CopySource,
+}
+
+impl Usage {
+    pub fn writes(self) -> bool {
+        !matches!(self, Usage::Sampled | Usage::CopySource)
+    }
+
+    pub fn layout(self) -> Layout {
+        match self {
+            Usage::Sampled => Layout::ReadOnly,
+            Usage::Storage | Usage::CopySource => Layout::General,
+            Usage::ColorAttachment | Usage::DepthAttachment => Layout::Attachment,
+        }
+    }
+}
+
+/// The layouts the graph moves images between. Between passes recorded
+/// through vulkano, which transitions images itself, they are `General`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Layout {
+    General,
+    ReadOnly,
+    Attachment,
+}
+
+/// A barrier on an image between two uses of it in one submission. `None`
+/// stands for any use, before the submission or after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Barrier {
+    pub image: &'static str,
+    pub src: Option<Usage>,
+    pub dst: Option<Usage>,
+    pub old_layout: Layout,
+    pub new_layout: Layout,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ImageState {
+    /// The last use in the current submission and whether its pass was
+    /// external.
+    last_use: Option<(Usage, bool)>,
+    layout: Layout,
+}
+
+/// Derives the barriers between the passes of a frame from the images they
+/// declare using, as the passes are recorded in order. Vulkano orders the
+/// commands it records itself, so barriers are only needed around external
+/// passes, which record through raw handles vulkano doesn't see.
+#[derive(Debug, Default)]
+pub struct BarrierTracker {
+    images: HashMap<&'static str, ImageState>,
+    queue: Option<QueueKind>,
+}
+
+/// What has to happen before a pass is recorded.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PassBarriers {
+    /// Whether the pass starts a new submission, after the barriers in
+    /// `end_submission` ended the current one.
+    pub new_submission: bool,
+    pub end_submission: Vec<Barrier>,
+    /// Barriers for the pass, at the start of its submission if it's new.
+    pub before_pass: Vec<Barrier>,
+}
+
+impl BarrierTracker {
+    /// The barriers before a pass on `queue` using `uses`, `external` if it
+    /// records through raw handles.
+    pub fn pass(
+        &mut self,
+        queue: QueueKind,
+        external: bool,
+        uses: &[(&'static str, Usage)],
+    ) -> PassBarriers {
+        let mut barriers = PassBarriers::default();
+        if self.queue != Some(queue) {
+            barriers.new_submission = self.queue.is_some();
+            barriers.end_submission = self.end_submission();
+            self.queue = Some(queue);
+        }
+
+        for &(image, usage) in uses {
+            let state = self.images.entry(image).or_insert(ImageState {
+                last_use: None,
+                layout: Layout::General,
+            });
+            let new_layout = if external {
+                usage.layout()
+            } else {
+                Layout::General
+            };
+            let hazard = state.last_use.is_some_and(|(last_usage, last_external)| {
+                (last_external || external) && (last_usage.writes() || usage.writes())
+            });
+            if hazard || state.layout != new_layout {
+                barriers.before_pass.push(Barrier {
+                    image,
+                    src: state.last_use.map(|(last_usage, _)| last_usage),
+                    dst: Some(usage),
+                    old_layout: state.layout,
+                    new_layout,
+                });
+            }
+            state.last_use = Some((usage, external));
+            state.layout = new_layout;
+        }
+        barriers
+    }
+
+    /// The barriers at the end of the current submission, which return the
+    /// images external passes left in other layouts to `Layout::General`.
+    /// The next submission waits on a semaphore for this one.
+    pub fn end_submission(&mut self) -> Vec<Barrier> {
+        let mut barriers = Vec::new();
+        for (&image, state) in &mut self.images {
+            if state.layout != Layout::General {
+                barriers.push(Barrier {
+                    image,
+                    src: state.last_use.map(|(usage, _)| usage),
+                    dst: None,
+                    old_layout: state.layout,
+                    new_layout: Layout::General,
+                });
+                state.layout = Layout::General;
+            }
+            state.last_use = None;
+        }
+        barriers.sort_by_key(|barrier| barrier.image);
+        barriers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_barrier_tracker() {
+        let mut tracker = BarrierTracker::default();
+        let world = [
+            ("color", Usage::ColorAttachment),
+            ("depth", Usage::DepthAttachment),
+        ];
+        assert_eq!(
+            tracker.pass(QueueKind::Graphics, false, &world),
+            PassBarriers::default()
+        );
+        // Vulkano orders its own passes
+        let post = [("color", Usage::Sampled), ("output", Usage::Storage)];
+        assert_eq!(
+            tracker.pass(QueueKind::Graphics, false, &post),
+            PassBarriers::default()
+        );
+
+        // External passes get their layouts, in a submission of their own
+        // on another queue
+        let upscale = [("output", Usage::Sampled), ("upscaled", Usage::Storage)];
+        let barriers = tracker.pass(QueueKind::Compute, true, &upscale);
+        assert!(barriers.new_submission);
+        assert!(barriers.end_submission.is_empty());
+        assert_eq!(
+            barriers.before_pass,
+            [Barrier {
+                image: "output",
+                src: None,
+                dst: Some(Usage::Sampled),
+                old_layout: Layout::General,
+                new_layout: Layout::ReadOnly,
+            }]
+        );
+
+        // Writes of external passes are made visible to the next pass
+        let sharpen = [("upscaled", Usage::Storage)];
+        let barriers = tracker.pass(QueueKind::Compute, false, &sharpen);
+        assert!(!barriers.new_submission);
+        assert_eq!(
+            barriers.before_pass,
+            [Barrier {
+                image: "upscaled",
+                src: Some(Usage::Storage),
+                dst: Some(Usage::Storage),
+                old_layout: Layout::General,
+                new_layout: Layout::General,
+            }]
+        );
+
+        // Layouts are restored when the submission ends
+        let present = [("upscaled", Usage::Sampled)];
+        let barriers = tracker.pass(QueueKind::Graphics, false, &present);
+        assert!(barriers.new_submission);
+        assert_eq!(
+            barriers.end_submission,
+            [Barrier {
+                image: "output",
+                src: Some(Usage::Sampled),
+                dst: None,
+                old_layout: Layout::ReadOnly,
+                new_layout: Layout::General,
+            }]
+        );
+        assert!(barriers.before_pass.is_empty());
+        assert!(tracker.end_submission().is_empty());
+    }
+}
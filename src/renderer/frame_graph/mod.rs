@@ -0,0 +1,278 @@
+use std::{collections::HashMap, sync::Arc};
+
+use log::debug;
+use tracing::info_span;
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    device::{Device, Queue},
+    image::{view::ImageView, ImageAspects},
+    sync::GpuFuture,
+    VulkanObject,
+};
+
+use super::{frames::Frame, statistics::PassTimer};
+
+use self::barriers::{Barrier, BarrierTracker, Layout};
+pub use self::barriers::{QueueKind, Usage};
+
+mod barriers;
+
+/// The passes of a frame, recorded in order as they are added. Every pass
+/// declares the images it uses by name, from which the graph derives the
+/// barriers and layout transitions vulkano doesn't do itself, and a pass on
+/// another queue than the one before starts a new submission, waiting on a
+/// semaphore for the one before it.
+pub struct FrameGraph<'a> {
+    frame: &'a Frame,
+    graphics_queue: Arc<Queue>,
+    compute_queue: Arc<Queue>,
+    images: HashMap<&'static str, Arc<ImageView>>,
+    tracker: BarrierTracker,
+    /// The submission being recorded.
+    builder: Option<(QueueKind, RecordingCommandBuffer)>,
+    /// Ends with the last submission.
+    future: Option<Box<dyn GpuFuture>>,
+    submitted: bool,
+    timer: Option<&'a mut PassTimer>,
+}
+
+impl<'a> FrameGraph<'a> {
+    /// A graph of the command buffers of `frame`, executing after `before`.
+    pub fn new(
+        frame: &'a Frame,
+        graphics_queue: Arc<Queue>,
+        compute_queue: Arc<Queue>,
+        before: impl GpuFuture + 'static,
+    ) -> Self {
+        Self {
+            frame,
+            graphics_queue,
+            compute_queue,
+            images: HashMap::new(),
+            tracker: BarrierTracker::default(),
+            builder: None,
+            future: Some(before.boxed()),
+            submitted: false,
+            timer: None,
+        }
+    }
+
+    /// Times the GPU work of every pass recorded from now on with `timer`.
+    pub fn with_timer(mut self, timer: &'a mut PassTimer) -> Self {
+        self.timer = Some(timer);
+        self
+    }
+
+    /// Makes `image` usable by passes as `name`.
+    pub fn import(&mut self, name: &'static str, image: Arc<ImageView>) {
+        self.images.insert(name, image);
+    }
+
+    /// Records a pass on `queue` using the images in `uses` with vulkano,
+    /// returning what `record` returns.
+    pub fn pass<R>(
+        &mut self,
+        name: &'static str,
+        queue: QueueKind,
+        uses: &[(&'static str, Usage)],
+        record: impl FnOnce(&mut RecordingCommandBuffer) -> R,
+    ) -> R {
+        self.record(name, queue, false, uses, record)
+    }
+
+    /// Records a pass recording through raw handles, like FSR. Its images
+    /// are moved into the layouts of their `Usage` before it and made
+    /// visible to later passes after it.
+    pub fn external_pass<R>(
+        &mut self,
+        name: &'static str,
+        queue: QueueKind,
+        uses: &[(&'static str, Usage)],
+        record: impl FnOnce(&mut RecordingCommandBuffer) -> R,
+    ) -> R {
+        self.record(name, queue, true, uses, record)
+    }
+
+    fn record<R>(
+        &mut self,
+        name: &'static str,
+        queue: QueueKind,
+        external: bool,
+        uses: &[(&'static str, Usage)],
+        record: impl FnOnce(&mut RecordingCommandBuffer) -> R,
+    ) -> R {
+        for (image, _) in uses {
+            assert!(
+                self.images.contains_key(image),
+                "pass {} uses {}, which wasn't imported",
+                name,
+                image
+            );
+        }
+        debug!("Recording pass {} on the {:?} queue", name, queue);
+        let _span = info_span!("pass", name).entered();
+
+        let barriers = self.tracker.pass(queue, external, uses);
+        if barriers.new_submission {
+            let (queue, builder) = self.builder.take().unwrap();
+            record_barriers(
+                self.graphics_queue.device(),
+                &builder,
+                queue,
+                &barriers.end_submission,
+                &self.images,
+            );
+            self.execute(queue, builder);
+        }
+        if self.builder.is_none() {
+            let builder = self.frame.begin_command_buffer(self.queue(queue));
+            self.builder = Some((queue, builder));
+        }
+        let (_, builder) = self.builder.as_mut().unwrap();
+        record_barriers(
+            self.graphics_queue.device(),
+            builder,
+            queue,
+            &barriers.before_pass,
+            &self.images,
+        );
+        let frame = self.frame.index();
+        if let Some(timer) = &mut self.timer {
+            timer.begin(builder, frame, name);
+        }
+        let result = record(builder);
+        if let Some(timer) = &mut self.timer {
+            timer.end(builder);
+        }
+        result
+    }
+
+    fn queue(&self, queue: QueueKind) -> &Arc<Queue> {
+        match queue {
+            QueueKind::Graphics => &self.graphics_queue,
+            QueueKind::Compute => &self.compute_queue,
+        }
+    }
+
+    fn execute(&mut self, queue: QueueKind, builder: RecordingCommandBuffer) {
+        let command_buffer = builder.end().unwrap();
+        let mut future = self.future.take().unwrap();
+        if self.submitted {
+            future = future.then_signal_semaphore().boxed();
+        }
+        self.future = Some(
+            future
+                .then_execute(self.queue(queue).clone(), command_buffer)
+                .unwrap()
+                .boxed(),
+        );
+        self.submitted = true;
+    }
+
+    /// Ends the last submission, returning the future of all of them.
+    pub fn finish(mut self) -> Box<dyn GpuFuture> {
+        let barriers = self.tracker.end_submission();
+        if let Some((queue, builder)) = self.builder.take() {
+            record_barriers(
+                self.graphics_queue.device(),
+                &builder,
+                queue,
+                &barriers,
+                &self.images,
+            );
+            self.execute(queue, builder);
+        }
+        self.future.take().unwrap()
+    }
+}
+
+fn record_barriers(
+    device: &Device,
+    builder: &RecordingCommandBuffer,
+    queue: QueueKind,
+    barriers: &[Barrier],
+    images: &HashMap<&'static str, Arc<ImageView>>,
+) {
+    if barriers.is_empty() {
+        return;
+    }
+    let image_memory_barriers = barriers
+        .iter()
+        .map(|barrier| {
+            let image = images[barrier.image].image();
+            let (src_stage_mask, src_access_mask) = stage_access(barrier.src, queue);
+            let (dst_stage_mask, dst_access_mask) = stage_access(barrier.dst, queue);
+            let aspect_mask = if image.format().aspects().intersects(ImageAspects::DEPTH) {
+                ash::vk::ImageAspectFlags::DEPTH
+            } else {
+                ash::vk::ImageAspectFlags::COLOR
+            };
+            ash::vk::ImageMemoryBarrier2 {
+                src_stage_mask,
+                src_access_mask,
+                dst_stage_mask,
+                dst_access_mask,
+                old_layout: image_layout(barrier.old_layout),
+                new_layout: image_layout(barrier.new_layout),
+                image: image.handle(),
+                subresource_range: ash::vk::ImageSubresourceRange {
+                    aspect_mask,
+                    level_count: ash::vk::REMAINING_MIP_LEVELS,
+                    layer_count: ash::vk::REMAINING_ARRAY_LAYERS,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        })
+        .collect::<Vec<_>>();
+    let dependency_info =
+        ash::vk::DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
+    let fns = device.fns();
+    unsafe {
+        (fns.v1_3.cmd_pipeline_barrier2)(builder.raw().handle(), &dependency_info);
+    }
+}
+
+/// The stages and accesses of `usage` on `queue`, any of them for `None`.
+fn stage_access(
+    usage: Option<Usage>,
+    queue: QueueKind,
+) -> (ash::vk::PipelineStageFlags2, ash::vk::AccessFlags2) {
+    use ash::vk::{AccessFlags2, PipelineStageFlags2};
+
+    let shader_stages = match queue {
+        QueueKind::Graphics => {
+            PipelineStageFlags2::FRAGMENT_SHADER | PipelineStageFlags2::COMPUTE_SHADER
+        }
+        QueueKind::Compute => PipelineStageFlags2::COMPUTE_SHADER,
+    };
+    match usage {
+        None => (
+            PipelineStageFlags2::ALL_COMMANDS,
+            AccessFlags2::MEMORY_READ | AccessFlags2::MEMORY_WRITE,
+        ),
+        Some(Usage::Sampled) => (shader_stages, AccessFlags2::SHADER_SAMPLED_READ),
+        Some(Usage::Storage) => (
+            shader_stages,
+            AccessFlags2::SHADER_STORAGE_READ | AccessFlags2::SHADER_STORAGE_WRITE,
+        ),
+        Some(Usage::ColorAttachment) => (
+            PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            AccessFlags2::COLOR_ATTACHMENT_READ | AccessFlags2::COLOR_ATTACHMENT_WRITE,
+        ),
+        Some(Usage::DepthAttachment) => (
+            PipelineStageFlags2::EARLY_FRAGMENT_TESTS | PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                | AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+        Some(Usage::CopySource) => (PipelineStageFlags2::COPY, AccessFlags2::TRANSFER_READ),
+    }
+}
+
+fn image_layout(layout: Layout) -> ash::vk::ImageLayout {
+    match layout {
+        Layout::General => ash::vk::ImageLayout::GENERAL,
+        Layout::ReadOnly => ash::vk::ImageLayout::READ_ONLY_OPTIMAL,
+        Layout::Attachment => ash::vk::ImageLayout::ATTACHMENT_OPTIMAL,
+    }
+}
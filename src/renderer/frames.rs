@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, CommandBufferBeginInfo, CommandBufferLevel,
+        CommandBufferUsage, RecordingCommandBuffer,
+    },
+    device::{Device, Queue},
+    sync::{future::FenceSignalFuture, GpuFuture},
+    VulkanObject,
+};
+
+/// Number of frames the CPU may record ahead of the GPU.
+pub const FRAMES_IN_FLIGHT: usize = 2;
+
+pub type FrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture>>>;
+
+/// Resources used by one frame in flight. They are reused once the fence of
+/// the frame that used them last is signaled.
+pub struct Frame {
+    index: usize,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    fence: Option<FrameFence>,
+}
+
+impl Frame {
+    /// Index of this frame in `0..FramesInFlight::new(_, count)`, for
+    /// per-frame resources kept elsewhere.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// A one-time command buffer for this frame. It starts with a barrier
+    /// ordering it after everything submitted to the queue before, as render
+    /// targets are shared by all frames in flight.
+    pub fn begin_command_buffer(&self, queue: &Queue) -> RecordingCommandBuffer {
+        let builder = RecordingCommandBuffer::new(
+            self.command_buffer_allocator.clone(),
+            queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let memory_barrier = ash::vk::MemoryBarrier2 {
+            src_stage_mask: ash::vk::PipelineStageFlags2::ALL_COMMANDS,
+            src_access_mask: ash::vk::AccessFlags2::MEMORY_WRITE,
+            dst_stage_mask: ash::vk::PipelineStageFlags2::ALL_COMMANDS,
+            dst_access_mask: ash::vk::AccessFlags2::MEMORY_READ
+                | ash::vk::AccessFlags2::MEMORY_WRITE,
+            ..Default::default()
+        };
+        let memory_barriers = [memory_barrier];
+        let dependency_info = ash::vk::DependencyInfo::default().memory_barriers(&memory_barriers);
+        let fns = queue.device().fns();
+        unsafe {
+            (fns.v1_3.cmd_pipeline_barrier2)(builder.raw().handle(), &dependency_info);
+        }
+
+        builder
+    }
+
+    /// Flushes the work of this frame, returning the fence that signals when
+    /// it is done.
+    pub fn submit(&mut self, future: impl GpuFuture + 'static) -> FrameFence {
+        let fence = Arc::new(future.boxed().then_signal_fence_and_flush().unwrap());
+        self.fence = Some(fence.clone());
+        fence
+    }
+}
+
+/// A ring of `Frame`s. Recording a frame only waits for the GPU to finish the
+/// frame `FRAMES_IN_FLIGHT` frames earlier, so recording overlaps execution
+/// of the frames in between.
+pub struct FramesInFlight {
+    frames: Vec<Frame>,
+    index: usize,
+}
+
+impl FramesInFlight {
+    pub fn new(device: &Arc<Device>, count: usize) -> Self {
+        assert!(count > 0);
+        let frames = (0..count)
+            .map(|index| Frame {
+                index,
+                command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                    device.clone(),
+                    Default::default(),
+                )),
+                fence: None,
+            })
+            .collect::<Vec<_>>();
+        Self {
+            index: frames.len() - 1,
+            frames,
+        }
+    }
+
+    /// Moves on to the next frame, waiting until the GPU is done with its
+    /// resources.
+    pub fn next_frame(&mut self) -> &mut Frame {
+        self.index = (self.index + 1) % self.frames.len();
+        let frame = &mut self.frames[self.index];
+        if let Some(fence) = frame.fence.take() {
+            fence.wait(None).unwrap();
+        }
+        frame
+    }
+}
@@ -0,0 +1,65 @@
+#version 460
+
+// FXAA: finds edges from the luma of the diagonal neighbours and blurs
+// along them, with one or two pairs of samples depending on how far the
+// edge reaches.
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform writeonly image2D destination;
+
+// Contrast below which pixels are left alone, relative to the brightest
+// neighbour and absolute for dark areas
+const float EDGE_THRESHOLD = 1.0 / 8.0;
+const float EDGE_THRESHOLD_MIN = 1.0 / 32.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float REDUCE_MIN = 1.0 / 128.0;
+// Farthest the samples along an edge go, in pixels
+const float SPAN_MAX = 8.0;
+
+// Of the linear colors, brought closer to how bright they look
+float luma(vec3 rgb) { return sqrt(dot(rgb, vec3(0.299, 0.587, 0.114))); }
+
+void main() {
+  ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  ivec2 size = imageSize(destination);
+  if (any(greaterThanEqual(pixel, size))) {
+    return;
+  }
+
+  vec2 texel = 1.0 / vec2(size);
+  vec2 uv = (vec2(pixel) + 0.5) * texel;
+  vec3 rgb_m = texture(color, uv).rgb;
+  float luma_nw = luma(texture(color, uv + vec2(-1.0, -1.0) * texel).rgb);
+  float luma_ne = luma(texture(color, uv + vec2(1.0, -1.0) * texel).rgb);
+  float luma_sw = luma(texture(color, uv + vec2(-1.0, 1.0) * texel).rgb);
+  float luma_se = luma(texture(color, uv + vec2(1.0, 1.0) * texel).rgb);
+  float luma_m = luma(rgb_m);
+
+  float luma_min =
+      min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+  float luma_max =
+      max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+  if (luma_max - luma_min < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD)) {
+    imageStore(destination, pixel, vec4(rgb_m, 1.0));
+    return;
+  }
+
+  // Along the edge, across the gradient
+  vec2 dir = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)),
+                  (luma_nw + luma_sw) - (luma_ne + luma_se));
+  float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL,
+                         REDUCE_MIN);
+  float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+  dir = clamp(dir * rcp_dir_min, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel;
+
+  vec3 rgb_a = 0.5 * (texture(color, uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+                      texture(color, uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+  vec3 rgb_b = rgb_a * 0.5 + 0.25 * (texture(color, uv - dir * 0.5).rgb +
+                                     texture(color, uv + dir * 0.5).rgb);
+  // The wider samples crossed another edge
+  float luma_b = luma(rgb_b);
+  vec3 result = luma_b < luma_min || luma_b > luma_max ? rgb_a : rgb_b;
+  imageStore(destination, pixel, vec4(result, 1.0));
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::AllocationCreateInfo,
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    app::App,
+    memory::{MemoryCategory, TrackedAllocation},
+};
+
+mod cs {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/fxaa/fxaa.comp.glsl",
+    );
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Smooths edges in the finished image alone, without jitter or history,
+/// for when neither FSR nor TAA is used.
+pub struct FxaaPass {
+    pipeline: Arc<ComputePipeline>,
+    output: Arc<ImageView>,
+    /// Per source image, the set filtering it into `output`.
+    sets: Vec<Arc<DescriptorSet>>,
+    _memory: TrackedAllocation,
+}
+
+impl FxaaPass {
+    /// A pass filtering any of `color_images`, which must be single-sampled
+    /// and of the same extent, into an image of `format`.
+    pub fn new(app: &App, color_images: &[Arc<ImageView>], format: Format) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let cs = cs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .unwrap()
+        };
+
+        let extent = color_images[0].image().extent();
+        let image = Image::new(
+            app.memory_allocator(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                extent,
+                format,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap();
+        let _memory = app
+            .memory_tracker
+            .track_image(MemoryCategory::RenderTargets, &image);
+        let output = ImageView::new_default(image).unwrap();
+
+        // Samples between pixels along the edges
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let set_layout = pipeline.layout().set_layouts()[0].clone();
+        let sets = color_images
+            .iter()
+            .map(|color| {
+                assert_eq!(color.image().extent(), extent);
+                DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    set_layout.clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(0, color.clone(), sampler.clone()),
+                        WriteDescriptorSet::image_view(1, output.clone()),
+                    ],
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            output,
+            sets,
+            _memory,
+        }
+    }
+
+    /// The image `apply` writes to.
+    pub fn output(&self) -> &Arc<ImageView> {
+        &self.output
+    }
+
+    /// Records filtering `color_images[source]`, returning the image the
+    /// result is written to.
+    pub fn apply(&self, builder: &mut RecordingCommandBuffer, source: usize) -> Arc<ImageView> {
+        let [width, height, _] = self.output.image().extent();
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                self.sets[source].clone(),
+            )
+            .unwrap();
+        unsafe {
+            builder
+                .dispatch([
+                    width.div_ceil(WORKGROUP_SIZE),
+                    height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                ])
+                .unwrap()
+        };
+        self.output.clone()
+    }
+}
@@ -0,0 +1,35 @@
+#version 460
+
+// Builds one level of the Hi-Z pyramid: every texel is the farthest depth of
+// the source texels it covers, the smallest one with reversed depth.
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+layout(set = 0, binding = 1, r32f) uniform writeonly image2D destination;
+
+layout(push_constant) uniform PushConstants { uint reversed_depth; }
+pc;
+
+void main() {
+  ivec2 dst = ivec2(gl_GlobalInvocationID.xy);
+  ivec2 dst_size = imageSize(destination);
+  if (any(greaterThanEqual(dst, dst_size))) {
+    return;
+  }
+
+  // With an odd source size the last texel also covers the third source texel
+  ivec2 src_size = textureSize(source, 0);
+  ivec2 extra = ivec2(equal(dst, dst_size - 1)) * (src_size & 1);
+
+  bool reversed = pc.reversed_depth != 0;
+  float depth = reversed ? 1.0 : 0.0;
+  for (int y = 0; y < 2 + extra.y; ++y) {
+    for (int x = 0; x < 2 + extra.x; ++x) {
+      ivec2 src = min(dst * 2 + ivec2(x, y), src_size - 1);
+      float src_depth = texelFetch(source, src, 0).r;
+      depth = reversed ? min(depth, src_depth) : max(depth, src_depth);
+    }
+  }
+  imageStore(destination, dst, vec4(depth));
+}
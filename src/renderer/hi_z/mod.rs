@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode},
+        view::{ImageView, ImageViewCreateInfo},
+        Image, ImageAspects, ImageCreateInfo, ImageSubresourceRange, ImageType, ImageUsage,
+    },
+    memory::allocator::AllocationCreateInfo,
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    app::App,
+    camera::DepthMode,
+    memory::{MemoryCategory, TrackedAllocation},
+};
+
+mod cs {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/hi_z/hi_z.comp.glsl",
+    );
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// A max-depth mip chain of a depth buffer. Level 0 is half the size of the
+/// depth buffer; every texel holds the farthest depth of the area it covers,
+/// so anything behind it is hidden. With reversed depth the farthest depth
+/// is the smallest one.
+pub struct HiZPyramid {
+    pipeline: Arc<ComputePipeline>,
+    depth_mode: DepthMode,
+    view: Arc<ImageView>,
+    /// Per depth buffer, the set building level 0 from it.
+    source_sets: Vec<Arc<DescriptorSet>>,
+    /// The sets building level `i + 1` from level `i`.
+    level_sets: Vec<Arc<DescriptorSet>>,
+    level_extents: Vec<[u32; 2]>,
+    _memory: TrackedAllocation,
+}
+
+impl HiZPyramid {
+    /// A pyramid that can be built from any of `depth_images`, which must all
+    /// have the same extent and hold depth of `depth_mode`.
+    pub fn new(app: &App, depth_images: &[Arc<ImageView>], depth_mode: DepthMode) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let cs = cs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .unwrap()
+        };
+
+        let depth_extent = depth_images[0].image().extent();
+        let mut level_extents = vec![[
+            depth_extent[0].div_ceil(2).max(1),
+            depth_extent[1].div_ceil(2).max(1),
+        ]];
+        while *level_extents.last().unwrap() != [1, 1] {
+            let [width, height] = *level_extents.last().unwrap();
+            level_extents.push([width.div_ceil(2), height.div_ceil(2)]);
+        }
+
+        let image = Image::new(
+            app.memory_allocator(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                extent: [level_extents[0][0], level_extents[0][1], 1],
+                format: Format::R32_SFLOAT,
+                mip_levels: level_extents.len() as u32,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap();
+        let _memory = app
+            .memory_tracker
+            .track_image(MemoryCategory::RenderTargets, &image);
+
+        let level_view = |level: usize| {
+            ImageView::new(
+                image.clone(),
+                ImageViewCreateInfo {
+                    subresource_range: ImageSubresourceRange {
+                        aspects: ImageAspects::COLOR,
+                        mip_levels: level as u32..level as u32 + 1,
+                        array_layers: 0..1,
+                    },
+                    ..ImageViewCreateInfo::from_image(&image)
+                },
+            )
+            .unwrap()
+        };
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                mipmap_mode: SamplerMipmapMode::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let set_layout = pipeline.layout().set_layouts()[0].clone();
+        let descriptor_set = |source: Arc<ImageView>, destination: Arc<ImageView>| {
+            DescriptorSet::new(
+                app.descriptor_set_allocator.clone(),
+                set_layout.clone(),
+                [
+                    WriteDescriptorSet::image_view_sampler(0, source, sampler.clone()),
+                    WriteDescriptorSet::image_view(1, destination),
+                ],
+                None,
+            )
+            .unwrap()
+        };
+
+        let source_sets = depth_images
+            .iter()
+            .map(|depth| {
+                assert_eq!(depth.image().extent(), depth_extent);
+                descriptor_set(depth.clone(), level_view(0))
+            })
+            .collect();
+        let level_sets = (1..level_extents.len())
+            .map(|level| descriptor_set(level_view(level - 1), level_view(level)))
+            .collect();
+
+        Self {
+            pipeline,
+            depth_mode,
+            view: ImageView::new_default(image).unwrap(),
+            source_sets,
+            level_sets,
+            level_extents,
+            _memory,
+        }
+    }
+
+    pub fn depth_mode(&self) -> DepthMode {
+        self.depth_mode
+    }
+
+    /// All levels, for sampling with `texelFetch`.
+    pub fn view(&self) -> &Arc<ImageView> {
+        &self.view
+    }
+
+    /// Records building the pyramid from `depth_images[source]`.
+    pub fn build(&self, builder: &mut RecordingCommandBuffer, source: usize) {
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    reversed_depth: self.depth_mode.is_reversed() as u32,
+                },
+            )
+            .unwrap();
+        let sets = [&self.source_sets[source]]
+            .into_iter()
+            .chain(&self.level_sets);
+        for (set, [width, height]) in sets.zip(&self.level_extents) {
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Compute,
+                    self.pipeline.layout().clone(),
+                    0,
+                    set.clone(),
+                )
+                .unwrap();
+            unsafe {
+                builder
+                    .dispatch([
+                        width.div_ceil(WORKGROUP_SIZE),
+                        height.div_ceil(WORKGROUP_SIZE),
+                        1,
+                    ])
+                    .unwrap()
+            };
+        }
+    }
+}
@@ -1,21 +1,134 @@
-mod culling;
+pub mod acceleration;
+pub mod ambient_occlusion;
+pub mod culling;
+pub mod encode;
+pub mod fog;
+pub mod frame_graph;
+pub mod frames;
+pub mod fxaa;
+pub mod hi_z;
+pub mod motion_blur;
+pub mod render_clouds;
+pub mod render_entities;
 pub mod render_faces;
+pub mod render_hud;
+pub mod render_particles;
+pub mod render_viewmodel;
+pub mod ssr;
+pub mod staging;
+pub mod statistics;
+pub mod taa;
+pub mod underwater;
+pub mod voxel_dda;
+pub mod voxel_renderer;
 
 use std::sync::Arc;
 
 use vulkano::{
-    command_buffer::{RecordingCommandBuffer, RenderingAttachmentInfo, RenderingInfo},
-    format::ClearValue,
-    image::view::ImageView,
-    pipeline::graphics::viewport::Viewport,
-    render_pass::{AttachmentLoadOp, AttachmentStoreOp},
+    command_buffer::{
+        RecordingCommandBuffer, RenderingAttachmentInfo, RenderingAttachmentResolveInfo,
+        RenderingInfo,
+    },
+    device::Device,
+    format::{ClearValue, Format},
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode},
+        view::ImageView,
+    },
+    pipeline::graphics::{depth_stencil::CompareOp, viewport::Viewport},
+    render_pass::{AttachmentLoadOp, AttachmentStoreOp, ResolveMode},
 };
 
+use crate::camera::DepthMode;
+
+/// An image the geometry passes draw to. With MSAA it is multisampled and
+/// resolved into `resolve` by the last pass writing it, since the Hi-Z
+/// pyramid and FSR read single samples.
+#[derive(Clone)]
+pub struct Attachment {
+    pub image: Arc<ImageView>,
+    pub resolve: Option<Arc<ImageView>>,
+}
+
+impl Attachment {
+    /// The image holding the result once the passes are done.
+    pub fn resolved(&self) -> &Arc<ImageView> {
+        self.resolve.as_ref().unwrap_or(&self.image)
+    }
+
+    /// The resolve of this attachment at the end of a pass, if it has one.
+    fn resolve_info(&self, mode: ResolveMode) -> Option<RenderingAttachmentResolveInfo> {
+        self.resolve
+            .clone()
+            .map(|image_view| RenderingAttachmentResolveInfo {
+                mode,
+                ..RenderingAttachmentResolveInfo::image_view(image_view)
+            })
+    }
+}
+
+/// The format of the color the world is rendered to and post-processed in,
+/// which is linear until `encode` writes it to the swapchain.
+pub const COLOR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+/// The color behind everything drawn, also reflected where reflections
+/// find nothing else.
+pub const SKY_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// Motion vectors are drawn as the change of the NDC position from the
+/// current frame to the previous one. This scales them to UV units, in
+/// which the image is 1 across, for the passes reprojecting with them.
+pub const MOTION_VECTOR_UV_SCALE: f32 = 0.5;
+
+/// Mip LOD bias picking the mips textures would have at `display_size` when
+/// rendering at `render_size` for upscaling, so they don't come out blurred.
+pub fn mip_lod_bias(render_size: [u32; 2], display_size: [u32; 2]) -> f32 {
+    (render_size[0] as f32 / display_size[0] as f32).log2()
+}
+
+/// The sampler of the block textures, shared by every pipeline drawing
+/// blocks. Pixelated, with `mip_lod_bias` from `mip_lod_bias`.
+pub fn block_sampler(device: Arc<Device>, mip_lod_bias: f32) -> Arc<Sampler> {
+    Sampler::new(
+        device,
+        SamplerCreateInfo {
+            mag_filter: Filter::Nearest,
+            min_filter: Filter::Nearest,
+            mipmap_mode: SamplerMipmapMode::Nearest,
+            address_mode: [SamplerAddressMode::Repeat; 3],
+            mip_lod_bias,
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+/// The format of the world's depth buffer. Reversed depth needs floats to
+/// keep its precision far away.
+pub fn depth_format(depth_mode: DepthMode) -> Format {
+    match depth_mode {
+        DepthMode::Standard => Format::D16_UNORM,
+        DepthMode::InfiniteReversed => Format::D32_SFLOAT,
+    }
+}
+
+/// The depth test keeping what is nearer.
+pub fn depth_compare_op(depth_mode: DepthMode) -> CompareOp {
+    match depth_mode {
+        DepthMode::Standard => CompareOp::Less,
+        DepthMode::InfiniteReversed => CompareOp::Greater,
+    }
+}
+
+/// Begins the rendering of the opaque world, clearing `depth` to the far
+/// depth of `depth_mode`. Depth is final after this pass, so it is resolved
+/// here.
 pub fn draw(
     mut builder: &mut RecordingCommandBuffer,
     dst_image: Arc<ImageView>,
     motion_vector_image: Arc<ImageView>,
-    depth_image: Arc<ImageView>,
+    depth: &Attachment,
+    depth_mode: DepthMode,
     viewport: Viewport,
     record_fn: impl FnOnce(&mut RecordingCommandBuffer),
 ) {
@@ -25,8 +138,7 @@ pub fn draw(
                 Some(RenderingAttachmentInfo {
                     load_op: AttachmentLoadOp::Clear,
                     store_op: AttachmentStoreOp::Store,
-                    clear_value: Some([0.0, 0.0, 0.0, 1.0].into()),
-                    // resolve_info: Some(RenderingAttachmentResolveInfo::image_view(dst_image)),
+                    clear_value: Some(SKY_COLOR.into()),
                     ..RenderingAttachmentInfo::image_view(dst_image)
                 }),
                 Some(RenderingAttachmentInfo {
@@ -36,11 +148,104 @@ pub fn draw(
                     ..RenderingAttachmentInfo::image_view(motion_vector_image)
                 }),
             ],
+            depth_attachment: Some(RenderingAttachmentInfo {
+                load_op: AttachmentLoadOp::Clear,
+                // Kept for the translucent pass and FSR
+                store_op: AttachmentStoreOp::Store,
+                clear_value: Some(ClearValue::Depth(depth_mode.far_depth())),
+                // Averaged depth would be nowhere, so one sample is kept
+                resolve_info: depth.resolve_info(ResolveMode::SampleZero),
+                ..RenderingAttachmentInfo::image_view(depth.image.clone())
+            }),
+
+            ..Default::default()
+        })
+        .unwrap()
+        .set_viewport(0, [viewport].into_iter().collect())
+        .unwrap();
+
+    record_fn(&mut builder);
+
+    builder.end_rendering().unwrap();
+}
+
+/// Begins the rendering of what is blended over the image `draw` left, like
+/// particles. Depth from `draw` is tested but not written, and the reactive
+/// mask for FSR is cleared, so only what this pass draws ends up in it.
+pub fn draw_translucent(
+    mut builder: &mut RecordingCommandBuffer,
+    dst_image: Arc<ImageView>,
+    reactive: &Attachment,
+    depth_image: Arc<ImageView>,
+    viewport: Viewport,
+    record_fn: impl FnOnce(&mut RecordingCommandBuffer),
+) {
+    builder
+        .begin_rendering(RenderingInfo {
+            color_attachments: vec![
+                Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Load,
+                    store_op: AttachmentStoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(dst_image)
+                }),
+                Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Clear,
+                    store_op: AttachmentStoreOp::Store,
+                    clear_value: Some([0.0, 0.0, 0.0, 0.0].into()),
+                    resolve_info: reactive.resolve_info(ResolveMode::Average),
+                    ..RenderingAttachmentInfo::image_view(reactive.image.clone())
+                }),
+            ],
+            depth_attachment: Some(RenderingAttachmentInfo {
+                load_op: AttachmentLoadOp::Load,
+                store_op: AttachmentStoreOp::Store,
+                ..RenderingAttachmentInfo::image_view(depth_image)
+            }),
+
+            ..Default::default()
+        })
+        .unwrap()
+        .set_viewport(0, [viewport].into_iter().collect())
+        .unwrap();
+
+    record_fn(&mut builder);
+
+    builder.end_rendering().unwrap();
+}
+
+/// Begins the rendering of the viewmodel over the finished image. It gets
+/// a cleared depth buffer of its own, `viewmodel_depth_image`, so it is
+/// never hidden by the world, which keeps its depth for Hi-Z culling and FSR.
+/// As the last geometry pass, it resolves the color and motion vectors.
+pub fn draw_viewmodel(
+    mut builder: &mut RecordingCommandBuffer,
+    color: &Attachment,
+    motion_vector: &Attachment,
+    viewmodel_depth_image: Arc<ImageView>,
+    viewport: Viewport,
+    record_fn: impl FnOnce(&mut RecordingCommandBuffer),
+) {
+    builder
+        .begin_rendering(RenderingInfo {
+            color_attachments: vec![
+                Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Load,
+                    store_op: AttachmentStoreOp::Store,
+                    resolve_info: color.resolve_info(ResolveMode::Average),
+                    ..RenderingAttachmentInfo::image_view(color.image.clone())
+                }),
+                Some(RenderingAttachmentInfo {
+                    load_op: AttachmentLoadOp::Load,
+                    store_op: AttachmentStoreOp::Store,
+                    resolve_info: motion_vector.resolve_info(ResolveMode::Average),
+                    ..RenderingAttachmentInfo::image_view(motion_vector.image.clone())
+                }),
+            ],
             depth_attachment: Some(RenderingAttachmentInfo {
                 load_op: AttachmentLoadOp::Clear,
                 store_op: AttachmentStoreOp::DontCare,
                 clear_value: Some(ClearValue::Depth(1.0)),
-                ..RenderingAttachmentInfo::image_view(depth_image)
+                ..RenderingAttachmentInfo::image_view(viewmodel_depth_image)
             }),
 
             ..Default::default()
@@ -53,3 +258,37 @@ pub fn draw(
 
     builder.end_rendering().unwrap();
 }
+
+/// Begins the rendering of 2D overlays like the HUD onto the upscaled
+/// `dst_image`, covering all of it.
+pub fn draw_overlay(
+    mut builder: &mut RecordingCommandBuffer,
+    dst_image: Arc<ImageView>,
+    record_fn: impl FnOnce(&mut RecordingCommandBuffer),
+) {
+    let [width, height, _] = dst_image.image().extent();
+    builder
+        .begin_rendering(RenderingInfo {
+            color_attachments: vec![Some(RenderingAttachmentInfo {
+                load_op: AttachmentLoadOp::Load,
+                store_op: AttachmentStoreOp::Store,
+                ..RenderingAttachmentInfo::image_view(dst_image)
+            })],
+            ..Default::default()
+        })
+        .unwrap()
+        .set_viewport(
+            0,
+            [Viewport {
+                extent: [width as f32, height as f32],
+                ..Default::default()
+            }]
+            .into_iter()
+            .collect(),
+        )
+        .unwrap();
+
+    record_fn(&mut builder);
+
+    builder.end_rendering().unwrap();
+}
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::AllocationCreateInfo,
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    app::App,
+    memory::{MemoryCategory, TrackedAllocation},
+    settings::MotionBlur,
+};
+
+use super::MOTION_VECTOR_UV_SCALE;
+
+mod cs {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/motion_blur/motion_blur.comp.glsl",
+    );
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Blurs the finished, upscaled image along the motion vectors the frame
+/// was rendered with, after anti-aliasing so it doesn't blur into history.
+pub struct MotionBlurPass {
+    pipeline: Arc<ComputePipeline>,
+    inputs: Vec<Arc<ImageView>>,
+    output: Arc<ImageView>,
+    /// Per input image and frame, the set blurring it into `output`.
+    sets: Vec<Vec<Arc<DescriptorSet>>>,
+    _memory: TrackedAllocation,
+}
+
+impl MotionBlurPass {
+    /// A pass blurring any of `inputs`, all of the same extent, with the
+    /// motion vectors of a frame in `motion_vector_images` into an image of
+    /// `format`. The motion vectors may be smaller than the inputs.
+    pub fn new(
+        app: &App,
+        inputs: &[Arc<ImageView>],
+        motion_vector_images: &[Arc<ImageView>],
+        format: Format,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let cs = cs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .unwrap()
+        };
+
+        let extent = inputs[0].image().extent();
+        let image = Image::new(
+            app.memory_allocator(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                extent,
+                format,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap();
+        let _memory = app
+            .memory_tracker
+            .track_image(MemoryCategory::RenderTargets, &image);
+        let output = ImageView::new_default(image).unwrap();
+
+        // Samples between pixels along the motion, and the motion vectors
+        // between render pixels
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let set_layout = pipeline.layout().set_layouts()[0].clone();
+        let sets = inputs
+            .iter()
+            .map(|input| {
+                assert_eq!(input.image().extent(), extent);
+                motion_vector_images
+                    .iter()
+                    .map(|motion_vector| {
+                        DescriptorSet::new(
+                            app.descriptor_set_allocator.clone(),
+                            set_layout.clone(),
+                            [
+                                WriteDescriptorSet::image_view_sampler(
+                                    0,
+                                    input.clone(),
+                                    sampler.clone(),
+                                ),
+                                WriteDescriptorSet::image_view_sampler(
+                                    1,
+                                    motion_vector.clone(),
+                                    sampler.clone(),
+                                ),
+                                WriteDescriptorSet::image_view(2, output.clone()),
+                            ],
+                            None,
+                        )
+                        .unwrap()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            inputs: inputs.to_vec(),
+            output,
+            sets,
+            _memory,
+        }
+    }
+
+    /// The image `apply` writes to.
+    pub fn output(&self) -> &Arc<ImageView> {
+        &self.output
+    }
+
+    /// Records blurring `input`, one of the inputs the pass was created
+    /// with, along `motion_vector_images[frame]`, returning the image the
+    /// result is written to.
+    pub fn apply(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        input: &Arc<ImageView>,
+        frame: usize,
+        settings: &MotionBlur,
+    ) -> Arc<ImageView> {
+        let input = self
+            .inputs
+            .iter()
+            .position(|i| Arc::ptr_eq(i, input))
+            .expect("not an input of the pass");
+        let [width, height, _] = self.output.image().extent();
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                self.sets[input][frame].clone(),
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    intensity: settings.intensity,
+                    motion_vector_scale: MOTION_VECTOR_UV_SCALE,
+                    samples: settings.samples.max(1),
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder
+                .dispatch([
+                    width.div_ceil(WORKGROUP_SIZE),
+                    height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                ])
+                .unwrap()
+        };
+        self.output.clone()
+    }
+}
@@ -0,0 +1,39 @@
+#version 460
+
+// Averages the image along each pixel's motion since the previous frame,
+// centered on the pixel so edges smear both ways like a shutter open
+// across the frame.
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D motion_vectors;
+layout(set = 0, binding = 2) uniform writeonly image2D destination;
+
+layout(push_constant) uniform PushConstants {
+  float intensity;            // fraction of the motion blurred over
+  float motion_vector_scale;  // from motion vectors to UV units
+  uint samples;               // at least 1
+}
+pc;
+
+void main() {
+  ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  ivec2 size = imageSize(destination);
+  if (any(greaterThanEqual(pixel, size))) {
+    return;
+  }
+
+  // The motion vectors may be at render resolution, but they are relative
+  // to the screen, so sampling them at the same UV is enough
+  vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+  vec2 motion = texture(motion_vectors, uv).xy * pc.motion_vector_scale *
+                pc.intensity;
+
+  vec3 sum = vec3(0.0);
+  for (uint i = 0; i < pc.samples; ++i) {
+    float t = (float(i) + 0.5) / float(pc.samples) - 0.5;
+    sum += texture(color, uv + motion * t).rgb;
+  }
+  imageStore(destination, pixel, vec4(sum / float(pc.samples), 1.0));
+}
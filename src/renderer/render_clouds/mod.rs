@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use cgmath::{Vector2, Zero};
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    image::SampleCount,
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{app::App, camera::DepthMode, weather::Weather};
+
+use super::{depth_compare_op, render_faces::Camera};
+
+mod vs {
+    vulkano_shaders::shader!(
+        ty: "vertex",
+        path: "src/renderer/render_clouds/render_clouds.vert.glsl",
+    );
+}
+
+mod fs {
+    vulkano_shaders::shader!(
+        ty: "fragment",
+        path: "src/renderer/render_clouds/render_clouds.frag.glsl",
+    );
+}
+
+/// Height of the cloud layer, in blocks.
+pub const CLOUD_HEIGHT: f32 = 192.0;
+/// How far from the camera clouds are drawn, in blocks.
+const CLOUD_RADIUS: f32 = 768.0;
+/// Drift of the clouds, in blocks per second.
+const WIND: Vector2<f32> = Vector2::new(1.5, 0.5);
+/// Distance after which the cloud pattern repeats, in blocks: 256 cells of
+/// 12 blocks, as in the fragment shader. The drift wraps around at it, so
+/// it keeps its precision.
+const PATTERN_PERIOD: f32 = 256.0 * 12.0;
+
+/// Draws a flat layer of blocky clouds drifting with the wind, into the
+/// render targets of the block faces, testing and writing the same depth.
+/// Motion vectors follow the drift, so FSR and TAA reproject the clouds
+/// instead of smearing them.
+pub struct RenderCloudsPipeline {
+    pipeline: Arc<GraphicsPipeline>,
+    scroll: Vector2<f32>,
+    /// The drift since the last frame.
+    scroll_delta: Vector2<f32>,
+}
+
+impl RenderCloudsPipeline {
+    pub fn new(
+        app: &App,
+        rendering_info: PipelineRenderingCreateInfo,
+        samples: SampleCount,
+        depth_mode: DepthMode,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let fs = fs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    // Vertices come from the vertex index
+                    vertex_input_state: Some(VertexInputState::default()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    // Seen from above and below
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState {
+                        rasterization_samples: samples,
+                        ..Default::default()
+                    }),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        rendering_info.color_attachment_formats.len() as u32,
+                        ColorBlendAttachmentState::default(),
+                    )),
+                    depth_stencil_state: Some(DepthStencilState {
+                        depth: Some(DepthState {
+                            compare_op: depth_compare_op(depth_mode),
+                            write_enable: true,
+                        }),
+                        ..Default::default()
+                    }),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(rendering_info.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .unwrap()
+        };
+
+        Self {
+            pipeline,
+            scroll: Vector2::zero(),
+            scroll_delta: Vector2::zero(),
+        }
+    }
+
+    /// Drifts the clouds by `delta` seconds of wind.
+    pub fn update(&mut self, delta: f32) {
+        self.scroll_delta = WIND * delta;
+        self.scroll = (self.scroll + self.scroll_delta).map(|x| x.rem_euclid(PATTERN_PERIOD));
+    }
+
+    /// Records the draw of the clouds, inside the rendering of the block
+    /// faces, darkened as `weather` darkens the sky.
+    pub fn render(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        previous_camera: &Camera,
+        camera: &Camera,
+        weather: &Weather,
+    ) {
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                vs::PushConstants {
+                    current_view_proj: (camera.proj * camera.view).into(),
+                    previous_view_proj: (previous_camera.proj * previous_camera.view).into(),
+                    camera_position: camera.position.to_homogeneous().into(),
+                    scroll: self.scroll.into(),
+                    scroll_delta: self.scroll_delta.into(),
+                    jitter: camera.jitter.into(),
+                    height: CLOUD_HEIGHT,
+                    radius: CLOUD_RADIUS,
+                    sky_light: weather.sky_light(),
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder.draw(6, 1, 0, 0).unwrap();
+        }
+    }
+}
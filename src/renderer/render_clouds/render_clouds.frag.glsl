@@ -0,0 +1,89 @@
+#version 460
+
+// Blocky clouds like Minecraft's: the pattern is a grid of cells, each
+// either cloud or clear sky, which is discarded.
+
+layout(location = 0) in VertexOut {
+  vec4 current_position;
+  vec4 previous_position;
+  vec2 pattern_position;
+}
+v_out;
+
+// Same as in the vertex shader
+layout(push_constant) uniform PushConstants {
+  mat4 current_view_proj;
+  mat4 previous_view_proj;
+  vec4 camera_position;
+  vec2 scroll;
+  vec2 scroll_delta;
+  vec2 jitter;
+  float height;
+  float radius;
+  float sky_light;  // fraction of the sun and sky light let through
+}
+pc;
+
+layout(location = 0) out vec4 frag_color;
+layout(location = 1) out vec2 motion_vector;
+
+const float PI = 3.14159265;
+// Same as in render_faces.frag.glsl
+const vec3 LIGHT_DIRECTION = normalize(vec3(0.3, 1.0, 0.5));
+const vec3 SUN_COLOR = vec3(1.0, 0.97, 0.92) * 0.4 * PI;
+const vec3 SKY_AMBIENT = vec3(0.62, 0.66, 0.75);
+const vec3 GROUND_AMBIENT = vec3(0.5, 0.47, 0.44);
+// Width of a cell, in blocks
+const float CELL_SIZE = 12.0;
+// Cells after which the pattern repeats, as `PATTERN_PERIOD` expects
+const int PERIOD = 256;
+// Averaged noise of a cell and its neighbors below which the cell is
+// cloud. The averages are around 0.5, so this covers about a third of the
+// sky
+const float THRESHOLD = 0.45;
+const vec3 CLOUD_ALBEDO = vec3(0.95);
+
+uint hash(uint x) {
+  x ^= x >> 16;
+  x *= 0x7feb352du;
+  x ^= x >> 15;
+  x *= 0x846ca68bu;
+  x ^= x >> 16;
+  return x;
+}
+
+bool cloudy(ivec2 cell) {
+  // Cells of neighbors clump together, so clouds aren't single cells
+  float sum = 0.0;
+  for (int x = -1; x <= 1; ++x) {
+    for (int y = -1; y <= 1; ++y) {
+      uvec2 c = uvec2((cell + ivec2(x, y)) & (PERIOD - 1));
+      float weight = x == 0 && y == 0 ? 2.0 : 1.0;
+      sum += weight * float(hash(c.x * uint(PERIOD) + c.y) & 0xffffu) / 65535.0;
+    }
+  }
+  return sum / 10.0 < THRESHOLD;
+}
+
+void main() {
+  ivec2 cell = ivec2(floor(v_out.pattern_position / CELL_SIZE));
+  // Round, so the clouds end as far away in every direction
+  float distance_to_camera =
+      length(v_out.pattern_position + pc.scroll - pc.camera_position.xz);
+  if (!cloudy(cell) || distance_to_camera > pc.radius) {
+    discard;
+  }
+
+  motion_vector = v_out.previous_position.xy / v_out.previous_position.w -
+                  v_out.current_position.xy / v_out.current_position.w;
+
+  // Lit from above by the sun, from below by the ground
+  bool below = pc.camera_position.y < pc.height;
+  vec3 color = below
+                   ? GROUND_AMBIENT * CLOUD_ALBEDO
+                   : (SUN_COLOR * LIGHT_DIRECTION.y / PI + SKY_AMBIENT) *
+                         CLOUD_ALBEDO;
+  color *= pc.sky_light;
+  // Alpha is 1 minus the reflectivity, which SSR reads
+  frag_color = vec4(color, 1.0);
+}
@@ -0,0 +1,50 @@
+#version 460
+
+// A horizontal quad at the height of the clouds, centered on the camera so
+// it never ends nearby.
+
+layout(push_constant) uniform PushConstants {
+  mat4 current_view_proj;
+  mat4 previous_view_proj;
+  vec4 camera_position;
+  // How far the clouds have drifted, and how far since the last frame
+  vec2 scroll;
+  vec2 scroll_delta;
+  vec2 jitter;
+  float height;
+  float radius;
+  float sky_light;  // for the fragment shader
+}
+pc;
+
+layout(location = 0) out VertexOut {
+  vec4 current_position;
+  vec4 previous_position;
+  // Where on the cloud pattern the vertex is
+  vec2 pattern_position;
+}
+v_out;
+
+// Corners of the two triangles of the quad
+const vec2 corners[6] = {
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(-1.0, 1.0),
+    vec2(1.0, -1.0),  vec2(1.0, 1.0),  vec2(-1.0, 1.0),
+};
+
+void main() {
+  vec2 xz = pc.camera_position.xz + corners[gl_VertexIndex] * pc.radius;
+  vec4 vertex = vec4(xz.x, pc.height, xz.y, 1.0);
+  // The quad stays where it is, but the pattern on it drifted, so the
+  // point of the pattern here was behind by the drift last frame
+  vec4 previous_vertex =
+      vertex - vec4(pc.scroll_delta.x, 0.0, pc.scroll_delta.y, 0.0);
+
+  mat4 jitterTransform = mat4(1.0);
+  jitterTransform[3] = vec4(pc.jitter, 0.0, 1.0);
+
+  vec4 currentPosition = pc.current_view_proj * vertex;
+  gl_Position = jitterTransform * currentPosition;
+  v_out.current_position = currentPosition;
+  v_out.previous_position = pc.previous_view_proj * previous_vertex;
+  v_out.pattern_position = xz - pc.scroll;
+}
@@ -0,0 +1,321 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy_ecs::entity::Entity;
+use cgmath::Matrix4;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    image::{sampler::Sampler, view::ImageView, SampleCount},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    app::App,
+    biome::{pack_tint_color, BiomeColors},
+    camera::DepthMode,
+    entity::{Renderable, Transform},
+    memory::{MemoryCategory, TrackedAllocation},
+    texture::MISSING_TEXTURE,
+    types::BlockRegistry,
+};
+
+use super::{
+    depth_compare_op,
+    frames::FRAMES_IN_FLIGHT,
+    render_faces::{Camera, GPU_FACE_DIRECTIONS},
+};
+
+/// Entities drawn per frame; the rest are skipped.
+pub const MAX_ENTITIES: usize = 4096;
+
+/// Vertices of the six faces of a box, as two triangles each.
+const BOX_VERTICES: u32 = 36;
+
+mod vs {
+    vulkano_shaders::shader!(
+        ty: "vertex",
+        path: "src/renderer/render_entities/render_entities.vert.glsl",
+    );
+}
+
+mod fs {
+    vulkano_shaders::shader!(
+        ty: "fragment",
+        path: "src/renderer/render_entities/render_entities.frag.glsl",
+    );
+}
+
+/// Texture of every face of every block type, in GPU face order.
+pub(super) fn face_textures(block_registry: &BlockRegistry) -> Vec<[u32; 6]> {
+    let fallback = block_registry
+        .texture_registry
+        .get_index_of(MISSING_TEXTURE)
+        .unwrap_or(0);
+    block_registry
+        .block_types
+        .values()
+        .map(|block_type| {
+            GPU_FACE_DIRECTIONS
+                .map(|direction| block_type.texture(direction).unwrap_or(fallback) as u32)
+        })
+        .collect()
+}
+
+/// Packed tint of every block type. Entities aren't in a biome, so tinted
+/// blocks get the default colors.
+pub(super) fn tints(block_registry: &BlockRegistry) -> Vec<u32> {
+    block_registry
+        .block_types
+        .values()
+        .map(|block_type| pack_tint_color(BiomeColors::default().tint_color(block_type.tint)))
+        .collect()
+}
+
+/// Draws the `Renderable` entities as instanced boxes and billboards into
+/// the render targets of the block faces, testing and writing the same
+/// depth. Motion vectors come from the transform each entity had the frame
+/// before, and for billboards the camera they faced then, so FSR and TAA
+/// reproject moving entities correctly.
+pub struct RenderEntitiesPipeline {
+    pipeline: Arc<GraphicsPipeline>,
+    /// The interpolated transforms of each frame in flight, rewritten while
+    /// the frame before may still be drawing its own.
+    instance_buffers: Vec<Subbuffer<[vs::Instance]>>,
+    instance_sets: Vec<Arc<DescriptorSet>>,
+    texture_set: Arc<DescriptorSet>,
+    _memory: Vec<TrackedAllocation>,
+
+    /// Texture of every face of every block type, in GPU face order.
+    face_textures: Vec<[u32; 6]>,
+    tints: Vec<u32>,
+    /// The model matrix every entity was drawn with in the last frame.
+    previous_models: HashMap<Entity, Matrix4<f32>>,
+    instance_count: u32,
+}
+
+impl RenderEntitiesPipeline {
+    /// `block_textures` are the layers `TextureId`s index, see
+    /// `RenderFacesPipeline::block_textures`.
+    pub fn new(
+        app: &App,
+        rendering_info: PipelineRenderingCreateInfo,
+        samples: SampleCount,
+        block_registry: &BlockRegistry,
+        block_textures: Arc<ImageView>,
+        block_sampler: Arc<Sampler>,
+        depth_mode: DepthMode,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let fs = fs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    // Vertices come from the vertex index
+                    vertex_input_state: Some(VertexInputState::default()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState {
+                        rasterization_samples: samples,
+                        ..Default::default()
+                    }),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        rendering_info.color_attachment_formats.len() as u32,
+                        ColorBlendAttachmentState::default(),
+                    )),
+                    depth_stencil_state: Some(DepthStencilState {
+                        depth: Some(DepthState {
+                            compare_op: depth_compare_op(depth_mode),
+                            write_enable: true,
+                        }),
+                        ..Default::default()
+                    }),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(rendering_info.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .unwrap()
+        };
+
+        let set_layouts = pipeline.layout().set_layouts();
+        let mut memory = Vec::new();
+        let instance_buffers = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let buffer = Buffer::new_slice::<vs::Instance>(
+                    app.context.memory_allocator().clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::STORAGE_BUFFER,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                            | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..Default::default()
+                    },
+                    MAX_ENTITIES as u64,
+                )
+                .unwrap();
+                memory.push(
+                    app.memory_tracker
+                        .track_buffer(MemoryCategory::EntityBuffers, &buffer),
+                );
+                buffer
+            })
+            .collect::<Vec<_>>();
+        let instance_sets = instance_buffers
+            .iter()
+            .map(|buffer| {
+                DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    set_layouts[0].clone(),
+                    [WriteDescriptorSet::buffer(0, buffer.clone())],
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let texture_set = DescriptorSet::new(
+            app.descriptor_set_allocator.clone(),
+            set_layouts[1].clone(),
+            [WriteDescriptorSet::image_view_sampler(
+                0,
+                block_textures,
+                block_sampler,
+            )],
+            None,
+        )
+        .unwrap();
+
+        Self {
+            pipeline,
+            instance_buffers,
+            instance_sets,
+            texture_set,
+            _memory: memory,
+            face_textures: face_textures(block_registry),
+            tints: tints(block_registry),
+            previous_models: HashMap::new(),
+            instance_count: 0,
+        }
+    }
+
+    /// Writes the instances of `renderables` for the frame at `frame_index`,
+    /// which has to be done with the GPU.
+    pub fn update(&mut self, frame_index: usize, renderables: &[(Entity, Transform, Renderable)]) {
+        let mut previous_models = HashMap::with_capacity(renderables.len());
+        let mut instances = self.instance_buffers[frame_index].write().unwrap();
+        for (instance, (entity, transform, renderable)) in instances.iter_mut().zip(renderables) {
+            let model = transform.matrix();
+            // Entities that just appeared didn't move
+            let previous_model = self.previous_models.get(entity).copied().unwrap_or(model);
+            previous_models.insert(*entity, model);
+            *instance = vs::Instance {
+                model: model.into(),
+                previous_model: previous_model.into(),
+                textures: self.face_textures[renderable.block_type_id],
+                tint: self.tints[renderable.block_type_id],
+                billboard: renderable.billboard as u32,
+            };
+        }
+        self.instance_count = renderables.len().min(MAX_ENTITIES) as u32;
+        self.previous_models = previous_models;
+    }
+
+    /// Records the draw of the instances written for `frame_index`, inside
+    /// the rendering of the block faces.
+    pub fn render(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        frame_index: usize,
+        previous_camera: &Camera,
+        camera: &Camera,
+    ) {
+        if self.instance_count == 0 {
+            return;
+        }
+        let [view, previous_view] = [camera.view, previous_camera.view];
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                self.pipeline.bind_point(),
+                self.pipeline.layout().clone(),
+                0,
+                vec![
+                    self.instance_sets[frame_index].clone(),
+                    self.texture_set.clone(),
+                ],
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                vs::PushConstants {
+                    current_view_proj: (camera.proj * camera.view).into(),
+                    previous_view_proj: (previous_camera.proj * previous_camera.view).into(),
+                    // The rows of the view rotation are the camera axes
+                    camera_right: [view.x.x, view.y.x, view.z.x, 0.0],
+                    camera_up: [view.x.y, view.y.y, view.z.y, 0.0],
+                    previous_camera_right: [
+                        previous_view.x.x,
+                        previous_view.y.x,
+                        previous_view.z.x,
+                        0.0,
+                    ],
+                    previous_camera_up: [
+                        previous_view.x.y,
+                        previous_view.y.y,
+                        previous_view.z.y,
+                        0.0,
+                    ],
+                    jitter: camera.jitter.into(),
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder
+                .draw(BOX_VERTICES, self.instance_count, 0, 0)
+                .unwrap();
+        }
+    }
+}
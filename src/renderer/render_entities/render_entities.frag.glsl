@@ -0,0 +1,30 @@
+#version 460
+
+layout(location = 0) in VertexOut {
+  vec4 current_position;
+  vec4 previous_position;
+  vec3 normal;
+  vec2 tex_coords;
+  flat uint texture_index;
+  flat vec3 tint;
+}
+v_out;
+
+layout(set = 1, binding = 0) uniform sampler2DArray block_textures;
+
+layout(location = 0) out vec4 frag_color;
+layout(location = 1) out vec2 motion_vector;
+
+const vec3 LIGHT_DIRECTION = normalize(vec3(0.3, 1.0, 0.5));
+
+void main() {
+  motion_vector = v_out.previous_position.xy / v_out.previous_position.w -
+                  v_out.current_position.xy / v_out.current_position.w;
+
+  vec4 texel = texture(block_textures,
+                       vec3(v_out.tex_coords, float(v_out.texture_index)));
+  // Simple per-face shading so the block edges stay readable
+  float shade = 0.6 + 0.4 * max(dot(v_out.normal, LIGHT_DIRECTION), 0.0);
+
+  frag_color = vec4(texel.rgb * v_out.tint * shade, 1.0);  // Set alpha to 1.0 for full opacity
+}
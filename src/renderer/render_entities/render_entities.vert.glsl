@@ -0,0 +1,113 @@
+#version 460
+
+//////////////////////////////////////////////////
+// UNIFORMS
+
+struct Instance {
+  mat4 model;
+  mat4 previous_model;
+  // Texture of every face, in the order of cube_vertices
+  uint textures[6];
+  uint tint;
+  uint billboard;  // a quad facing the camera with textures[0]
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer InstanceBuffer {
+  Instance instances[];
+};
+
+layout(push_constant) uniform PushConstants {
+  mat4 current_view_proj;
+  mat4 previous_view_proj;
+  vec4 camera_right;
+  vec4 camera_up;
+  // Billboards faced the previous camera in the previous frame
+  vec4 previous_camera_right;
+  vec4 previous_camera_up;
+  vec2 jitter;
+}
+pc;
+
+//////////////////////////////////////////////////
+// OUTPUTS
+
+layout(location = 0) out VertexOut {
+  vec4 current_position;
+  vec4 previous_position;
+  vec3 normal;
+  vec2 tex_coords;
+  flat uint texture_index;
+  flat vec3 tint;
+}
+v_out;
+
+//////////////////////////////////////////////////
+// The same faces as the mesh shader of render_faces, so entities are
+// textured like the blocks they look like
+const vec3 cube_vertices[6][4] = {
+    {vec3(0, 0, 0), vec3(0, 1, 0), vec3(1, 1, 0), vec3(1, 0, 0)},
+    {vec3(0, 0, 1), vec3(1, 0, 1), vec3(1, 1, 1), vec3(0, 1, 1)},
+    {vec3(0, 0, 0), vec3(1, 0, 0), vec3(1, 0, 1), vec3(0, 0, 1)},
+    {vec3(0, 1, 0), vec3(0, 1, 1), vec3(1, 1, 1), vec3(1, 1, 0)},
+    {vec3(0, 0, 0), vec3(0, 0, 1), vec3(0, 1, 1), vec3(0, 1, 0)},
+    {vec3(1, 0, 0), vec3(1, 1, 0), vec3(1, 1, 1), vec3(1, 0, 1)},
+};
+
+const vec3 cube_normals[6] = {
+    vec3(0, 0, -1), vec3(0, 0, 1), vec3(0, -1, 0),
+    vec3(0, 1, 0),  vec3(-1, 0, 0), vec3(1, 0, 0),
+};
+
+// Corners of the two triangles of a face
+const uint face_indices[6] = {0, 1, 3, 1, 2, 3};
+
+const vec2 face_corners[4] = {
+    vec2(0.0, 0.0),
+    vec2(1.0, 0.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0),
+};
+
+void main() {
+  Instance instance = instances[gl_InstanceIndex];
+  uint face = gl_VertexIndex / 6;
+  uint corner = face_indices[gl_VertexIndex % 6];
+
+  vec4 current_vertex;
+  vec4 previous_vertex;
+  if (instance.billboard != 0) {
+    // Billboards only need the first face
+    if (face > 0) {
+      gl_Position = vec4(0.0, 0.0, 0.0, 1.0);
+      return;
+    }
+    vec2 offset = face_corners[corner] - 0.5;
+    vec3 along = pc.camera_right.xyz * offset.x + pc.camera_up.xyz * offset.y;
+    vec3 previous_along = pc.previous_camera_right.xyz * offset.x +
+                          pc.previous_camera_up.xyz * offset.y;
+    vec4 center = vec4(0.5, 0.5, 0.5, 1.0);
+    current_vertex = instance.model * center +
+                     vec4(along * length(instance.model[0].xyz), 0.0);
+    previous_vertex =
+        instance.previous_model * center +
+        vec4(previous_along * length(instance.previous_model[0].xyz), 0.0);
+    v_out.normal = cross(pc.camera_right.xyz, pc.camera_up.xyz);
+    v_out.tex_coords = vec2(face_corners[corner].x, 1.0 - face_corners[corner].y);
+  } else {
+    vec4 vertex = vec4(cube_vertices[face][corner], 1.0);
+    current_vertex = instance.model * vertex;
+    previous_vertex = instance.previous_model * vertex;
+    v_out.normal = normalize(mat3(instance.model) * cube_normals[face]);
+    v_out.tex_coords = face_corners[corner];
+  }
+
+  mat4 jitterTransform = mat4(1.0);
+  jitterTransform[3] = vec4(pc.jitter, 0.0, 1.0);
+
+  vec4 currentPosition = pc.current_view_proj * current_vertex;
+  gl_Position = jitterTransform * currentPosition;
+  v_out.current_position = currentPosition;
+  v_out.previous_position = pc.previous_view_proj * previous_vertex;
+  v_out.texture_index = instance.textures[face];
+  v_out.tint = unpackUnorm4x8(instance.tint).rgb;
+}
@@ -0,0 +1,128 @@
+use vulkano::padded::Padded;
+
+use crate::{
+    biome::{pack_tint_color, BiomeColors},
+    model::{Occlusion, Voxel},
+    texture::MISSING_TEXTURE,
+    types::{BlockRegistry, BlockTypeId, Direction, Tint},
+};
+
+use super::{encoding::BlockVoxels, task, GpuBlock, GpuMaterial};
+
+/// Face order used by `cube_vertices` and `cube_normals` in the mesh shader,
+/// which is also the bit order of `BlockVoxels::connected_bits`.
+pub const GPU_FACE_DIRECTIONS: [Direction; 6] = [
+    Direction::North,
+    Direction::South,
+    Direction::Down,
+    Direction::Up,
+    Direction::West,
+    Direction::East,
+];
+
+/// `BlockVoxels::connected_bits` of a block whose faces toward `visible` are
+/// the only ones not hidden by their neighbors.
+pub fn connected_bits(visible: impl IntoIterator<Item = Direction>) -> u32 {
+    let visible = visible.into_iter().collect::<Vec<_>>();
+    GPU_FACE_DIRECTIONS
+        .iter()
+        .enumerate()
+        .filter(|(_, direction)| !visible.contains(direction))
+        .fold(0, |bits, (i, _)| bits | 1 << i)
+}
+
+/// The block registry converted into the layout of the GPU `VoxelBuffer`.
+pub struct BakedBlockModels {
+    pub voxels: Vec<task::Voxel>,
+    /// Voxel range of every block type, indexed by `BlockTypeId`.
+    pub blocks: Vec<GpuBlock>,
+    pub tints: Vec<Tint>,
+    /// `BlockType::reflectivity` of every block type, as 0 to 255.
+    pub reflectivities: Vec<u8>,
+    /// `BlockType::material` of every block type, which the voxels refer to.
+    pub materials: Vec<GpuMaterial>,
+    /// `BlockRegistry::occlusion`, for culling the faces of sections.
+    pub occlusion: Vec<Occlusion>,
+}
+
+impl BakedBlockModels {
+    pub fn bake(block_registry: &BlockRegistry) -> Self {
+        let fallback = block_registry
+            .texture_registry
+            .get_index_of(MISSING_TEXTURE)
+            .unwrap_or(0);
+
+        let mut voxels = Vec::new();
+        let mut blocks = Vec::with_capacity(block_registry.block_types.len());
+        let mut tints = Vec::with_capacity(block_registry.block_types.len());
+        let mut reflectivities = Vec::with_capacity(block_registry.block_types.len());
+        let mut materials = Vec::with_capacity(block_registry.block_types.len());
+        for (block_type_id, block_type) in block_registry.block_types.values().enumerate() {
+            let voxel_offset = voxels.len() as u32;
+            // Block types without textures (air) have nothing to render.
+            if !block_type.textures.0.is_empty() {
+                let model = block_type.model(fallback);
+                voxels.extend(
+                    model
+                        .voxels
+                        .iter()
+                        .map(|voxel| bake_voxel(voxel, block_type_id as u32)),
+                );
+            }
+            blocks.push(GpuBlock {
+                voxels: BlockVoxels {
+                    offset: voxel_offset,
+                    len: voxels.len() as u32 - voxel_offset,
+                    connected_bits: 0,
+                }
+                .pack(),
+                tint: pack_tint_color(None),
+            });
+            tints.push(block_type.tint);
+            reflectivities.push((block_type.reflectivity * 255.0).round() as u8);
+            let material = block_type.material;
+            materials.push(GpuMaterial {
+                roughness: material.roughness,
+                metallic: material.metallic,
+                emissive: material.emissive,
+                translucency: material.translucency,
+            });
+        }
+
+        Self {
+            voxels,
+            blocks,
+            tints,
+            reflectivities,
+            materials,
+            occlusion: block_registry.occlusion(),
+        }
+    }
+
+    /// The GPU block for `block_type_id`, tinted with the biome colors of the
+    /// column it is in. The alpha of the tint is 1 minus its reflectivity.
+    pub fn gpu_block(&self, block_type_id: BlockTypeId, biome_colors: &BiomeColors) -> GpuBlock {
+        let tint = pack_tint_color(biome_colors.tint_color(self.tints[block_type_id]));
+        let alpha = 255 - self.reflectivities[block_type_id] as u32;
+        GpuBlock {
+            tint: tint & 0x00ff_ffff | alpha << 24,
+            ..self.blocks[block_type_id]
+        }
+    }
+}
+
+fn bake_voxel(voxel: &Voxel, material: u32) -> task::Voxel {
+    task::Voxel {
+        faces: GPU_FACE_DIRECTIONS.map(|direction| {
+            let face = voxel.faces.get(direction);
+            Padded(task::VoxelFace {
+                uv: face.uv,
+                texture_index: face.texture as u32,
+                cullface: face.cullface.is_some() as u32,
+            })
+        }),
+        from: voxel.from.map(|v| v / 16.0),
+        material,
+        to: Padded(voxel.to.map(|v| v / 16.0)),
+    }
+}
@@ -0,0 +1,70 @@
+/// Bits of `GpuBlock::voxels` holding the offset of the first voxel of the
+/// block, then those holding how many voxels it has. The 6 bits above them
+/// are the connected bits. The shaders unpack them the same way.
+const VOXEL_OFFSET_BITS: u32 = 16;
+const VOXEL_LEN_BITS: u32 = 10;
+
+/// The voxels of a block and which of its faces are hidden, packed into one
+/// `u32` so a block takes 8 bytes with its tint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockVoxels {
+    pub offset: u32,
+    pub len: u32,
+    /// Bit `i` is set if the face toward `GPU_FACE_DIRECTIONS[i]` is hidden.
+    pub connected_bits: u32,
+}
+
+impl BlockVoxels {
+    pub fn pack(self) -> u32 {
+        assert!(
+            self.offset < 1 << VOXEL_OFFSET_BITS,
+            "too many voxels in the block models"
+        );
+        assert!(self.len < 1 << VOXEL_LEN_BITS);
+        assert!(self.connected_bits < 1 << 6);
+        self.offset
+            | self.len << VOXEL_OFFSET_BITS
+            | self.connected_bits << (VOXEL_OFFSET_BITS + VOXEL_LEN_BITS)
+    }
+
+    pub fn unpack(voxels: u32) -> Self {
+        Self {
+            offset: voxels & ((1 << VOXEL_OFFSET_BITS) - 1),
+            len: voxels >> VOXEL_OFFSET_BITS & ((1 << VOXEL_LEN_BITS) - 1),
+            connected_bits: voxels >> (VOXEL_OFFSET_BITS + VOXEL_LEN_BITS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_voxels() {
+        assert_eq!(BlockVoxels::unpack(0).len, 0);
+        for voxels in [
+            BlockVoxels {
+                offset: 1234,
+                len: 3,
+                connected_bits: 0b101101,
+            },
+            BlockVoxels {
+                offset: (1 << 16) - 1,
+                len: (1 << 10) - 1,
+                connected_bits: 0b111111,
+            },
+        ] {
+            assert_eq!(BlockVoxels::unpack(voxels.pack()), voxels);
+        }
+        assert_eq!(
+            BlockVoxels {
+                offset: 1,
+                len: 2,
+                connected_bits: 1,
+            }
+            .pack(),
+            1 | 2 << 16 | 1 << 26
+        );
+    }
+}
@@ -1,24 +1,40 @@
+//! Blocks drawn as faces generated by task and mesh shaders from the chunks
+//! in `GpuChunkStorage`. The chunks follow the world through its events:
+//! `RenderFacesPipeline::update_chunks` culls the faces of sections as they
+//! enter render distance or are replaced, redoes the blocks around each
+//! edited block, and records the `ChunkUpdate`s as copies into the chunk
+//! buffers, so `World::set_block` is all it takes to see an edit.
+
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
+    collections::{BTreeMap, HashMap, HashSet},
+    mem::{offset_of, size_of},
+    sync::{mpsc::Receiver, Arc},
 };
 
 use cgmath::Deg;
+use tracing::{info_span, instrument};
 use vulkano::{
+    acceleration_structure::AccelerationStructure,
     buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
-    command_buffer::{CopyBufferToImageInfo, RecordingCommandBuffer},
-    descriptor_set::{DescriptorSet, WriteDescriptorSet},
-    device::Queue,
+    command_buffer::{
+        BufferCopy, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage, CopyBufferInfo,
+        CopyBufferToImageInfo, DrawMeshTasksIndirectCommand, RecordingCommandBuffer,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::{DeviceOwned, Queue},
     format::Format,
     image::{
+        sampler::Sampler,
         view::{ImageView, ImageViewCreateInfo, ImageViewType},
-        Image, ImageCreateInfo, ImageType, ImageUsage,
+        Image, ImageCreateInfo, ImageType, ImageUsage, SampleCount,
     },
     memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
-    padded::Padded,
     pipeline::{
+        compute::ComputePipelineCreateInfo,
         graphics::{
-            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            color_blend::{ColorBlendAttachmentState, ColorBlendState, ColorComponents},
             depth_stencil::{CompareOp, DepthState, DepthStencilState},
             multisample::MultisampleState,
             rasterization::{CullMode, RasterizationState},
@@ -27,11 +43,53 @@ use vulkano::{
             GraphicsPipelineCreateInfo,
         },
         layout::PipelineDescriptorSetLayoutCreateInfo,
-        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+        ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint,
+        PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+    sync::{self, GpuFuture},
+    VulkanObject,
+};
+
+use crate::{
+    app::App,
+    camera::DepthMode,
+    events::WorldEvent,
+    memory::{MemoryCategory, MemoryTracker, TrackedAllocation},
+    renderer::{
+        culling::{
+            blocks_affected_by_block, cull_faces_for_block, cull_faces_for_chunk,
+            sections_affected_by_block, CaveCuller, SectionBounds,
+        },
+        SKY_COLOR,
+    },
+    settings::WorldEdges,
+    texture::TextureRegistry,
+    types::{
+        local_block_position, BlockRegistry, BlockTypeId, Chunk, ChunkPosition, ColumnPosition,
+        Direction, World, CHUNK_SIZE,
     },
+    weather::Weather,
 };
 
-use crate::{app::App, types::ChunkPosition};
+pub use self::{
+    bake::{BakedBlockModels, GPU_FACE_DIRECTIONS},
+    encoding::BlockVoxels,
+};
+
+use self::pages::{ChunkPages, MAX_CHUNK_PAGES};
+
+use super::{
+    depth_compare_op,
+    hi_z::HiZPyramid,
+    staging::{StagingRing, UploadFence},
+};
+
+mod bake;
+mod encoding;
+mod pages;
+
+/// Size of the ring all uploads of the pipeline are staged through.
+const STAGING_RING_SIZE: u64 = 32 << 20;
 
 mod task {
     vulkano_shaders::shader!(
@@ -56,16 +114,55 @@ mod frag {
     );
 }
 
+/// The fragment shader tracing shadows, which needs ray queries.
+mod frag_ray_traced_shadows {
+    vulkano_shaders::shader!(
+        ty: "fragment",
+        path: "src/renderer/render_faces/render_faces.frag.glsl",
+        define: [("RAY_TRACED_SHADOWS", "")],
+        vulkan_version: "1.3"
+    );
+}
+
+mod cull {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/render_faces/render_faces.cull.comp.glsl",
+    );
+}
+
+/// Workgroups of the culling pass scanning one chunk, 256 blocks each.
+const CULL_WORKGROUPS_PER_CHUNK: u32 = (CHUNK_SIZE.pow(3) / 256) as u32;
+
 // Fix-sized array of CHUNK_SIZE^3 blocks, stored sparsely.
 pub use task::Block as GpuBlock;
 pub use task::Chunk as GpuChunk;
+pub use task::Voxel as GpuVoxel;
 
+pub use frag::Material as GpuMaterial;
+
+/// Chunk buffers read by the culling pass and the task and mesh shaders.
+/// They live in device-local memory the host never maps; all writes are
+/// `copy_buffer`s recorded into the frame's command buffer, ordered after the
+/// reads of earlier submissions by `wait_for_shader_reads`.
 struct GpuChunkStorage {
-    chunk_buffer: Subbuffer<task::ChunkBuffer>,
-    index_buffer: Subbuffer<task::IndexBuffer>,
+    /// The chunk slots, split over buffers by `ChunkPages`.
+    chunk_pages: Vec<Subbuffer<task::ChunkBuffer>>,
+    /// Slots of the chunks the culling pass scans.
+    visible_chunk_buffer: Subbuffer<[u32]>,
+    /// The blocks that passed culling this frame, compacted by the culling
+    /// pass.
+    visible_index_buffer: Subbuffer<task::IndexBuffer>,
+    /// Task workgroups to dispatch, counted by the culling pass.
+    draw_command_buffer: Subbuffer<[DrawMeshTasksIndirectCommand]>,
 
-    chunk_blocks_map: HashMap<ChunkPosition, (u32, HashSet<u32>)>, // chunk index, block indices
+    chunk_indices: HashMap<ChunkPosition, u32>,
     chunk_holes: Vec<u32>,
+    /// Bounds of the blocks of the stored chunks, none for empty ones, kept
+    /// up to date by `block_changed` so edits don't rescan the section.
+    chunk_bounds: HashMap<ChunkPosition, Option<SectionBounds>>,
+
+    _memory: Vec<TrackedAllocation>,
 }
 
 struct ChunkUpdate {
@@ -73,83 +170,418 @@ struct ChunkUpdate {
     block: Option<GpuBlock>,
 }
 
+/// What a block slot without voxels holds; the culling pass skips it.
+const EMPTY_BLOCK: GpuBlock = GpuBlock { voxels: 0, tint: 0 };
+
+/// Index of a block inside a `GpuChunk`, from its position inside the section.
+fn gpu_block_index(x: u32, y: u32, z: u32) -> u32 {
+    let size = CHUNK_SIZE as u32;
+    x + size * y + size * size * z
+}
+
+fn column_sections_uploaded(
+    gpu_chunk_storage: &GpuChunkStorage,
+    world: &World,
+    column: ColumnPosition,
+) -> bool {
+    column
+        .sections(world.height)
+        .all(|chunk_position| gpu_chunk_storage.contains(chunk_position))
+}
+
+/// Updates adding every block of a section with at least one visible face.
+fn section_updates(
+    world: &World,
+    chunk_position: ChunkPosition,
+    baked_models: &BakedBlockModels,
+    edges: WorldEdges,
+) -> Vec<ChunkUpdate> {
+    let chunk = &world.chunks[&chunk_position];
+    let mut visible_faces = BTreeMap::<_, Vec<_>>::new();
+    for face in cull_faces_for_chunk(world, &baked_models.occlusion, chunk, chunk_position, edges) {
+        visible_faces
+            .entry((face.position, face.block_type_id))
+            .or_default()
+            .push(face.direction);
+    }
+
+    visible_faces
+        .into_iter()
+        .map(|((position, block_type_id), directions)| {
+            visible_block_update(chunk, position, block_type_id, directions, baked_models)
+        })
+        .collect()
+}
+
+/// The update redoing the single block at `position` after it or one of its
+/// neighbors changed, emptying its slot if it has no visible faces left.
+fn block_update(
+    world: &World,
+    position: [i32; 3],
+    baked_models: &BakedBlockModels,
+    edges: WorldEdges,
+) -> ChunkUpdate {
+    let chunk = &world.chunks[&ChunkPosition::of_block(position)];
+    let [x, y, z] = local_block_position(position).map(|v| v as u32);
+    let faces = cull_faces_for_block(world, &baked_models.occlusion, position, edges);
+    match faces.first() {
+        Some(face) => {
+            let block_type_id = face.block_type_id;
+            let directions = faces.iter().map(|face| face.direction).collect();
+            visible_block_update(chunk, (x, y, z), block_type_id, directions, baked_models)
+        }
+        None => ChunkUpdate {
+            block_index: gpu_block_index(x, y, z),
+            block: None,
+        },
+    }
+}
+
+/// The update adding the block at `(x, y, z)` of `chunk`, whose faces
+/// toward `directions` are visible.
+fn visible_block_update(
+    chunk: &Chunk,
+    (x, y, z): (u32, u32, u32),
+    block_type_id: BlockTypeId,
+    directions: Vec<Direction>,
+    baked_models: &BakedBlockModels,
+) -> ChunkUpdate {
+    let block = baked_models.gpu_block(block_type_id, &chunk.biome_colors[x as usize][z as usize]);
+    let voxels = BlockVoxels {
+        // The mesh shader skips the cullfaces toward hidden sides
+        connected_bits: bake::connected_bits(directions),
+        ..BlockVoxels::unpack(block.voxels)
+    };
+    ChunkUpdate {
+        block_index: gpu_block_index(x, y, z),
+        block: Some(GpuBlock {
+            voxels: voxels.pack(),
+            ..block
+        }),
+    }
+}
+
 impl GpuChunkStorage {
-    pub fn new(allocator: Arc<StandardMemoryAllocator>, chunks: u64) -> Self {
-        let chunk_buffer = Buffer::new_unsized(
+    pub fn new(
+        allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &Arc<MemoryTracker>,
+        chunks: u64,
+    ) -> Self {
+        let max_storage_buffer_range = allocator
+            .device()
+            .physical_device()
+            .properties()
+            .max_storage_buffer_range;
+        let pages = ChunkPages::new(
+            chunks,
+            size_of::<GpuChunk>() as u64,
+            max_storage_buffer_range as u64,
+        );
+        let chunk_pages = (0..pages.count())
+            .map(|page| {
+                Buffer::new_unsized(
+                    allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                        ..Default::default()
+                    },
+                    pages.page_len(page),
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let visible_chunk_buffer = Buffer::new_slice(
             allocator.clone(),
             BufferCreateInfo {
-                usage: BufferUsage::STORAGE_BUFFER,
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
                 ..Default::default()
             },
             AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
                 ..Default::default()
             },
             chunks,
         )
         .unwrap();
 
-        let index_buffer = Buffer::new_unsized(
+        let visible_index_buffer = Buffer::new_unsized(
             allocator.clone(),
             BufferCreateInfo {
                 usage: BufferUsage::STORAGE_BUFFER,
                 ..Default::default()
             },
             AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            // A chunk is 16x16x16 blocks, but not more than one buffer
+            // holds, the blocks past it aren't drawn
+            (chunks * 16 * 16 * 16).min(max_storage_buffer_range as u64 / 4),
+        )
+        .unwrap();
+
+        let draw_command_buffer = Buffer::new_slice(
+            allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::INDIRECT_BUFFER
+                    | BufferUsage::STORAGE_BUFFER
+                    | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
                 ..Default::default()
             },
-            chunks * 16 * 16 * 16, // a chunk is 16x16x16 blocks
+            1,
         )
         .unwrap();
 
+        let mut memory = chunk_pages
+            .iter()
+            .map(|page| memory_tracker.track_buffer(MemoryCategory::ChunkBuffers, page))
+            .collect::<Vec<_>>();
+        memory.extend([
+            memory_tracker.track_buffer(MemoryCategory::ChunkBuffers, &visible_chunk_buffer),
+            memory_tracker.track_buffer(MemoryCategory::ChunkBuffers, &visible_index_buffer),
+            memory_tracker.track_buffer(MemoryCategory::ChunkBuffers, &draw_command_buffer),
+        ]);
+
         Self {
-            chunk_buffer,
-            index_buffer,
-            chunk_blocks_map: HashMap::new(),
-            chunk_holes: (0..chunks as u32).rev().collect(),
+            chunk_pages,
+            visible_chunk_buffer,
+            visible_index_buffer,
+            draw_command_buffer,
+            chunk_indices: HashMap::new(),
+            chunk_holes: pages.chunk_indices().rev().collect(),
+            chunk_bounds: HashMap::new(),
+            _memory: memory,
+        }
+    }
+
+    /// Writes the chunk pages to the array of `binding`. The shaders never
+    /// index the slots past the pages there are, which repeat the first.
+    fn write_chunk_pages(&self, binding: u32) -> WriteDescriptorSet {
+        let first = self.chunk_pages[0].clone();
+        let pages = self
+            .chunk_pages
+            .iter()
+            .cloned()
+            .chain(std::iter::repeat(first))
+            .take(MAX_CHUNK_PAGES);
+        WriteDescriptorSet::buffer_array(binding, 0, pages)
+    }
+
+    /// Records a barrier making the copies recorded after it wait for the
+    /// culling, task and mesh dispatches of earlier submissions, which may
+    /// still be reading the buffers. The command buffer only synchronizes against
+    /// commands recorded into itself.
+    pub fn wait_for_shader_reads(&self, command_buffer: &mut RecordingCommandBuffer) {
+        let memory_barrier = ash::vk::MemoryBarrier2 {
+            src_stage_mask: ash::vk::PipelineStageFlags2::COMPUTE_SHADER
+                | ash::vk::PipelineStageFlags2::TASK_SHADER_EXT
+                | ash::vk::PipelineStageFlags2::MESH_SHADER_EXT,
+            src_access_mask: ash::vk::AccessFlags2::SHADER_STORAGE_READ,
+            dst_stage_mask: ash::vk::PipelineStageFlags2::COPY,
+            dst_access_mask: ash::vk::AccessFlags2::TRANSFER_WRITE,
+            ..Default::default()
+        };
+        let memory_barriers = [memory_barrier];
+        let dependency_info = ash::vk::DependencyInfo::default().memory_barriers(&memory_barriers);
+        let fns = self.visible_chunk_buffer.device().fns();
+        unsafe {
+            (fns.v1_3.cmd_pipeline_barrier2)(command_buffer.raw().handle(), &dependency_info);
         }
     }
 
+    /// Records the copies applying `updates` to the chunk at `chunk_position`
+    /// into `command_buffer`, staging the data through `staging`. `bounds`
+    /// are those of its blocks after the updates, which the culling pass
+    /// tests against the frustum instead of the whole section.
     pub fn update(
         &mut self,
+        staging: &mut StagingRing,
+        command_buffer: &mut RecordingCommandBuffer,
         chunk_position: ChunkPosition,
+        bounds: Option<SectionBounds>,
         updates: impl IntoIterator<Item = ChunkUpdate>,
     ) {
-        let (chunk_index, block_indices) = self
-            .chunk_blocks_map
-            .entry(chunk_position)
-            .or_insert_with(|| {
-                let chunk_index = self.chunk_holes.pop().unwrap();
-                (chunk_index, HashSet::new())
-            });
+        self.chunk_bounds.insert(chunk_position, bounds);
+        let chunk_index = match self.chunk_indices.get(&chunk_position) {
+            Some(&chunk_index) => chunk_index,
+            None => {
+                let chunk_index = self.chunk_holes.pop().expect("GpuChunkStorage is full");
+                self.chunk_indices.insert(chunk_position, chunk_index);
+
+                // The slot still holds the blocks of the chunk that used it last
+                let (page, slot) = ChunkPages::locate(chunk_index);
+                let blocks_offset =
+                    slot * size_of::<GpuChunk>() as u64 + offset_of!(GpuChunk, blocks) as u64;
+                let blocks_size = (CHUNK_SIZE.pow(3) * size_of::<GpuBlock>()) as u64;
+                command_buffer
+                    .fill_buffer(
+                        self.chunk_pages[page]
+                            .clone()
+                            .into_bytes()
+                            .slice(blocks_offset..blocks_offset + blocks_size)
+                            .reinterpret::<[u32]>(),
+                        0,
+                    )
+                    .unwrap();
+                chunk_index
+            }
+        };
 
-        let mut chunk = self.chunk_buffer.write().unwrap();
+        // Later updates of the same block win
+        let mut blocks = BTreeMap::new();
         for update in updates {
-            if let Some(block) = update.block {
-                chunk.chunks[*chunk_index as usize].blocks[update.block_index as usize] = block;
-                block_indices.insert(update.block_index);
-            } else {
-                block_indices.remove(&update.block_index);
+            blocks.insert(update.block_index, update.block.unwrap_or(EMPTY_BLOCK));
+        }
+
+        let (page, slot) = ChunkPages::locate(chunk_index);
+        let chunk_offset = slot * size_of::<GpuChunk>() as u64;
+        let packed_bounds = bounds.map_or(0, |bounds| bounds.pack()) as i32;
+        let position = staging.upload(&[[
+            chunk_position.x,
+            chunk_position.y,
+            chunk_position.z,
+            packed_bounds,
+        ]]);
+        command_buffer
+            .copy_buffer(CopyBufferInfo {
+                regions: [BufferCopy {
+                    dst_offset: chunk_offset + offset_of!(GpuChunk, position) as u64,
+                    size: position.size(),
+                    ..Default::default()
+                }]
+                .into_iter()
+                .collect(),
+                ..CopyBufferInfo::buffers(position, self.chunk_pages[page].clone())
+            })
+            .unwrap();
+
+        if blocks.is_empty() {
+            return;
+        }
+        let staged_blocks = staging.upload(&blocks.values().copied().collect::<Vec<_>>());
+
+        // One region per run of consecutive block indices
+        let block_size = size_of::<GpuBlock>() as u64;
+        let blocks_offset = chunk_offset + offset_of!(GpuChunk, blocks) as u64;
+        let mut regions = Vec::<BufferCopy>::new();
+        for (i, &block_index) in blocks.keys().enumerate() {
+            let src_offset = i as u64 * block_size;
+            let dst_offset = blocks_offset + block_index as u64 * block_size;
+            match regions.last_mut() {
+                Some(region)
+                    if region.src_offset + region.size == src_offset
+                        && region.dst_offset + region.size == dst_offset =>
+                {
+                    region.size += block_size;
+                }
+                _ => regions.push(BufferCopy {
+                    src_offset,
+                    dst_offset,
+                    size: block_size,
+                    ..Default::default()
+                }),
             }
         }
+        command_buffer
+            .copy_buffer(CopyBufferInfo {
+                regions: regions.into_iter().collect(),
+                ..CopyBufferInfo::buffers(staged_blocks, self.chunk_pages[page].clone())
+            })
+            .unwrap();
     }
 
-    pub fn upload_indices(&self) -> usize {
-        let mut index_write = self.index_buffer.write().unwrap();
-        let mut i = 0;
-        for (_, (chunk_index, block_indices)) in self.chunk_blocks_map.iter() {
-            for block_index in block_indices.iter() {
-                index_write.indices[i] = [*chunk_index, *block_index];
-                i += 1;
-            }
+    pub fn contains(&self, chunk_position: ChunkPosition) -> bool {
+        self.chunk_indices.contains_key(&chunk_position)
+    }
+
+    /// The bounds of the chunk at `chunk_position` as of the last `update`,
+    /// with the changes seen by `block_changed` since. Those not known are
+    /// worked out from `world`.
+    pub fn bounds(&self, world: &World, chunk_position: ChunkPosition) -> Option<SectionBounds> {
+        match self.chunk_bounds.get(&chunk_position) {
+            Some(&bounds) => bounds,
+            None => SectionBounds::of_chunk(&world.chunks[&chunk_position]),
+        }
+    }
+
+    /// Grows or shrinks the bounds of the stored chunk holding the block at
+    /// `position` after it changed in `world`.
+    pub fn block_changed(&mut self, world: &World, position: [i32; 3]) {
+        let chunk_position = ChunkPosition::of_block(position);
+        if let (Some(bounds), Some(chunk)) = (
+            self.chunk_bounds.get_mut(&chunk_position),
+            world.chunks.get(&chunk_position),
+        ) {
+            let local = local_block_position(position).map(|v| v as u8);
+            *bounds = SectionBounds::after_change(*bounds, chunk, local);
+        }
+    }
+
+    /// Forgets the bounds of the chunk at `chunk_position`, for when it was
+    /// replaced as a whole.
+    pub fn forget_bounds(&mut self, chunk_position: ChunkPosition) {
+        self.chunk_bounds.remove(&chunk_position);
+    }
+
+    /// Frees the slot of the chunk at `chunk_position` for reuse. Its blocks
+    /// are no longer scanned from the next `upload_visible_chunks` on.
+    pub fn remove(&mut self, chunk_position: ChunkPosition) {
+        if let Some(chunk_index) = self.chunk_indices.remove(&chunk_position) {
+            self.chunk_holes.push(chunk_index);
         }
-        i
+        self.chunk_bounds.remove(&chunk_position);
     }
 
-    // pub fn upload_indices_with_culling(&self, frustum: Frustum) {}
+    /// Records the upload of the slots of the stored chunks in `visible` for
+    /// the culling pass to scan, returning how many there are. Empty chunks
+    /// are left out, having no blocks to scan. They are
+    /// ordered front to back from `camera_section`, which the culling pass
+    /// mostly keeps, so nearer faces are drawn first and hide the faces
+    /// behind them from the depth test before they are shaded.
+    #[instrument(skip_all)]
+    pub fn upload_visible_chunks(
+        &self,
+        staging: &mut StagingRing,
+        command_buffer: &mut RecordingCommandBuffer,
+        visible: &HashSet<ChunkPosition>,
+        camera_section: ChunkPosition,
+    ) -> usize {
+        let mut visible_chunks = self
+            .chunk_indices
+            .iter()
+            .filter(|(chunk_position, _)| {
+                visible.contains(chunk_position)
+                    && self.chunk_bounds.get(chunk_position) != Some(&None)
+            })
+            .collect::<Vec<_>>();
+        visible_chunks
+            .sort_by_key(|(chunk_position, _)| chunk_position.distance_squared(camera_section));
+        let chunk_indices = visible_chunks
+            .into_iter()
+            .map(|(_, &chunk_index)| chunk_index)
+            .collect::<Vec<_>>();
+        if chunk_indices.is_empty() {
+            return 0;
+        }
+
+        let staged = staging.upload(&chunk_indices);
+        command_buffer
+            .copy_buffer(CopyBufferInfo::buffers(
+                staged,
+                self.visible_chunk_buffer.clone(),
+            ))
+            .unwrap();
+        chunk_indices.len()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -158,46 +590,51 @@ pub struct Camera {
     pub proj: cgmath::Matrix4<f32>,
     pub position: cgmath::Point3<f32>,
     pub near: f32,
+    /// Infinite without a far plane, see `DepthMode::InfiniteReversed`.
     pub far: f32,
     pub fovy: Deg<f32>,
     pub jitter: cgmath::Vector2<f32>,
 }
 
-fn upload_png(
-    bytes: &[u8],
+/// Uploads every registered texture as one layer of a 2D array image, in
+/// registry order so `TextureId`s can be used as layer indices. With
+/// `normal_maps`, the layers are their normal maps instead.
+fn upload_textures(
+    texture_registry: &TextureRegistry,
+    normal_maps: bool,
     memory_allocator: Arc<StandardMemoryAllocator>,
+    memory_tracker: &Arc<MemoryTracker>,
+    staging: &mut StagingRing,
     command_buffer: &mut RecordingCommandBuffer,
-) -> Arc<ImageView> {
-    let decoder = png::Decoder::new(bytes);
-    let mut reader = decoder.read_info().unwrap();
-    let info = reader.info();
-    let extent = [info.width, info.height, 1];
-
-    let upload_buffer = Buffer::new_slice(
-        memory_allocator.clone(),
-        BufferCreateInfo {
-            usage: BufferUsage::TRANSFER_SRC,
-            ..Default::default()
-        },
-        AllocationCreateInfo {
-            memory_type_filter: MemoryTypeFilter::PREFER_HOST
-                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-            ..Default::default()
-        },
-        (info.width * info.height * 4) as u64,
-    )
-    .unwrap();
+) -> (Arc<ImageView>, TrackedAllocation) {
+    let (width, height) = texture_registry
+        .dimensions()
+        .expect("no textures registered");
 
-    reader
-        .next_frame(&mut upload_buffer.write().unwrap())
-        .unwrap();
+    let pixels = texture_registry
+        .values()
+        .flat_map(|texture| {
+            if normal_maps {
+                texture.normal_map_or_flat().into_owned().into_raw()
+            } else {
+                texture.image.as_raw().clone()
+            }
+        })
+        .collect::<Vec<u8>>();
+    let upload_buffer = staging.upload(&pixels);
 
     let image = Image::new(
         memory_allocator,
         ImageCreateInfo {
             image_type: ImageType::Dim2d,
-            format: Format::R8G8B8A8_SRGB,
-            extent,
+            // Normals are linear, unlike the colors
+            format: if normal_maps {
+                Format::R8G8B8A8_UNORM
+            } else {
+                Format::R8G8B8A8_SRGB
+            },
+            extent: [width, height, 1],
+            array_layers: texture_registry.len() as u32,
             usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
             ..Default::default()
         },
@@ -212,18 +649,51 @@ fn upload_png(
         ))
         .unwrap();
 
+    let memory = memory_tracker.track_image(MemoryCategory::Textures, &image);
     let view_create_info = ImageViewCreateInfo {
         view_type: ImageViewType::Dim2dArray,
         ..ImageViewCreateInfo::from_image(&image)
     };
-    ImageView::new(image, view_create_info).unwrap()
+    (ImageView::new(image, view_create_info).unwrap(), memory)
 }
 
 pub struct RenderFacesPipeline {
     pipeline: Arc<GraphicsPipeline>,
+    /// Draws the same faces into the depth buffer only, before `pipeline`
+    /// shades the ones whose depth is equal to it, so every pixel is shaded
+    /// once however many faces cover it.
+    depth_prepass: Option<Arc<GraphicsPipeline>>,
+    /// With ray traced shadows, the last one holds the acceleration
+    /// structure they are traced against.
     descriptor_sets: Vec<Arc<DescriptorSet>>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    ray_traced_shadows: bool,
+    cull_pipeline: Arc<ComputePipeline>,
+    cull_descriptor_set: Arc<DescriptorSet>,
+    depth_mode: DepthMode,
+    world_edges: WorldEdges,
+    /// Distance from the camera at which `WorldEdges::FogWall` hides the
+    /// faces completely, the radius of render distance.
+    fog_wall_distance: f32,
 
+    baked_models: BakedBlockModels,
+    /// The block textures as layers indexed by `TextureId`.
+    block_textures: Arc<ImageView>,
     gpu_chunk_storage: GpuChunkStorage,
+    staging: StagingRing,
+    _memory: Vec<TrackedAllocation>,
+    /// Number of chunks the culling pass scans.
+    visible_chunk_count: u32,
+
+    cave_culler: CaveCuller,
+    /// Block edits and section loads of the world the chunks come from.
+    world_events: Receiver<WorldEvent>,
+    /// Sections whose chunks the culling pass scans.
+    visible_sections: HashSet<ChunkPosition>,
+    /// Whether chunks moved to other slots since the last upload.
+    visible_chunks_outdated: bool,
+    /// The section of the camera the visible chunks were ordered from.
+    camera_section: Option<ChunkPosition>,
 }
 
 impl RenderFacesPipeline {
@@ -231,8 +701,20 @@ impl RenderFacesPipeline {
         app: &App,
         queue: Arc<Queue>,
         rendering_info: PipelineRenderingCreateInfo,
+        samples: SampleCount,
+        block_registry: &BlockRegistry,
+        world_events: Receiver<WorldEvent>,
+        chunk_capacity: u64,
+        hi_z: &HiZPyramid,
+        block_sampler: Arc<Sampler>,
+        ray_traced_shadows: bool,
+        depth_prepass: bool,
+        world_edges: WorldEdges,
+        render_distance: u32,
     ) -> RenderFacesPipeline {
-        let pipeline = {
+        // The depth buffer is the one the pyramid is built from
+        let depth_mode = hi_z.depth_mode();
+        let (pipeline, depth_prepass) = {
             let device = queue.device().clone();
             let task = task::load(device.clone())
                 .unwrap()
@@ -242,10 +724,14 @@ impl RenderFacesPipeline {
                 .unwrap()
                 .entry_point("main")
                 .unwrap();
-            let frag = frag::load(device.clone())
-                .unwrap()
-                .entry_point("main")
-                .unwrap();
+            let frag = if ray_traced_shadows {
+                frag_ray_traced_shadows::load(device.clone())
+            } else {
+                frag::load(device.clone())
+            }
+            .unwrap()
+            .entry_point("main")
+            .unwrap();
 
             let stages = [
                 PipelineShaderStageCreateInfo::new(task),
@@ -261,59 +747,124 @@ impl RenderFacesPipeline {
             )
             .unwrap();
 
-            GraphicsPipeline::new(
-                device.clone(),
-                None,
-                GraphicsPipelineCreateInfo {
-                    stages: stages.into_iter().collect(),
-                    viewport_state: Some(ViewportState::default()),
-                    rasterization_state: Some(RasterizationState {
-                        // cull_mode: CullMode::None,
-                        ..Default::default()
+            let create_info = GraphicsPipelineCreateInfo {
+                stages: stages.iter().cloned().collect(),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState {
+                    // cull_mode: CullMode::None,
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: samples,
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    rendering_info.color_attachment_formats.len() as u32,
+                    ColorBlendAttachmentState::default(),
+                )),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        compare_op: depth_compare_op(depth_mode),
+                        write_enable: true,
                     }),
-                    multisample_state: Some(MultisampleState::default()),
-                    color_blend_state: Some(ColorBlendState::with_attachment_states(
-                        rendering_info.color_attachment_formats.len() as u32,
-                        ColorBlendAttachmentState::default(),
-                    )),
-                    depth_stencil_state: Some(DepthStencilState {
-                        depth: Some(DepthState {
-                            compare_op: CompareOp::Less,
-                            write_enable: true,
-                        }),
-                        ..Default::default()
+                    ..Default::default()
+                }),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(rendering_info.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            };
+
+            let depth_prepass = depth_prepass.then(|| {
+                // No fragment shader and no color written, only depth
+                GraphicsPipeline::new(
+                    device.clone(),
+                    None,
+                    GraphicsPipelineCreateInfo {
+                        stages: stages[..2].iter().cloned().collect(),
+                        color_blend_state: Some(ColorBlendState::with_attachment_states(
+                            rendering_info.color_attachment_formats.len() as u32,
+                            ColorBlendAttachmentState {
+                                color_write_mask: ColorComponents::empty(),
+                                ..Default::default()
+                            },
+                        )),
+                        ..create_info.clone()
+                    },
+                )
+                .unwrap()
+            });
+            let mut create_info = create_info;
+            if depth_prepass.is_some() {
+                // Only the faces the prepass left nearest are shaded
+                create_info.depth_stencil_state = Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        compare_op: CompareOp::Equal,
+                        write_enable: false,
                     }),
-                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
-                    subpass: Some(rendering_info.into()),
-                    ..GraphicsPipelineCreateInfo::layout(layout)
-                },
+                    ..Default::default()
+                });
+            }
+            let pipeline = GraphicsPipeline::new(device.clone(), None, create_info).unwrap();
+            (pipeline, depth_prepass)
+        };
+
+        let cull_pipeline = {
+            let device = queue.device().clone();
+            let cs = cull::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
             )
             .unwrap()
         };
 
-        let mut gpu_chunk_storage = GpuChunkStorage::new(app.context.memory_allocator().clone(), 1);
-        let chunk_updates = (0..16 * 16 * 16).map(|i| ChunkUpdate {
-            block_index: i,
-            block: Some(GpuBlock {
-                voxel_offset: 0,
-                voxel_len: 2,
-                connected_bits: 0,
-            }),
-        });
-        gpu_chunk_storage.update(ChunkPosition { x: 0, z: 0 }, chunk_updates);
-        gpu_chunk_storage.upload_indices();
-
-        let descriptor_sets = {
-            // let mut command_buffer = RecordingCommandBuffer::new(
-            //     app.command_buffer_allocator.clone(),
-            //     queue.queue_family_index(),
-            //     CommandBufferLevel::Primary,
-            //     CommandBufferBeginInfo {
-            //         usage: CommandBufferUsage::OneTimeSubmit,
-            //         ..Default::default()
-            //     },
-            // )
-            // .unwrap();
+        let baked_models = BakedBlockModels::bake(block_registry);
+
+        let gpu_chunk_storage = GpuChunkStorage::new(
+            app.context.memory_allocator().clone(),
+            &app.memory_tracker,
+            chunk_capacity,
+        );
+        let cull_descriptor_set = DescriptorSet::new(
+            app.descriptor_set_allocator.clone(),
+            cull_pipeline.layout().set_layouts()[0].clone(),
+            [
+                gpu_chunk_storage.write_chunk_pages(0),
+                WriteDescriptorSet::buffer(1, gpu_chunk_storage.visible_chunk_buffer.clone()),
+                WriteDescriptorSet::buffer(2, gpu_chunk_storage.visible_index_buffer.clone()),
+                WriteDescriptorSet::buffer(3, gpu_chunk_storage.draw_command_buffer.clone()),
+            ],
+            None,
+        )
+        .unwrap();
+        let mut staging = StagingRing::new(app.memory_allocator(), STAGING_RING_SIZE);
+        let mut memory = vec![app
+            .memory_tracker
+            .track_buffer(MemoryCategory::Staging, staging.buffer())];
+
+        let (descriptor_sets, block_textures) = {
+            let mut command_buffer = RecordingCommandBuffer::new(
+                app.command_buffer_allocator.clone(),
+                queue.queue_family_index(),
+                CommandBufferLevel::Primary,
+                CommandBufferBeginInfo {
+                    usage: CommandBufferUsage::OneTimeSubmit,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
 
             let set_layouts = pipeline.layout().set_layouts();
 
@@ -321,8 +872,8 @@ impl RenderFacesPipeline {
                 app.descriptor_set_allocator.clone(),
                 set_layouts[0].clone(),
                 [
-                    WriteDescriptorSet::buffer(0, gpu_chunk_storage.chunk_buffer.clone()),
-                    WriteDescriptorSet::buffer(1, gpu_chunk_storage.index_buffer.clone()),
+                    gpu_chunk_storage.write_chunk_pages(0),
+                    WriteDescriptorSet::buffer(1, gpu_chunk_storage.visible_index_buffer.clone()),
                 ],
                 None,
             )
@@ -331,104 +882,472 @@ impl RenderFacesPipeline {
             let voxel_buffer = Buffer::new_unsized::<task::VoxelBuffer>(
                 app.context.memory_allocator().clone(),
                 BufferCreateInfo {
-                    usage: BufferUsage::STORAGE_BUFFER,
+                    usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
                     ..Default::default()
                 },
+                // Only written by the copy from the staging ring below
                 AllocationCreateInfo {
-                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
                     ..Default::default()
                 },
-                2,
+                baked_models.voxels.len().max(1) as u64,
             )
             .unwrap();
+            if !baked_models.voxels.is_empty() {
+                let staged_voxels = staging.upload(&baked_models.voxels);
+                command_buffer
+                    .copy_buffer(CopyBufferInfo::buffers(staged_voxels, voxel_buffer.clone()))
+                    .unwrap();
+            }
+            memory.push(
+                app.memory_tracker
+                    .track_buffer(MemoryCategory::BlockModels, &voxel_buffer),
+            );
 
-            {
-                let mut voxel_write = voxel_buffer.write().unwrap();
-                voxel_write.voxels[0] = task::Voxel {
-                    faces: [
-                        Padded(task::VoxelFace {
-                            cullface: 1,
-                            texture_index: 0,
-                            uv: [0.0, 0.0, 1.0, 1.0],
-                        }),
-                        Padded(task::VoxelFace {
-                            cullface: 1,
-                            texture_index: 0,
-                            uv: [0.0, 0.0, 1.0, 1.0],
-                        }),
-                        Padded(task::VoxelFace {
-                            cullface: 1,
-                            texture_index: 0,
-                            uv: [0.0, 0.0, 1.0, 1.0],
-                        }),
-                        Padded(task::VoxelFace {
-                            cullface: 1,
-                            texture_index: 0,
-                            uv: [0.0, 0.0, 1.0, 1.0],
-                        }),
-                        Padded(task::VoxelFace {
-                            cullface: 1,
-                            texture_index: 0,
-                            uv: [0.0, 0.0, 1.0, 1.0],
-                        }),
-                        Padded(task::VoxelFace {
-                            cullface: 6,
-                            texture_index: 0,
-                            uv: [0.0, 0.0, 1.0, 1.0],
-                        }),
-                    ],
-                    from: Padded([0.0, 0.0, 0.0]),
-                    to: Padded([1.0, 1.0, 1.0]),
-                };
-                voxel_write.voxels[1] = voxel_write.voxels[0];
-                voxel_write.voxels[1].from = Padded([0.5, 0.5, 0.5]);
-                voxel_write.voxels[1].to = Padded([1.5, 1.5, 1.5]);
+            let material_buffer = Buffer::new_slice::<GpuMaterial>(
+                app.context.memory_allocator().clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                    ..Default::default()
+                },
+                baked_models.materials.len().max(1) as u64,
+            )
+            .unwrap();
+            if !baked_models.materials.is_empty() {
+                let staged_materials = staging.upload(&baked_models.materials);
+                command_buffer
+                    .copy_buffer(CopyBufferInfo::buffers(
+                        staged_materials,
+                        material_buffer.clone(),
+                    ))
+                    .unwrap();
             }
+            memory.push(
+                app.memory_tracker
+                    .track_buffer(MemoryCategory::BlockModels, &material_buffer),
+            );
 
             let descriptor_set_1 = DescriptorSet::new(
                 app.descriptor_set_allocator.clone(),
                 set_layouts[1].clone(),
-                [WriteDescriptorSet::buffer(0, voxel_buffer.clone())],
+                [
+                    WriteDescriptorSet::buffer(0, voxel_buffer.clone()),
+                    WriteDescriptorSet::buffer(1, material_buffer),
+                ],
                 None,
             )
             .unwrap();
 
-            vec![descriptor_set_0, descriptor_set_1]
+            let (textures, textures_memory) = upload_textures(
+                &block_registry.texture_registry,
+                false,
+                app.memory_allocator(),
+                &app.memory_tracker,
+                &mut staging,
+                &mut command_buffer,
+            );
+            memory.push(textures_memory);
+            let (normal_maps, normal_maps_memory) = upload_textures(
+                &block_registry.texture_registry,
+                true,
+                app.memory_allocator(),
+                &app.memory_tracker,
+                &mut staging,
+                &mut command_buffer,
+            );
+            memory.push(normal_maps_memory);
+
+            let descriptor_set_2 = DescriptorSet::new(
+                app.descriptor_set_allocator.clone(),
+                set_layouts[2].clone(),
+                [
+                    WriteDescriptorSet::image_view_sampler(
+                        0,
+                        textures.clone(),
+                        block_sampler.clone(),
+                    ),
+                    WriteDescriptorSet::image_view_sampler(1, normal_maps, block_sampler.clone()),
+                ],
+                None,
+            )
+            .unwrap();
+
+            // Only read with texelFetch, so the sampler doesn't matter
+            let descriptor_set_3 = DescriptorSet::new(
+                app.descriptor_set_allocator.clone(),
+                set_layouts[3].clone(),
+                [WriteDescriptorSet::image_view_sampler(
+                    0,
+                    hi_z.view().clone(),
+                    block_sampler,
+                )],
+                None,
+            )
+            .unwrap();
+
+            let uploaded = Arc::new(
+                sync::now(queue.device().clone())
+                    .then_execute(queue.clone(), command_buffer.end().unwrap())
+                    .unwrap()
+                    .then_signal_fence_and_flush()
+                    .unwrap(),
+            );
+            uploaded.wait(None).unwrap();
+            staging.submit(uploaded);
+
+            (
+                vec![
+                    descriptor_set_0,
+                    descriptor_set_1,
+                    descriptor_set_2,
+                    descriptor_set_3,
+                ],
+                textures,
+            )
         };
         Self {
             pipeline,
+            depth_prepass,
             descriptor_sets,
+            descriptor_set_allocator: app.descriptor_set_allocator.clone(),
+            ray_traced_shadows,
+            cull_pipeline,
+            cull_descriptor_set,
+            depth_mode,
+            world_edges,
+            fog_wall_distance: (render_distance as usize * CHUNK_SIZE) as f32,
+            baked_models,
+            block_textures,
             gpu_chunk_storage,
+            staging,
+            _memory: memory,
+            visible_chunk_count: 0,
+            cave_culler: CaveCuller::default(),
+            world_events,
+            visible_sections: HashSet::new(),
+            visible_chunks_outdated: false,
+            camera_section: None,
         }
     }
 
-    pub fn render_cube_faces(
-        &self,
-        builder: &mut RecordingCommandBuffer,
-        previous_camera: &Camera,
-        camera: &Camera,
+    /// Traces the shadows against `top_level` from now on. Has to be called
+    /// before drawing with ray traced shadows, and again whenever the
+    /// structure is replaced.
+    pub fn set_acceleration_structure(&mut self, top_level: Arc<AccelerationStructure>) {
+        assert!(self.ray_traced_shadows);
+        let set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.pipeline.layout().set_layouts()[4].clone(),
+            [WriteDescriptorSet::acceleration_structure(0, top_level)],
+            None,
+        )
+        .unwrap();
+        self.descriptor_sets.truncate(4);
+        self.descriptor_sets.push(set);
+    }
+
+    /// The block textures, for other pipelines drawing with them.
+    pub fn block_textures(&self) -> &Arc<ImageView> {
+        &self.block_textures
+    }
+
+    /// Records the upload of the columns that `entered` render distance into
+    /// `command_buffer` and frees the slots of those that `left` it. They are
+    /// drawn from the next `update_visibility` on. The command buffer has to
+    /// be followed by `submit_uploads`.
+    #[instrument(skip_all)]
+    pub fn update_chunks(
+        &mut self,
+        command_buffer: &mut RecordingCommandBuffer,
+        world: &World,
+        entered: &[ColumnPosition],
+        left: &[ColumnPosition],
+    ) {
+        let (reloaded, edited) = self.receive_world_events(world);
+        if entered.is_empty() && left.is_empty() && reloaded.is_empty() && edited.is_empty() {
+            return;
+        }
+
+        self.gpu_chunk_storage.wait_for_shader_reads(command_buffer);
+
+        for &column in left {
+            for chunk_position in column.sections(world.height) {
+                self.gpu_chunk_storage.remove(chunk_position);
+                self.cave_culler.remove(chunk_position);
+            }
+        }
+
+        // Faces on the borders of already uploaded neighbors may have been
+        // hidden by or exposed through the new columns, so those are redone.
+        let mut columns = entered.iter().copied().collect::<HashSet<_>>();
+        for &column in entered {
+            columns.extend(column.neighbors().into_iter().filter(|neighbor| {
+                column_sections_uploaded(&self.gpu_chunk_storage, world, *neighbor)
+            }));
+        }
+        let mut sections = columns
+            .into_iter()
+            .flat_map(|column| column.sections(world.height))
+            .collect::<HashSet<_>>();
+        sections.extend(reloaded.iter().copied());
+
+        let edited_sections = edited
+            .iter()
+            .flat_map(|&position| sections_affected_by_block(position))
+            .filter(|&chunk_position| self.gpu_chunk_storage.contains(chunk_position))
+            .collect::<HashSet<_>>();
+        self.cave_culler.update(
+            world,
+            entered
+                .iter()
+                .flat_map(|column| column.sections(world.height))
+                .chain(reloaded)
+                .chain(edited_sections),
+        );
+
+        // Edits only redo the blocks around them, in sections that aren't
+        // redone as a whole anyway
+        let mut block_updates = HashMap::<_, Vec<_>>::new();
+        let affected_blocks = edited
+            .iter()
+            .flat_map(|&position| blocks_affected_by_block(position))
+            .collect::<HashSet<_>>();
+        for position in affected_blocks {
+            let chunk_position = ChunkPosition::of_block(position);
+            if sections.contains(&chunk_position)
+                || !self.gpu_chunk_storage.contains(chunk_position)
+            {
+                continue;
+            }
+            block_updates
+                .entry(chunk_position)
+                .or_default()
+                .push(block_update(
+                    world,
+                    position,
+                    &self.baked_models,
+                    self.world_edges,
+                ));
+        }
+        for (chunk_position, updates) in block_updates {
+            let bounds = self.gpu_chunk_storage.bounds(world, chunk_position);
+            self.gpu_chunk_storage.update(
+                &mut self.staging,
+                command_buffer,
+                chunk_position,
+                bounds,
+                updates,
+            );
+        }
+
+        let _span = info_span!("upload_sections", count = sections.len()).entered();
+        for chunk_position in sections {
+            let updates =
+                section_updates(world, chunk_position, &self.baked_models, self.world_edges);
+            let bounds = self.gpu_chunk_storage.bounds(world, chunk_position);
+            self.gpu_chunk_storage.remove(chunk_position);
+            self.gpu_chunk_storage.update(
+                &mut self.staging,
+                command_buffer,
+                chunk_position,
+                bounds,
+                updates,
+            );
+        }
+
+        self.visible_chunks_outdated = true;
+    }
+
+    /// Handles the world events received since the last call, returning the
+    /// uploaded sections that were replaced as a whole and have to be
+    /// uploaded again, and the blocks that changed. Unloaded sections are
+    /// dropped right away.
+    fn receive_world_events(
+        &mut self,
+        world: &World,
+    ) -> (HashSet<ChunkPosition>, HashSet<[i32; 3]>) {
+        let mut reloaded = HashSet::new();
+        let mut edited = HashSet::new();
+        for event in self.world_events.try_iter() {
+            match event {
+                WorldEvent::BlockChanged { position, .. } => {
+                    self.gpu_chunk_storage.block_changed(world, position);
+                    edited.insert(position);
+                }
+                WorldEvent::ChunkLoaded(chunk_position) => {
+                    self.gpu_chunk_storage.forget_bounds(chunk_position);
+                    reloaded.insert(chunk_position);
+                }
+                WorldEvent::ChunkUnloaded(chunk_position) => {
+                    reloaded.remove(&chunk_position);
+                    self.gpu_chunk_storage.remove(chunk_position);
+                    self.cave_culler.remove(chunk_position);
+                    self.visible_chunks_outdated = true;
+                    // Faces of the neighbors toward it are shown from now on
+                    if self.world_edges != WorldEdges::Solid {
+                        reloaded.extend(
+                            [
+                                Direction::North,
+                                Direction::South,
+                                Direction::East,
+                                Direction::West,
+                            ]
+                            .map(|direction| chunk_position.offset(direction)),
+                        );
+                    }
+                }
+            }
+        }
+        reloaded.retain(|&chunk_position| self.gpu_chunk_storage.contains(chunk_position));
+        (reloaded, edited)
+    }
+
+    /// Records the upload of the chunks the camera at `camera_position` can
+    /// see into, if they changed.
+    #[instrument(skip_all)]
+    pub fn update_visibility(
+        &mut self,
+        command_buffer: &mut RecordingCommandBuffer,
+        camera_position: [i32; 3],
     ) {
+        let visible_sections = self.cave_culler.visible_sections(camera_position);
+        let camera_section = ChunkPosition::of_block(camera_position);
+        // Moving to another section changes the order too
+        if !self.visible_chunks_outdated
+            && visible_sections == self.visible_sections
+            && self.camera_section == Some(camera_section)
+        {
+            return;
+        }
+
+        self.gpu_chunk_storage.wait_for_shader_reads(command_buffer);
+        self.visible_chunk_count = self.gpu_chunk_storage.upload_visible_chunks(
+            &mut self.staging,
+            command_buffer,
+            &visible_sections,
+            camera_section,
+        ) as u32;
+        self.visible_sections = visible_sections;
+        self.visible_chunks_outdated = false;
+        self.camera_section = Some(camera_section);
+    }
+
+    /// Number of uploaded chunks the camera can see into, which the culling
+    /// pass scans.
+    pub fn visible_chunk_count(&self) -> u32 {
+        self.visible_chunk_count
+    }
+
+    /// Hands the staging space used by the uploads recorded since the last
+    /// call back to the ring once `fence` is signaled, returning how many
+    /// bytes they uploaded.
+    pub fn submit_uploads(&mut self, fence: impl UploadFence + 'static) -> u64 {
+        self.staging.submit(fence)
+    }
+
+    /// Records the culling pass, which must run outside of rendering before
+    /// `render_cube_faces`.
+    #[instrument(skip_all)]
+    pub fn cull_blocks(&mut self, builder: &mut RecordingCommandBuffer, camera: &Camera) {
+        if self.visible_chunk_count == 0 {
+            return;
+        }
+
+        let reset = self.staging.upload(&[DrawMeshTasksIndirectCommand {
+            group_count_x: 0,
+            group_count_y: 1,
+            group_count_z: 1,
+        }]);
         builder
-            .bind_pipeline_graphics(self.pipeline.clone())
+            .copy_buffer(CopyBufferInfo::buffers(
+                reset,
+                self.gpu_chunk_storage.draw_command_buffer.clone(),
+            ))
+            .unwrap()
+            .bind_pipeline_compute(self.cull_pipeline.clone())
             .unwrap()
             .bind_descriptor_sets(
-                self.pipeline.bind_point(),
-                self.pipeline.layout().clone(),
+                PipelineBindPoint::Compute,
+                self.cull_pipeline.layout().clone(),
                 0,
-                self.descriptor_sets.to_vec(),
+                self.cull_descriptor_set.clone(),
             )
             .unwrap()
             .push_constants(
-                self.pipeline.layout().clone(),
+                self.cull_pipeline.layout().clone(),
                 0,
-                mesh::PushConstants {
-                    current_view_proj: (camera.proj * camera.view).into(),
-                    previous_view_proj: (previous_camera.proj * previous_camera.view).into(),
-                    jitter: camera.jitter.into(),
+                cull::PushConstants {
+                    view_proj: (camera.proj * camera.view).into(),
                 },
             )
             .unwrap();
-        unsafe { builder.draw_mesh_tasks([16u32.pow(3), 1, 1]).unwrap() };
+        unsafe {
+            builder
+                .dispatch([self.visible_chunk_count * CULL_WORKGROUPS_PER_CHUNK, 1, 1])
+                .unwrap()
+        };
+    }
+
+    /// Records the draw of all uploaded blocks. With `occlusion_culling`,
+    /// blocks hidden in the Hi-Z pyramid passed to `new` are skipped, so it
+    /// has to be built from the depth drawn with `previous_camera`.
+    pub fn render_cube_faces(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        previous_camera: &Camera,
+        camera: &Camera,
+        occlusion_culling: bool,
+        weather: &Weather,
+    ) {
+        // Both share the layout, and the prepass draws the same faces
+        for pipeline in self.depth_prepass.iter().chain([&self.pipeline]) {
+            builder
+                .bind_pipeline_graphics(pipeline.clone())
+                .unwrap()
+                .bind_descriptor_sets(
+                    pipeline.bind_point(),
+                    pipeline.layout().clone(),
+                    0,
+                    self.descriptor_sets.to_vec(),
+                )
+                .unwrap()
+                .push_constants(
+                    pipeline.layout().clone(),
+                    0,
+                    mesh::PushConstants {
+                        current_view_proj: (camera.proj * camera.view).into(),
+                        previous_view_proj: (previous_camera.proj * previous_camera.view).into(),
+                        jitter: camera.jitter.into(),
+                        occlusion_culling: occlusion_culling as u32,
+                        reversed_depth: self.depth_mode.is_reversed() as u32,
+                        camera_position: camera.position.to_homogeneous().into(),
+                        wetness: weather.wetness(),
+                        sky_light: weather.sky_light(),
+                        fog_wall: match self.world_edges {
+                            WorldEdges::FogWall => [
+                                SKY_COLOR[0],
+                                SKY_COLOR[1],
+                                SKY_COLOR[2],
+                                self.fog_wall_distance,
+                            ],
+                            WorldEdges::Solid | WorldEdges::Air => [0.0; 4],
+                        },
+                    },
+                )
+                .unwrap();
+            if self.visible_chunk_count > 0 {
+                unsafe {
+                    builder
+                        .draw_mesh_tasks_indirect(
+                            self.gpu_chunk_storage.draw_command_buffer.clone(),
+                        )
+                        .unwrap()
+                };
+            }
+        }
     }
 }
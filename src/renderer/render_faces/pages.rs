@@ -0,0 +1,97 @@
+/// Bits of a chunk index holding the slot of the chunk inside its page, the
+/// bits above them hold the page. The shaders split indices the same way.
+pub const CHUNK_PAGE_SHIFT: u32 = 16;
+/// Length of the array of chunk buffers the shaders declare.
+pub const MAX_CHUNK_PAGES: usize = 8;
+
+/// How the chunk slots are split into buffers, as one buffer holding all of
+/// them would exceed the storage buffer range of the device for large render
+/// distances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkPages {
+    chunks: u64,
+    chunks_per_page: u64,
+}
+
+impl ChunkPages {
+    /// Pages of at most `max_page_size` bytes holding `chunks` chunks of
+    /// `chunk_size` bytes.
+    pub fn new(chunks: u64, chunk_size: u64, max_page_size: u64) -> Self {
+        assert!(chunks > 0);
+        let chunks_per_page = (max_page_size / chunk_size)
+            .min(1 << CHUNK_PAGE_SHIFT)
+            .min(chunks);
+        assert!(chunks_per_page > 0, "a chunk doesn't fit in a buffer");
+        let pages = Self {
+            chunks,
+            chunks_per_page,
+        };
+        assert!(
+            pages.count() <= MAX_CHUNK_PAGES,
+            "{} chunks need more than {} pages",
+            chunks,
+            MAX_CHUNK_PAGES
+        );
+        pages
+    }
+
+    pub fn count(&self) -> usize {
+        self.chunks.div_ceil(self.chunks_per_page) as usize
+    }
+
+    /// Number of chunks in `page`; the last one may be shorter.
+    pub fn page_len(&self, page: usize) -> u64 {
+        let start = page as u64 * self.chunks_per_page;
+        (self.chunks - start).min(self.chunks_per_page)
+    }
+
+    /// The indices of all chunk slots.
+    pub fn chunk_indices(&self) -> impl DoubleEndedIterator<Item = u32> + '_ {
+        (0..self.count()).flat_map(move |page| {
+            (0..self.page_len(page) as u32)
+                .map(move |slot| (page as u32) << CHUNK_PAGE_SHIFT | slot)
+        })
+    }
+
+    /// The page of the chunk at `chunk_index` and its slot inside the page.
+    pub fn locate(chunk_index: u32) -> (usize, u64) {
+        (
+            (chunk_index >> CHUNK_PAGE_SHIFT) as usize,
+            (chunk_index & ((1 << CHUNK_PAGE_SHIFT) - 1)) as u64,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_pages() {
+        // Everything in one page while it fits
+        let pages = ChunkPages::new(10, 100, 4096);
+        assert_eq!(pages.count(), 1);
+        assert_eq!(pages.page_len(0), 10);
+        assert_eq!(
+            pages.chunk_indices().collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+
+        // 40 chunks fit in a page, the last page holds the rest
+        let pages = ChunkPages::new(100, 100, 4096);
+        assert_eq!(pages.count(), 3);
+        assert_eq!([0, 1, 2].map(|page| pages.page_len(page)), [40, 40, 20]);
+        let indices = pages.chunk_indices().collect::<Vec<_>>();
+        assert_eq!(indices.len(), 100);
+        assert_eq!(indices[40], 1 << CHUNK_PAGE_SHIFT);
+        assert_eq!(ChunkPages::locate(indices[99]), (2, 19));
+
+        // Pages never hold more chunks than the slot bits can address
+        let pages = ChunkPages::new(1 << 18, 1, u64::MAX);
+        assert_eq!(pages.count(), 4);
+        assert_eq!(
+            ChunkPages::locate(pages.chunk_indices().last().unwrap()),
+            (3, (1 << 16) - 1)
+        );
+    }
+}
@@ -0,0 +1,121 @@
+#version 460
+
+// Scans the blocks of the visible chunks and compacts the ones that have
+// voxels and are inside the view frustum into the visible index buffer,
+// counting them in the indirect draw command the task shader is dispatched
+// with. Every chunk is scanned by CHUNK_SIZE^3 / 256 workgroups. The visible
+// chunks come front to back, and as workgroups mostly run in order, so do
+// the blocks.
+
+layout(local_size_x = 256, local_size_y = 1, local_size_z = 1) in;
+
+// voxels: the offset of the first voxel in the low 16 bits, the number of
+// voxels in the 10 bits above and the connected bits in the top 6, like
+// `BlockVoxels` packs them
+struct Block {
+  uint voxels;
+  uint tint;  // RGBA8 biome color, white if untinted, with 1 minus the
+              // reflectivity as alpha
+};
+
+uint voxel_offset(Block block) { return block.voxels & 0xffff; }
+uint voxel_len(Block block) { return block.voxels >> 16 & 0x3ff; }
+uint connected_bits(Block block) { return block.voxels >> 26; }
+
+// A visible block is its chunk index above the bits of its block index
+const uint BLOCK_INDEX_BITS = 12;
+
+const uint CHUNK_SIZE = 16;
+struct Chunk {
+  ivec4 position;  // xyz: section position in chunks, w: packed block bounds
+  Block blocks[CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+};
+
+// Chunks are split over pages of buffers, as one buffer may not hold them
+// all. The bits of a chunk index above CHUNK_PAGE_SHIFT are its page, those
+// below its slot in the page, like `ChunkPages` splits them.
+const uint CHUNK_PAGE_SHIFT = 16;
+const uint CHUNK_SLOT_MASK = (1u << CHUNK_PAGE_SHIFT) - 1u;
+const uint MAX_CHUNK_PAGES = 8;
+layout(std430, set = 0, binding = 0) readonly buffer ChunkBuffer {
+  Chunk chunks[];
+}
+chunk_pages[MAX_CHUNK_PAGES];
+#define CHUNK(index) \
+  chunk_pages[(index) >> CHUNK_PAGE_SHIFT].chunks[(index) & CHUNK_SLOT_MASK]
+layout(std430, set = 0, binding = 1) readonly buffer VisibleChunkBuffer {
+  uint visible_chunks[];
+};
+layout(std430, set = 0, binding = 2) writeonly buffer VisibleIndexBuffer {
+  uint visible_indices[];
+};
+// VkDrawMeshTasksIndirectCommandEXT, reset to (0, 1, 1) before the pass
+layout(std430, set = 0, binding = 3) buffer DrawCommand {
+  uint group_count_x;
+  uint group_count_y;
+  uint group_count_z;
+};
+
+layout(push_constant) uniform PushConstants { mat4 view_proj; }
+pc;
+
+// Whether the chunk of the workgroup may be inside the frustum
+shared bool chunk_in_frustum;
+
+// Whether any part of the box of `size` at `box_min` may be inside the
+// frustum, i.e. its corners are not all outside the same clip plane.
+bool in_frustum(vec3 box_min, vec3 size) {
+  uint outside_all = 0x3f;
+  for (int i = 0; i < 8; ++i) {
+    vec3 corner =
+        box_min + vec3(i & 1, (i >> 1) & 1, (i >> 2) & 1) * size;
+    vec4 clip = pc.view_proj * vec4(corner, 1.0);
+    uint outside = 0;
+    outside |= clip.x < -clip.w ? 0x01 : 0;
+    outside |= clip.x > clip.w ? 0x02 : 0;
+    outside |= clip.y < -clip.w ? 0x04 : 0;
+    outside |= clip.y > clip.w ? 0x08 : 0;
+    outside |= clip.z < 0.0 ? 0x10 : 0;
+    outside |= clip.z > clip.w ? 0x20 : 0;
+    outside_all &= outside;
+  }
+  return outside_all == 0;
+}
+
+void main() {
+  const uint WORKGROUPS_PER_CHUNK = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE / 256;
+  uint chunk_index = visible_chunks[gl_WorkGroupID.x / WORKGROUPS_PER_CHUNK];
+  uint block_index =
+      gl_WorkGroupID.x % WORKGROUPS_PER_CHUNK * 256 + gl_LocalInvocationID.x;
+  ivec3 chunk_origin = CHUNK(chunk_index).position.xyz * int(CHUNK_SIZE);
+
+  // Whole chunks outside the frustum are skipped without reading a block.
+  // Only the box around their blocks is tested, 4 bits per coordinate of
+  // its first and last block, see `SectionBounds::pack`.
+  if (gl_LocalInvocationIndex == 0) {
+    uint bounds = uint(CHUNK(chunk_index).position.w);
+    uvec3 bounds_min = uvec3(bounds, bounds >> 4, bounds >> 8) & 0xf;
+    uvec3 bounds_max = uvec3(bounds >> 12, bounds >> 16, bounds >> 20) & 0xf;
+    chunk_in_frustum = in_frustum(vec3(chunk_origin + ivec3(bounds_min)),
+                                  vec3(bounds_max - bounds_min + 1));
+  }
+  barrier();
+  if (!chunk_in_frustum ||
+      voxel_len(CHUNK(chunk_index).blocks[block_index]) == 0) {
+    return;
+  }
+
+  vec3 block_min = vec3(chunk_origin + ivec3(block_index % CHUNK_SIZE,
+                                             (block_index / CHUNK_SIZE) % CHUNK_SIZE,
+                                             block_index / (CHUNK_SIZE * CHUNK_SIZE)));
+  if (!in_frustum(block_min, vec3(1.0))) {
+    return;
+  }
+
+  // The task shader skips the blocks counted past the end of the buffer
+  uint visible_index = atomicAdd(group_count_x, 1);
+  if (visible_index < visible_indices.length()) {
+    visible_indices[visible_index] =
+        chunk_index << BLOCK_INDEX_BITS | block_index;
+  }
+}
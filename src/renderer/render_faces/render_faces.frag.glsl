@@ -1,23 +1,177 @@
 #version 460
 
+// With RAY_TRACED_SHADOWS defined, faces toward the sun trace a ray toward
+// it through the world's acceleration structure and are shaded as facing
+// away where it hits something.
+#ifdef RAY_TRACED_SHADOWS
+#extension GL_EXT_ray_query : require
+#endif
+
 layout(location = 0) in VertexOut {
   vec4 current_position;
   vec4 previous_position;
+  vec3 world_position;
   vec3 normal;
+  // Directions of increasing u and decreasing v, for the normal map
+  flat vec3 tangent;
+  flat vec3 bitangent;
   vec2 tex_coords;
   flat uint texture_index;
+  flat uint material;
+  flat vec4 tint;
 }
 v_out;
 
+// `BlockType::material` of every block type
+struct Material {
+  float roughness;
+  float metallic;
+  float emissive;
+  float translucency;
+};
+
+layout(std430, set = 1, binding = 1) readonly buffer MaterialBuffer {
+  Material materials[];
+};
+
+layout(set = 2, binding = 0) uniform sampler2DArray block_textures;
+// Flat where a texture has no normal map
+layout(set = 2, binding = 1) uniform sampler2DArray block_normals;
+
+#ifdef RAY_TRACED_SHADOWS
+layout(set = 4, binding = 0) uniform accelerationStructureEXT world;
+#endif
+
+// Same as in the mesh shader
+layout(push_constant) uniform PushConstants {
+  mat4 current_view_proj;
+  mat4 previous_view_proj;
+  vec2 jitter;
+  uint occlusion_culling;
+  uint reversed_depth;
+  vec4 camera_position;  // for the view direction of specular highlights
+  // rgb: color faces fade into toward the edge of render distance, w: the
+  // distance they are hidden from, 0 without a fog wall
+  vec4 fog_wall;
+  float wetness;  // from 0 when dry to 1 when soaked by rain
+  float sky_light;  // fraction of the sun and sky light let through
+}
+pc;
+
 layout(location = 0) out vec4 frag_color;
 layout(location = 1) out vec2 motion_vector;
 
+const float PI = 3.14159265;
+const vec3 LIGHT_DIRECTION = normalize(vec3(0.3, 1.0, 0.5));
+// Radiance of the sun; a white diffuse face toward it gets 0.4 from it
+const vec3 SUN_COLOR = vec3(1.0, 0.97, 0.92) * 0.4 * PI;
+// Light from the sky above and bounced off the ground below
+const vec3 SKY_AMBIENT = vec3(0.62, 0.66, 0.75);
+const vec3 GROUND_AMBIENT = vec3(0.5, 0.47, 0.44);
+// Reflectance of dielectrics seen head-on
+const vec3 DIELECTRIC_F0 = vec3(0.04);
+// How far shadows are cast, in blocks
+const float SHADOW_DISTANCE = 128.0;
+// Blocks along a section, over which faces fade into the fog wall
+const float CHUNK_SIZE = 16.0;
+// Albedo and roughness left on soaked faces facing up
+const float WET_DARKENING = 0.6;
+const float WET_ROUGHNESS = 0.3;
+
+float sunlight(float facing) {
+#ifdef RAY_TRACED_SHADOWS
+  if (facing > 0.0) {
+    rayQueryEXT ray_query;
+    // Off the face, so the ray doesn't hit it
+    vec3 origin = v_out.world_position + v_out.normal * 0.001;
+    rayQueryInitializeEXT(
+        ray_query, world,
+        gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT, 0xff,
+        origin, 0.0, LIGHT_DIRECTION, SHADOW_DISTANCE);
+    while (rayQueryProceedEXT(ray_query)) {
+    }
+    if (rayQueryGetIntersectionTypeEXT(ray_query, true) !=
+        gl_RayQueryCommittedIntersectionNoneEXT) {
+      return 0.0;
+    }
+  }
+#endif
+  return max(facing, 0.0);
+}
+
+// GGX normal distribution
+float distribution(float n_dot_h, float alpha) {
+  float alpha_2 = alpha * alpha;
+  float d = n_dot_h * n_dot_h * (alpha_2 - 1.0) + 1.0;
+  return alpha_2 / (PI * d * d);
+}
+
+// Smith masking and shadowing, with Schlick's approximation for each side
+float visibility(float n_dot_l, float n_dot_v, float roughness) {
+  float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+  float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+  float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+  return g_l * g_v;
+}
+
+vec3 fresnel(float cos_theta, vec3 f0) {
+  return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
 void main() {
   motion_vector = v_out.previous_position.xy / v_out.previous_position.w -
                   v_out.current_position.xy / v_out.current_position.w;
 
-  // Map each component from [-1, 1] to [0, 1]
-  vec3 color = v_out.normal * 0.5 + 0.5;
+  vec3 layer = vec3(v_out.tex_coords, float(v_out.texture_index));
+  vec4 texel = texture(block_textures, layer);
+  vec3 mapped = texture(block_normals, layer).xyz * 2.0 - 1.0;
+  vec3 normal = normalize(mat3(v_out.tangent, v_out.bitangent, v_out.normal) *
+                          mapped);
+  Material material = materials[v_out.material];
+  // Rain soaks faces the more they face up, darkening them and making them
+  // shinier
+  float wet = pc.wetness * clamp(v_out.normal.y, 0.0, 1.0);
+  vec3 albedo = texel.rgb * v_out.tint.rgb * mix(1.0, WET_DARKENING, wet);
+  material.roughness *= mix(1.0, WET_ROUGHNESS, wet);
+  vec3 diffuse_color = albedo * (1.0 - material.metallic);
+  vec3 f0 = mix(DIELECTRIC_F0, albedo, material.metallic);
+  // Keep highlights of smooth faces from collapsing to a point
+  float roughness = max(material.roughness, 0.05);
+
+  vec3 to_camera = normalize(pc.camera_position.xyz - v_out.world_position);
+  vec3 halfway = normalize(LIGHT_DIRECTION + to_camera);
+  float n_dot_l = dot(normal, LIGHT_DIRECTION);
+  float n_dot_v = max(dot(normal, to_camera), 1e-4);
+  float n_dot_h = max(dot(normal, halfway), 0.0);
+
+  // Lambert diffuse and GGX specular, with the diffuse part of light
+  // reflected at the surface taken out
+  vec3 f = fresnel(max(dot(halfway, to_camera), 0.0), f0);
+  float lit = max(n_dot_l, 0.0);
+  vec3 specular = f * distribution(n_dot_h, roughness * roughness) *
+                  visibility(lit, n_dot_v, roughness) /
+                  max(4.0 * lit * n_dot_v, 1e-4);
+  vec3 diffuse = (1.0 - f) * diffuse_color / PI;
+  // Translucent blocks let the sun through from behind
+  vec3 transmitted =
+      material.translucency * max(-n_dot_l, 0.0) * diffuse_color / PI;
+  vec3 color = ((diffuse + specular) * sunlight(n_dot_l) + transmitted) *
+               SUN_COLOR * pc.sky_light;
+
+  vec3 ambient = mix(GROUND_AMBIENT, SKY_AMBIENT, normal.y * 0.5 + 0.5) *
+                 pc.sky_light;
+  color += ambient * (diffuse_color + fresnel(n_dot_v, f0) * material.metallic);
+  color += albedo * material.emissive;
+
+  // Faces fade out over the last section before the edge of the world, so
+  // it looks like a wall of fog
+  if (pc.fog_wall.w > 0.0) {
+    float distance =
+        length(v_out.world_position.xz - pc.camera_position.xz);
+    float fog = smoothstep(pc.fog_wall.w - CHUNK_SIZE, pc.fog_wall.w, distance);
+    color = mix(color, pc.fog_wall.rgb, fog);
+  }
 
-  frag_color = vec4(color, 1.0);  // Set alpha to 1.0 for full opacity
+  // Alpha is left for the reflectivity, which SSR reads
+  frag_color = vec4(color, v_out.tint.a);
 }
@@ -18,6 +18,7 @@ struct VoxelFace {
 
 struct Voxel {
   vec3 from;
+  uint material;  // block type the voxel belongs to
   vec3 to;
   VoxelFace faces[6];
 };
@@ -28,6 +29,12 @@ layout(push_constant) uniform PushConstants {
   mat4 current_view_proj;
   mat4 previous_view_proj;
   vec2 jitter;
+  uint occlusion_culling;  // test blocks against the previous frame's Hi-Z
+  uint reversed_depth;     // depth goes from 1 near to 0 far
+  vec4 camera_position;    // faces seen from behind are culled
+  vec4 fog_wall;           // for the fragment shader
+  float wetness;
+  float sky_light;
 }
 pc;
 
@@ -35,6 +42,7 @@ struct Task {
   vec3 block_translation;
   uint voxel_offset;
   uint connected_bits;
+  uint tint;
 };
 taskPayloadSharedEXT Task task;
 
@@ -44,9 +52,15 @@ taskPayloadSharedEXT Task task;
 layout(location = 0) out VertexOut {
   vec4 current_position;
   vec4 previous_position;
+  vec3 world_position;
   vec3 normal;
+  // Directions of increasing u and decreasing v, for the normal map
+  flat vec3 tangent;
+  flat vec3 bitangent;
   vec2 tex_coords;
   flat uint texture_index;
+  flat uint material;
+  flat vec4 tint;
 }
 v_out[];
 
@@ -85,7 +99,18 @@ const vec3 cube_normals[6] = {
 struct Face {
   vec3 vertices[4];
   vec3 normal;
+  vec3 tangent;
+  vec3 bitangent;
   vec2 tex_coords[4];
+  uint texture_index;
+};
+
+// Corners of the face's uv rectangle, in the same order as cube_vertices
+const vec2 face_corners[4] = {
+    vec2(0.0, 0.0),
+    vec2(1.0, 0.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0),
 };
 
 // Function to generate all faces of a voxel
@@ -101,12 +126,26 @@ uint generateVoxelFaces(Voxel voxel, out Face faces[6]) {
       faces[faceCount].vertices[j] =
           voxel.from + cube_vertices[i][j] * (voxel.to - voxel.from);
     }
+    // Faces seen from behind or without area, like the sides of flat
+    // models, cover no pixels
+    vec3 edge_0 = faces[faceCount].vertices[1] - faces[faceCount].vertices[0];
+    vec3 edge_1 = faces[faceCount].vertices[3] - faces[faceCount].vertices[0];
+    vec3 area = cross(edge_0, edge_1);
+    vec3 to_camera = pc.camera_position.xyz - task.block_translation -
+                     faces[faceCount].vertices[0];
+    if (dot(area, area) == 0.0 || dot(cube_normals[i], to_camera) <= 0.0) {
+      continue;
+    }
     faces[faceCount].normal = cube_normals[i];
-    // TODO: calculate tex_coords
-    faces[faceCount].tex_coords[0] = vec2(0.0, 0.0);
-    faces[faceCount].tex_coords[1] = vec2(1.0, 0.0);
-    faces[faceCount].tex_coords[2] = vec2(1.0, 1.0);
-    faces[faceCount].tex_coords[3] = vec2(0.0, 1.0);
+    vec4 uv = voxel.faces[i].uv;
+    for (int j = 0; j < 4; ++j) {
+      faces[faceCount].tex_coords[j] = mix(uv.xy, uv.zw, face_corners[j]);
+    }
+    faces[faceCount].texture_index = voxel.faces[i].texture_index;
+    // u runs along the first edge and v along the last one, backwards where
+    // the uv rectangle is flipped
+    faces[faceCount].tangent = normalize(edge_0) * sign(uv.z - uv.x);
+    faces[faceCount].bitangent = -normalize(edge_1) * sign(uv.w - uv.y);
 
     faceCount++;
   }
@@ -123,6 +162,8 @@ void main() {
 
   SetMeshOutputsEXT(faceCount * 4, faceCount * 2);
 
+  vec4 tint = unpackUnorm4x8(task.tint);
+
   mat4 jitterTransform = mat4(1.0);
   jitterTransform[3] = vec4(pc.jitter, 0.0, 1.0);
 
@@ -137,9 +178,14 @@ void main() {
           jitterTransform * currentPosition;
       v_out[i * 4 + j].current_position = currentPosition;
       v_out[i * 4 + j].previous_position = pc.previous_view_proj * vertex;
+      v_out[i * 4 + j].world_position = vertex.xyz;
       v_out[i * 4 + j].normal = faces[i].normal;
+      v_out[i * 4 + j].tangent = faces[i].tangent;
+      v_out[i * 4 + j].bitangent = faces[i].bitangent;
       v_out[i * 4 + j].tex_coords = faces[i].tex_coords[j];
-      v_out[i * 4 + j].texture_index = voxel.faces[i].texture_index;
+      v_out[i * 4 + j].texture_index = faces[i].texture_index;
+      v_out[i * 4 + j].material = voxel.material;
+      v_out[i * 4 + j].tint = tint;
     }
   }
 }
@@ -7,19 +7,40 @@ layout(local_size_x = 1, local_size_y = 1, local_size_z = 1) in;
 //////////////////////////////////////////////////
 // UNIFORMS
 
+// voxels: the offset of the first voxel in the low 16 bits, the number of
+// voxels in the 10 bits above and the connected bits in the top 6, like
+// `BlockVoxels` packs them
 struct Block {
-  uint voxel_offset;
-  uint voxel_len;
-  uint connected_bits;  // 6 bits, can be u8
+  uint voxels;
+  uint tint;  // RGBA8 biome color, white if untinted, with 1 minus the
+              // reflectivity as alpha
 };
 
+uint voxel_offset(Block block) { return block.voxels & 0xffff; }
+uint voxel_len(Block block) { return block.voxels >> 16 & 0x3ff; }
+uint connected_bits(Block block) { return block.voxels >> 26; }
+
+// A visible block is its chunk index above the bits of its block index
+const uint BLOCK_INDEX_BITS = 12;
+
 const uint CHUNK_SIZE = 16;
 struct Chunk {
+  ivec4 position;  // xyz: section position in chunks, w: packed block bounds
   Block blocks[CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
 };
 
-layout(std430, set = 0, binding = 0) buffer ChunkBuffer { Chunk chunks[]; };
-layout(std430, set = 0, binding = 1) buffer IndexBuffer { uvec2 indices[]; };
+// Chunks are split over pages of buffers, as one buffer may not hold them
+// all. The bits of a chunk index above CHUNK_PAGE_SHIFT are its page, those
+// below its slot in the page, like `ChunkPages` splits them.
+const uint CHUNK_PAGE_SHIFT = 16;
+const uint CHUNK_SLOT_MASK = (1u << CHUNK_PAGE_SHIFT) - 1u;
+const uint MAX_CHUNK_PAGES = 8;
+layout(std430, set = 0, binding = 0) buffer ChunkBuffer { Chunk chunks[]; }
+chunk_pages[MAX_CHUNK_PAGES];
+#define CHUNK(index) \
+  chunk_pages[(index) >> CHUNK_PAGE_SHIFT].chunks[(index) & CHUNK_SLOT_MASK]
+// The blocks the culling pass found visible, one workgroup each
+layout(std430, set = 0, binding = 1) buffer IndexBuffer { uint indices[]; };
 
 struct VoxelFace {
   vec4 uv;
@@ -29,12 +50,29 @@ struct VoxelFace {
 
 struct Voxel {
   vec3 from;
+  uint material;  // block type the voxel belongs to
   vec3 to;
   VoxelFace faces[6];
 };
 
 layout(std430, set = 1, binding = 0) buffer VoxelBuffer { Voxel voxels[]; };
 
+layout(push_constant) uniform PushConstants {
+  mat4 current_view_proj;
+  mat4 previous_view_proj;
+  vec2 jitter;
+  uint occlusion_culling;  // test blocks against the previous frame's Hi-Z
+  uint reversed_depth;     // depth goes from 1 near to 0 far
+  vec4 camera_position;    // faces seen from behind are culled
+  vec4 fog_wall;           // for the fragment shader
+  float wetness;
+  float sky_light;
+}
+pc;
+
+// Farthest-depth pyramid of the previous frame's depth buffer
+layout(set = 3, binding = 0) uniform sampler2D hi_z;
+
 //////////////////////////////////////////////////
 // OUTPUTS
 
@@ -42,6 +80,7 @@ struct Task {
   vec3 block_translation;
   uint voxel_offset;
   uint connected_bits;
+  uint tint;
 };
 taskPayloadSharedEXT Task task;
 
@@ -52,21 +91,73 @@ uint voxel_count_lod(uint voxels_for_current_block) {
   return voxels_for_current_block;
 }
 
+// Whether the block at `block_min` was entirely behind the previous frame's
+// depth. Blocks that were off-screen or crossing the near plane are kept.
+bool occluded(vec3 block_min) {
+  vec3 ndc_min = vec3(1.0);
+  vec3 ndc_max = vec3(-1.0);
+  for (int i = 0; i < 8; ++i) {
+    vec3 corner = block_min + vec3(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+    vec4 clip = pc.previous_view_proj * vec4(corner, 1.0);
+    if (clip.w <= 0.0) {
+      return false;
+    }
+    vec3 ndc = clip.xyz / clip.w;
+    ndc_min = min(ndc_min, ndc);
+    ndc_max = max(ndc_max, ndc);
+  }
+  if (any(greaterThan(ndc_min.xy, vec2(1.0))) ||
+      any(lessThan(ndc_max.xy, vec2(-1.0)))) {
+    return false;
+  }
+
+  vec2 uv_min = clamp(ndc_min.xy * 0.5 + 0.5, 0.0, 1.0);
+  vec2 uv_max = clamp(ndc_max.xy * 0.5 + 0.5, 0.0, 1.0);
+
+  // The level at which the block covers at most 2x2 texels
+  vec2 size = (uv_max - uv_min) * vec2(textureSize(hi_z, 0));
+  int level = int(ceil(log2(max(max(size.x, size.y), 1.0))));
+  level = clamp(level, 0, textureQueryLevels(hi_z) - 1);
+
+  ivec2 level_size = textureSize(hi_z, level);
+  ivec2 texel_min = min(ivec2(uv_min * vec2(level_size)), level_size - 1);
+  ivec2 texel_max = min(ivec2(uv_max * vec2(level_size)), level_size - 1);
+  bool reversed = pc.reversed_depth != 0;
+  float far_depth = reversed ? 1.0 : 0.0;
+  for (int y = texel_min.y; y <= texel_max.y; ++y) {
+    for (int x = texel_min.x; x <= texel_max.x; ++x) {
+      float depth = texelFetch(hi_z, ivec2(x, y), level).r;
+      far_depth = reversed ? min(far_depth, depth) : max(far_depth, depth);
+    }
+  }
+  // Hidden if even its nearest corner is behind
+  return reversed ? ndc_max.z < far_depth : ndc_min.z > far_depth;
+}
+
 void main() {
-  uvec2 index = indices[gl_GlobalInvocationID.x];
-  uint chunk_index = index.x;
-  uint block_index = index.y;
-  Block block = chunks[chunk_index].blocks[block_index];
+  if (gl_GlobalInvocationID.x >= indices.length()) {
+    return;
+  }
+  uint index = indices[gl_GlobalInvocationID.x];
+  uint chunk_index = index >> BLOCK_INDEX_BITS;
+  uint block_index = index & ((1u << BLOCK_INDEX_BITS) - 1u);
+  Block block = CHUNK(chunk_index).blocks[block_index];
+  ivec3 chunk_origin = CHUNK(chunk_index).position.xyz * int(CHUNK_SIZE);
 
-  task.voxel_offset = block.voxel_offset;
-  task.connected_bits = block.connected_bits;
+  task.voxel_offset = voxel_offset(block);
+  task.connected_bits = connected_bits(block);
+  task.tint = block.tint;
   task.block_translation =  // x, y, z
-      vec3(block_index % CHUNK_SIZE, (block_index / CHUNK_SIZE) % CHUNK_SIZE,
-           block_index / (CHUNK_SIZE * CHUNK_SIZE));
+      vec3(chunk_origin + ivec3(block_index % CHUNK_SIZE,
+                                (block_index / CHUNK_SIZE) % CHUNK_SIZE,
+                                block_index / (CHUNK_SIZE * CHUNK_SIZE)));
 
-  if (block.voxel_len == 0) {
+  if (voxel_len(block) == 0) {
+    return;
+  }
+  if (pc.occlusion_culling != 0 && occluded(task.block_translation)) {
     return;
   }
   // Render a single block which may contains multiple voxels
-  EmitMeshTasksEXT(voxel_count_lod(block.voxel_len), 1, 1);
+  EmitMeshTasksEXT(voxel_count_lod(voxel_len(block)), 1, 1);
 }
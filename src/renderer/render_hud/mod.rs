@@ -0,0 +1,363 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{CopyBufferToImageInfo, RecordingCommandBuffer},
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+    shader::SpecializationConstant,
+};
+
+use crate::{
+    app::App,
+    hud::{HudQuad, HudTexture},
+    memory::{MemoryCategory, TrackedAllocation},
+    text::{GlyphAtlas, ATLAS_SIZE},
+};
+
+use super::{encode::needs_srgb_encoding, frames::FRAMES_IN_FLIGHT};
+
+/// Quads drawn per frame; the rest are skipped.
+pub const MAX_HUD_QUADS: usize = 16384;
+
+/// Vertices of a quad, as two triangles.
+const QUAD_VERTICES: u32 = 6;
+/// Texture index of plain colored quads.
+const UNTEXTURED: u32 = u32::MAX;
+/// Texture index of quads sampling the glyph atlas.
+const GLYPHS: u32 = u32::MAX - 1;
+
+mod vs {
+    vulkano_shaders::shader!(
+        ty: "vertex",
+        path: "src/renderer/render_hud/render_hud.vert.glsl",
+    );
+}
+
+mod fs {
+    vulkano_shaders::shader!(
+        ty: "fragment",
+        path: "src/renderer/render_hud/render_hud.frag.glsl",
+    );
+}
+
+/// Draws the quads of the `Hud` blended over the upscaled image, see
+/// `draw_overlay`, in display pixels so the HUD stays sharp whatever the
+/// render resolution.
+pub struct RenderHudPipeline {
+    pipeline: Arc<GraphicsPipeline>,
+    /// The crosshair, text and hotbar quads of each frame in flight.
+    quad_buffers: Vec<Subbuffer<[vs::Quad]>>,
+    quad_sets: Vec<Arc<DescriptorSet>>,
+    /// Glyph atlases are sampled by the frames in flight too, so each has a
+    /// copy, updated from its staging buffer when the atlas changed.
+    glyph_staging: Vec<Subbuffer<[u8]>>,
+    glyph_images: Vec<Arc<Image>>,
+    /// `GlyphAtlas::version` of each copy.
+    glyph_versions: Vec<Option<u64>>,
+    texture_sets: Vec<Arc<DescriptorSet>>,
+    _memory: Vec<TrackedAllocation>,
+    quad_count: u32,
+}
+
+impl RenderHudPipeline {
+    /// `rendering_info` has to describe the image the HUD is drawn on.
+    /// `block_textures` are the layers `TextureId`s index, see
+    /// `RenderFacesPipeline::block_textures`. Glyphs come from the atlas of
+    /// a `TextRenderer`, see `upload_glyphs`.
+    pub fn new(
+        app: &App,
+        rendering_info: PipelineRenderingCreateInfo,
+        block_textures: Arc<ImageView>,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let encode_srgb =
+                needs_srgb_encoding(rendering_info.color_attachment_formats[0].unwrap());
+            let fs = fs::load(device.clone())
+                .unwrap()
+                .specialize(
+                    [(0, SpecializationConstant::Bool(encode_srgb))]
+                        .into_iter()
+                        .collect(),
+                )
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    // Vertices come from the vertex index
+                    vertex_input_state: Some(VertexInputState::default()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        rendering_info.color_attachment_formats.len() as u32,
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend::alpha()),
+                            ..Default::default()
+                        },
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(rendering_info.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .unwrap()
+        };
+
+        let set_layouts = pipeline.layout().set_layouts();
+        let mut memory = Vec::new();
+        let quad_buffers = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let buffer = Buffer::new_slice::<vs::Quad>(
+                    app.context.memory_allocator().clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::STORAGE_BUFFER,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                            | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..Default::default()
+                    },
+                    MAX_HUD_QUADS as u64,
+                )
+                .unwrap();
+                memory.push(
+                    app.memory_tracker
+                        .track_buffer(MemoryCategory::HudBuffers, &buffer),
+                );
+                buffer
+            })
+            .collect::<Vec<_>>();
+        let quad_sets = quad_buffers
+            .iter()
+            .map(|buffer| {
+                DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    set_layouts[0].clone(),
+                    [WriteDescriptorSet::buffer(0, buffer.clone())],
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        // Blocks are shown much larger than their textures, so keep them crisp
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                mipmap_mode: SamplerMipmapMode::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // Glyphs are drawn at the size they were rasterized at
+        let glyph_sampler = Sampler::new(device.clone(), SamplerCreateInfo::default()).unwrap();
+        let glyph_staging = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let buffer = Buffer::new_slice::<u8>(
+                    app.context.memory_allocator().clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::TRANSFER_SRC,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                            | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..Default::default()
+                    },
+                    (ATLAS_SIZE * ATLAS_SIZE) as u64,
+                )
+                .unwrap();
+                memory.push(
+                    app.memory_tracker
+                        .track_buffer(MemoryCategory::HudBuffers, &buffer),
+                );
+                buffer
+            })
+            .collect::<Vec<_>>();
+        let glyph_images = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let image = Image::new(
+                    app.context.memory_allocator().clone(),
+                    ImageCreateInfo {
+                        image_type: ImageType::Dim2d,
+                        format: Format::R8_UNORM,
+                        extent: [ATLAS_SIZE, ATLAS_SIZE, 1],
+                        usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo::default(),
+                )
+                .unwrap();
+                memory.push(
+                    app.memory_tracker
+                        .track_image(MemoryCategory::HudBuffers, &image),
+                );
+                image
+            })
+            .collect::<Vec<_>>();
+        let texture_sets = glyph_images
+            .iter()
+            .map(|image| {
+                DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    set_layouts[1].clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(
+                            0,
+                            block_textures.clone(),
+                            sampler.clone(),
+                        ),
+                        WriteDescriptorSet::image_view_sampler(
+                            1,
+                            ImageView::new_default(image.clone()).unwrap(),
+                            glyph_sampler.clone(),
+                        ),
+                    ],
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            quad_buffers,
+            quad_sets,
+            glyph_staging,
+            glyph_images,
+            glyph_versions: vec![None; FRAMES_IN_FLIGHT],
+            texture_sets,
+            _memory: memory,
+            quad_count: 0,
+        }
+    }
+
+    /// Records the copy of `atlas` to the frame at `frame_index`, which has
+    /// to be done with the GPU, if its copy is outdated. Has to be recorded
+    /// outside of a render pass, before `render`.
+    pub fn upload_glyphs(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        frame_index: usize,
+        atlas: &GlyphAtlas,
+    ) {
+        assert_eq!(atlas.size(), ATLAS_SIZE);
+        if self.glyph_versions[frame_index] == Some(atlas.version()) {
+            return;
+        }
+        self.glyph_staging[frame_index]
+            .write()
+            .unwrap()
+            .copy_from_slice(atlas.pixels());
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+                self.glyph_staging[frame_index].clone(),
+                self.glyph_images[frame_index].clone(),
+            ))
+            .unwrap();
+        self.glyph_versions[frame_index] = Some(atlas.version());
+    }
+
+    /// Writes `quads` for the frame at `frame_index`, which has to be done
+    /// with the GPU.
+    pub fn update(&mut self, frame_index: usize, quads: &[HudQuad]) {
+        let mut buffer = self.quad_buffers[frame_index].write().unwrap();
+        for (quad, hud_quad) in buffer.iter_mut().zip(quads) {
+            *quad = vs::Quad {
+                position: hud_quad.position,
+                size: hud_quad.size,
+                color: hud_quad.color,
+                uv: hud_quad.uv,
+                texture_index: match hud_quad.texture {
+                    None => UNTEXTURED,
+                    Some(HudTexture::Block(texture)) => texture as u32,
+                    Some(HudTexture::Glyphs) => GLYPHS,
+                },
+                padding: [0; 3],
+            };
+        }
+        self.quad_count = quads.len().min(MAX_HUD_QUADS) as u32;
+    }
+
+    /// Records the draw of the quads written for `frame_index` on a screen
+    /// of `screen_size` pixels, inside the overlay pass.
+    pub fn render(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        frame_index: usize,
+        screen_size: [f32; 2],
+    ) {
+        if self.quad_count == 0 {
+            return;
+        }
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                self.pipeline.bind_point(),
+                self.pipeline.layout().clone(),
+                0,
+                vec![
+                    self.quad_sets[frame_index].clone(),
+                    self.texture_sets[frame_index].clone(),
+                ],
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                vs::PushConstants { screen_size },
+            )
+            .unwrap();
+        unsafe {
+            builder.draw(QUAD_VERTICES, self.quad_count, 0, 0).unwrap();
+        }
+    }
+}
@@ -0,0 +1,38 @@
+#version 460
+
+layout(location = 0) in VertexOut {
+  vec2 tex_coords;
+  flat uint texture_index;
+  vec4 color;
+}
+v_out;
+
+// Set for swapchain formats that don't encode sRGB on write
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+layout(set = 1, binding = 0) uniform sampler2DArray block_textures;
+// Coverage of the glyphs in the red channel
+layout(set = 1, binding = 1) uniform sampler2D glyph_atlas;
+
+layout(location = 0) out vec4 frag_color;
+
+const uint UNTEXTURED = 0xFFFFFFFF;
+const uint GLYPHS = 0xFFFFFFFE;
+
+vec3 encode_srgb(vec3 linear) {
+  return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055,
+             greaterThan(linear, vec3(0.0031308)));
+}
+
+void main() {
+  frag_color = v_out.color;
+  if (v_out.texture_index == GLYPHS) {
+    frag_color.a *= texture(glyph_atlas, v_out.tex_coords).r;
+  } else if (v_out.texture_index != UNTEXTURED) {
+    frag_color *= texture(block_textures,
+                          vec3(v_out.tex_coords, float(v_out.texture_index)));
+  }
+  if (ENCODE_SRGB) {
+    frag_color.rgb = encode_srgb(clamp(frag_color.rgb, 0.0, 1.0));
+  }
+}
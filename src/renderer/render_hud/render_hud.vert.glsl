@@ -0,0 +1,52 @@
+#version 460
+
+//////////////////////////////////////////////////
+// UNIFORMS
+
+struct Quad {
+  vec2 position;  // top left corner, in pixels
+  vec2 size;
+  vec4 color;           // multiplied with the texture, if any
+  vec4 uv;              // left, top, right and bottom edges in the texture
+  uint texture_index;   // 0xFFFFFFFF for a plain color, 0xFFFFFFFE for glyphs
+  // Keeps the std430 stride equal to the size of the struct
+  uint padding[3];
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer QuadBuffer {
+  Quad quads[];
+};
+
+layout(push_constant) uniform PushConstants {
+  vec2 screen_size;
+}
+pc;
+
+//////////////////////////////////////////////////
+// OUTPUTS
+
+layout(location = 0) out VertexOut {
+  vec2 tex_coords;
+  flat uint texture_index;
+  vec4 color;
+}
+v_out;
+
+//////////////////////////////////////////////////
+
+// Corners of the two triangles of the quad
+const vec2 corners[6] = {
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0),
+    vec2(1.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0),
+};
+
+void main() {
+  Quad quad = quads[gl_InstanceIndex];
+  vec2 pixel = quad.position + quad.size * corners[gl_VertexIndex];
+
+  // Pixels from the top left to Vulkan's clip space, where y points down
+  gl_Position = vec4(pixel / pc.screen_size * 2.0 - 1.0, 0.0, 1.0);
+  v_out.tex_coords = mix(quad.uv.xy, quad.uv.zw, corners[gl_VertexIndex]);
+  v_out.texture_index = quad.texture_index;
+  v_out.color = quad.color;
+}
@@ -0,0 +1,301 @@
+use std::sync::Arc;
+
+use cgmath::{MetricSpace, Point3, Vector3};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    image::{sampler::Sampler, view::ImageView, SampleCount},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::{
+        graphics::{
+            color_blend::{
+                AttachmentBlend, BlendFactor, BlendOp, ColorBlendAttachmentState, ColorBlendState,
+            },
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    app::App,
+    camera::DepthMode,
+    memory::{MemoryCategory, TrackedAllocation},
+    particles::{Particle, ParticleKind, MAX_PARTICLES},
+};
+
+use super::{depth_compare_op, frames::FRAMES_IN_FLIGHT, render_faces::Camera};
+
+/// Vertices of a quad, as two triangles.
+const QUAD_VERTICES: u32 = 6;
+/// Texture index of particles without a texture.
+const UNTEXTURED: u32 = u32::MAX;
+/// Seconds of motion a raindrop's streak covers.
+const RAIN_STREAK: f32 = 0.03;
+
+mod vs {
+    vulkano_shaders::shader!(
+        ty: "vertex",
+        path: "src/renderer/render_particles/render_particles.vert.glsl",
+    );
+}
+
+mod fs {
+    vulkano_shaders::shader!(
+        ty: "fragment",
+        path: "src/renderer/render_particles/render_particles.frag.glsl",
+    );
+}
+
+/// Draws particles as instanced quads facing the camera, blended over the
+/// opaque image in the translucent pass, see `draw_translucent`. They test
+/// against the depth of the opaque pass without writing it, and write their
+/// opacity into the reactive mask, since FSR can't reproject them with the
+/// motion vectors of the geometry behind.
+pub struct RenderParticlesPipeline {
+    pipeline: Arc<GraphicsPipeline>,
+    /// The sorted particles of each frame in flight. They move every frame,
+    /// so the next frame can't write over the ones still being blended.
+    instance_buffers: Vec<Subbuffer<[vs::Instance]>>,
+    instance_sets: Vec<Arc<DescriptorSet>>,
+    texture_set: Arc<DescriptorSet>,
+    _memory: Vec<TrackedAllocation>,
+    instance_count: u32,
+}
+
+impl RenderParticlesPipeline {
+    /// `rendering_info` has to describe the attachments of the translucent
+    /// pass: color, then the reactive mask. `block_textures` are the layers
+    /// `TextureId`s index, see `RenderFacesPipeline::block_textures`.
+    pub fn new(
+        app: &App,
+        rendering_info: PipelineRenderingCreateInfo,
+        samples: SampleCount,
+        block_textures: Arc<ImageView>,
+        block_sampler: Arc<Sampler>,
+        depth_mode: DepthMode,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let fs = fs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+
+            // Where particles overlap, the most opaque one decides
+            let reactive_blend = AttachmentBlend {
+                src_color_blend_factor: BlendFactor::One,
+                dst_color_blend_factor: BlendFactor::One,
+                color_blend_op: BlendOp::Max,
+                src_alpha_blend_factor: BlendFactor::One,
+                dst_alpha_blend_factor: BlendFactor::One,
+                alpha_blend_op: BlendOp::Max,
+            };
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    // Vertices come from the vertex index
+                    vertex_input_state: Some(VertexInputState::default()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState {
+                        rasterization_samples: samples,
+                        ..Default::default()
+                    }),
+                    color_blend_state: Some(ColorBlendState {
+                        attachments: vec![
+                            ColorBlendAttachmentState {
+                                blend: Some(AttachmentBlend::alpha()),
+                                ..Default::default()
+                            },
+                            ColorBlendAttachmentState {
+                                blend: Some(reactive_blend),
+                                ..Default::default()
+                            },
+                        ],
+                        ..Default::default()
+                    }),
+                    depth_stencil_state: Some(DepthStencilState {
+                        depth: Some(DepthState {
+                            compare_op: depth_compare_op(depth_mode),
+                            write_enable: false,
+                        }),
+                        ..Default::default()
+                    }),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(rendering_info.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .unwrap()
+        };
+
+        let set_layouts = pipeline.layout().set_layouts();
+        let mut memory = Vec::new();
+        let instance_buffers = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let buffer = Buffer::new_slice::<vs::Instance>(
+                    app.context.memory_allocator().clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::STORAGE_BUFFER,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                            | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..Default::default()
+                    },
+                    MAX_PARTICLES as u64,
+                )
+                .unwrap();
+                memory.push(
+                    app.memory_tracker
+                        .track_buffer(MemoryCategory::ParticleBuffers, &buffer),
+                );
+                buffer
+            })
+            .collect::<Vec<_>>();
+        let instance_sets = instance_buffers
+            .iter()
+            .map(|buffer| {
+                DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    set_layouts[0].clone(),
+                    [WriteDescriptorSet::buffer(0, buffer.clone())],
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let texture_set = DescriptorSet::new(
+            app.descriptor_set_allocator.clone(),
+            set_layouts[1].clone(),
+            [WriteDescriptorSet::image_view_sampler(
+                0,
+                block_textures,
+                block_sampler,
+            )],
+            None,
+        )
+        .unwrap();
+
+        Self {
+            pipeline,
+            instance_buffers,
+            instance_sets,
+            texture_set,
+            _memory: memory,
+            instance_count: 0,
+        }
+    }
+
+    /// Writes the instances of `particles` for the frame at `frame_index`,
+    /// which has to be done with the GPU, sorted back to front from
+    /// `camera_position` so they blend in order. Beyond `MAX_PARTICLES`,
+    /// the furthest are left out.
+    pub fn update(
+        &mut self,
+        frame_index: usize,
+        particles: &[Particle],
+        camera_position: Point3<f32>,
+    ) {
+        let mut sorted = particles.iter().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| {
+            let distance = |particle: &Particle| particle.position.distance2(camera_position);
+            distance(b).total_cmp(&distance(a))
+        });
+        let sorted = &sorted[sorted.len().saturating_sub(MAX_PARTICLES)..];
+
+        let mut instances = self.instance_buffers[frame_index].write().unwrap();
+        for (instance, particle) in instances.iter_mut().zip(sorted) {
+            let stretch = match particle.kind {
+                ParticleKind::Rain => particle.velocity * RAIN_STREAK,
+                _ => Vector3::new(0.0, 0.0, 0.0),
+            };
+            let [r, g, b, a] = particle.color;
+            *instance = vs::Instance {
+                position: particle.position.into(),
+                size: particle.size,
+                stretch: stretch.into(),
+                texture_index: particle
+                    .texture
+                    .map_or(UNTEXTURED, |texture| texture as u32),
+                uv: particle.uv,
+                color: [r, g, b, a * particle.opacity()],
+            };
+        }
+        self.instance_count = sorted.len() as u32;
+    }
+
+    /// Records the draw of the instances written for `frame_index`, inside
+    /// the translucent pass.
+    pub fn render(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        frame_index: usize,
+        camera: &Camera,
+    ) {
+        if self.instance_count == 0 {
+            return;
+        }
+        let view = camera.view;
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                self.pipeline.bind_point(),
+                self.pipeline.layout().clone(),
+                0,
+                vec![
+                    self.instance_sets[frame_index].clone(),
+                    self.texture_set.clone(),
+                ],
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                vs::PushConstants {
+                    view_proj: (camera.proj * camera.view).into(),
+                    // The rows of the view rotation are the camera axes
+                    camera_right: [view.x.x, view.y.x, view.z.x, 0.0],
+                    camera_up: [view.x.y, view.y.y, view.z.y, 0.0],
+                    jitter: camera.jitter.into(),
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder
+                .draw(QUAD_VERTICES, self.instance_count, 0, 0)
+                .unwrap();
+        }
+    }
+}
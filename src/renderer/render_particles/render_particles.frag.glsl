@@ -0,0 +1,33 @@
+#version 460
+
+layout(location = 0) in VertexOut {
+  vec2 tex_coords;
+  flat uint texture_index;
+  vec4 color;
+}
+v_out;
+
+layout(set = 1, binding = 0) uniform sampler2DArray block_textures;
+
+layout(location = 0) out vec4 frag_color;
+layout(location = 1) out float reactive;
+
+const uint UNTEXTURED = 0xFFFFFFFF;
+// FSR recommends keeping the reactive mask below 1
+const float MAX_REACTIVE = 0.9;
+
+void main() {
+  vec4 color = v_out.color;
+  if (v_out.texture_index != UNTEXTURED) {
+    color *= texture(block_textures,
+                     vec3(v_out.tex_coords, float(v_out.texture_index)));
+  }
+  if (color.a < 0.01) {
+    discard;
+  }
+
+  frag_color = color;
+  // Particles move on their own, so FSR leans on the current frame where
+  // they cover the image
+  reactive = color.a * MAX_REACTIVE;
+}
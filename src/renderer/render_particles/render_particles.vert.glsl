@@ -0,0 +1,61 @@
+#version 460
+
+//////////////////////////////////////////////////
+// UNIFORMS
+
+struct Instance {
+  vec3 position;
+  float size;
+  // Added to the top edge, so raindrops are drawn as streaks
+  vec3 stretch;
+  uint texture_index;  // 0xFFFFFFFF for untextured particles
+  vec4 uv;             // u_min, v_min, u_max, v_max
+  vec4 color;
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer InstanceBuffer {
+  Instance instances[];
+};
+
+layout(push_constant) uniform PushConstants {
+  mat4 view_proj;
+  vec4 camera_right;
+  vec4 camera_up;
+  vec2 jitter;
+}
+pc;
+
+//////////////////////////////////////////////////
+// OUTPUTS
+
+layout(location = 0) out VertexOut {
+  vec2 tex_coords;
+  flat uint texture_index;
+  vec4 color;
+}
+v_out;
+
+//////////////////////////////////////////////////
+
+// Corners of the two triangles of the quad
+const vec2 corners[6] = {
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0),
+    vec2(1.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0),
+};
+
+void main() {
+  Instance instance = instances[gl_InstanceIndex];
+  vec2 corner = corners[gl_VertexIndex];
+
+  vec2 offset = (corner - 0.5) * instance.size;
+  vec3 position = instance.position + pc.camera_right.xyz * offset.x +
+                  pc.camera_up.xyz * offset.y + instance.stretch * corner.y;
+
+  mat4 jitterTransform = mat4(1.0);
+  jitterTransform[3] = vec4(pc.jitter, 0.0, 1.0);
+
+  gl_Position = jitterTransform * pc.view_proj * vec4(position, 1.0);
+  v_out.tex_coords = mix(instance.uv.xw, instance.uv.zy, corner);
+  v_out.texture_index = instance.texture_index;
+  v_out.color = instance.color;
+}
@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use cgmath::{Matrix4, SquareMatrix};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    image::{sampler::Sampler, view::ImageView, SampleCount},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            depth_stencil::{CompareOp, DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    app::App,
+    biome::pack_tint_color,
+    memory::{MemoryCategory, TrackedAllocation},
+    types::BlockRegistry,
+    viewmodel::ViewModel,
+};
+
+use super::{
+    frames::FRAMES_IN_FLIGHT,
+    render_entities::{face_textures, tints},
+    render_faces::Camera,
+};
+
+/// Vertices of the six faces of a box, as two triangles each.
+const BOX_VERTICES: u32 = 36;
+const HAND_COLOR: [u8; 3] = [224, 172, 140];
+
+mod vs {
+    vulkano_shaders::shader!(
+        ty: "vertex",
+        path: "src/renderer/render_viewmodel/render_viewmodel.vert.glsl",
+    );
+}
+
+mod fs {
+    vulkano_shaders::shader!(
+        ty: "fragment",
+        path: "src/renderer/render_viewmodel/render_viewmodel.frag.glsl",
+    );
+}
+
+/// Draws the `ViewModel` in its own pass after the world, see
+/// `draw_viewmodel`, with the viewmodel projection and a depth buffer of its
+/// own, so the world's depth stays intact for Hi-Z culling and FSR.
+pub struct RenderViewModelPipeline {
+    pipeline: Arc<GraphicsPipeline>,
+    /// The held block and its swing of each frame in flight.
+    buffers: Vec<Subbuffer<vs::ViewModelBuffer>>,
+    sets: Vec<Arc<DescriptorSet>>,
+    texture_set: Arc<DescriptorSet>,
+    _memory: Vec<TrackedAllocation>,
+
+    face_textures: Vec<[u32; 6]>,
+    tints: Vec<u32>,
+    /// Model to clip space of the last frame, for motion vectors.
+    previous_transform: Option<Matrix4<f32>>,
+}
+
+impl RenderViewModelPipeline {
+    /// `block_textures` are the layers `TextureId`s index, see
+    /// `RenderFacesPipeline::block_textures`.
+    pub fn new(
+        app: &App,
+        rendering_info: PipelineRenderingCreateInfo,
+        samples: SampleCount,
+        block_registry: &BlockRegistry,
+        block_textures: Arc<ImageView>,
+        block_sampler: Arc<Sampler>,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let fs = fs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    // Vertices come from the vertex index
+                    vertex_input_state: Some(VertexInputState::default()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState {
+                        rasterization_samples: samples,
+                        ..Default::default()
+                    }),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        rendering_info.color_attachment_formats.len() as u32,
+                        ColorBlendAttachmentState::default(),
+                    )),
+                    depth_stencil_state: Some(DepthStencilState {
+                        depth: Some(DepthState {
+                            compare_op: CompareOp::Less,
+                            write_enable: true,
+                        }),
+                        ..Default::default()
+                    }),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(rendering_info.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .unwrap()
+        };
+
+        let set_layouts = pipeline.layout().set_layouts();
+        let mut memory = Vec::new();
+        let buffers = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let buffer = Buffer::new_sized::<vs::ViewModelBuffer>(
+                    app.context.memory_allocator().clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::STORAGE_BUFFER,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                            | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                memory.push(
+                    app.memory_tracker
+                        .track_buffer(MemoryCategory::EntityBuffers, &buffer),
+                );
+                buffer
+            })
+            .collect::<Vec<_>>();
+        let sets = buffers
+            .iter()
+            .map(|buffer| {
+                DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    set_layouts[0].clone(),
+                    [WriteDescriptorSet::buffer(0, buffer.clone())],
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let texture_set = DescriptorSet::new(
+            app.descriptor_set_allocator.clone(),
+            set_layouts[1].clone(),
+            [WriteDescriptorSet::image_view_sampler(
+                0,
+                block_textures,
+                block_sampler,
+            )],
+            None,
+        )
+        .unwrap();
+
+        Self {
+            pipeline,
+            buffers,
+            sets,
+            texture_set,
+            _memory: memory,
+            face_textures: face_textures(block_registry),
+            tints: tints(block_registry),
+            previous_transform: None,
+        }
+    }
+
+    /// Writes `viewmodel` as seen from `camera` for the frame at
+    /// `frame_index`, which has to be done with the GPU.
+    pub fn update(&mut self, frame_index: usize, viewmodel: &ViewModel, camera: &Camera) {
+        let aspect = camera.proj.y.y / camera.proj.x.x;
+        let model = viewmodel.transform();
+        let transform = ViewModel::projection(aspect) * model;
+        // It moves with the camera, so only the swing shows in motion vectors
+        let previous_transform = self.previous_transform.unwrap_or(transform);
+        self.previous_transform = Some(transform);
+
+        let (textures, tint) = match viewmodel.held {
+            0 => ([0; 6], pack_tint_color(Some(HAND_COLOR))),
+            id => (self.face_textures[id], self.tints[id]),
+        };
+        *self.buffers[frame_index].write().unwrap() = vs::ViewModelBuffer {
+            current_transform: transform.into(),
+            previous_transform: previous_transform.into(),
+            normal_transform: (camera.view.invert().unwrap() * model).into(),
+            textures,
+            tint,
+            textured: (viewmodel.held != 0) as u32,
+        };
+    }
+
+    /// Records the draw of the viewmodel written for `frame_index`, inside
+    /// its pass.
+    pub fn render(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        frame_index: usize,
+        camera: &Camera,
+    ) {
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                self.pipeline.bind_point(),
+                self.pipeline.layout().clone(),
+                0,
+                vec![self.sets[frame_index].clone(), self.texture_set.clone()],
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                vs::PushConstants {
+                    jitter: camera.jitter.into(),
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder.draw(BOX_VERTICES, 1, 0, 0).unwrap();
+        }
+    }
+}
@@ -0,0 +1,35 @@
+#version 460
+
+layout(location = 0) in VertexOut {
+  vec4 current_position;
+  vec4 previous_position;
+  vec3 normal;
+  vec2 tex_coords;
+  flat uint texture_index;
+  flat vec3 tint;
+}
+v_out;
+
+layout(set = 1, binding = 0) uniform sampler2DArray block_textures;
+
+layout(location = 0) out vec4 frag_color;
+layout(location = 1) out vec2 motion_vector;
+
+const vec3 LIGHT_DIRECTION = normalize(vec3(0.3, 1.0, 0.5));
+const uint UNTEXTURED = 0xFFFFFFFF;
+
+void main() {
+  motion_vector = v_out.previous_position.xy / v_out.previous_position.w -
+                  v_out.current_position.xy / v_out.current_position.w;
+
+  vec3 albedo = v_out.tint;
+  if (v_out.texture_index != UNTEXTURED) {
+    albedo *= texture(block_textures,
+                      vec3(v_out.tex_coords, float(v_out.texture_index)))
+                  .rgb;
+  }
+  // Lit like the blocks in the world, so the held block matches them
+  float shade = 0.6 + 0.4 * max(dot(v_out.normal, LIGHT_DIRECTION), 0.0);
+
+  frag_color = vec4(albedo * shade, 1.0);
+}
@@ -0,0 +1,84 @@
+#version 460
+
+//////////////////////////////////////////////////
+// UNIFORMS
+
+layout(std430, set = 0, binding = 0) readonly buffer ViewModelBuffer {
+  // Model space to clip space, this frame and the last
+  mat4 current_transform;
+  mat4 previous_transform;
+  // Model space to world space, for lighting
+  mat4 normal_transform;
+  // Texture of every face, in the order of cube_vertices
+  uint textures[6];
+  uint tint;
+  uint textured;  // 0 for the hand, which only has its tint
+}
+view_model;
+
+layout(push_constant) uniform PushConstants {
+  vec2 jitter;
+}
+pc;
+
+//////////////////////////////////////////////////
+// OUTPUTS
+
+layout(location = 0) out VertexOut {
+  vec4 current_position;
+  vec4 previous_position;
+  vec3 normal;
+  vec2 tex_coords;
+  flat uint texture_index;
+  flat vec3 tint;
+}
+v_out;
+
+//////////////////////////////////////////////////
+// The same faces as render_entities, so the held block is textured like
+// the placed one
+const vec3 cube_vertices[6][4] = {
+    {vec3(0, 0, 0), vec3(0, 1, 0), vec3(1, 1, 0), vec3(1, 0, 0)},
+    {vec3(0, 0, 1), vec3(1, 0, 1), vec3(1, 1, 1), vec3(0, 1, 1)},
+    {vec3(0, 0, 0), vec3(1, 0, 0), vec3(1, 0, 1), vec3(0, 0, 1)},
+    {vec3(0, 1, 0), vec3(0, 1, 1), vec3(1, 1, 1), vec3(1, 1, 0)},
+    {vec3(0, 0, 0), vec3(0, 0, 1), vec3(0, 1, 1), vec3(0, 1, 0)},
+    {vec3(1, 0, 0), vec3(1, 1, 0), vec3(1, 1, 1), vec3(1, 0, 1)},
+};
+
+const vec3 cube_normals[6] = {
+    vec3(0, 0, -1), vec3(0, 0, 1), vec3(0, -1, 0),
+    vec3(0, 1, 0),  vec3(-1, 0, 0), vec3(1, 0, 0),
+};
+
+// Corners of the two triangles of a face
+const uint face_indices[6] = {0, 1, 3, 1, 2, 3};
+
+const vec2 face_corners[4] = {
+    vec2(0.0, 0.0),
+    vec2(1.0, 0.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0),
+};
+
+const uint UNTEXTURED = 0xFFFFFFFF;
+
+void main() {
+  uint face = gl_VertexIndex / 6;
+  uint corner = face_indices[gl_VertexIndex % 6];
+  vec4 vertex = vec4(cube_vertices[face][corner], 1.0);
+
+  mat4 jitterTransform = mat4(1.0);
+  jitterTransform[3] = vec4(pc.jitter, 0.0, 1.0);
+
+  vec4 currentPosition = view_model.current_transform * vertex;
+  gl_Position = jitterTransform * currentPosition;
+  v_out.current_position = currentPosition;
+  v_out.previous_position = view_model.previous_transform * vertex;
+  v_out.normal =
+      normalize(mat3(view_model.normal_transform) * cube_normals[face]);
+  v_out.tex_coords = face_corners[corner];
+  v_out.texture_index =
+      view_model.textured != 0 ? view_model.textures[face] : UNTEXTURED;
+  v_out.tint = unpackUnorm4x8(view_model.tint).rgb;
+}
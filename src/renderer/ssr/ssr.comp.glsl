@@ -0,0 +1,126 @@
+#version 460
+
+// Reflects the frame in itself where the color's alpha says a surface is
+// reflective. Rays are marched through the depth buffer in view space from
+// the normals the depths around each pixel give, and take the color where
+// they pass behind what was drawn. Rays leaving the screen or going too far
+// reflect the sky.
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+// Alpha is 1 minus the reflectivity of what was drawn
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+layout(set = 0, binding = 2) uniform writeonly image2D destination;
+
+layout(push_constant) uniform PushConstants {
+  mat4 proj;  // without jitter
+  mat4 inverse_proj;
+  vec4 sky_color;
+  float far_depth;  // depth where nothing was drawn
+  uint max_steps;
+}
+pc;
+
+// Thickness given to what was drawn, in blocks, as the depth buffer only
+// has its front
+const float THICKNESS = 0.5;
+// Length of a ray, in blocks
+const float MAX_DISTANCE = 32.0;
+// Halvings of the last step to find where the ray hit
+const int REFINE_STEPS = 4;
+
+vec3 view_position(vec2 uv, float d) {
+  vec4 position = pc.inverse_proj * vec4(uv * 2.0 - 1.0, d, 1.0);
+  return position.xyz / position.w;
+}
+
+vec3 view_position_at(ivec2 pixel, ivec2 size) {
+  vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+  return view_position(uv, texelFetch(depth, pixel, 0).r);
+}
+
+// The uv `position` is drawn at, and its depth
+vec3 project(vec3 position) {
+  vec4 clip = pc.proj * vec4(position, 1.0);
+  return vec3(clip.xy / clip.w * 0.5 + 0.5, clip.z / clip.w);
+}
+
+// Of the two neighbors along an axis, the one nearer in depth, so normals
+// don't bend across edges
+vec3 nearer(vec3 center, vec3 a, vec3 b) {
+  return abs(a.z - center.z) < abs(b.z - center.z) ? center - a : b - center;
+}
+
+void main() {
+  ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  ivec2 size = imageSize(destination);
+  if (any(greaterThanEqual(pixel, size))) {
+    return;
+  }
+
+  vec4 current = texelFetch(color, pixel, 0);
+  float reflectivity = 1.0 - current.a;
+  float d = texelFetch(depth, pixel, 0).r;
+  if (reflectivity <= 0.0 || d == pc.far_depth) {
+    imageStore(destination, pixel, vec4(current.rgb, 1.0));
+    return;
+  }
+
+  vec3 position = view_position_at(pixel, size);
+  vec3 left = view_position_at(max(pixel - ivec2(1, 0), 0), size);
+  vec3 right = view_position_at(min(pixel + ivec2(1, 0), size - 1), size);
+  vec3 down = view_position_at(max(pixel - ivec2(0, 1), 0), size);
+  vec3 up = view_position_at(min(pixel + ivec2(0, 1), size - 1), size);
+  vec3 dx = nearer(position, left, right);
+  vec3 dy = nearer(position, down, up);
+  vec3 normal = normalize(cross(dx, dy));
+  vec3 view_direction = normalize(position);
+  // Toward the camera, whichever way up the image is
+  if (dot(normal, view_direction) > 0.0) {
+    normal = -normal;
+  }
+  vec3 direction = reflect(view_direction, normal);
+
+  vec3 reflection = pc.sky_color.rgb;
+  float step_length = MAX_DISTANCE / float(pc.max_steps);
+  vec3 ray = position + normal * 0.01;
+  for (uint i = 0; i < pc.max_steps; ++i) {
+    vec3 next = ray + direction * step_length;
+    vec3 projected = project(next);
+    if (any(lessThan(projected.xy, vec2(0.0))) ||
+        any(greaterThan(projected.xy, vec2(1.0))) || next.z >= 0.0) {
+      break;
+    }
+    float scene_depth = texture(depth, projected.xy).r;
+    if (scene_depth != pc.far_depth) {
+      float scene_z = view_position(projected.xy, scene_depth).z;
+      // View space looks down -z, so behind is below
+      if (next.z < scene_z && next.z > scene_z - THICKNESS) {
+        vec3 front = ray;
+        vec3 back = next;
+        for (int j = 0; j < REFINE_STEPS; ++j) {
+          vec3 middle = (front + back) * 0.5;
+          vec3 middle_projected = project(middle);
+          float middle_depth = texture(depth, middle_projected.xy).r;
+          float middle_z = view_position(middle_projected.xy, middle_depth).z;
+          if (middle.z < middle_z) {
+            back = middle;
+          } else {
+            front = middle;
+          }
+        }
+        vec2 hit_uv = project(back).xy;
+        // Fades out toward the screen edges, past which nothing is known
+        vec2 edge = min(hit_uv, 1.0 - hit_uv);
+        float fade = clamp(min(edge.x, edge.y) * 10.0, 0.0, 1.0);
+        reflection = mix(pc.sky_color.rgb, texture(color, hit_uv).rgb, fade);
+        break;
+      }
+    }
+    ray = next;
+  }
+
+  imageStore(destination, pixel,
+             vec4(mix(current.rgb, reflection, reflectivity), 1.0));
+}
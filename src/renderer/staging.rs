@@ -0,0 +1,245 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use log::debug;
+
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    sync::{future::FenceSignalFuture, GpuFuture},
+};
+
+/// Alignment of every allocation, enough for any GPU struct copied through the
+/// ring.
+const ALIGNMENT: u64 = 16;
+
+/// Something that tells when the GPU is done with a submission.
+pub trait UploadFence {
+    fn is_signaled(&self) -> bool;
+    fn wait(&self);
+}
+
+impl<F: GpuFuture> UploadFence for FenceSignalFuture<F> {
+    fn is_signaled(&self) -> bool {
+        FenceSignalFuture::is_signaled(self).unwrap()
+    }
+
+    fn wait(&self) {
+        FenceSignalFuture::wait(self, None).unwrap()
+    }
+}
+
+impl<T: UploadFence + ?Sized> UploadFence for Arc<T> {
+    fn is_signaled(&self) -> bool {
+        (**self).is_signaled()
+    }
+
+    fn wait(&self) {
+        (**self).wait()
+    }
+}
+
+/// Offset bookkeeping of `StagingRing`. `head` and `tail` only ever grow;
+/// positions in the buffer are them modulo `capacity`.
+#[derive(Debug)]
+struct RingAllocator {
+    capacity: u64,
+    head: u64,
+    tail: u64,
+    /// `head` at every submission still in flight, oldest first.
+    submitted: VecDeque<u64>,
+}
+
+impl RingAllocator {
+    fn new(capacity: u64) -> Self {
+        assert!(capacity > 0 && capacity % ALIGNMENT == 0);
+        Self {
+            capacity,
+            head: 0,
+            tail: 0,
+            submitted: VecDeque::new(),
+        }
+    }
+
+    /// Offset of `size` free bytes, or `None` if in-flight submissions have to
+    /// finish first or `size` is larger than the ring.
+    fn try_allocate(&mut self, size: u64) -> Option<u64> {
+        let size = size.next_multiple_of(ALIGNMENT);
+        if size > self.capacity {
+            return None;
+        }
+
+        // Allocations never wrap around the end of the buffer
+        let mut start = self.head;
+        let offset = start % self.capacity;
+        if offset + size > self.capacity {
+            start += self.capacity - offset;
+        }
+        if start + size - self.tail > self.capacity {
+            return None;
+        }
+
+        self.head = start + size;
+        Some(start % self.capacity)
+    }
+
+    /// Marks everything allocated so far as used by a new submission.
+    fn submit(&mut self) {
+        self.submitted.push_back(self.head);
+    }
+
+    /// Frees the allocations of the oldest submission.
+    fn release_oldest(&mut self) {
+        self.tail = self.submitted.pop_front().unwrap();
+    }
+}
+
+/// A persistent host-visible buffer all uploads are staged through. Regions
+/// are handed out in a ring and reused once the fence of the submission that
+/// copied out of them is signaled, so uploads don't allocate. Uploads that
+/// don't fit, when a submission stages more than the ring holds, get buffers
+/// of their own instead.
+pub struct StagingRing {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    buffer: Subbuffer<[u8]>,
+    allocator: RingAllocator,
+    /// Fences of the submissions in flight, with the buffers of their
+    /// uploads that didn't fit into the ring.
+    fences: VecDeque<(Box<dyn UploadFence>, Vec<Subbuffer<[u8]>>)>,
+    /// Buffers of their own of the uploads since the last submission.
+    overflow: Vec<Subbuffer<[u8]>>,
+    /// Bytes allocated since the last submission.
+    staged: u64,
+}
+
+impl StagingRing {
+    pub fn new(memory_allocator: Arc<StandardMemoryAllocator>, size: u64) -> Self {
+        let buffer = staging_buffer(memory_allocator.clone(), size);
+        Self {
+            memory_allocator,
+            buffer,
+            allocator: RingAllocator::new(size),
+            fences: VecDeque::new(),
+            overflow: Vec::new(),
+            staged: 0,
+        }
+    }
+
+    pub fn buffer(&self) -> &Subbuffer<[u8]> {
+        &self.buffer
+    }
+
+    /// Space for `len` values, valid until the next `submit` has finished on
+    /// the GPU. Blocks on in-flight submissions if the ring is full, and
+    /// allocates a buffer for the values if that doesn't make room.
+    pub fn allocate<T: BufferContents>(&mut self, len: u64) -> Subbuffer<[T]> {
+        let size = len * std::mem::size_of::<T>() as u64;
+        self.staged += size;
+        let offset = loop {
+            self.reclaim();
+            if let Some(offset) = self.allocator.try_allocate(size) {
+                break offset;
+            }
+            // Nothing in flight frees enough space if the ring is all this
+            // submission's or smaller than the upload
+            match self.fences.front() {
+                Some((oldest, _)) if size <= self.allocator.capacity => oldest.wait(),
+                _ => {
+                    debug!("Staging {} bytes outside the full staging ring", size);
+                    let buffer = staging_buffer(self.memory_allocator.clone(), size);
+                    self.overflow.push(buffer.clone());
+                    return buffer.reinterpret::<[T]>();
+                }
+            }
+        };
+
+        self.buffer
+            .clone()
+            .slice(offset..offset + size)
+            .reinterpret::<[T]>()
+    }
+
+    pub fn upload<T: BufferContents + Copy>(&mut self, data: &[T]) -> Subbuffer<[T]> {
+        let staged = self.allocate::<T>(data.len() as u64);
+        staged.write().unwrap().copy_from_slice(data);
+        staged
+    }
+
+    /// Ties every region allocated since the last submission to `fence`, which
+    /// must signal after the copies out of them have executed. Returns how
+    /// many bytes they hold.
+    pub fn submit(&mut self, fence: impl UploadFence + 'static) -> u64 {
+        self.allocator.submit();
+        self.fences
+            .push_back((Box::new(fence), std::mem::take(&mut self.overflow)));
+        std::mem::take(&mut self.staged)
+    }
+
+    fn reclaim(&mut self) {
+        while self
+            .fences
+            .front()
+            .is_some_and(|(fence, _)| fence.is_signaled())
+        {
+            self.fences.pop_front();
+            self.allocator.release_oldest();
+        }
+    }
+}
+
+fn staging_buffer(memory_allocator: Arc<StandardMemoryAllocator>, size: u64) -> Subbuffer<[u8]> {
+    Buffer::new_slice::<u8>(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        size,
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_reuses_released_space() {
+        let mut ring = RingAllocator::new(64);
+        assert_eq!(ring.try_allocate(20), Some(0));
+        assert_eq!(ring.try_allocate(16), Some(32));
+        ring.submit();
+        assert_eq!(ring.try_allocate(16), Some(48));
+        // Full until the first submission is released
+        assert_eq!(ring.try_allocate(16), None);
+
+        ring.release_oldest();
+        assert_eq!(ring.try_allocate(32), Some(0));
+    }
+
+    #[test]
+    fn test_ring_allocations_do_not_wrap() {
+        let mut ring = RingAllocator::new(64);
+        assert_eq!(ring.try_allocate(48), Some(0));
+        ring.submit();
+        ring.release_oldest();
+
+        // 32 bytes don't fit in the last 16, so the allocation starts over at 0
+        // and the skipped bytes count as used until the ring comes around
+        assert_eq!(ring.try_allocate(32), Some(0));
+        assert_eq!(ring.try_allocate(16), Some(32));
+        assert_eq!(ring.try_allocate(16), None);
+    }
+
+    #[test]
+    fn test_ring_rejects_uploads_larger_than_itself() {
+        let mut ring = RingAllocator::new(64);
+        assert_eq!(ring.try_allocate(80), None);
+        // Nothing was taken
+        assert_eq!(ring.try_allocate(64), Some(0));
+    }
+}
@@ -0,0 +1,221 @@
+use std::{sync::Arc, time::Duration};
+
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    device::Device,
+    query::{
+        QueryControlFlags, QueryPipelineStatisticFlags, QueryPool, QueryPoolCreateInfo,
+        QueryResultFlags, QueryType,
+    },
+    sync::PipelineStage,
+};
+
+/// Passes of a frame `PassTimer` times, later ones go untimed.
+const MAX_TIMED_PASSES: usize = 32;
+
+/// What drawing the world cost in a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawStatistics {
+    /// Primitives that reached the rasterizer, after the mesh shaders culled
+    /// faces seen from behind.
+    pub primitives: u64,
+    /// How often every pixel was shaded on average, which drawing front to
+    /// back and the depth prepass bring closer to 1.
+    pub overdraw: f32,
+}
+
+/// Pipeline statistics of drawing the world, one query per frame in flight.
+pub struct DrawStatisticsQuery {
+    query_pool: Arc<QueryPool>,
+    /// Whether the query of each frame was ever begun, as queries can't be
+    /// read before.
+    measured: Vec<bool>,
+}
+
+impl DrawStatisticsQuery {
+    pub fn new(device: Arc<Device>, frames_in_flight: usize) -> Self {
+        let query_pool = QueryPool::new(
+            device,
+            QueryPoolCreateInfo {
+                query_count: frames_in_flight as u32,
+                pipeline_statistics: QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS
+                    | QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS,
+                ..QueryPoolCreateInfo::query_type(QueryType::PipelineStatistics)
+            },
+        )
+        .unwrap();
+        Self {
+            query_pool,
+            measured: vec![false; frames_in_flight],
+        }
+    }
+
+    /// Records resetting the query of `frame`, outside of any rendering.
+    pub fn reset(&self, builder: &mut RecordingCommandBuffer, frame: usize) {
+        let frame = frame as u32;
+        unsafe {
+            builder
+                .reset_query_pool(self.query_pool.clone(), frame..frame + 1)
+                .unwrap();
+        }
+    }
+
+    /// Counts what `record_fn` records into the query of `frame`.
+    pub fn measure(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        frame: usize,
+        record_fn: impl FnOnce(&mut RecordingCommandBuffer),
+    ) {
+        let frame = frame as u32;
+        unsafe {
+            builder
+                .begin_query(self.query_pool.clone(), frame, QueryControlFlags::empty())
+                .unwrap();
+        }
+        record_fn(builder);
+        unsafe {
+            builder.end_query(self.query_pool.clone(), frame).unwrap();
+        }
+        self.measured[frame as usize] = true;
+    }
+
+    /// The statistics of the last time `frame` was drawn, with `pixels`
+    /// drawn to, or `None` if it wasn't yet. Has to be called before the
+    /// query of `frame` is reset again.
+    pub fn statistics(&self, frame: usize, pixels: f32) -> Option<DrawStatistics> {
+        if !self.measured[frame] {
+            return None;
+        }
+        let frame = frame as u32;
+        // In the order of the flags' bits
+        let mut results = [0u64; 2];
+        let available = self
+            .query_pool
+            .get_results(frame..frame + 1, &mut results, QueryResultFlags::empty())
+            .unwrap();
+        let [primitives, fragments] = results;
+        available.then(|| DrawStatistics {
+            primitives,
+            overdraw: fragments as f32 / pixels,
+        })
+    }
+}
+
+/// GPU time of the frame graph passes, from timestamps written before and
+/// after each, with the queries of every frame in flight apart.
+pub struct PassTimer {
+    query_pool: Arc<QueryPool>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    /// Names of the passes timed in each frame in flight, in order.
+    passes: Vec<Vec<&'static str>>,
+    /// Query of the timestamp after the pass being recorded, if it is timed.
+    end_query: Option<u32>,
+}
+
+impl PassTimer {
+    /// A timer for `frames_in_flight` frames, or `None` if the device can't
+    /// write timestamps on every graphics and compute queue.
+    pub fn new(device: Arc<Device>, frames_in_flight: usize) -> Option<Self> {
+        let properties = device.physical_device().properties();
+        if !properties.timestamp_compute_and_graphics {
+            return None;
+        }
+        let timestamp_period = properties.timestamp_period;
+        let query_pool = QueryPool::new(
+            device,
+            QueryPoolCreateInfo {
+                query_count: (frames_in_flight * MAX_TIMED_PASSES * 2) as u32,
+                ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+            },
+        )
+        .unwrap();
+        Some(Self {
+            query_pool,
+            timestamp_period,
+            passes: vec![Vec::new(); frames_in_flight],
+            end_query: None,
+        })
+    }
+
+    fn first_query(frame: usize) -> u32 {
+        (frame * MAX_TIMED_PASSES * 2) as u32
+    }
+
+    /// Records the timestamp before the pass `name` of `frame`, outside of
+    /// any rendering. The first pass of a frame resets its queries, so
+    /// `timings` has to be called before a frame is drawn again.
+    pub fn begin(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        frame: usize,
+        name: &'static str,
+    ) {
+        let passes = &mut self.passes[frame];
+        let first = Self::first_query(frame);
+        if passes.is_empty() {
+            let queries = first..first + (MAX_TIMED_PASSES * 2) as u32;
+            unsafe {
+                builder
+                    .reset_query_pool(self.query_pool.clone(), queries)
+                    .unwrap();
+            }
+        }
+        if passes.len() == MAX_TIMED_PASSES {
+            self.end_query = None;
+            return;
+        }
+        let query = first + passes.len() as u32 * 2;
+        passes.push(name);
+        self.end_query = Some(query + 1);
+        unsafe {
+            builder
+                .write_timestamp(self.query_pool.clone(), query, PipelineStage::TopOfPipe)
+                .unwrap();
+        }
+    }
+
+    /// Records the timestamp after the pass `begin` was last called for.
+    pub fn end(&mut self, builder: &mut RecordingCommandBuffer) {
+        let Some(query) = self.end_query.take() else {
+            return;
+        };
+        unsafe {
+            builder
+                .write_timestamp(self.query_pool.clone(), query, PipelineStage::BottomOfPipe)
+                .unwrap();
+        }
+    }
+
+    /// The time each pass took the last time `frame` was drawn, in the order
+    /// they were recorded, or `None` if they aren't all available. Has to be
+    /// called before the first pass of `frame` is timed again.
+    pub fn timings(&mut self, frame: usize) -> Option<Vec<(&'static str, Duration)>> {
+        let passes = std::mem::take(&mut self.passes[frame]);
+        if passes.is_empty() {
+            return None;
+        }
+        let first = Self::first_query(frame);
+        let mut results = vec![0u64; passes.len() * 2];
+        let available = self
+            .query_pool
+            .get_results(
+                first..first + results.len() as u32,
+                &mut results,
+                QueryResultFlags::empty(),
+            )
+            .unwrap();
+        let period = self.timestamp_period as f64;
+        available.then(|| {
+            passes
+                .into_iter()
+                .zip(results.chunks_exact(2))
+                .map(|(name, times)| {
+                    let ticks = times[1].saturating_sub(times[0]);
+                    (name, Duration::from_nanos((ticks as f64 * period) as u64))
+                })
+                .collect()
+        })
+    }
+}
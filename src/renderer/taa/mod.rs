@@ -0,0 +1,269 @@
+use std::sync::Arc;
+
+use cgmath::Vector2;
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::AllocationCreateInfo,
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    app::App,
+    memory::{MemoryCategory, TrackedAllocation},
+};
+
+use super::MOTION_VECTOR_UV_SCALE;
+
+mod cs {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/taa/taa.comp.glsl",
+    );
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+/// Weight of the current frame in the blend with the history.
+const BLEND: f32 = 0.1;
+/// Length of the jitter sequence.
+const JITTER_PHASES: u32 = 8;
+
+/// The `index`th element of the Halton sequence of `base`, in `0..1`.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Subpixel offset of the `phase`th frame, in pixels from -0.5 to 0.5.
+/// Starts at 1, as the Halton sequence starts at the corner.
+pub fn jitter_offset(phase: u32) -> [f32; 2] {
+    let index = phase % JITTER_PHASES + 1;
+    [halton(index, 2) - 0.5, halton(index, 3) - 0.5]
+}
+
+/// Temporal anti-aliasing without upscaling, for when FSR is not used: the
+/// frames are jittered and every frame is blended into the reprojected
+/// history of the previous ones.
+pub struct TaaPass {
+    pipeline: Arc<ComputePipeline>,
+    /// Ping-ponged: each frame reads the history the previous frame wrote
+    /// to the other one.
+    history: [Arc<ImageView>; 2],
+    /// Per source frame, the sets writing `history[0]` and `history[1]`.
+    sets: Vec<[Arc<DescriptorSet>; 2]>,
+    extent: [u32; 2],
+    /// The history written last, if any.
+    current: Option<usize>,
+    phase: u32,
+    _memory: Vec<TrackedAllocation>,
+}
+
+impl TaaPass {
+    /// A pass resolving any of `color_images` with the motion vectors of the
+    /// same index, all single-sampled and of the same extent, into an image
+    /// of `format`.
+    pub fn new(
+        app: &App,
+        color_images: &[Arc<ImageView>],
+        motion_vector_images: &[Arc<ImageView>],
+        format: Format,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let cs = cs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .unwrap()
+        };
+
+        let [width, height, _] = color_images[0].image().extent();
+        let mut memory = Vec::new();
+        let history = [(); 2].map(|_| {
+            let image = Image::new(
+                app.memory_allocator(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    extent: [width, height, 1],
+                    format,
+                    usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .unwrap();
+            memory.push(
+                app.memory_tracker
+                    .track_image(MemoryCategory::RenderTargets, &image),
+            );
+            ImageView::new_default(image).unwrap()
+        });
+
+        // The history is reprojected between pixels
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let set_layout = pipeline.layout().set_layouts()[0].clone();
+        let sets = color_images
+            .iter()
+            .zip(motion_vector_images)
+            .map(|(color, motion_vector)| {
+                assert_eq!(color.image().extent(), [width, height, 1]);
+                [0, 1].map(|destination| {
+                    DescriptorSet::new(
+                        app.descriptor_set_allocator.clone(),
+                        set_layout.clone(),
+                        [
+                            WriteDescriptorSet::image_view_sampler(
+                                0,
+                                color.clone(),
+                                sampler.clone(),
+                            ),
+                            WriteDescriptorSet::image_view_sampler(
+                                1,
+                                motion_vector.clone(),
+                                sampler.clone(),
+                            ),
+                            WriteDescriptorSet::image_view_sampler(
+                                2,
+                                history[1 - destination].clone(),
+                                sampler.clone(),
+                            ),
+                            WriteDescriptorSet::image_view(3, history[destination].clone()),
+                        ],
+                        None,
+                    )
+                    .unwrap()
+                })
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            history,
+            sets,
+            extent: [width, height],
+            current: None,
+            phase: 0,
+            _memory: memory,
+        }
+    }
+
+    /// The images `resolve` writes to.
+    pub fn history(&self) -> &[Arc<ImageView>] {
+        &self.history
+    }
+
+    /// The image of `history` the next `resolve` writes to.
+    pub fn next_output(&self) -> &Arc<ImageView> {
+        &self.history[self.current.map_or(0, |current| 1 - current)]
+    }
+
+    /// The jitter of the next frame in NDC, like `FsrContextVulkan::step_jitter`.
+    pub fn step_jitter(&mut self) -> Vector2<f32> {
+        let [x, y] = jitter_offset(self.phase);
+        self.phase = (self.phase + 1) % JITTER_PHASES;
+        [
+            2.0 * x / self.extent[0] as f32,
+            -2.0 * y / self.extent[1] as f32,
+        ]
+        .into()
+    }
+
+    /// Records blending `color_images[source]` into the history, returning
+    /// the image the result is written to.
+    pub fn resolve(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        source: usize,
+    ) -> Arc<ImageView> {
+        let destination = self.current.map_or(0, |current| 1 - current);
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                self.sets[source][destination].clone(),
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    blend: BLEND,
+                    motion_vector_scale: MOTION_VECTOR_UV_SCALE,
+                    history_valid: self.current.is_some() as u32,
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder
+                .dispatch([
+                    self.extent[0].div_ceil(WORKGROUP_SIZE),
+                    self.extent[1].div_ceil(WORKGROUP_SIZE),
+                    1,
+                ])
+                .unwrap()
+        };
+        self.current = Some(destination);
+        self.history[destination].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_offset() {
+        assert_eq!(halton(1, 2), 0.5);
+        assert_eq!(halton(3, 3), 1.0 / 9.0);
+        let offsets = (0..JITTER_PHASES).map(jitter_offset).collect::<Vec<_>>();
+        assert_eq!(jitter_offset(JITTER_PHASES), offsets[0]);
+        for (i, offset) in offsets.iter().enumerate() {
+            assert!(offset.iter().all(|v| (-0.5..0.5).contains(v)));
+            assert!(!offsets[..i].contains(offset));
+        }
+        // Spread around the pixel center
+        let mean = offsets.iter().map(|offset| offset[0]).sum::<f32>() / JITTER_PHASES as f32;
+        assert!(mean.abs() < 0.1);
+    }
+}
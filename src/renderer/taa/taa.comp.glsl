@@ -0,0 +1,54 @@
+#version 460
+
+// Blends the current frame into the history of the previous ones. The
+// history is reprojected along the motion vectors and clamped to the colors
+// around the pixel, so it can't ghost where something else came into view.
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D motion_vectors;
+layout(set = 0, binding = 2) uniform sampler2D history;
+layout(set = 0, binding = 3) uniform writeonly image2D destination;
+
+layout(push_constant) uniform PushConstants {
+  float blend;                // weight of the current frame
+  float motion_vector_scale;  // from motion vectors to UV units
+  uint history_valid;         // 0 when there is no history yet
+}
+pc;
+
+void main() {
+  ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  ivec2 size = imageSize(destination);
+  if (any(greaterThanEqual(pixel, size))) {
+    return;
+  }
+
+  vec3 current = texelFetch(color, pixel, 0).rgb;
+  vec3 neighborhood_min = current;
+  vec3 neighborhood_max = current;
+  for (int y = -1; y <= 1; ++y) {
+    for (int x = -1; x <= 1; ++x) {
+      ivec2 neighbor = clamp(pixel + ivec2(x, y), ivec2(0), size - 1);
+      vec3 neighbor_color = texelFetch(color, neighbor, 0).rgb;
+      neighborhood_min = min(neighborhood_min, neighbor_color);
+      neighborhood_max = max(neighborhood_max, neighbor_color);
+    }
+  }
+
+  // Motion vectors point to the previous frame
+  vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+  vec2 previous_uv =
+      uv + texelFetch(motion_vectors, pixel, 0).xy * pc.motion_vector_scale;
+  bool on_screen = all(greaterThanEqual(previous_uv, vec2(0.0))) &&
+                   all(lessThanEqual(previous_uv, vec2(1.0)));
+
+  vec3 result = current;
+  if (pc.history_valid != 0 && on_screen) {
+    vec3 previous = texture(history, previous_uv).rgb;
+    previous = clamp(previous, neighborhood_min, neighborhood_max);
+    result = mix(previous, current, pc.blend);
+  }
+  imageStore(destination, pixel, vec4(result, 1.0));
+}
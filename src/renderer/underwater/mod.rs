@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use cgmath::SquareMatrix;
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+    },
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{app::App, camera::DepthMode};
+
+use super::render_faces::Camera;
+
+mod cs {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/underwater/underwater.comp.glsl",
+    );
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Tints, fogs and wobbles the rendered frame while the camera is in water.
+/// Runs last before anti-aliasing, and only on frames the camera is
+/// underwater.
+pub struct UnderwaterPass {
+    pipeline: Arc<ComputePipeline>,
+    outputs: Vec<Arc<ImageView>>,
+    /// Per frame, the set writing its color into its output.
+    sets: Vec<Arc<DescriptorSet>>,
+    depth_mode: DepthMode,
+    /// Seconds spent underwater, which move the wobble.
+    time: f32,
+}
+
+impl UnderwaterPass {
+    /// A pass reading each of `color_images` with the depth of the same
+    /// index into the output of the same index, all single-sampled and of
+    /// the same extent. The outputs must be storage images.
+    pub fn new(
+        app: &App,
+        color_images: &[Arc<ImageView>],
+        depth_images: &[Arc<ImageView>],
+        outputs: Vec<Arc<ImageView>>,
+        depth_mode: DepthMode,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let cs = cs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .unwrap()
+        };
+
+        // The wobble samples between pixels
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // Depths are not interpolated between pixels
+        let depth_sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let set_layout = pipeline.layout().set_layouts()[0].clone();
+        let sets = color_images
+            .iter()
+            .zip(depth_images)
+            .zip(&outputs)
+            .map(|((color, depth), output)| {
+                assert_eq!(color.image().extent(), output.image().extent());
+                DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    set_layout.clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(0, color.clone(), sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(
+                            1,
+                            depth.clone(),
+                            depth_sampler.clone(),
+                        ),
+                        WriteDescriptorSet::image_view(2, output.clone()),
+                    ],
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            outputs,
+            sets,
+            depth_mode,
+            time: 0.0,
+        }
+    }
+
+    /// The images `apply` writes to, one per frame.
+    pub fn outputs(&self) -> &[Arc<ImageView>] {
+        &self.outputs
+    }
+
+    /// Records the view from underwater of the frame `frame` was rendered
+    /// into as seen by `camera`, `delta` seconds after the last one,
+    /// returning the image the result is written to.
+    pub fn apply(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        frame: usize,
+        camera: &Camera,
+        delta: f32,
+    ) -> Arc<ImageView> {
+        self.time += delta;
+        let output = &self.outputs[frame];
+        let [width, height, _] = output.image().extent();
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                self.sets[frame].clone(),
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    inverse_proj: camera.proj.invert().unwrap().into(),
+                    far_depth: self.depth_mode.far_depth(),
+                    time: self.time,
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder
+                .dispatch([
+                    width.div_ceil(WORKGROUP_SIZE),
+                    height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                ])
+                .unwrap()
+        };
+        output.clone()
+    }
+}
@@ -0,0 +1,53 @@
+#version 460
+
+// What the camera sees from inside water: the image wobbles as if through
+// moving water, is tinted blue, and fades into the color of the water
+// within a few blocks.
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+layout(set = 0, binding = 2) uniform writeonly image2D destination;
+
+layout(push_constant) uniform PushConstants {
+  mat4 inverse_proj;  // without jitter
+  float far_depth;    // depth where nothing was drawn
+  float time;         // seconds, moves the wobble
+}
+pc;
+
+// Multiplied with everything seen through the water
+const vec3 TINT = vec3(0.45, 0.65, 1.0);
+// What the view fades into far away
+const vec3 WATER_COLOR = vec3(0.02, 0.08, 0.2);
+// Distance over which all but a third of the light is lost, in blocks
+const float FOG_DISTANCE = 8.0;
+// Of the wobble, in UV units, waves across the screen, and per second
+const float WOBBLE_AMPLITUDE = 0.004;
+const float WOBBLE_FREQUENCY = 12.0;
+const float WOBBLE_SPEED = 2.0;
+
+void main() {
+  ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  ivec2 size = imageSize(destination);
+  if (any(greaterThanEqual(pixel, size))) {
+    return;
+  }
+
+  vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+  float phase = pc.time * WOBBLE_SPEED;
+  uv += WOBBLE_AMPLITUDE * vec2(sin(uv.y * WOBBLE_FREQUENCY + phase),
+                                cos(uv.x * WOBBLE_FREQUENCY + phase * 1.3));
+  uv = clamp(uv, vec2(0.0), vec2(1.0));
+
+  vec3 current = texture(color, uv).rgb * TINT;
+  float d = texture(depth, uv).r;
+  float fog = 1.0;
+  if (d != pc.far_depth) {
+    vec4 position = pc.inverse_proj * vec4(uv * 2.0 - 1.0, d, 1.0);
+    fog = 1.0 - exp(-length(position.xyz / position.w) / FOG_DISTANCE);
+  }
+
+  imageStore(destination, pixel, vec4(mix(current, WATER_COLOR, fog), 1.0));
+}
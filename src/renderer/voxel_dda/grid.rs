@@ -0,0 +1,80 @@
+use crate::types::{ChunkPosition, WorldHeight};
+
+/// The grid of sections the rays are marched through, as slots into the
+/// chunk buffer. It wraps around instead of following the camera: a section
+/// goes to the cell of its position modulo the extent, which is just large
+/// enough for the sections within render distance of any center to never
+/// share a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkGrid {
+    /// Cells along x, y and z.
+    pub extent: [u32; 3],
+}
+
+impl ChunkGrid {
+    pub fn new(render_distance: u32, height: WorldHeight) -> Self {
+        let width = 2 * render_distance + 1;
+        Self {
+            extent: [width, height.sections().len() as u32, width],
+        }
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.extent.iter().product::<u32>() as usize
+    }
+
+    /// The cell of the section at `chunk_position`.
+    pub fn cell(&self, chunk_position: ChunkPosition) -> [u32; 3] {
+        let [x, y, z] = [chunk_position.x, chunk_position.y, chunk_position.z];
+        let [width, height, depth] = self.extent.map(|extent| extent as i32);
+        [
+            x.rem_euclid(width),
+            y.rem_euclid(height),
+            z.rem_euclid(depth),
+        ]
+        .map(|v| v as u32)
+    }
+
+    /// Index of the cell of the section at `chunk_position`, x changing
+    /// fastest.
+    pub fn index(&self, chunk_position: ChunkPosition) -> usize {
+        let [x, y, z] = self.cell(chunk_position).map(|v| v as usize);
+        let [width, height, _] = self.extent.map(|extent| extent as usize);
+        x + width * (y + height * z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::types::ColumnPosition;
+
+    #[test]
+    fn test_chunk_grid() {
+        let height = WorldHeight::default();
+        let grid = ChunkGrid::new(4, height);
+        assert_eq!(grid.extent, [9, height.sections().len() as u32, 9]);
+
+        for center in [[0, 0], [-3, 7], [100, -100]] {
+            let mut indices = HashSet::new();
+            for dx in -4..=4 {
+                for dz in -4..=4 {
+                    let column = ColumnPosition {
+                        x: center[0] + dx,
+                        z: center[1] + dz,
+                    };
+                    for chunk_position in column.sections(height) {
+                        let cell = grid.cell(chunk_position);
+                        assert!((0..3).all(|axis| cell[axis] < grid.extent[axis]));
+                        let index = grid.index(chunk_position);
+                        assert!(index < grid.cell_count());
+                        assert!(indices.insert(index), "{:?}", chunk_position);
+                    }
+                }
+            }
+            assert_eq!(indices.len(), grid.cell_count());
+        }
+    }
+}
@@ -0,0 +1,970 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem::{offset_of, size_of},
+    sync::{mpsc::Receiver, Arc},
+};
+
+use cgmath::SquareMatrix;
+use tracing::instrument;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        BufferCopy, BufferImageCopy, CopyBufferInfo, CopyBufferToImageInfo, RecordingCommandBuffer,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    format::Format,
+    image::{
+        sampler::{Sampler, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage, SampleCount,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo,
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            depth_stencil::{CompareOp, DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint,
+        PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    app::App,
+    biome::{pack_tint_color, BiomeColors},
+    camera::DepthMode,
+    events::WorldEvent,
+    memory::{MemoryCategory, MemoryTracker, TrackedAllocation},
+    settings::VoxelStorage,
+    svo::{Node, SparseVoxelOctree},
+    types::{Chunk, ChunkPosition, ColumnPosition, World, CHUNK_SIZE},
+};
+
+use super::{
+    frames::FRAMES_IN_FLIGHT,
+    render_faces::{BakedBlockModels, BlockVoxels, Camera, GpuVoxel},
+    staging::{StagingRing, UploadFence},
+    SKY_COLOR,
+};
+
+pub use self::grid::ChunkGrid;
+use self::ranges::RangeAllocator;
+
+mod grid;
+mod ranges;
+
+mod cs {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/voxel_dda/voxel_dda.comp.glsl",
+    );
+}
+
+/// The compute shader marching through the octrees of
+/// `VoxelStorage::Octree`.
+mod cs_octree {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/voxel_dda/voxel_dda.comp.glsl",
+        define: [("SPARSE_VOXEL_OCTREE", "")],
+    );
+}
+
+/// The compute shader sampling the bricks of `VoxelStorage::BlockAtlas`.
+mod cs_atlas {
+    vulkano_shaders::shader!(
+        ty: "compute",
+        path: "src/renderer/voxel_dda/voxel_dda.comp.glsl",
+        define: [("BLOCK_ATLAS", "")],
+    );
+}
+
+// The screen-covering triangle of the encode pass
+mod vs {
+    vulkano_shaders::shader!(
+        ty: "vertex",
+        path: "src/renderer/encode/encode.vert.glsl",
+    );
+}
+
+mod fs {
+    vulkano_shaders::shader!(
+        ty: "fragment",
+        path: "src/renderer/voxel_dda/voxel_dda.frag.glsl",
+    );
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+/// Size of the ring the sections are staged through.
+const STAGING_RING_SIZE: u64 = 16 << 20;
+/// Octree nodes there is room for per grid cell before the node buffer
+/// grows. Most sections are uniform or only split near their surface.
+const INITIAL_NODES_PER_CELL: u64 = 64;
+/// Set on the shader's leaf nodes, which hold a block type.
+const LEAF_BIT: u32 = 1 << 31;
+
+/// Per block of a section, 1 plus the offset of its first voxel and its
+/// tint, like the shader's `Chunk::cells`. Blocks without voxels are 0.
+fn section_cells(chunk: &Chunk, baked_models: &BakedBlockModels) -> Vec<[u32; 2]> {
+    let mut cells = vec![[0; 2]; CHUNK_SIZE.pow(3)];
+    for (y, layer) in chunk.blocks.iter().enumerate() {
+        for (x, row) in layer.iter().enumerate() {
+            for (z, &block_type_id) in row.iter().enumerate() {
+                let block = baked_models.gpu_block(block_type_id, &chunk.biome_colors[x][z]);
+                let voxels = BlockVoxels::unpack(block.voxels);
+                if voxels.len > 0 {
+                    cells[x + CHUNK_SIZE * (y + CHUNK_SIZE * z)] = [voxels.offset + 1, block.tint];
+                }
+            }
+        }
+    }
+    cells
+}
+
+/// Per column of `chunk`, x changing fastest, its grass and foliage colors,
+/// like the `Chunk::biome_colors` of the variants of the shader with block
+/// types.
+fn section_biome_colors(chunk: &Chunk) -> Vec<[u32; 2]> {
+    let mut colors = vec![[0; 2]; CHUNK_SIZE.pow(2)];
+    for (x, row) in chunk.biome_colors.iter().enumerate() {
+        for (z, biome_colors) in row.iter().enumerate() {
+            colors[x + CHUNK_SIZE * z] = [biome_colors.grass, biome_colors.foliage]
+                .map(|color| pack_tint_color(Some(color)));
+        }
+    }
+    colors
+}
+
+/// The block types of `chunk` like its brick in the block atlas, x changing
+/// fastest, then y.
+fn section_brick(chunk: &Chunk) -> Vec<u16> {
+    let mut brick = Vec::with_capacity(CHUNK_SIZE.pow(3));
+    for z in 0..CHUNK_SIZE {
+        for layer in &chunk.blocks {
+            brick.extend(layer.iter().map(|row| row[z] as u16));
+        }
+    }
+    brick
+}
+
+/// The nodes of `octree` like the shader's `nodes`, placed at `offset` into
+/// them.
+fn pack_nodes(octree: &SparseVoxelOctree, offset: u32) -> Vec<u32> {
+    octree
+        .nodes
+        .iter()
+        .map(|node| match *node {
+            Node::Leaf(block_type_id) => LEAF_BIT | block_type_id as u32,
+            Node::Branch(first) => offset + first,
+        })
+        .collect()
+}
+
+/// Per block type, 1 plus the offset of its first voxel, its `Tint` and its
+/// alpha, like the shader's `block_types`. The tint of a column is only
+/// known from the section.
+fn gpu_block_types(baked_models: &BakedBlockModels) -> Vec<[u32; 4]> {
+    let white = pack_tint_color(None) & 0x00ff_ffff;
+    (0..baked_models.blocks.len())
+        .map(|block_type_id| {
+            let block = baked_models.gpu_block(block_type_id, &BiomeColors::default());
+            let voxels = BlockVoxels::unpack(block.voxels);
+            let first_voxel = if voxels.len > 0 { voxels.offset + 1 } else { 0 };
+            [
+                first_voxel,
+                baked_models.tints[block_type_id] as u32,
+                white | block.tint & 0xff00_0000,
+                0,
+            ]
+        })
+        .collect()
+}
+
+/// The octrees of the sections in `VoxelStorage::Octree`, all in a buffer
+/// that doubles when they no longer fit.
+struct OctreeNodes {
+    buffer: Subbuffer<[u32]>,
+    /// Reads `buffer`, created again with it.
+    set: Arc<DescriptorSet>,
+    ranges: RangeAllocator,
+    /// Offset and length of the nodes of every uploaded section.
+    sections: HashMap<ChunkPosition, (u64, u64)>,
+    _memory: TrackedAllocation,
+}
+
+/// What the rays of a frame hit, until it is copied into the frame's
+/// attachments.
+struct RayTargets {
+    /// Writes the images, for the compute pass.
+    storage_set: Arc<DescriptorSet>,
+    /// Reads them, for the copy.
+    sampled_set: Arc<DescriptorSet>,
+}
+
+/// An experimental alternative to `RenderFacesPipeline` that doesn't mesh
+/// at all: a compute pass marches a ray per pixel through the blocks of the
+/// sections within render distance, kept in a grid of sections stored as
+/// `VoxelStorage` says, and the color, motion and depth of the hits are then
+/// copied into the frame's attachments like the faces would have been drawn,
+/// so FSR and the passes drawn after keep working.
+pub struct VoxelDdaRenderer {
+    pipeline: Arc<ComputePipeline>,
+    copy_pipeline: Arc<GraphicsPipeline>,
+    /// The sections, the grid and the voxels and textures of the blocks.
+    world_set: Arc<DescriptorSet>,
+    /// Per frame in flight.
+    targets: Vec<RayTargets>,
+    extent: [u32; 2],
+    depth_mode: DepthMode,
+    /// How far rays go, in blocks.
+    max_distance: f32,
+
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    memory_tracker: Arc<MemoryTracker>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    baked_models: BakedBlockModels,
+    grid: ChunkGrid,
+    voxel_storage: VoxelStorage,
+    /// A `cs::Chunk`, or the `Chunk` of the other variants of the shader,
+    /// per slot.
+    chunk_buffer: Subbuffer<[u8]>,
+    /// Size of a chunk in `chunk_buffer`.
+    chunk_size: u64,
+    /// The shader's `block_types`, unless `VoxelStorage::Dense`.
+    block_types: Option<Subbuffer<[[u32; 4]]>>,
+    /// With `VoxelStorage::Octree`.
+    octree: Option<OctreeNodes>,
+    /// With `VoxelStorage::BlockAtlas`.
+    block_atlas: Option<Arc<Image>>,
+    grid_buffer: Subbuffer<[u32]>,
+    voxel_buffer: Subbuffer<[GpuVoxel]>,
+    /// Per grid cell, 1 plus the slot of the section in it, as uploaded to
+    /// `grid_buffer` when `grid_outdated`.
+    grid_slots: Vec<u32>,
+    grid_outdated: bool,
+    /// Slots of the uploaded sections.
+    chunk_slots: HashMap<ChunkPosition, u32>,
+    free_slots: Vec<u32>,
+    voxels_uploaded: bool,
+    staging: StagingRing,
+    /// Block edits and section loads of the world the sections come from.
+    world_events: Receiver<WorldEvent>,
+    _memory: Vec<TrackedAllocation>,
+}
+
+impl VoxelDdaRenderer {
+    /// A renderer for `world` within `render_distance` columns of the
+    /// camera, copying into attachments of `rendering_info` with `samples`,
+    /// traced at `extent` with the sections stored as `voxel_storage` says.
+    /// The block textures are those of `RenderFacesPipeline::block_textures`.
+    pub fn new(
+        app: &App,
+        rendering_info: PipelineRenderingCreateInfo,
+        samples: SampleCount,
+        world: &World,
+        render_distance: u32,
+        block_textures: Arc<ImageView>,
+        block_sampler: Arc<Sampler>,
+        extent: [u32; 2],
+        depth_mode: DepthMode,
+        voxel_storage: VoxelStorage,
+    ) -> Self {
+        let device = app.context.device().clone();
+        let pipeline = {
+            let cs = match voxel_storage {
+                VoxelStorage::Dense => cs::load(device.clone()),
+                VoxelStorage::Octree => cs_octree::load(device.clone()),
+                VoxelStorage::BlockAtlas => cs_atlas::load(device.clone()),
+            }
+            .unwrap()
+            .entry_point("main")
+            .unwrap();
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .unwrap()
+        };
+        let copy_pipeline = {
+            let vs = vs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let fs = fs::load(device.clone())
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    // Vertices come from the vertex index
+                    vertex_input_state: Some(VertexInputState::default()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState {
+                        rasterization_samples: samples,
+                        ..Default::default()
+                    }),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        rendering_info.color_attachment_formats.len() as u32,
+                        ColorBlendAttachmentState::default(),
+                    )),
+                    // Every pixel is written, the sky at the far depth
+                    depth_stencil_state: Some(DepthStencilState {
+                        depth: Some(DepthState {
+                            compare_op: CompareOp::Always,
+                            write_enable: true,
+                        }),
+                        ..Default::default()
+                    }),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(rendering_info.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .unwrap()
+        };
+
+        let grid = ChunkGrid::new(render_distance, world.height);
+        let baked_models = BakedBlockModels::bake(&world.block_registry);
+        let device_local = || AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        };
+        let storage_buffer = || BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+            ..Default::default()
+        };
+        let chunk_size = match voxel_storage {
+            VoxelStorage::Dense => size_of::<cs::Chunk>(),
+            VoxelStorage::Octree => size_of::<cs_octree::Chunk>(),
+            VoxelStorage::BlockAtlas => size_of::<cs_atlas::Chunk>(),
+        } as u64;
+        // The sections within render distance never share a cell, so there
+        // are as many slots as cells
+        let chunk_buffer = Buffer::new_slice::<u8>(
+            app.memory_allocator(),
+            storage_buffer(),
+            device_local(),
+            grid.cell_count() as u64 * chunk_size,
+        )
+        .unwrap();
+        let grid_buffer = Buffer::new_slice::<u32>(
+            app.memory_allocator(),
+            storage_buffer(),
+            device_local(),
+            grid.cell_count() as u64,
+        )
+        .unwrap();
+        let voxel_buffer = Buffer::new_slice::<GpuVoxel>(
+            app.memory_allocator(),
+            storage_buffer(),
+            device_local(),
+            baked_models.voxels.len().max(1) as u64,
+        )
+        .unwrap();
+        let staging = StagingRing::new(app.memory_allocator(), STAGING_RING_SIZE);
+        let mut memory = vec![
+            app.memory_tracker
+                .track_buffer(MemoryCategory::ChunkBuffers, &chunk_buffer),
+            app.memory_tracker
+                .track_buffer(MemoryCategory::ChunkBuffers, &grid_buffer),
+            app.memory_tracker
+                .track_buffer(MemoryCategory::BlockModels, &voxel_buffer),
+            app.memory_tracker
+                .track_buffer(MemoryCategory::Staging, staging.buffer()),
+        ];
+
+        let block_types = (voxel_storage != VoxelStorage::Dense).then(|| {
+            let block_types = Buffer::new_slice::<[u32; 4]>(
+                app.memory_allocator(),
+                storage_buffer(),
+                device_local(),
+                baked_models.blocks.len().max(1) as u64,
+            )
+            .unwrap();
+            memory.push(
+                app.memory_tracker
+                    .track_buffer(MemoryCategory::BlockModels, &block_types),
+            );
+            block_types
+        });
+        let octree = (voxel_storage == VoxelStorage::Octree).then(|| {
+            let capacity = grid.cell_count() as u64 * INITIAL_NODES_PER_CELL;
+            let buffer = node_buffer(app.memory_allocator(), capacity);
+            OctreeNodes {
+                set: node_set(&app.descriptor_set_allocator, &pipeline, &buffer),
+                ranges: RangeAllocator::new(capacity),
+                sections: HashMap::new(),
+                _memory: app
+                    .memory_tracker
+                    .track_buffer(MemoryCategory::ChunkBuffers, &buffer),
+                buffer,
+            }
+        });
+        let block_atlas = (voxel_storage == VoxelStorage::BlockAtlas).then(|| {
+            assert!(
+                baked_models.blocks.len() <= 1 << 16,
+                "too many block types for the block atlas"
+            );
+            let image = Image::new(
+                app.memory_allocator(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim3d,
+                    format: if baked_models.blocks.len() <= 1 << 8 {
+                        Format::R8_UINT
+                    } else {
+                        Format::R16_UINT
+                    },
+                    extent: grid.extent.map(|extent| extent * CHUNK_SIZE as u32),
+                    usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .unwrap();
+            memory.push(
+                app.memory_tracker
+                    .track_image(MemoryCategory::ChunkBuffers, &image),
+            );
+            image
+        });
+
+        // Only read with texelFetch, so the sampler doesn't matter
+        let sampler = Sampler::new(device.clone(), SamplerCreateInfo::default()).unwrap();
+        let world_set = DescriptorSet::new(
+            app.descriptor_set_allocator.clone(),
+            pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::buffer(0, chunk_buffer.clone()),
+                WriteDescriptorSet::buffer(1, grid_buffer.clone()),
+                WriteDescriptorSet::buffer(2, voxel_buffer.clone()),
+                WriteDescriptorSet::image_view_sampler(3, block_textures, block_sampler),
+            ]
+            .into_iter()
+            .chain(
+                block_types
+                    .as_ref()
+                    .map(|block_types| WriteDescriptorSet::buffer(4, block_types.clone())),
+            )
+            .chain(block_atlas.as_ref().map(|block_atlas| {
+                WriteDescriptorSet::image_view_sampler(
+                    5,
+                    ImageView::new_default(block_atlas.clone()).unwrap(),
+                    sampler.clone(),
+                )
+            })),
+            None,
+        )
+        .unwrap();
+
+        let targets = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let [color, motion_vector, depth] = [
+                    Format::R16G16B16A16_SFLOAT,
+                    Format::R16G16_SFLOAT,
+                    Format::R32_SFLOAT,
+                ]
+                .map(|format| {
+                    let image = Image::new(
+                        app.memory_allocator(),
+                        ImageCreateInfo {
+                            image_type: ImageType::Dim2d,
+                            extent: [extent[0], extent[1], 1],
+                            format,
+                            usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                            ..Default::default()
+                        },
+                        AllocationCreateInfo::default(),
+                    )
+                    .unwrap();
+                    memory.push(
+                        app.memory_tracker
+                            .track_image(MemoryCategory::RenderTargets, &image),
+                    );
+                    ImageView::new_default(image).unwrap()
+                });
+                let storage_set = DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    pipeline.layout().set_layouts()[1].clone(),
+                    [
+                        WriteDescriptorSet::image_view(0, color.clone()),
+                        WriteDescriptorSet::image_view(1, motion_vector.clone()),
+                        WriteDescriptorSet::image_view(2, depth.clone()),
+                    ],
+                    None,
+                )
+                .unwrap();
+                let sampled_set = DescriptorSet::new(
+                    app.descriptor_set_allocator.clone(),
+                    copy_pipeline.layout().set_layouts()[0].clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(0, color, sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(1, motion_vector, sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(2, depth, sampler.clone()),
+                    ],
+                    None,
+                )
+                .unwrap();
+                RayTargets {
+                    storage_set,
+                    sampled_set,
+                }
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            copy_pipeline,
+            world_set,
+            targets,
+            extent,
+            depth_mode,
+            // Columns at the edge of render distance still have their far
+            // side in range
+            max_distance: ((render_distance + 1) as usize * CHUNK_SIZE) as f32,
+            memory_allocator: app.memory_allocator(),
+            memory_tracker: app.memory_tracker.clone(),
+            descriptor_set_allocator: app.descriptor_set_allocator.clone(),
+            baked_models,
+            grid,
+            voxel_storage,
+            chunk_buffer,
+            chunk_size,
+            block_types,
+            octree,
+            block_atlas,
+            grid_buffer,
+            voxel_buffer,
+            grid_slots: vec![0; grid.cell_count()],
+            grid_outdated: true,
+            chunk_slots: HashMap::new(),
+            free_slots: (0..grid.cell_count() as u32).rev().collect(),
+            voxels_uploaded: false,
+            staging,
+            world_events: world.events.subscribe(),
+            _memory: memory,
+        }
+    }
+
+    /// Records the upload of the sections of the columns that `entered`
+    /// render distance and of the uploaded ones that changed, and drops
+    /// those of the columns that `left` it. The buffers are only read by
+    /// frames, which `begin_command_buffer` orders after the earlier ones.
+    /// The command buffer has to be followed by `submit_uploads`.
+    #[instrument(skip_all)]
+    pub fn update_chunks(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        world: &World,
+        entered: &[ColumnPosition],
+        left: &[ColumnPosition],
+    ) {
+        if !self.voxels_uploaded && !self.baked_models.voxels.is_empty() {
+            let staged_voxels = self.staging.upload(&self.baked_models.voxels);
+            builder
+                .copy_buffer(CopyBufferInfo::buffers(
+                    staged_voxels,
+                    self.voxel_buffer.clone(),
+                ))
+                .unwrap();
+            if let Some(block_types) = &self.block_types {
+                let staged_block_types = self.staging.upload(&gpu_block_types(&self.baked_models));
+                builder
+                    .copy_buffer(CopyBufferInfo::buffers(
+                        staged_block_types,
+                        block_types.clone(),
+                    ))
+                    .unwrap();
+            }
+        }
+        self.voxels_uploaded = true;
+
+        let mut changed = self.receive_world_events();
+        for &column in left {
+            for chunk_position in column.sections(world.height) {
+                self.remove(chunk_position);
+                changed.remove(&chunk_position);
+            }
+        }
+        changed.extend(
+            entered
+                .iter()
+                .flat_map(|column| column.sections(world.height)),
+        );
+        for chunk_position in changed {
+            if let Some(chunk) = world.chunks.get(&chunk_position) {
+                self.upload(builder, chunk_position, chunk);
+            }
+        }
+
+        if self.grid_outdated {
+            let staged_slots = self.staging.upload(&self.grid_slots);
+            builder
+                .copy_buffer(CopyBufferInfo::buffers(
+                    staged_slots,
+                    self.grid_buffer.clone(),
+                ))
+                .unwrap();
+            self.grid_outdated = false;
+        }
+    }
+
+    /// Handles the world events received since the last call, returning the
+    /// uploaded sections whose blocks changed. Unloaded sections are dropped
+    /// right away.
+    fn receive_world_events(&mut self) -> HashSet<ChunkPosition> {
+        let mut changed = HashSet::new();
+        for event in self.world_events.try_iter().collect::<Vec<_>>() {
+            match event {
+                WorldEvent::BlockChanged { position, .. } => {
+                    changed.insert(ChunkPosition::of_block(position));
+                }
+                WorldEvent::ChunkLoaded(chunk_position) => {
+                    changed.insert(chunk_position);
+                }
+                WorldEvent::ChunkUnloaded(chunk_position) => {
+                    changed.remove(&chunk_position);
+                    self.remove(chunk_position);
+                }
+            }
+        }
+        changed.retain(|chunk_position| self.chunk_slots.contains_key(chunk_position));
+        changed
+    }
+
+    /// Records the copy of the blocks of `chunk` into the slot of the
+    /// section at `chunk_position`, taking a free one the first time, and
+    /// into the node buffer or the block atlas if they are stored there.
+    fn upload(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        chunk_position: ChunkPosition,
+        chunk: &Chunk,
+    ) {
+        let slot = match self.chunk_slots.get(&chunk_position) {
+            Some(&slot) => slot,
+            None => {
+                let slot = self.free_slots.pop().expect("VoxelDdaRenderer is full");
+                self.chunk_slots.insert(chunk_position, slot);
+                self.grid_slots[self.grid.index(chunk_position)] = slot + 1;
+                self.grid_outdated = true;
+                slot
+            }
+        };
+
+        let root = match self.voxel_storage {
+            VoxelStorage::Dense => 0,
+            VoxelStorage::Octree => self.upload_nodes(builder, chunk_position, chunk) as i32,
+            VoxelStorage::BlockAtlas => {
+                self.upload_brick(builder, chunk_position, chunk);
+                0
+            }
+        };
+        let blocks = if self.voxel_storage == VoxelStorage::Dense {
+            let cells = self
+                .staging
+                .upload(&section_cells(chunk, &self.baked_models));
+            (cells.into_bytes(), offset_of!(cs::Chunk, cells))
+        } else {
+            // The same in the octree variant
+            let biome_colors = self.staging.upload(&section_biome_colors(chunk));
+            (
+                biome_colors.into_bytes(),
+                offset_of!(cs_atlas::Chunk, biome_colors),
+            )
+        };
+
+        let chunk_offset = slot as u64 * self.chunk_size;
+        let position =
+            self.staging
+                .upload(&[[chunk_position.x, chunk_position.y, chunk_position.z, root]]);
+        for (source, offset) in [
+            // First in both variants of the shader
+            (position.into_bytes(), offset_of!(cs::Chunk, position)),
+            blocks,
+        ] {
+            builder
+                .copy_buffer(CopyBufferInfo {
+                    regions: [BufferCopy {
+                        dst_offset: chunk_offset + offset as u64,
+                        size: source.size(),
+                        ..Default::default()
+                    }]
+                    .into_iter()
+                    .collect(),
+                    ..CopyBufferInfo::buffers(source, self.chunk_buffer.clone())
+                })
+                .unwrap();
+        }
+    }
+
+    /// Records the copy of the octree of `chunk` into the node buffer in
+    /// place of that of the section at `chunk_position`, returning the
+    /// offset of its root.
+    fn upload_nodes(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        chunk_position: ChunkPosition,
+        chunk: &Chunk,
+    ) -> u64 {
+        let octree = SparseVoxelOctree::from_chunk(chunk);
+        let len = octree.nodes.len() as u64;
+        let nodes = self.octree.as_mut().unwrap();
+        if let Some((offset, len)) = nodes.sections.remove(&chunk_position) {
+            nodes.ranges.free(offset, len);
+        }
+        let offset = self.allocate_nodes(builder, len);
+        let staged_nodes = self.staging.upload(&pack_nodes(&octree, offset as u32));
+        let nodes = self.octree.as_mut().unwrap();
+        nodes.sections.insert(chunk_position, (offset, len));
+        builder
+            .copy_buffer(CopyBufferInfo {
+                regions: [BufferCopy {
+                    dst_offset: offset * size_of::<u32>() as u64,
+                    size: staged_nodes.size(),
+                    ..Default::default()
+                }]
+                .into_iter()
+                .collect(),
+                ..CopyBufferInfo::buffers(staged_nodes, nodes.buffer.clone())
+            })
+            .unwrap();
+        offset
+    }
+
+    /// Records the copy of the block types of `chunk` into the brick of the
+    /// section at `chunk_position` in the block atlas.
+    fn upload_brick(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        chunk_position: ChunkPosition,
+        chunk: &Chunk,
+    ) {
+        let block_atlas = self.block_atlas.clone().unwrap();
+        let brick = section_brick(chunk);
+        let staged_brick = if block_atlas.format() == Format::R8_UINT {
+            let brick: Vec<u8> = brick
+                .iter()
+                .map(|&block_type_id| block_type_id as u8)
+                .collect();
+            self.staging.upload(&brick).into_bytes()
+        } else {
+            self.staging.upload(&brick).into_bytes()
+        };
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo {
+                regions: [BufferImageCopy {
+                    image_subresource: block_atlas.subresource_layers(),
+                    image_offset: self
+                        .grid
+                        .cell(chunk_position)
+                        .map(|v| v * CHUNK_SIZE as u32),
+                    image_extent: [CHUNK_SIZE as u32; 3],
+                    ..Default::default()
+                }]
+                .into_iter()
+                .collect(),
+                ..CopyBufferToImageInfo::buffer_image(staged_brick, block_atlas)
+            })
+            .unwrap();
+    }
+
+    /// Offset of `len` free nodes in the node buffer, recording its growth
+    /// if there isn't room for them.
+    fn allocate_nodes(&mut self, builder: &mut RecordingCommandBuffer, len: u64) -> u64 {
+        let octree = self.octree.as_mut().unwrap();
+        if let Some(offset) = octree.ranges.allocate(len) {
+            return offset;
+        }
+
+        let capacity = (octree.ranges.capacity() * 2).max(octree.ranges.capacity() + len);
+        let buffer = node_buffer(self.memory_allocator.clone(), capacity);
+        builder
+            .copy_buffer(CopyBufferInfo::buffers(
+                octree.buffer.clone(),
+                buffer.clone(),
+            ))
+            .unwrap();
+        octree.set = node_set(&self.descriptor_set_allocator, &self.pipeline, &buffer);
+        octree._memory = self
+            .memory_tracker
+            .track_buffer(MemoryCategory::ChunkBuffers, &buffer);
+        octree.buffer = buffer;
+        octree.ranges.grow(capacity);
+        octree.ranges.allocate(len).unwrap()
+    }
+
+    /// Frees the slot of the section at `chunk_position`, its grid cell and
+    /// its nodes.
+    fn remove(&mut self, chunk_position: ChunkPosition) {
+        if let Some(octree) = &mut self.octree {
+            if let Some((offset, len)) = octree.sections.remove(&chunk_position) {
+                octree.ranges.free(offset, len);
+            }
+        }
+        if let Some(slot) = self.chunk_slots.remove(&chunk_position) {
+            self.free_slots.push(slot);
+            let cell = &mut self.grid_slots[self.grid.index(chunk_position)];
+            if *cell == slot + 1 {
+                *cell = 0;
+                self.grid_outdated = true;
+            }
+        }
+    }
+
+    /// Number of uploaded sections rays are traced through.
+    pub fn chunk_count(&self) -> usize {
+        self.chunk_slots.len()
+    }
+
+    /// Hands the staging space used by the uploads recorded since the last
+    /// call back to the ring once `fence` is signaled, returning how many
+    /// bytes they uploaded.
+    pub fn submit_uploads(&mut self, fence: impl UploadFence + 'static) -> u64 {
+        self.staging.submit(fence)
+    }
+
+    /// Records marching the rays of frame `frame` as seen by `camera`, after
+    /// `previous_camera` the frame before. Must run outside of rendering,
+    /// before `render`.
+    pub fn trace(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        frame: usize,
+        previous_camera: &Camera,
+        camera: &Camera,
+    ) {
+        let view_proj = camera.proj * camera.view;
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                [
+                    self.world_set.clone(),
+                    self.targets[frame].storage_set.clone(),
+                ]
+                .into_iter()
+                .chain(self.octree.as_ref().map(|octree| octree.set.clone()))
+                .collect::<Vec<_>>(),
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                // The same in both variants of the shader
+                cs::PushConstants {
+                    inverse_view_proj: view_proj.invert().unwrap().into(),
+                    view_proj: view_proj.into(),
+                    previous_view_proj: (previous_camera.proj * previous_camera.view).into(),
+                    camera_position: camera.position.to_homogeneous().into(),
+                    sky_color: SKY_COLOR,
+                    grid_extent: self.grid.extent,
+                    far_depth: self.depth_mode.far_depth(),
+                    jitter: camera.jitter.into(),
+                    max_distance: self.max_distance,
+                },
+            )
+            .unwrap();
+        unsafe {
+            builder
+                .dispatch([
+                    self.extent[0].div_ceil(WORKGROUP_SIZE),
+                    self.extent[1].div_ceil(WORKGROUP_SIZE),
+                    1,
+                ])
+                .unwrap()
+        };
+    }
+
+    /// Records copying what the rays of frame `frame` hit into the
+    /// attachments of a rendering begun by `draw`, in place of
+    /// `RenderFacesPipeline::render_cube_faces`.
+    pub fn render(&self, builder: &mut RecordingCommandBuffer, frame: usize) {
+        builder
+            .bind_pipeline_graphics(self.copy_pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.copy_pipeline.layout().clone(),
+                0,
+                self.targets[frame].sampled_set.clone(),
+            )
+            .unwrap();
+        unsafe {
+            builder.draw(3, 1, 0, 0).unwrap();
+        }
+    }
+}
+
+/// A node buffer with room for `capacity` nodes, which can be copied into a
+/// larger one.
+fn node_buffer(memory_allocator: Arc<StandardMemoryAllocator>, capacity: u64) -> Subbuffer<[u32]> {
+    Buffer::new_slice::<u32>(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER
+                | BufferUsage::TRANSFER_SRC
+                | BufferUsage::TRANSFER_DST,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        },
+        capacity,
+    )
+    .unwrap()
+}
+
+fn node_set(
+    descriptor_set_allocator: &Arc<StandardDescriptorSetAllocator>,
+    pipeline: &ComputePipeline,
+    buffer: &Subbuffer<[u32]>,
+) -> Arc<DescriptorSet> {
+    DescriptorSet::new(
+        descriptor_set_allocator.clone(),
+        pipeline.layout().set_layouts()[2].clone(),
+        [WriteDescriptorSet::buffer(0, buffer.clone())],
+        None,
+    )
+    .unwrap()
+}
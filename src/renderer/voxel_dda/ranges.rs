@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+/// First-fit allocation of ranges of a buffer, for the nodes of the section
+/// octrees, which vary in length from section to section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeAllocator {
+    capacity: u64,
+    /// Length of every free range by its offset. Adjacent free ranges are
+    /// always merged.
+    free: BTreeMap<u64, u64>,
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            free: BTreeMap::from([(0, capacity)]),
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Offset of the first free range of `len`, or `None` without one.
+    pub fn allocate(&mut self, len: u64) -> Option<u64> {
+        let (&offset, &free_len) = self.free.iter().find(|&(_, &free_len)| free_len >= len)?;
+        self.free.remove(&offset);
+        if free_len > len {
+            self.free.insert(offset + len, free_len - len);
+        }
+        Some(offset)
+    }
+
+    /// Frees the range of `len` at `offset` that `allocate` returned.
+    pub fn free(&mut self, mut offset: u64, mut len: u64) {
+        if let Some((&next, &next_len)) = self.free.range(offset + len..).next() {
+            if next == offset + len {
+                self.free.remove(&next);
+                len += next_len;
+            }
+        }
+        if let Some((&previous, &previous_len)) = self.free.range(..offset).next_back() {
+            if previous + previous_len == offset {
+                self.free.remove(&previous);
+                offset = previous;
+                len += previous_len;
+            }
+        }
+        self.free.insert(offset, len);
+    }
+
+    /// Extends the space ranges are allocated from to `capacity`.
+    pub fn grow(&mut self, capacity: u64) {
+        assert!(capacity >= self.capacity);
+        let old_capacity = self.capacity;
+        self.capacity = capacity;
+        if capacity > old_capacity {
+            self.free(old_capacity, capacity - old_capacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_allocator() {
+        let mut ranges = RangeAllocator::new(16);
+        assert_eq!(ranges.allocate(4), Some(0));
+        assert_eq!(ranges.allocate(8), Some(4));
+        assert_eq!(ranges.allocate(8), None);
+        assert_eq!(ranges.allocate(4), Some(12));
+
+        // Freed ranges are reused first, merged with their neighbours
+        ranges.free(0, 4);
+        assert_eq!(ranges.allocate(2), Some(0));
+        ranges.free(4, 8);
+        assert_eq!(ranges.allocate(10), Some(2));
+        ranges.free(0, 2);
+        ranges.free(2, 10);
+        assert_eq!(ranges.allocate(12), Some(0));
+
+        // Growing merges with the free space at the end
+        ranges.free(8, 4);
+        ranges.grow(32);
+        assert_eq!(ranges.capacity(), 32);
+        assert_eq!(ranges.allocate(20), None);
+        ranges.free(12, 4);
+        assert_eq!(ranges.allocate(24), Some(8));
+    }
+}
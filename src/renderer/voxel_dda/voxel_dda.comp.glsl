@@ -0,0 +1,324 @@
+#version 460
+
+// Marches a ray per pixel through the blocks of the chunk grid, one block
+// at a time with a DDA and a whole cube at a time across empty sections and,
+// with SPARSE_VOXEL_OCTREE, across empty octree nodes, and writes the color,
+// motion and depth of what it hits. Every block is traced as a full cube
+// with the faces of its first voxel.
+//
+// Sections are stored as cells of their blocks by default, as octrees of
+// block types with SPARSE_VOXEL_OCTREE, or as bricks of block types in a
+// 3D texture with BLOCK_ATLAS.
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+#if defined(SPARSE_VOXEL_OCTREE) || defined(BLOCK_ATLAS)
+#define BLOCK_TYPES
+#endif
+
+const uint CHUNK_SIZE = 16;
+#ifdef BLOCK_TYPES
+struct Chunk {
+  // xyz: section position in chunks, w: with SPARSE_VOXEL_OCTREE the index
+  // of the root of its octree
+  ivec4 position;
+  // Per column, x changing fastest: the RGBA8 grass and foliage colors
+  uvec2 biome_colors[CHUNK_SIZE * CHUNK_SIZE];
+};
+#else
+struct Chunk {
+  ivec4 position;  // xyz: section position in chunks, w unused
+  // Per block: 1 plus the offset of its first voxel, 0 without voxels, and
+  // the RGBA8 tint with 1 minus the reflectivity as alpha
+  uvec2 cells[CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+};
+#endif
+
+layout(std430, set = 0, binding = 0) readonly buffer ChunkBuffer {
+  Chunk chunks[];
+};
+// Per grid cell, 1 plus the slot of the section in it, 0 without one
+layout(std430, set = 0, binding = 1) readonly buffer GridBuffer {
+  uint slots[];
+};
+
+struct VoxelFace {
+  vec4 uv;
+  uint texture_index;
+  bool cullface;
+};
+
+struct Voxel {
+  vec3 from;
+  uint material;  // block type the voxel belongs to
+  vec3 to;
+  VoxelFace faces[6];
+};
+
+layout(std430, set = 0, binding = 2) readonly buffer VoxelBuffer {
+  Voxel voxels[];
+};
+
+layout(set = 0, binding = 3) uniform sampler2DArray block_textures;
+
+#ifdef BLOCK_TYPES
+const uint TINT_GRASS = 1;
+const uint TINT_FOLIAGE = 2;
+// Per block type: 1 plus the offset of its first voxel, 0 without voxels,
+// its tint, and white with 1 minus its reflectivity as alpha
+layout(std430, set = 0, binding = 4) readonly buffer BlockTypeBuffer {
+  uvec4 block_types[];
+};
+#endif
+
+#ifdef SPARSE_VOXEL_OCTREE
+// Set on leaves, which hold a block type; branches hold the index of the
+// first of their eight children, child i being the upper half along x for
+// bit 0, along y for bit 1 and along z for bit 2
+const uint LEAF_BIT = 0x80000000u;
+// In a set of its own, as the buffer is replaced when it grows
+layout(std430, set = 2, binding = 0) readonly buffer NodeBuffer {
+  uint nodes[];
+};
+#endif
+
+#ifdef BLOCK_ATLAS
+// The block types of every section in the brick at its grid cell, which is
+// at the positions of its blocks modulo the extent of the grid in blocks
+layout(set = 0, binding = 5) uniform usampler3D block_atlas;
+#endif
+
+layout(set = 1, binding = 0, rgba16f) uniform writeonly image2D color;
+layout(set = 1, binding = 1, rg16f) uniform writeonly image2D motion_vector;
+layout(set = 1, binding = 2, r32f) uniform writeonly image2D depth;
+
+layout(push_constant) uniform PushConstants {
+  mat4 inverse_view_proj;
+  mat4 view_proj;
+  mat4 previous_view_proj;
+  vec4 camera_position;
+  vec4 sky_color;
+  uvec3 grid_extent;
+  float far_depth;
+  vec2 jitter;
+  float max_distance;  // in blocks
+}
+pc;
+
+// Steps through blocks or across sections before a ray gives up
+const int MAX_STEPS = 1024;
+
+const vec3 LIGHT_DIRECTION = normalize(vec3(0.3, 1.0, 0.5));
+
+// Slot of the section at `chunk`, or -1 if it is not in the grid
+int chunk_slot(ivec3 chunk) {
+  ivec3 extent = ivec3(pc.grid_extent);
+  ivec3 cell = chunk - extent * ivec3(floor(vec3(chunk) / vec3(extent)));
+  uint slot = slots[cell.x + extent.x * (cell.y + extent.y * cell.z)];
+  // A section further away may wrap to the same cell
+  if (slot == 0 || chunks[slot - 1].position.xyz != chunk) {
+    return -1;
+  }
+  return int(slot - 1);
+}
+
+// Direction of the ray through `pixel`, undoing the jitter the mesh path
+// applies after projecting
+vec3 ray_direction(vec2 pixel) {
+  vec2 ndc = pixel / vec2(imageSize(color)) * 2.0 - 1.0 - pc.jitter;
+  vec4 point = pc.inverse_view_proj * vec4(ndc, 0.5, 1.0);
+  return normalize(point.xyz / point.w - pc.camera_position.xyz);
+}
+
+#ifdef BLOCK_TYPES
+// The cell of the block of type `block_type_id` at `local` in the section
+// in `slot` like `Chunk::cells` would hold it, false without voxels
+bool block_cell(int slot, ivec3 local, uint block_type_id, out uvec2 cell) {
+  uvec4 block_type = block_types[block_type_id];
+  if (block_type.x == 0) {
+    return false;
+  }
+  uint tint = block_type.z;
+  if (block_type.y == TINT_GRASS || block_type.y == TINT_FOLIAGE) {
+    uvec2 colors = chunks[slot].biome_colors[local.x + CHUNK_SIZE * local.z];
+    uint color = block_type.y == TINT_GRASS ? colors.x : colors.y;
+    tint = color & 0x00ffffffu | tint & 0xff000000u;
+  }
+  cell = uvec2(block_type.x, tint);
+  return true;
+}
+#endif
+
+// Finds the cell of the block at `local` in the section in `slot`,
+// returning false with the edge of the empty cube the block is in if it
+// has no voxels
+#if defined(SPARSE_VOXEL_OCTREE)
+bool find_cell(int slot, ivec3 local, out uvec2 cell, out int empty_size) {
+  uint node = nodes[chunks[slot].position.w];
+  int size = int(CHUNK_SIZE);
+  while ((node & LEAF_BIT) == 0) {
+    size /= 2;
+    ivec3 upper_half = min(local & size, 1);
+    uint child = uint(upper_half.x | upper_half.y << 1 | upper_half.z << 2);
+    node = nodes[node + child];
+  }
+  empty_size = size;
+  return block_cell(slot, local, node & ~LEAF_BIT, cell);
+}
+#elif defined(BLOCK_ATLAS)
+bool find_cell(int slot, ivec3 local, out uvec2 cell, out int empty_size) {
+  ivec3 extent = ivec3(pc.grid_extent) * int(CHUNK_SIZE);
+  ivec3 block = chunks[slot].position.xyz * int(CHUNK_SIZE) + local;
+  ivec3 texel = block - extent * ivec3(floor(vec3(block) / vec3(extent)));
+  empty_size = 1;
+  return block_cell(slot, local, texelFetch(block_atlas, texel, 0).r, cell);
+}
+#else
+bool find_cell(int slot, ivec3 local, out uvec2 cell, out int empty_size) {
+  uint index = local.x + CHUNK_SIZE * (local.y + CHUNK_SIZE * local.z);
+  cell = chunks[slot].cells[index];
+  empty_size = 1;
+  return cell.x != 0;
+}
+#endif
+
+struct Hit {
+  ivec3 block;
+  uvec2 cell;
+  float distance;
+  int axis;  // crossed into the block along, -1 if the ray started in it
+};
+
+bool march(vec3 origin, vec3 direction, out Hit hit) {
+  // No zero components, so every axis crosses a boundary at some point
+  direction = mix(direction, vec3(1e-6), equal(direction, vec3(0.0)));
+  vec3 inverse = 1.0 / direction;
+  ivec3 step_direction = ivec3(sign(direction));
+  vec3 upper = max(vec3(step_direction), 0.0);
+
+  ivec3 block = ivec3(floor(origin));
+  // Distance along the ray to the next boundary on each axis
+  vec3 side = (vec3(block) + upper - origin) * inverse;
+  float t = 0.0;
+  int axis = -1;
+  for (int i = 0; i < MAX_STEPS && t < pc.max_distance; ++i) {
+    ivec3 chunk = block >> 4;
+    int slot = chunk_slot(chunk);
+    // Edge of the empty cube of blocks the ray is in, a missing section
+    // being empty as a whole
+    int empty_size = int(CHUNK_SIZE);
+    if (slot >= 0) {
+      ivec3 local = block & ivec3(CHUNK_SIZE - 1);
+      uvec2 cell;
+      if (find_cell(slot, local, cell, empty_size)) {
+        hit = Hit(block, cell, t, axis);
+        return true;
+      }
+    }
+
+    if (empty_size > 1) {
+      // Across to where the ray leaves the cube, which is aligned to its
+      // size
+      ivec3 cube_min = block & ~(empty_size - 1);
+      vec3 exit =
+          (vec3(cube_min) + upper * float(empty_size) - origin) * inverse;
+      t = min(exit.x, min(exit.y, exit.z));
+      axis = t == exit.x ? 0 : t == exit.y ? 1 : 2;
+      block = ivec3(floor(origin + direction * t));
+      block[axis] = cube_min[axis] +
+                    (step_direction[axis] > 0 ? empty_size : -1);
+      side = (vec3(block) + upper - origin) * inverse;
+      continue;
+    }
+
+    if (side.x < side.y && side.x < side.z) {
+      axis = 0;
+    } else if (side.y < side.z) {
+      axis = 1;
+    } else {
+      axis = 2;
+    }
+    t = side[axis];
+    block[axis] += step_direction[axis];
+    side[axis] += abs(inverse[axis]);
+  }
+  return false;
+}
+
+// Index into `Voxel::faces` of the face with `normal`, in the order of
+// GPU_FACE_DIRECTIONS
+uint face_index(int axis, float normal) {
+  uint first = axis == 2 ? 0 : axis == 1 ? 2 : 4;
+  return first + (normal > 0.0 ? 1 : 0);
+}
+
+// Where `local` lies on the face, matching the corners the mesh shader
+// gives each face in cube_vertices
+vec2 face_coords(uint face, vec3 local) {
+  switch (face) {
+    case 0:
+      return local.yx;
+    case 1:
+      return local.xy;
+    case 2:
+      return local.xz;
+    case 3:
+      return local.zx;
+    case 4:
+      return local.zy;
+    default:
+      return local.yz;
+  }
+}
+
+void main() {
+  ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+  if (any(greaterThanEqual(pixel, imageSize(color)))) {
+    return;
+  }
+
+  vec3 origin = pc.camera_position.xyz;
+  vec3 direction = ray_direction(vec2(pixel) + 0.5);
+  Hit hit;
+  if (!march(origin, direction, hit)) {
+    imageStore(color, pixel, pc.sky_color);
+    imageStore(motion_vector, pixel, vec4(0.0));
+    imageStore(depth, pixel, vec4(pc.far_depth));
+    return;
+  }
+
+  vec3 normal = -direction;
+  if (hit.axis >= 0) {
+    normal = vec3(0.0);
+    normal[hit.axis] = -sign(direction[hit.axis]);
+  }
+  int axis = hit.axis >= 0 ? hit.axis : 1;
+  uint face = face_index(axis, normal[axis]);
+  vec3 position = origin + direction * hit.distance;
+  vec3 local = clamp(position - vec3(hit.block), 0.0, 1.0);
+
+  VoxelFace voxel_face = voxels[hit.cell.x - 1].faces[face];
+  vec2 tex_coords = mix(voxel_face.uv.xy, voxel_face.uv.zw,
+                        face_coords(face, local));
+  // No derivatives in compute, so the level comes from how many texels a
+  // pixel covers at the hit's distance
+  float pixel_angle =
+      distance(direction, ray_direction(vec2(pixel) + vec2(1.5, 0.5)));
+  float texels = hit.distance * pixel_angle *
+                 float(textureSize(block_textures, 0).x) /
+                 max(abs(dot(direction, normal)), 0.25);
+  vec4 texel = textureLod(
+      block_textures, vec3(tex_coords, float(voxel_face.texture_index)),
+      log2(max(texels, 1.0)));
+
+  vec4 tint = unpackUnorm4x8(hit.cell.y);
+  float shade = 0.6 + 0.4 * max(dot(normal, LIGHT_DIRECTION), 0.0);
+  imageStore(color, pixel, vec4(texel.rgb * tint.rgb * shade, tint.a));
+
+  vec4 current = pc.view_proj * vec4(position, 1.0);
+  vec4 previous = pc.previous_view_proj * vec4(position, 1.0);
+  imageStore(motion_vector, pixel,
+             vec4(previous.xy / previous.w - current.xy / current.w, 0.0,
+                  0.0));
+  imageStore(depth, pixel, vec4(current.z / current.w));
+}
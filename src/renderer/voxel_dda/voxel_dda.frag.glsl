@@ -0,0 +1,18 @@
+#version 460
+
+// Copies what the rays hit into the attachments of the frame, depth
+// included, so what is drawn after it is tested against the blocks.
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D motion_vectors;
+layout(set = 0, binding = 2) uniform sampler2D depth;
+
+layout(location = 0) out vec4 frag_color;
+layout(location = 1) out vec2 motion_vector;
+
+void main() {
+  ivec2 pixel = ivec2(gl_FragCoord.xy);
+  frag_color = texelFetch(color, pixel, 0);
+  motion_vector = texelFetch(motion_vectors, pixel, 0).xy;
+  gl_FragDepth = texelFetch(depth, pixel, 0).r;
+}
@@ -0,0 +1,1144 @@
+//! Everything that draws a frame of the world behind one type: the render
+//! targets, the pipelines and post-processing passes the graphics settings
+//! pick, FSR and the upload of the chunks that load and change. Users create
+//! a `VoxelRenderer` for a window and hand it the world, a camera and what
+//! else a frame shows.
+
+use std::{
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+
+use bevy_ecs::entity::Entity;
+use cgmath::Vector2;
+use log::{debug, info, warn};
+use vulkano::{
+    device::Queue,
+    format::Format,
+    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage, SampleCount},
+    memory::allocator::AllocationCreateInfo,
+    pipeline::graphics::{subpass::PipelineRenderingCreateInfo, viewport::Viewport},
+    sync::{GpuFuture, Sharing},
+    VulkanObject,
+};
+use vulkano_util::renderer::VulkanoWindowRenderer;
+
+use crate::{
+    app::App,
+    camera::DepthMode,
+    chunk_loader::{ChunkLoader, LoaderUpdate},
+    entity::{Renderable, Transform},
+    fsr::FsrContextVulkan,
+    hud::Hud,
+    lut::Lut,
+    memory::{MemoryCategory, TrackedAllocation},
+    particles::Particle,
+    settings::{
+        AmbientOcclusion, AntiAliasing, FsrSettings, GraphicsSettings, Shadows, WorldRenderer,
+    },
+    types::{World, WATER_BLOCK},
+    viewmodel::ViewModel,
+    weather::Weather,
+};
+
+use super::{
+    acceleration::WorldAccelerationStructure,
+    ambient_occlusion::AmbientOcclusionPass,
+    block_sampler, depth_format, draw, draw_overlay, draw_translucent, draw_viewmodel,
+    encode::EncodePass,
+    fog::FogPass,
+    frame_graph::{FrameGraph, QueueKind, Usage},
+    frames::{FramesInFlight, FRAMES_IN_FLIGHT},
+    fxaa::FxaaPass,
+    hi_z::HiZPyramid,
+    mip_lod_bias,
+    motion_blur::MotionBlurPass,
+    render_clouds::RenderCloudsPipeline,
+    render_entities::RenderEntitiesPipeline,
+    render_faces::{Camera, RenderFacesPipeline},
+    render_hud::RenderHudPipeline,
+    render_particles::RenderParticlesPipeline,
+    render_viewmodel::RenderViewModelPipeline,
+    ssr::SsrPass,
+    statistics::{DrawStatistics, DrawStatisticsQuery, PassTimer},
+    taa::TaaPass,
+    underwater::UnderwaterPass,
+    voxel_dda::VoxelDdaRenderer,
+    Attachment, COLOR_FORMAT,
+};
+
+/// A render-size or display-size image, shared between the graphics and the
+/// compute queue family if they differ.
+pub fn render_target(
+    app: &App,
+    extent: [u32; 3],
+    format: Format,
+    usage: ImageUsage,
+    samples: SampleCount,
+    queue_family_indices: &[u32],
+) -> Arc<ImageView> {
+    let sharing = if queue_family_indices.len() > 1 {
+        Sharing::Concurrent(queue_family_indices.iter().copied().collect())
+    } else {
+        Sharing::Exclusive
+    };
+    let view = ImageView::new_default(
+        Image::new(
+            app.memory_allocator(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                extent,
+                format,
+                usage,
+                samples,
+                sharing,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    debug!(
+        "{:?} image view: {:?}, image: {:?}",
+        format,
+        view.handle(),
+        view.image().handle()
+    );
+    view
+}
+
+/// The images a frame is drawn into, which FSR then upscales.
+struct RenderTargets {
+    color: Attachment,
+    depth: Attachment,
+    motion_vector: Attachment,
+    /// How much FSR should trust this frame over its history, written by
+    /// the translucent pass.
+    reactive: Attachment,
+    /// Depth of the viewmodel pass, apart from the world's.
+    viewmodel_depth: Arc<ImageView>,
+}
+
+impl RenderTargets {
+    fn new(
+        app: &App,
+        extent: [u32; 3],
+        depth_mode: DepthMode,
+        samples: SampleCount,
+        queue_family_indices: &[u32],
+    ) -> Self {
+        let image = |format, usage| {
+            render_target(app, extent, format, usage, samples, queue_family_indices)
+        };
+        // With MSAA, resolved into a single-sampled image of its own
+        let attachment = |format, usage| Attachment {
+            image: image(format, usage),
+            resolve: (samples != SampleCount::Sample1).then(|| {
+                render_target(
+                    app,
+                    extent,
+                    format,
+                    usage,
+                    SampleCount::Sample1,
+                    queue_family_indices,
+                )
+            }),
+        };
+        Self {
+            // Also read as it is without anti-aliasing
+            color: attachment(
+                COLOR_FORMAT,
+                ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            ),
+            depth: attachment(
+                depth_format(depth_mode),
+                ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::SAMPLED,
+            ),
+            motion_vector: attachment(
+                Format::R16G16_SFLOAT,
+                ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            ),
+            reactive: attachment(
+                Format::R8_UNORM,
+                ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            ),
+            viewmodel_depth: image(Format::D16_UNORM, ImageUsage::DEPTH_STENCIL_ATTACHMENT),
+        }
+    }
+}
+
+/// How the rendered frame becomes the displayed one.
+enum FinalPass {
+    /// Upscaled and anti-aliased by FSR on the compute queue into `output`.
+    Fsr {
+        context: FsrContextVulkan,
+        output: Arc<ImageView>,
+    },
+    /// Anti-aliased at display resolution on the graphics queue.
+    Taa(TaaPass),
+    Fxaa(FxaaPass),
+    /// Shown as it was rendered.
+    Off,
+}
+
+impl FinalPass {
+    /// The jitter of the next frame's projection.
+    fn step_jitter(&mut self) -> Vector2<f32> {
+        match self {
+            FinalPass::Fsr { context, .. } => unsafe { context.step_jitter() },
+            FinalPass::Taa(taa) => taa.step_jitter(),
+            FinalPass::Fxaa(_) | FinalPass::Off => Vector2::new(0.0, 0.0),
+        }
+    }
+
+    /// Every image the pass may leave the frame in, given the color images
+    /// it is fed.
+    fn outputs(&self, scene_colors: &[Arc<ImageView>]) -> Vec<Arc<ImageView>> {
+        match self {
+            FinalPass::Fsr { output, .. } => vec![output.clone()],
+            FinalPass::Taa(taa) => taa.history().to_vec(),
+            FinalPass::Fxaa(fxaa) => vec![fxaa.output().clone()],
+            FinalPass::Off => scene_colors.to_vec(),
+        }
+    }
+}
+
+/// What a frame shows besides the world, and how much time it covers.
+pub struct FrameInput<'a> {
+    /// Real time the frame before took.
+    pub elapsed: Duration,
+    /// Simulated time since the frame before, for what moves with the
+    /// world.
+    pub delta: Duration,
+    /// Columns that came into and left render distance since the frame
+    /// before.
+    pub columns: &'a LoaderUpdate,
+    pub weather: &'a Weather,
+    pub renderables: &'a [(Entity, Transform, Renderable)],
+    pub particles: &'a [Particle],
+    /// The held item, drawn in first person only.
+    pub viewmodel: Option<&'a ViewModel>,
+    pub hud: &'a Hud,
+}
+
+/// What a frame cost. The GPU measurements are read once the GPU is done
+/// with them, so they are those of the frame drawn with the same frame in
+/// flight before.
+#[derive(Debug, Clone, Default)]
+pub struct FrameStatistics {
+    /// `None` until the query was read once.
+    pub draw: Option<DrawStatistics>,
+    /// Chunks the world renderer drew.
+    pub chunks: usize,
+    /// Bytes staged for upload to the GPU.
+    pub upload_bytes: u64,
+    /// GPU time of every pass, empty without `with_pass_timer`.
+    pub passes: Vec<(&'static str, Duration)>,
+}
+
+/// Draws the world into the swapchain of a window with the passes of the
+/// graphics settings, keeping the chunks on the GPU in step with the world.
+pub struct VoxelRenderer {
+    queue: Arc<Queue>,
+    compute_queue: Arc<Queue>,
+    ash_device: ash::Device,
+    settings: GraphicsSettings,
+    render_size: [u32; 2],
+    display_size: [u32; 2],
+    depth_mode: DepthMode,
+    render_targets: Vec<RenderTargets>,
+    /// Rebuilt as the world loads and changes, like the chunk buffers.
+    world_acceleration_structure: Option<WorldAccelerationStructure>,
+    ray_tracing_stages: ash::vk::PipelineStageFlags2,
+    ray_traced_shadows: bool,
+    light_shafts: bool,
+    ambient_occlusion: Option<AmbientOcclusionPass>,
+    ssr: Option<SsrPass>,
+    fog: Option<FogPass>,
+    underwater: UnderwaterPass,
+    final_pass: FinalPass,
+    motion_blur: Option<MotionBlurPass>,
+    encode_pass: EncodePass,
+    hi_z: HiZPyramid,
+    render_faces_pipeline: RenderFacesPipeline,
+    voxel_dda_renderer: Option<VoxelDdaRenderer>,
+    render_entities_pipeline: RenderEntitiesPipeline,
+    render_clouds_pipeline: Option<RenderCloudsPipeline>,
+    render_particles_pipeline: RenderParticlesPipeline,
+    render_viewmodel_pipeline: RenderViewModelPipeline,
+    render_hud_pipeline: RenderHudPipeline,
+    frames: FramesInFlight,
+    draw_statistics: DrawStatisticsQuery,
+    pass_timer: Option<PassTimer>,
+    previous_camera: Option<Camera>,
+    /// Frame whose depth the Hi-Z pyramid is built from.
+    previous_frame: Option<usize>,
+    _render_target_memory: Vec<TrackedAllocation>,
+    /// Host memory, but it lives as long as the FSR context's GPU
+    /// resources.
+    _fsr_memory: Option<TrackedAllocation>,
+}
+
+impl VoxelRenderer {
+    /// A renderer of `world` into the swapchain of `window`, with room for
+    /// the sections within the render distance of the settings.
+    pub fn new(
+        app: &App,
+        settings: &GraphicsSettings,
+        world: &mut World,
+        window: &VulkanoWindowRenderer,
+    ) -> Self {
+        let depth_mode = settings.depth_mode;
+        let render_distance = settings.render_distance;
+        let chunk_capacity = ChunkLoader::new(render_distance as i32, 0, 0).max_visible_columns()
+            * world.height.sections().len();
+        let queue = app.context.graphics_queue().clone();
+        let samples = {
+            let properties = app.context.device().physical_device().properties();
+            let supported = properties.framebuffer_color_sample_counts
+                & properties.framebuffer_depth_sample_counts;
+            let samples = SampleCount::try_from(settings.msaa.samples()).unwrap();
+            if supported.contains_enum(samples) {
+                samples
+            } else {
+                warn!(
+                    "{:?} MSAA is not supported, rendering without",
+                    settings.msaa
+                );
+                SampleCount::Sample1
+            }
+        };
+
+        // The swapchain is recreated to the size of the window before the
+        // next image is acquired
+        let display_size = window.window_size().map(|size| size as u32);
+        let fsr_context = match settings.anti_aliasing {
+            AntiAliasing::Fsr => {
+                let context = unsafe {
+                    FsrContextVulkan::new(
+                        app.context.device(),
+                        settings.fsr.quality.render_size(display_size),
+                        display_size,
+                        depth_mode,
+                        settings.fsr.clone(),
+                    )
+                };
+                match &context {
+                    Some(_) => info!("FsrContextVulkan created"),
+                    None => warn!("FSR is not available, falling back to TAA"),
+                }
+                context
+            }
+            AntiAliasing::Taa | AntiAliasing::Fxaa | AntiAliasing::Off => None,
+        };
+        // Only FSR upscales, the rest render at display resolution
+        let render_size = fsr_context
+            .as_ref()
+            .map_or(display_size, |context| context.render_size());
+        let render_size_extent = [render_size[0], render_size[1], 1];
+
+        println!("Render size: {:?}", render_size);
+        println!("Display size: {:?}", display_size);
+
+        // FSR runs on the compute queue while the next frame is drawn on the
+        // graphics queue. Each frame in flight gets its own FSR inputs so the
+        // draw doesn't overwrite the ones still being upscaled.
+        let compute_queue = app.context.compute_queue().clone();
+        let mut queue_family_indices = vec![queue.queue_family_index()];
+        if compute_queue.queue_family_index() != queue.queue_family_index() {
+            queue_family_indices.push(compute_queue.queue_family_index());
+        }
+        info!(
+            "FSR queue family: {}, graphics queue family: {}",
+            compute_queue.queue_family_index(),
+            queue.queue_family_index()
+        );
+
+        let swapchain_format = window.swapchain_format();
+        let render_targets = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                RenderTargets::new(
+                    app,
+                    render_size_extent,
+                    depth_mode,
+                    samples,
+                    &queue_family_indices,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let resolved_colors = render_targets
+            .iter()
+            .map(|targets| targets.color.resolved().clone())
+            .collect::<Vec<_>>();
+        let resolved_depths = render_targets
+            .iter()
+            .map(|targets| targets.depth.resolved().clone())
+            .collect::<Vec<_>>();
+        let resolved_motion_vectors = render_targets
+            .iter()
+            .map(|targets| targets.motion_vector.resolved().clone())
+            .collect::<Vec<_>>();
+        let ray_traced_shadows = settings.shadows == Shadows::RayTraced && app.ray_query;
+        let ray_traced_ambient_occlusion =
+            settings.ambient_occlusion == AmbientOcclusion::RayTraced && app.ray_query;
+        let light_shafts = settings.fog.enabled && settings.fog.light_shafts && app.ray_query;
+        if settings.ray_traced() && !app.ray_query {
+            warn!("No device supports ray queries, the ray traced options are off");
+        }
+        let world_acceleration_structure =
+            (ray_traced_shadows || ray_traced_ambient_occlusion || light_shafts).then(|| {
+                WorldAccelerationStructure::new(
+                    app,
+                    &world.block_registry,
+                    world.events.subscribe(),
+                )
+            });
+        let mut ray_tracing_stages = ash::vk::PipelineStageFlags2::empty();
+        if ray_traced_shadows {
+            ray_tracing_stages |= ash::vk::PipelineStageFlags2::FRAGMENT_SHADER;
+        }
+        if ray_traced_ambient_occlusion || light_shafts {
+            ray_tracing_stages |= ash::vk::PipelineStageFlags2::COMPUTE_SHADER;
+        }
+
+        // Per frame, an image the rendered frame is processed into
+        let frame_images = || {
+            (0..FRAMES_IN_FLIGHT)
+                .map(|_| {
+                    render_target(
+                        app,
+                        render_size_extent,
+                        COLOR_FORMAT,
+                        ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                        SampleCount::Sample1,
+                        &queue_family_indices,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        // Darkens the rendered frame before anything else reads it
+        let ambient_occlusion = ray_traced_ambient_occlusion.then(|| {
+            AmbientOcclusionPass::new(
+                app,
+                &resolved_colors,
+                &resolved_depths,
+                &resolved_motion_vectors,
+                frame_images(),
+                depth_mode,
+            )
+        });
+        let lit_colors = match &ambient_occlusion {
+            Some(ambient_occlusion) => ambient_occlusion.outputs().to_vec(),
+            None => resolved_colors,
+        };
+        // Reflects the frame into images of its own, which are then
+        // anti-aliased in its place
+        let ssr = settings.reflections.then(|| {
+            SsrPass::new(
+                app,
+                &lit_colors,
+                &resolved_depths,
+                frame_images(),
+                depth_mode,
+            )
+        });
+        let reflected_colors = match &ssr {
+            Some(ssr) => ssr.outputs().to_vec(),
+            None => lit_colors,
+        };
+        // Fogs the frame last, so what is reflected is fogged only once
+        let fog = settings.fog.enabled.then(|| {
+            FogPass::new(
+                app,
+                &reflected_colors,
+                &resolved_depths,
+                frame_images(),
+                depth_mode,
+                light_shafts,
+            )
+        });
+        let scene_colors = match &fog {
+            Some(fog) => fog.outputs().to_vec(),
+            None => reflected_colors,
+        };
+        // Only runs while the camera is in water, so the passes after it read
+        // either the scene colors or its outputs, which are taken as sources
+        // after them
+        let underwater = UnderwaterPass::new(
+            app,
+            &scene_colors,
+            &resolved_depths,
+            frame_images(),
+            depth_mode,
+        );
+        let source_colors = scene_colors
+            .iter()
+            .chain(underwater.outputs())
+            .cloned()
+            .collect::<Vec<_>>();
+        let source_motion_vectors = resolved_motion_vectors.repeat(2);
+        let final_pass = match (fsr_context, settings.anti_aliasing) {
+            (Some(context), _) => FinalPass::Fsr {
+                context,
+                output: render_target(
+                    app,
+                    display_size_extent,
+                    COLOR_FORMAT,
+                    ImageUsage::COLOR_ATTACHMENT | ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                    SampleCount::Sample1,
+                    &queue_family_indices,
+                ),
+            },
+            (None, AntiAliasing::Fxaa) => {
+                FinalPass::Fxaa(FxaaPass::new(app, &source_colors, COLOR_FORMAT))
+            }
+            (None, AntiAliasing::Off) => FinalPass::Off,
+            // Also where FSR is not available
+            (None, _) => FinalPass::Taa(TaaPass::new(
+                app,
+                &source_colors,
+                &source_motion_vectors,
+                COLOR_FORMAT,
+            )),
+        };
+        // Blurs whichever image the final pass leaves, at display resolution
+        let motion_blur = settings.motion_blur.enabled.then(|| {
+            MotionBlurPass::new(
+                app,
+                &final_pass.outputs(&source_colors),
+                &resolved_motion_vectors,
+                COLOR_FORMAT,
+            )
+        });
+        let encode_pass = EncodePass::new(
+            app,
+            PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(swapchain_format)],
+                ..Default::default()
+            },
+            &match &motion_blur {
+                Some(motion_blur) => vec![motion_blur.output().clone()],
+                None => final_pass.outputs(&source_colors),
+            },
+            &match &settings.color_lut {
+                Some(path) => Lut::load(path).unwrap_or_else(|err| {
+                    warn!("Failed to load the color LUT {:?}: {}", path, err);
+                    Lut::neutral()
+                }),
+                None => Lut::neutral(),
+            },
+            settings.gamma_test,
+        );
+
+        // Occlusion culling tests against the previous frame's depth
+        let hi_z = HiZPyramid::new(app, &resolved_depths, depth_mode);
+
+        // Upscaled textures keep the sharpness of the display resolution
+        let block_sampler = block_sampler(
+            app.context.device().clone(),
+            mip_lod_bias(render_size, display_size),
+        );
+        let rendering_info = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(COLOR_FORMAT), Some(Format::R16G16_SFLOAT)],
+            depth_attachment_format: Some(depth_format(depth_mode)),
+            ..Default::default()
+        };
+        let voxel_dda = settings.world_renderer == WorldRenderer::VoxelDda;
+        // With the DDA renderer, the faces pipeline only lends its block
+        // textures to the other pipelines, so it gets no chunks
+        let render_faces_pipeline = RenderFacesPipeline::new(
+            app,
+            queue.clone(),
+            rendering_info.clone(),
+            samples,
+            &world.block_registry,
+            if voxel_dda {
+                mpsc::channel().1
+            } else {
+                world.events.subscribe()
+            },
+            if voxel_dda { 1 } else { chunk_capacity as u64 },
+            &hi_z,
+            block_sampler.clone(),
+            ray_traced_shadows,
+            settings.depth_prepass,
+            settings.world_edges,
+            render_distance,
+        );
+        let voxel_dda_renderer = voxel_dda.then(|| {
+            VoxelDdaRenderer::new(
+                app,
+                rendering_info.clone(),
+                samples,
+                world,
+                render_distance,
+                render_faces_pipeline.block_textures().clone(),
+                block_sampler.clone(),
+                render_size,
+                depth_mode,
+                settings.voxel_storage,
+            )
+        });
+        let render_entities_pipeline = RenderEntitiesPipeline::new(
+            app,
+            rendering_info.clone(),
+            samples,
+            &world.block_registry,
+            render_faces_pipeline.block_textures().clone(),
+            block_sampler.clone(),
+            depth_mode,
+        );
+        let render_clouds_pipeline = settings
+            .clouds
+            .then(|| RenderCloudsPipeline::new(app, rendering_info.clone(), samples, depth_mode));
+        let translucent_rendering_info = PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(COLOR_FORMAT), Some(Format::R8_UNORM)],
+            depth_attachment_format: Some(depth_format(depth_mode)),
+            ..Default::default()
+        };
+        let render_particles_pipeline = RenderParticlesPipeline::new(
+            app,
+            translucent_rendering_info,
+            samples,
+            render_faces_pipeline.block_textures().clone(),
+            block_sampler.clone(),
+            depth_mode,
+        );
+        // The viewmodel has its own projection and depth, which are standard
+        let render_viewmodel_pipeline = RenderViewModelPipeline::new(
+            app,
+            PipelineRenderingCreateInfo {
+                depth_attachment_format: Some(Format::D16_UNORM),
+                ..rendering_info
+            },
+            samples,
+            &world.block_registry,
+            render_faces_pipeline.block_textures().clone(),
+            block_sampler.clone(),
+        );
+        // The HUD is drawn straight onto the swapchain image, after upscaling
+        let render_hud_pipeline = RenderHudPipeline::new(
+            app,
+            PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(swapchain_format)],
+                ..Default::default()
+            },
+            render_faces_pipeline.block_textures().clone(),
+        );
+
+        let ash_device = unsafe {
+            ash::Device::load(
+                &app.context.instance().fns().v1_0,
+                app.context.device().handle(),
+            )
+        };
+
+        let memory_tracker = &app.memory_tracker;
+        let render_target_memory = render_targets
+            .iter()
+            .flat_map(|targets| {
+                [
+                    &targets.color,
+                    &targets.depth,
+                    &targets.motion_vector,
+                    &targets.reactive,
+                ]
+                .into_iter()
+                .flat_map(|attachment| [Some(&attachment.image), attachment.resolve.as_ref()])
+                .flatten()
+                .chain([&targets.viewmodel_depth])
+            })
+            .chain(match &final_pass {
+                FinalPass::Fsr { output, .. } => Some(output),
+                _ => None,
+            })
+            .chain(
+                ambient_occlusion
+                    .iter()
+                    .flat_map(|ambient_occlusion| ambient_occlusion.outputs()),
+            )
+            .chain(ssr.iter().flat_map(|ssr| ssr.outputs()))
+            .chain(fog.iter().flat_map(|fog| fog.outputs()))
+            .chain(underwater.outputs())
+            .map(|view| memory_tracker.track_image(MemoryCategory::RenderTargets, view.image()))
+            .collect::<Vec<_>>();
+        let fsr_memory = match &final_pass {
+            FinalPass::Fsr { context, .. } => Some(memory_tracker.track(
+                MemoryCategory::FsrScratch,
+                context.scratch_memory_size() as u64,
+            )),
+            _ => None,
+        };
+
+        Self {
+            queue,
+            compute_queue,
+            ash_device,
+            settings: settings.clone(),
+            render_size,
+            display_size,
+            depth_mode,
+            render_targets,
+            world_acceleration_structure,
+            ray_tracing_stages,
+            ray_traced_shadows,
+            light_shafts,
+            ambient_occlusion,
+            ssr,
+            fog,
+            underwater,
+            final_pass,
+            motion_blur,
+            encode_pass,
+            hi_z,
+            render_faces_pipeline,
+            voxel_dda_renderer,
+            render_entities_pipeline,
+            render_clouds_pipeline,
+            render_particles_pipeline,
+            render_viewmodel_pipeline,
+            render_hud_pipeline,
+            frames: FramesInFlight::new(app.context.device(), FRAMES_IN_FLIGHT),
+            draw_statistics: DrawStatisticsQuery::new(
+                app.context.device().clone(),
+                FRAMES_IN_FLIGHT,
+            ),
+            pass_timer: None,
+            previous_camera: None,
+            previous_frame: None,
+            _render_target_memory: render_target_memory,
+            _fsr_memory: fsr_memory,
+        }
+    }
+
+    /// Times the GPU work of every pass for `FrameStatistics::passes`, if
+    /// the device supports timestamps.
+    pub fn with_pass_timer(mut self, app: &App) -> Self {
+        self.pass_timer = PassTimer::new(app.context.device().clone(), FRAMES_IN_FLIGHT);
+        self
+    }
+
+    /// Size of the swapchain images, which the HUD is laid out in.
+    pub fn display_size(&self) -> [u32; 2] {
+        self.display_size
+    }
+
+    /// Changes the FSR settings, returning whether FSR is used.
+    pub fn set_fsr_settings(&mut self, settings: FsrSettings) -> bool {
+        self.settings.fsr = settings.clone();
+        match &mut self.final_pass {
+            FinalPass::Fsr { context, .. } => {
+                // The context may be created again, so nothing may still
+                // use it
+                unsafe {
+                    self.ash_device.device_wait_idle().unwrap();
+                    context.set_settings(settings);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Draws a frame of `world` as `camera` sees it into the next swapchain
+    /// image of `window` and presents it, after uploading the columns that
+    /// came into render distance and the edits made since the frame before.
+    /// The camera is jittered for the final pass here.
+    pub fn render(
+        &mut self,
+        window: &mut VulkanoWindowRenderer,
+        world: &World,
+        camera: &Camera,
+        input: &FrameInput,
+    ) -> FrameStatistics {
+        let frame = self.frames.next_frame();
+        // Before the statistics query is reset for this frame
+        let draw_statistics = self.draw_statistics.statistics(
+            frame.index(),
+            (self.render_size[0] * self.render_size[1]) as f32,
+        );
+        let passes = self
+            .pass_timer
+            .as_mut()
+            .and_then(|timer| timer.timings(frame.index()))
+            .unwrap_or_default();
+        let RenderTargets {
+            color: color_image,
+            depth: depth_image,
+            motion_vector: motion_vector_image,
+            reactive: reactive_image,
+            viewmodel_depth: viewmodel_depth_image,
+        } = &self.render_targets[frame.index()];
+        let before = window.acquire(None, |_| {}).unwrap();
+
+        let camera = &Camera {
+            jitter: self.final_pass.step_jitter(),
+            ..camera.clone()
+        };
+        let previous_camera = self.previous_camera.replace(camera.clone());
+        let previous_camera = previous_camera.as_ref().unwrap_or(camera);
+        let previous_frame = self.previous_frame.replace(frame.index());
+        let camera_block = [
+            camera.position.x.floor() as i32,
+            camera.position.y.floor() as i32,
+            camera.position.z.floor() as i32,
+        ];
+        let elapsed = input.elapsed;
+        let delta = input.delta;
+
+        self.render_entities_pipeline
+            .update(frame.index(), input.renderables);
+        if let Some(render_clouds_pipeline) = &mut self.render_clouds_pipeline {
+            render_clouds_pipeline.update(delta.as_secs_f32());
+        }
+        self.render_particles_pipeline
+            .update(frame.index(), input.particles, camera.position);
+        if let Some(viewmodel) = input.viewmodel {
+            self.render_viewmodel_pipeline
+                .update(frame.index(), viewmodel, camera);
+        }
+        let screen_size = [self.display_size[0] as f32, self.display_size[1] as f32];
+        self.render_hud_pipeline
+            .update(frame.index(), input.hud.quads());
+        let viewport = Viewport {
+            extent: [self.render_size[0] as f32, self.render_size[1] as f32],
+            ..Default::default()
+        };
+
+        debug!(
+            "Swapchain image view: {:?}, image: {:?}",
+            window.swapchain_image_view().handle(),
+            window.swapchain_image_view().image().handle()
+        );
+
+        let mut graph = FrameGraph::new(
+            frame,
+            self.queue.clone(),
+            self.compute_queue.clone(),
+            before,
+        );
+        if let Some(timer) = &mut self.pass_timer {
+            graph = graph.with_timer(timer);
+        }
+        graph.import("color", color_image.resolved().clone());
+        graph.import("depth", depth_image.resolved().clone());
+        graph.import("motion_vectors", motion_vector_image.resolved().clone());
+        graph.import("reactive", reactive_image.resolved().clone());
+        graph.import("swapchain", window.swapchain_image_view());
+
+        let render_faces_pipeline = &mut self.render_faces_pipeline;
+        let voxel_dda_renderer = &mut self.voxel_dda_renderer;
+        let draw_statistics_query = &self.draw_statistics;
+        graph.pass(
+            "world",
+            QueueKind::Graphics,
+            &[
+                ("color", Usage::ColorAttachment),
+                ("depth", Usage::DepthAttachment),
+                ("motion_vectors", Usage::ColorAttachment),
+                ("reactive", Usage::ColorAttachment),
+            ],
+            |builder| {
+                match voxel_dda_renderer {
+                    Some(voxel_dda_renderer) => voxel_dda_renderer.update_chunks(
+                        builder,
+                        world,
+                        &input.columns.entered,
+                        &input.columns.left,
+                    ),
+                    None => {
+                        render_faces_pipeline.update_chunks(
+                            builder,
+                            world,
+                            &input.columns.entered,
+                            &input.columns.left,
+                        );
+                        render_faces_pipeline.update_visibility(builder, camera_block);
+                    }
+                }
+                if let Some(acceleration_structure) = &mut self.world_acceleration_structure {
+                    if acceleration_structure.update(builder, world, self.ray_tracing_stages) {
+                        let top_level = acceleration_structure.top_level();
+                        if self.ray_traced_shadows {
+                            render_faces_pipeline.set_acceleration_structure(top_level.clone());
+                        }
+                        if let Some(ambient_occlusion) = &mut self.ambient_occlusion {
+                            ambient_occlusion.set_acceleration_structure(top_level.clone());
+                        }
+                        if self.light_shafts {
+                            // Light shafts are only traced in fog
+                            self.fog
+                                .as_mut()
+                                .unwrap()
+                                .set_acceleration_structure(top_level.clone());
+                        }
+                    }
+                }
+                if let Some(previous_frame) = previous_frame {
+                    self.hi_z.build(builder, previous_frame);
+                }
+                draw_statistics_query.reset(builder, frame.index());
+                match voxel_dda_renderer {
+                    Some(voxel_dda_renderer) => {
+                        voxel_dda_renderer.trace(builder, frame.index(), previous_camera, camera)
+                    }
+                    None => render_faces_pipeline.cull_blocks(builder, camera),
+                }
+
+                draw(
+                    builder,
+                    color_image.image.clone(),
+                    motion_vector_image.image.clone(),
+                    depth_image,
+                    self.depth_mode,
+                    viewport.clone(),
+                    |builder| {
+                        draw_statistics_query.measure(builder, frame.index(), |builder| {
+                            match voxel_dda_renderer {
+                                Some(voxel_dda_renderer) => {
+                                    voxel_dda_renderer.render(builder, frame.index())
+                                }
+                                None => render_faces_pipeline.render_cube_faces(
+                                    builder,
+                                    previous_camera,
+                                    camera,
+                                    previous_frame.is_some(),
+                                    input.weather,
+                                ),
+                            }
+                        });
+                        self.render_entities_pipeline.render(
+                            builder,
+                            frame.index(),
+                            previous_camera,
+                            camera,
+                        );
+                        if let Some(render_clouds_pipeline) = &self.render_clouds_pipeline {
+                            render_clouds_pipeline.render(
+                                builder,
+                                previous_camera,
+                                camera,
+                                input.weather,
+                            );
+                        }
+                    },
+                );
+                draw_translucent(
+                    builder,
+                    color_image.image.clone(),
+                    reactive_image,
+                    depth_image.image.clone(),
+                    viewport.clone(),
+                    |builder| {
+                        self.render_particles_pipeline
+                            .render(builder, frame.index(), camera)
+                    },
+                );
+                draw_viewmodel(
+                    builder,
+                    color_image,
+                    motion_vector_image,
+                    viewmodel_depth_image.clone(),
+                    viewport,
+                    |builder| {
+                        if input.viewmodel.is_some() {
+                            self.render_viewmodel_pipeline
+                                .render(builder, frame.index(), camera);
+                        }
+                    },
+                );
+            },
+        );
+
+        let (mut lit_color, mut lit_image) = ("color", color_image.resolved().clone());
+        if let Some(ambient_occlusion) = &mut self.ambient_occlusion {
+            graph.import(
+                "ambient_occlusion",
+                ambient_occlusion.outputs()[frame.index()].clone(),
+            );
+            lit_image = graph.pass(
+                "ambient_occlusion",
+                QueueKind::Graphics,
+                &[
+                    ("color", Usage::Sampled),
+                    ("depth", Usage::Sampled),
+                    ("ambient_occlusion", Usage::Storage),
+                ],
+                |builder| ambient_occlusion.apply(builder, frame.index(), camera),
+            );
+            lit_color = "ambient_occlusion";
+        }
+        let (mut scene_color, mut scene_image) = (lit_color, lit_image);
+        if let Some(ssr) = &self.ssr {
+            graph.import("reflections", ssr.outputs()[frame.index()].clone());
+            scene_image = graph.pass(
+                "reflections",
+                QueueKind::Graphics,
+                &[
+                    (lit_color, Usage::Sampled),
+                    ("depth", Usage::Sampled),
+                    ("reflections", Usage::Storage),
+                ],
+                |builder| ssr.apply(builder, frame.index(), camera),
+            );
+            scene_color = "reflections";
+        }
+        if let Some(fog) = &mut self.fog {
+            graph.import("fog", fog.outputs()[frame.index()].clone());
+            scene_image = graph.pass(
+                "fog",
+                QueueKind::Graphics,
+                &[
+                    (scene_color, Usage::Sampled),
+                    ("depth", Usage::Sampled),
+                    ("fog", Usage::Storage),
+                ],
+                |builder| fog.apply(builder, frame.index(), camera, &self.settings.fog),
+            );
+            scene_color = "fog";
+        }
+        let mut source = frame.index();
+        if world.block_registry.block_types[world[camera_block]].base_name() == WATER_BLOCK {
+            let underwater = &mut self.underwater;
+            graph.import("underwater", underwater.outputs()[frame.index()].clone());
+            scene_image = graph.pass(
+                "underwater",
+                QueueKind::Graphics,
+                &[
+                    (scene_color, Usage::Sampled),
+                    ("depth", Usage::Sampled),
+                    ("underwater", Usage::Storage),
+                ],
+                |builder| underwater.apply(builder, frame.index(), camera, delta.as_secs_f32()),
+            );
+            scene_color = "underwater";
+            source += FRAMES_IN_FLIGHT;
+        }
+
+        let (output, output_image) = match &mut self.final_pass {
+            FinalPass::Fsr { context, output } => {
+                graph.import("upscaled", output.clone());
+                graph.external_pass(
+                    "fsr",
+                    QueueKind::Compute,
+                    &[
+                        (scene_color, Usage::Sampled),
+                        ("depth", Usage::Sampled),
+                        ("motion_vectors", Usage::Sampled),
+                        ("reactive", Usage::Sampled),
+                        ("upscaled", Usage::Storage),
+                    ],
+                    |builder| {
+                        debug!("fsr_command_buffer: {:?}", builder.raw().handle());
+                        unsafe {
+                            context.dispatch(
+                                &builder.raw(),
+                                &scene_image,
+                                depth_image.resolved(),
+                                motion_vector_image.resolved(),
+                                reactive_image.resolved(),
+                                output,
+                                elapsed.as_millis() as f32,
+                                camera,
+                            )
+                        }
+                    },
+                );
+                ("upscaled", output.clone())
+            }
+            FinalPass::Taa(taa) => {
+                graph.import("anti_aliased", taa.next_output().clone());
+                let output_image = graph.pass(
+                    "taa",
+                    QueueKind::Graphics,
+                    &[
+                        (scene_color, Usage::Sampled),
+                        ("motion_vectors", Usage::Sampled),
+                        ("anti_aliased", Usage::Storage),
+                    ],
+                    |builder| taa.resolve(builder, source),
+                );
+                ("anti_aliased", output_image)
+            }
+            FinalPass::Fxaa(fxaa) => {
+                graph.import("anti_aliased", fxaa.output().clone());
+                let output_image = graph.pass(
+                    "fxaa",
+                    QueueKind::Graphics,
+                    &[
+                        (scene_color, Usage::Sampled),
+                        ("anti_aliased", Usage::Storage),
+                    ],
+                    |builder| fxaa.apply(builder, source),
+                );
+                ("anti_aliased", output_image)
+            }
+            FinalPass::Off => (scene_color, scene_image),
+        };
+
+        // The swapchain image belongs to the graphics queue
+        let mut present_uses = vec![
+            (output, Usage::Sampled),
+            ("swapchain", Usage::ColorAttachment),
+        ];
+        if let Some(motion_blur) = &self.motion_blur {
+            graph.import("motion_blur", motion_blur.output().clone());
+            present_uses.extend([
+                ("motion_vectors", Usage::Sampled),
+                ("motion_blur", Usage::Storage),
+            ]);
+        }
+        graph.pass("present", QueueKind::Graphics, &present_uses, |builder| {
+            let output_image = match &self.motion_blur {
+                Some(motion_blur) => motion_blur.apply(
+                    builder,
+                    &output_image,
+                    frame.index(),
+                    &self.settings.motion_blur,
+                ),
+                None => output_image,
+            };
+            self.encode_pass.upload_lut(builder);
+            self.render_hud_pipeline.upload_glyphs(
+                builder,
+                frame.index(),
+                input.hud.text_renderer().atlas(),
+            );
+            draw_overlay(builder, window.swapchain_image_view(), |builder| {
+                self.encode_pass.draw(builder, &output_image);
+                self.render_hud_pipeline
+                    .render(builder, frame.index(), screen_size);
+            });
+        });
+
+        let after = frame.submit(graph.finish());
+        let mut upload_bytes = self.render_faces_pipeline.submit_uploads(after.clone());
+        if let Some(voxel_dda_renderer) = &mut self.voxel_dda_renderer {
+            upload_bytes += voxel_dda_renderer.submit_uploads(after.clone());
+        }
+        let chunks = match &self.voxel_dda_renderer {
+            Some(voxel_dda_renderer) => voxel_dda_renderer.chunk_count(),
+            None => self.render_faces_pipeline.visible_chunk_count() as usize,
+        };
+        // Don't wait here; `frames` waits before a frame's resources are reused
+        window.present(after.boxed(), false);
+        FrameStatistics {
+            draw: draw_statistics,
+            chunks,
+            upload_bytes,
+            passes,
+        }
+    }
+}
+
+impl Drop for VoxelRenderer {
+    /// Waits for the frames in flight, whose images and FSR context are
+    /// about to go away with the renderer.
+    fn drop(&mut self) {
+        unsafe {
+            self.ash_device.device_wait_idle().unwrap();
+        }
+    }
+}
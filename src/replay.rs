@@ -0,0 +1,171 @@
+//! Recordings of the input of a game, and playing them back. A recording
+//! holds the input of every step of the simulation along with the time the
+//! step took, so playing it back steps the simulation as it was played, for
+//! reproducing bugs and for benchmarks that do the same every run. Both run
+//! against the world generated from the seed, without the saved one.
+//!
+//! A recording is a header line followed by a line per step, all JSON, and
+//! is written out after every step so a crash keeps what led to it.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use winit::{event::MouseButton, keyboard::KeyCode};
+
+/// Version of the recordings written now.
+const REPLAY_VERSION: u32 = 1;
+
+/// Input from the window the game reacts to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Input {
+    MousePressed(MouseButton),
+    /// Scrolled by lines, up being positive.
+    Scroll(f32),
+    Key {
+        code: KeyCode,
+        pressed: bool,
+        /// What the key types, if anything.
+        text: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    seed: u64,
+}
+
+/// The input of a step of the simulation, in the order it came in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// Time since the step before.
+    pub elapsed: Duration,
+    pub inputs: Vec<Input>,
+}
+
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Starts a recording at `path` of a game in the world of `seed`.
+    pub fn create(path: impl AsRef<Path>, seed: u64) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = Header {
+            version: REPLAY_VERSION,
+            seed,
+        };
+        writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+        Ok(Self { writer })
+    }
+
+    /// Adds the next frame.
+    pub fn record(&mut self, frame: &RecordedFrame) -> io::Result<()> {
+        writeln!(self.writer, "{}", serde_json::to_string(frame)?)?;
+        self.writer.flush()
+    }
+}
+
+/// The frames of a recording, to play back in order.
+pub struct Player {
+    frames: std::vec::IntoIter<RecordedFrame>,
+}
+
+impl Player {
+    /// Reads the recording at `path`, which has to be of the world of
+    /// `seed`. A frame cut off by a crash while recording ends it.
+    pub fn open(path: impl AsRef<Path>, seed: u64) -> io::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: Header = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Err(invalid_data("empty recording".to_string())),
+        };
+        if header.version != REPLAY_VERSION {
+            return Err(invalid_data(format!(
+                "recording of version {}, expected {}",
+                header.version, REPLAY_VERSION
+            )));
+        }
+        if header.seed != seed {
+            return Err(invalid_data(format!(
+                "recording of the world with seed {}, not {}",
+                header.seed, seed
+            )));
+        }
+        let mut frames = Vec::new();
+        for line in lines {
+            match serde_json::from_str(&line?) {
+                Ok(frame) => frames.push(frame),
+                Err(_) => break,
+            }
+        }
+        Ok(Self {
+            frames: frames.into_iter(),
+        })
+    }
+
+    /// The next frame, or `None` once all were played.
+    pub fn next_frame(&mut self) -> Option<RecordedFrame> {
+        self.frames.next()
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_replay() {
+        let path = std::env::temp_dir().join(format!("block-world-replay-{}", std::process::id()));
+        let frames = [
+            RecordedFrame {
+                elapsed: Duration::from_micros(16_700),
+                inputs: vec![
+                    Input::Key {
+                        code: KeyCode::Slash,
+                        pressed: true,
+                        text: Some("/".to_string()),
+                    },
+                    Input::Scroll(-1.0),
+                ],
+            },
+            RecordedFrame {
+                elapsed: Duration::from_millis(20),
+                inputs: vec![Input::MousePressed(MouseButton::Left)],
+            },
+        ];
+        let mut recorder = Recorder::create(&path, 7).unwrap();
+        for frame in &frames {
+            recorder.record(frame).unwrap();
+        }
+        drop(recorder);
+
+        let mut player = Player::open(&path, 7).unwrap();
+        assert_eq!(player.next_frame().as_ref(), Some(&frames[0]));
+        assert_eq!(player.next_frame().as_ref(), Some(&frames[1]));
+        assert_eq!(player.next_frame(), None);
+        assert_eq!(
+            Player::open(&path, 8).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // A crash in the middle of writing the second frame
+        let recording = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &recording[..recording.len() - 10]).unwrap();
+        let mut player = Player::open(&path, 7).unwrap();
+        assert_eq!(player.next_frame().as_ref(), Some(&frames[0]));
+        assert_eq!(player.next_frame(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+use std::{collections::HashSet, fs, io, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    texture::TextureRegistry,
+    types::{
+        BlockRotation, BlockTextures, BlockType, Direction, Material, Orientation, Shape, Tint,
+        MAX_FLUID_LEVEL, UNKNOWN_BLOCK,
+    },
+};
+
+/// File the block definitions are read from at startup.
+pub const BLOCK_DEFINITIONS: &str = "blocks.json";
+/// Definitions built into the binary, for registries made without the file.
+const BUILTIN_BLOCK_DEFINITIONS: &str = include_str!("../../blocks.json");
+/// Block types the world generator can't do without.
+const REQUIRED_BLOCKS: [&str; 4] = ["stone", "grass", "log", "leaves"];
+pub const MAX_LIGHT_LEVEL: u8 = 15;
+/// States of the variants of fluids, by level.
+const FLUID_LEVELS: [&str; MAX_FLUID_LEVEL as usize + 1] = [
+    "level=0", "level=1", "level=2", "level=3", "level=4", "level=5", "level=6", "level=7",
+];
+
+/// Texture names of the faces of a block. The most specific one wins: a
+/// direction over `end` (up and down) or `side` (the others) over `all`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaceTextures {
+    pub all: Option<String>,
+    pub end: Option<String>,
+    pub side: Option<String>,
+    pub up: Option<String>,
+    pub down: Option<String>,
+    pub north: Option<String>,
+    pub south: Option<String>,
+    pub east: Option<String>,
+    pub west: Option<String>,
+}
+
+impl FaceTextures {
+    pub fn get(&self, direction: Direction) -> Option<&str> {
+        let (face, group) = match direction {
+            Direction::Up => (&self.up, &self.end),
+            Direction::Down => (&self.down, &self.end),
+            Direction::North => (&self.north, &self.side),
+            Direction::South => (&self.south, &self.side),
+            Direction::East => (&self.east, &self.side),
+            Direction::West => (&self.west, &self.side),
+        };
+        face.as_ref()
+            .or(group.as_ref())
+            .or(self.all.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// One entry of a block definitions file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockDefinition {
+    pub name: String,
+    pub textures: FaceTextures,
+    #[serde(default)]
+    pub transparent: bool,
+    #[serde(default)]
+    pub tint: Tint,
+    /// Light the block emits, up to `MAX_LIGHT_LEVEL`.
+    #[serde(default)]
+    pub light_level: u8,
+    /// How long the block takes to break, relative to other blocks.
+    #[serde(default = "default_hardness")]
+    pub hardness: f32,
+    /// A fluid is defined as its source, `{"Fluid": 0}`, which registers a
+    /// variant for every level of flowing fluid.
+    #[serde(default)]
+    pub shape: Shape,
+    /// Rotated variants to register, with `textures` as the unrotated ones.
+    #[serde(default)]
+    pub orientation: Orientation,
+    /// How much of the surroundings the block mirrors, from 0 to 1, with
+    /// screen-space reflections on.
+    #[serde(default)]
+    pub reflectivity: f32,
+    /// Surface parameters for shading; unset ones are those of a rough,
+    /// opaque dielectric.
+    #[serde(default)]
+    pub material: Material,
+    /// Whether the block falls through air and fluids below it, like sand.
+    #[serde(default)]
+    pub gravity: bool,
+}
+
+fn default_hardness() -> f32 {
+    1.0
+}
+
+/// Parses the JSON array of block definitions in `json` and checks it. Air
+/// and the unknown block are built in and can't be defined.
+pub fn parse_definitions(json: &str) -> io::Result<Vec<BlockDefinition>> {
+    let definitions: Vec<BlockDefinition> = serde_json::from_str(json)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    let mut names = HashSet::new();
+    for definition in &definitions {
+        let name = &definition.name;
+        if name == "air" || name == UNKNOWN_BLOCK {
+            return invalid(format!("block {:?} is built in", name));
+        }
+        if name.contains('[') {
+            return invalid(format!("block name {:?} contains a state", name));
+        }
+        if !names.insert(name.as_str()) {
+            return invalid(format!("block {:?} is defined twice", name));
+        }
+        if let Some(direction) = Direction::ALL
+            .into_iter()
+            .find(|&direction| definition.textures.get(direction).is_none())
+        {
+            return invalid(format!(
+                "block {:?} has no texture for its {:?} face",
+                name, direction
+            ));
+        }
+        if definition.light_level > MAX_LIGHT_LEVEL {
+            return invalid(format!(
+                "light level {} of block {:?} is above {}",
+                definition.light_level, name, MAX_LIGHT_LEVEL
+            ));
+        }
+        if definition.hardness.is_nan() || definition.hardness < 0.0 {
+            return invalid(format!("block {:?} has an invalid hardness", name));
+        }
+        if !(0.0..=1.0).contains(&definition.reflectivity) {
+            return invalid(format!("block {:?} has an invalid reflectivity", name));
+        }
+        if let Some(parameter) = definition.material.invalid_parameter() {
+            return invalid(format!("block {:?} has an invalid {}", name, parameter));
+        }
+        if let Shape::Fluid(level) = definition.shape {
+            if level != 0 || definition.orientation != Orientation::None {
+                return invalid(format!(
+                    "fluid {:?} must be defined as an unoriented source",
+                    name
+                ));
+            }
+        }
+    }
+    if let Some(name) = REQUIRED_BLOCKS
+        .into_iter()
+        .find(|name| !names.contains(name))
+    {
+        return invalid(format!("required block {:?} isn't defined", name));
+    }
+    Ok(definitions)
+}
+
+pub fn load_definitions(path: impl AsRef<Path>) -> io::Result<Vec<BlockDefinition>> {
+    parse_definitions(&fs::read_to_string(path)?)
+}
+
+pub fn builtin_definitions() -> Vec<BlockDefinition> {
+    parse_definitions(BUILTIN_BLOCK_DEFINITIONS).unwrap()
+}
+
+/// Turns `definitions` into block types, air first and the unknown block
+/// last, with the variants of oriented blocks after each other. Missing
+/// textures are reported once and replaced with the placeholder.
+pub fn build_block_types(
+    definitions: Vec<BlockDefinition>,
+    texture_registry: &mut TextureRegistry,
+) -> Vec<BlockType> {
+    texture_registry.report_missing(definitions.iter().flat_map(|definition| {
+        Direction::ALL
+            .into_iter()
+            .filter_map(|direction| definition.textures.get(direction))
+    }));
+
+    let air = BlockType {
+        name: "air".to_string(),
+        transparent: true,
+        textures: BlockTextures::default(),
+        tint: Tint::None,
+        light_level: 0,
+        hardness: 0.0,
+        shape: Shape::Cube,
+        orientation: Orientation::None,
+        reflectivity: 0.0,
+        material: Material::default(),
+        gravity: false,
+        rotation: BlockRotation::NONE,
+    };
+    let unknown = BlockType {
+        name: UNKNOWN_BLOCK.to_string(),
+        transparent: false,
+        textures: BlockTextures::uniform(texture_registry.missing_texture_index()),
+        tint: Tint::None,
+        light_level: 0,
+        hardness: default_hardness(),
+        shape: Shape::Cube,
+        orientation: Orientation::None,
+        reflectivity: 0.0,
+        material: Material::default(),
+        gravity: false,
+        rotation: BlockRotation::NONE,
+    };
+    let defined = definitions
+        .into_iter()
+        .flat_map(|definition| {
+            let textures = BlockTextures(
+                Direction::ALL
+                    .into_iter()
+                    .map(|direction| {
+                        let name = definition.textures.get(direction).unwrap();
+                        let texture_id = texture_registry
+                            .get_index_of(name)
+                            .unwrap_or_else(|| texture_registry.missing_texture_index());
+                        (direction, texture_id)
+                    })
+                    .collect(),
+            );
+            let variants = match definition.shape {
+                Shape::Fluid(_) => FLUID_LEVELS
+                    .into_iter()
+                    .map(|state| (state, BlockRotation::NONE))
+                    .collect(),
+                _ => definition.orientation.variants(),
+            };
+            variants
+                .into_iter()
+                .enumerate()
+                .map(move |(i, (state, rotation))| BlockType {
+                    name: if i == 0 {
+                        definition.name.clone()
+                    } else {
+                        format!("{}[{}]", definition.name, state)
+                    },
+                    textures: textures.clone(),
+                    transparent: definition.transparent,
+                    tint: definition.tint,
+                    light_level: definition.light_level,
+                    hardness: definition.hardness,
+                    shape: match definition.shape {
+                        Shape::Fluid(_) => Shape::Fluid(i as u8),
+                        shape => shape,
+                    },
+                    orientation: definition.orientation,
+                    reflectivity: definition.reflectivity,
+                    material: definition.material,
+                    gravity: definition.gravity,
+                    rotation,
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let mut block_types = vec![air];
+    block_types.extend(defined);
+    block_types.push(unknown);
+    block_types
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_definitions() {
+        let definitions = builtin_definitions();
+        let log = definitions.iter().find(|d| d.name == "log").unwrap();
+        assert_eq!(log.textures.get(Direction::Up), Some("log_top"));
+        assert_eq!(log.textures.get(Direction::East), Some("log"));
+        assert_eq!(definitions[0].light_level, 0);
+        assert_eq!(definitions[0].material, Material::default());
+
+        let mut texture_registry = TextureRegistry::default();
+        let block_types = build_block_types(definitions, &mut texture_registry);
+        assert_eq!(block_types[0].name, "air");
+        assert_eq!(block_types.last().unwrap().name, UNKNOWN_BLOCK);
+        let names = block_types
+            .iter()
+            .map(|b| b.name.as_str())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"log[axis=x]"));
+        assert!(names.contains(&"log[axis=z]"));
+        // Flowing water follows its source, level by level
+        let water = names.iter().position(|&name| name == "water").unwrap();
+        assert_eq!(names[water + 3], "water[level=3]");
+        assert_eq!(block_types[water + 3].shape, Shape::Fluid(3));
+
+        let with = |extra: &str| {
+            let mut json = BUILTIN_BLOCK_DEFINITIONS.trim_end().to_string();
+            json.pop();
+            format!("{}, {}]", json, extra)
+        };
+        let error = |json: &str| parse_definitions(json).unwrap_err().to_string();
+        assert!(parse_definitions(&with(
+            r#"{"name": "lamp", "textures": {"all": "lamp", "up": "lamp_top"}, "light_level": 15}"#
+        ))
+        .is_ok());
+        assert!(
+            error(&with(r#"{"name": "glass", "textures": {"side": "glass"}}"#)).contains("Up face")
+        );
+        assert!(
+            error(&with(r#"{"name": "stone", "textures": {"all": "stone"}}"#)).contains("twice")
+        );
+        assert!(
+            error(&with(r#"{"name": "air", "textures": {"all": "air"}}"#)).contains("built in")
+        );
+        assert!(error(&with(
+            r#"{"name": "sun", "textures": {"all": "sun"}, "light_level": 16}"#
+        ))
+        .contains("light level"));
+        assert!(error(&with(
+            r#"{"name": "mirror", "textures": {"all": "mirror"}, "reflectivity": 1.5}"#
+        ))
+        .contains("reflectivity"));
+        assert!(parse_definitions(&with(
+            r#"{"name": "lava", "textures": {"all": "lava"}, "material": {"emissive": 2.0}}"#
+        ))
+        .is_ok());
+        assert!(error(&with(
+            r#"{"name": "gold", "textures": {"all": "gold"}, "material": {"metallic": -1.0}}"#
+        ))
+        .contains("metallic"));
+        assert!(error(&with(
+            r#"{"name": "ice", "textures": {"all": "ice"}, "material": {"gloss": 1.0}}"#
+        ))
+        .contains("unknown field"));
+        assert!(error(r#"[{"name": "stone", "textures": {"all": "stone"}}]"#).contains("required"));
+        assert!(error(r#"[{"name": "stone", "texture": "stone"}]"#).contains("unknown field"));
+    }
+}
@@ -1 +1,2 @@
+pub mod blocks;
 mod pack;
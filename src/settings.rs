@@ -0,0 +1,430 @@
+//! Graphics options kept in a JSON file next to the world, so they survive
+//! restarts. Options missing from the file take their defaults.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::camera::DepthMode;
+
+/// Where the settings are read from and written to.
+pub const SETTINGS_PATH: &str = "settings.json";
+/// Largest render distance the settings menu offers, and that servers send
+/// columns within.
+pub const MAX_RENDER_DISTANCE: u32 = 16;
+
+/// Multisampling of the geometry passes, resolved before upscaling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Msaa {
+    #[default]
+    Off,
+    X2,
+    X4,
+}
+
+impl Msaa {
+    /// Samples per pixel.
+    pub fn samples(self) -> u32 {
+        match self {
+            Msaa::Off => 1,
+            Msaa::X2 => 2,
+            Msaa::X4 => 4,
+        }
+    }
+}
+
+/// How the edges of the rendered image are smoothed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AntiAliasing {
+    /// Temporal upscaling by FSR, falling back to TAA where it is not
+    /// available.
+    #[default]
+    Fsr,
+    /// Temporal anti-aliasing at display resolution.
+    Taa,
+    /// FXAA at display resolution, cheaper than TAA but blurrier and with
+    /// shimmering edges in motion.
+    Fxaa,
+    Off,
+}
+
+/// The format of the swapchain images. Colors are shown as sRGB either way,
+/// but with `Unorm` they are encoded by a shader instead of on write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwapchainFormat {
+    #[default]
+    Srgb,
+    Unorm,
+}
+
+/// How the sun casts shadows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shadows {
+    #[default]
+    Off,
+    /// Traced with ray queries against the loaded world, where the device
+    /// supports them.
+    RayTraced,
+}
+
+/// How corners and crevices are darkened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AmbientOcclusion {
+    #[default]
+    Off,
+    /// Short rays against the loaded world, where the device supports ray
+    /// queries.
+    RayTraced,
+}
+
+/// What draws the blocks of the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorldRenderer {
+    /// Block faces generated by task and mesh shaders.
+    #[default]
+    MeshShaders,
+    /// Rays marched through the blocks by a compute shader, without meshes.
+    /// Experimental, for comparing against the mesh shaders.
+    VoxelDda,
+}
+
+/// How `WorldRenderer::VoxelDda` stores the blocks of sections on the GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VoxelStorage {
+    /// Every block of a section.
+    #[default]
+    Dense,
+    /// A sparse voxel octree per section, smaller for sections with large
+    /// empty or uniform regions, which rays cross in one step.
+    Octree,
+    /// A brick of block types per section in a 3D texture, 8 or 16 bits per
+    /// block depending on how many block types there are.
+    BlockAtlas,
+}
+
+/// What the faces at the edge of the loaded world show toward the sections
+/// that aren't loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorldEdges {
+    /// Nothing, as if unloaded sections were solid, so the world can be
+    /// seen into from its edges.
+    #[default]
+    Solid,
+    /// The faces, as if unloaded sections were air, closing off the world.
+    Air,
+    /// The faces, fading into the sky toward the edge of render distance.
+    FogWall,
+}
+
+/// Blur along the motion of the camera and of what moves on screen.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MotionBlur {
+    pub enabled: bool,
+    /// Fraction of the motion since the last frame that is blurred over.
+    pub intensity: f32,
+    /// Samples along the motion per pixel.
+    pub samples: u32,
+}
+
+impl Default for MotionBlur {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 0.5,
+            samples: 8,
+        }
+    }
+}
+
+/// Fog lit by the sun, marched through along every pixel's view ray.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fog {
+    pub enabled: bool,
+    /// Fraction of light scattered or absorbed per block at sea level.
+    pub density: f32,
+    /// How quickly the fog thins out above sea level, per block.
+    pub height_falloff: f32,
+    /// Samples along each view ray.
+    pub samples: u32,
+    /// Shadows the fog where blocks hide the sun, so shafts of light come
+    /// through canopies and cave openings. Traced with ray queries, where
+    /// the device supports them.
+    pub light_shafts: bool,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            density: 0.01,
+            height_falloff: 0.02,
+            samples: 16,
+            light_shafts: true,
+        }
+    }
+}
+
+/// How much smaller than the display FSR renders the frame before upscaling
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsrQuality {
+    /// At display resolution, only smoothing the edges.
+    #[default]
+    Native,
+    Quality,
+    Balanced,
+    Performance,
+    UltraPerformance,
+}
+
+impl FsrQuality {
+    pub const ALL: [FsrQuality; 5] = [
+        FsrQuality::Native,
+        FsrQuality::Quality,
+        FsrQuality::Balanced,
+        FsrQuality::Performance,
+        FsrQuality::UltraPerformance,
+    ];
+
+    /// The display size divided by the render size, along either axis.
+    pub fn scale(self) -> f32 {
+        match self {
+            FsrQuality::Native => 1.0,
+            FsrQuality::Quality => 1.5,
+            FsrQuality::Balanced => 1.7,
+            FsrQuality::Performance => 2.0,
+            FsrQuality::UltraPerformance => 3.0,
+        }
+    }
+
+    /// What FSR renders at to upscale to `display_size`.
+    pub fn render_size(self, display_size: [u32; 2]) -> [u32; 2] {
+        display_size.map(|size| ((size as f32 / self.scale()).round() as u32).max(1))
+    }
+}
+
+/// Options of the FSR upscaler, which can be changed while it runs, except
+/// the quality, which takes render targets of another size.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FsrSettings {
+    pub quality: FsrQuality,
+    /// Sharpens the upscaled image.
+    pub sharpening: bool,
+    /// From 0, the least sharp, to 1.
+    pub sharpness: f32,
+    /// Has FSR work out the exposure of each frame instead of assuming 1.
+    pub auto_exposure: bool,
+    /// Has FSR log warnings about how it is used.
+    pub debug_checking: bool,
+}
+
+impl Default for FsrSettings {
+    fn default() -> Self {
+        Self {
+            quality: FsrQuality::Native,
+            sharpening: true,
+            sharpness: 0.5,
+            auto_exposure: true,
+            debug_checking: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    /// How far the world is loaded and drawn around the camera, in chunk
+    /// columns.
+    pub render_distance: u32,
+    /// Waits for the display to show a frame before presenting the next, so
+    /// frames never tear.
+    pub vsync: bool,
+    /// Borderless on the whole of the monitor the window is on.
+    pub fullscreen: bool,
+    pub world_renderer: WorldRenderer,
+    pub voxel_storage: VoxelStorage,
+    /// Only for `WorldRenderer::MeshShaders`.
+    pub world_edges: WorldEdges,
+    pub msaa: Msaa,
+    pub anti_aliasing: AntiAliasing,
+    pub motion_blur: MotionBlur,
+    pub fog: Fog,
+    pub fsr: FsrSettings,
+    /// Screen-space reflections on reflective blocks.
+    pub reflections: bool,
+    /// A layer of blocky clouds drifting high above the ground.
+    pub clouds: bool,
+    pub shadows: Shadows,
+    pub ambient_occlusion: AmbientOcclusion,
+    /// Draws the depth of the blocks before shading them, so faces hidden
+    /// behind others, as in forests and caves, aren't shaded. Only for
+    /// `WorldRenderer::MeshShaders`.
+    pub depth_prepass: bool,
+    pub swapchain_format: SwapchainFormat,
+    /// Draws a strip at the top of the screen whose halves only look
+    /// equally bright when colors are encoded for display right.
+    pub gamma_test: bool,
+    /// `.cube` file the final image is graded with, none leaves it as it
+    /// is.
+    pub color_lut: Option<PathBuf>,
+    /// Picked with `--infinite-far` for the run rather than kept in the
+    /// file.
+    #[serde(skip)]
+    pub depth_mode: DepthMode,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            render_distance: 4,
+            vsync: true,
+            fullscreen: false,
+            world_renderer: Default::default(),
+            voxel_storage: Default::default(),
+            world_edges: Default::default(),
+            msaa: Default::default(),
+            anti_aliasing: Default::default(),
+            motion_blur: Default::default(),
+            fog: Default::default(),
+            fsr: Default::default(),
+            reflections: false,
+            clouds: false,
+            shadows: Default::default(),
+            ambient_occlusion: Default::default(),
+            depth_prepass: false,
+            swapchain_format: Default::default(),
+            gamma_test: false,
+            color_lut: None,
+            depth_mode: Default::default(),
+        }
+    }
+}
+
+impl GraphicsSettings {
+    /// Whether any option traces rays, which needs ray queries.
+    pub fn ray_traced(&self) -> bool {
+        self.shadows == Shadows::RayTraced
+            || self.ambient_occlusion == AmbientOcclusion::RayTraced
+            || (self.fog.enabled && self.fog.light_shafts)
+    }
+
+    /// Reads the settings at `path`. Without a file there, the defaults are
+    /// written to it so they can be edited.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        match fs::read(path.as_ref()) {
+            Ok(json) => Ok(serde_json::from_slice(&json)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let settings = Self::default();
+                settings.save(path)?;
+                Ok(settings)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_file() {
+        let path =
+            std::env::temp_dir().join(format!("block-world-settings-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(GraphicsSettings::load(&path).unwrap(), Default::default());
+        assert!(path.exists());
+
+        let settings = GraphicsSettings {
+            render_distance: 8,
+            vsync: false,
+            fullscreen: true,
+            world_renderer: WorldRenderer::VoxelDda,
+            voxel_storage: VoxelStorage::Octree,
+            world_edges: WorldEdges::FogWall,
+            msaa: Msaa::X4,
+            anti_aliasing: AntiAliasing::Taa,
+            motion_blur: MotionBlur {
+                enabled: true,
+                ..Default::default()
+            },
+            fog: Fog {
+                enabled: true,
+                density: 0.05,
+                ..Default::default()
+            },
+            fsr: FsrSettings {
+                quality: FsrQuality::Balanced,
+                sharpness: 0.2,
+                debug_checking: false,
+                ..Default::default()
+            },
+            reflections: true,
+            clouds: true,
+            shadows: Shadows::RayTraced,
+            ambient_occlusion: AmbientOcclusion::RayTraced,
+            depth_prepass: true,
+            swapchain_format: SwapchainFormat::Unorm,
+            gamma_test: true,
+            color_lut: Some("film.cube".into()),
+            depth_mode: DepthMode::Standard,
+        };
+        settings.save(&path).unwrap();
+        assert_eq!(GraphicsSettings::load(&path).unwrap(), settings);
+        // The depth mode is for the run only
+        let infinite_far = GraphicsSettings {
+            depth_mode: DepthMode::InfiniteReversed,
+            ..settings.clone()
+        };
+        infinite_far.save(&path).unwrap();
+        assert_eq!(GraphicsSettings::load(&path).unwrap(), settings);
+        assert_eq!(settings.msaa.samples(), 4);
+        assert_eq!(settings.fsr.quality.render_size([1700, 850]), [1000, 500]);
+
+        // Options missing from the file keep their defaults
+        fs::write(&path, r#"{"anti_aliasing": "taa"}"#).unwrap();
+        assert_eq!(
+            GraphicsSettings::load(&path).unwrap(),
+            GraphicsSettings {
+                anti_aliasing: AntiAliasing::Taa,
+                ..Default::default()
+            }
+        );
+        fs::write(
+            &path,
+            r#"{"anti_aliasing": "off", "msaa": "x2", "motion_blur": {"samples": 4}}"#,
+        )
+        .unwrap();
+        let settings = GraphicsSettings::load(&path).unwrap();
+        assert_eq!(settings.anti_aliasing, AntiAliasing::Off);
+        assert_eq!(settings.msaa, Msaa::X2);
+        assert_eq!(settings.motion_blur.samples, 4);
+        assert_eq!(settings.motion_blur.intensity, 0.5);
+        assert!(!settings.fog.enabled);
+        assert!(settings.fog.light_shafts);
+        assert_eq!(settings.render_distance, 4);
+        assert!(settings.vsync);
+        fs::write(&path, r#"{"msaa": "x3"}"#).unwrap();
+        assert!(GraphicsSettings::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
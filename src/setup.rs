@@ -0,0 +1,91 @@
+//! Setting up the world the game and the dedicated server share, and
+//! running the server.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+use crate::{
+    biome::BiomeRegistry,
+    chunk_loader::ChunkLoader,
+    entity::{Entities, TICK_RATE},
+    falling::FallingBlocks,
+    net::Server,
+    plugin::{PluginHost, PLUGIN_DIRECTORY},
+    resources::blocks::BLOCK_DEFINITIONS,
+    storage::RegionStorage,
+    texture::TextureRegistry,
+    tick::WorldTicks,
+    types::{BlockRegistry, World},
+    worldgen::{WorldGenerator, WorldSeed},
+};
+
+/// Columns kept loaded outside render distance before the least recently used
+/// are unloaded.
+pub const MAX_CACHED_COLUMNS: usize = 64;
+/// Where unloaded columns are saved.
+pub const REGION_DIRECTORY: &str = "world/region";
+/// How often the headless server saves its world.
+const SERVER_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Loads the block definitions and plugins into a new world with its
+/// generator.
+pub fn create_world() -> (World, WorldGenerator, Rc<RefCell<PluginHost>>) {
+    let mut block_registry =
+        BlockRegistry::load(BLOCK_DEFINITIONS, TextureRegistry::new()).unwrap();
+    let mut plugins = PluginHost::new();
+    plugins.load_directory(PLUGIN_DIRECTORY).unwrap();
+    plugins.register_blocks(&mut block_registry);
+    let mut world = World::new(block_registry);
+    plugins.subscribe(&mut world.events);
+    let plugins = Rc::new(RefCell::new(plugins));
+    let mut generator = WorldGenerator::new(
+        WorldSeed(0),
+        &world.block_registry,
+        BiomeRegistry::default(),
+    );
+    generator.add_column_hook({
+        let plugins = plugins.clone();
+        move |column| plugins.borrow_mut().generate_column(column)
+    });
+    (world, generator, plugins)
+}
+
+/// Runs the world without a window, for clients started with `--connect`.
+pub fn serve(address: &str) {
+    let (mut world, generator, plugins) = create_world();
+    // Columns are loaded as clients request them, regardless of a budget
+    let loader = ChunkLoader::new(0, MAX_CACHED_COLUMNS, usize::MAX)
+        .with_storage(RegionStorage::new(REGION_DIRECTORY, &world.block_registry).unwrap());
+    let mut server = Server::bind(address, &mut world, loader).unwrap();
+    info!("Serving on {}", server.local_addr().unwrap());
+
+    let mut entities = Entities::default();
+    let mut world_ticks =
+        WorldTicks::with_builtin_behaviors(generator.seed.feature("ticks"), &mut world);
+    let mut falling_blocks = FallingBlocks::new(&mut world, &mut entities);
+    let tick_duration = Duration::from_secs(1) / TICK_RATE;
+    let mut saved = Instant::now();
+    for tick in 0.. {
+        let tick_start = Instant::now();
+        server.update(&mut world, &generator);
+        world_ticks.tick(&mut world);
+        {
+            let mut plugins = plugins.borrow_mut();
+            plugins.update(&mut world);
+            plugins.tick(&mut world, tick);
+        }
+        entities.tick();
+        falling_blocks.update(&mut world, &mut entities);
+        if saved.elapsed() >= SERVER_SAVE_INTERVAL {
+            server.save(&world);
+            saved = Instant::now();
+        }
+        thread::sleep(tick_duration.saturating_sub(tick_start.elapsed()));
+    }
+}
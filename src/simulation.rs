@@ -0,0 +1,862 @@
+//! The game's simulation on a thread of its own: input, commands, chunk
+//! loading and generation, ticks, plugins, entities, weather and particles.
+//! The render thread never waits on it. After every step the simulation
+//! sends what changed in its world and a `Snapshot` of what there is to
+//! draw, and the render thread makes the same changes to a copy of the
+//! world that the renderer reads.
+//!
+//! Steps take the input that came in since the step before, so recordings
+//! are of steps rather than frames and play back the same.
+
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use bevy_ecs::entity::Entity;
+use cgmath::{Point3, Vector3};
+use log::{info, warn};
+use tracing::info_span;
+use winit::{event::MouseButton, keyboard::KeyCode};
+
+use crate::{
+    autosave::Autosave,
+    camera::{CameraMode, CameraSettings, DepthMode, SPRINT_SPEED},
+    chunk_loader::{ChunkLoader, LoaderUpdate},
+    console::{parse_command, Command, Console, FsrOption},
+    edit::EditHistory,
+    entity::{Entities, PreviousTransform, Renderable, Transform},
+    events::WorldEvent,
+    falling::FallingBlocks,
+    hotbar::{action_target, Action, Hotbar},
+    net::Client,
+    particles::{Particle, Particles},
+    plugin::PluginHost,
+    replay::{Input, Player, RecordedFrame, Recorder},
+    storage::schematic::{load_schematic, save_schematic},
+    tick::WorldTicks,
+    types::{BlockTypeId, Chunk, ChunkPosition, ColumnPosition, World, UNKNOWN_BLOCK},
+    viewmodel::ViewModel,
+    weather::{Precipitation, Weather},
+    worldgen::{structure::Structure, WorldGenerator, SEA_LEVEL},
+};
+
+/// Where `/snapshot` saves snapshots of the region directory, by name.
+const SNAPSHOT_DIRECTORY: &str = "world/snapshots";
+/// Where `/schem` saves and loads schematics, by name.
+const SCHEMATIC_DIRECTORY: &str = "schematics";
+/// Brush edits that `/undo` can revert.
+const MAX_UNDO_EDITS: usize = 256;
+/// Raindrops or snowflakes spawned per second in a full storm.
+const PRECIPITATION_RATE: f32 = 600.0;
+/// How far around the camera rain and snow fall, in blocks.
+const PRECIPITATION_RADIUS: f32 = 16.0;
+/// How often the columns changed in the local game are saved.
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Shortest time between the starts of two steps, so the simulation doesn't
+/// spin faster than anything can be drawn.
+const STEP_INTERVAL: Duration = Duration::from_micros(1_000_000 / 240);
+
+/// What the render thread draws of the simulation as of a step.
+pub struct Snapshot {
+    /// Where the player's eyes are.
+    pub eye: Point3<f32>,
+    /// Where the camera is, behind the eyes in third person.
+    pub position: Point3<f32>,
+    pub forward: Vector3<f32>,
+    pub camera_settings: CameraSettings,
+    pub weather: Weather,
+    pub renderables: Vec<(Entity, Transform, Renderable)>,
+    pub particles: Vec<Particle>,
+    pub viewmodel: ViewModel,
+    pub hotbar: Hotbar,
+    pub console: Console,
+    /// Columns waiting to be written to disk.
+    pub saving: usize,
+}
+
+impl Snapshot {
+    pub fn third_person(&self) -> bool {
+        self.camera_settings.mode == CameraMode::ThirdPerson
+    }
+}
+
+/// A change to the simulated world, to make to the render thread's copy.
+enum WorldChange {
+    Loaded(ChunkPosition, Box<Chunk>),
+    Unloaded(ChunkPosition),
+    Block([i32; 3], BlockTypeId),
+}
+
+/// The changes made to `world` that `events` received since the last call.
+/// A section is copied as it is now, with the changes made to it since it
+/// loaded.
+fn world_changes(world: &World, events: &Receiver<WorldEvent>) -> Vec<WorldChange> {
+    events
+        .try_iter()
+        .filter_map(|event| match event {
+            WorldEvent::ChunkLoaded(chunk_position) => world
+                .chunks
+                .get(&chunk_position)
+                .map(|chunk| WorldChange::Loaded(chunk_position, Box::new(chunk.clone()))),
+            WorldEvent::ChunkUnloaded(chunk_position) => {
+                Some(WorldChange::Unloaded(chunk_position))
+            }
+            WorldEvent::BlockChanged {
+                position, after, ..
+            } => Some(WorldChange::Block(position, after)),
+        })
+        .collect()
+}
+
+impl WorldChange {
+    fn apply(self, world: &mut World) {
+        match self {
+            WorldChange::Loaded(chunk_position, chunk) => {
+                world.insert_chunks([(chunk_position, *chunk)])
+            }
+            WorldChange::Unloaded(chunk_position) => {
+                if world.chunks.remove(&chunk_position).is_some() {
+                    world.events.emit(WorldEvent::ChunkUnloaded(chunk_position));
+                }
+            }
+            WorldChange::Block(position, block_type_id) => world.set_block(position, block_type_id),
+        }
+    }
+}
+
+/// What happened in one or more steps of the simulation.
+pub struct Update {
+    /// Simulated time the steps covered.
+    pub delta: Duration,
+    pub columns: LoaderUpdate,
+    /// Given with `/fsr`, for the render thread, which owns the graphics
+    /// settings.
+    pub fsr: Vec<FsrOption>,
+    /// As of the last step, if there was one.
+    pub snapshot: Option<Snapshot>,
+    changes: Vec<WorldChange>,
+}
+
+impl Update {
+    fn merge(&mut self, later: Update) {
+        self.delta += later.delta;
+        self.columns.merge(later.columns);
+        self.fsr.extend(later.fsr);
+        if later.snapshot.is_some() {
+            self.snapshot = later.snapshot;
+        }
+        self.changes.extend(later.changes);
+    }
+}
+
+/// What the render thread sends the simulation.
+enum Message {
+    Input(Input),
+    /// A line for the console, in reply to a command handled by the render
+    /// thread.
+    Print(String),
+    /// Columns around the camera to load, from the next step on.
+    RenderDistance(u32),
+}
+
+/// The state of the game besides what is drawn, stepped with the input of
+/// the window.
+pub struct Simulation {
+    world: World,
+    generator: WorldGenerator,
+    plugins: Rc<RefCell<PluginHost>>,
+    chunk_loader: ChunkLoader,
+    /// When connected, the server simulates the world and this mirrors it.
+    client: Option<Client>,
+    recorder: Option<Recorder>,
+    replay: Option<Player>,
+    world_events: Receiver<WorldEvent>,
+    /// Edits made with `/brush`, for `/undo` and `/redo`.
+    edit_history: EditHistory,
+    autosave: Autosave,
+    world_ticks: WorldTicks,
+    entities: Entities,
+    falling_blocks: FallingBlocks,
+    /// Drawn in third person, as a box of the placeholder block until
+    /// players have a model.
+    player: Entity,
+    player_block: BlockTypeId,
+    particles: Particles,
+    weather: Weather,
+    viewmodel: ViewModel,
+    hotbar: Hotbar,
+    console: Console,
+    camera_settings: CameraSettings,
+    /// Where the flight starts, moved by `/tp`.
+    flight_origin: Point3<f32>,
+    flight_time: f32,
+    forward: Vector3<f32>,
+    /// Simulated seconds per real second, set by `/timescale`.
+    time_scale: f32,
+}
+
+impl Simulation {
+    pub fn new(
+        mut world: World,
+        generator: WorldGenerator,
+        plugins: Rc<RefCell<PluginHost>>,
+        chunk_loader: ChunkLoader,
+        depth_mode: DepthMode,
+    ) -> Self {
+        let flight_origin = Point3::new(0.0, SEA_LEVEL as f32 + 30.0, 0.0);
+        let mut entities = Entities::default();
+        let player = entities.ecs.spawn(Transform::at(flight_origin)).id();
+        let player_block = world
+            .block_registry
+            .block_types
+            .get_index_of(UNKNOWN_BLOCK)
+            .unwrap();
+        Self {
+            world_events: world.events.subscribe(),
+            edit_history: EditHistory::new(MAX_UNDO_EDITS),
+            autosave: Autosave::new(&mut world, AUTOSAVE_INTERVAL),
+            world_ticks: WorldTicks::with_builtin_behaviors(
+                generator.seed.feature("ticks"),
+                &mut world,
+            ),
+            falling_blocks: FallingBlocks::new(&mut world, &mut entities),
+            particles: Particles::new(&mut world),
+            weather: Weather::new(generator.seed.feature("weather")),
+            hotbar: Hotbar::new(&world.block_registry),
+            console: Console::new(&world.block_registry),
+            world,
+            generator,
+            plugins,
+            chunk_loader,
+            client: None,
+            recorder: None,
+            replay: None,
+            entities,
+            player,
+            player_block,
+            viewmodel: ViewModel::default(),
+            camera_settings: CameraSettings {
+                depth_mode,
+                ..Default::default()
+            },
+            flight_origin,
+            flight_time: 0.0,
+            forward: Vector3::new(1.0, -0.4, 0.0),
+            time_scale: 1.0,
+        }
+    }
+
+    /// Streams the world from the server `client` is connected to.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Records the input of every step.
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Steps with the input of the recording instead of the window's, until
+    /// the recording ends.
+    pub fn with_replay(mut self, replay: Player) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    /// A copy of the world as it is now, without its event subscribers.
+    fn world_copy(&self) -> World {
+        let mut world = World::with_height(self.world.block_registry.clone(), self.world.height);
+        world.chunks = self.world.chunks.clone();
+        world
+    }
+
+    /// Flies across the world from the origin so chunks stream in and out of
+    /// render distance, `flight_time` seconds into the flight.
+    fn eye(&self) -> Point3<f32> {
+        let time = self.flight_time;
+        self.flight_origin + Vector3::new(time * 8.0, time.sin() * 3.0, (time * 0.2).sin() * 16.0)
+    }
+
+    /// Moves the game on by a step with the input since the step before,
+    /// `None` after the last step of a recording played back or once the
+    /// server is gone.
+    fn step(&mut self, mut recorded: RecordedFrame) -> Option<Update> {
+        let _span = info_span!("step").entered();
+        if let Some(replay) = &mut self.replay {
+            match replay.next_frame() {
+                Some(frame) => recorded = frame,
+                None => {
+                    info!("Played back the whole recording");
+                    return None;
+                }
+            }
+        }
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.record(&recorded) {
+                warn!("Failed to record the step, stopping the recording: {}", err);
+                self.recorder = None;
+            }
+        }
+        let elapsed = recorded.elapsed;
+        let mut actions = Vec::new();
+        for input in &recorded.inputs {
+            handle_input(
+                input,
+                &mut self.hotbar,
+                &mut actions,
+                &mut self.console,
+                &mut self.camera_settings,
+            );
+        }
+
+        let delta = elapsed.mul_f32(self.time_scale);
+        self.camera_settings.update(elapsed.as_secs_f32());
+        let speed = if self.camera_settings.sprinting {
+            SPRINT_SPEED
+        } else {
+            1.0
+        };
+        self.flight_time += delta.as_secs_f32() * speed;
+        let eye = self.eye();
+        let position = self
+            .camera_settings
+            .mode
+            .position(&self.world, eye, self.forward);
+        let third_person = self.camera_settings.mode == CameraMode::ThirdPerson;
+
+        let camera_block = [
+            position.x.floor() as i32,
+            position.y.floor() as i32,
+            position.z.floor() as i32,
+        ];
+        let world = &mut self.world;
+        let columns = match &mut self.client {
+            // The server runs the plugins
+            Some(client) => {
+                let in_range = self
+                    .chunk_loader
+                    .columns_in_range(ColumnPosition::of_block(camera_block));
+                match client.update(world, position.into(), &in_range) {
+                    Ok(update) => update,
+                    Err(err) => {
+                        warn!("Lost the connection to the server: {}", err);
+                        return None;
+                    }
+                }
+            }
+            None => {
+                let _span = info_span!("simulate").entered();
+                let loader_update = self
+                    .chunk_loader
+                    .update(world, &self.generator, camera_block);
+                self.autosave.update(world, &mut self.chunk_loader, delta);
+                let mut plugins = self.plugins.borrow_mut();
+                plugins.update(world);
+                let ticks = self.world_ticks.update(world, delta);
+                let end = self.world_ticks.tick_number();
+                for tick in end - ticks as u64..end {
+                    plugins.tick(world, tick);
+                }
+                loader_update
+            }
+        };
+
+        let held = self.hotbar.held();
+        for action in actions {
+            self.viewmodel.swing();
+            let Some((position, block_type_id)) =
+                action_target(&self.world, eye.into(), self.forward.into(), action, held)
+            else {
+                continue;
+            };
+            set_block(&mut self.world, &mut self.client, position, block_type_id);
+        }
+        self.viewmodel.held = held;
+
+        let mut fsr = Vec::new();
+        for line in self.console.take_submitted() {
+            let reply = match parse_command(&line, &self.world.block_registry) {
+                Err(error) => error,
+                Ok(Command::Teleport([x, y, z])) => {
+                    self.flight_origin = Point3::new(x, y, z);
+                    self.flight_time = 0.0;
+                    format!("Teleported to {} {} {}", x, y, z)
+                }
+                Ok(Command::Fill {
+                    min,
+                    max,
+                    block_type_id,
+                }) => {
+                    let ys =
+                        min[1].max(self.world.height.min_y)..max[1].min(self.world.height.max_y);
+                    for x in min[0]..max[0] {
+                        for y in ys.clone() {
+                            for z in min[2]..max[2] {
+                                set_block(
+                                    &mut self.world,
+                                    &mut self.client,
+                                    [x, y, z],
+                                    block_type_id,
+                                );
+                            }
+                        }
+                    }
+                    format!(
+                        "Filled {} blocks with {}",
+                        (max[0] - min[0]) * ys.len() as i32 * (max[2] - min[2]),
+                        self.world.block_registry.block_types[block_type_id].name
+                    )
+                }
+                Ok(Command::Seed) => match &self.client {
+                    Some(_) => "Only the server knows the seed".to_string(),
+                    None => format!("Seed: {}", self.generator.seed.0),
+                },
+                Ok(Command::SetBlock {
+                    position,
+                    block_type_id,
+                }) => {
+                    set_block(&mut self.world, &mut self.client, position, block_type_id);
+                    format!(
+                        "Set {:?} to {}",
+                        position, self.world.block_registry.block_types[block_type_id].name
+                    )
+                }
+                Ok(Command::TimeScale(scale)) => {
+                    self.time_scale = scale;
+                    format!("Time runs {}x as fast", scale)
+                }
+                Ok(Command::Fov(degrees)) => {
+                    self.camera_settings.fovy = cgmath::Deg(degrees);
+                    format!("Field of view set to {} degrees", degrees)
+                }
+                Ok(Command::Clip { near, far }) => {
+                    let settings = &mut self.camera_settings;
+                    settings.near = near;
+                    settings.far = far;
+                    match settings.depth_mode {
+                        DepthMode::Standard => format!("Clipping from {} to {}", near, far),
+                        DepthMode::InfiniteReversed => {
+                            format!("Clipping from {}, the far plane is infinite", near)
+                        }
+                    }
+                }
+                // Applied and replied to by the render thread, which owns
+                // the graphics settings
+                Ok(Command::Fsr(option)) => {
+                    fsr.push(option);
+                    continue;
+                }
+                Ok(Command::Snapshot(name)) => match &self.client {
+                    Some(_) => "Only the server can save snapshots".to_string(),
+                    None => {
+                        let destination = Path::new(SNAPSHOT_DIRECTORY).join(&name);
+                        match self.chunk_loader.snapshot(&self.world, &destination) {
+                            Ok(()) => format!("Saved snapshot {}", name),
+                            Err(err) => format!("Failed to save snapshot {}: {}", name, err),
+                        }
+                    }
+                },
+                Ok(Command::Restore(name)) => match &self.client {
+                    Some(_) => "Only the server can restore snapshots".to_string(),
+                    None => {
+                        let source = Path::new(SNAPSHOT_DIRECTORY).join(&name);
+                        match self
+                            .chunk_loader
+                            .restore(&mut self.world, &self.generator, &source)
+                        {
+                            Ok(()) => {
+                                // Edits of the world before can't be undone
+                                self.edit_history = EditHistory::new(MAX_UNDO_EDITS);
+                                format!("Restored snapshot {}", name)
+                            }
+                            Err(err) => format!("Failed to restore snapshot {}: {}", name, err),
+                        }
+                    }
+                },
+                Ok(Command::SaveSchematic { name, min, max }) => {
+                    let structure = Structure::copy(&self.world, &name, min, max);
+                    let path = schematic_path(&name);
+                    let saved = fs::create_dir_all(SCHEMATIC_DIRECTORY).and_then(|()| {
+                        save_schematic(&path, &structure, &self.world.block_registry)
+                    });
+                    match saved {
+                        Ok(()) => format!("Saved schematic {}", name),
+                        Err(err) => format!("Failed to save schematic {}: {}", name, err),
+                    }
+                }
+                Ok(Command::LoadSchematic {
+                    name,
+                    origin,
+                    rotation,
+                }) => match load_schematic(schematic_path(&name), &self.world.block_registry) {
+                    Ok(structure) => {
+                        match &mut self.client {
+                            Some(client) => {
+                                // The server makes the changes, block by block
+                                for block in structure.rotated(rotation).blocks {
+                                    let position = [0, 1, 2].map(|i| origin[i] + block.offset[i]);
+                                    if self.world.height.contains(position[1]) {
+                                        client.set_block(position, block.block_type_id);
+                                    }
+                                }
+                            }
+                            None => structure.paste(&mut self.world, origin, rotation),
+                        }
+                        format!("Loaded schematic {} at {:?}", name, origin)
+                    }
+                    Err(err) => format!("Failed to load schematic {}: {}", name, err),
+                },
+                // Edits change the local world, which the server would
+                // overwrite
+                Ok(Command::Brush { .. } | Command::Undo | Command::Redo)
+                    if self.client.is_some() =>
+                {
+                    "Only the server can make edits".to_string()
+                }
+                Ok(Command::Brush {
+                    brush,
+                    block_type_id,
+                    mask,
+                }) => {
+                    let changed = self.edit_history.edit(&mut self.world, |transaction| {
+                        transaction.paint(&brush, block_type_id, mask)
+                    });
+                    format!(
+                        "Painted {} blocks with {}",
+                        changed, self.world.block_registry.block_types[block_type_id].name
+                    )
+                }
+                Ok(Command::Undo) if !self.edit_history.can_undo() => "Nothing to undo".to_string(),
+                Ok(Command::Undo) => {
+                    self.edit_history.undo(&mut self.world);
+                    "Undid the last edit".to_string()
+                }
+                Ok(Command::Redo) if !self.edit_history.can_redo() => "Nothing to redo".to_string(),
+                Ok(Command::Redo) => {
+                    self.edit_history.redo(&mut self.world);
+                    "Redid the last undone edit".to_string()
+                }
+            };
+            self.console.print(reply);
+        }
+
+        self.entities.update(delta);
+        // The server simulates falling blocks for clients
+        if self.client.is_none() {
+            let landed = self
+                .falling_blocks
+                .update(&mut self.world, &mut self.entities);
+            for position in landed {
+                self.particles.spawn_landing(position);
+            }
+        }
+        // Moves with the camera rather than in ticks, so it is not
+        // interpolated
+        let body = Transform::at(eye - Vector3::new(0.5, 0.5, 0.5));
+        let mut player_entity = self.entities.ecs.entity_mut(self.player);
+        player_entity.insert((body, PreviousTransform(body)));
+        if third_person {
+            player_entity.insert(Renderable {
+                block_type_id: self.player_block,
+                billboard: false,
+            });
+        } else {
+            player_entity.remove::<Renderable>();
+        }
+        let biome = &self.generator.biome_registry.biomes[self.world.biome(camera_block)];
+        let precipitation = Precipitation::of(biome);
+        self.weather.update(delta.as_secs_f32(), precipitation);
+        let drops = (PRECIPITATION_RATE * self.weather.intensity() * delta.as_secs_f32()) as usize;
+        match precipitation {
+            Precipitation::Rain => self
+                .particles
+                .spawn_rain(position, PRECIPITATION_RADIUS, drops),
+            Precipitation::Snow => self
+                .particles
+                .spawn_snow(position, PRECIPITATION_RADIUS, drops),
+            Precipitation::None => {}
+        }
+        self.particles.update(&self.world, delta.as_secs_f32());
+        self.viewmodel.update(elapsed.as_secs_f32());
+
+        Some(Update {
+            delta,
+            columns,
+            fsr,
+            snapshot: Some(Snapshot {
+                eye,
+                position,
+                forward: self.forward,
+                camera_settings: self.camera_settings.clone(),
+                weather: self.weather.clone(),
+                renderables: self.entities.renderables(),
+                particles: self.particles.particles().to_vec(),
+                viewmodel: self.viewmodel.clone(),
+                hotbar: self.hotbar.clone(),
+                console: self.console.clone(),
+                saving: self.chunk_loader.saving(),
+            }),
+            changes: self.world_changes(),
+        })
+    }
+}
+
+/// Steps a `Simulation` on a background thread, as often as every
+/// `STEP_INTERVAL`, until it is dropped or the simulation ends.
+pub struct SimulationThread {
+    sender: Option<Sender<Message>>,
+    updates: Receiver<Update>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SimulationThread {
+    /// Starts the simulation `setup` returns on the thread, where it stays,
+    /// returning a copy of its world for the render thread. Nothing starts
+    /// if `setup` returns `None`.
+    pub fn spawn(
+        setup: impl FnOnce() -> Option<Simulation> + Send + 'static,
+    ) -> Option<(Self, World)> {
+        let (sender, messages) = channel();
+        let (update_sender, updates) = channel();
+        let (world_sender, world) = channel();
+        let thread = thread::Builder::new()
+            .name("simulation".to_string())
+            .spawn(move || {
+                let Some(mut simulation) = setup() else {
+                    return;
+                };
+                if world_sender.send(simulation.world_copy()).is_err() {
+                    return;
+                }
+                let mut step_start = Instant::now();
+                loop {
+                    let mut inputs = Vec::new();
+                    loop {
+                        match messages.try_recv() {
+                            Ok(Message::Input(input)) => inputs.push(input),
+                            Ok(Message::Print(line)) => simulation.console.print(line),
+                            Ok(Message::RenderDistance(distance)) => {
+                                simulation.chunk_loader.render_distance = distance as i32
+                            }
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Disconnected) => return,
+                        }
+                    }
+                    let recorded = RecordedFrame {
+                        elapsed: step_start.elapsed(),
+                        inputs,
+                    };
+                    step_start = Instant::now();
+                    let Some(update) = simulation.step(recorded) else {
+                        return;
+                    };
+                    if update_sender.send(update).is_err() {
+                        return;
+                    }
+                    thread::sleep(STEP_INTERVAL.saturating_sub(step_start.elapsed()));
+                }
+            })
+            .unwrap();
+        let world = world.recv().ok()?;
+        Some((
+            Self {
+                sender: Some(sender),
+                updates,
+                thread: Some(thread),
+            },
+            world,
+        ))
+    }
+
+    /// Hands input from the window to the next step.
+    pub fn input(&self, input: Input) {
+        self.send(Message::Input(input));
+    }
+
+    /// Prints `line` in the console.
+    pub fn print(&self, line: impl Into<String>) {
+        self.send(Message::Print(line.into()));
+    }
+
+    /// Loads the columns within `distance` of the camera from the next step
+    /// on, letting go of those further away.
+    pub fn set_render_distance(&self, distance: u32) {
+        self.send(Message::RenderDistance(distance));
+    }
+
+    fn send(&self, message: Message) {
+        // The simulation may have ended, which `update` tells
+        let _ = self.sender.as_ref().unwrap().send(message);
+    }
+
+    /// Makes the changes of the steps since the last call to the copy of
+    /// the world, returning what else they did merged, without a snapshot
+    /// if there was no step. `None` once the simulation has ended.
+    pub fn update(&self, world: &mut World) -> Option<Update> {
+        let mut update = Update {
+            delta: Duration::ZERO,
+            columns: LoaderUpdate::default(),
+            fsr: Vec::new(),
+            snapshot: None,
+            changes: Vec::new(),
+        };
+        loop {
+            match self.updates.try_recv() {
+                Ok(step) => update.merge(step),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) if update.snapshot.is_none() => return None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        let _span = info_span!("apply_world_changes", count = update.changes.len()).entered();
+        for change in update.changes.drain(..) {
+            change.apply(world);
+        }
+        Some(update)
+    }
+}
+
+impl Drop for SimulationThread {
+    /// Stops the simulation after the step it is in, and waits for it to
+    /// finish saving.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Reacts to `input` from the window. Clicks and keys go to the console
+/// while it is open.
+fn handle_input(
+    input: &Input,
+    hotbar: &mut Hotbar,
+    actions: &mut Vec<Action>,
+    console: &mut Console,
+    camera_settings: &mut CameraSettings,
+) {
+    match input {
+        Input::MousePressed(button) if !console.is_open() => match button {
+            MouseButton::Left => actions.push(Action::Break),
+            MouseButton::Right => actions.push(Action::Place),
+            _ => {}
+        },
+        // Scrolling up moves to the slot on the left
+        &Input::Scroll(y) if y != 0.0 => hotbar.scroll(-y.signum() as i32),
+        // Held down rather than pressed, so releases count too
+        &Input::Key { code, pressed, .. }
+            if matches!(code, KeyCode::KeyC | KeyCode::ControlLeft) && !console.is_open() =>
+        {
+            match code {
+                KeyCode::KeyC => camera_settings.zooming = pressed,
+                _ => camera_settings.sprinting = pressed,
+            }
+        }
+        Input::Key {
+            code,
+            pressed: true,
+            text,
+        } => {
+            if console.is_open() {
+                match code {
+                    KeyCode::Escape | KeyCode::Backquote => console.close(),
+                    KeyCode::Enter | KeyCode::NumpadEnter => console.submit(),
+                    KeyCode::Backspace => console.backspace(),
+                    KeyCode::ArrowUp => console.history_previous(),
+                    KeyCode::ArrowDown => console.history_next(),
+                    KeyCode::Tab => console.complete(),
+                    _ => console.type_text(text.as_deref().unwrap_or_default()),
+                }
+            } else if *code == KeyCode::Backquote {
+                console.open("");
+            } else if *code == KeyCode::Slash {
+                console.open("/");
+            } else if *code == KeyCode::F5 {
+                camera_settings.mode = camera_settings.mode.toggled();
+            } else if let Some(slot) = hotbar_slot(*code) {
+                hotbar.select(slot);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The hotbar slot a number key selects.
+fn hotbar_slot(code: KeyCode) -> Option<usize> {
+    let digits = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    digits.iter().position(|&digit| digit == code)
+}
+
+fn schematic_path(name: &str) -> PathBuf {
+    Path::new(SCHEMATIC_DIRECTORY).join(format!("{}.schem", name))
+}
+
+/// Sets a block of the local world, or asks the server to when connected.
+fn set_block(
+    world: &mut World,
+    client: &mut Option<Client>,
+    position: [i32; 3],
+    block_type_id: BlockTypeId,
+) {
+    match client {
+        Some(client) => client.set_block(position, block_type_id),
+        None => world.set_block(position, block_type_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::BlockRegistry;
+
+    use super::*;
+
+    #[test]
+    fn test_world_changes_keep_copy_in_step() {
+        let mut world = World::new(BlockRegistry::default());
+        let events = world.events.subscribe();
+        let mut copy = World::new(BlockRegistry::default());
+        let copy_events = copy.events.subscribe();
+
+        let column = ColumnPosition { x: 0, z: 0 };
+        world.insert_chunks(
+            column
+                .sections(world.height)
+                .map(|section| (section, Default::default())),
+        );
+        world.set_block([1, 2, 3], 1);
+        world.set_block([17, 2, 3], 1);
+        world.unload_column(ColumnPosition { x: 1, z: 0 });
+        for change in world_changes(&world, &events) {
+            change.apply(&mut copy);
+        }
+
+        assert_eq!(copy.chunks.len(), world.chunks.len());
+        assert_eq!(copy[[1, 2, 3]], 1);
+        assert!(!copy
+            .chunks
+            .contains_key(&ChunkPosition::of_block([17, 2, 3])));
+        // The copy tells its own subscribers, like the renderer's
+        assert!(copy_events
+            .try_iter()
+            .any(|event| matches!(event, WorldEvent::ChunkLoaded(_))));
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use log::{info, warn};
+
+use crate::types::{
+    BlockRegistry, BlockTypeId, Chunk, ChunkPosition, ColumnPosition, Direction, WorldHeight,
+    CHUNK_SIZE,
+};
+
+use super::{invalid_data, minecraft::MinecraftBlocks, nbt, nbt::Tag, RegionStorage};
+
+const SECTOR_SIZE: usize = 4096;
+
+/// A column with its sections.
+type ImportedColumn = (ColumnPosition, Vec<(ChunkPosition, Chunk)>);
+
+/// Converts the columns of a Minecraft Java Edition world (Anvil `.mca`
+/// region files, 1.16 and later) to this crate's `Chunk`s, mapping block
+/// states with `MinecraftBlocks`. Biomes are not imported.
+pub struct AnvilImporter {
+    blocks: MinecraftBlocks,
+}
+
+impl AnvilImporter {
+    pub fn new(block_registry: &BlockRegistry) -> Self {
+        Self {
+            blocks: MinecraftBlocks::new(block_registry),
+        }
+    }
+
+    /// Converts every `.mca` file in `directory` (a world's `region`
+    /// directory), saving the columns to `storage`. Sections outside `height`
+    /// are dropped. Returns the number of columns imported.
+    pub fn import_directory(
+        &mut self,
+        directory: impl AsRef<Path>,
+        block_registry: &BlockRegistry,
+        height: WorldHeight,
+        storage: &mut RegionStorage,
+    ) -> io::Result<usize> {
+        let mut imported = 0;
+        for entry in fs::read_dir(directory.as_ref())? {
+            let path = entry?.path();
+            if !path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("mca"))
+            {
+                continue;
+            }
+            let columns = match self.read_region(&fs::read(&path)?, block_registry, height) {
+                Ok(columns) => columns,
+                Err(err) => {
+                    warn!("Skipping region file {:?}: {}", path, err);
+                    continue;
+                }
+            };
+            for (column, sections) in columns {
+                storage.save_column(column, &sections)?;
+                imported += 1;
+            }
+        }
+        info!(
+            "Imported {} columns from {:?}",
+            imported,
+            directory.as_ref()
+        );
+        Ok(imported)
+    }
+
+    /// The columns of an `.mca` file. Columns that fail to decode are skipped.
+    pub fn read_region(
+        &mut self,
+        bytes: &[u8],
+        block_registry: &BlockRegistry,
+        height: WorldHeight,
+    ) -> io::Result<Vec<ImportedColumn>> {
+        if bytes.len() < 2 * SECTOR_SIZE {
+            return Err(invalid_data("region file header is truncated"));
+        }
+
+        let mut columns = Vec::new();
+        for location in bytes[..SECTOR_SIZE].chunks_exact(4) {
+            let offset = u32::from_be_bytes([0, location[0], location[1], location[2]]) as usize;
+            if offset == 0 {
+                continue;
+            }
+            let column = read_column_nbt(bytes, offset * SECTOR_SIZE)
+                .and_then(|root| self.convert_column(&root, block_registry, height));
+            match column {
+                Ok(column) => columns.push(column),
+                Err(err) => warn!("Skipping column at sector {}: {}", offset, err),
+            }
+        }
+        Ok(columns)
+    }
+
+    fn convert_column(
+        &mut self,
+        root: &Tag,
+        block_registry: &BlockRegistry,
+        height: WorldHeight,
+    ) -> io::Result<ImportedColumn> {
+        // Before 1.18 everything is nested in "Level", with other field names
+        let modern = root.get("Level").is_none();
+        let level = root.get("Level").unwrap_or(root);
+        let (sections_name, palette_name, data_name) = if modern {
+            ("sections", "palette", "data")
+        } else {
+            ("Sections", "Palette", "BlockStates")
+        };
+        let coordinate = |name: &str| {
+            level
+                .get(name)
+                .and_then(Tag::as_i64)
+                .map(|value| value as i32)
+                .ok_or_else(|| invalid_data(format!("column has no {}", name)))
+        };
+        let column = ColumnPosition {
+            x: coordinate("xPos")?,
+            z: coordinate("zPos")?,
+        };
+
+        let mut chunks: HashMap<i32, Chunk> = HashMap::new();
+        let sections = level
+            .get(sections_name)
+            .and_then(Tag::as_list)
+            .unwrap_or_default();
+        for section in sections {
+            let Some(y) = section.get("Y").and_then(Tag::as_i64) else {
+                continue;
+            };
+            let y = y as i32;
+            if !height.contains_section(y) {
+                continue;
+            }
+            let states = if modern {
+                section.get("block_states")
+            } else {
+                Some(section)
+            };
+            let Some(palette) = states
+                .and_then(|states| states.get(palette_name))
+                .and_then(Tag::as_list)
+            else {
+                continue;
+            };
+            let palette = palette
+                .iter()
+                .map(|state| {
+                    let name = state.get("Name").and_then(Tag::as_str).unwrap_or_default();
+                    let id = self.blocks.block_type(name, block_registry);
+                    // Logs and the like lying on their side
+                    let axis = state
+                        .get("Properties")
+                        .and_then(|properties| properties.get("axis"))
+                        .and_then(Tag::as_str);
+                    match axis {
+                        Some("x") => block_registry.oriented(id, Direction::East),
+                        Some("z") => block_registry.oriented(id, Direction::South),
+                        _ => id,
+                    }
+                })
+                .collect::<Vec<_>>();
+            let data = states
+                .and_then(|states| states.get(data_name))
+                .and_then(Tag::as_long_array)
+                .unwrap_or_default();
+            chunks.insert(y, unpack_section(&palette, data)?);
+        }
+
+        let sections = column
+            .sections(height)
+            .map(|position| {
+                let chunk = chunks.remove(&position.y).unwrap_or_default();
+                (position, chunk)
+            })
+            .collect();
+        Ok((column, sections))
+    }
+}
+
+/// Decompresses the NBT of the column stored at byte `start` of a region
+/// file.
+fn read_column_nbt(bytes: &[u8], start: usize) -> io::Result<Tag> {
+    let header = bytes
+        .get(start..start + 5)
+        .ok_or_else(|| invalid_data("column offset is out of the file"))?;
+    let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+    let compressed = len
+        .checked_sub(1)
+        .and_then(|len| bytes.get(start + 5..start + 5 + len))
+        .ok_or_else(|| invalid_data("column length is out of the file"))?;
+
+    let decompressed = match header[4] {
+        1 => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(compressed).read_to_end(&mut decompressed)?;
+            decompressed
+        }
+        2 => {
+            let mut decompressed = Vec::new();
+            ZlibDecoder::new(compressed).read_to_end(&mut decompressed)?;
+            decompressed
+        }
+        3 => compressed.to_vec(),
+        compression => {
+            return Err(invalid_data(format!(
+                "unsupported column compression {}",
+                compression
+            )))
+        }
+    };
+    nbt::read(&decompressed)
+}
+
+/// Unpacks a section's palette indices: `bits` per block, at least 4, packed
+/// into longs from the lowest bits up without spanning two longs, in YZX
+/// order. A palette of one block has no data.
+fn unpack_section(palette: &[BlockTypeId], data: &[i64]) -> io::Result<Chunk> {
+    let mut chunk = Chunk::default();
+    if palette.is_empty() {
+        return Ok(chunk);
+    }
+    if palette.len() == 1 {
+        chunk.blocks = [[[palette[0]; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+        return Ok(chunk);
+    }
+
+    let bits = (usize::BITS - (palette.len() - 1).leading_zeros()).max(4) as usize;
+    let per_long = 64 / bits;
+    let block_count = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+    if data.len() < block_count.div_ceil(per_long) {
+        return Err(invalid_data("section block data is truncated"));
+    }
+    for i in 0..block_count {
+        let long = data[i / per_long] as u64;
+        let index = (long >> (i % per_long * bits)) as usize & ((1 << bits) - 1);
+        let block_type = *palette
+            .get(index)
+            .ok_or_else(|| invalid_data("section block is out of its palette"))?;
+
+        let (x, z, y) = (
+            i % CHUNK_SIZE,
+            i / CHUNK_SIZE % CHUNK_SIZE,
+            i / (CHUNK_SIZE * CHUNK_SIZE),
+        );
+        chunk.blocks[y][x][z] = block_type;
+    }
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use crate::types::UNKNOWN_BLOCK;
+
+    use super::{nbt::compound, *};
+
+    /// A region file holding a single 1.18 column at `column`.
+    fn region_file(column: ColumnPosition, sections: Vec<Tag>) -> Vec<u8> {
+        let root = compound([
+            ("xPos", Tag::Int(column.x)),
+            ("zPos", Tag::Int(column.z)),
+            ("sections", Tag::List(sections)),
+        ]);
+        let nbt = nbt::write("", &root);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&nbt).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut bytes = vec![0; 2 * SECTOR_SIZE];
+        let index = (column.z.rem_euclid(32) * 32 + column.x.rem_euclid(32)) as usize;
+        let sectors = (compressed.len() + 5).div_ceil(SECTOR_SIZE) as u8;
+        bytes[index * 4..index * 4 + 4].copy_from_slice(&[0, 0, 2, sectors]);
+        bytes.extend_from_slice(&(compressed.len() as u32 + 1).to_be_bytes());
+        bytes.push(2);
+        bytes.extend_from_slice(&compressed);
+        bytes.resize((2 + sectors as usize) * SECTOR_SIZE, 0);
+        bytes
+    }
+
+    #[test]
+    fn test_import_column() {
+        let block_registry = BlockRegistry::default();
+        let id = |name: &str| block_registry.block_types.get_index_of(name).unwrap();
+
+        let mut palette = ["air", "grass_block", "oak_log", "diamond_ore"]
+            .map(|name| compound([("Name", Tag::String(format!("minecraft:{}", name)))]));
+        palette[2] = compound([
+            ("Name", Tag::String("minecraft:oak_log".to_string())),
+            (
+                "Properties",
+                compound([("axis", Tag::String("z".to_string()))]),
+            ),
+        ]);
+        // 4 bits per block, 16 blocks per long
+        let mut data = vec![0i64; 256];
+        data[0] |= 1;
+        let log = 1 + 2 * 16 + 3 * 256;
+        data[log / 16] |= 2 << (log % 16 * 4);
+        data[255] |= 3 << 60;
+        let section = |y, palette: Vec<Tag>, data| {
+            let mut states = vec![("palette", Tag::List(palette))];
+            if let Some(data) = data {
+                states.push(("data", Tag::LongArray(data)));
+            }
+            compound([
+                ("Y", Tag::Byte(y)),
+                (
+                    "block_states",
+                    Tag::Compound(
+                        states
+                            .into_iter()
+                            .map(|(name, value)| (name.to_string(), value))
+                            .collect(),
+                    ),
+                ),
+            ])
+        };
+        let stone = compound([("Name", Tag::String("minecraft:stone".to_string()))]);
+
+        let column = ColumnPosition { x: -1, z: 33 };
+        let bytes = region_file(
+            column,
+            vec![
+                section(0, palette.to_vec(), Some(data)),
+                section(1, vec![stone.clone()], None),
+                // Below the world, dropped
+                section(-1, vec![stone], None),
+            ],
+        );
+
+        let height = WorldHeight::new(0, 64);
+        let mut importer = AnvilImporter::new(&block_registry);
+        let columns = importer
+            .read_region(&bytes, &block_registry, height)
+            .unwrap();
+        assert_eq!(columns.len(), 1);
+        let (imported_column, sections) = &columns[0];
+        assert_eq!(*imported_column, column);
+        assert_eq!(sections.len(), 4);
+
+        let blocks = &sections[0].1.blocks;
+        assert_eq!(blocks[0][0][0], id("grass"));
+        assert_eq!(blocks[3][1][2], id("log[axis=z]"));
+        assert_eq!(blocks[15][15][15], id(UNKNOWN_BLOCK));
+        assert_eq!(blocks[3][2][1], id("air"));
+        assert_eq!(sections[1].1.blocks[9][4][2], id("stone"));
+        assert_eq!(sections[2].1.blocks[0][0][0], id("air"));
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::types::ColumnPosition;
+
+use super::invalid_data;
+
+/// Column x and z, payload length and CRC-32 of the payload.
+const RECORD_HEADER_LEN: usize = 16;
+
+/// Column payloads written since the region files were last updated,
+/// appended to a file and synced to disk before a write counts as done. A
+/// crash while appending tears at most the last record, which is dropped
+/// when the journal is opened again.
+pub struct Journal {
+    file: File,
+    len: u64,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating an empty one if it doesn't
+    /// exist, and returns it with its records, oldest first.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<(Self, Vec<(ColumnPosition, Vec<u8>)>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut records = Vec::new();
+        let mut rest = bytes.as_slice();
+        while rest.len() >= RECORD_HEADER_LEN {
+            let word = |i: usize| u32::from_le_bytes(rest[i * 4..i * 4 + 4].try_into().unwrap());
+            let column = ColumnPosition {
+                x: word(0) as i32,
+                z: word(1) as i32,
+            };
+            let (len, checksum) = (word(2) as usize, word(3));
+            let Some(payload) = rest[RECORD_HEADER_LEN..].get(..len) else {
+                break;
+            };
+            if crc32fast::hash(payload) != checksum {
+                break;
+            }
+            records.push((column, payload.to_vec()));
+            rest = &rest[RECORD_HEADER_LEN + len..];
+        }
+
+        // Later records go after the last whole one
+        let len = (bytes.len() - rest.len()) as u64;
+        if !rest.is_empty() {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+        Ok((Self { file, len }, records))
+    }
+
+    /// Appends a record of `payload` for `column`, returning once it is on
+    /// disk.
+    pub fn append(&mut self, column: ColumnPosition, payload: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(payload.len())
+            .map_err(|_| invalid_data("column too large for the journal"))?;
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        for word in [
+            column.x as u32,
+            column.z as u32,
+            len,
+            crc32fast::hash(payload),
+        ] {
+            record.extend_from_slice(&word.to_le_bytes());
+        }
+        record.extend_from_slice(payload);
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.len += record.len() as u64;
+        Ok(())
+    }
+
+    /// Size of the journal in bytes.
+    pub fn size(&self) -> u64 {
+        self.len
+    }
+
+    /// Drops every record, once they are all in the region files.
+    pub fn clear(&mut self) -> io::Result<()> {
+        if self.len > 0 {
+            self.file.set_len(0)?;
+            self.file.sync_all()?;
+            self.len = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the file at `path` with `bytes` so that it holds either the old
+/// or the new contents after a crash: they are written to a temporary file
+/// next to it and synced, which is then renamed over it.
+pub fn write_atomic(path: impl AsRef<Path>, bytes: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    let temporary = temporary_path(path);
+    let mut file = File::create(&temporary)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    replace(&temporary, path)
+}
+
+/// The temporary file `path` is written to before `replace` moves it over.
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Renames the synced file `temporary` over `path`, and syncs the directory
+/// so the rename itself survives a crash.
+pub fn replace(temporary: &Path, path: &Path) -> io::Result<()> {
+    fs::rename(temporary, path)?;
+    sync_directory(path.parent().unwrap_or(Path::new(".")))
+}
+
+/// Makes `to` a synced copy of the file `from`, hard linked where the file
+/// system allows. Only for files that are replaced rather than changed, as
+/// a link shares later changes.
+pub fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::hard_link(from, to).is_err() {
+        fs::copy(from, to)?;
+        File::open(to)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn sync_directory(directory: &Path) -> io::Result<()> {
+    File::open(directory)?.sync_all()
+}
+
+/// Renames are durable once they return on Windows.
+#[cfg(not(unix))]
+pub fn sync_directory(_directory: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_torn_record() {
+        let path = std::env::temp_dir().join(format!("block-world-journal-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let (mut journal, records) = Journal::open(&path).unwrap();
+        assert!(records.is_empty());
+        let column = ColumnPosition { x: -1, z: 7 };
+        journal.append(column, &[1; 100]).unwrap();
+        journal.append(column, &[2; 100]).unwrap();
+        drop(journal);
+
+        // A crash in the middle of the second record
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 30]).unwrap();
+        let (mut journal, records) = Journal::open(&path).unwrap();
+        assert_eq!(records, vec![(column, vec![1; 100])]);
+        assert_eq!(journal.size(), (RECORD_HEADER_LEN + 100) as u64);
+
+        journal.append(column, &[3; 10]).unwrap();
+        drop(journal);
+        let (mut journal, records) = Journal::open(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].1, vec![3; 10]);
+
+        journal.clear().unwrap();
+        drop(journal);
+        let (_, records) = Journal::open(&path).unwrap();
+        assert!(records.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
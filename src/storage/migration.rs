@@ -0,0 +1,81 @@
+//! Upgrades of saves written by older versions. Every column payload is
+//! brought to `SAVE_VERSION` by running the migrations after the version it
+//! was saved with in order, after which its block ids are remapped from the
+//! registry it was saved with to the current one.
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the saves written now. Changing how columns are encoded needs
+/// a bump and a migration from the previous version in `MIGRATIONS`.
+pub const SAVE_VERSION: u32 = 2;
+
+/// Upgrades a column payload of version `i + 1` to version `i + 2`.
+type Migration = fn(Vec<u8>) -> io::Result<Vec<u8>>;
+
+const MIGRATIONS: [Migration; SAVE_VERSION as usize - 1] = [from_v1];
+
+/// Block type names of the registry version 1 saves were written with, which
+/// they didn't record.
+const V1_BLOCK_NAMES: [&str; 17] = [
+    "air",
+    "stone",
+    "grass",
+    "dirt",
+    "sand",
+    "log",
+    "log[axis=x]",
+    "log[axis=z]",
+    "leaves",
+    "stone_slab",
+    "stone_stairs",
+    "stone_stairs[facing=east]",
+    "stone_stairs[facing=south]",
+    "stone_stairs[facing=west]",
+    "fence",
+    "water",
+    "unknown",
+];
+
+/// Written next to the region files, saying how their columns are encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SaveInfo {
+    pub version: u32,
+    /// Names of the block types by the ids the columns were saved with.
+    pub block_names: Vec<String>,
+    /// Regions, as `[x, z]`, already migrated to the current version by a
+    /// migration that was interrupted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub migrated: Vec<[i32; 2]>,
+}
+
+impl SaveInfo {
+    /// The info of saves from before it was written, which are version 1.
+    pub fn v1() -> Self {
+        Self {
+            version: 1,
+            block_names: V1_BLOCK_NAMES.map(str::to_string).to_vec(),
+            migrated: Vec::new(),
+        }
+    }
+}
+
+/// Upgrades a column payload saved with `version` to `SAVE_VERSION`. Fails
+/// with `InvalidData` for versions newer than this one.
+pub fn migrate_column(version: u32, payload: Vec<u8>) -> io::Result<Vec<u8>> {
+    if version == 0 || version > SAVE_VERSION {
+        return Err(super::invalid_data(format!(
+            "unsupported save version {}",
+            version
+        )));
+    }
+    MIGRATIONS[version as usize - 1..]
+        .iter()
+        .try_fold(payload, |payload, migration| migration(payload))
+}
+
+/// Version 2 only added the save info, so the columns are encoded the same.
+fn from_v1(payload: Vec<u8>) -> io::Result<Vec<u8>> {
+    Ok(payload)
+}
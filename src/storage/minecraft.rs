@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::types::{BlockRegistry, BlockTypeId, UNKNOWN_BLOCK};
+
+/// Minecraft blocks imported as one of the built-in block types, by name
+/// without the `minecraft:` namespace. Blocks not listed here that share a
+/// name with a block type are imported as that type.
+const ALIASES: &[(&str, &str)] = &[
+    ("cave_air", "air"),
+    ("void_air", "air"),
+    // Fluids have no block type yet
+    ("water", "air"),
+    ("lava", "air"),
+    ("grass_block", "grass"),
+    ("coarse_dirt", "dirt"),
+    ("rooted_dirt", "dirt"),
+    ("podzol", "dirt"),
+    ("mycelium", "dirt"),
+    ("farmland", "dirt"),
+    ("dirt_path", "dirt"),
+    ("red_sand", "sand"),
+    ("sandstone", "sand"),
+    ("gravel", "sand"),
+    ("deepslate", "stone"),
+    ("granite", "stone"),
+    ("diorite", "stone"),
+    ("andesite", "stone"),
+    ("tuff", "stone"),
+    ("cobblestone", "stone"),
+    ("bedrock", "stone"),
+];
+
+/// The Minecraft blocks built-in block types are exported as, where their
+/// names differ.
+const EXPORT_NAMES: &[(&str, &str)] = &[
+    ("grass", "grass_block"),
+    ("log", "oak_log"),
+    ("leaves", "oak_leaves"),
+    (UNKNOWN_BLOCK, "air"),
+];
+
+/// Maps Minecraft block states to block types by name, through `ALIASES` and
+/// the `_log`, `_wood` and `_leaves` suffixes. Anything else becomes the
+/// `UNKNOWN_BLOCK` placeholder.
+pub struct MinecraftBlocks {
+    block_types: HashMap<String, BlockTypeId>,
+    unknown: BlockTypeId,
+}
+
+impl MinecraftBlocks {
+    pub fn new(block_registry: &BlockRegistry) -> Self {
+        let unknown = block_registry
+            .block_types
+            .get_index_of(UNKNOWN_BLOCK)
+            .unwrap();
+        // The block definitions may leave out the types aliased to
+        let block_types = ALIASES
+            .iter()
+            .map(|(name, block_type)| {
+                let id = block_registry
+                    .block_types
+                    .get_index_of(*block_type)
+                    .unwrap_or(unknown);
+                (format!("minecraft:{}", name), id)
+            })
+            .collect();
+        Self {
+            block_types,
+            unknown,
+        }
+    }
+
+    /// The block type a namespaced block state, like
+    /// `minecraft:oak_log[axis=y]`, is imported as. Properties are ignored.
+    pub fn block_type(&mut self, state: &str, block_registry: &BlockRegistry) -> BlockTypeId {
+        let name = state.split('[').next().unwrap();
+        if let Some(&id) = self.block_types.get(name) {
+            return id;
+        }
+        let path = name.strip_prefix("minecraft:").unwrap_or(name);
+        let block_type = if path.ends_with("_log") || path.ends_with("_wood") {
+            "log"
+        } else if path.ends_with("_leaves") {
+            "leaves"
+        } else {
+            path
+        };
+        let id = block_registry
+            .block_types
+            .get_index_of(block_type)
+            .unwrap_or_else(|| {
+                warn!("Importing unknown block {:?} as a placeholder", name);
+                self.unknown
+            });
+        self.block_types.insert(name.to_string(), id);
+        id
+    }
+
+    /// The namespaced Minecraft block a block type is exported as.
+    pub fn minecraft_name(block_type: &str) -> String {
+        let name = EXPORT_NAMES
+            .iter()
+            .find(|(name, _)| *name == block_type)
+            .map_or(block_type, |(_, minecraft_name)| minecraft_name);
+        format!("minecraft:{}", name)
+    }
+}
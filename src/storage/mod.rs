@@ -0,0 +1,681 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+use crate::types::{BlockRegistry, Chunk, ChunkPosition, ColumnPosition};
+
+pub use self::{anvil::AnvilImporter, writer::ColumnWriter};
+use self::{
+    journal::{link_or_copy, replace, sync_directory, temporary_path, write_atomic, Journal},
+    migration::{migrate_column, SaveInfo, SAVE_VERSION},
+    region::{RegionFile, REGION_ENTRIES, REGION_SIZE},
+};
+
+mod anvil;
+mod journal;
+mod migration;
+mod minecraft;
+mod nbt;
+mod region;
+pub mod schematic;
+mod writer;
+
+/// File in the storage directory holding its `SaveInfo`.
+const SAVE_INFO_FILE: &str = "save.json";
+/// File in the storage directory holding its `Journal`.
+const JOURNAL_FILE: &str = "journal";
+/// Journal size at which its columns are written into the region files.
+const CHECKPOINT_SIZE: u64 = 8 << 20;
+
+/// Position of a region, in units of `REGION_SIZE` columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RegionPosition {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl RegionPosition {
+    /// The region containing `column`, and the index of its entry there.
+    pub fn of_column(column: ColumnPosition) -> (Self, usize) {
+        let region = Self {
+            x: column.x.div_euclid(REGION_SIZE),
+            z: column.z.div_euclid(REGION_SIZE),
+        };
+        let x = column.x.rem_euclid(REGION_SIZE);
+        let z = column.z.rem_euclid(REGION_SIZE);
+        (region, (z * REGION_SIZE + x) as usize)
+    }
+
+    /// The column of the entry `index` of the region.
+    pub fn column(&self, index: usize) -> ColumnPosition {
+        let index = index as i32;
+        ColumnPosition {
+            x: self.x * REGION_SIZE + index % REGION_SIZE,
+            z: self.z * REGION_SIZE + index / REGION_SIZE,
+        }
+    }
+
+    fn file_name(&self) -> String {
+        format!("r.{}.{}.region", self.x, self.z)
+    }
+
+    /// The region a file is for, if it is named like a region file.
+    fn from_file_name(name: &str) -> Option<Self> {
+        let mut parts = name.strip_suffix(".region")?.split('.');
+        let (Some("r"), Some(x), Some(z), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        Some(Self {
+            x: x.parse().ok()?,
+            z: z.parse().ok()?,
+        })
+    }
+}
+
+/// Columns saved to region files in `directory`, each region file holding
+/// `REGION_SIZE`x`REGION_SIZE` columns. Region files are opened on first use
+/// and kept open.
+///
+/// Region files are never changed in place, so a crash can't leave one
+/// half written. Saved columns go to a journal first, and once it has grown
+/// large, or when the storage is dropped, every region with journaled
+/// columns is replaced by an updated copy. Opening the storage finishes what
+/// a crash interrupted by replaying the journal.
+pub struct RegionStorage {
+    directory: PathBuf,
+    regions: HashMap<RegionPosition, RegionFile>,
+    journal: Journal,
+    /// Payloads of the columns in the journal, which the region files don't
+    /// have yet.
+    journaled: HashMap<ColumnPosition, Vec<u8>>,
+}
+
+impl RegionStorage {
+    /// Opens the columns saved in `directory` for a world with
+    /// `block_registry`. Saves of an older version or with another registry
+    /// are migrated first, rewriting every column.
+    pub fn new(directory: impl AsRef<Path>, block_registry: &BlockRegistry) -> io::Result<Self> {
+        fs::create_dir_all(directory.as_ref())?;
+        let (journal, records) = Journal::open(directory.as_ref().join(JOURNAL_FILE))?;
+        let mut storage = Self {
+            directory: directory.as_ref().to_path_buf(),
+            regions: HashMap::new(),
+            journal,
+            journaled: records.into_iter().collect(),
+        };
+        storage.checkpoint()?;
+        storage.upgrade(block_registry)?;
+        Ok(storage)
+    }
+
+    /// Migrates the saved columns if the save info says they were saved by
+    /// an older version or with another registry than `block_registry`.
+    fn upgrade(&mut self, block_registry: &BlockRegistry) -> io::Result<()> {
+        let current = SaveInfo {
+            version: SAVE_VERSION,
+            block_names: block_registry.block_names(),
+            migrated: Vec::new(),
+        };
+        let saved = match fs::read(self.directory.join(SAVE_INFO_FILE)) {
+            Ok(json) => Some(serde_json::from_slice(&json)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                // Saves from before the info was written
+                (!region_positions(&self.directory)?.is_empty()).then(SaveInfo::v1)
+            }
+            Err(err) => return Err(err),
+        };
+        if let Some(saved) = saved.filter(|saved| *saved != current) {
+            self.migrate(&saved, block_registry)?;
+        }
+        self.write_save_info(&current)
+    }
+
+    fn write_save_info(&self, info: &SaveInfo) -> io::Result<()> {
+        write_atomic(
+            self.directory.join(SAVE_INFO_FILE),
+            &serde_json::to_vec_pretty(info)?,
+        )
+    }
+
+    /// Rewrites every column saved as `saved` says in the current version
+    /// with the ids of `block_registry`, one region at a time. The regions
+    /// done are recorded in the save info, so an interrupted migration
+    /// continues with the others. A migrated region is only moved over the
+    /// old one once recorded, so no region is migrated twice: a region
+    /// recorded but not yet moved is moved when the migration continues.
+    fn migrate(&mut self, saved: &SaveInfo, block_registry: &BlockRegistry) -> io::Result<()> {
+        if saved.version > SAVE_VERSION {
+            return Err(invalid_data(format!(
+                "saved with the newer version {}",
+                saved.version
+            )));
+        }
+        let mapping = block_registry.id_mapping(&saved.block_names);
+        let mut progress = saved.clone();
+        let mut migrated = 0;
+        for region in region_positions(&self.directory)? {
+            let path = self.directory.join(region.file_name());
+            let pending = migrated_path(&path);
+            if saved.migrated.contains(&[region.x, region.z]) {
+                if pending.exists() {
+                    self.regions.remove(&region);
+                    replace(&pending, &path)?;
+                }
+                continue;
+            }
+            let file = self.region(region)?;
+            let mut payloads = Vec::new();
+            for index in 0..REGION_ENTRIES {
+                if let Some(payload) = file.read(index)? {
+                    payloads.push((index, payload));
+                }
+            }
+            for (_, payload) in &mut payloads {
+                let mut sections =
+                    decode_column(&migrate_column(saved.version, std::mem::take(payload))?)?;
+                for (_, chunk) in &mut sections {
+                    chunk.remap_blocks(&mapping);
+                }
+                let sections = sections
+                    .iter()
+                    .map(|(y, chunk)| (*y, chunk))
+                    .collect::<Vec<_>>();
+                *payload = encode_column(&sections)?;
+            }
+            self.copy_region(region, &payloads, &pending)?;
+            migrated += payloads.len();
+            progress.migrated.push([region.x, region.z]);
+            self.write_save_info(&progress)?;
+            replace(&pending, &path)?;
+        }
+        info!(
+            "Migrated {} saved columns from version {} to {}",
+            migrated, saved.version, SAVE_VERSION
+        );
+        Ok(())
+    }
+
+    /// Replaces the region file of `region` with a synced copy that has
+    /// `payloads` written to their entries.
+    fn replace_region(
+        &mut self,
+        region: RegionPosition,
+        payloads: &[(usize, Vec<u8>)],
+    ) -> io::Result<()> {
+        let path = self.directory.join(region.file_name());
+        let temporary = temporary_path(&path);
+        self.copy_region(region, payloads, &temporary)?;
+        replace(&temporary, &path)
+    }
+
+    /// Writes a synced copy of the region file of `region` to `copy`, with
+    /// `payloads` written to their entries.
+    fn copy_region(
+        &mut self,
+        region: RegionPosition,
+        payloads: &[(usize, Vec<u8>)],
+        copy: &Path,
+    ) -> io::Result<()> {
+        self.regions.remove(&region);
+        let path = self.directory.join(region.file_name());
+        match fs::copy(&path, copy) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                // Left by an interrupted copy of a region that didn't exist
+                let _ = fs::remove_file(copy);
+            }
+            Err(err) => return Err(err),
+        }
+        let mut file = RegionFile::open(copy)?;
+        for (index, payload) in payloads {
+            file.write(*index, payload)?;
+        }
+        file.sync()
+    }
+
+    /// Writes the journaled columns into their region files and empties the
+    /// journal.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        let mut regions: HashMap<RegionPosition, Vec<(usize, Vec<u8>)>> = HashMap::new();
+        for (&column, payload) in &self.journaled {
+            let (region, index) = RegionPosition::of_column(column);
+            regions
+                .entry(region)
+                .or_default()
+                .push((index, payload.clone()));
+        }
+        for (region, payloads) in regions {
+            self.replace_region(region, &payloads)?;
+        }
+        self.journaled.clear();
+        self.journal.clear()
+    }
+
+    /// Copies the saved columns into a new directory `destination`, along
+    /// with the save info they need to be read. Region files are hard linked
+    /// where possible, which is safe as they are only ever replaced. The copy
+    /// is made next to `destination` and renamed to it once complete, so a
+    /// crash can't leave a partial snapshot behind.
+    pub fn snapshot(&mut self, destination: impl AsRef<Path>) -> io::Result<()> {
+        let destination = destination.as_ref();
+        if destination.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", destination.display()),
+            ));
+        }
+        self.checkpoint()?;
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = temporary_path(destination);
+        match fs::remove_dir_all(&temporary) {
+            Ok(()) => {}
+            // Nothing left by an interrupted snapshot
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        fs::create_dir(&temporary)?;
+        for name in snapshot_files(&self.directory)? {
+            link_or_copy(&self.directory.join(&name), &temporary.join(&name))?;
+        }
+        sync_directory(&temporary)?;
+        replace(&temporary, destination)
+    }
+
+    /// Replaces the saved columns with those of the snapshot in `source`,
+    /// dropping the columns saved since, and migrates them like `new` does.
+    /// The snapshot itself is left as it was. A crash while restoring can
+    /// leave a mix of both, which restoring again cleans up.
+    pub fn restore(
+        &mut self,
+        source: impl AsRef<Path>,
+        block_registry: &BlockRegistry,
+    ) -> io::Result<()> {
+        let source = source.as_ref();
+        let files = snapshot_files(source)?;
+        if !files.iter().any(|name| name == SAVE_INFO_FILE) {
+            return Err(invalid_data(format!(
+                "{} is not a snapshot",
+                source.display()
+            )));
+        }
+        self.journaled.clear();
+        self.journal.clear()?;
+        self.regions.clear();
+
+        let restored = region_positions(source)?;
+        for region in region_positions(&self.directory)? {
+            if !restored.contains(&region) {
+                fs::remove_file(self.directory.join(region.file_name()))?;
+            }
+        }
+        for name in files {
+            let path = self.directory.join(&name);
+            let temporary = temporary_path(&path);
+            let _ = fs::remove_file(&temporary);
+            link_or_copy(&source.join(&name), &temporary)?;
+            replace(&temporary, &path)?;
+        }
+        info!("Restored the saved columns from {}", source.display());
+        self.upgrade(block_registry)
+    }
+
+    fn region(&mut self, region: RegionPosition) -> io::Result<&mut RegionFile> {
+        if !self.regions.contains_key(&region) {
+            let file = RegionFile::open(self.directory.join(region.file_name()))?;
+            self.regions.insert(region, file);
+        }
+        Ok(self.regions.get_mut(&region).unwrap())
+    }
+
+    /// Writes the `sections` of `column`, replacing what was saved before.
+    pub fn save_column(
+        &mut self,
+        column: ColumnPosition,
+        sections: &[(ChunkPosition, Chunk)],
+    ) -> io::Result<()> {
+        let data = sections
+            .iter()
+            .map(|(position, chunk)| {
+                assert_eq!((position.x, position.z), (column.x, column.z));
+                (position.y, chunk)
+            })
+            .collect::<Vec<_>>();
+        let payload = encode_column(&data)?;
+
+        self.journal.append(column, &payload)?;
+        self.journaled.insert(column, payload);
+        if self.journal.size() >= CHECKPOINT_SIZE {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// The saved sections of `column`, if it was saved.
+    pub fn load_column(
+        &mut self,
+        column: ColumnPosition,
+    ) -> io::Result<Option<Vec<(ChunkPosition, Chunk)>>> {
+        let payload = match self.journaled.get(&column) {
+            Some(payload) => payload.clone(),
+            None => {
+                let (region, index) = RegionPosition::of_column(column);
+                let Some(payload) = self.region(region)?.read(index)? else {
+                    return Ok(None);
+                };
+                payload
+            }
+        };
+        Ok(Some(
+            decode_column(&payload)?
+                .into_iter()
+                .map(|(y, chunk)| {
+                    let position = ChunkPosition {
+                        x: column.x,
+                        y,
+                        z: column.z,
+                    };
+                    (position, chunk)
+                })
+                .collect(),
+        ))
+    }
+}
+
+impl Drop for RegionStorage {
+    fn drop(&mut self) {
+        if let Err(err) = self.checkpoint() {
+            warn!(
+                "Failed to write the journaled columns to the regions: {}",
+                err
+            );
+        }
+    }
+}
+
+/// Positions of the region files in `directory`.
+fn region_positions(directory: &Path) -> io::Result<Vec<RegionPosition>> {
+    let mut regions = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        if let Some(region) = entry?
+            .file_name()
+            .to_str()
+            .and_then(RegionPosition::from_file_name)
+        {
+            regions.push(region);
+        }
+    }
+    Ok(regions)
+}
+
+/// Where `RegionStorage::migrate` writes the migrated copy of the region file
+/// `path` before the save info records it.
+fn migrated_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".migrated");
+    path.with_file_name(name)
+}
+
+/// Names of the files in `directory` a snapshot of it consists of: the
+/// region files and the save info.
+fn snapshot_files(directory: &Path) -> io::Result<Vec<String>> {
+    let mut names = region_positions(directory)?
+        .iter()
+        .map(RegionPosition::file_name)
+        .collect::<Vec<_>>();
+    if directory.join(SAVE_INFO_FILE).exists() {
+        names.push(SAVE_INFO_FILE.to_string());
+    }
+    Ok(names)
+}
+
+/// Encodes the sections of a column with their section y.
+fn encode_column(sections: &[(i32, &Chunk)]) -> io::Result<Vec<u8>> {
+    bincode::serde::encode_to_vec(sections, bincode::config::standard())
+        .map_err(|err| invalid_data(err.to_string()))
+}
+
+fn decode_column(payload: &[u8]) -> io::Result<Vec<(i32, Chunk)>> {
+    let (sections, _) = bincode::serde::decode_from_slice(payload, bincode::config::standard())
+        .map_err(|err| invalid_data(err.to_string()))?;
+    Ok(sections)
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{BlockTypeId, UNKNOWN_BLOCK};
+
+    use super::*;
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("block-world-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn test_column_round_trip() {
+        let directory = temp_directory("storage");
+        let block_registry = BlockRegistry::default();
+        let mut storage = RegionStorage::new(&directory, &block_registry).unwrap();
+
+        let column = ColumnPosition { x: -33, z: 5 };
+        let (region, index) = RegionPosition::of_column(column);
+        assert_eq!(region, RegionPosition { x: -2, z: 0 });
+        assert_eq!(region.column(index), column);
+
+        let mut chunk = Chunk::default();
+        chunk.blocks[1][2][3] = 4;
+        let position = ChunkPosition {
+            x: column.x,
+            y: -1,
+            z: column.z,
+        };
+        storage.save_column(column, &[(position, chunk)]).unwrap();
+        assert!(storage
+            .load_column(ColumnPosition { x: -32, z: 5 })
+            .unwrap()
+            .is_none());
+
+        // Reopen the region file from disk
+        drop(storage);
+        let mut storage = RegionStorage::new(&directory, &block_registry).unwrap();
+        let sections = storage.load_column(column).unwrap().unwrap();
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].0, position);
+        assert_eq!(sections[0].1.blocks[1][2][3], 4);
+        drop(storage);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_crash_recovery() {
+        let directory = temp_directory("crash");
+        let block_registry = BlockRegistry::default();
+        let column = ColumnPosition { x: 0, z: 0 };
+        let save = |storage: &mut RegionStorage, block| {
+            let mut chunk = Chunk::default();
+            chunk.blocks[0][0][0] = block;
+            let position = ChunkPosition { x: 0, y: 0, z: 0 };
+            storage.save_column(column, &[(position, chunk)]).unwrap();
+        };
+        let load = |storage: &mut RegionStorage| {
+            storage.load_column(column).unwrap().unwrap()[0].1.blocks[0][0][0]
+        };
+
+        let mut storage = RegionStorage::new(&directory, &block_registry).unwrap();
+        save(&mut storage, 1);
+        drop(storage);
+        let region_path = directory.join(RegionPosition { x: 0, z: 0 }.file_name());
+        let checkpointed = fs::read(&region_path).unwrap();
+
+        // A crash before the journal reached the region file, which is left
+        // as it was
+        let mut storage = RegionStorage::new(&directory, &block_registry).unwrap();
+        save(&mut storage, 2);
+        std::mem::forget(storage);
+        assert_eq!(fs::read(&region_path).unwrap(), checkpointed);
+        // and a crash while copying the region file for the next checkpoint
+        fs::write(temporary_path(&region_path), [0; 100]).unwrap();
+
+        let mut storage = RegionStorage::new(&directory, &block_registry).unwrap();
+        assert_eq!(load(&mut storage), 2);
+        assert_eq!(storage.journal.size(), 0);
+        drop(storage);
+        let mut region = RegionFile::open(&region_path).unwrap();
+        let (_, index) = RegionPosition::of_column(column);
+        let sections = decode_column(&region.read(index).unwrap().unwrap()).unwrap();
+        assert_eq!(sections[0].1.blocks[0][0][0], 2);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let directory = temp_directory("snapshot");
+        let snapshot = directory.join("snapshots").join("before");
+        let block_registry = BlockRegistry::default();
+        let mut storage = RegionStorage::new(directory.join("region"), &block_registry).unwrap();
+        let save = |storage: &mut RegionStorage, column: ColumnPosition, block| {
+            let mut chunk = Chunk::default();
+            chunk.blocks[0][0][0] = block;
+            let position = ChunkPosition {
+                x: column.x,
+                y: 0,
+                z: column.z,
+            };
+            storage.save_column(column, &[(position, chunk)]).unwrap();
+        };
+        let load = |storage: &mut RegionStorage, column| {
+            let sections = storage.load_column(column).unwrap();
+            sections.map(|sections| sections[0].1.blocks[0][0][0])
+        };
+        let near = ColumnPosition { x: 0, z: 0 };
+        let far = ColumnPosition { x: 100, z: 0 };
+
+        // Journaled columns are part of the snapshot
+        save(&mut storage, near, 1);
+        storage.snapshot(&snapshot).unwrap();
+        assert_eq!(
+            storage.snapshot(&snapshot).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        save(&mut storage, near, 2);
+        save(&mut storage, far, 3);
+        storage.checkpoint().unwrap();
+        // Changing the world leaves the snapshot as it was
+        storage.restore(&snapshot, &block_registry).unwrap();
+        assert_eq!(load(&mut storage, near), Some(1));
+        assert_eq!(load(&mut storage, far), None);
+
+        // and restoring it again too
+        save(&mut storage, near, 4);
+        drop(storage);
+        let mut storage = RegionStorage::new(directory.join("region"), &block_registry).unwrap();
+        assert_eq!(load(&mut storage, near), Some(4));
+        storage.restore(&snapshot, &block_registry).unwrap();
+        assert_eq!(load(&mut storage, near), Some(1));
+        assert_eq!(
+            storage
+                .restore(directory.join("snapshots"), &block_registry)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        drop(storage);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_migration() {
+        let directory = temp_directory("migration");
+        let block_registry = BlockRegistry::default();
+        let id = |name: &str| block_registry.block_types.get_index_of(name).unwrap();
+        let column = ColumnPosition { x: 3, z: -4 };
+        let position = ChunkPosition {
+            x: column.x,
+            y: 2,
+            z: column.z,
+        };
+        let save = |blocks: [BlockTypeId; 2]| {
+            let mut storage = RegionStorage::new(&directory, &block_registry).unwrap();
+            let mut chunk = Chunk::default();
+            chunk.blocks[0][0][..2].copy_from_slice(&blocks);
+            storage.save_column(column, &[(position, chunk)]).unwrap();
+        };
+        let load = || {
+            let mut storage = RegionStorage::new(&directory, &block_registry)?;
+            let sections = storage.load_column(column)?.unwrap();
+            io::Result::Ok(sections[0].1.blocks[0][0][..2].to_vec())
+        };
+        let write_info = |info: &SaveInfo| {
+            fs::write(
+                directory.join(SAVE_INFO_FILE),
+                serde_json::to_vec(info).unwrap(),
+            )
+            .unwrap();
+        };
+
+        // Saves without save info are version 1, with its log and unknown ids
+        save([5, 16]);
+        fs::remove_file(directory.join(SAVE_INFO_FILE)).unwrap();
+        assert_eq!(load().unwrap(), [id("log"), id(UNKNOWN_BLOCK)]);
+        // Migrated once
+        assert_eq!(load().unwrap(), [id("log"), id(UNKNOWN_BLOCK)]);
+
+        // Saves with another registry are remapped by name
+        save([1, 2]);
+        let info = SaveInfo {
+            version: SAVE_VERSION,
+            block_names: ["air", "dirt", "marble"].map(str::to_string).to_vec(),
+            migrated: Vec::new(),
+        };
+        write_info(&info);
+        assert_eq!(load().unwrap(), [id("dirt"), 0]);
+
+        // Regions an interrupted migration finished are skipped
+        save([1, 2]);
+        write_info(&SaveInfo {
+            migrated: vec![[0, -1]],
+            ..info.clone()
+        });
+        assert_eq!(load().unwrap(), [1, 2]);
+
+        // A migration interrupted after recording a region, before moving
+        // its migrated copy over the old file, moves it without migrating
+        // the region again
+        save([1, 2]);
+        write_info(&info);
+        let path = directory.join(RegionPosition { x: 0, z: -1 }.file_name());
+        let old = fs::read(&path).unwrap();
+        assert_eq!(load().unwrap(), [id("dirt"), 0]);
+        fs::rename(&path, migrated_path(&path)).unwrap();
+        fs::write(&path, old).unwrap();
+        write_info(&SaveInfo {
+            migrated: vec![[0, -1]],
+            ..info.clone()
+        });
+        assert_eq!(load().unwrap(), [id("dirt"), 0]);
+        assert!(!migrated_path(&path).exists());
+
+        // Saves of newer versions are refused
+        write_info(&SaveInfo {
+            version: SAVE_VERSION + 1,
+            ..info
+        });
+        assert_eq!(load().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
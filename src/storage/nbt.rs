@@ -0,0 +1,305 @@
+use std::{collections::HashMap, io};
+
+use super::invalid_data;
+
+/// A value of Minecraft's Named Binary Tag format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    /// The field `name` of a compound.
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(fields) => fields.get(name),
+            _ => None,
+        }
+    }
+
+    /// Any integer tag, widened.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Tag::Byte(value) => Some(value as i64),
+            Tag::Short(value) => Some(value as i64),
+            Tag::Int(value) => Some(value as i64),
+            Tag::Long(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_long_array(&self) -> Option<&[i64]> {
+        match self {
+            Tag::LongArray(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte_array(&self) -> Option<&[i8]> {
+        match self {
+            Tag::ByteArray(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_int_array(&self) -> Option<&[i32]> {
+        match self {
+            Tag::IntArray(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&HashMap<String, Tag>> {
+        match self {
+            Tag::Compound(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+}
+
+/// A compound of `fields`.
+pub fn compound<const N: usize>(fields: [(&str, Tag); N]) -> Tag {
+    Tag::Compound(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    )
+}
+
+/// Nesting deeper than this is treated as corruption rather than recursed
+/// into.
+const MAX_DEPTH: usize = 512;
+
+/// Reads an uncompressed NBT document, returning the root tag.
+pub fn read(bytes: &[u8]) -> io::Result<Tag> {
+    let mut reader = Reader { bytes };
+    let id = reader.u8()?;
+    if id != 10 {
+        return Err(invalid_data("NBT root is not a compound"));
+    }
+    reader.string()?;
+    reader.payload(id, 0)
+}
+
+/// Writes `root`, which must be a compound, as an uncompressed NBT document.
+pub fn write(root_name: &str, root: &Tag) -> Vec<u8> {
+    assert!(matches!(root, Tag::Compound(_)));
+    let mut bytes = vec![root.id()];
+    write_string(root_name, &mut bytes);
+    write_payload(root, &mut bytes);
+    bytes
+}
+
+fn write_string(value: &str, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+fn write_payload(tag: &Tag, bytes: &mut Vec<u8>) {
+    match tag {
+        Tag::Byte(value) => bytes.push(*value as u8),
+        Tag::Short(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::Int(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::Long(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::Float(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::Double(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::ByteArray(values) => {
+            bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            bytes.extend(values.iter().map(|&value| value as u8));
+        }
+        Tag::String(value) => write_string(value, bytes),
+        Tag::List(values) => {
+            bytes.push(values.first().map_or(0, Tag::id));
+            bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values {
+                write_payload(value, bytes);
+            }
+        }
+        Tag::Compound(fields) => {
+            for (name, value) in fields {
+                bytes.push(value.id());
+                write_string(name, bytes);
+                write_payload(value, bytes);
+            }
+            bytes.push(0);
+        }
+        Tag::IntArray(values) => {
+            bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values {
+                bytes.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        Tag::LongArray(values) => {
+            bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values {
+                bytes.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated NBT document",
+            ));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    fn length(&mut self) -> io::Result<usize> {
+        let len = self.i32()?;
+        // Every element takes at least a byte, which bounds the allocation
+        if len < 0 || len as usize > self.bytes.len() {
+            return Err(invalid_data("invalid NBT length"));
+        }
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = u16::from_be_bytes(self.array()?) as usize;
+        // Java's modified UTF-8 only differs for NUL and supplementary
+        // characters, which block names don't use
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn payload(&mut self, id: u8, depth: usize) -> io::Result<Tag> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("NBT nested too deeply"));
+        }
+        Ok(match id {
+            1 => Tag::Byte(self.u8()? as i8),
+            2 => Tag::Short(i16::from_be_bytes(self.array()?)),
+            3 => Tag::Int(self.i32()?),
+            4 => Tag::Long(i64::from_be_bytes(self.array()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.array()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.array()?)),
+            7 => {
+                let len = self.length()?;
+                Tag::ByteArray(self.take(len)?.iter().map(|&b| b as i8).collect())
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let element_id = self.u8()?;
+                let len = self.length()?;
+                let mut values = Vec::with_capacity(len);
+                for _ in 0..len {
+                    values.push(self.payload(element_id, depth + 1)?);
+                }
+                Tag::List(values)
+            }
+            10 => {
+                let mut fields = HashMap::new();
+                loop {
+                    let field_id = self.u8()?;
+                    if field_id == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    fields.insert(name, self.payload(field_id, depth + 1)?);
+                }
+                Tag::Compound(fields)
+            }
+            11 => {
+                let len = self.length()?;
+                let values = (0..len).map(|_| self.i32()).collect::<io::Result<_>>()?;
+                Tag::IntArray(values)
+            }
+            12 => {
+                let len = self.length()?;
+                let values = (0..len)
+                    .map(|_| Ok(i64::from_be_bytes(self.array()?)))
+                    .collect::<io::Result<_>>()?;
+                Tag::LongArray(values)
+            }
+            _ => return Err(invalid_data(format!("unknown NBT tag {}", id))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let root = compound([
+            ("name", Tag::String("stone".to_string())),
+            ("size", Tag::Short(-3)),
+            ("empty", Tag::List(Vec::new())),
+            (
+                "states",
+                Tag::List(vec![compound([("Y", Tag::Byte(-4))]), compound([])]),
+            ),
+            ("data", Tag::LongArray(vec![i64::MIN, 1])),
+            ("bytes", Tag::ByteArray(vec![-1, 2])),
+        ]);
+        assert_eq!(read(&write("", &root)).unwrap(), root);
+
+        let bytes = write("", &root);
+        assert!(read(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use super::invalid_data;
+
+/// Edge length of a region, in columns.
+pub const REGION_SIZE: i32 = 32;
+/// Number of entries of a region file.
+pub const REGION_ENTRIES: usize = (REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: [u8; 4] = *b"BWRG";
+const VERSION: u32 = 1;
+const SECTOR_SIZE: u64 = 4096;
+const ENTRY_LEN: usize = 16;
+/// Magic, version, checksum of the entries and a reserved word.
+const HEADER_PREFIX_LEN: usize = 16;
+const HEADER_LEN: usize = HEADER_PREFIX_LEN + REGION_ENTRIES * ENTRY_LEN;
+const HEADER_SECTORS: u32 = (HEADER_LEN as u64).div_ceil(SECTOR_SIZE) as u32;
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Where the payload of one entry lives. An entry with `sector_count == 0` is
+/// empty.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct RegionEntry {
+    sector_offset: u32,
+    sector_count: u32,
+    /// Length of the compressed payload in bytes.
+    length: u32,
+    /// CRC-32 of the compressed payload.
+    checksum: u32,
+}
+
+impl RegionEntry {
+    fn sectors(&self) -> std::ops::Range<u32> {
+        self.sector_offset..self.sector_offset + self.sector_count
+    }
+}
+
+/// A file holding the payloads of `REGION_ENTRIES` entries, each compressed
+/// with zstd. The header maps every entry to a run of 4 KiB sectors, so a
+/// rewritten payload that still fits its sectors is written in place and
+/// only the header changes otherwise. The header and every payload carry a
+/// CRC-32 to detect corruption.
+pub struct RegionFile {
+    file: File,
+    entries: Vec<RegionEntry>,
+}
+
+impl RegionFile {
+    /// Opens the region file at `path`, creating an empty one if it doesn't
+    /// exist. Fails with `InvalidData` if the header is corrupted.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        if file.metadata()?.len() == 0 {
+            let mut region = Self {
+                file,
+                entries: vec![RegionEntry::default(); REGION_ENTRIES],
+            };
+            region.write_header()?;
+            return Ok(region);
+        }
+
+        let mut header = vec![0; HEADER_LEN];
+        file.read_exact(&mut header)?;
+        if header[0..4] != MAGIC {
+            return Err(invalid_data("not a region file"));
+        }
+        let version = read_u32(&header, 4);
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported region file version {}",
+                version
+            )));
+        }
+        let entry_bytes = &header[HEADER_PREFIX_LEN..];
+        if read_u32(&header, 8) != crc32fast::hash(entry_bytes) {
+            return Err(invalid_data("region file header checksum mismatch"));
+        }
+
+        let entries = entry_bytes
+            .chunks_exact(ENTRY_LEN)
+            .map(|entry| RegionEntry {
+                sector_offset: read_u32(entry, 0),
+                sector_count: read_u32(entry, 4),
+                length: read_u32(entry, 8),
+                checksum: read_u32(entry, 12),
+            })
+            .collect();
+        Ok(Self { file, entries })
+    }
+
+    /// The decompressed payload of entry `index`, if it has one. Fails with
+    /// `InvalidData` if the payload is corrupted.
+    pub fn read(&mut self, index: usize) -> io::Result<Option<Vec<u8>>> {
+        let entry = self.entries[index];
+        if entry.sector_count == 0 {
+            return Ok(None);
+        }
+
+        let mut compressed = vec![0; entry.length as usize];
+        self.file
+            .seek(SeekFrom::Start(entry.sector_offset as u64 * SECTOR_SIZE))?;
+        self.file.read_exact(&mut compressed)?;
+        if crc32fast::hash(&compressed) != entry.checksum {
+            return Err(invalid_data(format!(
+                "region entry {} checksum mismatch",
+                index
+            )));
+        }
+        zstd::decode_all(compressed.as_slice()).map(Some)
+    }
+
+    /// Compresses and stores `payload` as entry `index`. The payload is
+    /// rewritten in place if it still fits the sectors of the old one, and
+    /// moved to the first gap large enough otherwise.
+    pub fn write(&mut self, index: usize, payload: &[u8]) -> io::Result<()> {
+        let mut compressed = zstd::encode_all(payload, COMPRESSION_LEVEL)?;
+        let length = compressed.len() as u32;
+        let checksum = crc32fast::hash(&compressed);
+        let sector_count = (length as u64).div_ceil(SECTOR_SIZE) as u32;
+
+        let old = self.entries[index];
+        let sector_offset = if old.sector_count != 0 && sector_count <= old.sector_count {
+            old.sector_offset
+        } else {
+            self.find_free_sectors(index, sector_count)
+        };
+
+        compressed.resize(sector_count as usize * SECTOR_SIZE as usize, 0);
+        self.file
+            .seek(SeekFrom::Start(sector_offset as u64 * SECTOR_SIZE))?;
+        self.file.write_all(&compressed)?;
+
+        self.entries[index] = RegionEntry {
+            sector_offset,
+            sector_count,
+            length,
+            checksum,
+        };
+        self.write_header()
+    }
+
+    /// Waits until everything written is on disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+
+    /// Start of the first run of `count` sectors not used by any entry other
+    /// than `index`.
+    fn find_free_sectors(&self, index: usize, count: u32) -> u32 {
+        let mut used = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|&(i, entry)| i != index && entry.sector_count != 0)
+            .map(|(_, entry)| entry.sectors())
+            .collect::<Vec<_>>();
+        used.sort_by_key(|sectors| sectors.start);
+
+        let mut start = HEADER_SECTORS;
+        for sectors in used {
+            if sectors.start >= start + count {
+                break;
+            }
+            start = start.max(sectors.end);
+        }
+        start
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut entry_bytes = Vec::with_capacity(REGION_ENTRIES * ENTRY_LEN);
+        for entry in &self.entries {
+            for value in [
+                entry.sector_offset,
+                entry.sector_count,
+                entry.length,
+                entry.checksum,
+            ] {
+                entry_bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        let mut header = Vec::with_capacity(HEADER_SECTORS as usize * SECTOR_SIZE as usize);
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&crc32fast::hash(&entry_bytes).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&entry_bytes);
+        header.resize(HEADER_SECTORS as usize * SECTOR_SIZE as usize, 0);
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "block-world-{}-{}.region",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_rewrite_in_place() {
+        let path = temp_path("rewrite");
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(3, &[1; 1000]).unwrap();
+        region.write(7, &[2; 1000]).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+
+        region.write(3, &[3; 2000]).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_eq!(region.read(3).unwrap(), Some(vec![3; 2000]));
+        assert_eq!(region.read(7).unwrap(), Some(vec![2; 1000]));
+        assert_eq!(region.read(4).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_grown_payload_moves() {
+        let path = temp_path("grow");
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(0, &[0; 16]).unwrap();
+        region.write(1, &[1; 16]).unwrap();
+
+        // Incompressible, so it no longer fits a single sector
+        let mut state = 1u32;
+        let noise = (0..20000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        region.write(0, &noise).unwrap();
+        assert_ne!(region.entries[0].sector_offset, HEADER_SECTORS);
+        // The old sectors of entry 0 are reused
+        region.write(2, &[2; 16]).unwrap();
+        assert_eq!(region.entries[2].sector_offset, HEADER_SECTORS);
+
+        assert_eq!(region.read(0).unwrap(), Some(noise));
+        assert_eq!(region.read(1).unwrap(), Some(vec![1; 16]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_detects_corruption() {
+        let path = temp_path("corrupt");
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(5, &[5; 100]).unwrap();
+        drop(region);
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[HEADER_SECTORS as usize * SECTOR_SIZE as usize + 4] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        let mut region = RegionFile::open(&path).unwrap();
+        let err = region.read(5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        bytes[HEADER_PREFIX_LEN + 5 * ENTRY_LEN] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        let err = RegionFile::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::{
+    types::BlockRegistry,
+    worldgen::structure::{Structure, StructureBlock},
+};
+
+use super::{
+    invalid_data,
+    minecraft::MinecraftBlocks,
+    nbt::{self, compound, Tag},
+};
+
+/// Version of the Sponge schematic format written.
+const SCHEMATIC_VERSION: i32 = 2;
+/// Minecraft data version written, that of 1.20.1.
+const DATA_VERSION: i32 = 3465;
+
+/// Reads a gzip-compressed Sponge schematic (`.schem`, version 2 or 3) as a
+/// structure named `name`. Air is kept, so pasting clears what the schematic
+/// covers, like WorldEdit does by default. Block entities and biomes are
+/// ignored.
+pub fn read_schematic(
+    bytes: &[u8],
+    name: &str,
+    block_registry: &BlockRegistry,
+) -> io::Result<Structure> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    let root = nbt::read(&decompressed)?;
+
+    // Version 3 nests everything in "Schematic" and the blocks in "Blocks"
+    let (schematic, palette, data) = match root.get("Schematic") {
+        Some(schematic) => {
+            let blocks = schematic
+                .get("Blocks")
+                .ok_or_else(|| invalid_data("schematic has no blocks"))?;
+            (schematic, blocks.get("Palette"), blocks.get("Data"))
+        }
+        None => (&root, root.get("Palette"), root.get("BlockData")),
+    };
+    let dimension = |name: &str| {
+        schematic
+            .get(name)
+            .and_then(Tag::as_i64)
+            // Dimensions are unsigned shorts
+            .map(|value| (value & 0xffff) as usize)
+            .ok_or_else(|| invalid_data(format!("schematic has no {}", name)))
+    };
+    let (width, height, length) = (
+        dimension("Width")?,
+        dimension("Height")?,
+        dimension("Length")?,
+    );
+    let offset = match schematic.get("Offset").and_then(Tag::as_int_array) {
+        Some(&[x, y, z]) => [x, y, z],
+        _ => [0; 3],
+    };
+
+    let mut blocks = MinecraftBlocks::new(block_registry);
+    let mut palette_ids = Vec::new();
+    for (state, index) in palette
+        .and_then(Tag::as_compound)
+        .ok_or_else(|| invalid_data("schematic has no palette"))?
+    {
+        let index = index
+            .as_i64()
+            .and_then(|index| usize::try_from(index).ok())
+            .ok_or_else(|| invalid_data("invalid schematic palette index"))?;
+        if index >= palette_ids.len() {
+            palette_ids.resize(index + 1, None);
+        }
+        palette_ids[index] = Some(blocks.block_type(state, block_registry));
+    }
+
+    let data = data
+        .and_then(Tag::as_byte_array)
+        .ok_or_else(|| invalid_data("schematic has no block data"))?;
+    // Every block takes at least a byte of data, so a volume beyond it is
+    // rejected before anything is allocated for it
+    let volume = width
+        .checked_mul(height)
+        .and_then(|area| area.checked_mul(length))
+        .filter(|&volume| volume <= data.len())
+        .ok_or_else(|| invalid_data("schematic block data is shorter than its volume"))?;
+    let mut data = data.iter().map(|&byte| byte as u8);
+    let mut structure_blocks = Vec::with_capacity(volume);
+    for y in 0..height {
+        for z in 0..length {
+            for x in 0..width {
+                let index = read_varint(&mut data)?;
+                let block_type_id = palette_ids
+                    .get(index)
+                    .copied()
+                    .flatten()
+                    .ok_or_else(|| invalid_data("schematic block is out of its palette"))?;
+                structure_blocks.push(StructureBlock {
+                    offset: [
+                        x as i32 + offset[0],
+                        y as i32 + offset[1],
+                        z as i32 + offset[2],
+                    ],
+                    block_type_id,
+                    replace_solid: true,
+                });
+            }
+        }
+    }
+
+    Ok(Structure {
+        name: name.to_string(),
+        blocks: structure_blocks,
+    })
+}
+
+/// Writes `structure` as a gzip-compressed Sponge schematic (version 2)
+/// spanning its bounds. Positions without a block are air, and the offset of
+/// the lowest corner is kept as the schematic offset.
+pub fn write_schematic(
+    structure: &Structure,
+    block_registry: &BlockRegistry,
+) -> io::Result<Vec<u8>> {
+    let (min, max) = structure.bounds().unwrap_or(([0; 3], [0; 3]));
+    let size = [0, 1, 2].map(|i| (max[i] - min[i] + 1) as usize);
+    if size.iter().any(|&size| size > u16::MAX as usize) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "structure is too large for a schematic",
+        ));
+    }
+    let [width, height, length] = size;
+
+    let mut palette = HashMap::from([(MinecraftBlocks::minecraft_name("air"), 0)]);
+    let mut indices = vec![0; width * height * length];
+    for block in &structure.blocks {
+        let name =
+            MinecraftBlocks::minecraft_name(&block_registry.block_types[block.block_type_id].name);
+        let next_index = palette.len();
+        let palette_index = *palette.entry(name).or_insert(next_index);
+        let [x, y, z] = [0, 1, 2].map(|i| (block.offset[i] - min[i]) as usize);
+        indices[x + z * width + y * width * length] = palette_index;
+    }
+
+    let mut data = Vec::new();
+    for index in indices {
+        write_varint(index, &mut data);
+    }
+    let root = compound([
+        ("Version", Tag::Int(SCHEMATIC_VERSION)),
+        ("DataVersion", Tag::Int(DATA_VERSION)),
+        ("Width", Tag::Short(width as u16 as i16)),
+        ("Height", Tag::Short(height as u16 as i16)),
+        ("Length", Tag::Short(length as u16 as i16)),
+        ("Offset", Tag::IntArray(min.to_vec())),
+        ("PaletteMax", Tag::Int(palette.len() as i32)),
+        (
+            "Palette",
+            Tag::Compound(
+                palette
+                    .into_iter()
+                    .map(|(name, index)| (name, Tag::Int(index as i32)))
+                    .collect(),
+            ),
+        ),
+        (
+            "BlockData",
+            Tag::ByteArray(data.into_iter().map(|byte| byte as i8).collect()),
+        ),
+    ]);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&nbt::write("Schematic", &root))?;
+    encoder.finish()
+}
+
+/// Reads the schematic at `path`, naming the structure after the file.
+pub fn load_schematic(
+    path: impl AsRef<Path>,
+    block_registry: &BlockRegistry,
+) -> io::Result<Structure> {
+    let path = path.as_ref();
+    let name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("schematic");
+    read_schematic(&fs::read(path)?, name, block_registry)
+}
+
+pub fn save_schematic(
+    path: impl AsRef<Path>,
+    structure: &Structure,
+    block_registry: &BlockRegistry,
+) -> io::Result<()> {
+    fs::write(path, write_schematic(structure, block_registry)?)
+}
+
+/// Reads an unsigned LEB128 varint, as block data is stored in.
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> io::Result<usize> {
+    let mut value = 0;
+    for shift in (0..32).step_by(7) {
+        let byte = bytes
+            .next()
+            .ok_or_else(|| invalid_data("schematic block data is truncated"))?;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("schematic varint is too long"))
+}
+
+fn write_varint(mut value: usize, bytes: &mut Vec<u8>) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::worldgen::structure::Rotation;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let block_registry = BlockRegistry::default();
+        let id = |name: &str| block_registry.block_types.get_index_of(name).unwrap();
+        let block = |offset, name| StructureBlock {
+            offset,
+            block_type_id: id(name),
+            replace_solid: true,
+        };
+        let structure = Structure {
+            name: "hut".to_string(),
+            blocks: vec![
+                block([-2, 0, 1], "log"),
+                block([200, 0, 1], "grass"),
+                block([-2, 3, 4], "leaves"),
+            ],
+        };
+
+        let bytes = write_schematic(&structure, &block_registry).unwrap();
+        let read = read_schematic(&bytes, "hut", &block_registry).unwrap();
+        assert_eq!(read.bounds(), structure.bounds());
+        assert_eq!(read.blocks.len(), 203 * 4 * 4);
+        for expected in &structure.blocks {
+            assert!(read.blocks.contains(expected));
+        }
+        let air = read
+            .blocks
+            .iter()
+            .filter(|block| block.block_type_id == id("air"))
+            .count();
+        assert_eq!(air, read.blocks.len() - 3);
+
+        // Rotating keeps the block count and turns the bounds
+        let rotated = read.rotated(Rotation::Clockwise90);
+        assert_eq!(rotated.bounds(), Some(([-4, 0, -2], [-1, 3, 200])));
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut bytes = Vec::new();
+        for value in [0, 127, 128, 300, 70000] {
+            write_varint(value, &mut bytes);
+        }
+        let mut bytes = bytes.into_iter();
+        for value in [0, 127, 128, 300, 70000] {
+            assert_eq!(read_varint(&mut bytes).unwrap(), value);
+        }
+        assert!(read_varint(&mut bytes).is_err());
+    }
+}
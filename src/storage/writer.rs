@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use log::warn;
+
+use crate::types::{Chunk, ChunkPosition, ColumnPosition};
+
+use super::RegionStorage;
+
+type Sections = Arc<Vec<(ChunkPosition, Chunk)>>;
+
+/// Columns queued but not written yet, by the latest sections queued for
+/// them. Notifies the condition whenever a column was written.
+#[derive(Default)]
+struct Pending {
+    columns: Mutex<HashMap<ColumnPosition, Sections>>,
+    written: Condvar,
+}
+
+/// Writes columns to a `RegionStorage` on a background thread, so saving
+/// never blocks the caller on disk. Columns are handed over as copies of
+/// their sections. Until a column is written, loading it returns the copy
+/// queued last, and only the copy queued last is ever written, so a column
+/// queued again before the thread got to it is written once.
+pub struct ColumnWriter {
+    storage: Arc<Mutex<RegionStorage>>,
+    pending: Arc<Pending>,
+    sender: Option<Sender<ColumnPosition>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ColumnWriter {
+    pub fn new(storage: RegionStorage) -> Self {
+        let storage = Arc::new(Mutex::new(storage));
+        let pending = Arc::new(Pending::default());
+        let (sender, receiver) = channel::<ColumnPosition>();
+        let thread = {
+            let storage = storage.clone();
+            let pending = pending.clone();
+            thread::Builder::new()
+                .name("column writer".to_string())
+                .spawn(move || {
+                    for column in receiver {
+                        let Some(sections) = pending.columns.lock().unwrap().get(&column).cloned()
+                        else {
+                            continue;
+                        };
+                        if let Err(err) = storage.lock().unwrap().save_column(column, &sections) {
+                            warn!("Failed to save column {:?}: {}", column, err);
+                        }
+                        let mut columns = pending.columns.lock().unwrap();
+                        // Queued again while it was written
+                        if columns
+                            .get(&column)
+                            .is_some_and(|queued| Arc::ptr_eq(queued, &sections))
+                        {
+                            columns.remove(&column);
+                        }
+                        pending.written.notify_all();
+                    }
+                })
+                .unwrap()
+        };
+        Self {
+            storage,
+            pending,
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    /// Queues `sections` to be written as `column`, replacing what was saved
+    /// or queued before.
+    pub fn save_column(&self, column: ColumnPosition, sections: Vec<(ChunkPosition, Chunk)>) {
+        self.pending
+            .columns
+            .lock()
+            .unwrap()
+            .insert(column, Arc::new(sections));
+        self.sender.as_ref().unwrap().send(column).unwrap();
+    }
+
+    /// The sections of `column` queued last, or the saved ones if none are
+    /// queued.
+    pub fn load_column(
+        &self,
+        column: ColumnPosition,
+    ) -> io::Result<Option<Vec<(ChunkPosition, Chunk)>>> {
+        if let Some(sections) = self.pending.columns.lock().unwrap().get(&column) {
+            return Ok(Some(sections.as_ref().clone()));
+        }
+        self.storage.lock().unwrap().load_column(column)
+    }
+
+    /// Number of columns queued and not written yet.
+    pub fn pending(&self) -> usize {
+        self.pending.columns.lock().unwrap().len()
+    }
+
+    /// Waits until every queued column is written.
+    pub fn flush(&self) {
+        let columns = self.pending.columns.lock().unwrap();
+        drop(
+            self.pending
+                .written
+                .wait_while(columns, |columns| !columns.is_empty())
+                .unwrap(),
+        );
+    }
+
+    /// Runs `f` on the storage once every queued column is written.
+    pub fn with_storage<T>(&self, f: impl FnOnce(&mut RegionStorage) -> T) -> T {
+        self.flush();
+        f(&mut self.storage.lock().unwrap())
+    }
+}
+
+impl Drop for ColumnWriter {
+    /// Writes the queued columns before returning.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use crate::types::BlockRegistry;
+
+    use super::*;
+
+    fn temp_directory() -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("block-world-writer-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn test_background_writes() {
+        let directory = temp_directory();
+        let block_registry = BlockRegistry::default();
+        let writer = ColumnWriter::new(RegionStorage::new(&directory, &block_registry).unwrap());
+
+        let column = ColumnPosition { x: 1, z: 2 };
+        let position = ChunkPosition { x: 1, y: 0, z: 2 };
+        for block in 1..=3 {
+            let mut chunk = Chunk::default();
+            chunk.blocks[0][0][0] = block;
+            writer.save_column(column, vec![(position, chunk)]);
+            // Queued columns load before they are written
+            let sections = writer.load_column(column).unwrap().unwrap();
+            assert_eq!(sections[0].1.blocks[0][0][0], block);
+        }
+        writer.flush();
+        assert_eq!(writer.pending(), 0);
+        drop(writer);
+
+        let mut storage = RegionStorage::new(&directory, &block_registry).unwrap();
+        let sections = storage.load_column(column).unwrap().unwrap();
+        assert_eq!(sections[0].1.blocks[0][0][0], 3);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
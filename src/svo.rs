@@ -0,0 +1,137 @@
+//! Sparse voxel octrees of sections. A region whose blocks are all of one
+//! type is one node however large it is, so sections that are mostly air,
+//! like those around floating islands, take a fraction of the memory of the
+//! dense `Chunk::blocks`, and rays can cross their empty regions in one step.
+
+use crate::types::{BlockTypeId, Chunk, CHUNK_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Node {
+    /// A cube of blocks all of the type.
+    Leaf(BlockTypeId),
+    /// Index of the first of the eight children, which follow each other.
+    /// Child `i` is the upper half along x if bit 0 is set, along y for bit
+    /// 1 and along z for bit 2.
+    Branch(u32),
+}
+
+/// The octree of the `CHUNK_SIZE`³ blocks of a section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseVoxelOctree {
+    /// The root comes first, the children of every branch after it.
+    pub nodes: Vec<Node>,
+}
+
+/// An octree before it is laid out in `SparseVoxelOctree::nodes`.
+enum Tree {
+    Leaf(BlockTypeId),
+    Branch(Box<[Tree; 8]>),
+}
+
+impl SparseVoxelOctree {
+    /// The octree of the blocks of `chunk`.
+    pub fn from_chunk(chunk: &Chunk) -> Self {
+        fn build(chunk: &Chunk, min: [usize; 3], size: usize) -> Tree {
+            if size == 1 {
+                let [x, y, z] = min;
+                return Tree::Leaf(chunk.blocks[y][x][z]);
+            }
+            let half = size / 2;
+            let children = Box::new(std::array::from_fn(|i| {
+                let child_min = [0, 1, 2].map(|axis| min[axis] + (i >> axis & 1) * half);
+                build(chunk, child_min, half)
+            }));
+            let first = match children[0] {
+                Tree::Leaf(block_type_id) => Some(block_type_id),
+                Tree::Branch(_) => None,
+            };
+            match first {
+                Some(first)
+                    if children
+                        .iter()
+                        .all(|child| matches!(child, Tree::Leaf(id) if *id == first)) =>
+                {
+                    Tree::Leaf(first)
+                }
+                _ => Tree::Branch(children),
+            }
+        }
+
+        fn lay_out(tree: Tree, index: usize, nodes: &mut Vec<Node>) {
+            match tree {
+                Tree::Leaf(block_type_id) => nodes[index] = Node::Leaf(block_type_id),
+                Tree::Branch(children) => {
+                    let first = nodes.len();
+                    nodes[index] = Node::Branch(first as u32);
+                    // Placeholders until the children are laid out
+                    nodes.extend([nodes[index]; 8]);
+                    for (i, child) in children.into_iter().enumerate() {
+                        lay_out(child, first + i, nodes);
+                    }
+                }
+            }
+        }
+
+        let mut nodes = vec![Node::Branch(0)];
+        lay_out(build(chunk, [0; 3], CHUNK_SIZE), 0, &mut nodes);
+        Self { nodes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The type of the block `[x, y, z]` of the section, found like the
+    /// shaders do.
+    fn block(octree: &SparseVoxelOctree, position: [usize; 3]) -> BlockTypeId {
+        let mut node = octree.nodes[0];
+        let mut size = CHUNK_SIZE;
+        loop {
+            match node {
+                Node::Leaf(block_type_id) => return block_type_id,
+                Node::Branch(first) => {
+                    size /= 2;
+                    let child = (0..3).fold(0, |child, axis| {
+                        child | ((position[axis] & size != 0) as usize) << axis
+                    });
+                    node = octree.nodes[first as usize + child];
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sparse_voxel_octree() {
+        // Uniform sections are a single leaf
+        let mut chunk = Chunk::default();
+        assert_eq!(SparseVoxelOctree::from_chunk(&chunk).nodes, [Node::Leaf(0)]);
+
+        // One block splits a node at every level on its way down
+        chunk.blocks[3][5][9] = 2;
+        let octree = SparseVoxelOctree::from_chunk(&chunk);
+        assert_eq!(octree.nodes.len(), 1 + 8 * 4);
+        assert_eq!(block(&octree, [5, 3, 9]), 2);
+        assert_eq!(block(&octree, [5, 3, 8]), 0);
+
+        // Solid below half the height, air above, leaves under the root
+        for layer in &mut chunk.blocks[..CHUNK_SIZE / 2] {
+            *layer = [[1; CHUNK_SIZE]; CHUNK_SIZE];
+        }
+        let octree = SparseVoxelOctree::from_chunk(&chunk);
+        assert_eq!(octree.nodes.len(), 1 + 8);
+        assert_eq!(octree.nodes[1], Node::Leaf(1));
+        assert_eq!(octree.nodes[3], Node::Leaf(0));
+
+        chunk.blocks[15][0][15] = 3;
+        chunk.blocks[0][15][0] = 4;
+        let octree = SparseVoxelOctree::from_chunk(&chunk);
+        for (y, layer) in chunk.blocks.iter().enumerate() {
+            for (x, row) in layer.iter().enumerate() {
+                for (z, &block_type_id) in row.iter().enumerate() {
+                    assert_eq!(block(&octree, [x, y, z]), block_type_id);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+/// Empty pixels kept around every glyph so filtering never picks up its
+/// neighbours.
+const GAP: u32 = 1;
+
+/// A single channel image the rasterized glyphs are packed into, row by row
+/// on shelves as high as the tallest glyph on them.
+#[derive(Debug, Clone)]
+pub struct GlyphAtlas {
+    size: u32,
+    /// Coverage of every pixel, row by row.
+    pixels: Vec<u8>,
+    shelf_top: u32,
+    shelf_height: u32,
+    /// Left edge of the free space on the current shelf.
+    cursor: u32,
+    /// Changes with every change of `pixels`, so uploads can tell whether
+    /// their copy is outdated.
+    version: u64,
+}
+
+impl GlyphAtlas {
+    /// An empty atlas of `size` by `size` pixels.
+    pub fn new(size: u32) -> Self {
+        Self {
+            size,
+            pixels: vec![0; (size * size) as usize],
+            shelf_top: 0,
+            shelf_height: 0,
+            cursor: 0,
+            version: 0,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Copies `bitmap`, `width` by `height` coverage values row by row, into
+    /// free space and returns its top left corner, or `None` if it does not
+    /// fit anymore.
+    pub fn insert(&mut self, width: u32, height: u32, bitmap: &[u8]) -> Option<[u32; 2]> {
+        assert_eq!(bitmap.len(), (width * height) as usize);
+        if self.cursor + width + GAP > self.size {
+            self.shelf_top += self.shelf_height;
+            self.shelf_height = 0;
+            self.cursor = 0;
+        }
+        if self.cursor + width + GAP > self.size || self.shelf_top + height + GAP > self.size {
+            return None;
+        }
+
+        let [x, y] = [self.cursor, self.shelf_top];
+        if width > 0 {
+            for (row, line) in bitmap.chunks_exact(width as usize).enumerate() {
+                let start = ((y + row as u32) * self.size + x) as usize;
+                self.pixels[start..start + width as usize].copy_from_slice(line);
+            }
+        }
+        self.cursor += width + GAP;
+        self.shelf_height = self.shelf_height.max(height + GAP);
+        self.version += 1;
+        Some([x, y])
+    }
+
+    /// Frees all the space, leaving the pixels to be overwritten.
+    pub fn clear(&mut self) {
+        self.shelf_top = 0;
+        self.shelf_height = 0;
+        self.cursor = 0;
+        self.version += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glyph_atlas() {
+        let mut atlas = GlyphAtlas::new(8);
+        assert_eq!(atlas.insert(3, 2, &[1; 6]), Some([0, 0]));
+        assert_eq!(atlas.insert(2, 4, &[2; 8]), Some([4, 0]));
+        assert_eq!(atlas.pixels()[..8], [1, 1, 1, 0, 2, 2, 0, 0]);
+        assert_eq!(atlas.pixels()[8..16], [1, 1, 1, 0, 2, 2, 0, 0]);
+
+        // Too wide for the shelf, so it starts the next one below the
+        // tallest glyph
+        let version = atlas.version();
+        assert_eq!(atlas.insert(3, 1, &[3; 3]), Some([0, 5]));
+        assert!(atlas.version() > version);
+        assert_eq!(atlas.insert(1, 3, &[4; 3]), None);
+
+        atlas.clear();
+        assert_eq!(atlas.insert(7, 7, &[5; 49]), Some([0, 0]));
+        assert_eq!(atlas.insert(8, 1, &[6; 8]), None);
+    }
+}
@@ -0,0 +1,201 @@
+//! Text drawn from a TrueType font: glyphs are rasterized on first use at the
+//! pixel size asked for and cached in a `GlyphAtlas`, and strings are laid
+//! out as quads sampling the atlas, for the HUD, debug overlays and chat.
+
+mod atlas;
+
+use std::collections::HashMap;
+
+use fontdue::{Font, FontSettings};
+
+pub use atlas::GlyphAtlas;
+
+/// The font used unless another one is given, see `fonts/LICENSE-DejaVu.txt`.
+const DEFAULT_FONT: &[u8] = include_bytes!("../../fonts/DejaVuSansMono.ttf");
+pub const ATLAS_SIZE: u32 = 1024;
+
+/// A glyph of laid out text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlyphQuad {
+    /// Top left corner, in pixels.
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    /// Left, top, right and bottom edges of the glyph in the atlas, from 0
+    /// to 1.
+    pub uv: [f32; 4],
+}
+
+/// A rasterized glyph, placed relative to the pen on the top of the line.
+#[derive(Debug, Clone, Copy)]
+struct Glyph {
+    offset: [f32; 2],
+    size: [f32; 2],
+    /// `None` for glyphs without pixels, like spaces.
+    uv: Option<[f32; 4]>,
+    advance: f32,
+}
+
+pub struct TextRenderer {
+    font: Font,
+    atlas: GlyphAtlas,
+    /// Glyphs in the atlas, by character and pixel size.
+    glyphs: HashMap<(char, u32), Glyph>,
+    /// Whether a glyph did not fit into the atlas, which is then emptied
+    /// before the next frame's text.
+    overflowed: bool,
+}
+
+impl Default for TextRenderer {
+    fn default() -> Self {
+        Self::new(DEFAULT_FONT)
+    }
+}
+
+impl TextRenderer {
+    /// Uses the TrueType or OpenType font in `font_data`.
+    pub fn new(font_data: &[u8]) -> Self {
+        let font = Font::from_bytes(font_data, FontSettings::default()).expect("invalid font");
+        Self {
+            font,
+            atlas: GlyphAtlas::new(ATLAS_SIZE),
+            glyphs: HashMap::new(),
+            overflowed: false,
+        }
+    }
+
+    pub fn atlas(&self) -> &GlyphAtlas {
+        &self.atlas
+    }
+
+    /// Starts the text of a frame. Glyphs laid out before stay valid until
+    /// then, so the atlas is only emptied here when it ran full.
+    pub fn begin_frame(&mut self) {
+        if self.overflowed {
+            self.atlas.clear();
+            self.glyphs.clear();
+            self.overflowed = false;
+        }
+    }
+
+    /// Pixels from the top of a line of text of `size` to the next.
+    pub fn line_height(&self, size: f32) -> f32 {
+        self.font
+            .horizontal_line_metrics(size)
+            .map_or(size, |metrics| metrics.new_line_size.ceil())
+    }
+
+    /// Lays out `text` in glyphs `size` pixels high with its top left corner
+    /// at `position`, appending them to `quads`. `\n` starts a new line.
+    /// Returns the width of the longest line.
+    pub fn layout(
+        &mut self,
+        position: [f32; 2],
+        size: f32,
+        text: &str,
+        quads: &mut Vec<GlyphQuad>,
+    ) -> f32 {
+        let line_height = self.line_height(size);
+        let mut width = 0.0f32;
+        for (line_index, line) in text.split('\n').enumerate() {
+            let top = position[1] + line_index as f32 * line_height;
+            let mut pen = 0.0;
+            for c in line.chars() {
+                let Some(glyph) = self.glyph(c, size) else {
+                    continue;
+                };
+                if let Some(uv) = glyph.uv {
+                    quads.push(GlyphQuad {
+                        // Whole pixels keep the glyphs sharp
+                        position: [
+                            (position[0] + pen + glyph.offset[0]).round(),
+                            (top + glyph.offset[1]).round(),
+                        ],
+                        size: glyph.size,
+                        uv,
+                    });
+                }
+                pen += glyph.advance;
+            }
+            width = width.max(pen);
+        }
+        width
+    }
+
+    /// The glyph of `c` at `size`, rasterized into the atlas if it is not
+    /// yet. `None` if the atlas is full.
+    fn glyph(&mut self, c: char, size: f32) -> Option<Glyph> {
+        let pixels = size.round().max(1.0) as u32;
+        if let Some(&glyph) = self.glyphs.get(&(c, pixels)) {
+            return Some(glyph);
+        }
+
+        let px = pixels as f32;
+        let (metrics, bitmap) = self.font.rasterize(c, px);
+        let [width, height] = [metrics.width as u32, metrics.height as u32];
+        let uv = if width == 0 || height == 0 {
+            None
+        } else {
+            let Some([x, y]) = self.atlas.insert(width, height, &bitmap) else {
+                self.overflowed = true;
+                return None;
+            };
+            let atlas_size = self.atlas.size() as f32;
+            Some([
+                x as f32 / atlas_size,
+                y as f32 / atlas_size,
+                (x + width) as f32 / atlas_size,
+                (y + height) as f32 / atlas_size,
+            ])
+        };
+        let ascent = self
+            .font
+            .horizontal_line_metrics(px)
+            .map_or(px, |metrics| metrics.ascent);
+        let glyph = Glyph {
+            // The bitmap's bottom is `ymin` above the baseline
+            offset: [
+                metrics.xmin as f32,
+                (ascent - (metrics.ymin + metrics.height as i32) as f32).round(),
+            ],
+            size: [width as f32, height as f32],
+            uv,
+            advance: metrics.advance_width,
+        };
+        self.glyphs.insert((c, pixels), glyph);
+        Some(glyph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let mut text = TextRenderer::default();
+        let mut quads = Vec::new();
+        let width = text.layout([10.0, 20.0], 16.0, "Ab c", &mut quads);
+        // No quad for the space, and the font is monospaced
+        assert_eq!(quads.len(), 3);
+        let advance = width / 4.0;
+        assert!(advance > 4.0 && advance < 16.0);
+        assert!(quads[2].position[0] >= 10.0 + advance * 3.0 - 1.0);
+        for quad in &quads {
+            assert!(quad.position[1] >= 20.0 && quad.position[1] + quad.size[1] <= 40.0);
+            let [left, top, right, bottom] = quad.uv.map(|v| v * ATLAS_SIZE as f32);
+            assert_eq!([right - left, bottom - top], quad.size);
+        }
+
+        // Cached glyphs leave the atlas alone, other sizes are added
+        let version = text.atlas().version();
+        let mut again = Vec::new();
+        text.layout([10.0, 20.0], 16.0, "Ab c", &mut again);
+        assert_eq!(again, quads);
+        assert_eq!(text.atlas().version(), version);
+        let mut larger = Vec::new();
+        let larger_width = text.layout([0.0, 0.0], 32.0, "Ab c\nd", &mut larger);
+        assert!(text.atlas().version() > version);
+        assert!((larger_width - width * 2.0).abs() < 1.0);
+        assert!(larger[3].position[1] >= text.line_height(32.0));
+    }
+}
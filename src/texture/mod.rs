@@ -1,11 +1,61 @@
-use std::ops::Deref;
+use std::{
+    borrow::Cow,
+    fs, io,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
 
-use image::RgbaImage;
-use indexmap::{indexmap, IndexMap};
+use image::{Rgba, RgbaImage};
+use indexmap::IndexMap;
+use log::{info, warn};
+
+use crate::types::TextureId;
+
+pub const TEXTURE_DIRECTORY: &str = "textures";
+/// Name under which the generated placeholder texture is registered.
+pub const MISSING_TEXTURE: &str = "missing";
+const DEFAULT_TEXTURE_SIZE: u32 = 16;
+/// Suffix of the file stem of a normal map, which belongs to the texture
+/// named by the rest of the stem.
+pub const NORMAL_MAP_SUFFIX: &str = "_n";
 
 #[derive(Debug, Clone)]
 pub struct Texture {
     pub image: RgbaImage,
+    /// Tangent space normals, with red toward increasing u and green toward
+    /// decreasing v like OpenGL normal maps.
+    pub normal_map: Option<RgbaImage>,
+}
+
+impl Texture {
+    /// The normal map, or one of normals straight out of the surface.
+    pub fn normal_map_or_flat(&self) -> Cow<'_, RgbaImage> {
+        match &self.normal_map {
+            Some(normal_map) => Cow::Borrowed(normal_map),
+            None => Cow::Owned(RgbaImage::from_pixel(
+                self.image.width(),
+                self.image.height(),
+                Rgba([128, 128, 255, 255]),
+            )),
+        }
+    }
+
+    /// Mean color of the opaque parts of the image, weighted by alpha, to
+    /// represent the texture where it is too small to be drawn.
+    pub fn average_color(&self) -> [u8; 3] {
+        let mut sum = [0u64; 3];
+        let mut weight = 0u64;
+        for Rgba([r, g, b, a]) in self.image.pixels() {
+            for (sum, channel) in sum.iter_mut().zip([r, g, b]) {
+                *sum += *channel as u64 * *a as u64;
+            }
+            weight += *a as u64;
+        }
+        if weight == 0 {
+            return [0; 3];
+        }
+        sum.map(|sum| (sum / weight) as u8)
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -13,11 +63,183 @@ pub struct TextureRegistry(pub IndexMap<String, Texture>);
 
 impl TextureRegistry {
     pub fn new() -> Self {
-        let stone_image = image::open("stone.png").unwrap().to_rgba8();
-        TextureRegistry(indexmap! {
-            "stone".to_string() => Texture { image: stone_image },
-        })
+        Self::from_directory(TEXTURE_DIRECTORY).unwrap()
+    }
+
+    /// Registers every PNG found under `directory` (recursively) by its
+    /// filename stem.
+    ///
+    /// All textures end up in a single texture array on the GPU, so they must
+    /// be square and share the dimensions of the first texture loaded. Files
+    /// that fail to decode, have mismatched dimensions or reuse an already
+    /// registered name are reported and skipped. Images whose stem ends with
+    /// `NORMAL_MAP_SUFFIX` become the normal map of the texture they name.
+    pub fn from_directory(directory: impl AsRef<Path>) -> io::Result<Self> {
+        let mut paths = Vec::new();
+        collect_pngs(directory.as_ref(), &mut paths)?;
+        // Sort so texture indices don't depend on the file system's ordering.
+        paths.sort();
+
+        let mut textures: IndexMap<String, Texture> = IndexMap::new();
+        let mut origins: IndexMap<String, PathBuf> = IndexMap::new();
+        let mut normal_maps = Vec::new();
+        let mut dimensions = None;
+
+        for path in paths {
+            let Some(name) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(str::to_string)
+            else {
+                warn!("Skipping texture with non UTF-8 name: {:?}", path);
+                continue;
+            };
+
+            if let Some(origin) = origins.get(&name) {
+                warn!(
+                    "Duplicate texture name {:?}: {:?} is shadowed by {:?}",
+                    name, path, origin
+                );
+                continue;
+            }
+
+            let image = match image::open(&path) {
+                Ok(image) => image.to_rgba8(),
+                Err(err) => {
+                    warn!("Failed to load texture {:?}: {}", path, err);
+                    continue;
+                }
+            };
+
+            if image.width() != image.height() {
+                warn!(
+                    "Texture {:?} is not square ({}x{})",
+                    path,
+                    image.width(),
+                    image.height()
+                );
+                continue;
+            }
+            let expected = *dimensions.get_or_insert(image.dimensions());
+            if image.dimensions() != expected {
+                warn!(
+                    "Texture {:?} is {}x{}, expected {}x{}",
+                    path,
+                    image.width(),
+                    image.height(),
+                    expected.0,
+                    expected.1
+                );
+                continue;
+            }
+
+            if let Some(texture) = name.strip_suffix(NORMAL_MAP_SUFFIX) {
+                normal_maps.push((texture.to_string(), path, image));
+                continue;
+            }
+            origins.insert(name.clone(), path);
+            textures.insert(
+                name,
+                Texture {
+                    image,
+                    normal_map: None,
+                },
+            );
+        }
+
+        for (name, path, normal_map) in normal_maps {
+            match textures.get_mut(&name) {
+                Some(texture) => texture.normal_map = Some(normal_map),
+                None => warn!("Normal map {:?} has no texture {:?}", path, name),
+            }
+        }
+
+        info!(
+            "Loaded {} textures from {:?}",
+            textures.len(),
+            directory.as_ref()
+        );
+        Ok(Self(textures))
+    }
+
+    /// Returns the names from `names` that are not registered, logging each one.
+    pub fn report_missing<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+        let mut missing = Vec::new();
+        for name in names {
+            if !self.contains_key(name) && !missing.contains(&name) {
+                warn!("Missing texture {:?}", name);
+                missing.push(name);
+            }
+        }
+        missing
+    }
+
+    /// Returns the index of `name`, or of the placeholder texture if `name` is
+    /// not registered. The placeholder is generated on first use.
+    pub fn get_index_or_missing(&mut self, name: &str) -> TextureId {
+        if let Some(index) = self.get_index_of(name) {
+            return index;
+        }
+        warn!("Missing texture {:?}, using placeholder", name);
+        self.missing_texture_index()
+    }
+
+    pub fn missing_texture_index(&mut self) -> TextureId {
+        self.get_index_or_placeholder(MISSING_TEXTURE)
+    }
+
+    /// Registers a placeholder image under `name` unless it is registered
+    /// already, for registries whose images are loaded later.
+    pub fn get_index_or_placeholder(&mut self, name: &str) -> TextureId {
+        if let Some(index) = self.get_index_of(name) {
+            return index;
+        }
+        let (size, _) = self
+            .dimensions()
+            .unwrap_or((DEFAULT_TEXTURE_SIZE, DEFAULT_TEXTURE_SIZE));
+        let (index, _) = self.0.insert_full(
+            name.to_string(),
+            Texture {
+                image: missing_texture_image(size),
+                normal_map: None,
+            },
+        );
+        index
+    }
+
+    /// Width and height shared by every registered texture.
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        self.values()
+            .next()
+            .map(|texture| texture.image.dimensions())
+    }
+}
+
+/// Magenta and black checkerboard with 2x2 cells, like the one Minecraft uses.
+fn missing_texture_image(size: u32) -> RgbaImage {
+    let half = (size / 2).max(1);
+    RgbaImage::from_fn(size, size, |x, y| {
+        if (x / half + y / half) % 2 == 0 {
+            Rgba([255, 0, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+fn collect_pngs(directory: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_pngs(&path, paths)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+        {
+            paths.push(path);
+        }
     }
+    Ok(())
 }
 
 impl Deref for TextureRegistry {
@@ -27,3 +249,54 @@ impl Deref for TextureRegistry {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_texture_placeholder() {
+        let mut texture_registry = TextureRegistry::default();
+        let index = texture_registry.get_index_or_missing("grass");
+        assert_eq!(texture_registry.get_index_of(MISSING_TEXTURE), Some(index));
+        assert_eq!(texture_registry.get_index_or_missing("dirt"), index);
+        assert_eq!(texture_registry.len(), 1);
+
+        let image = &texture_registry[index].image;
+        assert_eq!(image.dimensions(), (16, 16));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 255, 255]));
+        assert_eq!(image.get_pixel(8, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(texture_registry[index].average_color(), [127, 0, 127]);
+        assert_eq!(
+            texture_registry[index].normal_map_or_flat().get_pixel(3, 5),
+            &Rgba([128, 128, 255, 255])
+        );
+    }
+
+    #[test]
+    fn test_normal_maps() {
+        let directory =
+            std::env::temp_dir().join(format!("block-world-textures-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let image = |color| RgbaImage::from_pixel(4, 4, Rgba(color));
+        image([1, 2, 3, 255])
+            .save(directory.join("stone.png"))
+            .unwrap();
+        image([200, 128, 200, 255])
+            .save(directory.join("stone_n.png"))
+            .unwrap();
+        image([128, 128, 255, 255])
+            .save(directory.join("orphan_n.png"))
+            .unwrap();
+
+        let texture_registry = TextureRegistry::from_directory(&directory).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(texture_registry.keys().collect::<Vec<_>>(), vec!["stone"]);
+        assert_eq!(
+            texture_registry["stone"]
+                .normal_map_or_flat()
+                .get_pixel(0, 0),
+            &Rgba([200, 128, 200, 255])
+        );
+    }
+}
@@ -0,0 +1,490 @@
+//! Ticks of the block world, run at the same fixed rate as the entity ticks.
+//! Every tick first notifies the blocks around every block changed since
+//! the last tick, which is how fluids notice they can flow on. Then it runs
+//! the block updates scheduled for it, and picks a few random blocks of
+//! every loaded section to tick, which is how grass spreads and leaves cut
+//! off from their tree decay. Block types get their tick behavior by
+//! registering a `BlockBehavior`.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    sync::mpsc::Receiver,
+    time::Duration,
+};
+
+use crate::{
+    entity::TICK_RATE,
+    events::WorldEvent,
+    fluid::{fluid_neighbor_update, fluid_tick},
+    types::{BlockRegistry, BlockTypeId, ChunkPosition, Direction, Shape, World, CHUNK_SIZE},
+    worldgen::random::Random,
+};
+
+/// Blocks of every loaded section picked for a random tick each tick.
+pub const RANDOM_TICKS_PER_SECTION: u32 = 3;
+/// Scheduled updates run per tick at most; the rest wait for the next one.
+const MAX_SCHEDULED_PER_TICK: usize = 65536;
+/// Leaves further than this from any log, on every axis, decay.
+const LEAVES_DECAY_DISTANCE: i32 = 4;
+
+/// Runs for a block at the position passed.
+pub type TickFn = fn(&mut TickContext, [i32; 3]);
+/// Runs for the block at the first position passed when the block at the
+/// second one changed.
+pub type NeighborUpdateFn = fn(&mut TickContext, [i32; 3], [i32; 3]);
+
+/// What ticks do to the blocks of a type.
+#[derive(Clone, Copy, Default)]
+pub struct BlockBehavior {
+    /// Runs the updates scheduled at the block with `TickContext::schedule`
+    /// or `WorldTicks::schedule`.
+    pub scheduled_tick: Option<TickFn>,
+    /// Runs when the block is picked for a random tick.
+    pub random_tick: Option<TickFn>,
+    /// Runs in the tick after the block itself or one of its six neighbors
+    /// changed. Changes it makes notify their neighbors in the next tick.
+    pub neighbor_update: Option<NeighborUpdateFn>,
+}
+
+/// Updates waiting for their tick, in the order they are due and, within a
+/// tick, in the order they were scheduled in. A block has one update
+/// waiting at most; scheduling another before it ran does nothing.
+#[derive(Debug, Default)]
+struct ScheduledUpdates {
+    queue: BinaryHeap<Reverse<(u64, u64, [i32; 3])>>,
+    pending: HashSet<[i32; 3]>,
+    scheduled: u64,
+}
+
+impl ScheduledUpdates {
+    fn push(&mut self, due: u64, position: [i32; 3]) {
+        if self.pending.insert(position) {
+            self.queue.push(Reverse((due, self.scheduled, position)));
+            self.scheduled += 1;
+        }
+    }
+
+    /// The next update due at or before `tick`.
+    fn pop_due(&mut self, tick: u64) -> Option<[i32; 3]> {
+        match self.queue.peek() {
+            Some(Reverse((due, ..))) if *due <= tick => {
+                let Reverse((.., position)) = self.queue.pop().unwrap();
+                self.pending.remove(&position);
+                Some(position)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `position` and the positions of its six neighbors.
+fn with_neighbors(position: [i32; 3]) -> [[i32; 3]; 7] {
+    let mut positions = [position; 7];
+    for (neighbor, direction) in positions[1..].iter_mut().zip(Direction::ALL) {
+        let (dx, dy, dz) = direction.to_offset();
+        *neighbor = [position[0] + dx, position[1] + dy, position[2] + dz];
+    }
+    positions
+}
+
+/// What a `TickFn` can act on.
+pub struct TickContext<'a> {
+    pub world: &'a mut World,
+    pub random: &'a mut Random,
+    /// The tick being run.
+    pub tick: u64,
+    scheduled: &'a mut ScheduledUpdates,
+}
+
+impl TickContext<'_> {
+    /// The block at `position`; air where no section is loaded.
+    pub fn block(&self, position: [i32; 3]) -> BlockTypeId {
+        self.world[position]
+    }
+
+    /// Sets the block at `position` if its section is loaded, so ticks never
+    /// create sections.
+    pub fn set_block(&mut self, position: [i32; 3], block_type_id: BlockTypeId) {
+        if self
+            .world
+            .chunks
+            .contains_key(&ChunkPosition::of_block(position))
+        {
+            self.world.set_block(position, block_type_id);
+        }
+    }
+
+    /// Schedules an update of the block at `position` `delay` ticks from now,
+    /// at least one.
+    pub fn schedule(&mut self, position: [i32; 3], delay: u64) {
+        self.scheduled.push(self.tick + delay.max(1), position);
+    }
+}
+
+/// The tick behaviors of the block types and the updates scheduled for later
+/// ticks.
+pub struct WorldTicks {
+    behaviors: HashMap<BlockTypeId, BlockBehavior>,
+    scheduled: ScheduledUpdates,
+    /// Block changes of the world ticked, for `BlockBehavior::neighbor_update`.
+    world_events: Receiver<WorldEvent>,
+    random: Random,
+    /// Number of ticks run so far.
+    tick: u64,
+    /// Time not used up by ticks yet.
+    accumulated: Duration,
+}
+
+impl WorldTicks {
+    /// Ticks of `world` without any behaviors; `seed` picks the random
+    /// ticks.
+    pub fn new(seed: u64, world: &mut World) -> Self {
+        Self {
+            behaviors: HashMap::new(),
+            scheduled: ScheduledUpdates::default(),
+            world_events: world.events.subscribe(),
+            random: Random::new(seed),
+            tick: 0,
+            accumulated: Duration::ZERO,
+        }
+    }
+
+    /// Ticks of `world` with the behaviors of its built-in block types:
+    /// grass spreads to dirt next to it and dies under opaque blocks, leaves
+    /// decay away from logs, and fluids flow.
+    pub fn with_builtin_behaviors(seed: u64, world: &mut World) -> Self {
+        let mut ticks = Self::new(seed, world);
+        let block_registry = &world.block_registry;
+        ticks.register(
+            block_registry,
+            "grass",
+            BlockBehavior {
+                random_tick: Some(grass_random_tick),
+                ..Default::default()
+            },
+        );
+        ticks.register(
+            block_registry,
+            "leaves",
+            BlockBehavior {
+                random_tick: Some(leaves_random_tick),
+                ..Default::default()
+            },
+        );
+        let fluids = block_registry
+            .block_types
+            .values()
+            .filter(|block_type| block_type.shape == Shape::Fluid(0))
+            .map(|block_type| block_type.name.clone())
+            .collect::<Vec<_>>();
+        for fluid in fluids {
+            ticks.register(
+                block_registry,
+                &fluid,
+                BlockBehavior {
+                    scheduled_tick: Some(fluid_tick),
+                    neighbor_update: Some(fluid_neighbor_update),
+                    ..Default::default()
+                },
+            );
+        }
+        ticks
+    }
+
+    /// Gives `behavior` to every variant of the block type `name` in
+    /// `block_registry`, replacing the behavior they had.
+    pub fn register(
+        &mut self,
+        block_registry: &BlockRegistry,
+        name: &str,
+        behavior: BlockBehavior,
+    ) {
+        for (block_type_id, block_type) in block_registry.block_types.values().enumerate() {
+            if block_type.base_name() == name {
+                self.behaviors.insert(block_type_id, behavior);
+            }
+        }
+    }
+
+    /// Schedules an update of the block at `position` `delay` ticks from the
+    /// current one, at least one.
+    pub fn schedule(&mut self, position: [i32; 3], delay: u64) {
+        self.scheduled.push(self.tick + delay.max(1), position);
+    }
+
+    /// Number of ticks run so far.
+    pub fn tick_number(&self) -> u64 {
+        self.tick
+    }
+
+    /// Runs one tick: the neighbor updates of the blocks changed since the
+    /// last tick, in the order of the changes, then the scheduled updates
+    /// due, then the random ticks of every loaded section in the order of
+    /// their positions, so the same world and seed always tick the same way.
+    pub fn tick(&mut self, world: &mut World) {
+        self.tick += 1;
+        let behaviors = &self.behaviors;
+        let mut notified = HashSet::new();
+        let changed = self
+            .world_events
+            .try_iter()
+            .filter_map(|event| match event {
+                WorldEvent::BlockChanged { position, .. } => Some(position),
+                _ => None,
+            })
+            .filter(|position| notified.insert(*position))
+            .collect::<Vec<_>>();
+
+        let mut context = TickContext {
+            world,
+            random: &mut self.random,
+            tick: self.tick,
+            scheduled: &mut self.scheduled,
+        };
+
+        for changed in changed {
+            for position in with_neighbors(changed) {
+                if let Some(neighbor_update) = behaviors
+                    .get(&context.block(position))
+                    .and_then(|behavior| behavior.neighbor_update)
+                {
+                    neighbor_update(&mut context, position, changed);
+                }
+            }
+        }
+
+        for _ in 0..MAX_SCHEDULED_PER_TICK {
+            let Some(position) = context.scheduled.pop_due(context.tick) else {
+                break;
+            };
+            if let Some(scheduled_tick) = behaviors
+                .get(&context.block(position))
+                .and_then(|behavior| behavior.scheduled_tick)
+            {
+                scheduled_tick(&mut context, position);
+            }
+        }
+
+        if behaviors
+            .values()
+            .all(|behavior| behavior.random_tick.is_none())
+        {
+            return;
+        }
+        let mut sections = context.world.chunks.keys().copied().collect::<Vec<_>>();
+        sections.sort_by_key(|section| (section.x, section.y, section.z));
+        let size = CHUNK_SIZE as u32;
+        for section in sections {
+            let origin = section.origin();
+            for _ in 0..RANDOM_TICKS_PER_SECTION {
+                let index = context.random.next_below(size.pow(3));
+                let local = [index % size, index / size % size, index / size.pow(2)];
+                let position = [0, 1, 2].map(|i| origin[i] + local[i] as i32);
+                if let Some(random_tick) = behaviors
+                    .get(&context.block(position))
+                    .and_then(|behavior| behavior.random_tick)
+                {
+                    random_tick(&mut context, position);
+                }
+            }
+        }
+    }
+
+    /// Runs as many ticks as fit into the time passed, keeping the rest for
+    /// the next update, and returns how many ran.
+    pub fn update(&mut self, world: &mut World, elapsed: Duration) -> u32 {
+        let tick_duration = Duration::from_secs(1) / TICK_RATE;
+        self.accumulated += elapsed;
+        let mut ticks = 0;
+        while self.accumulated >= tick_duration {
+            self.accumulated -= tick_duration;
+            self.tick(world);
+            ticks += 1;
+        }
+        ticks
+    }
+}
+
+fn block_id(world: &World, name: &str) -> Option<BlockTypeId> {
+    world.block_registry.block_types.get_index_of(name)
+}
+
+/// Grass under an opaque block turns to dirt. Otherwise it spreads to a
+/// random dirt block around it with nothing opaque on top.
+fn grass_random_tick(context: &mut TickContext, position: [i32; 3]) {
+    let (Some(grass), Some(dirt)) = (
+        block_id(context.world, "grass"),
+        block_id(context.world, "dirt"),
+    ) else {
+        return;
+    };
+    let covered = |context: &TickContext, [x, y, z]: [i32; 3]| {
+        context
+            .world
+            .block_registry
+            .is_block_opaque_cube(context.block([x, y + 1, z]))
+    };
+    if covered(context, position) {
+        context.set_block(position, dirt);
+        return;
+    }
+    let offset = [3, 3, 3].map(|range| context.random.next_below(range) as i32 - 1);
+    let target = [0, 1, 2].map(|i| position[i] + offset[i]);
+    if context.block(target) == dirt && !covered(context, target) {
+        context.set_block(target, grass);
+    }
+}
+
+/// Leaves with no log within `LEAVES_DECAY_DISTANCE` decay to air.
+fn leaves_random_tick(context: &mut TickContext, position: [i32; 3]) {
+    let d = LEAVES_DECAY_DISTANCE;
+    let block_types = &context.world.block_registry.block_types;
+    let supported = (-d..=d).any(|dx| {
+        (-d..=d).any(|dy| {
+            (-d..=d).any(|dz| {
+                let neighbor = [position[0] + dx, position[1] + dy, position[2] + dz];
+                block_types[context.block(neighbor)].base_name() == "log"
+            })
+        })
+    });
+    if !supported {
+        context.set_block(position, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{BlockRegistry, Chunk};
+
+    use super::*;
+
+    fn world_with_section() -> World {
+        let mut world = World::new(BlockRegistry::default());
+        world.insert_chunks([(ChunkPosition { x: 0, y: 4, z: 0 }, Chunk::default())]);
+        world
+    }
+
+    fn id(world: &World, name: &str) -> BlockTypeId {
+        block_id(world, name).unwrap()
+    }
+
+    #[test]
+    fn test_scheduled_updates() {
+        let mut world = world_with_section();
+        let stone = id(&world, "stone");
+        let mut ticks = WorldTicks::new(0, &mut world);
+        // Stone moves up one block per update, scheduling the next
+        ticks.register(
+            &world.block_registry,
+            "stone",
+            BlockBehavior {
+                scheduled_tick: Some(|context, [x, y, z]| {
+                    let stone = context.block([x, y, z]);
+                    context.set_block([x, y, z], 0);
+                    context.set_block([x, y + 1, z], stone);
+                    context.schedule([x, y + 1, z], 2);
+                }),
+                ..Default::default()
+            },
+        );
+        world.set_block([1, 64, 1], stone);
+        ticks.schedule([1, 64, 1], 1);
+
+        for _ in 0..5 {
+            ticks.tick(&mut world);
+        }
+        assert_eq!(ticks.tick_number(), 5);
+        // Moved in ticks 1, 3 and 5
+        assert_eq!(world[[1, 67, 1]], stone);
+        assert_eq!(world[[1, 64, 1]], 0);
+
+        // Updates of blocks without a behavior do nothing
+        ticks.schedule([2, 64, 2], 0);
+        ticks.tick(&mut world);
+        assert_eq!(world[[2, 64, 2]], 0);
+        assert_eq!(world[[1, 67, 1]], stone);
+    }
+
+    #[test]
+    fn test_neighbor_updates() {
+        let mut world = world_with_section();
+        let log = id(&world, "log");
+        world.fill_cuboid([1, 64, 1], [2, 68, 2], log);
+        let mut ticks = WorldTicks::new(0, &mut world);
+        // Logs break when the block below them is removed
+        ticks.register(
+            &world.block_registry,
+            "log",
+            BlockBehavior {
+                neighbor_update: Some(|context, [x, y, z], changed| {
+                    if changed == [x, y - 1, z] && context.block(changed) == 0 {
+                        context.set_block([x, y, z], 0);
+                    }
+                }),
+                ..Default::default()
+            },
+        );
+        // Changes made before the ticks subscribed notify nothing
+        ticks.tick(&mut world);
+        assert_eq!(world[[1, 65, 1]], log);
+
+        world.set_block([1, 64, 1], 0);
+        // Every tick notifies the neighbors of the previous one's changes
+        ticks.tick(&mut world);
+        assert_eq!(world[[1, 65, 1]], 0);
+        assert_eq!(world[[1, 66, 1]], log);
+        ticks.tick(&mut world);
+        ticks.tick(&mut world);
+        assert_eq!(world[[1, 67, 1]], 0);
+    }
+
+    #[test]
+    fn test_fixed_ticks() {
+        let mut world = world_with_section();
+        let mut ticks = WorldTicks::new(0, &mut world);
+        assert_eq!(ticks.update(&mut world, Duration::from_millis(30)), 0);
+        assert_eq!(ticks.update(&mut world, Duration::from_millis(95)), 2);
+        assert_eq!(ticks.tick_number(), 2);
+    }
+
+    #[test]
+    fn test_grass_spreads() {
+        let mut world = world_with_section();
+        let (grass, dirt, stone) = (id(&world, "grass"), id(&world, "dirt"), id(&world, "stone"));
+        world.fill_cuboid([0, 64, 0], [16, 65, 16], dirt);
+        world.set_block([8, 64, 8], grass);
+        // Covered dirt never turns to grass
+        world.set_block([9, 65, 8], stone);
+
+        let mut ticks = WorldTicks::with_builtin_behaviors(7, &mut world);
+        for _ in 0..200_000 {
+            ticks.tick(&mut world);
+        }
+        assert_eq!(world[[7, 64, 8]], grass);
+        assert_eq!(world[[9, 64, 8]], dirt);
+
+        // Grass covered later dies
+        world.set_block([7, 65, 8], stone);
+        for _ in 0..200_000 {
+            ticks.tick(&mut world);
+        }
+        assert_eq!(world[[7, 64, 8]], dirt);
+    }
+
+    #[test]
+    fn test_leaves_decay() {
+        let mut world = world_with_section();
+        let (log, leaves) = (id(&world, "log"), id(&world, "leaves"));
+        world.set_block([2, 64, 2], log);
+        world.set_block([3, 64, 2], leaves);
+        world.set_block([12, 64, 12], leaves);
+
+        let mut ticks = WorldTicks::with_builtin_behaviors(7, &mut world);
+        for _ in 0..20_000 {
+            ticks.tick(&mut world);
+        }
+        assert_eq!(world[[3, 64, 2]], leaves);
+        assert_eq!(world[[12, 64, 12]], 0);
+    }
+}
@@ -1,11 +1,20 @@
 use indexmap::{indexmap, IndexMap};
-use serde::{Deserialize, Serialize};
+use log::warn;
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    collections::HashMap,
-    ops::{Index, IndexMut},
+    collections::{BTreeMap, HashMap},
+    io,
+    ops::{Index, IndexMut, Neg, Range},
+    path::Path,
 };
 
-use crate::texture::TextureRegistry;
+use crate::{
+    biome::{BiomeColors, BiomeId, TintColor},
+    events::{WorldEvent, WorldEvents},
+    model::{Faces, Model, Occlusion, SideCoverage},
+    resources::blocks::{self, BlockDefinition},
+    texture::TextureRegistry,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[repr(u8)]
@@ -35,6 +44,42 @@ impl Direction {
             West => (-1, 0, 0),
         }
     }
+
+    /// The axis (0 for x, 1 for y, 2 for z) the direction points along.
+    pub fn axis(&self) -> usize {
+        match self {
+            Direction::East | Direction::West => 0,
+            Direction::Up | Direction::Down => 1,
+            Direction::North | Direction::South => 2,
+        }
+    }
+
+    /// Whether the direction points towards growing coordinates.
+    pub fn is_positive(&self) -> bool {
+        matches!(self, Direction::Up | Direction::South | Direction::East)
+    }
+
+    /// The two axes spanning faces pointing in this direction. For side
+    /// faces the second one is y.
+    pub fn plane_axes(&self) -> [usize; 2] {
+        match self.axis() {
+            0 => [2, 1],
+            1 => [0, 2],
+            _ => [0, 1],
+        }
+    }
+
+    pub fn opposite(&self) -> Direction {
+        use Direction::*;
+        match self {
+            Up => Down,
+            Down => Up,
+            North => South,
+            South => North,
+            East => West,
+            West => East,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -48,6 +93,192 @@ impl BlockTextures {
         }
         Self(textures)
     }
+
+    /// Log-like textures: `top` on the up and down faces, `side` elsewhere.
+    pub fn column(top: TextureId, side: TextureId) -> Self {
+        let mut textures = HashMap::new();
+        for &direction in Direction::ALL.iter() {
+            let texture_id = match direction {
+                Direction::Up | Direction::Down => top,
+                _ => side,
+            };
+            textures.insert(direction, texture_id);
+        }
+        Self(textures)
+    }
+}
+
+/// Which biome color a block's (grayscale) textures are multiplied with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum Tint {
+    #[default]
+    None,
+    Grass,
+    Foliage,
+}
+
+/// Quarter turns of a block model, first about the x axis (up towards south)
+/// and then clockwise about the y axis seen from above, like the rotations of
+/// Minecraft block states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+pub struct BlockRotation {
+    pub x: u8,
+    pub y: u8,
+}
+
+impl BlockRotation {
+    pub const NONE: BlockRotation = BlockRotation { x: 0, y: 0 };
+
+    pub fn new(x: u8, y: u8) -> Self {
+        Self { x: x % 4, y: y % 4 }
+    }
+
+    /// Turns a vector relative to the center of the block.
+    pub fn rotate_vector<T: Copy + Neg<Output = T>>(&self, vector: [T; 3]) -> [T; 3] {
+        let mut v = vector;
+        for _ in 0..self.x {
+            v = [v[0], -v[2], v[1]];
+        }
+        for _ in 0..self.y {
+            v = [-v[2], v[1], v[0]];
+        }
+        v
+    }
+
+    pub fn rotate(&self, direction: Direction) -> Direction {
+        let (x, y, z) = direction.to_offset();
+        let [x, y, z] = self.rotate_vector([x, y, z]);
+        Direction::ALL
+            .into_iter()
+            .find(|direction| direction.to_offset() == (x, y, z))
+            .unwrap()
+    }
+
+    /// The direction `rotate` turns into `direction`.
+    pub fn unrotate(&self, direction: Direction) -> Direction {
+        Direction::ALL
+            .into_iter()
+            .find(|&unrotated| self.rotate(unrotated) == direction)
+            .unwrap()
+    }
+}
+
+/// Which rotated variants of a block are registered. The unrotated variant
+/// keeps the plain block name; the others are named after their state, like
+/// `log[axis=x]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum Orientation {
+    #[default]
+    None,
+    /// Like logs: the model's up axis lies along x, y or z.
+    Axis,
+    /// Like stairs: the model's north side faces any side.
+    Horizontal,
+    /// The model's north side faces any direction.
+    Facing,
+}
+
+impl Orientation {
+    /// The direction the front of the unrotated model faces.
+    pub fn front(&self) -> Direction {
+        match self {
+            Orientation::Axis => Direction::Up,
+            _ => Direction::North,
+        }
+    }
+
+    /// The state and rotation of every variant, the unrotated one first.
+    pub fn variants(&self) -> Vec<(&'static str, BlockRotation)> {
+        let horizontal = [
+            ("facing=north", BlockRotation::new(0, 0)),
+            ("facing=east", BlockRotation::new(0, 1)),
+            ("facing=south", BlockRotation::new(0, 2)),
+            ("facing=west", BlockRotation::new(0, 3)),
+        ];
+        match self {
+            Orientation::None => vec![("", BlockRotation::NONE)],
+            Orientation::Axis => vec![
+                ("axis=y", BlockRotation::new(0, 0)),
+                ("axis=x", BlockRotation::new(1, 1)),
+                ("axis=z", BlockRotation::new(1, 0)),
+            ],
+            Orientation::Horizontal => horizontal.to_vec(),
+            Orientation::Facing => [
+                horizontal.as_slice(),
+                &[
+                    ("facing=up", BlockRotation::new(1, 0)),
+                    ("facing=down", BlockRotation::new(3, 0)),
+                ],
+            ]
+            .concat(),
+        }
+    }
+}
+
+/// The model of a block, before its rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum Shape {
+    #[default]
+    Cube,
+    /// The lower half of a block.
+    Slab,
+    /// A slab with a step on its north half.
+    Stairs,
+    /// A post in the middle of the block. Connections to neighbors aren't
+    /// modelled.
+    Fence,
+    /// A fluid at `level`: 0 for its source, a full block, and up to
+    /// `MAX_FLUID_LEVEL` for flowing fluid, lower the further it is from a
+    /// source. Defined with level 0; the flowing variants follow the source
+    /// in the registry in the order of their levels.
+    Fluid(u8),
+}
+
+/// Level of flowing fluid furthest from its source.
+pub const MAX_FLUID_LEVEL: u8 = 7;
+
+/// Surface parameters of a block, baked into the material buffer the
+/// fragment shader reads.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Material {
+    /// From 0 for a polished surface to 1 for a fully diffuse one.
+    pub roughness: f32,
+    /// From 0 for a dielectric to 1 for a metal.
+    pub metallic: f32,
+    /// Light given off by the surface, as a multiple of its color.
+    pub emissive: f32,
+    /// How much light passes through the block, from 0 to 1.
+    pub translucency: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            roughness: 1.0,
+            metallic: 0.0,
+            emissive: 0.0,
+            translucency: 0.0,
+        }
+    }
+}
+
+impl Material {
+    /// The name of the first parameter out of its range, if any.
+    pub fn invalid_parameter(&self) -> Option<&'static str> {
+        let unit = 0.0..=1.0;
+        if !unit.contains(&self.roughness) {
+            Some("roughness")
+        } else if !unit.contains(&self.metallic) {
+            Some("metallic")
+        } else if !(self.emissive >= 0.0 && self.emissive.is_finite()) {
+            Some("emissive")
+        } else if !unit.contains(&self.translucency) {
+            Some("translucency")
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -55,9 +286,60 @@ pub struct BlockType {
     pub name: String,
     pub textures: BlockTextures,
     pub transparent: bool,
+    #[serde(default)]
+    pub tint: Tint,
+    #[serde(default)]
+    pub light_level: u8,
+    #[serde(default)]
+    pub hardness: f32,
+    #[serde(default)]
+    pub shape: Shape,
+    #[serde(default)]
+    pub orientation: Orientation,
+    /// How much of the surroundings the block mirrors, from 0 to 1.
+    #[serde(default)]
+    pub reflectivity: f32,
+    #[serde(default)]
+    pub material: Material,
+    /// Whether the block falls when nothing holds it up.
+    #[serde(default)]
+    pub gravity: bool,
+    /// Rotation of this variant's model. `textures` are those of the
+    /// unrotated model, see `texture`.
+    #[serde(default)]
+    pub rotation: BlockRotation,
+}
+
+impl BlockType {
+    /// The texture of the face pointing in `direction` once rotated.
+    pub fn texture(&self, direction: Direction) -> Option<TextureId> {
+        self.textures
+            .0
+            .get(&self.rotation.unrotate(direction))
+            .copied()
+    }
+
+    /// The rotated model of the block, with `fallback` on faces without a
+    /// texture.
+    pub fn model(&self, fallback: TextureId) -> Model {
+        Model::from_shape(
+            self.shape,
+            Faces::from_block_textures(&self.textures, fallback),
+        )
+        .rotated(self.rotation)
+    }
+
+    /// The name without the state of the variant.
+    pub fn base_name(&self) -> &str {
+        self.name.split('[').next().unwrap()
+    }
 }
 
 pub type BlockTypeId = usize;
+/// Placeholder block type for imported blocks without a block type.
+pub const UNKNOWN_BLOCK: &str = "unknown";
+/// The block type the camera is underwater in.
+pub const WATER_BLOCK: &str = "water";
 pub type TextureId = usize;
 
 #[derive(Debug, Clone)]
@@ -67,41 +349,34 @@ pub struct BlockRegistry {
 }
 
 impl Default for BlockRegistry {
+    /// The built-in block types without any loaded textures; every face uses
+    /// the missing-texture placeholder.
     fn default() -> Self {
-        let block_types = indexmap! {
-            "air".to_string() => BlockType {
-                name: "air".to_string(),
-                transparent: true,
-                textures: BlockTextures::default(),
-            },
-        };
-
-        Self {
-            block_types,
-            texture_registry: TextureRegistry::default(),
-        }
+        Self::new(TextureRegistry::default())
     }
 }
 
 impl BlockRegistry {
+    /// The block types defined in the `blocks.json` built into the binary.
     pub fn new(texture_registry: TextureRegistry) -> Self {
-        let block_types = indexmap! {
-            "air".to_string() => BlockType {
-                name: "air".to_string(),
-                transparent: true,
-                textures: BlockTextures::default(),
-            },
-            "stone".to_string() => BlockType {
-                name: "stone".to_string(),
-                transparent: false,
-                textures: BlockTextures::uniform(texture_registry.get_index_of("stone").unwrap()),
-            },
-            "grass".to_string() => BlockType {
-                name: "grass".to_string(),
-                transparent: false,
-                textures: BlockTextures::uniform(texture_registry.get_index_of("grass").unwrap()),
-            },
-        };
+        Self::from_definitions(blocks::builtin_definitions(), texture_registry)
+    }
+
+    /// Reads the block definitions at `path`, see `blocks::parse_definitions`.
+    pub fn load(path: impl AsRef<Path>, texture_registry: TextureRegistry) -> io::Result<Self> {
+        let definitions = blocks::load_definitions(path)?;
+        Ok(Self::from_definitions(definitions, texture_registry))
+    }
+
+    pub fn from_definitions(
+        definitions: Vec<BlockDefinition>,
+        mut texture_registry: TextureRegistry,
+    ) -> Self {
+        let block_types: IndexMap<String, BlockType> =
+            blocks::build_block_types(definitions, &mut texture_registry)
+                .into_iter()
+                .map(|block_type| (block_type.name.clone(), block_type))
+                .collect();
 
         assert!(block_types.get_index_of("air") == Some(0));
 
@@ -111,53 +386,638 @@ impl BlockRegistry {
         }
     }
 
+    /// The variant of the block `block_type_id` whose front faces `facing`,
+    /// or lies along its axis for `Orientation::Axis`. Blocks that can't
+    /// face there are returned as they are.
+    pub fn oriented(&self, block_type_id: BlockTypeId, facing: Direction) -> BlockTypeId {
+        let block_type = &self.block_types[block_type_id];
+        let front = block_type.orientation.front();
+        let faces = |rotation: BlockRotation| {
+            let rotated = rotation.rotate(front);
+            match block_type.orientation {
+                Orientation::None => false,
+                Orientation::Axis => rotated.axis() == facing.axis(),
+                _ => rotated == facing,
+            }
+        };
+        self.block_types
+            .values()
+            .position(|variant| {
+                variant.base_name() == block_type.base_name() && faces(variant.rotation)
+            })
+            .unwrap_or(block_type_id)
+    }
+
     pub fn is_block_transparent(&self, block_type_id: BlockTypeId) -> bool {
         self.block_types[block_type_id].transparent
     }
+
+    /// Whether the block fills its whole space and can't be seen through.
+    pub fn is_block_opaque_cube(&self, block_type_id: BlockTypeId) -> bool {
+        let block_type = &self.block_types[block_type_id];
+        !block_type.transparent && block_type.shape == Shape::Cube
+    }
+
+    /// How every block type meets its neighbors, indexed by `BlockTypeId`.
+    /// Transparent blocks hide nothing.
+    pub fn occlusion(&self) -> Vec<Occlusion> {
+        self.block_types
+            .values()
+            .map(|block_type| match block_type.shape {
+                _ if block_type.transparent => Occlusion {
+                    sides: [SideCoverage::EMPTY; 6],
+                    inner: [true; 6],
+                },
+                Shape::Cube => Occlusion::FULL,
+                _ => block_type.model(0).occlusion(),
+            })
+            .collect()
+    }
+
+    /// Block type names in id order. Saved alongside block ids so they can be
+    /// mapped back with `id_mapping` when the registry has changed since.
+    pub fn block_names(&self) -> Vec<String> {
+        self.block_types.keys().cloned().collect()
+    }
+
+    /// Maps the ids of a registry with the given `block_names` to ids of this
+    /// one. Blocks this registry doesn't know become air.
+    pub fn id_mapping(&self, block_names: &[String]) -> Vec<BlockTypeId> {
+        block_names
+            .iter()
+            .map(|name| {
+                self.block_types.get_index_of(name).unwrap_or_else(|| {
+                    warn!("Unknown block type {:?}, replacing it with air", name);
+                    0
+                })
+            })
+            .collect()
+    }
+
+    /// Points the block textures at the images of `texture_registry`, looking
+    /// them up by name. Deserialized registries only hold placeholders.
+    pub fn with_textures(mut self, mut texture_registry: TextureRegistry) -> Self {
+        let names: Vec<String> = self.texture_registry.keys().cloned().collect();
+        for block_type in self.block_types.values_mut() {
+            for texture_id in block_type.textures.0.values_mut() {
+                *texture_id = texture_registry.get_index_or_missing(&names[*texture_id]);
+            }
+        }
+        self.texture_registry = texture_registry;
+        self
+    }
+}
+
+/// Serialized form of `BlockRegistry`: texture ids are only meaningful along
+/// with the texture names, while the images are left to the texture pack.
+#[derive(Deserialize, Serialize)]
+struct BlockRegistryData<B> {
+    block_types: Vec<B>,
+    textures: Vec<String>,
+}
+
+impl Serialize for BlockRegistry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        BlockRegistryData {
+            block_types: self.block_types.values().collect(),
+            textures: self.texture_registry.keys().cloned().collect(),
+        }
+        .serialize(serializer)
+    }
 }
 
+impl<'de> Deserialize<'de> for BlockRegistry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = BlockRegistryData::<BlockType>::deserialize(deserializer)?;
+        if data
+            .block_types
+            .first()
+            .map(|block_type| block_type.name.as_str())
+            != Some("air")
+        {
+            return Err(de::Error::custom("the first block type must be air"));
+        }
+
+        let mut texture_registry = TextureRegistry::default();
+        for name in &data.textures {
+            texture_registry.get_index_or_placeholder(name);
+        }
+        let mut block_types = IndexMap::new();
+        for block_type in data.block_types {
+            if let Some(&texture_id) = block_type
+                .textures
+                .0
+                .values()
+                .find(|&&texture_id| texture_id >= data.textures.len())
+            {
+                return Err(de::Error::custom(format!(
+                    "block type {:?} uses unknown texture {}",
+                    block_type.name, texture_id
+                )));
+            }
+            let name = block_type.name.clone();
+            if block_types.insert(name.clone(), block_type).is_some() {
+                return Err(de::Error::custom(format!(
+                    "duplicate block type {:?}",
+                    name
+                )));
+            }
+        }
+
+        Ok(Self {
+            block_types,
+            texture_registry,
+        })
+    }
+}
+
+/// Edge length of a chunk section, in blocks.
+pub const CHUNK_SIZE: usize = 16;
+
+/// A 16x16x16 section of the world.
 #[derive(Debug, Clone)]
 pub struct Chunk {
-    pub blocks: [[[BlockTypeId; 16]; 16]; 256],
+    /// Blocks of the section, indexed by `[y][x][z]`.
+    pub blocks: [[[BlockTypeId; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+    /// Biome of every column, indexed by `[x][z]`.
+    pub biomes: [[BiomeId; CHUNK_SIZE]; CHUNK_SIZE],
+    /// Biome colors of every column, indexed by `[x][z]`.
+    pub biome_colors: [[BiomeColors; CHUNK_SIZE]; CHUNK_SIZE],
+    /// State of the few blocks that carry more than their type, by position
+    /// `[x, y, z]` inside the section.
+    pub block_entities: BTreeMap<[u8; 3], BlockEntity>,
 }
 
 impl Default for Chunk {
     fn default() -> Self {
         Self {
-            blocks: [[[0; 16]; 16]; 256],
+            blocks: [[[0; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+            biomes: [[0; CHUNK_SIZE]; CHUNK_SIZE],
+            biome_colors: [[BiomeColors::default(); CHUNK_SIZE]; CHUNK_SIZE],
+            block_entities: BTreeMap::new(),
+        }
+    }
+}
+
+/// State a block carries beyond its type, like the text of a sign or the
+/// contents of a chest. Holds any serializable value, encoded so chunks can
+/// be saved without knowing its type.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlockEntity {
+    data: Vec<u8>,
+}
+
+impl BlockEntity {
+    pub fn new<T: Serialize>(value: &T) -> Self {
+        Self {
+            data: bincode::serde::encode_to_vec(value, bincode::config::standard()).unwrap(),
+        }
+    }
+
+    /// The value, or `None` if the data doesn't decode as a `T`. The type
+    /// isn't recorded, so other types may still decode.
+    pub fn value<T: DeserializeOwned>(&self) -> Option<T> {
+        bincode::serde::decode_from_slice(&self.data, bincode::config::standard())
+            .ok()
+            .filter(|&(_, read)| read == self.data.len())
+            .map(|(value, _)| value)
+    }
+}
+
+impl Chunk {
+    /// Replaces every block id with `mapping[id]`, see
+    /// `BlockRegistry::id_mapping`.
+    pub fn remap_blocks(&mut self, mapping: &[BlockTypeId]) {
+        for block in self.blocks.iter_mut().flatten().flatten() {
+            *block = mapping.get(*block).copied().unwrap_or(0);
         }
     }
 }
 
+/// Serialized form of `Chunk`. Sections are mostly long runs of the same
+/// block, so every array is stored run-length encoded in memory order.
+#[derive(Deserialize, Serialize)]
+struct ChunkData {
+    blocks: Vec<(u32, u16)>,
+    biomes: Vec<(u32, u16)>,
+    biome_colors: Vec<(BiomeColors, u16)>,
+    block_entities: Vec<([u8; 3], BlockEntity)>,
+}
+
+fn encode_runs<T: PartialEq>(values: impl IntoIterator<Item = T>) -> Vec<(T, u16)> {
+    let mut runs: Vec<(T, u16)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((last, length)) if *last == value => *length += 1,
+            _ => runs.push((value, 1)),
+        }
+    }
+    runs
+}
+
+fn decode_runs<'a, T: Copy + 'a, E: de::Error>(
+    runs: &[(T, u16)],
+    destination: impl IntoIterator<Item = &'a mut T>,
+) -> Result<(), E> {
+    let mut values = runs
+        .iter()
+        .flat_map(|&(value, length)| std::iter::repeat_n(value, length as usize));
+    for slot in destination {
+        *slot = values
+            .next()
+            .ok_or_else(|| E::custom("too few values in chunk"))?;
+    }
+    match values.next() {
+        Some(_) => Err(E::custom("too many values in chunk")),
+        None => Ok(()),
+    }
+}
+
+impl Serialize for Chunk {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ChunkData {
+            blocks: encode_runs(self.blocks.iter().flatten().flatten().map(|&id| id as u32)),
+            biomes: encode_runs(self.biomes.iter().flatten().map(|&id| id as u32)),
+            biome_colors: encode_runs(self.biome_colors.iter().flatten().copied()),
+            block_entities: self
+                .block_entities
+                .iter()
+                .map(|(&position, block_entity)| (position, block_entity.clone()))
+                .collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Chunk {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = ChunkData::deserialize(deserializer)?;
+        let widen = |runs: Vec<(u32, u16)>| -> Vec<(usize, u16)> {
+            runs.into_iter()
+                .map(|(id, length)| (id as usize, length))
+                .collect()
+        };
+
+        let mut chunk = Chunk::default();
+        decode_runs(
+            &widen(data.blocks),
+            chunk.blocks.iter_mut().flatten().flatten(),
+        )?;
+        decode_runs(&widen(data.biomes), chunk.biomes.iter_mut().flatten())?;
+        decode_runs(&data.biome_colors, chunk.biome_colors.iter_mut().flatten())?;
+        for (position, block_entity) in data.block_entities {
+            if position.iter().any(|&v| v as usize >= CHUNK_SIZE) {
+                return Err(de::Error::custom("block entity outside the chunk"));
+            }
+            chunk.block_entities.insert(position, block_entity);
+        }
+        Ok(chunk)
+    }
+}
+
+/// Position of a chunk section, in units of `CHUNK_SIZE` blocks.
 #[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, Hash, Copy)]
 pub struct ChunkPosition {
     pub x: i32,
+    pub y: i32,
     pub z: i32,
 }
 
+impl ChunkPosition {
+    /// The section containing the block at `position`.
+    pub fn of_block(position: [i32; 3]) -> Self {
+        let size = CHUNK_SIZE as i32;
+        Self {
+            x: position[0].div_euclid(size),
+            y: position[1].div_euclid(size),
+            z: position[2].div_euclid(size),
+        }
+    }
+
+    /// World coordinates of the section's lowest corner.
+    pub fn origin(&self) -> [i32; 3] {
+        let size = CHUNK_SIZE as i32;
+        [self.x * size, self.y * size, self.z * size]
+    }
+
+    pub fn offset(&self, direction: Direction) -> Self {
+        let (dx, dy, dz) = direction.to_offset();
+        Self {
+            x: self.x + dx,
+            y: self.y + dy,
+            z: self.z + dz,
+        }
+    }
+
+    /// Squared distance to `other` in sections, for ordering sections by
+    /// how near they are.
+    pub fn distance_squared(&self, other: ChunkPosition) -> i32 {
+        let [dx, dy, dz] = [self.x - other.x, self.y - other.y, self.z - other.z];
+        dx * dx + dy * dy + dz * dz
+    }
+}
+
+/// Position of a column of sections, in units of `CHUNK_SIZE` blocks. Columns
+/// are the unit chunks are generated, loaded and unloaded in.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, Hash, Copy)]
+pub struct ColumnPosition {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl ColumnPosition {
+    /// The column containing the block at `position`.
+    pub fn of_block(position: [i32; 3]) -> Self {
+        let size = CHUNK_SIZE as i32;
+        Self {
+            x: position[0].div_euclid(size),
+            z: position[2].div_euclid(size),
+        }
+    }
+
+    /// Positions of all sections of the column.
+    pub fn sections(self, height: WorldHeight) -> impl Iterator<Item = ChunkPosition> {
+        height.sections().map(move |y| ChunkPosition {
+            x: self.x,
+            y,
+            z: self.z,
+        })
+    }
+
+    /// The four horizontally adjacent columns.
+    pub fn neighbors(self) -> [ColumnPosition; 4] {
+        [(1, 0), (-1, 0), (0, 1), (0, -1)].map(|(dx, dz)| ColumnPosition {
+            x: self.x + dx,
+            z: self.z + dz,
+        })
+    }
+}
+
+/// Position of a block inside its section, as `[x, y, z]`.
+pub fn local_block_position(position: [i32; 3]) -> [usize; 3] {
+    position.map(|coordinate| coordinate.rem_euclid(CHUNK_SIZE as i32) as usize)
+}
+
+/// Vertical extent of the world in blocks, from `min_y` (inclusive) to `max_y`
+/// (exclusive). Both are multiples of `CHUNK_SIZE` so sections never straddle
+/// the bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WorldHeight {
+    pub min_y: i32,
+    pub max_y: i32,
+}
+
+impl Default for WorldHeight {
+    fn default() -> Self {
+        Self::new(0, 256)
+    }
+}
+
+impl WorldHeight {
+    pub fn new(min_y: i32, max_y: i32) -> Self {
+        let size = CHUNK_SIZE as i32;
+        assert!(
+            min_y % size == 0 && max_y % size == 0,
+            "world height {}..{} is not aligned to chunk sections",
+            min_y,
+            max_y
+        );
+        assert!(min_y < max_y, "world height {}..{} is empty", min_y, max_y);
+        Self { min_y, max_y }
+    }
+
+    pub fn contains(&self, y: i32) -> bool {
+        (self.min_y..self.max_y).contains(&y)
+    }
+
+    /// Section y coordinates of every section in a chunk column.
+    pub fn sections(&self) -> Range<i32> {
+        let size = CHUNK_SIZE as i32;
+        self.min_y / size..self.max_y / size
+    }
+
+    pub fn contains_section(&self, section_y: i32) -> bool {
+        self.sections().contains(&section_y)
+    }
+}
+
+/// Everything about a world that isn't in its sections. `block_names` maps
+/// the block ids of the saved sections to block types.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WorldMetadata {
+    pub height: WorldHeight,
+    pub block_names: Vec<String>,
+}
+
 pub struct World {
+    /// Loaded sections. Columns are always loaded as a whole, with a section
+    /// for every y in `height.sections()`. Changes made to them directly or
+    /// by indexing the world emit no events.
     pub chunks: HashMap<ChunkPosition, Chunk>,
     pub block_registry: BlockRegistry,
+    pub height: WorldHeight,
+    pub events: WorldEvents,
 }
 
 impl World {
     pub fn new(block_registry: BlockRegistry) -> Self {
+        Self::with_height(block_registry, WorldHeight::default())
+    }
+
+    pub fn with_height(block_registry: BlockRegistry, height: WorldHeight) -> Self {
         Self {
             chunks: HashMap::new(),
             block_registry,
+            height,
+            events: WorldEvents::default(),
+        }
+    }
+
+    pub fn metadata(&self) -> WorldMetadata {
+        WorldMetadata {
+            height: self.height,
+            block_names: self.block_registry.block_names(),
+        }
+    }
+
+    pub fn is_column_loaded(&self, column: ColumnPosition) -> bool {
+        column
+            .sections(self.height)
+            .all(|chunk_position| self.chunks.contains_key(&chunk_position))
+    }
+
+    /// Adds `chunks` to the world, replacing loaded sections at the same
+    /// positions.
+    pub fn insert_chunks(&mut self, chunks: impl IntoIterator<Item = (ChunkPosition, Chunk)>) {
+        for (chunk_position, chunk) in chunks {
+            self.chunks.insert(chunk_position, chunk);
+            self.events.emit(WorldEvent::ChunkLoaded(chunk_position));
+        }
+    }
+
+    /// Removes all sections of `column` from the world, returning them so they
+    /// can be persisted.
+    pub fn unload_column(&mut self, column: ColumnPosition) -> Vec<(ChunkPosition, Chunk)> {
+        let sections = column
+            .sections(self.height)
+            .filter_map(|chunk_position| {
+                self.chunks
+                    .remove(&chunk_position)
+                    .map(|chunk| (chunk_position, chunk))
+            })
+            .collect::<Vec<_>>();
+        for &(chunk_position, _) in &sections {
+            self.events.emit(WorldEvent::ChunkUnloaded(chunk_position));
+        }
+        sections
+    }
+
+    /// Sets the block at `position`, which must be inside the world height.
+    /// An unloaded section is created empty first.
+    pub fn set_block(&mut self, position: [i32; 3], block_type_id: BlockTypeId) {
+        let chunk_position = ChunkPosition::of_block(position);
+        if !self.chunks.contains_key(&chunk_position) {
+            self.insert_chunks([(chunk_position, Chunk::default())]);
+        }
+        let before = std::mem::replace(&mut self[position], block_type_id);
+        if before != block_type_id {
+            self.remove_block_entity(position);
+            self.events.emit(WorldEvent::BlockChanged {
+                position,
+                before,
+                after: block_type_id,
+            });
+        }
+    }
+
+    pub fn block_entity(&self, position: [i32; 3]) -> Option<&BlockEntity> {
+        self.chunks
+            .get(&ChunkPosition::of_block(position))?
+            .block_entities
+            .get(&local_block_position(position).map(|v| v as u8))
+    }
+
+    pub fn block_entity_mut(&mut self, position: [i32; 3]) -> Option<&mut BlockEntity> {
+        self.chunks
+            .get_mut(&ChunkPosition::of_block(position))?
+            .block_entities
+            .get_mut(&local_block_position(position).map(|v| v as u8))
+    }
+
+    /// Attaches `block_entity` to the block at `position`, returning the one
+    /// it replaces. It stays until the block is changed through `set_block`
+    /// or it is removed. Positions in unloaded sections are ignored.
+    pub fn set_block_entity(
+        &mut self,
+        position: [i32; 3],
+        block_entity: BlockEntity,
+    ) -> Option<BlockEntity> {
+        self.chunks
+            .get_mut(&ChunkPosition::of_block(position))?
+            .block_entities
+            .insert(
+                local_block_position(position).map(|v| v as u8),
+                block_entity,
+            )
+    }
+
+    pub fn remove_block_entity(&mut self, position: [i32; 3]) -> Option<BlockEntity> {
+        self.chunks
+            .get_mut(&ChunkPosition::of_block(position))?
+            .block_entities
+            .remove(&local_block_position(position).map(|v| v as u8))
+    }
+
+    /// The biome of the column of `position`, the first biome where it
+    /// isn't loaded.
+    pub fn biome(&self, position: [i32; 3]) -> BiomeId {
+        let [x, _, z] = local_block_position(position);
+        self.chunks
+            .get(&ChunkPosition::of_block(position))
+            .map_or(0, |chunk| chunk.biomes[x][z])
+    }
+
+    pub fn biome_colors(&self, position: [i32; 3]) -> BiomeColors {
+        let [x, _, z] = local_block_position(position);
+        self.chunks
+            .get(&ChunkPosition::of_block(position))
+            .map(|chunk| chunk.biome_colors[x][z])
+            .unwrap_or_default()
+    }
+
+    /// The color the block at `position` is tinted with, if its type is tinted.
+    pub fn tint_color(&self, position: [i32; 3]) -> Option<TintColor> {
+        let block_type = &self.block_registry.block_types[self[position]];
+        self.biome_colors(position).tint_color(block_type.tint)
+    }
+
+    /// The first block that isn't air along the ray from `origin` in
+    /// `direction`, at most `max_distance` blocks away. A block the ray
+    /// starts in isn't hit.
+    pub fn raycast(
+        &self,
+        origin: [f32; 3],
+        direction: [f32; 3],
+        max_distance: f32,
+    ) -> Option<RaycastHit> {
+        let length = direction.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length == 0.0 {
+            return None;
+        }
+        let direction = direction.map(|v| v / length);
+        let mut block = origin.map(|v| v.floor() as i32);
+        let step = direction.map(|v| if v > 0.0 { 1 } else { -1 });
+        // Distance along the ray between the planes of each axis, and to
+        // the next one
+        let delta = direction.map(|v| (1.0 / v).abs());
+        let mut next = [0, 1, 2].map(|i| {
+            let to_plane = if direction[i] > 0.0 {
+                block[i] as f32 + 1.0 - origin[i]
+            } else {
+                origin[i] - block[i] as f32
+            };
+            to_plane * delta[i]
+        });
+
+        loop {
+            let axis = (0..3).min_by(|&a, &b| next[a].total_cmp(&next[b])).unwrap();
+            if next[axis] > max_distance {
+                return None;
+            }
+            let distance = next[axis];
+            block[axis] += step[axis];
+            next[axis] += delta[axis];
+            if self[block] != 0 {
+                let face = match (axis, step[axis] > 0) {
+                    (0, true) => Direction::West,
+                    (0, false) => Direction::East,
+                    (1, true) => Direction::Down,
+                    (1, false) => Direction::Up,
+                    (_, true) => Direction::North,
+                    (_, false) => Direction::South,
+                };
+                return Some(RaycastHit {
+                    position: block,
+                    face,
+                    distance,
+                });
+            }
         }
     }
 
     pub fn fill_sphere(&mut self, center: [i32; 3], radius: i32, block_type_id: BlockTypeId) {
         for x in center[0] - radius..center[0] + radius {
             for y in center[1] - radius..center[1] + radius {
+                if !self.height.contains(y) {
+                    continue;
+                }
                 for z in center[2] - radius..center[2] + radius {
                     let dx = x - center[0];
                     let dy = y - center[1];
                     let dz = z - center[2];
 
                     if dx * dx + dy * dy + dz * dz <= radius * radius {
-                        self[[x, y, z]] = block_type_id;
+                        self.set_block([x, y, z], block_type_id);
                     }
                 }
             }
@@ -166,27 +1026,33 @@ impl World {
 
     pub fn fill_cuboid(&mut self, min: [i32; 3], max: [i32; 3], block_type_id: BlockTypeId) {
         for x in min[0]..max[0] {
-            for y in min[1]..max[1] {
+            for y in min[1].max(self.height.min_y)..max[1].min(self.height.max_y) {
                 for z in min[2]..max[2] {
-                    self[[x, y, z]] = block_type_id;
+                    self.set_block([x, y, z], block_type_id);
                 }
             }
         }
     }
 }
 
+/// A block hit by `World::raycast`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub position: [i32; 3],
+    /// The face the ray entered through.
+    pub face: Direction,
+    /// How far along the ray the face is.
+    pub distance: f32,
+}
+
 impl Index<[i32; 3]> for World {
     type Output = BlockTypeId;
 
+    /// Blocks in unloaded sections and outside the world height are air.
     fn index(&self, index: [i32; 3]) -> &Self::Output {
-        let chunk_position = ChunkPosition {
-            x: index[0] / 16,
-            z: index[2] / 16,
-        };
-
-        if let Some(chunk) = self.chunks.get(&chunk_position) {
-            &chunk.blocks[(index[0] % 16) as usize][(index[2] % 16) as usize]
-                [(index[1] % 256) as usize]
+        if let Some(chunk) = self.chunks.get(&ChunkPosition::of_block(index)) {
+            let [x, y, z] = local_block_position(index);
+            &chunk.blocks[y][x][z]
         } else {
             &0
         }
@@ -195,17 +1061,189 @@ impl Index<[i32; 3]> for World {
 
 impl IndexMut<[i32; 3]> for World {
     fn index_mut(&mut self, index: [i32; 3]) -> &mut Self::Output {
-        let chunk_position = ChunkPosition {
-            x: index[0] / 16,
-            z: index[2] / 16,
-        };
+        assert!(
+            self.height.contains(index[1]),
+            "block {:?} is outside the world height",
+            index
+        );
 
         let chunk = self
             .chunks
-            .entry(chunk_position)
+            .entry(ChunkPosition::of_block(index))
             .or_insert_with(|| Chunk::default());
 
-        &mut chunk.blocks[(index[0] % 16) as usize][(index[2] % 16) as usize]
-            [(index[1] % 256) as usize]
+        let [x, y, z] = local_block_position(index);
+        &mut chunk.blocks[y][x][z]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negative_block_positions() {
+        let mut world = World::with_height(BlockRegistry::default(), WorldHeight::new(-64, 64));
+        world[[-1, -1, -17]] = 1;
+
+        let chunk_position = ChunkPosition {
+            x: -1,
+            y: -1,
+            z: -2,
+        };
+        assert_eq!(ChunkPosition::of_block([-1, -1, -17]), chunk_position);
+        assert_eq!(
+            chunk_position.distance_squared(ChunkPosition::of_block([0, 20, 0])),
+            1 + 4 + 4
+        );
+        assert_eq!(world.chunks[&chunk_position].blocks[15][15][15], 1);
+        assert_eq!(world[[-1, -1, -17]], 1);
+        assert_eq!(world[[15, 15, 15]], 0);
+    }
+
+    #[test]
+    fn test_world_height_sections() {
+        let height = WorldHeight::new(-64, 320);
+        assert_eq!(height.sections(), -4..20);
+        assert!(height.contains(-64));
+        assert!(!height.contains(320));
+        assert!(!height.contains_section(20));
+    }
+
+    #[test]
+    fn test_chunk_round_trip() {
+        let mut chunk = Chunk::default();
+        chunk.blocks[0] = [[1; CHUNK_SIZE]; CHUNK_SIZE];
+        chunk.blocks[3][4][5] = 6;
+        chunk.biomes[2][7] = 3;
+        chunk.biome_colors[2][7].grass = [10, 20, 30];
+        chunk
+            .block_entities
+            .insert([1, 2, 3], BlockEntity::new(&"sign text"));
+
+        let json = serde_json::to_string(&chunk).unwrap();
+        let decoded: Chunk = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.blocks, chunk.blocks);
+        assert_eq!(decoded.biomes, chunk.biomes);
+        assert_eq!(decoded.biome_colors, chunk.biome_colors);
+        assert_eq!(decoded.block_entities, chunk.block_entities);
+
+        let truncated = json.replacen("[1,256]", "[1,255]", 1);
+        assert!(serde_json::from_str::<Chunk>(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_block_registry_round_trip() {
+        let block_registry = BlockRegistry::default();
+        let json = serde_json::to_string(&block_registry).unwrap();
+        let decoded: BlockRegistry = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.block_names(), block_registry.block_names());
+        assert_eq!(
+            decoded.block_types["log"].textures.0[&Direction::Up],
+            block_registry.block_types["log"].textures.0[&Direction::Up]
+        );
+
+        let saved = vec!["air".to_string(), "dirt".to_string(), "marble".to_string()];
+        let mapping = block_registry.id_mapping(&saved);
+        assert_eq!(mapping, [0, 3, 0]);
+
+        let mut chunk = Chunk::default();
+        chunk.blocks[0][0][0] = 1;
+        chunk.blocks[0][0][1] = 2;
+        chunk.remap_blocks(&mapping);
+        assert_eq!(chunk.blocks[0][0][..2], [3, 0]);
+    }
+
+    #[test]
+    fn test_oriented_blocks() {
+        let block_registry = BlockRegistry::default();
+        let id = |name: &str| block_registry.block_types.get_index_of(name).unwrap();
+        let log = id("log");
+        assert_eq!(
+            block_registry.oriented(log, Direction::West),
+            id("log[axis=x]")
+        );
+        assert_eq!(
+            block_registry.oriented(id("log[axis=x]"), Direction::Down),
+            log
+        );
+        assert_eq!(
+            block_registry.oriented(log, Direction::North),
+            id("log[axis=z]")
+        );
+        assert_eq!(
+            block_registry.oriented(id("stone"), Direction::East),
+            id("stone")
+        );
+
+        // The end faces of a log along x face east and west
+        let log_x = &block_registry.block_types["log[axis=x]"];
+        assert_eq!(log_x.rotation.unrotate(Direction::East), Direction::Down);
+        assert_eq!(log_x.rotation.unrotate(Direction::West), Direction::Up);
+        for direction in Direction::ALL {
+            let rotation = BlockRotation::new(3, 2);
+            assert_eq!(rotation.unrotate(rotation.rotate(direction)), direction);
+        }
+    }
+
+    #[test]
+    fn test_raycast() {
+        let mut world = World::new(BlockRegistry::default());
+        world.set_block([3, 10, 0], 1);
+        world.fill_cuboid([-4, 5, -4], [4, 6, 4], 1);
+
+        let hit = world.raycast([0.5, 10.5, 0.5], [1.0, 0.0, 0.0], 6.0);
+        assert_eq!(
+            hit,
+            Some(RaycastHit {
+                position: [3, 10, 0],
+                face: Direction::West,
+                distance: 2.5,
+            })
+        );
+        assert_eq!(world.raycast([0.5, 10.5, 0.5], [1.0, 0.0, 0.0], 2.0), None);
+        assert_eq!(world.raycast([0.5, 10.5, 0.5], [-1.0, 0.0, 0.0], 6.0), None);
+
+        let hit = world
+            .raycast([0.5, 10.5, 0.5], [-1.0, -2.0, 0.5], 10.0)
+            .unwrap();
+        assert_eq!(hit.position, [-2, 5, 1]);
+        assert_eq!(hit.face, Direction::Up);
+        // The top of the block is 4.5 below the origin, and the ray drops 2
+        // for every 5.25.sqrt() it travels
+        assert!((hit.distance - 4.5 / 2.0 * 5.25f32.sqrt()).abs() < 1e-4);
+        // Starting inside a block doesn't hit it
+        assert_eq!(world.raycast([3.5, 10.5, 0.5], [0.0, 1.0, 0.0], 5.0), None);
+    }
+
+    #[test]
+    fn test_block_entities() {
+        #[derive(Debug, PartialEq, Deserialize, Serialize)]
+        struct Container {
+            items: Vec<(String, u32)>,
+        }
+
+        let mut world = World::new(BlockRegistry::default());
+        let position = [-3, 10, 20];
+        let container = Container {
+            items: vec![("stone".to_string(), 12)],
+        };
+        // Nothing is kept for unloaded sections
+        assert!(world
+            .set_block_entity(position, BlockEntity::new(&container))
+            .is_none());
+        assert!(world.block_entity(position).is_none());
+
+        world.set_block(position, 1);
+        world.set_block_entity(position, BlockEntity::new(&container));
+        let block_entity = world.block_entity(position).unwrap();
+        assert_eq!(block_entity.value::<Container>(), Some(container));
+        assert_eq!(block_entity.value::<String>(), None);
+
+        // Setting the same block keeps the entity, changing it removes it
+        world.set_block(position, 1);
+        assert!(world.block_entity(position).is_some());
+        world.set_block(position, 2);
+        assert!(world.block_entity(position).is_none());
     }
 }
@@ -0,0 +1,108 @@
+//! What the player holds in first person: the selected block, or the bare
+//! hand, drawn over the world in a layer of its own with its own projection
+//! so it never clips into nearby blocks.
+
+use std::f32::consts::PI;
+
+use cgmath::{perspective, Deg, Matrix4, Rad, Vector3};
+
+use crate::types::BlockTypeId;
+
+/// Seconds a swing takes.
+pub const SWING_DURATION: f32 = 0.3;
+/// Vertical field of view of the viewmodel layer, independent of the
+/// camera's so the held block keeps its size when the camera zooms.
+const FOVY: Deg<f32> = Deg(70.0);
+const NEAR: f32 = 0.05;
+const FAR: f32 = 10.0;
+
+#[derive(Debug, Clone, Default)]
+pub struct ViewModel {
+    /// The block in hand; air shows the hand itself.
+    pub held: BlockTypeId,
+    /// Seconds into the current swing, if swinging.
+    swing: Option<f32>,
+}
+
+impl ViewModel {
+    /// Starts a swing, restarting one that is under way.
+    pub fn swing(&mut self) {
+        self.swing = Some(0.0);
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        self.swing = self
+            .swing
+            .map(|time| time + delta)
+            .filter(|&time| time < SWING_DURATION);
+    }
+
+    /// How far the current swing is, from 0 to 1; 0 when not swinging.
+    pub fn swing_progress(&self) -> f32 {
+        self.swing.map_or(0.0, |time| time / SWING_DURATION)
+    }
+
+    /// Transforms the unit box of the held block, or of the hand, to view
+    /// space, where the camera looks down -z.
+    pub fn transform(&self) -> Matrix4<f32> {
+        let progress = self.swing_progress();
+        // Down and in towards the center, then back, like a punch
+        let arc = (progress.sqrt() * PI).sin();
+        let swing = Matrix4::from_translation(Vector3::new(
+            -0.3 * arc,
+            0.15 * (progress.sqrt() * PI * 2.0).sin(),
+            -0.2 * (progress * PI).sin(),
+        )) * Matrix4::from_angle_x(Rad(-1.2 * (progress * progress * PI).sin()));
+
+        let rest = if self.held == 0 {
+            // An arm reaching forward from the lower right
+            Matrix4::from_translation(Vector3::new(0.55, -0.6, -0.8))
+                * Matrix4::from_angle_x(Deg(10.0))
+                * Matrix4::from_angle_y(Deg(-15.0))
+                * Matrix4::from_nonuniform_scale(0.2, 0.2, 0.7)
+        } else {
+            Matrix4::from_translation(Vector3::new(0.5, -0.45, -0.9))
+                * Matrix4::from_angle_y(Deg(45.0))
+                * Matrix4::from_scale(0.4)
+        };
+        swing * rest * Matrix4::from_translation(Vector3::new(-0.5, -0.5, -0.5))
+    }
+
+    /// The projection of the viewmodel layer for an image of `aspect`.
+    pub fn projection(aspect: f32) -> Matrix4<f32> {
+        perspective(FOVY, aspect, NEAR, FAR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, Transform};
+
+    use super::*;
+
+    #[test]
+    fn test_swing() {
+        let mut viewmodel = ViewModel {
+            held: 1,
+            ..Default::default()
+        };
+        let rest = viewmodel.transform();
+        let center =
+            |transform: Matrix4<f32>| transform.transform_point(Point3::new(0.5, 0.5, 0.5));
+        // In front of the camera, to the lower right
+        let rest_center = center(rest);
+        assert!(rest_center.x > 0.0 && rest_center.y < 0.0 && rest_center.z < -NEAR);
+
+        viewmodel.swing();
+        viewmodel.update(SWING_DURATION / 2.0);
+        assert!((viewmodel.swing_progress() - 0.5).abs() < 1e-6);
+        assert!(center(viewmodel.transform()).x < rest_center.x);
+
+        viewmodel.update(SWING_DURATION);
+        assert_eq!(viewmodel.swing_progress(), 0.0);
+        assert_eq!(viewmodel.transform(), rest);
+
+        viewmodel.held = 0;
+        assert_ne!(viewmodel.transform(), rest);
+    }
+}
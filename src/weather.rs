@@ -0,0 +1,188 @@
+//! Rain and snow coming and going over time. The weather is the same
+//! everywhere; the biome under the camera decides whether it falls as rain,
+//! snow or not at all.
+
+use crate::{biome::Biome, worldgen::random::Random};
+
+/// Seconds the sky stays clear, at least and at most.
+const CLEAR_DURATION: (f32, f32) = (300.0, 900.0);
+/// Seconds a spell of rain or snow lasts, at least and at most.
+const STORM_DURATION: (f32, f32) = (120.0, 480.0);
+/// Seconds the intensity takes to go from 0 to 1 or back.
+const TRANSITION_DURATION: f32 = 20.0;
+/// Seconds surfaces take to dry once it stops raining.
+const DRYING_DURATION: f32 = 60.0;
+/// Biomes colder than this get snow, in the range of the climate noise.
+const SNOW_TEMPERATURE: f32 = -0.3;
+/// Biomes drier than this get nothing, in the range of the climate noise.
+const DRY_HUMIDITY: f32 = -0.4;
+/// How much of the sun and sky light is left in a full storm.
+const STORM_SKY_LIGHT: f32 = 0.5;
+
+/// What falls from the sky in a biome while it storms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precipitation {
+    None,
+    Rain,
+    Snow,
+}
+
+impl Precipitation {
+    pub fn of(biome: &Biome) -> Self {
+        if biome.humidity < DRY_HUMIDITY {
+            Precipitation::None
+        } else if biome.temperature < SNOW_TEMPERATURE {
+            Precipitation::Snow
+        } else {
+            Precipitation::Rain
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeatherState {
+    Clear,
+    Storm,
+}
+
+#[derive(Clone)]
+pub struct Weather {
+    state: WeatherState,
+    /// Seconds until the state changes.
+    remaining: f32,
+    /// From 0 with a clear sky to 1 in a full storm, easing toward the
+    /// state.
+    intensity: f32,
+    /// From 0 when dry to 1 when soaked, following the rain the camera has
+    /// been under.
+    wetness: f32,
+    random: Random,
+}
+
+impl Weather {
+    pub fn new(seed: u64) -> Self {
+        let mut weather = Self {
+            state: WeatherState::Clear,
+            remaining: 0.0,
+            intensity: 0.0,
+            wetness: 0.0,
+            random: Random::new(seed),
+        };
+        weather.remaining = weather.duration(CLEAR_DURATION);
+        weather
+    }
+
+    fn duration(&mut self, (min, max): (f32, f32)) -> f32 {
+        min + self.random.next_f32() * (max - min)
+    }
+
+    pub fn state(&self) -> WeatherState {
+        self.state
+    }
+
+    /// Switches to `state` now, for as long as it would last otherwise.
+    pub fn set_state(&mut self, state: WeatherState) {
+        self.state = state;
+        self.remaining = self.duration(match state {
+            WeatherState::Clear => CLEAR_DURATION,
+            WeatherState::Storm => STORM_DURATION,
+        });
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    pub fn wetness(&self) -> f32 {
+        self.wetness
+    }
+
+    /// How much of the sun and sky light gets through the clouds, from 1
+    /// with a clear sky down to `STORM_SKY_LIGHT`.
+    pub fn sky_light(&self) -> f32 {
+        1.0 - (1.0 - STORM_SKY_LIGHT) * self.intensity
+    }
+
+    /// Moves the weather on by `delta` seconds, with `precipitation` falling
+    /// where the camera is while it storms.
+    pub fn update(&mut self, delta: f32, precipitation: Precipitation) {
+        self.remaining -= delta;
+        if self.remaining <= 0.0 {
+            self.set_state(match self.state {
+                WeatherState::Clear => WeatherState::Storm,
+                WeatherState::Storm => WeatherState::Clear,
+            });
+        }
+
+        let target = match self.state {
+            WeatherState::Clear => 0.0,
+            WeatherState::Storm => 1.0,
+        };
+        let step = delta / TRANSITION_DURATION;
+        self.intensity = if self.intensity < target {
+            (self.intensity + step).min(target)
+        } else {
+            (self.intensity - step).max(target)
+        };
+
+        // Surfaces soak as fast as the rain comes in, and dry slowly
+        let rain = match precipitation {
+            Precipitation::Rain => self.intensity,
+            _ => 0.0,
+        };
+        self.wetness = if self.wetness < rain {
+            rain
+        } else {
+            (self.wetness - delta / DRYING_DURATION).max(rain)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::biome::BiomeRegistry;
+
+    use super::*;
+
+    #[test]
+    fn test_precipitation() {
+        let biome_registry = BiomeRegistry::default();
+        let precipitation = |name: &str| Precipitation::of(&biome_registry.biomes[name]);
+        assert_eq!(precipitation("plains"), Precipitation::Rain);
+        assert_eq!(precipitation("forest"), Precipitation::Rain);
+        assert_eq!(precipitation("desert"), Precipitation::None);
+        assert_eq!(precipitation("tundra"), Precipitation::Snow);
+    }
+
+    #[test]
+    fn test_weather() {
+        let mut weather = Weather::new(0);
+        assert_eq!(weather.state(), WeatherState::Clear);
+        assert_eq!(weather.sky_light(), 1.0);
+
+        // Clear spells end on their own
+        let mut elapsed = 0.0;
+        while weather.state() == WeatherState::Clear {
+            weather.update(1.0, Precipitation::Rain);
+            elapsed += 1.0;
+        }
+        assert!(elapsed <= CLEAR_DURATION.1 + 1.0);
+
+        // The storm builds up, soaking what it rains on
+        weather.update(TRANSITION_DURATION / 2.0, Precipitation::Rain);
+        assert!(weather.intensity() > 0.4 && weather.intensity() < 0.6);
+        weather.update(TRANSITION_DURATION, Precipitation::Rain);
+        assert_eq!(weather.intensity(), 1.0);
+        assert_eq!(weather.wetness(), 1.0);
+        assert_eq!(weather.sky_light(), STORM_SKY_LIGHT);
+
+        // Snow doesn't wet surfaces, which dry slowly
+        weather.update(DRYING_DURATION / 2.0, Precipitation::Snow);
+        assert!((weather.wetness() - 0.5).abs() < 1e-4);
+
+        weather.set_state(WeatherState::Clear);
+        weather.update(TRANSITION_DURATION, Precipitation::Rain);
+        assert_eq!(weather.intensity(), 0.0);
+        assert_eq!(weather.sky_light(), 1.0);
+    }
+}
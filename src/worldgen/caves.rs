@@ -0,0 +1,210 @@
+use std::f32::consts::TAU;
+
+use cgmath::Vector3;
+use noise::{NoiseFn, Perlin};
+
+use super::{random::Random, ChunkColumn};
+
+/// Layers above the bottom of the world caves never carve, so the world never
+/// has holes in its floor.
+const FLOOR_THICKNESS: i32 = 4;
+/// Noise caves stay this many blocks below the surface; only worms open up
+/// cave entrances.
+const SURFACE_MARGIN: i32 = 8;
+const CHEESE_THRESHOLD: f64 = 0.55;
+
+const WORM_CHANCE: f32 = 0.15;
+const WORM_STEPS: usize = 64;
+/// How far (in chunks) a worm can travel from the chunk it starts in.
+const WORM_REACH_CHUNKS: i32 = (WORM_STEPS as i32 + 16) / 16;
+
+pub struct CaveCarver {
+    seed: u64,
+    cheese_noise: Perlin,
+}
+
+impl CaveCarver {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            cheese_noise: Perlin::new(seed as u32),
+        }
+    }
+
+    /// Carves caves out of a column whose terrain surface is at `heights`
+    /// (indexed by `[x][z]`).
+    pub fn carve(&self, column: &mut ChunkColumn, heights: &[[i32; 16]; 16]) {
+        self.carve_cheese(column, heights);
+
+        // Worms cross column borders, so every worm that could reach this
+        // column is simulated and only the part inside it is carved. This keeps
+        // the result independent of the order columns are generated in.
+        for dx in -WORM_REACH_CHUNKS..=WORM_REACH_CHUNKS {
+            for dz in -WORM_REACH_CHUNKS..=WORM_REACH_CHUNKS {
+                self.carve_worms(column, column.x + dx, column.z + dz);
+            }
+        }
+    }
+
+    fn carve_cheese(&self, column: &mut ChunkColumn, heights: &[[i32; 16]; 16]) {
+        let min_y = column.height.min_y + FLOOR_THICKNESS;
+        for x in 0..16 {
+            for z in 0..16 {
+                let world_x = (column.x * 16 + x as i32) as f64;
+                let world_z = (column.z * 16 + z as i32) as f64;
+                for y in min_y..heights[x][z] - SURFACE_MARGIN {
+                    // Stretched horizontally for wide, flat caverns
+                    let density =
+                        self.cheese_noise
+                            .get([world_x / 48.0, y as f64 / 24.0, world_z / 48.0]);
+                    if density > CHEESE_THRESHOLD {
+                        *column.block_mut(x, y, z) = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Carves the worms starting in the column at `origin_x` and `origin_z`.
+    fn carve_worms(&self, column: &mut ChunkColumn, origin_x: i32, origin_z: i32) {
+        let mut random = Random::for_column(self.seed, origin_x, origin_z);
+        if random.next_f32() > WORM_CHANCE {
+            return;
+        }
+
+        let worm_count = 1 + random.next_below(2);
+        for _ in 0..worm_count {
+            let mut position = Vector3::new(
+                (origin_x * 16) as f32 + random.next_f32() * 16.0,
+                (column.height.min_y + 16) as f32 + random.next_f32() * 64.0,
+                (origin_z * 16) as f32 + random.next_f32() * 16.0,
+            );
+            let mut yaw = random.next_f32() * TAU;
+            let mut pitch = (random.next_f32() - 0.5) * 0.5;
+            let radius = 1.5 + random.next_f32() * 2.0;
+
+            for _ in 0..WORM_STEPS {
+                position += Vector3::new(
+                    yaw.cos() * pitch.cos(),
+                    pitch.sin(),
+                    yaw.sin() * pitch.cos(),
+                );
+                yaw += (random.next_f32() - 0.5) * 0.6;
+                pitch = pitch * 0.7 + (random.next_f32() - 0.5) * 0.4;
+                carve_sphere(column, position, radius);
+            }
+        }
+    }
+}
+
+fn carve_sphere(column: &mut ChunkColumn, center: Vector3<f32>, radius: f32) {
+    let origin_x = column.x * 16;
+    let origin_z = column.z * 16;
+
+    let min_x = ((center.x - radius).floor() as i32 - origin_x).max(0);
+    let max_x = ((center.x + radius).ceil() as i32 - origin_x).min(15);
+    let min_z = ((center.z - radius).floor() as i32 - origin_z).max(0);
+    let max_z = ((center.z + radius).ceil() as i32 - origin_z).min(15);
+    let min_y = ((center.y - radius).floor() as i32).max(column.height.min_y + FLOOR_THICKNESS);
+    let max_y = ((center.y + radius).ceil() as i32).min(column.height.max_y - 1);
+
+    for x in min_x..=max_x {
+        for z in min_z..=max_z {
+            for y in min_y..=max_y {
+                let dx = (origin_x + x) as f32 + 0.5 - center.x;
+                let dy = y as f32 + 0.5 - center.y;
+                let dz = (origin_z + z) as f32 + 0.5 - center.z;
+                if dx * dx + dy * dy + dz * dz <= radius * radius {
+                    *column.block_mut(x as usize, y, z as usize) = 0;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{WorldHeight, CHUNK_SIZE};
+
+    use super::*;
+
+    /// Stone from the bottom of the world to the top.
+    fn solid_column(x: i32, z: i32) -> ChunkColumn {
+        let mut column = ChunkColumn::new(x, z, WorldHeight::default());
+        for section in &mut column.sections {
+            section.blocks = [[[1; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+        }
+        column
+    }
+
+    fn carved(column: &ChunkColumn) -> Vec<(usize, i32, usize)> {
+        let mut carved = Vec::new();
+        for x in 0..16 {
+            for z in 0..16 {
+                for y in column.height.min_y..column.height.max_y {
+                    if column.block(x, y, z) == 0 {
+                        carved.push((x, y, z));
+                    }
+                }
+            }
+        }
+        carved
+    }
+
+    #[test]
+    fn test_worms_cross_columns() {
+        // The first seed with a worm starting in column (0, 0) that reaches
+        // one of its neighbors
+        let (carver, worm) = (0..64)
+            .find_map(|seed| {
+                let carver = CaveCarver::new(seed);
+                let worm = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                    .into_iter()
+                    .map(|(x, z)| {
+                        let mut column = solid_column(x, z);
+                        carver.carve_worms(&mut column, 0, 0);
+                        column
+                    })
+                    .find(|column| !carved(column).is_empty())?;
+                Some((carver, worm))
+            })
+            .expect("no worm left its column");
+
+        // Carving the neighbor on its own carves the same worm
+        let heights = [[200; 16]; 16];
+        let mut neighbor = solid_column(worm.x, worm.z);
+        carver.carve(&mut neighbor, &heights);
+        for (x, y, z) in carved(&worm) {
+            assert_eq!(neighbor.block(x, y, z), 0);
+        }
+
+        // The same seed carves the same blocks
+        let mut again = solid_column(worm.x, worm.z);
+        CaveCarver::new(carver.seed).carve(&mut again, &heights);
+        assert_eq!(carved(&again), carved(&neighbor));
+    }
+
+    #[test]
+    fn test_caves_keep_floor_and_surface() {
+        let height = WorldHeight::default();
+        let surface = 120;
+        let heights = [[surface; 16]; 16];
+        for seed in 0..4 {
+            let carver = CaveCarver::new(seed);
+            for (x, z) in [(0, 0), (5, -3), (-8, 12)] {
+                let mut column = solid_column(x, z);
+                carver.carve(&mut column, &heights);
+                for (_, y, _) in carved(&column) {
+                    assert!(y >= height.min_y + FLOOR_THICKNESS, "carved the floor");
+                }
+
+                // Only worms open up the ground, noise caves stay below it
+                let mut column = solid_column(x, z);
+                carver.carve_cheese(&mut column, &heights);
+                for (_, y, _) in carved(&column) {
+                    assert!(y < surface - SURFACE_MARGIN, "carved the surface");
+                }
+            }
+        }
+    }
+}
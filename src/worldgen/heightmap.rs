@@ -0,0 +1,198 @@
+use std::{io, path::Path};
+
+use image::{DynamicImage, ImageBuffer, Luma};
+use log::warn;
+
+use crate::types::{BlockRegistry, BlockTypeId, ColumnPosition, World, WorldHeight, CHUNK_SIZE};
+
+use super::{ChunkColumn, SEA_LEVEL, SUBSURFACE_DEPTH};
+
+/// How a heightmap is turned into terrain.
+#[derive(Debug, Clone)]
+pub struct HeightmapSettings {
+    /// Blocks per pixel along x and z. The heightmap is interpolated
+    /// between pixels.
+    pub horizontal_scale: f64,
+    /// Blocks between a black and a white pixel.
+    pub vertical_scale: f64,
+    /// Height of the surface at black pixels.
+    pub base_y: i32,
+    /// Surfaces below this are covered with `underwater_block` instead of
+    /// `surface_block`.
+    pub sea_level: i32,
+    /// Names of the block types covering the surface, the few layers below it
+    /// and the surface below sea level.
+    pub surface_block: String,
+    pub subsurface_block: String,
+    pub underwater_block: String,
+    /// Number of subsurface layers between the surface block and stone.
+    pub subsurface_depth: i32,
+}
+
+impl Default for HeightmapSettings {
+    fn default() -> Self {
+        Self {
+            horizontal_scale: 1.0,
+            vertical_scale: 64.0,
+            base_y: SEA_LEVEL - 16,
+            sea_level: SEA_LEVEL,
+            surface_block: "grass".to_string(),
+            subsurface_block: "dirt".to_string(),
+            underwater_block: "sand".to_string(),
+            subsurface_depth: SUBSURFACE_DEPTH,
+        }
+    }
+}
+
+/// Builds terrain from a grayscale heightmap whose top-left pixel lies at the
+/// world origin, with x to the right and z downwards. Outside the image the
+/// edge pixels continue.
+pub struct HeightmapGenerator {
+    heightmap: ImageBuffer<Luma<u16>, Vec<u16>>,
+    settings: HeightmapSettings,
+    surface: BlockTypeId,
+    subsurface: BlockTypeId,
+    underwater: BlockTypeId,
+    stone: BlockTypeId,
+}
+
+impl HeightmapGenerator {
+    /// Colored images are converted to grayscale; 16-bit images keep their
+    /// precision.
+    pub fn new(
+        heightmap: &DynamicImage,
+        settings: HeightmapSettings,
+        block_registry: &BlockRegistry,
+    ) -> Self {
+        let stone = block_registry.block_types.get_index_of("stone").unwrap();
+        let resolve = |name: &str| {
+            block_registry
+                .block_types
+                .get_index_of(name)
+                .unwrap_or_else(|| {
+                    warn!("Unknown heightmap block {:?}, using stone", name);
+                    stone
+                })
+        };
+        Self {
+            heightmap: heightmap.to_luma16(),
+            surface: resolve(&settings.surface_block),
+            subsurface: resolve(&settings.subsurface_block),
+            underwater: resolve(&settings.underwater_block),
+            stone,
+            settings,
+        }
+    }
+
+    pub fn open(
+        path: impl AsRef<Path>,
+        settings: HeightmapSettings,
+        block_registry: &BlockRegistry,
+    ) -> io::Result<Self> {
+        let heightmap = image::open(path).map_err(io::Error::other)?;
+        Ok(Self::new(&heightmap, settings, block_registry))
+    }
+
+    /// The columns the heightmap covers.
+    pub fn columns(&self) -> impl Iterator<Item = ColumnPosition> {
+        let [width, length] = [self.heightmap.width(), self.heightmap.height()].map(|size| {
+            (size as f64 * self.settings.horizontal_scale / CHUNK_SIZE as f64).ceil() as i32
+        });
+        (0..width).flat_map(move |x| (0..length).map(move |z| ColumnPosition { x, z }))
+    }
+
+    /// Y coordinate of the topmost solid block of the column.
+    pub fn height_at(&self, x: i32, z: i32, world_height: WorldHeight) -> i32 {
+        let value = self.sample(
+            x as f64 / self.settings.horizontal_scale,
+            z as f64 / self.settings.horizontal_scale,
+        );
+        let height = self.settings.base_y as f64 + value * self.settings.vertical_scale;
+        (height.round() as i32).clamp(world_height.min_y + 1, world_height.max_y - 1)
+    }
+
+    /// Bilinearly interpolated brightness in `0.0..=1.0` at pixel coordinates
+    /// `x` and `y`.
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let pixel = |x: i64, y: i64| {
+            let x = x.clamp(0, self.heightmap.width() as i64 - 1) as u32;
+            let y = y.clamp(0, self.heightmap.height() as i64 - 1) as u32;
+            self.heightmap.get_pixel(x, y).0[0] as f64 / u16::MAX as f64
+        };
+        let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+        let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+        let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1, y0) * fx;
+        let bottom = pixel(x0, y0 + 1) * (1.0 - fx) + pixel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    /// Generates the column of sections at column coordinates `x` and `z`.
+    pub fn generate_column(&self, x: i32, z: i32, world_height: WorldHeight) -> ChunkColumn {
+        let mut column = ChunkColumn::new(x, z, world_height);
+        for local_x in 0..CHUNK_SIZE {
+            for local_z in 0..CHUNK_SIZE {
+                let world_x = x * CHUNK_SIZE as i32 + local_x as i32;
+                let world_z = z * CHUNK_SIZE as i32 + local_z as i32;
+                let height = self.height_at(world_x, world_z, world_height);
+                let surface = if height < self.settings.sea_level {
+                    self.underwater
+                } else {
+                    self.surface
+                };
+                for y in world_height.min_y..=height {
+                    *column.block_mut(local_x, y, local_z) = if y == height {
+                        surface
+                    } else if y >= height - self.settings.subsurface_depth {
+                        self.subsurface
+                    } else {
+                        self.stone
+                    };
+                }
+            }
+        }
+        column
+    }
+
+    /// Generates `column` into `world`.
+    pub fn generate(&self, world: &mut World, column: ColumnPosition) {
+        let column = self.generate_column(column.x, column.z, world.height);
+        world.insert_chunks(column.into_chunks());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GrayImage;
+
+    use super::*;
+
+    #[test]
+    fn test_heightmap_terrain() {
+        let block_registry = BlockRegistry::default();
+        let id = |name: &str| block_registry.block_types.get_index_of(name).unwrap();
+        // Black on the left, white on the right
+        let heightmap = GrayImage::from_fn(2, 2, |x, _| Luma([x as u8 * 255]));
+        let generator = HeightmapGenerator::new(
+            &DynamicImage::ImageLuma8(heightmap),
+            HeightmapSettings {
+                horizontal_scale: 8.0,
+                ..Default::default()
+            },
+            &block_registry,
+        );
+        assert_eq!(generator.columns().count(), 1);
+
+        let height = WorldHeight::default();
+        assert_eq!(generator.height_at(0, 0, height), SEA_LEVEL - 16);
+        assert_eq!(generator.height_at(4, 0, height), SEA_LEVEL + 16);
+        // Past the right edge, white continues
+        assert_eq!(generator.height_at(100, 0, height), SEA_LEVEL + 48);
+
+        let column = generator.generate_column(0, 0, height);
+        assert_eq!(column.block(0, SEA_LEVEL - 16, 0), id("sand"));
+        assert_eq!(column.block(0, SEA_LEVEL - 17, 0), id("dirt"));
+        assert_eq!(column.block(0, SEA_LEVEL - 20, 0), id("stone"));
+        assert_eq!(column.block(0, SEA_LEVEL - 15, 0), 0);
+        assert_eq!(column.block(15, SEA_LEVEL + 48, 0), id("grass"));
+    }
+}
@@ -0,0 +1,336 @@
+mod caves;
+pub mod heightmap;
+pub mod random;
+pub mod structure;
+
+use log::warn;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+
+use crate::{
+    biome::{BiomeId, BiomeRegistry},
+    types::{
+        BlockRegistry, BlockTypeId, Chunk, ChunkPosition, ColumnPosition, World, WorldHeight,
+        CHUNK_SIZE,
+    },
+};
+
+use self::{caves::CaveCarver, random::Random, structure::Structure};
+
+pub use self::random::WorldSeed;
+
+pub const SEA_LEVEL: i32 = 64;
+/// Number of subsurface layers between the surface block and stone.
+const SUBSURFACE_DEPTH: i32 = 3;
+const RUIN_CHANCE: f32 = 0.01;
+/// How far (in chunks) a structure can extend beyond the chunk it starts in.
+const STRUCTURE_REACH_CHUNKS: i32 = 1;
+
+/// Block types a biome builds its terrain from, resolved from the names in
+/// `Biome`.
+#[derive(Debug, Clone, Copy)]
+struct BiomeBlocks {
+    surface: BlockTypeId,
+    subsurface: BlockTypeId,
+}
+
+/// All sections of one chunk column while it is being generated, so terrain,
+/// caves and structures can be written without caring about section borders.
+pub struct ChunkColumn {
+    pub x: i32,
+    pub z: i32,
+    pub height: WorldHeight,
+    /// One section per y in `height.sections()`, bottom to top.
+    pub sections: Vec<Chunk>,
+}
+
+impl ChunkColumn {
+    pub fn new(x: i32, z: i32, height: WorldHeight) -> Self {
+        Self {
+            x,
+            z,
+            height,
+            sections: height.sections().map(|_| Chunk::default()).collect(),
+        }
+    }
+
+    /// The block at local `x` and `z` and world `y`, which must be inside the
+    /// world height.
+    pub fn block_mut(&mut self, x: usize, y: i32, z: usize) -> &mut BlockTypeId {
+        let y = (y - self.height.min_y) as usize;
+        &mut self.sections[y / CHUNK_SIZE].blocks[y % CHUNK_SIZE][x][z]
+    }
+
+    pub fn block(&self, x: usize, y: i32, z: usize) -> BlockTypeId {
+        let y = (y - self.height.min_y) as usize;
+        self.sections[y / CHUNK_SIZE].blocks[y % CHUNK_SIZE][x][z]
+    }
+
+    pub fn into_chunks(self) -> impl Iterator<Item = (ChunkPosition, Chunk)> {
+        let (x, z) = (self.x, self.z);
+        self.height
+            .sections()
+            .zip(self.sections)
+            .map(move |(y, chunk)| (ChunkPosition { x, y, z }, chunk))
+    }
+}
+
+type ColumnHook = Box<dyn Fn(&mut ChunkColumn)>;
+
+/// Generates chunk columns purely from the world seed and the column position,
+/// so a column comes out the same no matter which columns were generated
+/// before it.
+pub struct WorldGenerator {
+    pub seed: WorldSeed,
+    pub biome_registry: BiomeRegistry,
+    biome_blocks: Vec<BiomeBlocks>,
+    stone: BlockTypeId,
+    grass: BlockTypeId,
+    log: BlockTypeId,
+    leaves: BlockTypeId,
+
+    height_noise: Fbm<Perlin>,
+    temperature_noise: Fbm<Perlin>,
+    humidity_noise: Fbm<Perlin>,
+    caves: CaveCarver,
+    structure_seed: u64,
+    /// Called on every generated column, last.
+    column_hooks: Vec<ColumnHook>,
+}
+
+impl WorldGenerator {
+    pub fn new(
+        seed: WorldSeed,
+        block_registry: &BlockRegistry,
+        biome_registry: BiomeRegistry,
+    ) -> Self {
+        let builtin = |name: &str| {
+            block_registry
+                .block_types
+                .get_index_of(name)
+                .unwrap_or_else(|| panic!("{} is a built-in block type", name))
+        };
+        let stone = builtin("stone");
+        let resolve = |name: &str| {
+            block_registry
+                .block_types
+                .get_index_of(name)
+                .unwrap_or_else(|| {
+                    warn!("Unknown biome block {:?}, using stone", name);
+                    stone
+                })
+        };
+        let biome_blocks = biome_registry
+            .biomes
+            .values()
+            .map(|biome| BiomeBlocks {
+                surface: resolve(&biome.surface_block),
+                subsurface: resolve(&biome.subsurface_block),
+            })
+            .collect();
+
+        Self {
+            seed,
+            biome_registry,
+            biome_blocks,
+            stone,
+            grass: builtin("grass"),
+            log: builtin("log"),
+            leaves: builtin("leaves"),
+            height_noise: Fbm::<Perlin>::new(seed.noise_seed("height"))
+                .set_octaves(4)
+                .set_frequency(1.0 / 128.0),
+            temperature_noise: Fbm::<Perlin>::new(seed.noise_seed("temperature"))
+                .set_octaves(2)
+                .set_frequency(1.0 / 512.0),
+            humidity_noise: Fbm::<Perlin>::new(seed.noise_seed("humidity"))
+                .set_octaves(2)
+                .set_frequency(1.0 / 512.0),
+            caves: CaveCarver::new(seed.feature("caves")),
+            structure_seed: seed.feature("structures"),
+            column_hooks: Vec::new(),
+        }
+    }
+
+    /// Lets `hook` change every column after it is generated, like plugins
+    /// do. Columns stay independent of the generation order only if the hook
+    /// keeps them so.
+    pub fn add_column_hook(&mut self, hook: impl Fn(&mut ChunkColumn) + 'static) {
+        self.column_hooks.push(Box::new(hook));
+    }
+
+    pub fn biome_at(&self, x: i32, z: i32) -> BiomeId {
+        let point = [x as f64, z as f64];
+        self.biome_registry.select(
+            self.temperature_noise.get(point) as f32,
+            self.humidity_noise.get(point) as f32,
+        )
+    }
+
+    /// Y coordinate of the topmost solid block of the column.
+    pub fn height_at(&self, x: i32, z: i32, world_height: WorldHeight) -> i32 {
+        let height = SEA_LEVEL as f64 + self.height_noise.get([x as f64, z as f64]) * 24.0;
+        (height as i32).clamp(world_height.min_y + 1, world_height.max_y - 1)
+    }
+
+    /// Generates the column of sections at column coordinates `x` and `z`.
+    pub fn generate_column(&self, x: i32, z: i32, world_height: WorldHeight) -> ChunkColumn {
+        let mut column = ChunkColumn::new(x, z, world_height);
+        let mut heights = [[0; CHUNK_SIZE]; CHUNK_SIZE];
+
+        for local_x in 0..CHUNK_SIZE {
+            for local_z in 0..CHUNK_SIZE {
+                let world_x = x * 16 + local_x as i32;
+                let world_z = z * 16 + local_z as i32;
+
+                let biome = self.biome_at(world_x, world_z);
+                let blocks = self.biome_blocks[biome];
+                for chunk in column.sections.iter_mut() {
+                    chunk.biomes[local_x][local_z] = biome;
+                    chunk.biome_colors[local_x][local_z] = self.biome_registry.biomes[biome].colors;
+                }
+
+                let height = self.height_at(world_x, world_z, world_height);
+                heights[local_x][local_z] = height;
+                for y in world_height.min_y..=height {
+                    *column.block_mut(local_x, y, local_z) = if y == height {
+                        blocks.surface
+                    } else if y >= height - SUBSURFACE_DEPTH {
+                        blocks.subsurface
+                    } else {
+                        self.stone
+                    };
+                }
+            }
+        }
+
+        self.caves.carve(&mut column, &heights);
+
+        // Structures are decided per origin column from the seed alone, so the
+        // parts of neighboring columns' structures reaching into this column
+        // can be stamped without those columns being generated.
+        for dx in -STRUCTURE_REACH_CHUNKS..=STRUCTURE_REACH_CHUNKS {
+            for dz in -STRUCTURE_REACH_CHUNKS..=STRUCTURE_REACH_CHUNKS {
+                for (structure, origin) in self.structures_in(x + dx, z + dz, world_height) {
+                    structure.stamp(&mut column, origin);
+                }
+            }
+        }
+
+        for hook in &self.column_hooks {
+            hook(&mut column);
+        }
+        column
+    }
+
+    /// Generates `column` into `world`.
+    pub fn generate(&self, world: &mut World, column: ColumnPosition) {
+        let column = self.generate_column(column.x, column.z, world.height);
+        world.insert_chunks(column.into_chunks());
+    }
+
+    /// The structures starting in the column at `column_x` and `column_z`,
+    /// with their origins in world coordinates.
+    fn structures_in(
+        &self,
+        column_x: i32,
+        column_z: i32,
+        world_height: WorldHeight,
+    ) -> Vec<(Structure, [i32; 3])> {
+        let mut random = Random::for_column(self.structure_seed, column_x, column_z);
+        let mut structures = Vec::new();
+        let column = |x: u32, z: u32| (column_x * 16 + x as i32, column_z * 16 + z as i32);
+
+        let (center_x, center_z) = column(8, 8);
+        let tree_density =
+            self.biome_registry.biomes[self.biome_at(center_x, center_z)].tree_density;
+        let tree_count = tree_density as u32 + (random.next_f32() < tree_density.fract()) as u32;
+        for _ in 0..tree_count {
+            let (x, z) = column(random.next_below(16), random.next_below(16));
+            let trunk_height = 4 + random.next_below(3) as i32;
+            // Trees only grow on grass
+            if self.biome_blocks[self.biome_at(x, z)].surface != self.grass {
+                continue;
+            }
+            let tree = Structure::tree(self.log, self.leaves, trunk_height);
+            structures.push((tree, [x, self.height_at(x, z, world_height) + 1, z]));
+        }
+
+        if random.next_f32() < RUIN_CHANCE {
+            let ruin = Structure::ruin(self.stone, &mut random);
+            structures.push((
+                ruin,
+                [
+                    center_x,
+                    self.height_at(center_x, center_z, world_height) + 1,
+                    center_z,
+                ],
+            ));
+        }
+
+        structures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::BlockRegistry;
+
+    use super::*;
+
+    fn generator(seed: u64) -> WorldGenerator {
+        WorldGenerator::new(
+            WorldSeed(seed),
+            &BlockRegistry::default(),
+            BiomeRegistry::default(),
+        )
+    }
+
+    fn blocks(column: ChunkColumn) -> Vec<[[[BlockTypeId; 16]; 16]; 16]> {
+        column.sections.iter().map(|chunk| chunk.blocks).collect()
+    }
+
+    #[test]
+    fn test_same_seed_same_chunk() {
+        let height = WorldHeight::default();
+        let a = generator(1234).generate_column(3, -7, height);
+        let b = generator(1234).generate_column(3, -7, height);
+        assert_eq!(a.sections[0].biomes, b.sections[0].biomes);
+        assert!(blocks(a) == blocks(b));
+
+        let a = generator(1234).generate_column(3, -7, height);
+        let c = generator(4321).generate_column(3, -7, height);
+        assert!(blocks(a) != blocks(c));
+    }
+
+    #[test]
+    fn test_generation_order_independent() {
+        let generator = generator(42);
+        let columns = (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |z| ColumnPosition { x, z }))
+            .collect::<Vec<_>>();
+
+        let mut forward = World::new(BlockRegistry::default());
+        for &column in columns.iter() {
+            generator.generate(&mut forward, column);
+        }
+        let mut backward = World::new(BlockRegistry::default());
+        for &column in columns.iter().rev() {
+            generator.generate(&mut backward, column);
+        }
+
+        assert_eq!(forward.chunks.len(), 9 * 16);
+        for (chunk_position, chunk) in forward.chunks.iter() {
+            assert!(chunk.blocks == backward.chunks[chunk_position].blocks);
+        }
+    }
+
+    #[test]
+    fn test_negative_world_height() {
+        let height = WorldHeight::new(-64, 128);
+        let column = generator(7).generate_column(0, 0, height);
+        assert_eq!(column.sections.len(), 12);
+        // Terrain reaches down to the bottom of the world
+        assert_ne!(column.block(0, -64, 0), 0);
+        assert_eq!(column.block(0, 127, 0), 0);
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+/// The single seed all of world generation is derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct WorldSeed(pub u64);
+
+impl WorldSeed {
+    /// An independent seed for one generation feature, so adding or changing a
+    /// feature doesn't shift the random streams of the others.
+    pub fn feature(&self, feature: &str) -> u64 {
+        // FNV-1a of the feature name, mixed with the world seed
+        let name_hash = feature
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+            });
+        Random::new(self.0 ^ name_hash).next_u64()
+    }
+
+    /// A feature seed for the `noise` crate, which takes 32-bit seeds.
+    pub fn noise_seed(&self, feature: &str) -> u32 {
+        (self.feature(feature) >> 32) as u32
+    }
+}
+
+/// Small deterministic PRNG (SplitMix64) used by world generation, so the same
+/// seed always produces the same world on every platform.
+#[derive(Debug, Clone)]
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// A generator seeded from `seed` and a chunk column position, so features
+    /// of a column don't depend on the order columns are generated in.
+    pub fn for_column(seed: u64, column_x: i32, column_z: i32) -> Self {
+        let position = ((column_x as u32 as u64) << 32) | column_z as u32 as u64;
+        Self::new(Self::new(seed ^ position).next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in `0.0..1.0`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniformly distributed in `0..bound`.
+    pub fn next_below(&mut self, bound: u32) -> u32 {
+        (self.next_u64() % bound as u64) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_seeds() {
+        let seed = WorldSeed(42);
+        assert_eq!(seed.feature("caves"), WorldSeed(42).feature("caves"));
+        assert_ne!(seed.feature("caves"), seed.feature("structures"));
+        assert_ne!(seed.feature("caves"), WorldSeed(43).feature("caves"));
+    }
+
+    #[test]
+    fn test_column_random_streams() {
+        assert_eq!(
+            Random::for_column(7, 1, 2).next_u64(),
+            Random::for_column(7, 1, 2).next_u64()
+        );
+        assert_ne!(
+            Random::for_column(7, 1, 2).next_u64(),
+            Random::for_column(7, 2, 1).next_u64()
+        );
+    }
+}
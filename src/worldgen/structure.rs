@@ -0,0 +1,275 @@
+use crate::types::{BlockTypeId, World};
+
+use super::{random::Random, ChunkColumn};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StructureBlock {
+    pub offset: [i32; 3],
+    pub block_type_id: BlockTypeId,
+    /// Whether the block may overwrite non-air blocks. Leaves don't, so trees
+    /// never cut into the terrain or into each other's trunks.
+    pub replace_solid: bool,
+}
+
+/// Rotation of a structure about the y axis, clockwise seen from above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+impl Rotation {
+    pub fn apply(&self, [x, y, z]: [i32; 3]) -> [i32; 3] {
+        match self {
+            Rotation::None => [x, y, z],
+            Rotation::Clockwise90 => [-z, y, x],
+            Rotation::Clockwise180 => [-x, y, -z],
+            Rotation::Clockwise270 => [z, y, -x],
+        }
+    }
+}
+
+/// A multi-block template stamped into the world relative to an origin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Structure {
+    pub name: String,
+    pub blocks: Vec<StructureBlock>,
+}
+
+impl Structure {
+    pub fn tree(log: BlockTypeId, leaves: BlockTypeId, trunk_height: i32) -> Self {
+        let mut blocks = Vec::new();
+
+        // Two wide layers around the top of the trunk, two narrow ones above
+        for dy in trunk_height - 2..trunk_height + 2 {
+            let radius: i32 = if dy < trunk_height { 2 } else { 1 };
+            for dx in -radius..=radius {
+                for dz in -radius..=radius {
+                    if dx.abs() == radius && dz.abs() == radius {
+                        continue;
+                    }
+                    blocks.push(StructureBlock {
+                        offset: [dx, dy, dz],
+                        block_type_id: leaves,
+                        replace_solid: false,
+                    });
+                }
+            }
+        }
+        // The trunk comes last so it replaces the leaves inside the canopy
+        for dy in 0..trunk_height {
+            blocks.push(StructureBlock {
+                offset: [0, dy, 0],
+                block_type_id: log,
+                replace_solid: true,
+            });
+        }
+
+        Self {
+            name: "tree".to_string(),
+            blocks,
+        }
+    }
+
+    /// A 7x7 foundation with crumbled walls of random height.
+    pub fn ruin(wall: BlockTypeId, random: &mut Random) -> Self {
+        let mut blocks = Vec::new();
+        for dx in -3..=3_i32 {
+            for dz in -3..=3_i32 {
+                blocks.push(StructureBlock {
+                    offset: [dx, -1, dz],
+                    block_type_id: wall,
+                    replace_solid: true,
+                });
+
+                let is_wall = dx.abs() == 3 || dz.abs() == 3;
+                if !is_wall {
+                    continue;
+                }
+                for dy in 0..random.next_below(4) as i32 {
+                    blocks.push(StructureBlock {
+                        offset: [dx, dy, dz],
+                        block_type_id: wall,
+                        replace_solid: true,
+                    });
+                }
+            }
+        }
+
+        Self {
+            name: "ruin".to_string(),
+            blocks,
+        }
+    }
+
+    /// The blocks in the cuboid from `min` (inclusive) to `max` (exclusive),
+    /// air included, with offsets relative to `min`.
+    pub fn copy(world: &World, name: &str, min: [i32; 3], max: [i32; 3]) -> Self {
+        let mut blocks = Vec::new();
+        for y in min[1]..max[1] {
+            for z in min[2]..max[2] {
+                for x in min[0]..max[0] {
+                    blocks.push(StructureBlock {
+                        offset: [x - min[0], y - min[1], z - min[2]],
+                        block_type_id: world[[x, y, z]],
+                        replace_solid: true,
+                    });
+                }
+            }
+        }
+
+        Self {
+            name: name.to_string(),
+            blocks,
+        }
+    }
+
+    pub fn rotated(&self, rotation: Rotation) -> Self {
+        Self {
+            name: self.name.clone(),
+            blocks: self
+                .blocks
+                .iter()
+                .map(|block| StructureBlock {
+                    offset: rotation.apply(block.offset),
+                    ..*block
+                })
+                .collect(),
+        }
+    }
+
+    /// The lowest and highest offset of any block, or `None` for an empty
+    /// structure.
+    pub fn bounds(&self) -> Option<([i32; 3], [i32; 3])> {
+        let first = self.blocks.first()?.offset;
+        Some(
+            self.blocks
+                .iter()
+                .fold((first, first), |(min, max), block| {
+                    (
+                        [0, 1, 2].map(|i| min[i].min(block.offset[i])),
+                        [0, 1, 2].map(|i| max[i].max(block.offset[i])),
+                    )
+                }),
+        )
+    }
+
+    /// Writes the structure, rotated by `rotation`, at `origin` in `world`.
+    /// Blocks outside the world height are dropped.
+    pub fn paste(&self, world: &mut World, origin: [i32; 3], rotation: Rotation) {
+        for block in &self.blocks {
+            let offset = rotation.apply(block.offset);
+            let position = [0, 1, 2].map(|i| origin[i] + offset[i]);
+            if !world.height.contains(position[1]) {
+                continue;
+            }
+            if block.replace_solid || world[position] == 0 {
+                world.set_block(position, block.block_type_id);
+            }
+        }
+    }
+
+    /// Writes the part of the structure at `origin` (world coordinates) that
+    /// falls inside `column`.
+    pub fn stamp(&self, column: &mut ChunkColumn, origin: [i32; 3]) {
+        for block in &self.blocks {
+            let x = origin[0] + block.offset[0] - column.x * 16;
+            let y = origin[1] + block.offset[1];
+            let z = origin[2] + block.offset[2] - column.z * 16;
+            if !(0..16).contains(&x) || !column.height.contains(y) || !(0..16).contains(&z) {
+                continue;
+            }
+
+            let current = column.block_mut(x as usize, y, z as usize);
+            if block.replace_solid || *current == 0 {
+                *current = block.block_type_id;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::WorldHeight;
+
+    use super::*;
+
+    #[test]
+    fn test_stamp_across_chunk_border() {
+        let structure = Structure {
+            name: "bar".to_string(),
+            blocks: (0..4)
+                .map(|dx| StructureBlock {
+                    offset: [dx, 0, 0],
+                    block_type_id: 1,
+                    replace_solid: true,
+                })
+                .collect(),
+        };
+
+        // Starts two blocks before the border of column (1, 0)
+        let mut column = ChunkColumn::new(0, 0, WorldHeight::default());
+        structure.stamp(&mut column, [14, 70, 3]);
+        assert_eq!(column.block(14, 70, 3), 1);
+        assert_eq!(column.block(15, 70, 3), 1);
+
+        let mut neighbor = ChunkColumn::new(1, 0, WorldHeight::default());
+        structure.stamp(&mut neighbor, [14, 70, 3]);
+        assert_eq!(neighbor.block(0, 70, 3), 1);
+        assert_eq!(neighbor.block(1, 70, 3), 1);
+        assert_eq!(neighbor.block(2, 70, 3), 0);
+    }
+
+    #[test]
+    fn test_leaves_do_not_replace_solid_blocks() {
+        let tree = Structure::tree(2, 3, 5);
+        let mut column = ChunkColumn::new(0, 0, WorldHeight::default());
+        *column.block_mut(8, 66, 9) = 1;
+
+        tree.stamp(&mut column, [8, 63, 8]);
+
+        assert_eq!(column.block(8, 66, 9), 1);
+        assert_eq!(column.block(8, 66, 8), 2);
+        assert_eq!(column.block(8, 68, 8), 3);
+        assert_eq!(column.block(8, 66, 10), 3);
+    }
+
+    #[test]
+    fn test_copy_and_paste_rotated() {
+        let mut world = World::new(crate::types::BlockRegistry::default());
+        world[[0, 10, 0]] = 1;
+        world[[1, 10, 0]] = 2;
+        world[[0, 11, 0]] = 3;
+        let structure = Structure::copy(&world, "copy", [0, 10, 0], [2, 12, 1]);
+        assert_eq!(structure.blocks.len(), 4);
+        assert_eq!(structure.bounds(), Some(([0, 0, 0], [1, 1, 0])));
+
+        // East turns into south
+        structure.paste(&mut world, [20, 50, 20], Rotation::Clockwise90);
+        assert_eq!(world[[20, 50, 20]], 1);
+        assert_eq!(world[[20, 50, 21]], 2);
+        assert_eq!(world[[20, 51, 20]], 3);
+        assert_eq!(world[[21, 50, 20]], 0);
+
+        // Air in the copy clears what was there
+        world[[20, 51, 21]] = 1;
+        structure.paste(&mut world, [20, 50, 21], Rotation::None);
+        assert_eq!(world[[20, 51, 21]], 3);
+        assert_eq!(world[[21, 51, 21]], 0);
+    }
+
+    #[test]
+    fn test_stamp_below_world_bottom() {
+        let tree = Structure::tree(2, 3, 5);
+        let mut column = ChunkColumn::new(0, 0, WorldHeight::new(-32, 32));
+
+        // The trunk's base is clipped away instead of wrapping around
+        tree.stamp(&mut column, [8, -34, 8]);
+
+        assert_eq!(column.block(8, -32, 8), 2);
+        assert_eq!(column.block(8, 31, 8), 0);
+    }
+}